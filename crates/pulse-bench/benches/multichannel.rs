@@ -0,0 +1,163 @@
+//! Multi-channel routing benchmarks for Pulse.
+//!
+//! Publishes are spread over thousands of channels whose publish rates and
+//! subscriber counts follow a Zipf distribution, so a few channels fan out
+//! to most connections while the long tail has one or two subscribers. This
+//! is closer to production routing than the single-channel benchmarks:
+//! channel lookups spread across the map and fan-out varies per publish.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tenvis_pulse_bench::zipf::{Rng, Zipf};
+use tenvis_pulse_core::{ConnectionHandle, Message, Router, RouterConfig};
+
+/// Connections subscribed across the channels.
+const CONNECTIONS: usize = 2_000;
+
+/// Extra channels each connection joins, picked by popularity.
+const SUBSCRIPTIONS_PER_CONNECTION: usize = 8;
+
+/// Zipf exponent for channel popularity.
+const EXPONENT: f64 = 1.0;
+
+/// Publishes between queue drains.
+const BATCH: usize = 1024;
+
+/// A router populated with a skewed workload.
+struct Workload {
+    router: Router,
+    handles: Vec<Arc<ConnectionHandle>>,
+    messages: Vec<Message>,
+}
+
+impl Workload {
+    /// Spread subscriptions over `channels` channels and pick the channels
+    /// for a sequence of publishes. Every channel gets one subscriber so
+    /// none is missing; the rest follow popularity.
+    fn new(channels: usize) -> Self {
+        let router = Router::with_config(RouterConfig {
+            max_channels: channels,
+            connection_queue_capacity: BATCH * 4,
+            ..RouterConfig::default()
+        });
+        let zipf = Zipf::new(channels, EXPONENT);
+        let mut rng = Rng::new(42);
+        let name = |rank: usize| format!("zipf:{rank}");
+
+        let handles: Vec<_> = (0..CONNECTIONS)
+            .map(|i| router.connect(&format!("conn-{i}")))
+            .collect();
+        for rank in 0..channels {
+            router
+                .subscribe_handle(&handles[rank % CONNECTIONS], &name(rank), None)
+                .unwrap();
+        }
+        for handle in &handles {
+            for _ in 0..SUBSCRIPTIONS_PER_CONNECTION {
+                let _ = router.subscribe_handle(handle, &name(zipf.sample(&mut rng)), None);
+            }
+        }
+
+        let messages = (0..BATCH * 16)
+            .map(|_| Message::new(name(zipf.sample(&mut rng)), vec![0u8; 64]))
+            .collect();
+        Self {
+            router,
+            handles,
+            messages,
+        }
+    }
+
+    /// Empty every queue, as the connection writers would.
+    fn drain(&self) {
+        for handle in &self.handles {
+            while handle.try_recv().is_some() {}
+        }
+    }
+}
+
+/// Benchmark aggregate publish throughput across skewed channels.
+fn bench_zipf_throughput(c: &mut Criterion) {
+    let mut group = c.benchmark_group("zipf_throughput");
+
+    for channels in [1_000, 10_000] {
+        let workload = Workload::new(channels);
+        group.throughput(Throughput::Elements(BATCH as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(channels),
+            &workload,
+            |b, workload| {
+                let mut next = 0;
+                b.iter_custom(|iters| {
+                    let mut elapsed = Duration::ZERO;
+                    for _ in 0..iters {
+                        let start = Instant::now();
+                        for _ in 0..BATCH {
+                            let message = workload.messages[next].clone();
+                            next = (next + 1) % workload.messages.len();
+                            black_box(workload.router.publish(message));
+                        }
+                        elapsed += start.elapsed();
+                        workload.drain();
+                    }
+                    elapsed
+                });
+            },
+        );
+    }
+
+    group.finish();
+}
+
+/// Benchmark per-publish latency across skewed channels, printing
+/// percentiles, since a few hot channels dominate the tail.
+fn bench_zipf_latency(c: &mut Criterion) {
+    let mut group = c.benchmark_group("zipf_latency");
+
+    for channels in [1_000, 10_000] {
+        let workload = Workload::new(channels);
+        let samples = Mutex::new(Vec::new());
+        group.bench_with_input(
+            BenchmarkId::from_parameter(channels),
+            &workload,
+            |b, workload| {
+                let mut next = 0;
+                b.iter_custom(|iters| {
+                    let mut samples = samples.lock().unwrap();
+                    let mut elapsed = Duration::ZERO;
+                    for i in 0..iters {
+                        let message = workload.messages[next].clone();
+                        next = (next + 1) % workload.messages.len();
+                        let start = Instant::now();
+                        black_box(workload.router.publish(message));
+                        let took = start.elapsed();
+                        elapsed += took;
+                        samples.push(took);
+                        if i % BATCH as u64 == 0 {
+                            workload.drain();
+                        }
+                    }
+                    elapsed
+                });
+            },
+        );
+
+        let mut samples = samples.into_inner().unwrap();
+        samples.sort_unstable();
+        let at = |q: f64| samples[((samples.len() - 1) as f64 * q) as usize];
+        println!(
+            "zipf_latency/{channels}: p50 {:?}  p99 {:?}  p99.9 {:?}  max {:?}  ({} publishes)",
+            at(0.5),
+            at(0.99),
+            at(0.999),
+            at(1.0),
+            samples.len()
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_zipf_throughput, bench_zipf_latency);
+criterion_main!(benches);
@@ -119,7 +119,7 @@ fn bench_router(c: &mut Criterion) {
 
 /// Benchmark channel operations.
 fn bench_channel(c: &mut Criterion) {
-    use tenvis_pulse_core::Channel;
+    use tenvis_pulse_core::{Channel, ConnId};
 
     let mut group = c.benchmark_group("channel");
 
@@ -127,15 +127,15 @@ fn bench_channel(c: &mut Criterion) {
         let mut channel = Channel::new("test");
         let mut i = 0u64;
         b.iter(|| {
-            let conn = format!("conn-{}", i);
+            let conn = ConnId::new(i);
             i += 1;
-            let _ = channel.subscribe(&conn);
+            let _ = channel.subscribe(conn);
         });
     });
 
     group.bench_function("publish", |b| {
         let mut channel = Channel::new("test");
-        let _rx = channel.subscribe("conn-1");
+        let _rx = channel.subscribe(ConnId::new(1));
 
         b.iter(|| channel.publish_payload(black_box(vec![0u8; 64])));
     });
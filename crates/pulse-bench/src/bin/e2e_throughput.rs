@@ -1,24 +1,109 @@
 //! End-to-end throughput benchmark for Pulse.
 //!
 //! This benchmark measures actual WebSocket message throughput with real network I/O.
+//!
+//! It connects to `ws://` or `wss://` URLs, so the cost of terminating TLS in
+//! the server can be measured. With `--compare`, it runs against a second
+//! URL afterwards, typically the same server's TLS listener, and reports the
+//! throughput and latency delta between the two.
+//!
+//! `--json <PATH>` saves the first run's results, and `--baseline <PATH>`
+//! checks them against a saved run, exiting with status 1 if throughput or
+//! latency got worse by more than `--max-regression` (default 5%).
 
-use bytes::BytesMut;
-use futures_util::{SinkExt, StreamExt};
 use pulse_protocol::{codec, Frame};
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use rustls::{ClientConfig, RootCertStore};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
+use tenvis_pulse_bench::report::{self, Report};
+use tenvis_pulse_transport::websocket::{WebSocketClientConfig, WebSocketClientTransport};
+use tenvis_pulse_transport::Connection;
 use tokio::sync::Barrier;
-use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
 
 const SERVER_URL: &str = "ws://127.0.0.1:8080/ws";
 const WARMUP_SECS: u64 = 2;
 const BENCH_SECS: u64 = 10;
 
+/// Record the latency of one in this many received messages.
+const LATENCY_SAMPLE_EVERY: u64 = 16;
+
+const USAGE: &str = "\
+Usage: e2e_throughput [CLIENTS] [OPTIONS]
+
+Arguments:
+  [CLIENTS]          Number of clients (default: 16)
+
+Options:
+  --url <URL>        Server URL, ws:// or wss:// (default: ws://127.0.0.1:8080/ws)
+  --compare <URL>    Run again against this URL and report the difference
+  --ca <PEM>         Trust the certificates in this file for wss:// instead
+                     of the built-in roots, e.g. for a self-signed server
+  --json <PATH>      Save the results as JSON
+  --baseline <PATH>  Compare with results saved by --json and exit with
+                     status 1 on a regression
+  --max-regression <PCT>
+                     How much worse a metric may get than the baseline
+                     (default: 5%)";
+
+/// Where and how clients connect.
+#[derive(Clone)]
+struct Target {
+    url: String,
+    tls: Option<Arc<ClientConfig>>,
+}
+
 #[tokio::main]
-async fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    let num_clients = args.get(1).and_then(|s| s.parse().ok()).unwrap_or(16);
+async fn main() -> Result<ExitCode, Error> {
+    let mut num_clients = 16;
+    let mut url = SERVER_URL.to_string();
+    let mut compare = None;
+    let mut ca = None;
+    let mut json = None;
+    let mut baseline = None;
+    let mut max_regression = 0.05;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} requires a value"));
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(ExitCode::SUCCESS);
+            }
+            "--url" => url = value()?,
+            "--compare" => compare = Some(value()?),
+            "--ca" => ca = Some(value()?),
+            "--json" => json = Some(value()?),
+            "--baseline" => baseline = Some(value()?),
+            "--max-regression" => {
+                let value = value()?;
+                max_regression = report::parse_percent(&value).ok_or(format!(
+                    "--max-regression expects a percentage, got {value:?}"
+                ))?;
+            }
+            _ => match arg.parse() {
+                Ok(n) => num_clients = n,
+                Err(_) => return Err(format!("Unknown argument: {arg}\n\n{USAGE}").into()),
+            },
+        }
+    }
+
+    tenvis_pulse_bin_support::install_crypto_provider();
+    let tls = match ca {
+        Some(path) => Some(Arc::new(client_config(&path)?)),
+        None => None,
+    };
+    // Read the baseline first, so a bad path fails before the run
+    let baseline: Option<Report> = match &baseline {
+        Some(path) => Some(serde_json::from_str(&std::fs::read_to_string(path)?)?),
+        None => None,
+    };
 
     println!("╔══════════════════════════════════════════════════════════════╗");
     println!("║         Pulse End-to-End Throughput Benchmark                ║");
@@ -27,26 +112,101 @@ async fn main() {
     println!("╚══════════════════════════════════════════════════════════════╝");
     println!();
 
-    run_pubsub_benchmark(num_clients).await;
+    let base = Target {
+        url,
+        tls: tls.clone(),
+    };
+    let base_results = run_pubsub_benchmark(&base, num_clients).await;
+
+    if let Some(url) = compare {
+        println!();
+        let other = Target { url, tls };
+        let other_results = run_pubsub_benchmark(&other, num_clients).await;
+        print_comparison(&base_results, &other_results);
+    }
+
+    if let Some(path) = json {
+        std::fs::write(&path, serde_json::to_string_pretty(&base_results)?)?;
+        println!();
+        println!("Results saved to {path}");
+    }
+    match baseline {
+        Some(baseline) if !check_baseline(&base_results, &baseline, max_regression) => {
+            Ok(ExitCode::FAILURE)
+        }
+        _ => Ok(ExitCode::SUCCESS),
+    }
+}
+
+/// Print how a run compares with the baseline, returning whether every
+/// metric is within `max_regression`.
+fn check_baseline(results: &Report, baseline: &Report, max_regression: f64) -> bool {
+    let checks = results.compare(baseline, max_regression);
+    println!();
+    println!(
+        "Baseline comparison (max regression {:.1}%)",
+        max_regression * 100.0
+    );
+    for check in &checks {
+        println!(
+            "  {:<18}{:>14.2}{:>14.2}{:>+9.1}%  {}",
+            check.metric,
+            check.baseline,
+            check.current,
+            check.regression * 100.0,
+            if check.failed { "REGRESSED" } else { "ok" }
+        );
+    }
+    let passed = checks.iter().all(|check| !check.failed);
+    if passed {
+        println!("No regressions");
+    }
+    passed
+}
+
+/// Build a TLS config trusting the certificates in a PEM file.
+fn client_config(path: &str) -> Result<ClientConfig, Error> {
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(path)? {
+        roots.add(cert?)?;
+    }
+    if roots.is_empty() {
+        return Err(format!("No certificates in {path}").into());
+    }
+    Ok(ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth())
 }
 
-async fn run_pubsub_benchmark(num_clients: usize) {
-    println!("📊 Pub/Sub Benchmark: {} clients", num_clients);
+async fn run_pubsub_benchmark(target: &Target, num_clients: usize) -> Report {
+    println!(
+        "📊 Pub/Sub Benchmark: {} clients on {}",
+        num_clients, target.url
+    );
     println!("   Warmup: {}s, Measurement: {}s", WARMUP_SECS, BENCH_SECS);
     println!();
 
     let message_count = Arc::new(AtomicU64::new(0));
+    let measuring = Arc::new(AtomicBool::new(false));
+    let latencies = Arc::new(Mutex::new(Vec::new()));
     let barrier = Arc::new(Barrier::new(num_clients + 1));
+    let epoch = Instant::now();
 
     let mut handles = Vec::new();
 
     // Spawn client tasks
     for client_id in 0..num_clients {
-        let msg_count = Arc::clone(&message_count);
+        let client = Client {
+            target: target.clone(),
+            message_count: Arc::clone(&message_count),
+            measuring: Arc::clone(&measuring),
+            latencies: Arc::clone(&latencies),
+            epoch,
+        };
         let barrier = Arc::clone(&barrier);
 
         let handle = tokio::spawn(async move {
-            if let Err(e) = run_client(client_id, msg_count, barrier).await {
+            if let Err(e) = run_client(client_id, client, barrier).await {
                 eprintln!("Client {} error: {}", client_id, e);
             }
         });
@@ -63,18 +223,48 @@ async fn run_pubsub_benchmark(num_clients: usize) {
 
     // Reset counter and start measurement
     message_count.store(0, Ordering::SeqCst);
+    measuring.store(true, Ordering::SeqCst);
     let start = Instant::now();
+    let cpu_start = cpu_time();
 
     println!("📈 Measuring for {}s...", BENCH_SECS);
     tokio::time::sleep(Duration::from_secs(BENCH_SECS)).await;
 
+    measuring.store(false, Ordering::SeqCst);
     let elapsed = start.elapsed();
     let total_messages = message_count.load(Ordering::SeqCst);
+    let cpu_percent = cpu_start
+        .zip(cpu_time())
+        .map(|(start, end)| (end - start).as_secs_f64() / elapsed.as_secs_f64() * 100.0);
+
+    // Signal clients to stop
+    for handle in handles {
+        handle.abort();
+    }
 
     // Calculate throughput
     let msgs_per_sec = total_messages as f64 / elapsed.as_secs_f64();
     let msgs_per_sec_per_client = msgs_per_sec / num_clients as f64;
 
+    let mut latencies = std::mem::take(&mut *latencies.lock().unwrap());
+    latencies.sort_unstable();
+    let percentile = |q: f64| {
+        latencies
+            .get(((latencies.len().saturating_sub(1)) as f64 * q) as usize)
+            .map_or(Duration::ZERO, |&nanos| Duration::from_nanos(nanos))
+    };
+    let results = Report {
+        url: target.url.clone(),
+        clients: num_clients,
+        duration_secs: elapsed.as_secs_f64(),
+        messages: total_messages,
+        throughput: msgs_per_sec,
+        per_client: msgs_per_sec_per_client,
+        latency_p50_ms: millis(percentile(0.5)),
+        latency_p99_ms: millis(percentile(0.99)),
+        cpu_percent,
+    };
+
     println!();
     println!("╔══════════════════════════════════════════════════════════════╗");
     println!("║                         RESULTS                              ║");
@@ -93,74 +283,173 @@ async fn run_pubsub_benchmark(num_clients: usize) {
     );
     println!(
         "║  Throughput:           {:>10.0} msg/s                    ║",
-        msgs_per_sec
+        results.throughput
     );
     println!(
         "║  Per-Client:           {:>10.0} msg/s                    ║",
-        msgs_per_sec_per_client
+        results.per_client
     );
+    println!(
+        "║  Latency p50:          {:>10.2} ms                       ║",
+        results.latency_p50_ms
+    );
+    println!(
+        "║  Latency p99:          {:>10.2} ms                       ║",
+        results.latency_p99_ms
+    );
+    if let Some(cpu) = results.cpu_percent {
+        println!(
+            "║  Client CPU:           {:>10.0} %                        ║",
+            cpu
+        );
+    }
     println!("╚══════════════════════════════════════════════════════════════╝");
 
-    // Signal clients to stop
-    for handle in handles {
-        handle.abort();
+    results
+}
+
+/// Print two runs side by side with the change from the first.
+fn print_comparison(first: &Report, second: &Report) {
+    let delta = |a: f64, b: f64| {
+        if a == 0.0 {
+            "n/a".to_string()
+        } else {
+            format!("{:+.1}%", (b - a) / a * 100.0)
+        }
+    };
+
+    println!();
+    println!("Comparison");
+    println!("  A: {}", first.url);
+    println!("  B: {}", second.url);
+    println!();
+    println!("  {:<20}{:>14}{:>14}{:>10}", "", "A", "B", "B vs A");
+    println!(
+        "  {:<20}{:>14.0}{:>14.0}{:>10}",
+        "Throughput (msg/s)",
+        first.throughput,
+        second.throughput,
+        delta(first.throughput, second.throughput)
+    );
+    println!(
+        "  {:<20}{:>14.0}{:>14.0}{:>10}",
+        "Per-client (msg/s)",
+        first.per_client,
+        second.per_client,
+        delta(first.per_client, second.per_client)
+    );
+    for (label, a, b) in [
+        (
+            "Latency p50 (ms)",
+            first.latency_p50_ms,
+            second.latency_p50_ms,
+        ),
+        (
+            "Latency p99 (ms)",
+            first.latency_p99_ms,
+            second.latency_p99_ms,
+        ),
+    ] {
+        println!("  {:<20}{:>14.2}{:>14.2}{:>10}", label, a, b, delta(a, b));
     }
 }
 
-async fn run_client(
-    client_id: usize,
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// CPU time used by this process so far.
+#[cfg(unix)]
+fn cpu_time() -> Option<Duration> {
+    let time = rustix::time::clock_gettime(rustix::time::ClockId::ProcessCPUTime);
+    Some(Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+}
+
+/// CPU time used by this process so far.
+#[cfg(not(unix))]
+fn cpu_time() -> Option<Duration> {
+    None
+}
+
+/// State a client task shares with the benchmark.
+struct Client {
+    target: Target,
     message_count: Arc<AtomicU64>,
-    barrier: Arc<Barrier>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    measuring: Arc<AtomicBool>,
+    latencies: Arc<Mutex<Vec<u64>>>,
+    /// Publish payloads carry nanoseconds since this instant, so receivers
+    /// can measure delivery latency.
+    epoch: Instant,
+}
+
+async fn run_client(client_id: usize, client: Client, barrier: Arc<Barrier>) -> Result<(), Error> {
     // Connect to server
-    let (ws, _) = connect_async(SERVER_URL).await?;
-    let (mut sender, mut receiver) = ws.split();
+    let transport = WebSocketClientTransport::new(WebSocketClientConfig {
+        tls: client.target.tls.clone(),
+        ..WebSocketClientConfig::default()
+    });
+    let connection = transport.connect(&client.target.url).await?;
+    let (mut sender, mut receiver) = Box::new(connection).split();
 
     // Wait for Connected frame from server
-    if let Some(Ok(_connected)) = receiver.next().await {
+    if let Ok(Some(_connected)) = receiver.recv().await {
         // Got Connected frame
     }
 
     // Subscribe to broadcast channel using proper Pulse protocol
-    let subscribe_frame = Frame::subscribe(client_id as u64, "benchmark");
-    let subscribe_bytes = codec::encode(&subscribe_frame)?;
     sender
-        .send(Message::Binary(subscribe_bytes.to_vec()))
+        .send(Frame::subscribe(client_id as u64, "benchmark"))
         .await?;
 
     // Wait for Subscribe Ack
-    if let Some(Ok(_ack)) = receiver.next().await {
+    if let Ok(Some(_ack)) = receiver.recv().await {
         // Got Ack, subscription is ready
     }
 
     // Wait for all clients to be ready
     barrier.wait().await;
 
-    // Pre-encode the publish frame for efficiency
-    let payload = vec![0u8; 64];
-    let publish_frame = Frame::publish("benchmark", payload);
-    let publish_bytes = codec::encode(&publish_frame)?;
-    let publish_msg = Message::Binary(publish_bytes.to_vec());
-
     // Spawn separate receiver task for full-duplex operation
-    let recv_count = message_count.clone();
+    let Client {
+        message_count,
+        measuring,
+        latencies,
+        epoch,
+        ..
+    } = client;
     let recv_task = tokio::spawn(async move {
-        let mut recv_buf = BytesMut::with_capacity(65536);
-
-        while let Some(result) = receiver.next().await {
-            if let Ok(Message::Binary(data)) = result {
-                recv_buf.extend_from_slice(&data);
-                // Decode all complete frames
-                while let Ok(Some(_frame)) = codec::decode_from(&mut recv_buf) {
-                    recv_count.fetch_add(1, Ordering::Relaxed);
+        let mut received = 0u64;
+        let mut samples = Vec::new();
+
+        while let Ok(Some(frame)) = receiver.recv().await {
+            message_count.fetch_add(1, Ordering::Relaxed);
+            received += 1;
+            if received % LATENCY_SAMPLE_EVERY != 0 || !measuring.load(Ordering::Relaxed) {
+                continue;
+            }
+            if let Frame::Publish { payload, .. } = frame {
+                if let Some(sent) = payload.get(..8) {
+                    let sent = u64::from_le_bytes(sent.try_into().unwrap());
+                    let now = epoch.elapsed().as_nanos() as u64;
+                    samples.push(now.saturating_sub(sent));
                 }
             }
+            // Hand samples over in batches; the task is aborted when the
+            // run ends
+            if samples.len() >= 256 {
+                latencies.lock().unwrap().append(&mut samples);
+            }
         }
     });
 
-    // Send loop - no waiting, just blast messages
+    // Send loop - no waiting, just blast messages, each stamped with its
+    // send time
+    let mut payload = vec![0u8; 64];
     loop {
-        if sender.send(publish_msg.clone()).await.is_err() {
+        let sent = epoch.elapsed().as_nanos() as u64;
+        payload[..8].copy_from_slice(&sent.to_le_bytes());
+        let publish_bytes = codec::encode(&Frame::publish("benchmark", payload.clone()))?;
+        if sender.send_raw(publish_bytes).await.is_err() {
             break;
         }
         // Small yield to not starve the receiver task
@@ -0,0 +1,365 @@
+//! Soak test for Pulse.
+//!
+//! Churns connections, channels and presence against a running server for
+//! as long as it's told to, in rounds. Between rounds the clients go quiet
+//! and the server's `GET /stats` is sampled: every count must return to
+//! where it was before the first round, and resident memory must level off
+//! rather than keep growing. Exits with status 1 on the first leak.
+//!
+//! ```bash
+//! # Terminal 1
+//! cargo run --release -p tenvis-pulse-server
+//!
+//! # Terminal 2: soak for four hours in ten-minute rounds
+//! cargo run --release -p tenvis-pulse-bench --bin soak -- --duration 14400 --round 600
+//! ```
+
+use bytes::{Bytes, BytesMut};
+use futures_util::{SinkExt, StreamExt};
+use http_body_util::{BodyExt, Empty};
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use pulse_protocol::{codec, Frame};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tenvis_pulse_bench::soak::{RssTrend, Sample};
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Instant};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+const USAGE: &str = "\
+Usage: soak [OPTIONS]
+
+Options:
+  -u, --url <URL>            Server WebSocket URL (default: ws://127.0.0.1:8080/ws)
+      --stats-url <URL>      Stats endpoint (default: /stats on the server's host)
+      --duration <SECS>      How long to run (default: 3600)
+      --round <SECS>         Churn between samples (default: 60)
+      --workers <N>          Concurrent clients (default: 32)
+      --warmup <ROUNDS>      Rounds before memory growth is measured (default: 3)
+      --max-rss-growth <PCT> Memory growth after warmup that fails the run (default: 25)";
+
+/// Channels shared by all workers, so subscriptions overlap.
+const SHARED_CHANNELS: usize = 8;
+
+/// Presence channels shared by all workers.
+const PRESENCE_CHANNELS: usize = 4;
+
+/// How long to wait for any one reply.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the server gets to clean up after the clients go quiet.
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Options {
+    url: String,
+    stats_url: String,
+    duration: Duration,
+    round: Duration,
+    workers: usize,
+    warmup: usize,
+    max_rss_growth: f64,
+}
+
+fn parse_options() -> Result<Options, Error> {
+    let mut url = "ws://127.0.0.1:8080/ws".to_string();
+    let mut stats_url = None;
+    let mut options = Options {
+        url: String::new(),
+        stats_url: String::new(),
+        duration: Duration::from_secs(3600),
+        round: Duration::from_secs(60),
+        workers: 32,
+        warmup: 3,
+        max_rss_growth: 25.0,
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(flag) = args.next() {
+        if flag == "-h" || flag == "--help" {
+            println!("{USAGE}");
+            std::process::exit(0);
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{flag} requires a value\n\n{USAGE}"))?;
+        match flag.as_str() {
+            "-u" | "--url" => url = value,
+            "--stats-url" => stats_url = Some(value),
+            "--duration" => options.duration = Duration::from_secs(value.parse()?),
+            "--round" => options.round = Duration::from_secs(value.parse()?),
+            "--workers" => options.workers = value.parse()?,
+            "--warmup" => options.warmup = value.parse()?,
+            "--max-rss-growth" => options.max_rss_growth = value.parse()?,
+            _ => return Err(format!("Unknown argument: {flag}\n\n{USAGE}").into()),
+        }
+    }
+    if options.round.is_zero() || options.workers == 0 {
+        return Err("--round and --workers must be at least 1".into());
+    }
+
+    options.stats_url = match stats_url {
+        Some(stats_url) => stats_url,
+        None => {
+            let host = url
+                .strip_prefix("ws://")
+                .and_then(|rest| rest.split('/').next())
+                .ok_or("pass --stats-url for servers not on plain ws://")?;
+            format!("http://{host}/stats")
+        }
+    };
+    options.url = url;
+    Ok(options)
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let options = match parse_options() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match soak(&options).await {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("soak: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Run rounds until the duration is up, returning whether no leak was
+/// found.
+async fn soak(options: &Options) -> Result<bool, Error> {
+    let stats = StatsClient::new(&options.stats_url)?;
+    let baseline = stats.sample().await?;
+    println!(
+        "Soaking {} with {} workers for {}s in {}s rounds",
+        options.url,
+        options.workers,
+        options.duration.as_secs(),
+        options.round.as_secs()
+    );
+    println!("Baseline: {}", describe(&baseline));
+    if baseline.rss_bytes.is_none() {
+        println!("The server doesn't report RSS; only router state will be checked");
+    }
+
+    let deadline = Instant::now() + options.duration;
+    let mut trend = RssTrend::new(options.warmup);
+    let mut round = 0;
+    while Instant::now() < deadline {
+        round += 1;
+        let (cycles, errors) = churn(options, round).await;
+        if cycles == 0 {
+            return Err(format!("round {round}: every cycle failed ({errors} errors)").into());
+        }
+
+        let (sample, leftovers) = settle(&stats, &baseline).await?;
+        println!(
+            "Round {round}: {cycles} cycles, {errors} errors; at rest {}",
+            describe(&sample)
+        );
+        if !leftovers.is_empty() {
+            println!("LEAK: state outlived its clients: {}", leftovers.join(", "));
+            return Ok(false);
+        }
+
+        if let Some(rss) = sample.rss_bytes {
+            trend.push(rss);
+            if let Some(growth) = trend.growth() {
+                let percent = growth * 100.0;
+                if percent > options.max_rss_growth {
+                    println!(
+                        "LEAK: RSS grew {percent:.1}% since warmup (limit {}%)",
+                        options.max_rss_growth
+                    );
+                    return Ok(false);
+                }
+            }
+        }
+    }
+
+    println!("No leaks after {round} rounds");
+    Ok(true)
+}
+
+/// Run every worker for one round, returning completed and failed cycles.
+async fn churn(options: &Options, round: u64) -> (u64, u64) {
+    let cycles = Arc::new(AtomicU64::new(0));
+    let errors = Arc::new(AtomicU64::new(0));
+    let until = Instant::now() + options.round;
+
+    let mut handles = Vec::with_capacity(options.workers);
+    for worker in 0..options.workers {
+        let url = options.url.clone();
+        let cycles = Arc::clone(&cycles);
+        let errors = Arc::clone(&errors);
+        handles.push(tokio::spawn(async move {
+            let mut n = 0;
+            while Instant::now() < until {
+                n += 1;
+                let name = format!("{round}-{worker}-{n}");
+                match cycle(&url, &name, worker + n as usize).await {
+                    Ok(()) => cycles.fetch_add(1, Ordering::Relaxed),
+                    Err(_) => errors.fetch_add(1, Ordering::Relaxed),
+                };
+            }
+        }));
+    }
+    for handle in handles {
+        let _ = handle.await;
+    }
+    (
+        cycles.load(Ordering::Relaxed),
+        errors.load(Ordering::Relaxed),
+    )
+}
+
+/// Connect, join a shared, a private and a presence channel, publish, and
+/// leave in one of three ways: unsubscribing first, closing, or dropping
+/// the socket without a close handshake.
+async fn cycle(url: &str, name: &str, pick: usize) -> Result<(), Error> {
+    let (mut socket, _) = connect_async(url).await?;
+    let mut buf = BytesMut::new();
+    reply(&mut socket, &mut buf, |frame| {
+        matches!(frame, Frame::Connected { .. })
+    })
+    .await?;
+
+    let channels = [
+        format!("soak:shared:{}", pick % SHARED_CHANNELS),
+        format!("soak:{name}"),
+        format!("presence:soak:{}", pick % PRESENCE_CHANNELS),
+    ];
+    for (id, channel) in (1..).zip(&channels) {
+        send(&mut socket, &Frame::subscribe(id, channel)).await?;
+        reply(
+            &mut socket,
+            &mut buf,
+            |frame| matches!(frame, Frame::SubscribeOk { id: got, .. } if *got == id),
+        )
+        .await?;
+    }
+    for (id, channel) in (4..).zip(&channels[..2]) {
+        let publish = Frame::publish_with_ack(id, channel, name.as_bytes().to_vec());
+        send(&mut socket, &publish).await?;
+        reply(
+            &mut socket,
+            &mut buf,
+            |frame| matches!(frame, Frame::PublishOk { id: got, .. } if *got == id),
+        )
+        .await?;
+    }
+
+    match pick % 3 {
+        0 => {
+            for (id, channel) in (6..).zip(&channels) {
+                send(&mut socket, &Frame::unsubscribe(id, channel)).await?;
+                reply(
+                    &mut socket,
+                    &mut buf,
+                    |frame| matches!(frame, Frame::Ack { id: got } if *got == id),
+                )
+                .await?;
+            }
+            socket.close(None).await?;
+        }
+        1 => socket.close(None).await?,
+        _ => drop(socket),
+    }
+    Ok(())
+}
+
+async fn send(socket: &mut Socket, frame: &Frame) -> Result<(), Error> {
+    let bytes = codec::encode(frame)?;
+    socket.send(Message::Binary(bytes.to_vec())).await?;
+    Ok(())
+}
+
+/// Read frames until `want` accepts one, answering pings and failing on
+/// errors.
+async fn reply(
+    socket: &mut Socket,
+    buf: &mut BytesMut,
+    want: impl Fn(&Frame) -> bool,
+) -> Result<(), Error> {
+    let deadline = Instant::now() + REPLY_TIMEOUT;
+    loop {
+        while let Some(frame) = codec::decode_from(buf)? {
+            if want(&frame) {
+                return Ok(());
+            }
+            match frame {
+                Frame::Ping { timestamp } => send(socket, &Frame::pong(timestamp)).await?,
+                Frame::Error { message, .. } => return Err(message.into()),
+                _ => {}
+            }
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match timeout(remaining, socket.next()).await? {
+            Some(Ok(Message::Binary(data))) => buf.extend_from_slice(&data),
+            Some(Ok(_)) => {}
+            Some(Err(e)) => return Err(e.into()),
+            None => return Err("server closed the connection".into()),
+        }
+    }
+}
+
+/// Wait for the server to clean up after the last round, returning the
+/// sample and whatever state is still held beyond the baseline.
+async fn settle(stats: &StatsClient, baseline: &Sample) -> Result<(Sample, Vec<String>), Error> {
+    let deadline = Instant::now() + SETTLE_TIMEOUT;
+    loop {
+        let sample = stats.sample().await?;
+        let leftovers = sample.leftovers(baseline);
+        if leftovers.is_empty() || Instant::now() >= deadline {
+            return Ok((sample, leftovers));
+        }
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+}
+
+fn describe(sample: &Sample) -> String {
+    let rss = match sample.rss_bytes {
+        Some(bytes) => format!("{:.1} MiB RSS", bytes as f64 / (1024.0 * 1024.0)),
+        None => "RSS unknown".to_string(),
+    };
+    format!(
+        "{} connections, {} channels, {} subscriptions, {rss}",
+        sample.connections, sample.channels, sample.subscriptions
+    )
+}
+
+/// Reads the server's `GET /stats`.
+struct StatsClient {
+    client: Client<hyper_util::client::legacy::connect::HttpConnector, Empty<Bytes>>,
+    url: hyper::Uri,
+}
+
+impl StatsClient {
+    fn new(url: &str) -> Result<Self, Error> {
+        Ok(Self {
+            client: Client::builder(TokioExecutor::new()).build_http(),
+            url: url.parse()?,
+        })
+    }
+
+    async fn sample(&self) -> Result<Sample, Error> {
+        let response = self.client.get(self.url.clone()).await?;
+        if !response.status().is_success() {
+            return Err(format!("{} returned {}", self.url, response.status()).into());
+        }
+        let body = response.into_body().collect().await?.to_bytes();
+        let stats: serde_json::Value = serde_json::from_slice(&body)?;
+        Sample::from_stats(&stats).ok_or_else(|| format!("{} has no router stats", self.url).into())
+    }
+}
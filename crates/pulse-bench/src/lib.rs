@@ -2,3 +2,7 @@
 //!
 //! This crate contains performance benchmarks for the Pulse realtime engine.
 //! Run benchmarks with: `cargo bench -p pulse-bench`
+
+pub mod report;
+pub mod soak;
+pub mod zipf;
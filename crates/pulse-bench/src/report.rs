@@ -0,0 +1,164 @@
+//! Machine-readable results for the end-to-end benchmark
+//! (`src/bin/e2e_throughput.rs`) and the regression gate that compares them.
+//!
+//! `e2e_throughput --json results.json` saves a [`Report`]; a later run with
+//! `--baseline results.json` checks itself against it with
+//! [`Report::compare`] and fails if any metric got worse by more than the
+//! allowed fraction.
+
+use serde::{Deserialize, Serialize};
+
+/// Results of one benchmark run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    /// Server URL the clients connected to.
+    pub url: String,
+    /// Number of clients.
+    pub clients: usize,
+    /// Length of the measurement window in seconds.
+    pub duration_secs: f64,
+    /// Messages received by all clients during the window.
+    pub messages: u64,
+    /// Messages received per second, across clients.
+    pub throughput: f64,
+    /// Messages received per second, per client.
+    pub per_client: f64,
+    /// Median delivery latency in milliseconds.
+    pub latency_p50_ms: f64,
+    /// 99th percentile delivery latency in milliseconds.
+    pub latency_p99_ms: f64,
+    /// CPU used by the benchmark process, as a percentage of one core,
+    /// where the platform reports it.
+    #[serde(default)]
+    pub cpu_percent: Option<f64>,
+}
+
+/// One metric compared against the baseline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    /// Metric name, as in the JSON.
+    pub metric: &'static str,
+    /// Value in the baseline.
+    pub baseline: f64,
+    /// Value in this run.
+    pub current: f64,
+    /// How much worse this run is, as a fraction of the baseline; negative
+    /// when it improved.
+    pub regression: f64,
+    /// Whether the regression exceeds the allowed fraction.
+    pub failed: bool,
+}
+
+impl Report {
+    /// Compare throughput and latency with `baseline`, allowing each to get
+    /// worse by `max_regression` (0.05 is 5%).
+    ///
+    /// Metrics that are zero in the baseline can't be compared and are
+    /// skipped. CPU is reported but not checked, since it depends on the
+    /// machine running the clients as much as on the server.
+    #[must_use]
+    pub fn compare(&self, baseline: &Report, max_regression: f64) -> Vec<Check> {
+        // (name, baseline, current, higher is better)
+        let metrics = [
+            ("throughput", baseline.throughput, self.throughput, true),
+            (
+                "latency_p50_ms",
+                baseline.latency_p50_ms,
+                self.latency_p50_ms,
+                false,
+            ),
+            (
+                "latency_p99_ms",
+                baseline.latency_p99_ms,
+                self.latency_p99_ms,
+                false,
+            ),
+        ];
+        metrics
+            .into_iter()
+            .filter(|(_, baseline, _, _)| *baseline > 0.0)
+            .map(|(metric, baseline, current, higher_is_better)| {
+                let change = (current - baseline) / baseline;
+                let regression = if higher_is_better { -change } else { change };
+                Check {
+                    metric,
+                    baseline,
+                    current,
+                    regression,
+                    failed: regression > max_regression,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Parse a percentage such as `5%` or `5` into a fraction.
+#[must_use]
+pub fn parse_percent(s: &str) -> Option<f64> {
+    let percent: f64 = s.strip_suffix('%').unwrap_or(s).trim().parse().ok()?;
+    (percent.is_finite() && percent >= 0.0).then_some(percent / 100.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(throughput: f64, p50: f64, p99: f64) -> Report {
+        Report {
+            url: "ws://127.0.0.1:8080/ws".to_string(),
+            clients: 16,
+            duration_secs: 10.0,
+            messages: (throughput * 10.0) as u64,
+            throughput,
+            per_client: throughput / 16.0,
+            latency_p50_ms: p50,
+            latency_p99_ms: p99,
+            cpu_percent: None,
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        let baseline = report(100_000.0, 2.0, 10.0);
+
+        // Within 5% everywhere, and faster
+        let checks = report(97_000.0, 1.5, 10.4).compare(&baseline, 0.05);
+        assert_eq!(checks.len(), 3);
+        assert!(checks.iter().all(|check| !check.failed));
+        assert!((checks[0].regression - 0.03).abs() < 1e-9);
+        assert!(checks[1].regression < 0.0);
+
+        // Throughput down 10%, p99 up 20%
+        let checks = report(90_000.0, 2.0, 12.0).compare(&baseline, 0.05);
+        let failed: Vec<_> = checks
+            .iter()
+            .filter(|c| c.failed)
+            .map(|c| c.metric)
+            .collect();
+        assert_eq!(failed, ["throughput", "latency_p99_ms"]);
+
+        // Nothing to compare against
+        let checks = report(1.0, 1.0, 1.0).compare(&report(0.0, 0.0, 0.0), 0.05);
+        assert!(checks.is_empty());
+    }
+
+    #[test]
+    fn test_report_json() {
+        let report = report(100_000.0, 2.0, 10.0);
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(serde_json::from_str::<Report>(&json).unwrap(), report);
+
+        // Reports saved without CPU still load
+        let mut value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        value.as_object_mut().unwrap().remove("cpu_percent");
+        assert_eq!(serde_json::from_value::<Report>(value).unwrap(), report);
+    }
+
+    #[test]
+    fn test_parse_percent() {
+        assert_eq!(parse_percent("5%"), Some(0.05));
+        assert_eq!(parse_percent("12.5"), Some(0.125));
+        assert_eq!(parse_percent("-1%"), None);
+        assert_eq!(parse_percent("fast"), None);
+    }
+}
@@ -0,0 +1,163 @@
+//! Leak checks for the soak test (`src/bin/soak.rs`).
+//!
+//! The soak test runs rounds of connection, channel and presence churn
+//! against a server and samples `GET /stats` whenever it goes quiet between
+//! rounds. Every round should leave the server as it found it:
+//! [`Sample::leftovers`] reports router state that outlived its clients,
+//! such as orphaned subscription sets, and [`RssTrend`] catches resident
+//! memory that keeps climbing from one quiet period to the next.
+
+use serde_json::Value;
+
+/// Server state read from `GET /stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Sample {
+    /// Connected clients.
+    pub connections: u64,
+    /// Channels in the router.
+    pub channels: u64,
+    /// Subscriptions across all channels.
+    pub subscriptions: u64,
+    /// Estimated bytes held by presence members.
+    pub presence_bytes: u64,
+    /// Messages waiting in connection queues.
+    pub queued: u64,
+    /// Resident set size, where the server reports it.
+    pub rss_bytes: Option<u64>,
+}
+
+impl Sample {
+    /// Read a sample from a `GET /stats` response body.
+    ///
+    /// Returns `None` if a router count is missing.
+    #[must_use]
+    pub fn from_stats(stats: &Value) -> Option<Self> {
+        let router = &stats["router"];
+        let memory = &stats["memory"];
+        Some(Self {
+            connections: router["connection_count"].as_u64()?,
+            channels: router["channel_count"].as_u64()?,
+            subscriptions: router["total_subscriptions"].as_u64()?,
+            presence_bytes: memory["router"]["presence"].as_u64().unwrap_or(0),
+            queued: memory["queued_messages"].as_u64().unwrap_or(0),
+            rss_bytes: memory["rss_bytes"].as_u64(),
+        })
+    }
+
+    /// Describe the router state held beyond `baseline`, one entry per
+    /// count; empty when nothing outlived its clients.
+    #[must_use]
+    pub fn leftovers(&self, baseline: &Sample) -> Vec<String> {
+        let counts = [
+            ("connections", self.connections, baseline.connections),
+            ("channels", self.channels, baseline.channels),
+            ("subscriptions", self.subscriptions, baseline.subscriptions),
+            (
+                "presence bytes",
+                self.presence_bytes,
+                baseline.presence_bytes,
+            ),
+            ("queued messages", self.queued, baseline.queued),
+        ];
+        counts
+            .into_iter()
+            .filter(|(_, now, then)| now > then)
+            .map(|(name, now, then)| format!("{} {name} (baseline {then})", now - then))
+            .collect()
+    }
+}
+
+/// Resident memory sampled at rest after each round.
+///
+/// Allocators keep freed memory around, so RSS rises over the first rounds
+/// and then levels off. Samples from the first `warmup` rounds are ignored;
+/// the rest are measured against the first one after them, using the
+/// median of the latest three so a single spike doesn't fail a run.
+#[derive(Debug, Clone)]
+pub struct RssTrend {
+    warmup: usize,
+    samples: Vec<u64>,
+}
+
+impl RssTrend {
+    /// Create a trend that ignores the first `warmup` samples.
+    #[must_use]
+    pub fn new(warmup: usize) -> Self {
+        Self {
+            warmup,
+            samples: Vec::new(),
+        }
+    }
+
+    /// Record a sample.
+    pub fn push(&mut self, rss_bytes: u64) {
+        self.samples.push(rss_bytes);
+    }
+
+    /// Growth since the end of warmup as a fraction (0.1 is 10%), or `None`
+    /// until there are samples to compare.
+    #[must_use]
+    pub fn growth(&self) -> Option<f64> {
+        let measured = self.samples.get(self.warmup..)?;
+        let (&reference, rest) = measured.split_first()?;
+        if rest.is_empty() || reference == 0 {
+            return None;
+        }
+        let mut latest: Vec<u64> = measured.iter().rev().take(3).copied().collect();
+        latest.sort_unstable();
+        let current = latest[latest.len() / 2];
+        Some((current as f64 - reference as f64) / reference as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_sample_leftovers() {
+        let stats = json!({
+            "router": {"channel_count": 3, "connection_count": 0, "total_subscriptions": 2},
+            "memory": {"rss_bytes": 4096, "queued_messages": 0, "router": {"presence": 0}},
+        });
+        let baseline = Sample::from_stats(&stats).unwrap();
+        assert_eq!(baseline.channels, 3);
+        assert_eq!(baseline.rss_bytes, Some(4096));
+        assert!(baseline.leftovers(&baseline).is_empty());
+
+        let after = Sample {
+            channels: 5,
+            subscriptions: 1,
+            presence_bytes: 120,
+            ..baseline
+        };
+        assert_eq!(
+            after.leftovers(&baseline),
+            ["2 channels (baseline 3)", "120 presence bytes (baseline 0)"]
+        );
+
+        assert!(Sample::from_stats(&json!({"router": {}})).is_none());
+    }
+
+    #[test]
+    fn test_rss_trend() {
+        let mut trend = RssTrend::new(2);
+        for rss in [10, 50, 100] {
+            trend.push(rss);
+        }
+        assert_eq!(trend.growth(), None);
+
+        // Levels off, with one spike
+        for rss in [104, 180, 102] {
+            trend.push(rss);
+        }
+        assert!(trend.growth().unwrap() < 0.05);
+
+        // Keeps climbing
+        for rss in [140, 160, 180] {
+            trend.push(rss);
+        }
+        assert!(trend.growth().unwrap() > 0.5);
+    }
+}
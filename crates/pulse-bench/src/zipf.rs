@@ -0,0 +1,116 @@
+//! Skewed popularity for benchmark workloads.
+//!
+//! Real channel traffic is far from uniform: a handful of rooms get most of
+//! the messages and subscribers while the long tail is nearly idle. [`Zipf`]
+//! picks channel ranks with probability proportional to `1 / rank^s`, and
+//! [`Rng`] drives it deterministically so runs are comparable.
+
+/// A Zipf distribution over ranks `0..n`, rank 0 the most popular.
+#[derive(Debug, Clone)]
+pub struct Zipf {
+    cdf: Vec<f64>,
+}
+
+impl Zipf {
+    /// Create a distribution over `n` ranks with exponent `s`; 1.0 is the
+    /// classic Zipf law, larger values skew harder.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is zero.
+    #[must_use]
+    pub fn new(n: usize, s: f64) -> Self {
+        assert!(n > 0, "Zipf needs at least one rank");
+        let mut total = 0.0;
+        let mut cdf: Vec<f64> = (1..=n)
+            .map(|rank| {
+                total += 1.0 / (rank as f64).powf(s);
+                total
+            })
+            .collect();
+        for p in &mut cdf {
+            *p /= total;
+        }
+        Self { cdf }
+    }
+
+    /// Number of ranks.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.cdf.len()
+    }
+
+    /// Whether there are no ranks; never true.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.cdf.is_empty()
+    }
+
+    /// Pick a rank.
+    pub fn sample(&self, rng: &mut Rng) -> usize {
+        let u = rng.next_f64();
+        self.cdf.partition_point(|&p| p < u).min(self.cdf.len() - 1)
+    }
+}
+
+/// A small seeded generator (SplitMix64), good enough for workloads.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    /// Create a generator from a seed.
+    #[must_use]
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    /// Next 64 random bits.
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Next number in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zipf() {
+        let zipf = Zipf::new(1000, 1.0);
+        let mut rng = Rng::new(7);
+        let mut counts = vec![0u32; zipf.len()];
+        for _ in 0..100_000 {
+            counts[zipf.sample(&mut rng)] += 1;
+        }
+
+        // Rank 0 gets about 1/H(1000), 13%, and twice rank 1's share
+        assert!((12_000..15_000).contains(&counts[0]), "{}", counts[0]);
+        let ratio = f64::from(counts[0]) / f64::from(counts[1]);
+        assert!((1.8..2.2).contains(&ratio), "{ratio}");
+        // The top 10 ranks outweigh the bottom 500
+        let head: u32 = counts[..10].iter().sum();
+        let tail: u32 = counts[500..].iter().sum();
+        assert!(head > tail);
+
+        assert_eq!(Zipf::new(1, 1.0).sample(&mut rng), 0);
+    }
+
+    #[test]
+    fn test_rng_deterministic() {
+        let mut a = Rng::new(1);
+        let mut b = Rng::new(1);
+        assert!((0..100).all(|_| a.next_u64() == b.next_u64()));
+        assert!((0..1000)
+            .map(|_| a.next_f64())
+            .all(|x| (0.0..1.0).contains(&x)));
+    }
+}
@@ -0,0 +1,116 @@
+//! Command-line scanning shared by the Pulse binaries.
+//!
+//! [`Args`] walks the arguments one flag or operand at a time, accepting
+//! both `--flag value` and `--flag=value`. What each flag means stays with
+//! the binary; this only splits the words up.
+//!
+//! ```rust
+//! use tenvis_pulse_bin_support::Args;
+//!
+//! let mut args = Args::new(["--url=ws://pulse/ws", "--json", "chat"].map(String::from));
+//! let (mut url, mut json, mut operands) = (None, false, Vec::new());
+//! while let Some(flag) = args.next() {
+//!     match flag.as_str() {
+//!         "--url" => url = Some(args.value()?),
+//!         "--json" => json = true,
+//!         _ => operands.push(flag),
+//!     }
+//! }
+//! assert_eq!(url.as_deref(), Some("ws://pulse/ws"));
+//! assert!(json);
+//! assert_eq!(operands, ["chat"]);
+//! # Ok::<(), tenvis_pulse_bin_support::MissingValue>(())
+//! ```
+
+use thiserror::Error;
+
+/// A flag was given without the value it takes.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{0} requires a value")]
+pub struct MissingValue(pub String);
+
+/// Command-line arguments, excluding the program name, split into flags and
+/// operands.
+#[derive(Debug)]
+pub struct Args<I> {
+    args: I,
+    /// The last flag returned, for error messages.
+    flag: String,
+    /// The value given to it with `=`, if not taken yet.
+    inline: Option<String>,
+}
+
+impl<I: Iterator<Item = String>> Args<I> {
+    /// Scan `args`.
+    pub fn new(args: impl IntoIterator<Item = String, IntoIter = I>) -> Self {
+        Self {
+            args: args.into_iter(),
+            flag: String::new(),
+            inline: None,
+        }
+    }
+
+    /// Take the value of the flag last returned: the part after `=`, or
+    /// else the next argument.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MissingValue`] if the arguments ran out.
+    pub fn value(&mut self) -> Result<String, MissingValue> {
+        self.inline
+            .take()
+            .or_else(|| self.args.next())
+            .ok_or_else(|| MissingValue(self.flag.clone()))
+    }
+}
+
+impl<I: Iterator<Item = String>> Iterator for Args<I> {
+    type Item = String;
+
+    /// The next flag, without any `=value`, or operand. A value given with
+    /// `=` to a flag that takes none is dropped.
+    fn next(&mut self) -> Option<String> {
+        let arg = self.args.next()?;
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => {
+                (flag.to_string(), Some(value.to_string()))
+            }
+            _ => (arg, None),
+        };
+        self.flag.clone_from(&flag);
+        self.inline = inline;
+        Some(flag)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Args<impl Iterator<Item = String> + '_> {
+        Args::new(line.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn test_args() {
+        let mut scanned = args("--port=9000 -c pulse.toml --json=yes operand -x=1");
+        assert_eq!(scanned.next().as_deref(), Some("--port"));
+        assert_eq!(scanned.value().unwrap(), "9000");
+        assert_eq!(scanned.next().as_deref(), Some("-c"));
+        assert_eq!(scanned.value().unwrap(), "pulse.toml");
+        // An unused inline value doesn't leak into the next flag
+        assert_eq!(scanned.next().as_deref(), Some("--json"));
+        assert_eq!(scanned.next().as_deref(), Some("operand"));
+        // Short flags keep their `=`
+        assert_eq!(scanned.next().as_deref(), Some("-x=1"));
+        assert_eq!(scanned.next(), None);
+
+        let mut scanned = args("--url");
+        scanned.next();
+        assert_eq!(scanned.value(), Err(MissingValue("--url".to_string())));
+        assert_eq!(
+            scanned.value().unwrap_err().to_string(),
+            "--url requires a value"
+        );
+    }
+}
@@ -0,0 +1,20 @@
+//! # pulse-bin-support
+//!
+//! Helpers shared by the Pulse client binaries (`pulse-cli`,
+//! `pulse-conformance` and the benchmarks), so each doesn't carry its own
+//! copy. Nothing here is needed to embed Pulse; the library crates don't
+//! depend on it.
+//!
+//! - [`Args`] scans command-line flags and their values.
+//! - [`install_crypto_provider`] sets up rustls for `wss://` URLs.
+
+pub mod args;
+
+pub use args::{Args, MissingValue};
+
+/// Make aws-lc-rs the process's rustls crypto provider, which `wss://`
+/// connections and `rustls::ClientConfig::builder` need. Does nothing if a
+/// provider is installed already.
+pub fn install_crypto_provider() {
+    let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+}
@@ -0,0 +1,128 @@
+//! `pulse-cli bench`: publish throughput through a running server.
+//!
+//! Every client subscribes to one channel and publishes to it as fast as the
+//! server accepts, so each message fans out to every client. Sent and
+//! received messages are counted after a short warmup.
+
+use crate::cli::BenchArgs;
+use crate::client::Client;
+use crate::output::Printer;
+use anyhow::Result;
+use futures_util::future::try_join_all;
+use pulse_protocol::{codec, Frame};
+use serde_json::json;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tenvis_pulse_transport::Connection;
+
+/// Time to run before counting, so connection setup does not skew results.
+const WARMUP: Duration = Duration::from_secs(1);
+
+/// Messages counted across all clients.
+#[derive(Default)]
+struct Counters {
+    sent: AtomicU64,
+    received: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl Counters {
+    fn reset(&self) {
+        self.sent.store(0, Ordering::Relaxed);
+        self.received.store(0, Ordering::Relaxed);
+        self.errors.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Run the benchmark and print its results.
+///
+/// # Errors
+///
+/// Returns an error if a client cannot connect or subscribe.
+pub async fn run(url: &str, token: Option<&str>, args: &BenchArgs, printer: Printer) -> Result<()> {
+    eprintln!("Connecting {} clients to {url}", args.clients);
+    let clients = try_join_all((0..args.clients).map(|_| async {
+        let mut client = Client::connect(url, token).await?;
+        let id = client.next_id();
+        client
+            .send(&Frame::subscribe(id, args.channel.as_str()))
+            .await?;
+        client.reply(id).await?;
+        anyhow::Ok(client)
+    }))
+    .await?;
+
+    let counters = Arc::new(Counters::default());
+    let publish = codec::encode(&Frame::publish(args.channel.as_str(), vec![0u8; args.size]))?;
+    let mut tasks = Vec::new();
+    for client in clients {
+        let (mut sender, mut receiver) = Box::new(client.into_socket()).split();
+
+        let counters_rx = Arc::clone(&counters);
+        tasks.push(tokio::spawn(async move {
+            while let Ok(Some(frame)) = receiver.recv().await {
+                let counter = match frame {
+                    Frame::Publish { .. } => &counters_rx.received,
+                    Frame::Error { .. } => &counters_rx.errors,
+                    _ => continue,
+                };
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        }));
+
+        let counters_tx = Arc::clone(&counters);
+        let publish = publish.clone();
+        tasks.push(tokio::spawn(async move {
+            while sender.send_raw(publish.clone()).await.is_ok() {
+                counters_tx.sent.fetch_add(1, Ordering::Relaxed);
+                // Let the receiving task keep up
+                tokio::task::yield_now().await;
+            }
+        }));
+    }
+
+    tokio::time::sleep(WARMUP).await;
+    counters.reset();
+    let start = Instant::now();
+    eprintln!("Measuring for {}s", args.duration);
+    tokio::time::sleep(Duration::from_secs(args.duration)).await;
+    let elapsed = start.elapsed().as_secs_f64();
+    let sent = counters.sent.load(Ordering::Relaxed);
+    let received = counters.received.load(Ordering::Relaxed);
+    let errors = counters.errors.load(Ordering::Relaxed);
+    for task in tasks {
+        task.abort();
+    }
+
+    let rate = |n: u64| n as f64 / elapsed;
+    let value = json!({
+        "type": "bench",
+        "clients": args.clients,
+        "channel": args.channel,
+        "size": args.size,
+        "duration": elapsed,
+        "sent": sent,
+        "received": received,
+        "errors": errors,
+        "sent_per_sec": rate(sent),
+        "received_per_sec": rate(received),
+    });
+    printer.value(&value, || {
+        format!(
+            "Clients:   {:>12}\n\
+             Duration:  {:>12.2} s\n\
+             Sent:      {:>12} ({:.0} msg/s)\n\
+             Received:  {:>12} ({:.0} msg/s)\n\
+             Errors:    {:>12}",
+            args.clients,
+            elapsed,
+            sent,
+            rate(sent),
+            received,
+            rate(received),
+            errors,
+        )
+    });
+    Ok(())
+}
@@ -0,0 +1,388 @@
+//! Command-line arguments.
+//!
+//! Global options go before or after the command; a command's own options
+//! go after it, e.g. `pulse-cli --json sub chat:lobby --last 10`.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::cmp::Ordering;
+use std::str::FromStr;
+use tenvis_pulse_bin_support::Args as Flags;
+
+/// Server URL used when neither `--url` nor `PULSE_URL` is given.
+pub const DEFAULT_URL: &str = "ws://127.0.0.1:8080/ws";
+
+/// Help text for `--help`.
+pub const USAGE: &str = "\
+Usage: pulse-cli [OPTIONS] <COMMAND>
+
+Commands:
+  sub <CHANNEL>...          Print messages published to channels
+  pub <CHANNEL> <PAYLOAD>   Publish a message and print the server's receipt
+  presence <CHANNEL>        Print the members of a presence channel
+  bench                     Measure publish throughput through the server
+  replay <FILE>             Publish a traffic capture again, with its pacing
+
+Options:
+  -u, --url <URL>           Server WebSocket URL (default: ws://127.0.0.1:8080/ws)
+      --token <TOKEN>       Token to send in the Connect frame
+      --json                Print one JSON object per line
+  -h, --help                Print help
+  -V, --version             Print version
+
+sub options:
+      --last <N>            Replay up to N retained messages first
+  -n, --count <N>           Exit after N messages
+
+pub options:
+      --event <NAME>        Event name
+      --trace               Trace delivery to $system:trace (needs the admin
+                            token)
+
+presence options:
+  -w, --watch               Keep printing joins, updates and leaves
+
+bench options:
+      --clients <N>         Connections publishing and subscribing (default: 16)
+      --duration <SECS>     Seconds to measure for (default: 10)
+      --size <BYTES>        Payload size (default: 64)
+      --channel <NAME>      Channel to publish to (default: bench)
+
+replay options:
+      --speed <FACTOR>      Publish faster (2) or slower (0.5) than captured
+                            (default: 1)
+      --prefix <PREFIX>     Prepend to every channel name
+
+The URL is also read from PULSE_URL. `presence` adds the `presence:` prefix
+to channels that lack it.";
+
+/// What the command line asks for.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Run a client command.
+    Run(Args),
+    /// Print help and exit.
+    Help,
+    /// Print the version and exit.
+    Version,
+}
+
+/// Options for running a client command.
+#[derive(Debug, PartialEq)]
+pub struct Args {
+    /// Server URL given with `--url`.
+    pub url: Option<String>,
+    /// Token to send in the Connect frame.
+    pub token: Option<String>,
+    /// Print JSON lines instead of text.
+    pub json: bool,
+    /// The command to run.
+    pub action: Action,
+}
+
+/// A client command.
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    /// Subscribe and print messages.
+    Sub {
+        /// Channels to subscribe to.
+        channels: Vec<String>,
+        /// Retained messages to replay first.
+        last: Option<u32>,
+        /// Messages to print before exiting.
+        count: Option<u64>,
+    },
+    /// Publish one message.
+    Pub {
+        /// Channel to publish to.
+        channel: String,
+        /// Message payload.
+        payload: String,
+        /// Event name.
+        event: Option<String>,
+        /// Ask the server to trace the message.
+        trace: bool,
+    },
+    /// Print presence members.
+    Presence {
+        /// Presence channel.
+        channel: String,
+        /// Keep printing changes after the snapshot.
+        watch: bool,
+    },
+    /// Measure throughput.
+    Bench(BenchArgs),
+    /// Publish a traffic capture.
+    Replay(ReplayArgs),
+}
+
+/// Options for `bench`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BenchArgs {
+    /// Number of connections.
+    pub clients: usize,
+    /// Seconds to measure for.
+    pub duration: u64,
+    /// Payload size in bytes.
+    pub size: usize,
+    /// Channel to publish to.
+    pub channel: String,
+}
+
+impl Default for BenchArgs {
+    fn default() -> Self {
+        Self {
+            clients: 16,
+            duration: 10,
+            size: 64,
+            channel: "bench".to_string(),
+        }
+    }
+}
+
+/// Options for `replay`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayArgs {
+    /// Capture file.
+    pub file: String,
+    /// How many times faster than captured to publish.
+    pub speed: f64,
+    /// Prefix added to every channel name.
+    pub prefix: Option<String>,
+}
+
+impl Action {
+    /// Start a command from its name, with default options.
+    fn named(name: &str) -> Result<Self> {
+        Ok(match name {
+            "sub" => Action::Sub {
+                channels: Vec::new(),
+                last: None,
+                count: None,
+            },
+            "pub" => Action::Pub {
+                channel: String::new(),
+                payload: String::new(),
+                event: None,
+                trace: false,
+            },
+            "presence" => Action::Presence {
+                channel: String::new(),
+                watch: false,
+            },
+            "bench" => Action::Bench(BenchArgs::default()),
+            "replay" => Action::Replay(ReplayArgs {
+                file: String::new(),
+                speed: 1.0,
+                prefix: None,
+            }),
+            _ => bail!("Unknown command: {name}\n\n{USAGE}"),
+        })
+    }
+
+    /// Fill in the command's operands.
+    fn finish(mut self, operands: Vec<String>) -> Result<Self> {
+        match &mut self {
+            Action::Sub { channels, .. } => {
+                if operands.is_empty() {
+                    bail!("sub requires at least one channel");
+                }
+                *channels = operands;
+            }
+            Action::Pub {
+                channel, payload, ..
+            } => {
+                let [c, p] = <[String; 2]>::try_from(operands)
+                    .map_err(|_| anyhow!("pub requires a channel and a payload"))?;
+                (*channel, *payload) = (c, p);
+            }
+            Action::Presence { channel, .. } => {
+                let [c] = <[String; 1]>::try_from(operands)
+                    .map_err(|_| anyhow!("presence requires one channel"))?;
+                *channel = if c.starts_with("presence:") {
+                    c
+                } else {
+                    format!("presence:{c}")
+                };
+            }
+            Action::Replay(replay) => {
+                let [file] = <[String; 1]>::try_from(operands)
+                    .map_err(|_| anyhow!("replay requires one capture file"))?;
+                replay.file = file;
+            }
+            Action::Bench(_) => {
+                if let Some(operand) = operands.first() {
+                    bail!("Unexpected argument: {operand}");
+                }
+            }
+        }
+        Ok(self)
+    }
+}
+
+/// Parse arguments, excluding the program name.
+///
+/// # Errors
+///
+/// Returns an error for unknown commands or flags, missing values, and
+/// missing or extra operands.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command> {
+    let (mut url, mut token, mut json) = (None, None, false);
+    let mut action: Option<Action> = None;
+    let mut operands = Vec::new();
+    let mut args = Flags::new(args);
+
+    while let Some(flag) = args.next() {
+        match (flag.as_str(), &mut action) {
+            ("-h" | "--help", _) => return Ok(Command::Help),
+            ("-V" | "--version", _) => return Ok(Command::Version),
+            ("-u" | "--url", _) => url = Some(args.value()?),
+            ("--token", _) => token = Some(args.value()?),
+            ("--json", _) => json = true,
+            ("--last", Some(Action::Sub { last, .. })) => {
+                *last = Some(number(&flag, &args.value()?)?);
+            }
+            ("-n" | "--count", Some(Action::Sub { count, .. })) => {
+                *count = Some(number(&flag, &args.value()?)?);
+            }
+            ("--event", Some(Action::Pub { event, .. })) => *event = Some(args.value()?),
+            ("--trace", Some(Action::Pub { trace, .. })) => *trace = true,
+            ("-w" | "--watch", Some(Action::Presence { watch, .. })) => *watch = true,
+            ("--clients", Some(Action::Bench(bench))) => {
+                bench.clients = number(&flag, &args.value()?)?;
+            }
+            ("--duration", Some(Action::Bench(bench))) => {
+                bench.duration = number(&flag, &args.value()?)?;
+            }
+            ("--size", Some(Action::Bench(bench))) => bench.size = number(&flag, &args.value()?)?,
+            ("--channel", Some(Action::Bench(bench))) => bench.channel = args.value()?,
+            ("--speed", Some(Action::Replay(replay))) => {
+                replay.speed = number(&flag, &args.value()?)?;
+            }
+            ("--prefix", Some(Action::Replay(replay))) => replay.prefix = Some(args.value()?),
+            (other, _) if other.starts_with('-') && other.len() > 1 => {
+                bail!("Unknown argument: {flag}\n\n{USAGE}")
+            }
+            (_, None) => action = Some(Action::named(&flag)?),
+            (_, Some(_)) => operands.push(flag),
+        }
+    }
+
+    let Some(action) = action else {
+        bail!("No command given\n\n{USAGE}");
+    };
+    Ok(Command::Run(Args {
+        url,
+        token,
+        json,
+        action: action.finish(operands)?,
+    }))
+}
+
+/// Parse a positive number given to a flag.
+fn number<T: FromStr + Default + PartialOrd>(flag: &str, value: &str) -> Result<T> {
+    let n: T = value
+        .parse()
+        .ok()
+        .with_context(|| format!("{flag} expects a number, got {value:?}"))?;
+    if n.partial_cmp(&T::default()) != Some(Ordering::Greater) {
+        bail!("{flag} must be greater than 0");
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Result<Command> {
+        parse(line.split_whitespace().map(str::to_string))
+    }
+
+    fn action(line: &str) -> Action {
+        match args(line).unwrap() {
+            Command::Run(args) => args.action,
+            other => panic!("expected Run, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let Command::Run(parsed) =
+            args("--json sub chat:lobby chat:ops --last 10 -u ws://pulse:8080/ws").unwrap()
+        else {
+            panic!("expected Run");
+        };
+        assert!(parsed.json);
+        assert_eq!(parsed.url.as_deref(), Some("ws://pulse:8080/ws"));
+        assert_eq!(
+            parsed.action,
+            Action::Sub {
+                channels: vec!["chat:lobby".to_string(), "chat:ops".to_string()],
+                last: Some(10),
+                count: None,
+            }
+        );
+
+        assert_eq!(
+            action("pub chat:lobby hi --event=message --trace"),
+            Action::Pub {
+                channel: "chat:lobby".to_string(),
+                payload: "hi".to_string(),
+                event: Some("message".to_string()),
+                trace: true,
+            }
+        );
+        assert_eq!(
+            action("presence chat:lobby -w"),
+            Action::Presence {
+                channel: "presence:chat:lobby".to_string(),
+                watch: true,
+            }
+        );
+        assert_eq!(
+            action("presence presence:room"),
+            Action::Presence {
+                channel: "presence:room".to_string(),
+                watch: false,
+            }
+        );
+        assert_eq!(
+            action("bench --clients 4 --size 1024"),
+            Action::Bench(BenchArgs {
+                clients: 4,
+                size: 1024,
+                ..BenchArgs::default()
+            })
+        );
+
+        assert_eq!(
+            action("replay traffic.cap --speed 2.5 --prefix=staging:"),
+            Action::Replay(ReplayArgs {
+                file: "traffic.cap".to_string(),
+                speed: 2.5,
+                prefix: Some("staging:".to_string()),
+            })
+        );
+
+        assert_eq!(args("sub chat:lobby --help").unwrap(), Command::Help);
+        assert_eq!(args("-V").unwrap(), Command::Version);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(args("").is_err());
+        assert!(args("listen chat:lobby").is_err());
+        assert!(args("sub").is_err());
+        assert!(args("pub chat:lobby").is_err());
+        assert!(args("presence a b").is_err());
+        assert!(args("bench extra").is_err());
+        assert!(args("sub chat:lobby --last").is_err());
+        assert!(args("sub chat:lobby --last 0").is_err());
+        assert!(args("sub chat:lobby -n many").is_err());
+        assert!(args("replay traffic.cap --speed -1").is_err());
+        assert!(args("replay").is_err());
+        // Options belong to the command they follow
+        assert!(args("--last 10 sub chat:lobby").is_err());
+        assert!(args("pub chat:lobby hi --watch").is_err());
+    }
+}
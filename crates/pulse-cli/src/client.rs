@@ -0,0 +1,144 @@
+//! A minimal Pulse WebSocket client.
+
+use anyhow::{anyhow, bail, Context, Result};
+use pulse_protocol::{codec, Frame, PROTOCOL_VERSION};
+use tenvis_pulse_transport::websocket;
+use tenvis_pulse_transport::{Connection, HeartbeatConfig, HeartbeatConnection};
+
+/// The connection under a [`Client`], which sends its heartbeats.
+pub type Socket = HeartbeatConnection;
+
+/// A connection to a Pulse server.
+pub struct Client {
+    socket: Socket,
+    /// ID the server assigned to this connection.
+    pub connection_id: String,
+    next_id: u64,
+}
+
+impl Client {
+    /// Connect and complete the handshake, sending `token` in a Connect frame
+    /// if given.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server cannot be reached or rejects the
+    /// connection.
+    pub async fn connect(url: &str, token: Option<&str>) -> Result<Self> {
+        let socket = websocket::connect(url)
+            .await
+            .with_context(|| format!("Failed to connect to {url}"))?;
+        let mut client = Self {
+            socket: HeartbeatConnection::new(Box::new(socket), HeartbeatConfig::default()),
+            connection_id: String::new(),
+            next_id: 1,
+        };
+
+        match client.recv().await? {
+            Some(Frame::Connected { connection_id, .. }) => {
+                client.connection_id = connection_id;
+            }
+            Some(frame) => return Err(unexpected(&frame)),
+            None => bail!("Server closed the connection during the handshake"),
+        }
+        if let Some(token) = token {
+            let connect = Frame::connect(PROTOCOL_VERSION.major, Some(token.to_string()));
+            client.send(&connect).await?;
+            // The server confirms with a second Connected frame
+            match client.recv().await? {
+                Some(Frame::Connected { .. }) => {}
+                Some(frame) => return Err(unexpected(&frame)),
+                None => bail!("Server closed the connection after Connect"),
+            }
+        }
+        Ok(client)
+    }
+
+    /// Get an ID for a request.
+    pub fn next_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Send a frame.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the frame cannot be encoded or sent.
+    pub async fn send(&mut self, frame: &Frame) -> Result<()> {
+        self.socket.send_raw(codec::encode(frame)?).await?;
+        Ok(())
+    }
+
+    /// Receive the next frame, sending heartbeats and answering pings along
+    /// the way. Returns `None` once the server closes the connection.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection fails, the server stops answering
+    /// pings, or it sends a frame that cannot be decoded.
+    pub async fn recv(&mut self) -> Result<Option<Frame>> {
+        Ok(self.socket.recv().await?)
+    }
+
+    /// Wait for the reply to request `id`, skipping other frames.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server sends an Error or Disconnect frame or
+    /// closes the connection first.
+    pub async fn reply(&mut self, id: u64) -> Result<Frame> {
+        loop {
+            let Some(frame) = self.recv().await? else {
+                bail!("Server closed the connection");
+            };
+            check(&frame)?;
+            if let Frame::SubscribeOk { id: reply, .. }
+            | Frame::PublishOk { id: reply, .. }
+            | Frame::Ack { id: reply } = frame
+            {
+                if reply == id {
+                    return Ok(frame);
+                }
+            }
+        }
+    }
+
+    /// Close the connection cleanly.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the close handshake fails.
+    pub async fn close(mut self) -> Result<()> {
+        self.socket.close().await?;
+        Ok(())
+    }
+
+    /// Take the underlying connection, for running a custom read and write
+    /// loop.
+    #[must_use]
+    pub fn into_socket(self) -> Socket {
+        self.socket
+    }
+}
+
+/// Fail on Error and Disconnect frames.
+///
+/// # Errors
+///
+/// Returns the server's error or reason for disconnecting.
+pub fn check(frame: &Frame) -> Result<()> {
+    match frame {
+        Frame::Error { code, message, .. } => bail!("Server error {code}: {message}"),
+        Frame::Disconnect { message, .. } => bail!("Disconnected: {message}"),
+        _ => Ok(()),
+    }
+}
+
+/// Error for a frame the handshake did not expect.
+fn unexpected(frame: &Frame) -> anyhow::Error {
+    check(frame)
+        .err()
+        .unwrap_or_else(|| anyhow!("Unexpected {:?} frame", frame.frame_type()))
+}
@@ -0,0 +1,181 @@
+//! # Pulse CLI
+//!
+//! Terminal client for debugging Pulse deployments.
+//!
+//! ## Usage
+//!
+//! ```bash
+//! # Print messages as they are published
+//! pulse-cli sub chat:lobby
+//!
+//! # Publish a message
+//! pulse-cli pub chat:lobby 'hi'
+//!
+//! # Follow a message through the server (admin token required)
+//! pulse-cli --token "$ADMIN_TOKEN" sub '$system:trace' &
+//! pulse-cli --token "$ADMIN_TOKEN" pub chat:lobby 'hi' --trace
+//!
+//! # List who is present, then keep watching
+//! pulse-cli presence chat:lobby --watch
+//!
+//! # Measure throughput against a remote server, as JSON
+//! pulse-cli --url wss://pulse.example.com/ws --json bench
+//!
+//! # Publish captured traffic to staging at twice the speed
+//! pulse-cli --url wss://staging.example.com/ws replay traffic.cap --speed 2
+//! ```
+
+mod bench;
+mod cli;
+mod client;
+mod output;
+mod replay;
+
+use anyhow::{bail, Result};
+use cli::Action;
+use client::Client;
+use output::Printer;
+use pulse_protocol::{Frame, PresenceAction, SubscribeOptions};
+use serde_json::Value;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = match cli::parse(std::env::args().skip(1))? {
+        cli::Command::Run(args) => args,
+        cli::Command::Help => {
+            println!("{}", cli::USAGE);
+            return Ok(());
+        }
+        cli::Command::Version => {
+            println!("pulse-cli {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+    };
+
+    tenvis_pulse_bin_support::install_crypto_provider();
+
+    let url = args
+        .url
+        .or_else(|| std::env::var("PULSE_URL").ok())
+        .unwrap_or_else(|| cli::DEFAULT_URL.to_string());
+    let token = args.token.as_deref();
+    let printer = Printer::new(args.json);
+
+    match args.action {
+        Action::Sub {
+            channels,
+            last,
+            count,
+        } => sub(&url, token, printer, channels, last, count).await,
+        Action::Pub {
+            channel,
+            payload,
+            event,
+            trace,
+        } => publish(&url, token, printer, channel, payload, event, trace).await,
+        Action::Presence { channel, watch } => presence(&url, token, printer, channel, watch).await,
+        Action::Bench(bench) => bench::run(&url, token, &bench, printer).await,
+        Action::Replay(replay) => replay::run(&url, token, &replay, printer).await,
+    }
+}
+
+/// Print messages published to `channels`, until `count` have arrived or the
+/// server closes the connection.
+async fn sub(
+    url: &str,
+    token: Option<&str>,
+    printer: Printer,
+    channels: Vec<String>,
+    last: Option<u32>,
+    count: Option<u64>,
+) -> Result<()> {
+    let mut client = Client::connect(url, token).await?;
+    for channel in channels {
+        let id = client.next_id();
+        let options = SubscribeOptions {
+            last,
+            ..SubscribeOptions::default()
+        };
+        client
+            .send(&Frame::subscribe_with_options(id, channel, options))
+            .await?;
+    }
+
+    // Replayed messages may arrive before SubscribeOk, so every frame is
+    // printed as it comes
+    let mut received = 0;
+    while let Some(frame) = client.recv().await? {
+        client::check(&frame)?;
+        printer.frame(&frame);
+        if matches!(frame, Frame::Publish { .. }) {
+            received += 1;
+            if count.is_some_and(|count| received >= count) {
+                return client.close().await;
+            }
+        }
+    }
+    bail!("Server closed the connection")
+}
+
+/// Publish a message and print the server's receipt.
+async fn publish(
+    url: &str,
+    token: Option<&str>,
+    printer: Printer,
+    channel: String,
+    payload: String,
+    event: Option<String>,
+    trace: bool,
+) -> Result<()> {
+    let mut client = Client::connect(url, token).await?;
+    let id = client.next_id();
+    let mut frame = Frame::publish_with_ack(id, channel, payload);
+    if let Frame::Publish {
+        event: name,
+        trace: traced,
+        ..
+    } = &mut frame
+    {
+        (*name, *traced) = (event, trace);
+    }
+    client.send(&frame).await?;
+    printer.frame(&client.reply(id).await?);
+    client.close().await
+}
+
+/// Print the members of a presence channel, and with `watch`, every change
+/// after that.
+///
+/// Subscribing joins the presence set, so this client is left out of what is
+/// printed; other members do see it.
+async fn presence(
+    url: &str,
+    token: Option<&str>,
+    printer: Printer,
+    channel: String,
+    watch: bool,
+) -> Result<()> {
+    let mut client = Client::connect(url, token).await?;
+    let id = client.next_id();
+    client.send(&Frame::subscribe(id, channel)).await?;
+
+    let own = Value::String(client.connection_id.clone());
+    while let Some(mut frame) = client.recv().await? {
+        client::check(&frame)?;
+        let Frame::Presence { action, data, .. } = &mut frame else {
+            continue;
+        };
+        match (action, data) {
+            (PresenceAction::Sync, Some(Value::Array(members))) => {
+                members.retain(|member| member["connection_id"] != own);
+                printer.frame(&frame);
+                if !watch {
+                    return client.close().await;
+                }
+            }
+            (_, Some(member)) if member["connection_id"] == own => {}
+            _ => printer.frame(&frame),
+        }
+    }
+    bail!("Server closed the connection")
+}
@@ -0,0 +1,320 @@
+//! Printing frames as text or JSON lines.
+//!
+//! Text output puts data on stdout and status on stderr, so piping `sub`
+//! into another tool only passes messages along. JSON output prints every
+//! line to stdout as an object with a `type` field.
+
+use pulse_protocol::{Frame, PresenceAction};
+use serde_json::{json, Value};
+
+/// A line to print.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Line {
+    /// Output of the command, for stdout.
+    Data(String),
+    /// Progress and diagnostics, for stderr.
+    Status(String),
+}
+
+impl Line {
+    /// Print the line where it belongs.
+    pub fn print(&self) {
+        match self {
+            Line::Data(line) => println!("{line}"),
+            Line::Status(line) => eprintln!("{line}"),
+        }
+    }
+}
+
+/// Prints frames in the format the user asked for.
+#[derive(Debug, Clone, Copy)]
+pub struct Printer {
+    json: bool,
+}
+
+impl Printer {
+    /// Create a printer, for JSON lines or text.
+    #[must_use]
+    pub fn new(json: bool) -> Self {
+        Self { json }
+    }
+
+    /// Print a frame, if it is one users care about.
+    pub fn frame(&self, frame: &Frame) {
+        if let Some(line) = self.render(frame) {
+            line.print();
+        }
+    }
+
+    /// Print a JSON value, or its text form.
+    pub fn value(&self, value: &Value, text: impl FnOnce() -> String) {
+        if self.json {
+            println!("{value}");
+        } else {
+            println!("{}", text());
+        }
+    }
+
+    /// Render a frame, or `None` for frames that are not shown.
+    #[must_use]
+    pub fn render(&self, frame: &Frame) -> Option<Line> {
+        let value = match frame {
+            Frame::Publish {
+                channel,
+                event,
+                seq,
+                message_id,
+                timestamp,
+                payload,
+                ..
+            } => json!({
+                "type": "message",
+                "channel": channel,
+                "event": event,
+                "seq": seq,
+                "message_id": message_id,
+                "timestamp": timestamp,
+                "payload": payload_value(payload),
+            }),
+            Frame::SubscribeOk {
+                channel,
+                seq,
+                subscribers,
+                presence,
+                history,
+                ..
+            } => json!({
+                "type": "subscribed",
+                "channel": channel,
+                "seq": seq,
+                "subscribers": subscribers,
+                "presence": presence,
+                "history": history,
+            }),
+            Frame::ReplayEnd {
+                channel,
+                replayed,
+                truncated,
+                ..
+            } => json!({
+                "type": "replay_end",
+                "channel": channel,
+                "replayed": replayed,
+                "truncated": truncated,
+            }),
+            Frame::PublishOk {
+                message_id,
+                seq,
+                recipients,
+                ..
+            } => json!({
+                "type": "published",
+                "message_id": message_id,
+                "seq": seq,
+                "recipients": recipients,
+            }),
+            Frame::Presence {
+                channel,
+                action,
+                data,
+                ..
+            } => json!({
+                "type": "presence",
+                "channel": channel,
+                "action": action_name(*action),
+                "data": data,
+            }),
+            _ => return None,
+        };
+        if self.json {
+            return Some(Line::Data(value.to_string()));
+        }
+        Some(text(frame, &value))
+    }
+}
+
+/// Text form of a rendered frame.
+fn text(frame: &Frame, value: &Value) -> Line {
+    let field = |name: &str| value[name].to_string();
+    match frame {
+        Frame::Publish {
+            channel,
+            event,
+            seq,
+            payload,
+            ..
+        } => {
+            let mut prefix = channel.clone();
+            if let Some(seq) = seq {
+                prefix.push_str(&format!(" #{seq}"));
+            }
+            if let Some(event) = event {
+                prefix.push_str(&format!(" {event}"));
+            }
+            Line::Data(format!("{prefix}: {}", payload_text(payload)))
+        }
+        Frame::SubscribeOk { channel, .. } => Line::Status(format!(
+            "Subscribed to {channel} at seq {}: {} subscribers, {} retained",
+            field("seq"),
+            field("subscribers"),
+            field("history"),
+        )),
+        Frame::ReplayEnd {
+            channel, truncated, ..
+        } => {
+            let evicted = if *truncated {
+                ", some had already been evicted"
+            } else {
+                ""
+            };
+            Line::Status(format!(
+                "Replayed {} messages on {channel}{evicted}",
+                field("replayed")
+            ))
+        }
+        Frame::PublishOk { .. } => Line::Data(format!(
+            "Published message {} at seq {} to {} recipients",
+            field("message_id"),
+            field("seq"),
+            field("recipients"),
+        )),
+        Frame::Presence {
+            channel,
+            action: PresenceAction::Sync,
+            data,
+            ..
+        } => {
+            let members = data.as_ref().and_then(Value::as_array);
+            let mut text = format!(
+                "{channel}: {} members",
+                members.map_or(0, |members| members.len())
+            );
+            for member in members.into_iter().flatten() {
+                text.push_str(&format!("\n  {}", member_text(member)));
+            }
+            Line::Data(text)
+        }
+        Frame::Presence { action, data, .. } => {
+            let sign = match action {
+                PresenceAction::Join => '+',
+                PresenceAction::Leave => '-',
+                _ => '~',
+            };
+            let member = data.as_ref().map(member_text).unwrap_or_default();
+            Line::Data(format!("{sign} {member}"))
+        }
+        _ => Line::Status(value.to_string()),
+    }
+}
+
+/// A presence member as `<connection id> <metadata>`.
+fn member_text(member: &Value) -> String {
+    let id = member["connection_id"].as_str().unwrap_or("?");
+    match &member["data"] {
+        Value::Null => id.to_string(),
+        data => format!("{id} {data}"),
+    }
+}
+
+/// Name of a presence action.
+fn action_name(action: PresenceAction) -> &'static str {
+    match action {
+        PresenceAction::Join => "join",
+        PresenceAction::Leave => "leave",
+        PresenceAction::Update => "update",
+        PresenceAction::Sync => "sync",
+    }
+}
+
+/// A payload as JSON: embedded as-is if it is JSON, as a string if it is
+/// text, and as an array of bytes otherwise.
+fn payload_value(payload: &[u8]) -> Value {
+    if let Ok(value) = serde_json::from_slice(payload) {
+        return value;
+    }
+    match std::str::from_utf8(payload) {
+        Ok(text) => Value::String(text.to_string()),
+        Err(_) => json!(payload),
+    }
+}
+
+/// A payload as text, or its size if it is binary.
+fn payload_text(payload: &[u8]) -> String {
+    match std::str::from_utf8(payload) {
+        Ok(text) => text.to_string(),
+        Err(_) => format!("<{} bytes>", payload.len()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut message = Frame::publish("chat:lobby", r#"{"text":"hi"}"#);
+        if let Frame::Publish { seq, event, .. } = &mut message {
+            *seq = Some(42);
+            *event = Some("message".to_string());
+        }
+
+        assert_eq!(
+            Printer::new(false).render(&message),
+            Some(Line::Data(
+                r#"chat:lobby #42 message: {"text":"hi"}"#.to_string()
+            ))
+        );
+        let Some(Line::Data(line)) = Printer::new(true).render(&message) else {
+            panic!("expected a data line");
+        };
+        let value: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["type"], "message");
+        assert_eq!(value["seq"], 42);
+        // JSON payloads are embedded rather than quoted
+        assert_eq!(value["payload"]["text"], "hi");
+
+        let binary = Frame::publish("chat:lobby", vec![0xff, 0x00]);
+        assert_eq!(
+            Printer::new(false).render(&binary),
+            Some(Line::Data("chat:lobby: <2 bytes>".to_string()))
+        );
+
+        assert_eq!(
+            Printer::new(false).render(&Frame::subscribe_ok(1, "chat:lobby", 7, 3, 0, 10)),
+            Some(Line::Status(
+                "Subscribed to chat:lobby at seq 7: 3 subscribers, 10 retained".to_string()
+            ))
+        );
+        assert_eq!(Printer::new(true).render(&Frame::ping()), None);
+    }
+
+    #[test]
+    fn test_render_presence() {
+        let sync = Frame::Presence {
+            id: 0,
+            channel: "presence:room".to_string(),
+            action: PresenceAction::Sync,
+            data: Some(json!([
+                {"connection_id": "a", "data": {"name": "Ada"}},
+                {"connection_id": "b", "data": null},
+            ])),
+        };
+        assert_eq!(
+            Printer::new(false).render(&sync),
+            Some(Line::Data(
+                "presence:room: 2 members\n  a {\"name\":\"Ada\"}\n  b".to_string()
+            ))
+        );
+
+        let leave = Frame::Presence {
+            id: 0,
+            channel: "presence:room".to_string(),
+            action: PresenceAction::Leave,
+            data: Some(json!({"connection_id": "b"})),
+        };
+        assert_eq!(
+            Printer::new(false).render(&leave),
+            Some(Line::Data("- b".to_string()))
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! `pulse-cli replay`: publish a traffic capture again.
+//!
+//! Records are published in order, each as long after the first as it was
+//! captured, divided by the speed factor. Server errors, such as rate limits,
+//! are counted and reported without stopping the replay.
+
+use crate::cli::ReplayArgs;
+use crate::client::Client;
+use crate::output::Printer;
+use anyhow::{bail, Context, Result};
+use bytes::BytesMut;
+use pulse_protocol::capture::{self, Record};
+use pulse_protocol::Frame;
+use serde_json::json;
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, Take};
+use tokio::time::{sleep_until, Instant};
+
+/// Reads records from a capture file, up to where it ended when opened, so
+/// a capture the server is still appending to is not replayed forever.
+struct Reader {
+    file: Take<File>,
+    buf: BytesMut,
+}
+
+impl Reader {
+    /// Open a capture file and check its header.
+    async fn open(path: &str) -> Result<Self> {
+        let mut file = File::open(path)
+            .await
+            .with_context(|| format!("Failed to open {path}"))?;
+        let mut header = [0u8; capture::HEADER_SIZE];
+        if file.read_exact(&mut header).await.is_err() {
+            bail!("{path} is not a capture file");
+        }
+        capture::check_header(&header).with_context(|| format!("Failed to read {path}"))?;
+        let len = file.metadata().await?.len();
+        Ok(Self {
+            file: file.take(len.saturating_sub(capture::HEADER_SIZE as u64)),
+            buf: BytesMut::with_capacity(64 * 1024),
+        })
+    }
+
+    /// Read the next record, or `None` at the end of the file.
+    async fn next(&mut self) -> Result<Option<Record>> {
+        loop {
+            if let Some(record) = capture::decode_record(&mut self.buf)? {
+                return Ok(Some(record));
+            }
+            if self.file.read_buf(&mut self.buf).await? == 0 {
+                if !self.buf.is_empty() {
+                    // The server stopped in the middle of writing a record
+                    eprintln!("Ignoring a truncated record at the end of the capture");
+                }
+                return Ok(None);
+            }
+        }
+    }
+}
+
+/// Replay a capture and print a summary.
+///
+/// # Errors
+///
+/// Returns an error if the capture cannot be read or the connection fails.
+pub async fn run(
+    url: &str,
+    token: Option<&str>,
+    args: &ReplayArgs,
+    printer: Printer,
+) -> Result<()> {
+    let mut reader = Reader::open(&args.file).await?;
+    let mut client = Client::connect(url, token).await?;
+    eprintln!("Replaying {} to {url}", args.file);
+
+    let start = Instant::now();
+    let mut first = None;
+    let (mut published, mut errors) = (0u64, 0u64);
+    while let Some(Record {
+        timestamp,
+        mut frame,
+    }) = reader.next().await?
+    {
+        // Wait until the record is due, reading replies meanwhile
+        let offset = timestamp.saturating_sub(*first.get_or_insert(timestamp));
+        let due = start + Duration::from_millis(offset).div_f64(args.speed);
+        loop {
+            tokio::select! {
+                () = sleep_until(due) => break,
+                reply = client.recv() => match reply? {
+                    Some(reply) => errors += u64::from(report(&reply)?),
+                    None => bail!("Server closed the connection"),
+                },
+            }
+        }
+
+        if let (Some(prefix), Frame::Publish { channel, .. }) = (&args.prefix, &mut frame) {
+            channel.insert_str(0, prefix);
+        }
+        client.send(&frame).await?;
+        published += 1;
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    client.close().await?;
+
+    let value = json!({
+        "type": "replay",
+        "file": args.file,
+        "published": published,
+        "errors": errors,
+        "duration": elapsed,
+    });
+    printer.value(&value, || {
+        format!("Published {published} messages in {elapsed:.2}s ({errors} errors)")
+    });
+    Ok(())
+}
+
+/// Report an error the server sent back. Returns whether it was one; fails
+/// if the server disconnected.
+fn report(frame: &Frame) -> Result<bool> {
+    match frame {
+        Frame::Error { code, message, .. } => {
+            eprintln!("Server error {code}: {message}");
+            Ok(true)
+        }
+        Frame::Disconnect { message, .. } => bail!("Disconnected: {message}"),
+        _ => Ok(false),
+    }
+}
@@ -0,0 +1,143 @@
+//! Command-line arguments.
+
+use anyhow::{bail, Context, Result};
+use std::time::Duration;
+use tenvis_pulse_bin_support::Args as Flags;
+
+/// Server URL used when neither `--url` nor `PULSE_URL` is given.
+pub const DEFAULT_URL: &str = "ws://127.0.0.1:8080/ws";
+
+/// Help text for `--help`.
+pub const USAGE: &str = "\
+Usage: pulse-conformance [OPTIONS] [SCENARIO]...
+
+Runs the protocol scenarios against a server and reports which pass. Give
+scenario names, or prefixes such as `presence/`, to run only those.
+
+Options:
+  -u, --url <URL>           Server WebSocket URL (default: ws://127.0.0.1:8080/ws)
+      --token <TOKEN>       Token to send in every Connect frame
+      --timeout <SECS>      Seconds to wait for each expected frame (default: 5)
+      --json                Print one JSON object per scenario
+  -l, --list                List scenarios instead of running them
+  -h, --help                Print help
+  -V, --version             Print version
+
+The URL is also read from PULSE_URL. Exits with status 1 if any scenario
+fails.";
+
+/// What the command line asks for.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Run scenarios.
+    Run(Args),
+    /// List scenarios.
+    List,
+    /// Print help and exit.
+    Help,
+    /// Print the version and exit.
+    Version,
+}
+
+/// Options for a run.
+#[derive(Debug, PartialEq)]
+pub struct Args {
+    /// Server URL given with `--url`.
+    pub url: Option<String>,
+    /// Token to send in Connect frames.
+    pub token: Option<String>,
+    /// How long to wait for each expected frame.
+    pub timeout: Duration,
+    /// Print JSON lines instead of text.
+    pub json: bool,
+    /// Scenario names or prefixes to run; all when empty.
+    pub only: Vec<String>,
+}
+
+/// Parse arguments, excluding the program name.
+///
+/// # Errors
+///
+/// Returns an error for unknown flags and missing or invalid values.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command> {
+    let mut parsed = Args {
+        url: None,
+        token: None,
+        timeout: Duration::from_secs(5),
+        json: false,
+        only: Vec::new(),
+    };
+    let mut list = false;
+    let mut args = Flags::new(args);
+
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "-u" | "--url" => parsed.url = Some(args.value()?),
+            "--token" => parsed.token = Some(args.value()?),
+            "--timeout" => {
+                let value = args.value()?;
+                let secs: f64 = value
+                    .parse()
+                    .ok()
+                    .filter(|secs: &f64| secs.is_finite() && *secs > 0.0)
+                    .with_context(|| {
+                        format!("--timeout expects a positive number, got {value:?}")
+                    })?;
+                parsed.timeout = Duration::from_secs_f64(secs);
+            }
+            "--json" => parsed.json = true,
+            "-l" | "--list" => list = true,
+            other if other.starts_with('-') && other.len() > 1 => {
+                bail!("Unknown argument: {flag}\n\n{USAGE}")
+            }
+            _ => parsed.only.push(flag),
+        }
+    }
+
+    if list {
+        return Ok(Command::List);
+    }
+    Ok(Command::Run(parsed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Result<Command> {
+        parse(line.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn test_parse() {
+        let Command::Run(parsed) =
+            args("--json presence/ frames/split -u ws://pulse:8080/ws --timeout=0.5").unwrap()
+        else {
+            panic!("expected Run");
+        };
+        assert!(parsed.json);
+        assert_eq!(parsed.url.as_deref(), Some("ws://pulse:8080/ws"));
+        assert_eq!(parsed.timeout, Duration::from_millis(500));
+        assert_eq!(parsed.only, vec!["presence/", "frames/split"]);
+
+        let Command::Run(parsed) = args("").unwrap() else {
+            panic!("expected Run");
+        };
+        assert_eq!(parsed.timeout, Duration::from_secs(5));
+        assert!(parsed.only.is_empty());
+
+        assert_eq!(args("--list").unwrap(), Command::List);
+        assert_eq!(args("presence/ --help").unwrap(), Command::Help);
+        assert_eq!(args("-V").unwrap(), Command::Version);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(args("--url").is_err());
+        assert!(args("--timeout 0").is_err());
+        assert!(args("--timeout soon").is_err());
+        assert!(args("--verbose").is_err());
+    }
+}
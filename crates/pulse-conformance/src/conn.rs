@@ -0,0 +1,187 @@
+//! A connection for driving a server frame by frame.
+//!
+//! Unlike a regular client, it sends whatever bytes a scenario asks for and
+//! fails as soon as the server sends something unexpected or takes too long.
+
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::BytesMut;
+use futures_util::{SinkExt, StreamExt};
+use pulse_protocol::{codec, Frame, PROTOCOL_VERSION};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time::{timeout_at, Instant};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+/// The server under test.
+#[derive(Debug, Clone)]
+pub struct Target {
+    /// WebSocket URL.
+    pub url: String,
+    /// Token to send in Connect frames.
+    pub token: Option<String>,
+    /// How long to wait for each expected frame.
+    pub timeout: Duration,
+}
+
+/// A connection to the server under test.
+pub struct Conn {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    buf: BytesMut,
+    timeout: Duration,
+    /// ID the server assigned to this connection.
+    pub connection_id: String,
+}
+
+impl Conn {
+    /// Open a connection and read the server's first Connected frame, which
+    /// is returned alongside it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server cannot be reached or opens with
+    /// another frame.
+    pub async fn open(target: &Target) -> Result<(Self, Frame)> {
+        let (socket, _) = connect_async(target.url.as_str())
+            .await
+            .with_context(|| format!("Failed to connect to {}", target.url))?;
+        let mut conn = Self {
+            socket,
+            buf: BytesMut::with_capacity(64 * 1024),
+            timeout: target.timeout,
+            connection_id: String::new(),
+        };
+        let connected = conn.recv().await.context("No Connected frame")?;
+        let Frame::Connected { connection_id, .. } = &connected else {
+            bail!("Expected Connected first, got {}", describe(&connected));
+        };
+        conn.connection_id = connection_id.clone();
+        Ok((conn, connected))
+    }
+
+    /// Open a connection ready for requests, completing the handshake with
+    /// the target's token if it has one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server cannot be reached or the handshake
+    /// fails.
+    pub async fn ready(target: &Target) -> Result<Self> {
+        let (mut conn, _) = Self::open(target).await?;
+        if let Some(token) = &target.token {
+            let connect = Frame::connect(PROTOCOL_VERSION.major, Some(token.clone()));
+            conn.send(&connect).await?;
+            conn.expect("Connected", |frame| {
+                matches!(frame, Frame::Connected { .. }).then_some(())
+            })
+            .await?;
+        }
+        Ok(conn)
+    }
+
+    /// Send a frame in its own WebSocket message.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the frame cannot be encoded or sent.
+    pub async fn send(&mut self, frame: &Frame) -> Result<()> {
+        self.send_bytes(codec::encode(frame)?.to_vec()).await
+    }
+
+    /// Send raw bytes as one binary WebSocket message.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection fails.
+    pub async fn send_bytes(&mut self, bytes: Vec<u8>) -> Result<()> {
+        self.socket
+            .send(Message::Binary(bytes))
+            .await
+            .context("Failed to send")
+    }
+
+    /// Receive the next frame.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if none arrives in time, the connection closes, or
+    /// the server sends bytes that do not decode.
+    pub async fn recv(&mut self) -> Result<Frame> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            if let Some(frame) = codec::decode_from(&mut self.buf)
+                .context("Server sent a frame that does not decode")?
+            {
+                return Ok(frame);
+            }
+            let message = timeout_at(deadline, self.socket.next())
+                .await
+                .map_err(|_| anyhow!("Timed out waiting for a frame"))?;
+            match message.transpose().context("Connection failed")? {
+                Some(Message::Binary(data)) => self.buf.extend_from_slice(&data),
+                Some(Message::Close(_)) | None => bail!("Server closed the connection"),
+                Some(_) => {}
+            }
+        }
+    }
+
+    /// Wait for the frame `pick` accepts, skipping frames it returns `None`
+    /// for. Server pings are answered and Error and Disconnect frames fail
+    /// unless accepted, so a scenario never mistakes a refusal for silence.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming `what` if the frame does not arrive.
+    pub async fn expect<T>(
+        &mut self,
+        what: &str,
+        mut pick: impl FnMut(&Frame) -> Option<T>,
+    ) -> Result<T> {
+        loop {
+            let frame = self
+                .recv()
+                .await
+                .with_context(|| format!("Expected {what}"))?;
+            if let Some(value) = pick(&frame) {
+                return Ok(value);
+            }
+            match frame {
+                Frame::Ping { timestamp } => self.send(&Frame::pong(timestamp)).await?,
+                Frame::Error { .. } | Frame::Disconnect { .. } => {
+                    bail!("Expected {what}, got {}", describe(&frame))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Wait for an Error frame with `code`, returning its request ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if another error, or none, arrives.
+    pub async fn expect_error(&mut self, code: u16) -> Result<u64> {
+        let what = format!("error {code}");
+        self.expect(&what, |frame| match frame {
+            Frame::Error { id, code: got, .. } if *got == code => Some(*id),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Close the connection cleanly.
+    pub async fn close(mut self) {
+        let _ = self.socket.close(None).await;
+    }
+}
+
+/// A frame as text for failure messages, with payloads left out.
+#[must_use]
+pub fn describe(frame: &Frame) -> String {
+    match frame {
+        Frame::Error { id, code, message } => format!("error {code} for request {id}: {message}"),
+        Frame::Disconnect { message, .. } => format!("Disconnect: {message}"),
+        Frame::Publish { channel, .. } => format!("Publish on {channel}"),
+        frame => format!("{:?}", frame.frame_type()),
+    }
+}
@@ -0,0 +1,111 @@
+//! # Pulse Conformance
+//!
+//! Checks a server against the wire protocol, for validating alternative
+//! server implementations, proxies in front of Pulse, and Pulse itself.
+//! Scenarios cover the handshake, ping/pong, publish and subscribe, error
+//! replies, large, split and coalesced frames, and presence.
+//!
+//! ## Usage
+//!
+//! ```bash
+//! # Run every scenario against a local server
+//! pulse-conformance
+//!
+//! # Run the presence and frame scenarios against a remote server
+//! pulse-conformance --url wss://pulse.example.com/ws presence/ frames/
+//!
+//! # Report as JSON lines, for CI
+//! pulse-conformance --json --token "$PULSE_TOKEN"
+//! ```
+
+mod cli;
+mod conn;
+mod scenarios;
+
+use anyhow::Result;
+use conn::Target;
+use scenarios::Scenario;
+use serde_json::json;
+use std::process::ExitCode;
+use std::time::Instant;
+
+#[tokio::main]
+async fn main() -> Result<ExitCode> {
+    let args = match cli::parse(std::env::args().skip(1))? {
+        cli::Command::Run(args) => args,
+        cli::Command::List => {
+            for scenario in Scenario::ALL {
+                println!("{:<28}{}", scenario.name(), scenario.description());
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        cli::Command::Help => {
+            println!("{}", cli::USAGE);
+            return Ok(ExitCode::SUCCESS);
+        }
+        cli::Command::Version => {
+            println!("pulse-conformance {}", env!("CARGO_PKG_VERSION"));
+            return Ok(ExitCode::SUCCESS);
+        }
+    };
+
+    tenvis_pulse_bin_support::install_crypto_provider();
+
+    let target = Target {
+        url: args
+            .url
+            .or_else(|| std::env::var("PULSE_URL").ok())
+            .unwrap_or_else(|| cli::DEFAULT_URL.to_string()),
+        token: args.token,
+        timeout: args.timeout,
+    };
+    let selected: Vec<Scenario> = Scenario::ALL
+        .into_iter()
+        .filter(|scenario| scenario.selected(&args.only))
+        .collect();
+    if selected.is_empty() {
+        anyhow::bail!("No scenarios match {}", args.only.join(", "));
+    }
+    if !args.json {
+        eprintln!(
+            "Checking {} against {} scenarios",
+            target.url,
+            selected.len()
+        );
+    }
+
+    let mut failed = 0;
+    for scenario in &selected {
+        let start = Instant::now();
+        let result = scenario.run(&target).await;
+        let elapsed = start.elapsed();
+        if result.is_err() {
+            failed += 1;
+        }
+
+        if args.json {
+            let value = json!({
+                "scenario": scenario.name(),
+                "passed": result.is_ok(),
+                "error": result.as_ref().err().map(|e| format!("{e:#}")),
+                "duration": elapsed.as_secs_f64(),
+            });
+            println!("{value}");
+        } else {
+            match &result {
+                Ok(()) => println!("PASS  {}", scenario.name()),
+                Err(e) => println!("FAIL  {}: {e:#}", scenario.name()),
+            }
+        }
+    }
+
+    if !args.json {
+        let passed = selected.len() - failed;
+        println!("\n{passed} passed, {failed} failed");
+    }
+    Ok(if failed == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
@@ -0,0 +1,664 @@
+//! The scenarios a server is checked against.
+//!
+//! Each scenario opens its own connections and uses channels no other run
+//! shares, so scenarios are independent and a server can be checked while
+//! it serves other traffic. Expectations follow `docs/PROTOCOL.md`; where
+//! the specification leaves servers a choice, such as the payload limit,
+//! scenarios stay within what every conforming server must accept.
+
+use crate::conn::{describe, Conn, Target};
+use anyhow::{bail, ensure, Result};
+use pulse_protocol::{codec, error_codes, Capabilities, Frame, PresenceAction, PROTOCOL_VERSION};
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Payload size for the large frame scenario, within the smallest limit a
+/// server is likely to be configured with.
+const LARGE_PAYLOAD: usize = 32 * 1024;
+
+/// A protocol scenario.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scenario {
+    /// The server opens with a Connected frame.
+    Connected,
+    /// Connect is answered with a Connected frame for the same connection.
+    Connect,
+    /// An unsupported protocol version is refused.
+    VersionMismatch,
+    /// Ping is answered with Pong.
+    PingPong,
+    /// A published message reaches a subscriber.
+    RoundTrip,
+    /// Unsubscribing stops delivery and cannot be repeated.
+    Unsubscribe,
+    /// Subscribing twice is refused.
+    AlreadySubscribed,
+    /// Malformed channel names are refused.
+    InvalidChannel,
+    /// `$` channels are closed to clients.
+    ReservedChannel,
+    /// A frame that does not decode is reported without closing.
+    MalformedFrame,
+    /// Large frames are delivered intact.
+    LargeFrame,
+    /// Frames over the maximum size are refused.
+    TooLarge,
+    /// A frame split across messages is reassembled.
+    SplitFrame,
+    /// Several frames in one message are all handled.
+    CoalescedFrames,
+    /// Presence members are synced, joined and left.
+    PresenceJoinLeave,
+    /// Presence metadata updates reach other members.
+    PresenceUpdate,
+}
+
+impl Scenario {
+    /// Every scenario, in the order they run.
+    pub const ALL: [Scenario; 16] = [
+        Scenario::Connected,
+        Scenario::Connect,
+        Scenario::VersionMismatch,
+        Scenario::PingPong,
+        Scenario::RoundTrip,
+        Scenario::Unsubscribe,
+        Scenario::AlreadySubscribed,
+        Scenario::InvalidChannel,
+        Scenario::ReservedChannel,
+        Scenario::MalformedFrame,
+        Scenario::LargeFrame,
+        Scenario::TooLarge,
+        Scenario::SplitFrame,
+        Scenario::CoalescedFrames,
+        Scenario::PresenceJoinLeave,
+        Scenario::PresenceUpdate,
+    ];
+
+    /// Name of the scenario, grouped by area.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Scenario::Connected => "handshake/connected",
+            Scenario::Connect => "handshake/connect",
+            Scenario::VersionMismatch => "handshake/version-mismatch",
+            Scenario::PingPong => "ping/pong",
+            Scenario::RoundTrip => "pubsub/round-trip",
+            Scenario::Unsubscribe => "pubsub/unsubscribe",
+            Scenario::AlreadySubscribed => "pubsub/already-subscribed",
+            Scenario::InvalidChannel => "errors/invalid-channel",
+            Scenario::ReservedChannel => "errors/reserved-channel",
+            Scenario::MalformedFrame => "errors/malformed-frame",
+            Scenario::LargeFrame => "frames/large",
+            Scenario::TooLarge => "frames/too-large",
+            Scenario::SplitFrame => "frames/split",
+            Scenario::CoalescedFrames => "frames/coalesced",
+            Scenario::PresenceJoinLeave => "presence/join-leave",
+            Scenario::PresenceUpdate => "presence/update",
+        }
+    }
+
+    /// What the scenario checks, for `--list`.
+    #[must_use]
+    pub fn description(self) -> &'static str {
+        match self {
+            Scenario::Connected => "The server opens with a Connected frame",
+            Scenario::Connect => "Connect is confirmed with a second Connected frame",
+            Scenario::VersionMismatch => "Connect with an unknown version gets error 1012",
+            Scenario::PingPong => "Ping is answered with a Pong echoing its timestamp",
+            Scenario::RoundTrip => "A publish reaches a subscriber and is confirmed",
+            Scenario::Unsubscribe => "Unsubscribe is acked; repeating it gets error 1008",
+            Scenario::AlreadySubscribed => "Subscribing twice gets error 1009",
+            Scenario::InvalidChannel => "Empty and non-printable channel names get error 1002",
+            Scenario::ReservedChannel => "Subscribing to a $ channel is refused",
+            Scenario::MalformedFrame => "Undecodable frames get error 1001 and are skipped",
+            Scenario::LargeFrame => "A 32 KiB payload is delivered intact",
+            Scenario::TooLarge => "A frame over 16 MiB gets error 1007",
+            Scenario::SplitFrame => "A frame split across three messages is reassembled",
+            Scenario::CoalescedFrames => "Every frame in a multi-frame message is handled",
+            Scenario::PresenceJoinLeave => "Members get Sync, Join and Leave frames",
+            Scenario::PresenceUpdate => "Metadata updates and Sync requests are answered",
+        }
+    }
+
+    /// Whether `filters` select the scenario: by full name, or by a prefix
+    /// such as `presence/`. Empty filters select everything.
+    #[must_use]
+    pub fn selected(self, filters: &[String]) -> bool {
+        filters.is_empty() || filters.iter().any(|f| self.name().starts_with(f.as_str()))
+    }
+
+    /// Run the scenario against `target`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first expectation the server did not meet.
+    pub async fn run(self, target: &Target) -> Result<()> {
+        match self {
+            Scenario::Connected => connected(target).await,
+            Scenario::Connect => connect(target).await,
+            Scenario::VersionMismatch => version_mismatch(target).await,
+            Scenario::PingPong => ping_pong(target).await,
+            Scenario::RoundTrip => round_trip(target).await,
+            Scenario::Unsubscribe => unsubscribe(target).await,
+            Scenario::AlreadySubscribed => already_subscribed(target).await,
+            Scenario::InvalidChannel => invalid_channel(target).await,
+            Scenario::ReservedChannel => reserved_channel(target).await,
+            Scenario::MalformedFrame => malformed_frame(target).await,
+            Scenario::LargeFrame => large_frame(target).await,
+            Scenario::TooLarge => too_large(target).await,
+            Scenario::SplitFrame => split_frame(target).await,
+            Scenario::CoalescedFrames => coalesced_frames(target).await,
+            Scenario::PresenceJoinLeave => presence_join_leave(target).await,
+            Scenario::PresenceUpdate => presence_update(target).await,
+        }
+    }
+}
+
+/// A channel name no other scenario or run uses.
+fn channel(prefix: &str) -> String {
+    static COUNT: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let count = COUNT.fetch_add(1, Ordering::Relaxed);
+    format!("{prefix}conformance:{nanos:x}:{count}")
+}
+
+/// Subscribe and wait for the SubscribeOk, returning its subscriber count.
+async fn subscribe(conn: &mut Conn, id: u64, channel: &str) -> Result<u32> {
+    conn.send(&Frame::subscribe(id, channel)).await?;
+    conn.expect("SubscribeOk", |frame| match frame {
+        Frame::SubscribeOk {
+            id: got,
+            channel: subscribed,
+            subscribers,
+            ..
+        } if *got == id && subscribed == channel => Some(*subscribers),
+        _ => None,
+    })
+    .await
+}
+
+/// Publish with an ID and wait for the PublishOk, returning its message ID
+/// and recipient count.
+async fn publish(conn: &mut Conn, id: u64, channel: &str, payload: &[u8]) -> Result<(u64, u32)> {
+    conn.send(&Frame::publish_with_ack(id, channel, payload.to_vec()))
+        .await?;
+    conn.expect("PublishOk", |frame| match frame {
+        Frame::PublishOk {
+            id: got,
+            message_id,
+            recipients,
+            ..
+        } if *got == id => Some((*message_id, *recipients)),
+        _ => None,
+    })
+    .await
+}
+
+/// Wait for a message on `channel`, returning its payload and message ID.
+async fn message(conn: &mut Conn, channel: &str) -> Result<(Vec<u8>, Option<u64>)> {
+    conn.expect("Publish", |frame| match frame {
+        Frame::Publish {
+            channel: got,
+            payload,
+            message_id,
+            ..
+        } if got == channel => Some((payload.clone(), *message_id)),
+        _ => None,
+    })
+    .await
+}
+
+/// Wait for the Sync frame on `channel`, returning its data.
+async fn sync(conn: &mut Conn, channel: &str) -> Result<Value> {
+    conn.expect("presence Sync", |frame| match frame {
+        Frame::Presence {
+            channel: got,
+            action: PresenceAction::Sync,
+            data,
+            ..
+        } if got == channel => Some(data.clone().unwrap_or(Value::Null)),
+        _ => None,
+    })
+    .await
+}
+
+/// Wait for a presence frame about `member` on `channel` with one of
+/// `actions`, returning its data. Subscribers also see their own joins, so
+/// frames about other members are skipped.
+async fn member_event(
+    conn: &mut Conn,
+    channel: &str,
+    member: &str,
+    actions: &[PresenceAction],
+) -> Result<Value> {
+    let what = format!("presence {actions:?} for {member}");
+    conn.expect(&what, |frame| match frame {
+        Frame::Presence {
+            channel: got,
+            action,
+            data: Some(data),
+            ..
+        } if got == channel && actions.contains(action) && data["connection_id"] == member => {
+            Some(data.clone())
+        }
+        _ => None,
+    })
+    .await
+}
+
+/// Connection IDs of the members in a Sync frame's data.
+fn members(data: &Value) -> Result<Vec<&str>> {
+    let Some(members) = data.as_array() else {
+        bail!("Sync data is not a list of members: {data}");
+    };
+    Ok(members
+        .iter()
+        .filter_map(|member| member["connection_id"].as_str())
+        .collect())
+}
+
+async fn connected(target: &Target) -> Result<()> {
+    let (conn, frame) = Conn::open(target).await?;
+    let Frame::Connected {
+        connection_id,
+        version,
+        heartbeat,
+        ..
+    } = frame
+    else {
+        unreachable!("open checks the first frame");
+    };
+    ensure!(!connection_id.is_empty(), "connection_id is empty");
+    ensure!(
+        version == PROTOCOL_VERSION.major,
+        "version is {version}, expected {}",
+        PROTOCOL_VERSION.major
+    );
+    ensure!(heartbeat > 0, "heartbeat is 0");
+    conn.close().await;
+    Ok(())
+}
+
+async fn connect(target: &Target) -> Result<()> {
+    let (mut conn, first) = Conn::open(target).await?;
+    let Frame::Connected {
+        capabilities: supported,
+        ..
+    } = first
+    else {
+        unreachable!("open checks the first frame");
+    };
+    let offered = Capabilities::BATCHING.union(Capabilities::from_bits(1 << 31));
+    conn.send(&Frame::Connect {
+        version: PROTOCOL_VERSION.major,
+        token: target.token.clone(),
+        capabilities: offered,
+        heartbeat: None,
+        user_id: None,
+    })
+    .await?;
+    let (connection_id, negotiated) = conn
+        .expect("Connected", |frame| match frame {
+            Frame::Connected {
+                connection_id,
+                capabilities,
+                ..
+            } => Some((connection_id.clone(), *capabilities)),
+            _ => None,
+        })
+        .await?;
+    ensure!(
+        connection_id == conn.connection_id,
+        "connection_id changed from {} to {connection_id}",
+        conn.connection_id
+    );
+    ensure!(
+        supported.intersection(offered).contains(negotiated),
+        "negotiated capabilities {:#x} are not both offered ({:#x}) and supported ({:#x})",
+        negotiated.bits(),
+        offered.bits(),
+        supported.bits()
+    );
+    conn.close().await;
+    Ok(())
+}
+
+async fn version_mismatch(target: &Target) -> Result<()> {
+    let (mut conn, _) = Conn::open(target).await?;
+    let version = PROTOCOL_VERSION.major.wrapping_add(100);
+    conn.send(&Frame::connect(version, target.token.clone()))
+        .await?;
+    conn.expect_error(error_codes::PROTOCOL_MISMATCH).await?;
+    conn.close().await;
+    Ok(())
+}
+
+async fn ping_pong(target: &Target) -> Result<()> {
+    let mut conn = Conn::ready(target).await?;
+    conn.send(&Frame::ping_with_timestamp(1_700_000_000_000))
+        .await?;
+    let timestamp = conn
+        .expect("Pong", |frame| match frame {
+            Frame::Pong { timestamp } => Some(*timestamp),
+            _ => None,
+        })
+        .await?;
+    ensure!(
+        timestamp == Some(1_700_000_000_000),
+        "Pong timestamp is {timestamp:?}, expected the Ping's"
+    );
+    conn.close().await;
+    Ok(())
+}
+
+async fn round_trip(target: &Target) -> Result<()> {
+    let channel = channel("");
+    let mut subscriber = Conn::ready(target).await?;
+    let subscribers = subscribe(&mut subscriber, 1, &channel).await?;
+    ensure!(
+        subscribers == 1,
+        "SubscribeOk counts {subscribers} subscribers, expected 1"
+    );
+
+    let mut publisher = Conn::ready(target).await?;
+    let (message_id, recipients) = publish(&mut publisher, 7, &channel, b"hello").await?;
+    ensure!(
+        recipients == 1,
+        "PublishOk counts {recipients} recipients, expected 1"
+    );
+
+    let (payload, delivered_id) = message(&mut subscriber, &channel).await?;
+    ensure!(payload == b"hello", "payload changed in delivery");
+    ensure!(
+        delivered_id == Some(message_id),
+        "delivered message_id is {delivered_id:?}, PublishOk said {message_id}"
+    );
+    subscriber.close().await;
+    publisher.close().await;
+    Ok(())
+}
+
+async fn unsubscribe(target: &Target) -> Result<()> {
+    let channel = channel("");
+    let mut conn = Conn::ready(target).await?;
+    subscribe(&mut conn, 1, &channel).await?;
+    conn.send(&Frame::unsubscribe(2, &channel)).await?;
+    conn.expect("Ack", |frame| {
+        matches!(frame, Frame::Ack { id: 2 }).then_some(())
+    })
+    .await?;
+
+    // Nothing is delivered after the Ack
+    let (_, recipients) = publish(&mut conn, 3, &channel, b"gone").await?;
+    ensure!(
+        recipients == 0,
+        "PublishOk counts {recipients} recipients after unsubscribing"
+    );
+
+    conn.send(&Frame::unsubscribe(4, &channel)).await?;
+    let id = conn.expect_error(error_codes::NOT_SUBSCRIBED).await?;
+    ensure!(id == 4, "error is for request {id}, expected 4");
+    conn.close().await;
+    Ok(())
+}
+
+async fn already_subscribed(target: &Target) -> Result<()> {
+    let channel = channel("");
+    let mut conn = Conn::ready(target).await?;
+    subscribe(&mut conn, 1, &channel).await?;
+    conn.send(&Frame::subscribe(2, &channel)).await?;
+    let id = conn.expect_error(error_codes::ALREADY_SUBSCRIBED).await?;
+    ensure!(id == 2, "error is for request {id}, expected 2");
+    conn.close().await;
+    Ok(())
+}
+
+async fn invalid_channel(target: &Target) -> Result<()> {
+    let mut conn = Conn::ready(target).await?;
+    let too_long = "x".repeat(257);
+    for (id, name) in [(1, ""), (2, "bad\nname"), (3, too_long.as_str())] {
+        conn.send(&Frame::subscribe(id, name)).await?;
+        let got = conn.expect_error(error_codes::INVALID_CHANNEL).await?;
+        ensure!(got == id, "error is for request {got}, expected {id}");
+    }
+    conn.close().await;
+    Ok(())
+}
+
+async fn reserved_channel(target: &Target) -> Result<()> {
+    let mut conn = Conn::ready(target).await?;
+    conn.send(&Frame::subscribe(1, channel("$"))).await?;
+    conn.expect("error 1002 or 1004", |frame| match frame {
+        Frame::Error { id: 1, code, .. }
+            if [error_codes::INVALID_CHANNEL, error_codes::FORBIDDEN].contains(code) =>
+        {
+            Some(())
+        }
+        _ => None,
+    })
+    .await?;
+    conn.close().await;
+    Ok(())
+}
+
+async fn malformed_frame(target: &Target) -> Result<()> {
+    let mut conn = Conn::ready(target).await?;
+    // A well-formed length prefix around bytes that are not a frame
+    let body = [0xc1, 0xff, 0x00];
+    let mut bytes = u32::try_from(body.len())?.to_be_bytes().to_vec();
+    bytes.extend_from_slice(&body);
+    conn.send_bytes(bytes).await?;
+    conn.expect_error(error_codes::INVALID_FRAME).await?;
+
+    // The connection is still usable
+    conn.send(&Frame::ping()).await?;
+    conn.expect("Pong after the error", |frame| {
+        matches!(frame, Frame::Pong { .. }).then_some(())
+    })
+    .await?;
+    conn.close().await;
+    Ok(())
+}
+
+async fn large_frame(target: &Target) -> Result<()> {
+    let channel = channel("");
+    let mut subscriber = Conn::ready(target).await?;
+    subscribe(&mut subscriber, 1, &channel).await?;
+
+    let sent: Vec<u8> = (0..LARGE_PAYLOAD).map(|i| (i % 251) as u8).collect();
+    let mut publisher = Conn::ready(target).await?;
+    publish(&mut publisher, 1, &channel, &sent).await?;
+    let (payload, _) = message(&mut subscriber, &channel).await?;
+    ensure!(
+        payload == sent,
+        "payload of {} bytes arrived as {} different bytes",
+        sent.len(),
+        payload.len()
+    );
+    subscriber.close().await;
+    publisher.close().await;
+    Ok(())
+}
+
+async fn too_large(target: &Target) -> Result<()> {
+    let mut conn = Conn::ready(target).await?;
+    // Only the length prefix is needed to know the frame is too large
+    let size = u32::try_from(codec::MAX_FRAME_SIZE + 1)?;
+    conn.send_bytes(size.to_be_bytes().to_vec()).await?;
+    conn.expect_error(error_codes::PAYLOAD_TOO_LARGE).await?;
+    conn.close().await;
+    Ok(())
+}
+
+async fn split_frame(target: &Target) -> Result<()> {
+    let channel = channel("");
+    let mut conn = Conn::ready(target).await?;
+    let bytes = codec::encode(&Frame::subscribe(1, &channel))?;
+    // Split inside the length prefix and inside the payload
+    let (head, rest) = bytes.split_at(2);
+    let (middle, tail) = rest.split_at(rest.len() / 2);
+    for part in [head, middle, tail] {
+        conn.send_bytes(part.to_vec()).await?;
+    }
+    conn.expect("SubscribeOk for the split frame", |frame| {
+        matches!(frame, Frame::SubscribeOk { id: 1, .. }).then_some(())
+    })
+    .await?;
+    conn.close().await;
+    Ok(())
+}
+
+async fn coalesced_frames(target: &Target) -> Result<()> {
+    let channel = channel("");
+    let mut conn = Conn::ready(target).await?;
+    let mut bytes = Vec::new();
+    for frame in [
+        Frame::subscribe(1, &channel),
+        Frame::ping_with_timestamp(42),
+        Frame::publish_with_ack(2, &channel, b"batched".to_vec()),
+    ] {
+        bytes.extend_from_slice(&codec::encode(&frame)?);
+    }
+    conn.send_bytes(bytes).await?;
+
+    let (mut subscribed, mut pong, mut published, mut delivered) = (false, false, false, false);
+    while !(subscribed && pong && published && delivered) {
+        let frame = conn.recv().await?;
+        match &frame {
+            Frame::SubscribeOk { id: 1, .. } => subscribed = true,
+            Frame::Pong {
+                timestamp: Some(42),
+            } => pong = true,
+            Frame::PublishOk { id: 2, .. } => published = true,
+            Frame::Publish { channel: got, .. } if *got == channel => delivered = true,
+            Frame::Error { .. } | Frame::Disconnect { .. } => bail!("Got {}", describe(&frame)),
+            _ => {}
+        }
+    }
+    conn.close().await;
+    Ok(())
+}
+
+async fn presence_join_leave(target: &Target) -> Result<()> {
+    let channel = channel("presence:");
+    let mut first = Conn::ready(target).await?;
+    subscribe(&mut first, 1, &channel).await?;
+    let snapshot = sync(&mut first, &channel).await?;
+    ensure!(
+        members(&snapshot)? == [first.connection_id.as_str()],
+        "first member's Sync lists {snapshot}"
+    );
+
+    let mut second = Conn::ready(target).await?;
+    subscribe(&mut second, 1, &channel).await?;
+    let snapshot = sync(&mut second, &channel).await?;
+    let mut listed = members(&snapshot)?;
+    listed.sort_unstable();
+    let mut expected = [first.connection_id.as_str(), second.connection_id.as_str()];
+    expected.sort_unstable();
+    ensure!(listed == expected, "second member's Sync lists {snapshot}");
+
+    let second_id = second.connection_id.clone();
+    member_event(&mut first, &channel, &second_id, &[PresenceAction::Join]).await?;
+    second.close().await;
+    member_event(&mut first, &channel, &second_id, &[PresenceAction::Leave]).await?;
+    first.close().await;
+    Ok(())
+}
+
+async fn presence_update(target: &Target) -> Result<()> {
+    let channel = channel("presence:");
+    let mut watcher = Conn::ready(target).await?;
+    subscribe(&mut watcher, 1, &channel).await?;
+    let mut member = Conn::ready(target).await?;
+    subscribe(&mut member, 1, &channel).await?;
+    let member_id = member.connection_id.clone();
+    member_event(&mut watcher, &channel, &member_id, &[PresenceAction::Join]).await?;
+
+    let status = json!({"status": "away"});
+    member
+        .send(&Frame::Presence {
+            id: 2,
+            channel: channel.clone(),
+            action: PresenceAction::Update,
+            data: Some(status.clone()),
+        })
+        .await?;
+    member
+        .expect("Ack", |frame| {
+            matches!(frame, Frame::Ack { id: 2 }).then_some(())
+        })
+        .await?;
+    let actions = [PresenceAction::Join, PresenceAction::Update];
+    let update = member_event(&mut watcher, &channel, &member_id, &actions).await?;
+    ensure!(
+        update["data"] == status,
+        "update carries {update}, expected {status}"
+    );
+
+    // A requested snapshot includes the new metadata
+    watcher
+        .send(&Frame::Presence {
+            id: 3,
+            channel: channel.clone(),
+            action: PresenceAction::Sync,
+            data: None,
+        })
+        .await?;
+    let snapshot = watcher
+        .expect("Sync reply", |frame| match frame {
+            Frame::Presence {
+                id: 3,
+                action: PresenceAction::Sync,
+                data,
+                ..
+            } => Some(data.clone().unwrap_or(Value::Null)),
+            _ => None,
+        })
+        .await?;
+    let updated = snapshot
+        .as_array()
+        .into_iter()
+        .flatten()
+        .any(|m| m["connection_id"] == member_id.as_str() && m["data"] == status);
+    ensure!(updated, "Sync reply lists {snapshot} without the update");
+    watcher.close().await;
+    member.close().await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scenarios() {
+        let mut names: Vec<_> = Scenario::ALL.iter().map(|s| s.name()).collect();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), Scenario::ALL.len());
+
+        let only = |filters: &[&str]| {
+            let filters: Vec<String> = filters.iter().map(|f| f.to_string()).collect();
+            Scenario::ALL
+                .iter()
+                .filter(|s| s.selected(&filters))
+                .count()
+        };
+        assert_eq!(only(&[]), Scenario::ALL.len());
+        assert_eq!(only(&["presence/"]), 2);
+        assert_eq!(only(&["frames/split", "ping/pong"]), 2);
+        assert_eq!(only(&["nothing"]), 0);
+    }
+
+    #[test]
+    fn test_channel() {
+        let name = channel("presence:");
+        assert!(name.starts_with("presence:conformance:"));
+        assert_ne!(name, channel("presence:"));
+    }
+}
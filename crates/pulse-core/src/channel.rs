@@ -2,21 +2,70 @@
 //!
 //! Channels are named rooms where connections can subscribe to receive messages.
 
-use crate::message::Message;
+use crate::connection::{ConnId, ConnectionHandle, DropPolicy};
+use crate::message::{Message, MessageKind};
+use crate::trace::{MessageTracer, TraceEvent};
 use bytes::Bytes;
-use std::collections::HashSet;
-use std::sync::Arc;
+use pulse_protocol::{Priority, SubscribeOptions};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
 use tokio::sync::broadcast;
 use tracing::{debug, trace};
 
 /// Maximum channel name length.
 pub const MAX_CHANNEL_NAME_LENGTH: usize = 256;
 
+/// Prefix of reserved channels the server publishes to, such as
+/// `$system:stats`. Their names fail validation, so clients can only be
+/// subscribed to them by the server through
+/// [`Router::subscribe_system`](crate::Router::subscribe_system).
+pub const SYSTEM_CHANNEL_PREFIX: &str = "$system:";
+
 /// Default broadcast channel capacity.
 const DEFAULT_CHANNEL_CAPACITY: usize = 1024;
 
 /// A channel identifier.
-pub type ChannelId = String;
+///
+/// Interned as `Arc<str>` so the name can be shared by the channel, the
+/// subscription index, and every message routed through it without copying.
+pub type ChannelId = Arc<str>;
+
+/// Channel semantics, encoded in the channel name prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChannelKind {
+    /// Open to any connection (`public:` prefix or no prefix).
+    Public,
+    /// Requires an auth signature to subscribe (`private:` prefix).
+    Private,
+    /// Tracks and syncs membership automatically (`presence:` prefix).
+    Presence,
+}
+
+impl ChannelKind {
+    /// Determine the kind of a channel from its name.
+    #[must_use]
+    pub fn from_name(name: &str) -> Self {
+        if name.starts_with("private:") {
+            ChannelKind::Private
+        } else if name.starts_with("presence:") {
+            ChannelKind::Presence
+        } else {
+            ChannelKind::Public
+        }
+    }
+
+    /// Check if subscribing requires an auth signature.
+    #[must_use]
+    pub fn requires_auth(self) -> bool {
+        self == ChannelKind::Private
+    }
+
+    /// Check if membership is tracked automatically.
+    #[must_use]
+    pub fn tracks_presence(self) -> bool {
+        self == ChannelKind::Presence
+    }
+}
 
 /// Validate a channel name.
 ///
@@ -40,17 +89,103 @@ pub fn validate_channel_name(name: &str) -> Result<(), &'static str> {
     Ok(())
 }
 
+/// A subscriber attached by handle, with its delivery options.
+#[derive(Debug)]
+struct Subscriber {
+    handle: Arc<ConnectionHandle>,
+    options: SubscribeOptions,
+}
+
+impl Subscriber {
+    /// Check if the subscriber's options let a message through.
+    fn wants(&self, message: &Message) -> bool {
+        match message.kind {
+            MessageKind::Presence(_) => !self.options.no_presence,
+            MessageKind::ReplayEnd { .. } => true,
+            MessageKind::Publish => {
+                let echo = message.source.as_deref() == Some(self.handle.id());
+                self.options.accepts_event(message.event.as_deref())
+                    && !(self.options.no_echo && echo)
+            }
+        }
+    }
+}
+
+/// Sequencing state and retained messages of a channel.
+#[derive(Debug, Default)]
+pub(crate) struct History {
+    /// Sequence number of the latest published message.
+    seq: u64,
+    /// Most recent messages, oldest first.
+    messages: VecDeque<Arc<Message>>,
+}
+
+impl History {
+    /// Retained messages after `since` passing `filter`, keeping the last
+    /// `last` of them.
+    fn retained(
+        &self,
+        since: Option<u64>,
+        last: Option<usize>,
+        filter: impl Fn(&Message) -> bool,
+    ) -> Vec<Arc<Message>> {
+        let mut messages: Vec<_> = self
+            .messages
+            .iter()
+            .rev()
+            .filter(|m| m.seq > since.unwrap_or(0) && filter(m))
+            .take(last.unwrap_or(usize::MAX))
+            .cloned()
+            .collect();
+        messages.reverse();
+        messages
+    }
+
+    /// Resolve a subscriber's replay cursor to a sequence number, and tell
+    /// whether messages after it have already been evicted.
+    ///
+    /// A `since_id` that is no longer retained falls back to `since`, or to
+    /// everything retained.
+    fn cursor(&self, options: &SubscribeOptions) -> (Option<u64>, bool) {
+        let since = match options.since_id {
+            Some(id) => match self.messages.iter().find(|m| m.id == id) {
+                Some(m) => Some(m.seq.max(options.since.unwrap_or(0))),
+                None if options.since.is_none() => return (None, self.seq > 0),
+                None => options.since,
+            },
+            None => options.since,
+        };
+        let Some(since) = since else {
+            return (None, false);
+        };
+        let oldest = self.messages.front().map_or(self.seq + 1, |m| m.seq);
+        (Some(since), since < self.seq && oldest > since + 1)
+    }
+}
+
 /// A channel for pub/sub messaging.
 #[derive(Debug)]
 pub struct Channel {
     /// Channel name.
     name: ChannelId,
+    /// Channel kind, derived from the name.
+    kind: ChannelKind,
     /// Broadcast sender for this channel.
     sender: broadcast::Sender<Arc<Message>>,
-    /// Set of subscribed connection IDs.
-    subscribers: HashSet<String>,
+    /// Set of subscribed connections.
+    subscribers: HashSet<ConnId>,
+    /// Outbound queues of subscribers attached by handle.
+    handles: HashMap<ConnId, Subscriber>,
+    /// Sequence counter and retained messages.
+    history: Mutex<History>,
+    /// Number of messages retained for replay (0 disables history).
+    history_size: usize,
     /// Channel capacity.
     capacity: usize,
+    /// Maximum number of subscribers, if limited.
+    max_subscribers: Option<usize>,
+    /// What subscribers' full outbound queues do with this channel's messages.
+    drop_policy: DropPolicy,
 }
 
 impl Channel {
@@ -64,20 +199,60 @@ impl Channel {
     #[must_use]
     pub fn with_capacity(name: impl Into<ChannelId>, capacity: usize) -> Self {
         let (sender, _) = broadcast::channel(capacity);
+        let name = name.into();
         Self {
-            name: name.into(),
+            kind: ChannelKind::from_name(&name),
+            name,
             sender,
             subscribers: HashSet::new(),
+            handles: HashMap::new(),
+            history: Mutex::new(History::default()),
+            history_size: 0,
             capacity,
+            max_subscribers: None,
+            drop_policy: DropPolicy::default(),
         }
     }
 
+    /// Limit the number of subscribers this channel admits.
+    #[must_use]
+    pub fn with_max_subscribers(mut self, max_subscribers: Option<usize>) -> Self {
+        self.max_subscribers = max_subscribers;
+        self
+    }
+
+    /// Set the policy applied when a subscriber's outbound queue is full.
+    #[must_use]
+    pub fn with_drop_policy(mut self, drop_policy: DropPolicy) -> Self {
+        self.drop_policy = drop_policy;
+        self
+    }
+
+    /// Retain the last `history_size` messages for replay to new subscribers.
+    #[must_use]
+    pub fn with_history_size(mut self, history_size: usize) -> Self {
+        self.history_size = history_size;
+        self
+    }
+
     /// Get the channel name.
     #[must_use]
     pub fn name(&self) -> &str {
         &self.name
     }
 
+    /// Get the interned channel identifier.
+    #[must_use]
+    pub fn id(&self) -> &ChannelId {
+        &self.name
+    }
+
+    /// Get the channel kind.
+    #[must_use]
+    pub fn kind(&self) -> ChannelKind {
+        self.kind
+    }
+
     /// Get the number of subscribers.
     #[must_use]
     pub fn subscriber_count(&self) -> usize {
@@ -86,43 +261,254 @@ impl Channel {
 
     /// Check if a connection is subscribed.
     #[must_use]
-    pub fn is_subscribed(&self, connection_id: &str) -> bool {
-        self.subscribers.contains(connection_id)
+    pub fn is_subscribed(&self, conn: ConnId) -> bool {
+        self.subscribers.contains(&conn)
     }
 
     /// Subscribe a connection to this channel.
     ///
     /// Returns a receiver for messages on this channel.
-    pub fn subscribe(
-        &mut self,
-        connection_id: impl Into<String>,
-    ) -> broadcast::Receiver<Arc<Message>> {
-        let conn_id = connection_id.into();
-        self.subscribers.insert(conn_id.clone());
-        debug!(channel = %self.name, connection = %conn_id, "Connection subscribed");
+    pub fn subscribe(&mut self, conn: ConnId) -> broadcast::Receiver<Arc<Message>> {
+        self.subscribers.insert(conn);
+        debug!(channel = %self.name, connection = %conn, "Connection subscribed");
         self.sender.subscribe()
     }
 
+    /// Subscribe a connection by its handle.
+    ///
+    /// Messages are pushed straight into the handle's queue instead of a
+    /// broadcast receiver.
+    pub fn subscribe_handle(&mut self, handle: Arc<ConnectionHandle>) {
+        self.subscribe_handle_with(handle, SubscribeOptions::default());
+    }
+
+    /// Subscribe a connection by its handle, with delivery options.
+    ///
+    /// Only messages passing the options' filters are queued. If the options
+    /// ask for a replay, matching retained messages are queued right away,
+    /// followed by a [`MessageKind::ReplayEnd`] marker, so they precede any
+    /// message published afterwards. Returns the number of messages replayed.
+    pub fn subscribe_handle_with(
+        &mut self,
+        handle: Arc<ConnectionHandle>,
+        options: SubscribeOptions,
+    ) -> usize {
+        let conn = handle.conn_id();
+        debug!(channel = %self.name, connection = %conn, "Connection subscribed");
+        let subscriber = Subscriber { handle, options };
+
+        let replayed = if subscriber.options.wants_replay() {
+            let history = self.lock_history();
+            let (since, truncated) = history.cursor(&subscriber.options);
+            let last = subscriber.options.last.map(|n| n as usize);
+            let replayed = history
+                .retained(since, last, |m| subscriber.wants(m))
+                .into_iter()
+                .map(|m| {
+                    // Low-priority messages would otherwise be delivered
+                    // after the marker
+                    Arc::new(Message {
+                        created_at: None,
+                        priority: m.priority.max(Priority::Normal),
+                        ..(*m).clone()
+                    })
+                })
+                .filter(|m| subscriber.handle.push_with(m.clone(), self.drop_policy))
+                .count();
+
+            // Tell the subscriber where replay ends and live delivery begins
+            let marker = Message {
+                seq: history.seq,
+                created_at: None,
+                ..Message::new(self.name.clone(), Bytes::new()).with_kind(MessageKind::ReplayEnd {
+                    replayed,
+                    truncated,
+                })
+            };
+            subscriber
+                .handle
+                .push_with(Arc::new(marker), self.drop_policy);
+            replayed
+        } else {
+            0
+        };
+
+        self.subscribers.insert(conn);
+        self.handles.insert(conn, subscriber);
+        replayed
+    }
+
+    /// Point a handle subscription at another handle, queueing `pending`
+    /// on it first so they are delivered before anything published
+    /// afterwards. Returns the number of pending messages queued.
+    pub(crate) fn redirect(
+        &mut self,
+        conn: ConnId,
+        handle: Arc<ConnectionHandle>,
+        pending: Vec<Arc<Message>>,
+    ) -> usize {
+        let Some(subscriber) = self.handles.get_mut(&conn) else {
+            return 0;
+        };
+        let queued = pending
+            .into_iter()
+            .filter(|m| handle.push_with(m.clone(), self.drop_policy))
+            .count();
+        subscriber.handle = handle;
+        queued
+    }
+
     /// Unsubscribe a connection from this channel.
     ///
     /// Returns `true` if the connection was subscribed.
-    pub fn unsubscribe(&mut self, connection_id: &str) -> bool {
-        let removed = self.subscribers.remove(connection_id);
+    pub fn unsubscribe(&mut self, conn: ConnId) -> bool {
+        let removed = self.subscribers.remove(&conn);
+        self.handles.remove(&conn);
         if removed {
-            debug!(channel = %self.name, connection = %connection_id, "Connection unsubscribed");
+            debug!(channel = %self.name, connection = %conn, "Connection unsubscribed");
         }
         removed
     }
 
     /// Publish a message to this channel.
     ///
-    /// Returns the number of receivers that received the message.
+    /// Published messages are assigned the next sequence number and retained
+    /// if the channel keeps history; presence changes are not. Returns the
+    /// number of receivers that received the message.
     pub fn publish(&self, message: Message) -> usize {
-        let msg = Arc::new(message);
+        self.publish_shared(message, None).1
+    }
+
+    /// Publish a message, returning it as delivered (with its sequence
+    /// number) along with the number of receivers.
+    pub(crate) fn publish_shared(
+        &self,
+        message: Message,
+        tracer: Option<&dyn MessageTracer>,
+    ) -> (Arc<Message>, usize) {
         trace!(channel = %self.name, "Publishing message");
+        if message.kind != MessageKind::Publish {
+            let msg = Arc::new(message);
+            return (msg.clone(), self.deliver(msg, None));
+        }
+
+        let mut history = self.lock_history();
+        let msg = self.sequence(&mut history, message);
+
+        // Deliver under the lock so subscribers see messages in sequence order
+        (msg.clone(), self.deliver(msg, tracer))
+    }
+
+    /// Give a message the next sequence number and retain it, with the
+    /// history locked by the caller.
+    pub(crate) fn sequence(&self, history: &mut History, mut message: Message) -> Arc<Message> {
+        history.seq += 1;
+        message.seq = history.seq;
+        let msg = Arc::new(message);
+        if self.history_size > 0 {
+            if history.messages.len() >= self.history_size {
+                history.messages.pop_front();
+            }
+            history.messages.push_back(msg.clone());
+        }
+        msg
+    }
+
+    fn deliver(&self, msg: Arc<Message>, tracer: Option<&dyn MessageTracer>) -> usize {
+        if let Some(tracer) = tracer.filter(|_| msg.trace) {
+            return self.deliver_traced(msg, tracer);
+        }
+        let queued = self
+            .recipients(&msg)
+            .filter(|handle| handle.push_with(msg.clone(), self.drop_policy))
+            .count();
+        self.broadcast(msg) + queued
+    }
+
+    /// Deliver a message, telling `tracer` what became of it at each
+    /// subscriber.
+    fn deliver_traced(&self, msg: Arc<Message>, tracer: &dyn MessageTracer) -> usize {
+        tracer.trace(
+            &msg,
+            TraceEvent::Routed {
+                subscribers: self.subscriber_count(),
+            },
+        );
+        let mut queued = 0;
+        for subscriber in self.handles.values() {
+            let connection = subscriber.handle.id();
+            let event = if !subscriber.wants(&msg) {
+                TraceEvent::Filtered { connection }
+            } else if subscriber.handle.push_with(msg.clone(), self.drop_policy) {
+                queued += 1;
+                TraceEvent::Enqueued { connection }
+            } else {
+                TraceEvent::Dropped { connection }
+            };
+            tracer.trace(&msg, event);
+        }
+        self.broadcast(msg) + queued
+    }
+
+    /// Handles of the subscribers that want a message.
+    pub(crate) fn recipients<'a>(
+        &'a self,
+        msg: &'a Message,
+    ) -> impl Iterator<Item = &'a Arc<ConnectionHandle>> + 'a {
+        self.handles
+            .values()
+            .filter(|s| s.wants(msg))
+            .map(|s| &s.handle)
+    }
+
+    /// Send a message to broadcast receivers, returning how many there are.
+    pub(crate) fn broadcast(&self, msg: Arc<Message>) -> usize {
         self.sender.send(msg).unwrap_or_default()
     }
 
+    /// Get the sequence number of the latest published message (0 if none).
+    #[must_use]
+    pub fn seq(&self) -> u64 {
+        self.lock_history().seq
+    }
+
+    /// Get the number of retained messages.
+    #[must_use]
+    pub fn history_len(&self) -> usize {
+        self.lock_history().messages.len()
+    }
+
+    /// Visit each retained message, oldest first.
+    pub(crate) fn for_each_retained(&self, mut f: impl FnMut(&Arc<Message>)) {
+        self.lock_history().messages.iter().for_each(&mut f);
+    }
+
+    /// Get the creation timestamp of the oldest retained message.
+    pub(crate) fn oldest_retained(&self) -> Option<u64> {
+        self.lock_history().messages.front().map(|m| m.timestamp)
+    }
+
+    /// Drop the oldest retained message, returning its estimated size.
+    pub(crate) fn evict_oldest(&self) -> Option<usize> {
+        self.lock_history()
+            .messages
+            .pop_front()
+            .map(|m| m.size_estimate())
+    }
+
+    /// Get retained messages, oldest first.
+    ///
+    /// Only messages with a sequence number greater than `since` are
+    /// returned, and at most the `last` most recent of those.
+    #[must_use]
+    pub fn history(&self, since: Option<u64>, last: Option<usize>) -> Vec<Arc<Message>> {
+        self.lock_history().retained(since, last, |_| true)
+    }
+
+    pub(crate) fn lock_history(&self) -> std::sync::MutexGuard<'_, History> {
+        self.history.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
     /// Publish raw payload to this channel.
     ///
     /// Returns the number of receivers that received the message.
@@ -133,8 +519,22 @@ impl Channel {
 
     /// Get all subscriber IDs.
     #[must_use]
-    pub fn subscribers(&self) -> Vec<String> {
-        self.subscribers.iter().cloned().collect()
+    pub fn subscribers(&self) -> Vec<ConnId> {
+        self.subscribers.iter().copied().collect()
+    }
+
+    /// Get how full the fullest subscriber's outbound queue is, as the
+    /// fraction of its capacity in use (0.0 without handle subscribers).
+    ///
+    /// One saturated subscriber is what loses messages, so it isn't
+    /// averaged with idle ones.
+    #[must_use]
+    pub fn queue_fill(&self) -> f64 {
+        self.handles
+            .values()
+            .filter(|s| s.handle.capacity() > 0)
+            .map(|s| s.handle.len() as f64 / s.handle.capacity() as f64)
+            .fold(0.0, f64::max)
     }
 
     /// Check if the channel is empty (no subscribers).
@@ -148,6 +548,31 @@ impl Channel {
     pub fn capacity(&self) -> usize {
         self.capacity
     }
+
+    /// Get the maximum number of subscribers, if limited.
+    #[must_use]
+    pub fn max_subscribers(&self) -> Option<usize> {
+        self.max_subscribers
+    }
+
+    /// Get the number of messages retained for replay.
+    #[must_use]
+    pub fn history_size(&self) -> usize {
+        self.history_size
+    }
+
+    /// Get the drop policy for full subscriber queues.
+    #[must_use]
+    pub fn drop_policy(&self) -> DropPolicy {
+        self.drop_policy
+    }
+
+    /// Check if the channel has reached its subscriber limit.
+    #[must_use]
+    pub fn is_full(&self) -> bool {
+        self.max_subscribers
+            .is_some_and(|max| self.subscribers.len() >= max)
+    }
 }
 
 #[cfg(test)]
@@ -162,23 +587,47 @@ mod tests {
         assert!(channel.is_empty());
     }
 
+    #[test]
+    fn test_channel_kind() {
+        assert_eq!(Channel::new("chat:lobby").kind(), ChannelKind::Public);
+        assert_eq!(Channel::new("public:lobby").kind(), ChannelKind::Public);
+        assert_eq!(Channel::new("private:user-1").kind(), ChannelKind::Private);
+        assert_eq!(Channel::new("presence:room").kind(), ChannelKind::Presence);
+        assert!(ChannelKind::Private.requires_auth());
+        assert!(!ChannelKind::Presence.requires_auth());
+        assert!(ChannelKind::Presence.tracks_presence());
+    }
+
     #[test]
     fn test_channel_subscribe_unsubscribe() {
         let mut channel = Channel::new("test");
 
-        let _rx = channel.subscribe("conn-1");
+        let _rx = channel.subscribe(ConnId::new(1));
         assert_eq!(channel.subscriber_count(), 1);
-        assert!(channel.is_subscribed("conn-1"));
+        assert!(channel.is_subscribed(ConnId::new(1)));
 
-        let _rx2 = channel.subscribe("conn-2");
+        let _rx2 = channel.subscribe(ConnId::new(2));
         assert_eq!(channel.subscriber_count(), 2);
 
-        assert!(channel.unsubscribe("conn-1"));
+        assert!(channel.unsubscribe(ConnId::new(1)));
         assert_eq!(channel.subscriber_count(), 1);
-        assert!(!channel.is_subscribed("conn-1"));
+        assert!(!channel.is_subscribed(ConnId::new(1)));
 
         // Unsubscribing non-existent connection
-        assert!(!channel.unsubscribe("conn-1"));
+        assert!(!channel.unsubscribe(ConnId::new(1)));
+    }
+
+    #[test]
+    fn test_channel_max_subscribers() {
+        let mut channel = Channel::new("call:1").with_max_subscribers(Some(2));
+        assert!(!channel.is_full());
+
+        let _rx1 = channel.subscribe(ConnId::new(1));
+        let _rx2 = channel.subscribe(ConnId::new(2));
+        assert!(channel.is_full());
+
+        channel.unsubscribe(ConnId::new(1));
+        assert!(!channel.is_full());
     }
 
     #[test]
@@ -194,12 +643,153 @@ mod tests {
     #[tokio::test]
     async fn test_channel_publish() {
         let mut channel = Channel::new("test");
-        let mut rx = channel.subscribe("conn-1");
+        let mut rx = channel.subscribe(ConnId::new(1));
 
         let count = channel.publish_payload(b"hello".to_vec());
         assert_eq!(count, 1);
 
         let msg = rx.recv().await.unwrap();
         assert_eq!(&msg.payload[..], b"hello");
+        assert!(Arc::ptr_eq(&msg.channel, channel.id()));
+    }
+
+    #[test]
+    fn test_channel_publish_to_handles() {
+        let mut channel = Channel::new("test");
+        let handle = ConnectionHandle::new(ConnId::new(1), "conn-1");
+        channel.subscribe_handle(handle.clone());
+        let mut rx = channel.subscribe(ConnId::new(2));
+        assert_eq!(channel.subscriber_count(), 2);
+
+        assert_eq!(channel.publish_payload(b"hello".to_vec()), 2);
+        assert_eq!(&handle.try_recv().unwrap().payload[..], b"hello");
+        assert!(rx.try_recv().is_ok());
+
+        channel.unsubscribe(ConnId::new(1));
+        assert_eq!(channel.publish_payload(b"again".to_vec()), 1);
+        assert!(handle.is_empty());
+    }
+
+    #[test]
+    fn test_channel_history_replay() {
+        let mut channel = Channel::new("ticker").with_history_size(3);
+        for payload in [b"1", b"2", b"3", b"4"] {
+            channel.publish_payload(payload.to_vec());
+        }
+        assert_eq!(channel.seq(), 4);
+        assert_eq!(channel.history_len(), 3);
+
+        let seqs = |messages: Vec<Arc<Message>>| messages.iter().map(|m| m.seq).collect::<Vec<_>>();
+        assert_eq!(seqs(channel.history(None, None)), vec![2, 3, 4]);
+        assert_eq!(seqs(channel.history(Some(2), None)), vec![3, 4]);
+        assert_eq!(seqs(channel.history(None, Some(1))), vec![4]);
+
+        let handle = ConnectionHandle::new(ConnId::new(1), "conn-1");
+        let options = SubscribeOptions {
+            since: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(channel.subscribe_handle_with(handle.clone(), options), 2);
+        channel.publish_payload(b"5".to_vec());
+
+        // Replayed copies carry no creation time to measure latency from
+        let received: Vec<_> = std::iter::from_fn(|| handle.try_recv())
+            .map(|m| (m.seq, m.created_at.is_some()))
+            .collect();
+        assert_eq!(
+            received,
+            vec![(3, false), (4, false), (4, false), (5, true)]
+        );
+    }
+
+    #[test]
+    fn test_channel_replay_cursor() {
+        let mut channel = Channel::new("ticker").with_history_size(2);
+        let ids: Vec<_> = (1..=4u8)
+            .map(|n| {
+                let message = Message::new("ticker", vec![n]);
+                let id = message.id;
+                channel.publish(message);
+                id
+            })
+            .collect();
+
+        let mut next_conn = 0;
+        let mut replay = |options: SubscribeOptions| {
+            next_conn += 1;
+            let handle = ConnectionHandle::new(ConnId::new(next_conn), "conn");
+            channel.subscribe_handle_with(handle.clone(), options);
+            std::iter::from_fn(|| handle.try_recv())
+                .map(|m| (m.seq, m.kind))
+                .collect::<Vec<_>>()
+        };
+        let end = |replayed, truncated| {
+            (
+                4,
+                MessageKind::ReplayEnd {
+                    replayed,
+                    truncated,
+                },
+            )
+        };
+
+        // Resume after the third message
+        let options = SubscribeOptions {
+            since_id: Some(ids[2]),
+            ..Default::default()
+        };
+        assert_eq!(
+            replay(options),
+            vec![(4, MessageKind::Publish), end(1, false)]
+        );
+
+        // Up to date: nothing to replay, but the marker still arrives
+        let options = SubscribeOptions {
+            since_id: Some(ids[3]),
+            ..Default::default()
+        };
+        assert_eq!(replay(options), vec![end(0, false)]);
+
+        // The second message was evicted, so resuming after the first has a gap
+        let options = SubscribeOptions {
+            since: Some(1),
+            ..Default::default()
+        };
+        let received = replay(options);
+        assert_eq!(received.len(), 3);
+        assert_eq!(received[2], end(2, true));
+
+        // An evicted cursor replays everything retained
+        let options = SubscribeOptions {
+            since_id: Some(ids[0]),
+            ..Default::default()
+        };
+        assert_eq!(replay(options).last(), Some(&end(2, true)));
+    }
+
+    #[test]
+    fn test_channel_subscriber_filters() {
+        let mut channel = Channel::new("ticker");
+        let handle = ConnectionHandle::new(ConnId::new(1), "conn-1");
+        let options = SubscribeOptions {
+            events: vec!["price".to_string()],
+            no_echo: true,
+            ..Default::default()
+        };
+        channel.subscribe_handle_with(handle.clone(), options);
+
+        channel.publish(Message::new("ticker", b"1".to_vec()).with_event("volume"));
+        channel.publish(
+            Message::new("ticker", b"2".to_vec())
+                .with_event("price")
+                .with_source("conn-1"),
+        );
+        assert_eq!(
+            channel.publish(Message::new("ticker", b"3".to_vec()).with_event("price")),
+            1
+        );
+
+        assert_eq!(&handle.try_recv().unwrap().payload[..], b"3");
+        assert!(handle.is_empty());
     }
 }
@@ -0,0 +1,851 @@
+//! Connection handles for Pulse.
+//!
+//! A [`ConnectionHandle`] is the router's view of a connected client: a
+//! bounded outbound queue that channels push messages into directly. The
+//! transport side drains the queue and writes to the socket, so a connection
+//! needs a single writer no matter how many channels it subscribes to.
+//!
+//! Messages are queued by [`Priority`]: higher-priority messages are taken
+//! first, and a full queue makes room by dropping lower-priority ones first,
+//! so control messages are not stuck behind a backlog of bulk traffic.
+
+use crate::message::Message;
+use pulse_protocol::{DisconnectReason, Priority};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+use tokio::sync::Notify;
+
+/// Default outbound queue capacity per connection.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
+/// Compact connection identifier used internally by the router.
+///
+/// Connections are known to clients by a string ID; the router assigns each
+/// one a `ConnId` so subscription sets and channel maps hash a `u64` instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConnId(u64);
+
+impl ConnId {
+    /// Create a connection ID from its raw value.
+    #[must_use]
+    pub const fn new(id: u64) -> Self {
+        Self(id)
+    }
+
+    /// Get the raw value.
+    #[must_use]
+    pub const fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl std::fmt::Display for ConnId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// What to do with a message when a connection's outbound queue is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DropPolicy {
+    /// Drop the oldest queued message to make room.
+    #[default]
+    DropOldest,
+    /// Drop the incoming message.
+    DropNewest,
+    /// Replace the queued message with the same channel and event name, so
+    /// only the latest value per key is kept. Falls back to dropping the
+    /// oldest message when there is nothing to replace.
+    Coalesce,
+    /// Close the connection rather than lose a message.
+    Disconnect,
+}
+
+/// Why the server closed a connection, reported to the client before the
+/// transport is closed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseReason {
+    /// Reason code.
+    pub reason: DisconnectReason,
+    /// Human-readable explanation.
+    pub message: String,
+    /// How long the client should wait before reconnecting, if it should.
+    pub reconnect_after: Option<Duration>,
+    /// Up to how much longer than `reconnect_after` each connection is asked
+    /// to wait, picked at random per connection when it is closed.
+    pub reconnect_jitter: Option<Duration>,
+    /// Host the client should reconnect to instead.
+    pub alternate_host: Option<String>,
+}
+
+impl CloseReason {
+    /// Create a close reason.
+    #[must_use]
+    pub fn new(reason: DisconnectReason, message: impl Into<String>) -> Self {
+        Self {
+            reason,
+            message: message.into(),
+            reconnect_after: None,
+            reconnect_jitter: None,
+            alternate_host: None,
+        }
+    }
+
+    /// Ask the client to reconnect after a delay.
+    #[must_use]
+    pub fn with_reconnect_after(mut self, delay: Duration) -> Self {
+        self.reconnect_after = Some(delay);
+        self
+    }
+
+    /// Spread reconnects over `spread` past `reconnect_after`, so clients
+    /// closed together don't all come back at once.
+    #[must_use]
+    pub fn with_reconnect_jitter(mut self, spread: Duration) -> Self {
+        self.reconnect_jitter = Some(spread);
+        self
+    }
+
+    /// Pick this connection's reconnect delay, somewhere in the jitter's
+    /// spread past `reconnect_after`.
+    #[must_use]
+    pub fn jittered(&self) -> Self {
+        let Some(spread) = self.reconnect_jitter else {
+            return self.clone();
+        };
+        Self {
+            reconnect_after: Some(
+                self.reconnect_after.unwrap_or_default() + spread.mul_f64(rand::random()),
+            ),
+            reconnect_jitter: None,
+            ..self.clone()
+        }
+    }
+
+    /// Point the client at another host to reconnect to.
+    #[must_use]
+    pub fn with_alternate_host(mut self, host: impl Into<String>) -> Self {
+        self.alternate_host = Some(host.into());
+        self
+    }
+}
+
+/// Overflow counters shared by every handle a router creates.
+///
+/// A connection lags when its queue fills up and messages start being
+/// dropped; it has caught up once the queue drains. Each episode is counted
+/// once, like a broadcast receiver's `Lagged` error.
+#[derive(Debug, Default)]
+pub struct QueueStats {
+    lag_events: AtomicU64,
+    dropped: [AtomicU64; 4],
+}
+
+impl QueueStats {
+    /// Get the number of times a connection started lagging.
+    #[must_use]
+    pub fn lag_events(&self) -> u64 {
+        self.lag_events.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of messages dropped or disconnected on under `policy`.
+    #[must_use]
+    pub fn dropped(&self, policy: DropPolicy) -> u64 {
+        self.dropped[policy as usize].load(Ordering::Relaxed)
+    }
+}
+
+/// What a client sent in the HTTP request that opened its connection.
+///
+/// Recorded by the transport at upgrade time; empty for connections that
+/// didn't arrive over HTTP, such as federation links.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionMetadata {
+    /// `User-Agent` header.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// `Origin` header.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    /// Subprotocols requested in `Sec-WebSocket-Protocol`, in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subprotocols: Vec<String>,
+    /// Other headers the server was configured to record, by lowercase name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+}
+
+/// What became of a message pushed to a connection's queue.
+enum Enqueued {
+    /// Queued, possibly in place of a message it supersedes.
+    Queued,
+    /// Dropped by the queue's overflow rules.
+    Dropped,
+    /// The queue overflowed under [`DropPolicy::Disconnect`].
+    Overflowed,
+}
+
+/// Queued messages, in one lane per priority.
+#[derive(Debug, Default)]
+struct Queue {
+    /// Lanes indexed by priority, each oldest first.
+    lanes: [VecDeque<Arc<Message>>; 3],
+}
+
+impl Queue {
+    fn lane(&mut self, priority: Priority) -> &mut VecDeque<Arc<Message>> {
+        &mut self.lanes[priority as usize]
+    }
+
+    fn len(&self) -> usize {
+        self.lanes.iter().map(VecDeque::len).sum()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.lanes.iter().all(VecDeque::is_empty)
+    }
+
+    /// Iterate in delivery order.
+    fn iter(&self) -> impl Iterator<Item = &Arc<Message>> {
+        self.lanes.iter().rev().flatten()
+    }
+
+    fn push_back(&mut self, message: Arc<Message>) {
+        self.lane(message.priority).push_back(message);
+    }
+
+    /// Take the oldest message of the highest priority queued.
+    fn pop_front(&mut self) -> Option<Arc<Message>> {
+        self.lanes.iter_mut().rev().find_map(VecDeque::pop_front)
+    }
+
+    /// Get the lowest priority queued.
+    fn lowest(&self) -> Option<Priority> {
+        [Priority::Low, Priority::Normal, Priority::High]
+            .into_iter()
+            .find(|p| !self.lanes[*p as usize].is_empty())
+    }
+}
+
+/// A connection's outbound message queue.
+///
+/// Publishers never wait on a slow consumer: when the queue is full, a
+/// lower-priority message is dropped to make room if one is queued;
+/// otherwise the channel's [`DropPolicy`] decides which message is lost, or
+/// whether the connection is closed instead.
+#[derive(Debug)]
+pub struct ConnectionHandle {
+    /// Internal connection ID.
+    conn_id: ConnId,
+    /// External connection ID.
+    id: String,
+    /// Queued messages.
+    queue: Mutex<Queue>,
+    /// Maximum number of queued messages.
+    capacity: usize,
+    /// Wakes the consumer when messages arrive or the handle closes.
+    notify: Notify,
+    /// Set once the connection is gone.
+    closed: AtomicBool,
+    /// Why the handle was closed, if it was closed deliberately.
+    close_reason: OnceLock<CloseReason>,
+    /// Number of messages dropped because the queue was full.
+    dropped: AtomicU64,
+    /// Most messages the queue has held at once.
+    max_queued: AtomicUsize,
+    /// Set while the queue is overflowing, until it drains.
+    lagging: AtomicBool,
+    /// Bytes received from the client.
+    bytes_in: AtomicU64,
+    /// Bytes sent to the client.
+    bytes_out: AtomicU64,
+    /// Messages the client published.
+    messages_in: AtomicU64,
+    /// Messages taken from the queue for delivery.
+    messages_out: AtomicU64,
+    /// Counters shared with the router's other handles.
+    stats: Arc<QueueStats>,
+    /// Application user the connection identified as.
+    user_id: OnceLock<String>,
+    /// Client IP address.
+    remote_ip: OnceLock<IpAddr>,
+    /// The request the connection was opened with.
+    metadata: OnceLock<ConnectionMetadata>,
+    /// Transport the client connected over, such as `websocket`.
+    transport: OnceLock<&'static str>,
+    /// When the handle was created.
+    connected_at: SystemTime,
+}
+
+impl ConnectionHandle {
+    /// Create a handle with the default queue capacity.
+    #[cfg(test)]
+    pub(crate) fn new(conn_id: ConnId, id: impl Into<String>) -> Arc<Self> {
+        Self::with_capacity(conn_id, id, DEFAULT_QUEUE_CAPACITY)
+    }
+
+    /// Create a handle with a specific queue capacity.
+    ///
+    /// Handles are created by [`Router::connect`](crate::Router::connect),
+    /// which assigns the internal ID.
+    #[cfg(test)]
+    pub(crate) fn with_capacity(
+        conn_id: ConnId,
+        id: impl Into<String>,
+        capacity: usize,
+    ) -> Arc<Self> {
+        Self::with_stats(conn_id, id, capacity, Arc::default())
+    }
+
+    /// Create a handle that counts overflows in `stats`.
+    pub(crate) fn with_stats(
+        conn_id: ConnId,
+        id: impl Into<String>,
+        capacity: usize,
+        stats: Arc<QueueStats>,
+    ) -> Arc<Self> {
+        let capacity = capacity.max(1);
+        Arc::new(Self {
+            conn_id,
+            id: id.into(),
+            queue: Mutex::new(Queue::default()),
+            capacity,
+            notify: Notify::new(),
+            closed: AtomicBool::new(false),
+            close_reason: OnceLock::new(),
+            dropped: AtomicU64::new(0),
+            max_queued: AtomicUsize::new(0),
+            lagging: AtomicBool::new(false),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            messages_in: AtomicU64::new(0),
+            messages_out: AtomicU64::new(0),
+            stats,
+            user_id: OnceLock::new(),
+            remote_ip: OnceLock::new(),
+            metadata: OnceLock::new(),
+            transport: OnceLock::new(),
+            connected_at: SystemTime::now(),
+        })
+    }
+
+    /// Get the external connection ID.
+    #[must_use]
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Get the internal connection ID.
+    #[must_use]
+    pub fn conn_id(&self) -> ConnId {
+        self.conn_id
+    }
+
+    /// Record the application user the connection identified as.
+    ///
+    /// A connection identifies once; returns `false` if a user was already set.
+    pub fn set_user_id(&self, user_id: impl Into<String>) -> bool {
+        self.user_id.set(user_id.into()).is_ok()
+    }
+
+    /// Get the application user the connection identified as.
+    #[must_use]
+    pub fn user_id(&self) -> Option<&str> {
+        self.user_id.get().map(String::as_str)
+    }
+
+    /// Record the client's IP address.
+    pub fn set_remote_ip(&self, ip: IpAddr) {
+        let _ = self.remote_ip.set(ip);
+    }
+
+    /// Get the client's IP address, if the transport recorded it.
+    #[must_use]
+    pub fn remote_ip(&self) -> Option<IpAddr> {
+        self.remote_ip.get().copied()
+    }
+
+    /// Record what the client sent when it connected.
+    ///
+    /// Set once by the transport; returns `false` if already set.
+    pub fn set_metadata(&self, metadata: ConnectionMetadata) -> bool {
+        self.metadata.set(metadata).is_ok()
+    }
+
+    /// Get what the client sent when it connected, if the transport
+    /// recorded it.
+    #[must_use]
+    pub fn metadata(&self) -> Option<&ConnectionMetadata> {
+        self.metadata.get()
+    }
+
+    /// Record the transport the client connected over, such as `websocket`.
+    ///
+    /// Set once by the transport; returns `false` if already set.
+    pub fn set_transport(&self, transport: &'static str) -> bool {
+        self.transport.set(transport).is_ok()
+    }
+
+    /// Get the transport the client connected over, if recorded.
+    #[must_use]
+    pub fn transport(&self) -> Option<&'static str> {
+        self.transport.get().copied()
+    }
+
+    /// Get when the connection was made.
+    #[must_use]
+    pub fn connected_at(&self) -> SystemTime {
+        self.connected_at
+    }
+
+    /// Get the queue capacity.
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Get the number of queued messages.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Check if the queue is empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// Get the number of messages dropped because the queue was full.
+    #[must_use]
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Count bytes received from the client.
+    pub fn record_bytes_in(&self, bytes: usize) {
+        self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Count bytes sent to the client.
+    pub fn record_bytes_out(&self, bytes: usize) {
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Get the bytes received from the client, as counted by its transport.
+    #[must_use]
+    pub fn bytes_in(&self) -> u64 {
+        self.bytes_in.load(Ordering::Relaxed)
+    }
+
+    /// Get the bytes sent to the client, as counted by its transport.
+    #[must_use]
+    pub fn bytes_out(&self) -> u64 {
+        self.bytes_out.load(Ordering::Relaxed)
+    }
+
+    /// Count messages the client published.
+    pub fn record_messages_in(&self, count: usize) {
+        self.messages_in.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    /// Get the number of messages the client published, as counted by its
+    /// transport.
+    #[must_use]
+    pub fn messages_in(&self) -> u64 {
+        self.messages_in.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of messages taken from the queue for delivery.
+    #[must_use]
+    pub fn messages_out(&self) -> u64 {
+        self.messages_out.load(Ordering::Relaxed)
+    }
+
+    /// Visit each queued message in delivery order.
+    pub(crate) fn for_each_queued(&self, mut f: impl FnMut(&Arc<Message>)) {
+        self.lock().iter().for_each(&mut f);
+    }
+
+    /// Get the most messages the queue has held at once.
+    #[must_use]
+    pub fn max_queued(&self) -> usize {
+        self.max_queued.load(Ordering::Relaxed)
+    }
+
+    /// Queue a message for delivery, dropping the oldest if the queue is full.
+    ///
+    /// Returns `false` if the handle is closed.
+    pub fn push(&self, message: Arc<Message>) -> bool {
+        self.push_with(message, DropPolicy::DropOldest)
+    }
+
+    /// Queue a message for delivery, applying `policy` if the queue is full.
+    ///
+    /// A full queue first drops a message of lower priority than `message`;
+    /// a message of lower priority than everything queued is dropped itself,
+    /// and [`DropPolicy::Disconnect`] always closes the connection.
+    ///
+    /// Returns `false` if the handle is closed or the message was not queued.
+    pub fn push_with(&self, message: Arc<Message>, policy: DropPolicy) -> bool {
+        self.push_batch([(message, policy)]) == 1
+    }
+
+    /// Queue several messages back to back, applying each one's policy if
+    /// the queue is full. No message pushed concurrently lands between them,
+    /// though a higher-priority one may still be taken first.
+    ///
+    /// Returns the number of messages queued.
+    pub(crate) fn push_batch(
+        &self,
+        messages: impl IntoIterator<Item = (Arc<Message>, DropPolicy)>,
+    ) -> usize {
+        if self.is_closed() {
+            return 0;
+        }
+        let mut queued = 0;
+        {
+            let mut queue = self.lock();
+            for (message, policy) in messages {
+                match self.enqueue(&mut queue, message, policy) {
+                    Enqueued::Queued => queued += 1,
+                    Enqueued::Dropped => {}
+                    Enqueued::Overflowed => {
+                        *queue = Queue::default();
+                        drop(queue);
+                        self.close_with(CloseReason::new(
+                            DisconnectReason::SlowConsumer,
+                            "Outbound queue overflowed",
+                        ));
+                        return 0;
+                    }
+                }
+            }
+            self.max_queued.fetch_max(queue.len(), Ordering::Relaxed);
+        }
+        if queued > 0 {
+            self.notify.notify_one();
+        }
+        queued
+    }
+
+    /// Add a message to a locked queue, making room if it is full.
+    fn enqueue(&self, queue: &mut Queue, message: Arc<Message>, policy: DropPolicy) -> Enqueued {
+        if queue.len() >= self.capacity {
+            // Superseded state updates are replaced whatever the policy
+            if message.coalesce_key.is_some() || policy == DropPolicy::Coalesce {
+                let lane = queue.lane(message.priority);
+                if let Some(slot) = Self::coalesce_slot(lane, &message) {
+                    lane[slot] = message;
+                    self.record_overflow(DropPolicy::Coalesce);
+                    return Enqueued::Queued;
+                }
+            }
+            self.record_overflow(policy);
+            if policy == DropPolicy::Disconnect {
+                return Enqueued::Overflowed;
+            }
+            let lowest = queue.lowest().unwrap_or(message.priority);
+            if lowest > message.priority {
+                return Enqueued::Dropped;
+            }
+            if policy == DropPolicy::DropNewest {
+                if lowest == message.priority {
+                    return Enqueued::Dropped;
+                }
+                queue.lane(lowest).pop_back();
+            } else {
+                queue.lane(lowest).pop_front();
+            }
+        }
+        queue.push_back(message);
+        Enqueued::Queued
+    }
+
+    /// Count a message lost to a full queue under `policy`.
+    fn record_overflow(&self, policy: DropPolicy) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        self.stats.dropped[policy as usize].fetch_add(1, Ordering::Relaxed);
+        if !self.lagging.swap(true, Ordering::Relaxed) {
+            self.stats.lag_events.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Find the newest message in a lane that `message` supersedes: one on
+    /// the same channel with the same coalescing key, or for unkeyed
+    /// messages, the same event name.
+    fn coalesce_slot(lane: &VecDeque<Arc<Message>>, message: &Message) -> Option<usize> {
+        lane.iter().rposition(|m| {
+            m.channel == message.channel
+                && match (&message.coalesce_key, &message.event) {
+                    (Some(key), _) => m.coalesce_key.as_ref() == Some(key),
+                    (None, Some(event)) => {
+                        m.coalesce_key.is_none() && m.event.as_ref() == Some(event)
+                    }
+                    (None, None) => false,
+                }
+        })
+    }
+
+    /// Take the next queued message without waiting.
+    #[must_use]
+    pub fn try_recv(&self) -> Option<Arc<Message>> {
+        let mut queue = self.lock();
+        let message = queue.pop_front();
+        if queue.is_empty() {
+            self.lagging.store(false, Ordering::Relaxed);
+        }
+        if message.is_some() {
+            self.messages_out.fetch_add(1, Ordering::Relaxed);
+        }
+        message
+    }
+
+    /// Wait for the next queued message.
+    ///
+    /// Returns `None` once the handle is closed and the queue is drained.
+    pub async fn recv(&self) -> Option<Arc<Message>> {
+        loop {
+            if let Some(message) = self.try_recv() {
+                return Some(message);
+            }
+            if self.is_closed() {
+                return None;
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    /// Close the handle, waking any pending [`recv`](Self::recv).
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.notify.notify_one();
+    }
+
+    /// Close the handle, recording why so the transport can tell the client.
+    ///
+    /// The first reason recorded wins. A reconnect jitter is resolved into
+    /// this connection's own delay.
+    pub fn close_with(&self, reason: CloseReason) {
+        let _ = self.close_reason.set(reason.jittered());
+        self.close();
+    }
+
+    /// Check if the handle is closed.
+    #[must_use]
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    /// Get why the handle was closed, if it was closed with a reason.
+    #[must_use]
+    pub fn close_reason(&self) -> Option<&CloseReason> {
+        self.close_reason.get()
+    }
+
+    /// Check if the handle was closed because its queue overflowed under
+    /// [`DropPolicy::Disconnect`].
+    #[must_use]
+    pub fn is_overflowed(&self) -> bool {
+        self.close_reason()
+            .is_some_and(|r| r.reason == DisconnectReason::SlowConsumer)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(payload: &'static [u8]) -> Arc<Message> {
+        Arc::new(Message::new("test", payload))
+    }
+
+    #[tokio::test]
+    async fn test_handle_push_recv() {
+        let handle = ConnectionHandle::new(ConnId::new(1), "conn-1");
+        assert!(handle.push(message(b"one")));
+        assert!(handle.push(message(b"two")));
+        assert_eq!(handle.len(), 2);
+
+        assert_eq!(&handle.recv().await.unwrap().payload[..], b"one");
+        assert_eq!(&handle.recv().await.unwrap().payload[..], b"two");
+        assert!(handle.try_recv().is_none());
+        assert_eq!(handle.messages_out(), 2);
+
+        handle.close();
+        assert!(!handle.push(message(b"three")));
+        assert!(handle.recv().await.is_none());
+    }
+
+    #[test]
+    fn test_handle_drops_oldest_when_full() {
+        let handle = ConnectionHandle::with_capacity(ConnId::new(1), "conn-1", 2);
+        handle.push(message(b"one"));
+        handle.push(message(b"two"));
+        handle.push(message(b"three"));
+
+        assert_eq!(handle.len(), 2);
+        assert_eq!(handle.dropped(), 1);
+        assert_eq!(&handle.try_recv().unwrap().payload[..], b"two");
+    }
+
+    #[test]
+    fn test_handle_drop_policies() {
+        let handle = ConnectionHandle::with_capacity(ConnId::new(1), "conn-1", 1);
+        handle.push(message(b"one"));
+        assert!(!handle.push_with(message(b"two"), DropPolicy::DropNewest));
+        assert_eq!(&handle.try_recv().unwrap().payload[..], b"one");
+
+        let tick =
+            |payload: &'static [u8]| Arc::new(Message::new("ticker", payload).with_event("price"));
+        let handle = ConnectionHandle::with_capacity(ConnId::new(1), "conn-1", 2);
+        handle.push(tick(b"1"));
+        handle.push(message(b"other"));
+        assert!(handle.push_with(tick(b"2"), DropPolicy::Coalesce));
+        assert_eq!(handle.len(), 2);
+        assert_eq!(&handle.try_recv().unwrap().payload[..], b"2");
+        assert_eq!(handle.dropped(), 1);
+
+        let handle = ConnectionHandle::with_capacity(ConnId::new(1), "conn-1", 1);
+        handle.push(message(b"one"));
+        assert!(!handle.push_with(message(b"two"), DropPolicy::Disconnect));
+        assert!(handle.is_closed());
+        assert!(handle.is_overflowed());
+        assert!(handle.is_empty());
+    }
+
+    #[test]
+    fn test_handle_priority() {
+        let at = |payload: &'static [u8], priority| {
+            Arc::new(Message::new("test", payload).with_priority(priority))
+        };
+        let handle = ConnectionHandle::with_capacity(ConnId::new(1), "conn-1", 3);
+        handle.push(at(b"low-1", Priority::Low));
+        handle.push(at(b"normal", Priority::Normal));
+        handle.push(at(b"low-2", Priority::Low));
+
+        // A full queue makes room from the lowest priority, whatever the policy
+        assert!(handle.push_with(at(b"kick", Priority::High), DropPolicy::DropNewest));
+        assert!(handle.push(at(b"presence", Priority::High)));
+        // Nothing lower is left to drop
+        assert!(!handle.push(at(b"telemetry", Priority::Low)));
+        assert_eq!(handle.dropped(), 3);
+
+        let order: Vec<_> = std::iter::from_fn(|| handle.try_recv())
+            .map(|m| m.payload.to_vec())
+            .collect();
+        assert_eq!(order, [&b"kick"[..], b"presence", b"normal"]);
+    }
+
+    #[test]
+    fn test_handle_coalesce_key() {
+        let cursor = |key: &'static str, payload: &'static [u8]| {
+            Arc::new(
+                Message::new("doc:1", payload)
+                    .with_event("cursor")
+                    .with_coalesce_key(key),
+            )
+        };
+        let stats = Arc::new(QueueStats::default());
+        let handle = ConnectionHandle::with_stats(ConnId::new(1), "conn-1", 3, stats.clone());
+        handle.push(cursor("cursor:alice", b"a1"));
+        handle.push(cursor("cursor:bob", b"b1"));
+        handle.push(message(b"chat"));
+
+        // Replaced in place under any policy once the queue is full
+        assert!(handle.push_with(cursor("cursor:alice", b"a2"), DropPolicy::DropNewest));
+        assert!(handle.push(cursor("cursor:carol", b"c1")));
+        assert_eq!(stats.dropped(DropPolicy::Coalesce), 1);
+        assert_eq!(stats.dropped(DropPolicy::DropOldest), 1);
+
+        let payloads: Vec<_> = std::iter::from_fn(|| handle.try_recv())
+            .map(|m| m.payload.to_vec())
+            .collect();
+        assert_eq!(payloads, [&b"b1"[..], b"chat", b"c1"]);
+    }
+
+    #[test]
+    fn test_handle_queue_stats() {
+        let stats = Arc::new(QueueStats::default());
+        let handle = ConnectionHandle::with_stats(ConnId::new(1), "conn-1", 2, stats.clone());
+        for payload in [b"1", b"2", b"3", b"4"] {
+            handle.push(message(payload));
+        }
+        assert!(!handle.push_with(message(b"5"), DropPolicy::DropNewest));
+        assert_eq!(handle.max_queued(), 2);
+        assert_eq!(stats.dropped(DropPolicy::DropOldest), 2);
+        assert_eq!(stats.dropped(DropPolicy::DropNewest), 1);
+        // One episode until the queue drains
+        assert_eq!(stats.lag_events(), 1);
+
+        while handle.try_recv().is_some() {}
+        handle.push(message(b"6"));
+        handle.push(message(b"7"));
+        handle.push(message(b"8"));
+        assert_eq!(stats.lag_events(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_handle_close_with_reason() {
+        let handle = ConnectionHandle::new(ConnId::new(1), "conn-1");
+        handle.push(message(b"one"));
+        handle.close_with(
+            CloseReason::new(DisconnectReason::Shutdown, "Restarting")
+                .with_reconnect_after(Duration::from_secs(1)),
+        );
+        handle.close_with(CloseReason::new(DisconnectReason::Kicked, "Later"));
+
+        // Queued messages drain before recv reports the close
+        assert!(handle.recv().await.is_some());
+        assert!(handle.recv().await.is_none());
+        let reason = handle.close_reason().unwrap();
+        assert_eq!(reason.reason, DisconnectReason::Shutdown);
+        assert_eq!(reason.reconnect_after, Some(Duration::from_secs(1)));
+        assert!(!handle.is_overflowed());
+    }
+
+    #[test]
+    fn test_close_reason_jitter() {
+        let reason = CloseReason::new(DisconnectReason::Shutdown, "Restarting")
+            .with_reconnect_after(Duration::from_secs(1))
+            .with_reconnect_jitter(Duration::from_secs(10));
+        let delays: Vec<_> = (0..20)
+            .map(|_| {
+                let jittered = reason.jittered();
+                assert_eq!(jittered.reconnect_jitter, None);
+                jittered.reconnect_after.unwrap()
+            })
+            .collect();
+        assert!(delays
+            .iter()
+            .all(|d| (Duration::from_secs(1)..Duration::from_secs(11)).contains(d)));
+        assert!(delays.iter().any(|d| *d != delays[0]));
+
+        // Without jitter the delay is kept as is
+        let plain = CloseReason::new(DisconnectReason::Kicked, "Bye");
+        assert_eq!(plain.jittered(), plain);
+    }
+
+    #[tokio::test]
+    async fn test_handle_recv_wakes_on_push() {
+        let handle = ConnectionHandle::new(ConnId::new(1), "conn-1");
+        let consumer = {
+            let handle = handle.clone();
+            tokio::spawn(async move { handle.recv().await })
+        };
+
+        tokio::task::yield_now().await;
+        handle.push(message(b"hello"));
+        let received = consumer.await.unwrap().unwrap();
+        assert_eq!(&received.payload[..], b"hello");
+    }
+}
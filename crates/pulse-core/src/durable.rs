@@ -0,0 +1,66 @@
+//! Durable named subscriptions.
+//!
+//! A durable subscription outlives the connection that created it. While a
+//! consumer is attached, the channel delivers to the consumer's handle as
+//! usual. When the consumer disconnects, the subscription falls back to a
+//! buffer handle of its own, which keeps collecting the channel's messages
+//! until a consumer reattaches by name or the subscription's TTL runs out.
+//! Reattaching delivers the buffered messages first, in order.
+
+use crate::channel::ChannelId;
+use crate::connection::{ConnId, ConnectionHandle};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Default for [`DurableConfig::ttl`].
+pub const DEFAULT_DURABLE_TTL: Duration = Duration::from_secs(300);
+
+/// Default for [`DurableConfig::capacity`].
+pub const DEFAULT_DURABLE_CAPACITY: usize = 10_000;
+
+/// Limits for durable subscriptions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DurableConfig {
+    /// Maximum number of durable subscriptions (0 disables them).
+    pub max_subscriptions: usize,
+    /// How long a subscription without a consumer is kept.
+    pub ttl: Duration,
+    /// Messages buffered while no consumer is attached; the channel's drop
+    /// policy applies beyond this.
+    pub capacity: usize,
+}
+
+impl Default for DurableConfig {
+    fn default() -> Self {
+        Self {
+            max_subscriptions: 0,
+            ttl: DEFAULT_DURABLE_TTL,
+            capacity: DEFAULT_DURABLE_CAPACITY,
+        }
+    }
+}
+
+/// A durable subscription's state.
+#[derive(Debug)]
+pub(crate) struct Durable {
+    /// Channel the subscription is for.
+    pub(crate) channel: ChannelId,
+    /// Holds messages while no consumer is attached; the channel knows the
+    /// subscription by this handle's connection ID.
+    pub(crate) buffer: Arc<ConnectionHandle>,
+    /// Attached consumer, if any.
+    pub(crate) consumer: Option<ConnId>,
+    /// When the last consumer detached.
+    pub(crate) detached_at: Option<Instant>,
+}
+
+impl Durable {
+    /// Check if the subscription has been without a consumer for `ttl`, or
+    /// its buffer was closed.
+    pub(crate) fn is_expired(&self, ttl: Duration, now: Instant) -> bool {
+        self.buffer.is_closed()
+            || self
+                .detached_at
+                .is_some_and(|at| now.saturating_duration_since(at) >= ttl)
+    }
+}
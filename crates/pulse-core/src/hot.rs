@@ -0,0 +1,317 @@
+//! Approximate top-K of the busiest channels.
+//!
+//! [`HotChannels`] keeps two space-saving sketches, one counting messages
+//! and one counting payload bytes per channel, in a fixed number of
+//! counters however many channels exist. A channel carrying more than
+//! 1/`counters` of a window's traffic is always counted, and a counted
+//! channel's estimate overstates it by at most the error reported with it.
+//!
+//! The sketches are sharded by channel, and each shard gets all `counters`:
+//! a channel's traffic all lands in one shard, so a channel over
+//! 1/`counters` of the total is over 1/`counters` of its shard's share too,
+//! however unevenly channels spread across shards.
+//!
+//! Counts build up over a window. [`HotChannels::rotate`] closes the window
+//! and turns its counts into rates, which [`HotChannels::report`] serves
+//! until the next rotation.
+
+use crate::channel::ChannelId;
+use serde::Serialize;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Instant;
+
+/// Counters per sketch unless configured otherwise.
+pub const DEFAULT_HOT_CHANNEL_COUNTERS: usize = 256;
+
+/// Independently locked sketches, so publishes to different channels
+/// rarely wait on each other. Each channel always lands in the same shard.
+const SHARDS: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+struct Counter {
+    count: u64,
+    /// How much of `count` may belong to channels this counter replaced.
+    error: u64,
+}
+
+/// A space-saving sketch of channel weights.
+#[derive(Debug, Default)]
+struct Sketch {
+    capacity: usize,
+    counters: HashMap<ChannelId, Counter>,
+}
+
+impl Sketch {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            counters: HashMap::with_capacity(capacity),
+        }
+    }
+
+    fn add(&mut self, channel: &ChannelId, weight: u64) {
+        if let Some(counter) = self.counters.get_mut(channel) {
+            counter.count += weight;
+            return;
+        }
+        if self.counters.len() < self.capacity {
+            let counter = Counter {
+                count: weight,
+                error: 0,
+            };
+            self.counters.insert(channel.clone(), counter);
+            return;
+        }
+        // The newcomer takes over the smallest counter, inheriting its
+        // count as error
+        let Some((smallest, min)) = self
+            .counters
+            .iter()
+            .min_by_key(|(_, counter)| counter.count)
+            .map(|(channel, counter)| (channel.clone(), counter.count))
+        else {
+            return;
+        };
+        self.counters.remove(&smallest);
+        let counter = Counter {
+            count: min + weight,
+            error: min,
+        };
+        self.counters.insert(channel.clone(), counter);
+    }
+}
+
+#[derive(Debug)]
+struct Shard {
+    messages: Sketch,
+    bytes: Sketch,
+}
+
+/// A channel's estimated rate in the last window.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HotChannel {
+    pub channel: String,
+    /// Messages or bytes per second.
+    pub rate: f64,
+    /// Most by which `rate` may overstate the channel's own traffic.
+    pub error: f64,
+}
+
+/// The busiest channels in the last window, highest rate first.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct HotChannelReport {
+    /// Length of the window the rates were measured over.
+    pub window_secs: f64,
+    /// By messages published per second.
+    pub by_messages: Vec<HotChannel>,
+    /// By payload bytes published per second.
+    pub by_bytes: Vec<HotChannel>,
+}
+
+impl HotChannelReport {
+    /// Keep only the `k` busiest channels of each ranking.
+    #[must_use]
+    pub fn top(mut self, k: usize) -> Self {
+        self.by_messages.truncate(k);
+        self.by_bytes.truncate(k);
+        self
+    }
+}
+
+/// Tracks the channels with the highest message and byte rates.
+#[derive(Debug)]
+pub struct HotChannels {
+    shards: Box<[Mutex<Shard>]>,
+    hasher: RandomState,
+    window_start: Mutex<Instant>,
+    last: Mutex<HotChannelReport>,
+}
+
+impl HotChannels {
+    /// Track channels in `counters` counters per sketch shard; 0 disables
+    /// tracking.
+    #[must_use]
+    pub fn new(counters: usize) -> Self {
+        let shards = if counters == 0 { 0 } else { SHARDS };
+        Self {
+            shards: (0..shards)
+                .map(|_| {
+                    Mutex::new(Shard {
+                        messages: Sketch::new(counters),
+                        bytes: Sketch::new(counters),
+                    })
+                })
+                .collect(),
+            hasher: RandomState::new(),
+            window_start: Mutex::new(Instant::now()),
+            last: Mutex::new(HotChannelReport::default()),
+        }
+    }
+
+    /// Count a message of `bytes` payload bytes published to `channel`.
+    pub fn record(&self, channel: &ChannelId, bytes: usize) {
+        if self.shards.is_empty() {
+            return;
+        }
+        let mut shard = lock(&self.shards[self.shard(channel)]);
+        shard.messages.add(channel, 1);
+        shard.bytes.add(channel, bytes as u64);
+    }
+
+    /// Close the current window, turning its counts into the rates served
+    /// by [`report`](Self::report), and start the next.
+    pub fn rotate(&self) {
+        let elapsed = {
+            let mut start = lock(&self.window_start);
+            let elapsed = start.elapsed();
+            *start = Instant::now();
+            elapsed
+        };
+        let mut messages = Vec::new();
+        let mut bytes = Vec::new();
+        for shard in &*self.shards {
+            let mut shard = lock(shard);
+            let capacity = shard.messages.capacity;
+            messages.extend(std::mem::replace(&mut shard.messages, Sketch::new(capacity)).counters);
+            bytes.extend(std::mem::replace(&mut shard.bytes, Sketch::new(capacity)).counters);
+        }
+        let secs = elapsed.as_secs_f64();
+        *lock(&self.last) = HotChannelReport {
+            window_secs: secs,
+            by_messages: ranked(messages, secs),
+            by_bytes: ranked(bytes, secs),
+        };
+    }
+
+    /// Get the busiest channels of the last completed window.
+    #[must_use]
+    pub fn report(&self) -> HotChannelReport {
+        lock(&self.last).clone()
+    }
+
+    fn shard(&self, channel: &str) -> usize {
+        self.hasher.hash_one(channel) as usize % self.shards.len()
+    }
+}
+
+/// Turn counters into rates, highest first.
+fn ranked(counters: Vec<(ChannelId, Counter)>, secs: f64) -> Vec<HotChannel> {
+    if secs <= 0.0 {
+        return Vec::new();
+    }
+    let mut ranked: Vec<HotChannel> = counters
+        .into_iter()
+        .map(|(channel, counter)| HotChannel {
+            channel: channel.to_string(),
+            rate: counter.count as f64 / secs,
+            error: counter.error as f64 / secs,
+        })
+        .collect();
+    ranked.sort_unstable_by(|a, b| {
+        b.rate
+            .total_cmp(&a.rate)
+            .then_with(|| a.channel.cmp(&b.channel))
+    });
+    ranked
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_sketch_keeps_heavy_hitters() {
+        // Far more channels than counters, with three heavy hitters
+        let mut sketch = Sketch::new(8);
+        for i in 0..2000u64 {
+            let name: ChannelId = match i % 4 {
+                0 => "hot:a".into(),
+                1 => "hot:b".into(),
+                _ => format!("cold:{i}").into(),
+            };
+            sketch.add(&name, 1);
+            if i % 10 == 0 {
+                sketch.add(&"hot:c".into(), 3);
+            }
+        }
+        assert_eq!(sketch.counters.len(), 8);
+        let a = sketch.counters["hot:a"];
+        assert!(a.count >= 500 && a.count - a.error <= 500);
+        assert!(sketch.counters.contains_key("hot:b"));
+        assert!(sketch.counters.contains_key("hot:c"));
+    }
+
+    #[test]
+    fn test_hot_channels_report() {
+        let hot = HotChannels::new(64);
+        let busy: ChannelId = "room:busy".into();
+        let big: ChannelId = "room:big".into();
+        for _ in 0..100 {
+            hot.record(&busy, 10);
+        }
+        hot.record(&big, 100_000);
+
+        // Nothing to report until the first window closes
+        assert!(hot.report().by_messages.is_empty());
+        std::thread::sleep(Duration::from_millis(10));
+        hot.rotate();
+        let report = hot.report().top(1);
+        assert_eq!(report.by_messages.len(), 1);
+        assert_eq!(report.by_messages[0].channel, "room:busy");
+        assert_eq!(report.by_bytes[0].channel, "room:big");
+        assert!(report.by_messages[0].rate > 0.0);
+        assert_eq!(report.by_messages[0].error, 0.0);
+
+        // Each window starts afresh
+        hot.rotate();
+        assert!(hot.report().by_messages.is_empty());
+
+        let disabled = HotChannels::new(0);
+        disabled.record(&busy, 10);
+        disabled.rotate();
+        assert!(disabled.report().by_messages.is_empty());
+    }
+
+    #[test]
+    fn test_hot_channels_skewed_shard() {
+        // Every channel lands in the heavy hitter's shard, which then sees
+        // all the traffic and must still honor the global bound
+        let hot = HotChannels::new(8);
+        let heavy: ChannelId = "room:heavy".into();
+        let shard = hot.shard(&heavy);
+        let cold: Vec<ChannelId> = (0..)
+            .map(|i| ChannelId::from(format!("room:{i}")))
+            .filter(|channel| hot.shard(channel) == shard)
+            .take(20)
+            .collect();
+
+        // 3 of every 23 messages, just over 1/8 of the traffic
+        for _ in 0..50 {
+            for _ in 0..3 {
+                hot.record(&heavy, 1);
+            }
+            for channel in &cold {
+                hot.record(channel, 1);
+            }
+        }
+        std::thread::sleep(Duration::from_millis(10));
+        hot.rotate();
+        let report = hot.report();
+        let counted = report
+            .by_messages
+            .iter()
+            .find(|c| c.channel == "room:heavy")
+            .expect("heavy hitter was dropped");
+        let count = counted.rate * report.window_secs;
+        let error = counted.error * report.window_secs;
+        assert!(count >= 149.5 && count - error <= 150.5);
+    }
+}
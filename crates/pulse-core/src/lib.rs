@@ -5,9 +5,20 @@
 //! This crate provides the fundamental building blocks:
 //!
 //! - **Channel** - Room/topic abstraction for grouping connections
+//! - **Auth** - Signature checks for private channels
+//! - **Connection** - Per-connection outbound queues fed by the router
+//! - **Durable** - Named subscriptions that buffer messages across reconnects
+//! - **Hot** - Approximate top-K of the busiest channels
+//! - **Listing** - Paginated listings of channels and subscribers
 //! - **Router** - High-performance pub/sub message routing
 //! - **Presence** - Track and broadcast user presence
 //! - **Message** - Internal message types
+//! - **Moderation** - Hooks that can veto publishes
+//! - **Occupancy** - Notifications when channels gain or lose all subscribers
+//! - **Pattern** - Glob patterns for per-channel settings
+//! - **Signing** - HMAC signatures proving who published a message
+//! - **Trace** - Following individual messages through the router
+//! - **Validator** - Pluggable channel naming rules
 //!
 //! ## Architecture
 //!
@@ -22,12 +33,43 @@
 //!                     └─────────────┘
 //! ```
 
+pub mod auth;
 pub mod channel;
+pub mod connection;
+pub mod durable;
+pub mod hot;
+pub mod listing;
 pub mod message;
+pub mod moderation;
+pub mod occupancy;
+pub mod pattern;
 pub mod presence;
 pub mod router;
+pub mod signing;
+pub mod trace;
+pub mod validator;
 
-pub use channel::{Channel, ChannelId};
-pub use message::Message;
-pub use presence::{Presence, PresenceState};
-pub use router::{Router, RouterConfig, RouterError};
+pub use auth::{ChannelAuthorizer, HmacAuthorizer};
+pub use channel::{Channel, ChannelId, ChannelKind, SYSTEM_CHANNEL_PREFIX};
+pub use connection::{
+    CloseReason, ConnId, ConnectionHandle, ConnectionMetadata, DropPolicy, QueueStats,
+};
+pub use durable::DurableConfig;
+pub use hot::{HotChannel, HotChannelReport, HotChannels};
+pub use listing::{
+    ChannelQuery, ChannelSort, Page, SubscriberInfo, SubscriberQuery, SubscriberSort,
+};
+pub use message::{Message, MessageKind};
+pub use moderation::{Moderator, Rejection, WordFilter};
+pub use occupancy::{OccupancyChange, OccupancyObserver};
+pub use pattern::ChannelPattern;
+pub use presence::{Presence, PresenceDelta, PresenceState};
+pub use pulse_protocol::Priority;
+pub use router::{
+    ChannelRule, ChannelStats, GroupPublishReceipt, MemoryEviction, MemoryUsage,
+    PatternPublishReceipt, PublishReceipt, Router, RouterConfig, RouterError, RouterStats,
+    SubscriptionInfo,
+};
+pub use signing::MessageSigner;
+pub use trace::{MessageTracer, TraceEvent};
+pub use validator::ChannelNameValidator;
@@ -0,0 +1,382 @@
+//! Cursor-based pagination for listing channels and subscribers.
+//!
+//! A listing is sorted by a key, and each page starts after the key of the
+//! previous page's last item, which [`Page::next_cursor`] carries as an
+//! opaque string. Items added or removed between pages don't shift the
+//! rest, but an item whose key changes meanwhile, such as a channel gaining
+//! subscribers while listed by subscriber count, may be skipped or repeated.
+//!
+//! Each page walks the whole channel or subscriber set once, without
+//! sorting more than the page itself, so paging through 100k subscribers a
+//! thousand at a time stays cheap.
+
+use crate::router::{ChannelStats, RouterError};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Items per page when a query sets no limit.
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// Most items a page holds, whatever the query asks for.
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+/// One page of a listing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Page<T> {
+    /// Items on this page, in listing order.
+    pub items: Vec<T>,
+    /// Cursor for the next page, or `None` on the last page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// Convert the items, keeping the cursor.
+    #[must_use]
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            next_cursor: self.next_cursor,
+        }
+    }
+}
+
+/// Order of a channel listing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelSort {
+    /// By name.
+    #[default]
+    Name,
+    /// Most subscribers first, then by name.
+    Subscribers,
+}
+
+/// Filters, order and position of a channel listing. Unset filters match
+/// everything.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ChannelQuery {
+    /// Only channels whose name starts with this.
+    #[serde(default)]
+    pub prefix: Option<String>,
+    /// Only channels with at least this many subscribers.
+    #[serde(default)]
+    pub min_subscribers: Option<usize>,
+    /// Only channels a connection of this user is subscribed to.
+    #[serde(default)]
+    pub user_id: Option<String>,
+    #[serde(default)]
+    pub sort: ChannelSort,
+    /// Cursor from the previous page.
+    #[serde(default)]
+    pub cursor: Option<String>,
+    /// Items per page, up to [`MAX_PAGE_SIZE`].
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+impl ChannelQuery {
+    /// Whether a channel passes the name and subscriber filters.
+    #[must_use]
+    pub fn matches(&self, stats: &ChannelStats) -> bool {
+        self.prefix
+            .as_ref()
+            .map_or(true, |prefix| stats.channel.starts_with(prefix.as_str()))
+            && self
+                .min_subscribers
+                .map_or(true, |min| stats.subscribers >= min)
+    }
+
+    /// Sort matching channels and take the page after the cursor.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RouterError::InvalidCursor`] if the cursor wasn't made by
+    /// a listing in the same order.
+    pub fn page(
+        &self,
+        channels: impl IntoIterator<Item = ChannelStats>,
+    ) -> Result<Page<ChannelStats>, RouterError> {
+        let channels = channels.into_iter().filter(|c| self.matches(c));
+        let limit = page_size(self.limit);
+        let cursor = self.cursor.as_deref();
+        match self.sort {
+            ChannelSort::Name => {
+                let after = cursor.map(str::to_string);
+                let (items, more) =
+                    paginate(channels, |c| c.channel.to_string(), after.as_ref(), limit);
+                Ok(page(items, more, |c| c.channel.to_string()))
+            }
+            ChannelSort::Subscribers => {
+                let after = cursor
+                    .map(|c| parse_cursor::<usize>(c).map(|(n, name)| (Reverse(n), name)))
+                    .transpose()?;
+                let key = |c: &ChannelStats| (Reverse(c.subscribers), c.channel.to_string());
+                let (items, more) = paginate(channels, key, after.as_ref(), limit);
+                Ok(page(items, more, |c| {
+                    format!("{}:{}", c.subscribers, c.channel)
+                }))
+            }
+        }
+    }
+}
+
+/// A channel subscriber, as listed by
+/// [`Router::list_subscribers`](crate::Router::list_subscribers).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SubscriberInfo {
+    pub connection_id: String,
+    pub user_id: Option<String>,
+    /// Transport the client connected over, such as `websocket`.
+    pub transport: Option<String>,
+    pub ip: Option<IpAddr>,
+    /// When the client connected, in milliseconds since the epoch.
+    pub connected_at: u64,
+}
+
+/// Order of a subscriber listing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriberSort {
+    /// By connection ID.
+    #[default]
+    ConnectionId,
+    /// Oldest connection first, then by connection ID.
+    ConnectedAt,
+}
+
+/// Filters, order and position of a subscriber listing. Unset filters
+/// match everything.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SubscriberQuery {
+    #[serde(default)]
+    pub user_id: Option<String>,
+    #[serde(default)]
+    pub transport: Option<String>,
+    /// Only connections made at or after this time, in milliseconds since
+    /// the epoch.
+    #[serde(default)]
+    pub connected_after: Option<u64>,
+    /// Only connections made before this time, in milliseconds since the
+    /// epoch.
+    #[serde(default)]
+    pub connected_before: Option<u64>,
+    #[serde(default)]
+    pub sort: SubscriberSort,
+    /// Cursor from the previous page.
+    #[serde(default)]
+    pub cursor: Option<String>,
+    /// Items per page, up to [`MAX_PAGE_SIZE`].
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+impl SubscriberQuery {
+    /// Whether a subscriber passes the filters.
+    #[must_use]
+    pub fn matches(&self, subscriber: &SubscriberInfo) -> bool {
+        self.user_id
+            .as_ref()
+            .map_or(true, |id| subscriber.user_id.as_ref() == Some(id))
+            && self
+                .transport
+                .as_ref()
+                .map_or(true, |t| subscriber.transport.as_ref() == Some(t))
+            && self
+                .connected_after
+                .map_or(true, |ms| subscriber.connected_at >= ms)
+            && self
+                .connected_before
+                .map_or(true, |ms| subscriber.connected_at < ms)
+    }
+
+    /// Sort matching subscribers and take the page after the cursor.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RouterError::InvalidCursor`] if the cursor wasn't made by
+    /// a listing in the same order.
+    pub fn page(
+        &self,
+        subscribers: impl IntoIterator<Item = SubscriberInfo>,
+    ) -> Result<Page<SubscriberInfo>, RouterError> {
+        let subscribers = subscribers.into_iter().filter(|s| self.matches(s));
+        let limit = page_size(self.limit);
+        let cursor = self.cursor.as_deref();
+        match self.sort {
+            SubscriberSort::ConnectionId => {
+                let after = cursor.map(str::to_string);
+                let key = |s: &SubscriberInfo| s.connection_id.clone();
+                let (items, more) = paginate(subscribers, key, after.as_ref(), limit);
+                Ok(page(items, more, |s| s.connection_id.clone()))
+            }
+            SubscriberSort::ConnectedAt => {
+                let after = cursor.map(parse_cursor::<u64>).transpose()?;
+                let key = |s: &SubscriberInfo| (s.connected_at, s.connection_id.clone());
+                let (items, more) = paginate(subscribers, key, after.as_ref(), limit);
+                Ok(page(items, more, |s| {
+                    format!("{}:{}", s.connected_at, s.connection_id)
+                }))
+            }
+        }
+    }
+}
+
+/// Milliseconds since the epoch, 0 before it.
+pub(crate) fn epoch_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+fn page_size(limit: Option<usize>) -> usize {
+    limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+}
+
+/// Split a `<number>:<name>` cursor.
+fn parse_cursor<N: std::str::FromStr>(cursor: &str) -> Result<(N, String), RouterError> {
+    cursor
+        .split_once(':')
+        .and_then(|(n, name)| Some((n.parse().ok()?, name.to_string())))
+        .ok_or_else(|| RouterError::InvalidCursor(cursor.to_string()))
+}
+
+/// Build a page, with a cursor from its last item if more follow.
+fn page<T>(items: Vec<T>, more: bool, cursor: impl Fn(&T) -> String) -> Page<T> {
+    let next_cursor = if more { items.last().map(cursor) } else { None };
+    Page { items, next_cursor }
+}
+
+/// Take the `limit` smallest items whose key is after `after`, in key
+/// order, and whether any more remain.
+fn paginate<T, K: Ord>(
+    items: impl Iterator<Item = T>,
+    key: impl Fn(&T) -> K,
+    after: Option<&K>,
+    limit: usize,
+) -> (Vec<T>, bool) {
+    let mut keyed: Vec<(K, T)> = items
+        .map(|item| (key(&item), item))
+        .filter(|(k, _)| after.map_or(true, |after| k > after))
+        .collect();
+    let more = keyed.len() > limit;
+    if more {
+        keyed.select_nth_unstable_by(limit, |a, b| a.0.cmp(&b.0));
+        keyed.truncate(limit);
+    }
+    keyed.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    (keyed.into_iter().map(|(_, item)| item).collect(), more)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(name: &str, subscribers: usize) -> ChannelStats {
+        ChannelStats {
+            channel: name.into(),
+            subscribers,
+            seq: 0,
+            history: 0,
+        }
+    }
+
+    fn names(page: &Page<ChannelStats>) -> Vec<&str> {
+        page.items.iter().map(|c| &*c.channel).collect()
+    }
+
+    #[test]
+    fn test_channel_pages() {
+        let channels: Vec<_> = (0..25)
+            .map(|i| channel(&format!("room:{i:02}"), i % 5))
+            .collect();
+
+        // Walk every page by name
+        let mut query = ChannelQuery {
+            limit: Some(10),
+            ..ChannelQuery::default()
+        };
+        let mut seen = Vec::new();
+        loop {
+            let page = query.page(channels.clone()).unwrap();
+            seen.extend(names(&page).iter().map(|s| s.to_string()));
+            match page.next_cursor {
+                Some(cursor) => query.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        let mut expected: Vec<_> = channels.iter().map(|c| c.channel.to_string()).collect();
+        expected.sort_unstable();
+        assert_eq!(seen, expected);
+
+        // Busiest first, filtered
+        let query = ChannelQuery {
+            sort: ChannelSort::Subscribers,
+            min_subscribers: Some(3),
+            limit: Some(3),
+            ..ChannelQuery::default()
+        };
+        let page = query.page(channels.clone()).unwrap();
+        assert_eq!(names(&page), ["room:04", "room:09", "room:14"]);
+        assert_eq!(page.next_cursor.as_deref(), Some("4:room:14"));
+        let query = ChannelQuery {
+            cursor: page.next_cursor,
+            ..query
+        };
+        let page = query.page(channels.clone()).unwrap();
+        assert_eq!(names(&page), ["room:19", "room:24", "room:03"]);
+
+        // A name cursor means nothing when sorting by subscribers
+        let query = ChannelQuery {
+            sort: ChannelSort::Subscribers,
+            cursor: Some("room:03".into()),
+            ..ChannelQuery::default()
+        };
+        assert!(matches!(
+            query.page(channels),
+            Err(RouterError::InvalidCursor(_))
+        ));
+    }
+
+    #[test]
+    fn test_subscriber_filters() {
+        let subscriber = |id: &str, transport: &str, connected_at| SubscriberInfo {
+            connection_id: id.into(),
+            user_id: Some(format!("user-{id}")),
+            transport: Some(transport.into()),
+            ip: None,
+            connected_at,
+        };
+        let subscribers = vec![
+            subscriber("c", "websocket", 300),
+            subscriber("a", "socket.io", 100),
+            subscriber("b", "websocket", 200),
+        ];
+
+        let query = SubscriberQuery {
+            sort: SubscriberSort::ConnectedAt,
+            transport: Some("websocket".into()),
+            limit: Some(1),
+            ..SubscriberQuery::default()
+        };
+        let page = query.page(subscribers.clone()).unwrap();
+        assert_eq!(page.items[0].connection_id, "b");
+        assert_eq!(page.next_cursor.as_deref(), Some("200:b"));
+
+        let query = SubscriberQuery {
+            connected_after: Some(200),
+            connected_before: Some(300),
+            ..SubscriberQuery::default()
+        };
+        let page = query.page(subscribers.clone()).unwrap();
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.next_cursor, None);
+
+        let query = SubscriberQuery {
+            user_id: Some("user-a".into()),
+            ..SubscriberQuery::default()
+        };
+        assert_eq!(query.page(subscribers).unwrap().items[0].connection_id, "a");
+    }
+}
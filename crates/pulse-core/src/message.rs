@@ -2,28 +2,107 @@
 //!
 //! These types are used internally for routing and communication.
 
+use crate::channel::ChannelId;
 use bytes::Bytes;
+use pulse_protocol::{PresenceAction, Priority};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 /// A unique message identifier.
+///
+/// IDs are snowflakes: 41 bits of milliseconds since [`ID_EPOCH_MS`], the
+/// 10-bit node ID set with [`set_node_id`], and a 12-bit sequence number.
+/// They are unique across a cluster whose nodes have distinct node IDs, and
+/// ordered by creation time to the millisecond, so sorting by ID sorts
+/// messages from different nodes by when they were published.
 pub type MessageId = u64;
 
-/// Atomic counter for ensuring unique IDs even within the same nanosecond.
-static ID_COUNTER: AtomicU64 = AtomicU64::new(0);
+/// Start of message ID time, 2025-01-01T00:00:00Z in milliseconds since the
+/// Unix epoch.
+pub const ID_EPOCH_MS: u64 = 1_735_689_600_000;
+
+/// Largest node ID.
+pub const MAX_NODE_ID: u16 = (1 << NODE_BITS) - 1;
+
+const NODE_BITS: u32 = 10;
+const SEQUENCE_BITS: u32 = 12;
+
+/// This node's ID, shifted into place.
+static NODE_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Time and sequence of the last ID generated, as `millis << 12 | sequence`.
+static LAST_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Set the node ID embedded in message IDs generated from now on.
+///
+/// Every node of a cluster needs its own ID for message IDs to be unique
+/// across it.
+///
+/// # Panics
+///
+/// Panics if `node_id` exceeds [`MAX_NODE_ID`].
+pub fn set_node_id(node_id: u16) {
+    assert!(
+        node_id <= MAX_NODE_ID,
+        "node ID {node_id} exceeds {MAX_NODE_ID}"
+    );
+    NODE_ID.store(u64::from(node_id) << SEQUENCE_BITS, Ordering::Relaxed);
+}
 
 /// Generate a unique message ID.
+///
+/// IDs from one node strictly increase, even if the clock steps back. A node
+/// generating more than 4096 IDs in a millisecond borrows from the next one.
 #[must_use]
 pub fn generate_message_id() -> MessageId {
-    // Combine timestamp with atomic counter for guaranteed uniqueness
-    let timestamp = SystemTime::now()
+    let now = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
-        .as_nanos() as u64;
-    let counter = ID_COUNTER.fetch_add(1, Ordering::Relaxed);
-    // Use lower bits for counter, upper bits for timestamp
-    timestamp.wrapping_add(counter)
+        .as_millis() as u64;
+    let now = now.saturating_sub(ID_EPOCH_MS) << SEQUENCE_BITS;
+    let mut last = LAST_ID.load(Ordering::Relaxed);
+    let next = loop {
+        let next = now.max(last + 1);
+        match LAST_ID.compare_exchange_weak(last, next, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => break next,
+            Err(actual) => last = actual,
+        }
+    };
+    let millis = next >> SEQUENCE_BITS;
+    let sequence = next & ((1 << SEQUENCE_BITS) - 1);
+    (millis << (NODE_BITS + SEQUENCE_BITS)) | NODE_ID.load(Ordering::Relaxed) | sequence
+}
+
+/// Get the time a message ID was generated, in milliseconds since the Unix
+/// epoch.
+#[must_use]
+pub fn message_id_timestamp(id: MessageId) -> u64 {
+    (id >> (NODE_BITS + SEQUENCE_BITS)) + ID_EPOCH_MS
+}
+
+/// Get the ID of the node that generated a message ID.
+#[must_use]
+pub fn message_id_node(id: MessageId) -> u16 {
+    ((id >> SEQUENCE_BITS) & u64::from(MAX_NODE_ID)) as u16
+}
+
+/// What a routed message carries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    /// An application payload published to the channel.
+    Publish,
+    /// A presence change; the payload is the JSON-encoded presence data.
+    Presence(PresenceAction),
+    /// Marks the end of a history replay to one subscriber; `seq` is the
+    /// channel's sequence number when it subscribed.
+    ReplayEnd {
+        /// Number of messages replayed.
+        replayed: usize,
+        /// Whether messages after the subscriber's cursor had already been
+        /// evicted from history, so the replay has a gap.
+        truncated: bool,
+    },
 }
 
 /// An internal message for routing.
@@ -34,29 +113,78 @@ pub struct Message {
     /// Source connection ID.
     pub source: Option<String>,
     /// Target channel.
-    pub channel: String,
+    pub channel: ChannelId,
     /// Optional event name.
     pub event: Option<String>,
+    /// Message kind.
+    pub kind: MessageKind,
+    /// Delivery priority in congested connection queues.
+    pub priority: Priority,
+    /// Coalescing key: a congested connection queue replaces its queued
+    /// message with the same key and channel instead of growing.
+    pub coalesce_key: Option<String>,
+    /// Group ID shared by messages published together with
+    /// [`Router::publish_group`](crate::Router::publish_group).
+    pub group_id: Option<u64>,
+    /// Whether the payload is end-to-end encrypted, and so opaque to the
+    /// server.
+    pub encrypted: bool,
+    /// ID of the key an encrypted payload was sealed with.
+    pub key_id: Option<String>,
+    /// ID of the signing key the publisher signed the message with.
+    pub signer: Option<String>,
+    /// Hex-encoded HMAC signature, see
+    /// [`MessageSigner`](crate::MessageSigner).
+    pub signature: Option<String>,
+    /// Region the message was first published in, when it was relayed from
+    /// another server (`None` for messages published here).
+    pub origin: Option<String>,
+    /// Whether to report each step in routing and delivering the message to
+    /// the router's [`MessageTracer`](crate::MessageTracer).
+    pub trace: bool,
+    /// Channel sequence number, assigned when published (0 for presence
+    /// changes, which are not sequenced).
+    pub seq: u64,
     /// Message payload (shared for zero-copy broadcast).
     pub payload: Arc<Bytes>,
+    /// For presence updates, the JSON-encoded
+    /// [`PresenceDelta`](crate::PresenceDelta) sent instead of the payload
+    /// to clients that take presence changes as deltas.
+    pub presence_delta: Option<Arc<Bytes>>,
     /// Timestamp when the message was created.
     pub timestamp: u64,
+    /// Monotonic creation time, for measuring delivery latency (`None` on
+    /// messages replayed from history).
+    pub created_at: Option<Instant>,
 }
 
 impl Message {
     /// Create a new message.
     #[must_use]
-    pub fn new(channel: impl Into<String>, payload: impl Into<Bytes>) -> Self {
+    pub fn new(channel: impl Into<ChannelId>, payload: impl Into<Bytes>) -> Self {
         Self {
             id: generate_message_id(),
             source: None,
             channel: channel.into(),
             event: None,
+            kind: MessageKind::Publish,
+            priority: Priority::Normal,
+            coalesce_key: None,
+            group_id: None,
+            encrypted: false,
+            key_id: None,
+            signer: None,
+            signature: None,
+            origin: None,
+            trace: false,
+            seq: 0,
             payload: Arc::new(payload.into()),
+            presence_delta: None,
             timestamp: SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .unwrap()
                 .as_millis() as u64,
+            created_at: Some(Instant::now()),
         }
     }
 
@@ -74,6 +202,70 @@ impl Message {
         self
     }
 
+    /// Create a message with a specific kind.
+    #[must_use]
+    pub fn with_kind(mut self, kind: MessageKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// Create a presence message with a delta of the change, see
+    /// [`Message::presence_delta`].
+    #[must_use]
+    pub fn with_presence_delta(mut self, delta: impl Into<Bytes>) -> Self {
+        self.presence_delta = Some(Arc::new(delta.into()));
+        self
+    }
+
+    /// Create a message with a delivery priority.
+    #[must_use]
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Create a message with a coalescing key, such as `cursor:user123`.
+    #[must_use]
+    pub fn with_coalesce_key(mut self, key: impl Into<String>) -> Self {
+        self.coalesce_key = Some(key.into());
+        self
+    }
+
+    /// Create a message with an end-to-end encrypted payload, sealed with the
+    /// key `key_id` if given.
+    #[must_use]
+    pub fn with_encryption(mut self, key_id: Option<String>) -> Self {
+        self.encrypted = true;
+        self.key_id = key_id;
+        self
+    }
+
+    /// Create a message signed with the signing key `signer`.
+    #[must_use]
+    pub fn with_signature(
+        mut self,
+        signer: impl Into<String>,
+        signature: impl Into<String>,
+    ) -> Self {
+        self.signer = Some(signer.into());
+        self.signature = Some(signature.into());
+        self
+    }
+
+    /// Create a message relayed from the region `origin`.
+    #[must_use]
+    pub fn with_origin(mut self, origin: impl Into<String>) -> Self {
+        self.origin = Some(origin.into());
+        self
+    }
+
+    /// Create a message whose routing and delivery is traced.
+    #[must_use]
+    pub fn with_trace(mut self) -> Self {
+        self.trace = true;
+        self
+    }
+
     /// Get the payload bytes.
     #[must_use]
     pub fn payload(&self) -> &Bytes {
@@ -85,6 +277,24 @@ impl Message {
     pub fn payload_size(&self) -> usize {
         self.payload.len()
     }
+
+    /// Approximate bytes held by the message, for memory accounting.
+    ///
+    /// The channel name is interned and shared, so it is not counted.
+    #[must_use]
+    pub fn size_estimate(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + std::mem::size_of::<Bytes>()
+            + self.payload.len()
+            + self.presence_delta.as_ref().map_or(0, |d| d.len())
+            + self.source.as_ref().map_or(0, String::len)
+            + self.event.as_ref().map_or(0, String::len)
+            + self.coalesce_key.as_ref().map_or(0, String::len)
+            + self.key_id.as_ref().map_or(0, String::len)
+            + self.signer.as_ref().map_or(0, String::len)
+            + self.signature.as_ref().map_or(0, String::len)
+            + self.origin.as_ref().map_or(0, String::len)
+    }
 }
 
 /// A message ready for delivery to a connection.
@@ -114,7 +324,7 @@ mod tests {
     #[test]
     fn test_message_creation() {
         let msg = Message::new("test-channel", b"hello".to_vec());
-        assert_eq!(msg.channel, "test-channel");
+        assert_eq!(&*msg.channel, "test-channel");
         assert_eq!(&msg.payload[..], b"hello");
         assert!(msg.source.is_none());
     }
@@ -133,7 +343,40 @@ mod tests {
     fn test_unique_message_ids() {
         let id1 = generate_message_id();
         let id2 = generate_message_id();
-        // IDs should be different (with high probability)
-        assert_ne!(id1, id2);
+        assert!(id2 > id1);
+
+        // Unique and increasing per thread under contention
+        let threads = (0..4)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    let ids = (0..10_000)
+                        .map(|_| generate_message_id())
+                        .collect::<Vec<_>>();
+                    assert!(ids.windows(2).all(|w| w[0] < w[1]));
+                    ids
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut ids = threads
+            .into_iter()
+            .flat_map(|t| t.join().unwrap())
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), 40_000);
+    }
+
+    #[test]
+    fn test_message_id_layout() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let id = generate_message_id();
+        // Borrowing ahead under load in other tests is bounded by their count
+        assert!(message_id_timestamp(id).abs_diff(now) < 1_000);
+        assert_eq!(message_id_node(id), 0);
+        assert_eq!(message_id_node(MAX_NODE_ID as u64 * 4096), MAX_NODE_ID);
+        assert_eq!(message_id_timestamp(0), ID_EPOCH_MS);
     }
 }
@@ -0,0 +1,120 @@
+//! Publish moderation for Pulse.
+//!
+//! A [`Moderator`] inspects messages before they are published and can veto
+//! them, e.g. to filter profanity or apply spam heuristics. The server asks
+//! it about publishes to the channels it is configured for and returns the
+//! rejection reason to the publisher as an error.
+
+use crate::connection::ConnectionHandle;
+use crate::message::Message;
+use async_trait::async_trait;
+use std::collections::HashSet;
+
+/// Why a moderator rejected a message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    /// Reason shown to the publisher.
+    pub reason: String,
+}
+
+impl Rejection {
+    /// Create a rejection.
+    #[must_use]
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self {
+            reason: reason.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.reason)
+    }
+}
+
+/// Decides whether a message may be published.
+///
+/// Implementations that need no I/O simply return without awaiting. The
+/// publisher's connection waits for the verdict, so slow checks delay its
+/// later frames.
+#[async_trait]
+pub trait Moderator: Send + Sync {
+    /// Check a message a connection is about to publish.
+    async fn moderate(
+        &self,
+        message: &Message,
+        publisher: &ConnectionHandle,
+    ) -> Result<(), Rejection>;
+}
+
+/// Moderator rejecting messages whose payload contains a blocked word.
+///
+/// Words are matched case-insensitively against the payload's alphanumeric
+/// runs, so `spam` blocks `Spam!` but not `spamalot`. End-to-end encrypted
+/// payloads cannot be read and always pass.
+#[derive(Debug, Clone)]
+pub struct WordFilter {
+    words: HashSet<String>,
+}
+
+impl WordFilter {
+    /// Create a filter blocking `words`.
+    #[must_use]
+    pub fn new<I, S>(words: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self {
+            words: words
+                .into_iter()
+                .map(|w| w.as_ref().to_lowercase())
+                .collect(),
+        }
+    }
+
+    /// Check if `text` contains a blocked word.
+    #[must_use]
+    pub fn matches(&self, text: &str) -> bool {
+        text.split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .any(|word| self.words.contains(&word.to_lowercase()))
+    }
+}
+
+#[async_trait]
+impl Moderator for WordFilter {
+    async fn moderate(&self, message: &Message, _: &ConnectionHandle) -> Result<(), Rejection> {
+        if !message.encrypted && self.matches(&String::from_utf8_lossy(&message.payload)) {
+            return Err(Rejection::new("Message contains a blocked word"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::ConnId;
+
+    #[tokio::test]
+    async fn test_word_filter() {
+        let filter = WordFilter::new(["Spam", "scam"]);
+        assert!(filter.matches("buy SPAM now!"));
+        assert!(filter.matches("a scam."));
+        assert!(!filter.matches("spamalot is a musical"));
+
+        let publisher = ConnectionHandle::new(ConnId::new(1), "conn-1");
+        let message = Message::new("chat", br#"{"text":"spam spam"}"#.to_vec());
+        let rejection = filter.moderate(&message, &publisher).await.unwrap_err();
+        assert_eq!(rejection.reason, "Message contains a blocked word");
+
+        let message = Message::new("chat", b"hello".to_vec());
+        assert!(filter.moderate(&message, &publisher).await.is_ok());
+
+        // Ciphertext is not inspected
+        let message = Message::new("chat", b"spam".to_vec()).with_encryption(None);
+        assert!(filter.moderate(&message, &publisher).await.is_ok());
+    }
+}
@@ -0,0 +1,27 @@
+//! Channel occupancy notifications for Pulse.
+//!
+//! A channel is occupied while it has at least one subscriber. The router
+//! tells an [`OccupancyObserver`] when a channel gains its first subscriber
+//! or loses its last, so applications can start and stop work for a channel
+//! (such as polling an upstream feed) only while someone is listening.
+
+use serde::{Deserialize, Serialize};
+
+/// A change in whether a channel has subscribers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OccupancyChange {
+    /// The channel gained its first subscriber.
+    Occupied,
+    /// The channel lost its last subscriber.
+    Vacated,
+}
+
+/// Notified when channels become occupied or vacated.
+///
+/// The router calls the observer while holding the channel's lock, so it
+/// must return quickly, e.g. by sending the change to a task.
+pub trait OccupancyObserver: Send + Sync {
+    /// Handle a channel's occupancy change.
+    fn occupancy_changed(&self, channel: &str, change: OccupancyChange);
+}
@@ -0,0 +1,124 @@
+//! Channel name patterns for Pulse.
+//!
+//! Patterns are simple globs used to apply settings to groups of channels,
+//! e.g. `call:*` matches every channel starting with `call:`.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A glob pattern matched against channel names.
+///
+/// `*` matches any sequence of characters (including none) and `?` matches
+/// exactly one character. All other characters match literally.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub struct ChannelPattern(String);
+
+impl ChannelPattern {
+    /// Create a new pattern.
+    #[must_use]
+    pub fn new(pattern: impl Into<String>) -> Self {
+        Self(pattern.into())
+    }
+
+    /// Get the pattern as a string slice.
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Check if the pattern contains no wildcards.
+    #[must_use]
+    pub fn is_literal(&self) -> bool {
+        !self.0.contains(['*', '?'])
+    }
+
+    /// Check if a channel name matches this pattern.
+    #[must_use]
+    pub fn matches(&self, name: &str) -> bool {
+        let pattern: Vec<char> = self.0.chars().collect();
+        let name: Vec<char> = name.chars().collect();
+        glob_match(&pattern, &name)
+    }
+}
+
+impl fmt::Display for ChannelPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<String> for ChannelPattern {
+    fn from(s: String) -> Self {
+        Self(s)
+    }
+}
+
+impl From<&str> for ChannelPattern {
+    fn from(s: &str) -> Self {
+        Self(s.to_string())
+    }
+}
+
+impl From<ChannelPattern> for String {
+    fn from(p: ChannelPattern) -> String {
+        p.0
+    }
+}
+
+/// Iterative glob matcher with single-star backtracking.
+fn glob_match(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((sp, sn)) => {
+                    p = sp + 1;
+                    n = sn + 1;
+                    star = Some((sp, sn + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_literal_pattern() {
+        let pattern = ChannelPattern::new("chat:lobby");
+        assert!(pattern.is_literal());
+        assert!(pattern.matches("chat:lobby"));
+        assert!(!pattern.matches("chat:lobby2"));
+    }
+
+    #[test]
+    fn test_wildcard_pattern() {
+        let pattern = ChannelPattern::new("call:*");
+        assert!(!pattern.is_literal());
+        assert!(pattern.matches("call:"));
+        assert!(pattern.matches("call:abc:def"));
+        assert!(!pattern.matches("chat:abc"));
+
+        let pattern = ChannelPattern::new("region:*:room-?");
+        assert!(pattern.matches("region:eu:room-1"));
+        assert!(!pattern.matches("region:eu:room-10"));
+
+        assert!(ChannelPattern::new("*").matches("anything"));
+    }
+}
@@ -2,9 +2,20 @@
 //!
 //! Presence allows tracking which users are online in a channel
 //! and sharing metadata about them.
+//!
+//! Each server owns the members connected to it. Members on other servers
+//! are kept per server, replaced wholesale whenever that server sends its
+//! members again, and merged into [`Presence::snapshot`].
+//!
+//! [`Presence`] is safe to share: every method takes `&self`, and members
+//! are sharded so heartbeats from many members of one channel don't
+//! serialize on a single lock.
 
+use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::net::IpAddr;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tracing::debug;
 
@@ -19,6 +30,9 @@ pub struct PresenceState {
     pub joined_at: u64,
     /// Last activity timestamp.
     pub last_seen: u64,
+    /// Client IP address, for server-side use. Not sent to other members.
+    #[serde(skip)]
+    pub remote_ip: Option<IpAddr>,
 }
 
 impl PresenceState {
@@ -35,6 +49,7 @@ impl PresenceState {
             data: None,
             joined_at: now,
             last_seen: now,
+            remote_ip: None,
         }
     }
 
@@ -67,7 +82,77 @@ impl PresenceState {
             .unwrap()
             .as_millis() as u64;
         let timeout_ms = timeout.as_millis() as u64;
-        now - self.last_seen > timeout_ms
+        now.saturating_sub(self.last_seen) > timeout_ms
+    }
+}
+
+/// A change to a member's presence data, broadcast on update instead of the
+/// member's whole state.
+///
+/// `data` is a JSON Merge Patch (RFC 7396) against the member's previous
+/// data: object fields present in it were added or changed, fields set to
+/// `null` were removed, and any other value replaces the data outright.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PresenceDelta {
+    /// Connection ID of the member.
+    pub connection_id: String,
+    /// Merge patch of the member's data.
+    pub data: Value,
+}
+
+impl PresenceDelta {
+    /// Apply the change to a copy of the member's state.
+    pub fn apply(&self, state: &mut PresenceState) {
+        let mut data = state.data.take().unwrap_or(Value::Null);
+        apply_merge_patch(&mut data, &self.data);
+        state.data = (!data.is_null()).then_some(data);
+    }
+}
+
+/// The merge patch that turns `old` into `new`, or `None` if they are equal.
+///
+/// Fields set to `null` in `new` cannot be told apart from removed ones, as
+/// in any merge patch.
+fn merge_patch(old: &Value, new: &Value) -> Option<Value> {
+    if old == new {
+        return None;
+    }
+    let (Value::Object(old), Value::Object(new)) = (old, new) else {
+        return Some(new.clone());
+    };
+    let mut patch = Map::new();
+    for (key, value) in new {
+        let change = match old.get(key) {
+            Some(previous) => merge_patch(previous, value),
+            None => Some(value.clone()),
+        };
+        if let Some(change) = change {
+            patch.insert(key.clone(), change);
+        }
+    }
+    for key in old.keys().filter(|key| !new.contains_key(*key)) {
+        patch.insert(key.clone(), Value::Null);
+    }
+    Some(Value::Object(patch))
+}
+
+/// Apply a merge patch to `target`.
+fn apply_merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    if let Value::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(key);
+            } else {
+                apply_merge_patch(target.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
     }
 }
 
@@ -75,7 +160,9 @@ impl PresenceState {
 #[derive(Debug, Default)]
 pub struct Presence {
     /// Map of connection ID to presence state.
-    members: HashMap<String, PresenceState>,
+    members: DashMap<String, PresenceState>,
+    /// Members connected to other servers, by server.
+    remote: DashMap<String, Vec<PresenceState>>,
 }
 
 impl Presence {
@@ -85,12 +172,43 @@ impl Presence {
         Self::default()
     }
 
-    /// Get the number of present members.
+    /// Get the number of present members on this server.
     #[must_use]
     pub fn count(&self) -> usize {
         self.members.len()
     }
 
+    /// Approximate bytes held by the members, for memory accounting.
+    #[must_use]
+    pub fn size_estimate(&self) -> usize {
+        self.members
+            .iter()
+            .map(|member| {
+                let (id, state) = member.pair();
+                std::mem::size_of::<(String, PresenceState)>()
+                    + id.len()
+                    + state.connection_id.len()
+                    + state.data.as_ref().map_or(0, |d| d.to_string().len())
+            })
+            .sum::<usize>()
+            + self
+                .remote
+                .iter()
+                .map(|server| {
+                    let (node, members) = server.pair();
+                    members
+                        .iter()
+                        .map(|state| {
+                            std::mem::size_of::<PresenceState>()
+                                + node.len()
+                                + state.connection_id.len()
+                                + state.data.as_ref().map_or(0, |d| d.to_string().len())
+                        })
+                        .sum::<usize>()
+                })
+                .sum::<usize>()
+    }
+
     /// Check if a connection is present.
     #[must_use]
     pub fn is_present(&self, connection_id: &str) -> bool {
@@ -99,27 +217,22 @@ impl Presence {
 
     /// Get the presence state for a connection.
     #[must_use]
-    pub fn get(&self, connection_id: &str) -> Option<&PresenceState> {
-        self.members.get(connection_id)
+    pub fn get(&self, connection_id: &str) -> Option<PresenceState> {
+        self.members.get(connection_id).map(|state| state.clone())
     }
 
     /// Add a member to presence.
     ///
     /// Returns `true` if this is a new member, `false` if updating existing.
-    pub fn join(
-        &mut self,
-        connection_id: impl Into<String>,
-        data: Option<serde_json::Value>,
-    ) -> bool {
+    pub fn join(&self, connection_id: impl Into<String>, data: Option<serde_json::Value>) -> bool {
         let conn_id = connection_id.into();
-        let is_new = !self.members.contains_key(&conn_id);
 
         let mut state = PresenceState::new(conn_id.clone());
         if let Some(d) = data {
             state = state.with_data(d);
         }
 
-        self.members.insert(conn_id.clone(), state);
+        let is_new = self.members.insert(conn_id.clone(), state).is_none();
 
         if is_new {
             debug!(connection = %conn_id, "Presence: member joined");
@@ -131,8 +244,8 @@ impl Presence {
     /// Remove a member from presence.
     ///
     /// Returns the removed presence state, if any.
-    pub fn leave(&mut self, connection_id: &str) -> Option<PresenceState> {
-        let state = self.members.remove(connection_id);
+    pub fn leave(&self, connection_id: &str) -> Option<PresenceState> {
+        let state = self.members.remove(connection_id).map(|(_, state)| state);
         if state.is_some() {
             debug!(connection = %connection_id, "Presence: member left");
         }
@@ -141,58 +254,100 @@ impl Presence {
 
     /// Update a member's presence data.
     ///
-    /// Returns `true` if the member exists and was updated.
-    pub fn update(&mut self, connection_id: &str, data: serde_json::Value) -> bool {
-        if let Some(state) = self.members.get_mut(connection_id) {
-            state.update_data(data);
-            true
-        } else {
-            false
+    /// Returns what changed, or `None` if the member does not exist or its
+    /// data is unchanged.
+    pub fn update(&self, connection_id: &str, data: Value) -> Option<PresenceDelta> {
+        let mut state = self.members.get_mut(connection_id)?;
+        let delta = merge_patch(state.data.as_ref().unwrap_or(&Value::Null), &data);
+        state.update_data(data);
+        delta.map(|data| PresenceDelta {
+            connection_id: connection_id.to_string(),
+            data,
+        })
+    }
+
+    /// Record a member's client IP address.
+    pub fn set_remote_ip(&self, connection_id: &str, ip: IpAddr) {
+        if let Some(mut state) = self.members.get_mut(connection_id) {
+            state.remote_ip = Some(ip);
         }
     }
 
     /// Touch a member's last seen timestamp.
-    pub fn touch(&mut self, connection_id: &str) {
-        if let Some(state) = self.members.get_mut(connection_id) {
+    pub fn touch(&self, connection_id: &str) {
+        if let Some(mut state) = self.members.get_mut(connection_id) {
             state.touch();
         }
     }
 
     /// Get all present members.
     #[must_use]
-    pub fn members(&self) -> Vec<&PresenceState> {
-        self.members.values().collect()
+    pub fn members(&self) -> Vec<PresenceState> {
+        self.members.iter().map(|member| member.clone()).collect()
     }
 
     /// Get all connection IDs.
     #[must_use]
-    pub fn connection_ids(&self) -> Vec<&str> {
-        self.members.keys().map(|s| s.as_str()).collect()
+    pub fn connection_ids(&self) -> Vec<String> {
+        self.members
+            .iter()
+            .map(|member| member.key().clone())
+            .collect()
     }
 
     /// Remove stale members (no activity for the given duration).
     ///
-    /// Returns the list of removed connection IDs.
-    pub fn prune_stale(&mut self, timeout: Duration) -> Vec<String> {
-        let stale: Vec<String> = self
-            .members
-            .iter()
-            .filter(|(_, state)| state.is_stale(timeout))
-            .map(|(id, _)| id.clone())
-            .collect();
-
-        for id in &stale {
-            self.members.remove(id);
+    /// Returns the removed members.
+    pub fn prune_stale(&self, timeout: Duration) -> Vec<PresenceState> {
+        let mut stale = Vec::new();
+        self.members.retain(|id, state| {
+            if !state.is_stale(timeout) {
+                return true;
+            }
             debug!(connection = %id, "Presence: pruned stale member");
-        }
-
+            stale.push(state.clone());
+            false
+        });
         stale
     }
 
-    /// Get full presence state as a serializable snapshot.
+    /// Get full presence state as a serializable snapshot, including
+    /// members on other servers.
     #[must_use]
     pub fn snapshot(&self) -> Vec<PresenceState> {
-        self.members.values().cloned().collect()
+        let mut snapshot = self.local_snapshot();
+        let mut seen: HashSet<String> = snapshot.iter().map(|s| s.connection_id.clone()).collect();
+        for server in &self.remote {
+            snapshot.extend(
+                server
+                    .iter()
+                    .filter(|state| seen.insert(state.connection_id.clone()))
+                    .cloned(),
+            );
+        }
+        snapshot
+    }
+
+    /// Get the members on this server, without those on other servers.
+    #[must_use]
+    pub fn local_snapshot(&self) -> Vec<PresenceState> {
+        self.members()
+    }
+
+    /// Replace the members connected to another server.
+    pub fn set_remote(&self, node: &str, members: Vec<PresenceState>) {
+        if members.is_empty() {
+            self.remote.remove(node);
+        } else {
+            self.remote.insert(node.to_string(), members);
+        }
+    }
+
+    /// Forget the members connected to another server.
+    ///
+    /// Returns `true` if any were known.
+    pub fn remove_remote(&self, node: &str) -> bool {
+        self.remote.remove(node).is_some()
     }
 
     /// Check if presence is empty.
@@ -217,7 +372,7 @@ mod tests {
 
     #[test]
     fn test_presence_join_leave() {
-        let mut presence = Presence::new();
+        let presence = Presence::new();
 
         assert!(presence.join("conn-1", None));
         assert!(!presence.join("conn-1", None)); // Already present
@@ -231,23 +386,109 @@ mod tests {
 
     #[test]
     fn test_presence_update() {
-        let mut presence = Presence::new();
+        let presence = Presence::new();
         presence.join("conn-1", None);
 
-        assert!(presence.update("conn-1", json!({"status": "away"})));
-        assert!(!presence.update("conn-2", json!({}))); // Doesn't exist
+        assert!(presence
+            .update("conn-1", json!({"status": "away"}))
+            .is_some());
+        assert!(presence.update("conn-2", json!({})).is_none()); // Doesn't exist
 
         let state = presence.get("conn-1").unwrap();
         assert!(state.data.is_some());
     }
 
+    #[test]
+    fn test_presence_update_delta() {
+        let presence = Presence::new();
+        let before =
+            json!({"name": "Alice", "status": "online", "avatar": {"url": "a.png", "size": 64}});
+        presence.join("conn-1", Some(before.clone()));
+        let mut copy = presence.get("conn-1").unwrap();
+
+        // Only changed fields are sent; removed ones are null
+        let after =
+            json!({"name": "Alice", "avatar": {"url": "b.png", "size": 64}, "typing": true});
+        let delta = presence.update("conn-1", after.clone()).unwrap();
+        assert_eq!(delta.connection_id, "conn-1");
+        assert_eq!(
+            delta.data,
+            json!({"status": null, "avatar": {"url": "b.png"}, "typing": true})
+        );
+        delta.apply(&mut copy);
+        assert_eq!(copy.data, Some(after.clone()));
+
+        // Nothing to send when nothing changed
+        assert!(presence.update("conn-1", after).is_none());
+
+        // Data that isn't an object is replaced whole
+        let delta = presence.update("conn-1", json!("away")).unwrap();
+        assert_eq!(delta.data, json!("away"));
+        delta.apply(&mut copy);
+        assert_eq!(copy.data, Some(json!("away")));
+    }
+
     #[test]
     fn test_presence_snapshot() {
-        let mut presence = Presence::new();
+        let presence = Presence::new();
         presence.join("conn-1", Some(json!({"name": "Alice"})));
         presence.join("conn-2", Some(json!({"name": "Bob"})));
 
         let snapshot = presence.snapshot();
         assert_eq!(snapshot.len(), 2);
     }
+
+    #[test]
+    fn test_presence_concurrent() {
+        let presence = Presence::new();
+        std::thread::scope(|scope| {
+            for t in 0..4 {
+                let presence = &presence;
+                scope.spawn(move || {
+                    for i in 0..250 {
+                        let id = format!("conn-{t}-{i}");
+                        presence.join(id.as_str(), None);
+                        presence.touch(&id);
+                        presence.update(&id, json!({"n": i}));
+                    }
+                });
+            }
+        });
+        assert_eq!(presence.count(), 1000);
+        assert!(presence.prune_stale(Duration::from_secs(60)).is_empty());
+        assert_eq!(
+            presence.get("conn-3-249").unwrap().data,
+            Some(json!({"n": 249}))
+        );
+    }
+
+    #[test]
+    fn test_presence_remote_members() {
+        let presence = Presence::new();
+        presence.join("conn-1", None);
+        presence.set_remote(
+            "eu",
+            vec![PresenceState::new("conn-2"), PresenceState::new("conn-3")],
+        );
+        // A member reported by two servers is listed once
+        presence.set_remote("us", vec![PresenceState::new("conn-3")]);
+        assert_eq!(presence.snapshot().len(), 3);
+        assert_eq!(presence.local_snapshot().len(), 1);
+        assert_eq!(presence.count(), 1);
+
+        // Each server's members are replaced as a whole
+        presence.set_remote("eu", vec![PresenceState::new("conn-4")]);
+        let mut ids: Vec<_> = presence
+            .snapshot()
+            .into_iter()
+            .map(|s| s.connection_id)
+            .collect();
+        ids.sort_unstable();
+        assert_eq!(ids, ["conn-1", "conn-3", "conn-4"]);
+
+        assert!(presence.remove_remote("us"));
+        presence.set_remote("eu", Vec::new());
+        assert!(!presence.remove_remote("eu"));
+        assert_eq!(presence.snapshot().len(), 1);
+    }
 }
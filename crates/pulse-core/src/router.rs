@@ -2,15 +2,37 @@
 //!
 //! The router manages channels and handles pub/sub message routing.
 
-use crate::channel::{validate_channel_name, Channel, ChannelId};
-use crate::message::Message;
-use crate::presence::{Presence, PresenceState};
+use crate::auth::ChannelAuthorizer;
+use crate::channel::{Channel, ChannelId, ChannelKind, SYSTEM_CHANNEL_PREFIX};
+use crate::connection::{
+    CloseReason, ConnId, ConnectionHandle, DropPolicy, QueueStats, DEFAULT_QUEUE_CAPACITY,
+};
+use crate::durable::{Durable, DurableConfig};
+use crate::hot::{HotChannelReport, HotChannels, DEFAULT_HOT_CHANNEL_COUNTERS};
+use crate::listing::{epoch_millis, ChannelQuery, Page, SubscriberInfo, SubscriberQuery};
+use crate::message::{generate_message_id, Message, MessageId, MessageKind};
+use crate::occupancy::{OccupancyChange, OccupancyObserver};
+use crate::pattern::ChannelPattern;
+use crate::presence::{Presence, PresenceDelta, PresenceState};
+use crate::trace::{MessageTracer, TraceEvent};
+use crate::validator::{ChannelNameValidator, DefaultValidator};
+use dashmap::mapref::one::RefMut;
 use dashmap::DashMap;
+use pulse_protocol::{PresenceAction, Priority, SubscribeOptions};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 use tokio::sync::broadcast;
 use tracing::{debug, info, trace, warn};
 
+/// Default for [`RouterConfig::unused_channel_ttl`].
+const DEFAULT_UNUSED_CHANNEL_TTL: Duration = Duration::from_secs(60);
+
 /// Router errors.
 #[derive(Debug, Error)]
 pub enum RouterError {
@@ -34,6 +56,34 @@ pub enum RouterError {
     #[error("Maximum subscriptions reached")]
     MaxSubscriptionsReached,
 
+    /// Maximum number of channels reached.
+    #[error("Maximum channels reached")]
+    MaxChannelsReached,
+
+    /// Channel has reached its subscriber limit.
+    #[error("Channel is full: {0}")]
+    ChannelFull(String),
+
+    /// Missing or invalid auth signature for a channel.
+    #[error("Unauthorized for channel: {0}")]
+    Unauthorized(String),
+
+    /// A durable subscription is attached to another connection.
+    #[error("Durable subscription is in use: {0}")]
+    DurableInUse(String),
+
+    /// A durable subscription was reattached on a different channel.
+    #[error("Durable subscription {0} belongs to another channel")]
+    DurableChannelMismatch(String),
+
+    /// Maximum number of durable subscriptions reached.
+    #[error("Maximum durable subscriptions reached")]
+    MaxDurableSubscriptionsReached,
+
+    /// A listing cursor that no listing in that order produced.
+    #[error("Invalid cursor: {0}")]
+    InvalidCursor(String),
+
     /// Internal error.
     #[error("Internal error: {0}")]
     Internal(String),
@@ -48,10 +98,187 @@ pub struct RouterConfig {
     pub max_subscriptions_per_connection: usize,
     /// Channel broadcast capacity.
     pub channel_capacity: usize,
+    /// Outbound queue capacity of connection handles.
+    pub connection_queue_capacity: usize,
     /// Whether to auto-create channels on subscribe.
+    ///
+    /// When disabled, subscribing to a channel that does not exist fails
+    /// with [`RouterError::ChannelNotFound`].
     pub auto_create_channels: bool,
+    /// Whether publishing to a missing channel creates it.
+    pub create_on_publish: bool,
+    /// How long a channel created by a publish may go without subscribers
+    /// or retained history before [`Router::expire_unused_channels`]
+    /// deletes it.
+    pub unused_channel_ttl: Duration,
     /// Whether to auto-delete empty channels.
     pub auto_delete_empty_channels: bool,
+    /// Per-channel settings, matched by pattern (first match wins).
+    pub channel_rules: Vec<ChannelRule>,
+    /// Approximate bytes router state may hold before
+    /// [`Router::enforce_memory_limit`] evicts history (`None` = unlimited).
+    pub max_memory: Option<usize>,
+    /// Which retained messages are evicted first over `max_memory`.
+    pub memory_eviction: MemoryEviction,
+    /// Limits for durable subscriptions.
+    pub durable: DurableConfig,
+    /// Counters in each sketch shard of the busiest channels (0 = not tracked),
+    /// see [`Router::hot_channels`].
+    pub hot_channel_counters: usize,
+}
+
+/// Which retained messages are evicted when router state is over its
+/// memory limit.
+///
+/// Only channel history is evicted: connection queues are already bounded,
+/// and presence reflects members that are still connected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemoryEviction {
+    /// Drop the oldest retained messages across all channels.
+    #[default]
+    OldestHistory,
+    /// Trim the channel retaining the most bytes, oldest messages first.
+    LargestHistory,
+}
+
+impl RouterConfig {
+    /// Find the first rule whose pattern matches a channel name.
+    #[must_use]
+    pub fn rule_for(&self, channel_name: &str) -> Option<&ChannelRule> {
+        self.channel_rules
+            .iter()
+            .find(|rule| rule.pattern.matches(channel_name))
+    }
+}
+
+/// Settings applied to channels matching a pattern.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelRule {
+    /// Channel name pattern this rule applies to.
+    pub pattern: ChannelPattern,
+    /// Maximum number of subscribers per channel.
+    #[serde(default)]
+    pub max_subscribers: Option<usize>,
+    /// What to do when a subscriber's outbound queue is full.
+    #[serde(default)]
+    pub drop_policy: DropPolicy,
+    /// Number of recent messages retained for replay on subscribe.
+    #[serde(default)]
+    pub history_size: usize,
+    /// Largest payload that may be published to a matching channel, in
+    /// bytes. Only limits below the server's global limit have an effect.
+    #[serde(default)]
+    pub max_payload_size: Option<usize>,
+    /// How long a presence member of a matching channel stays listed
+    /// without refreshing its presence, in milliseconds. Clients refresh
+    /// with every heartbeat; members that stop are removed with a Leave but
+    /// stay subscribed.
+    #[serde(default)]
+    pub presence_ttl_ms: Option<u64>,
+}
+
+impl ChannelRule {
+    /// Create a rule with no settings for the given pattern.
+    #[must_use]
+    pub fn new(pattern: impl Into<ChannelPattern>) -> Self {
+        Self {
+            pattern: pattern.into(),
+            max_subscribers: None,
+            drop_policy: DropPolicy::default(),
+            history_size: 0,
+            max_payload_size: None,
+            presence_ttl_ms: None,
+        }
+    }
+
+    /// Limit the number of subscribers per matching channel.
+    #[must_use]
+    pub fn with_max_subscribers(mut self, max: usize) -> Self {
+        self.max_subscribers = Some(max);
+        self
+    }
+
+    /// Set the policy for full subscriber queues on matching channels.
+    #[must_use]
+    pub fn with_drop_policy(mut self, drop_policy: DropPolicy) -> Self {
+        self.drop_policy = drop_policy;
+        self
+    }
+
+    /// Retain recent messages on matching channels for replay.
+    #[must_use]
+    pub fn with_history_size(mut self, history_size: usize) -> Self {
+        self.history_size = history_size;
+        self
+    }
+
+    /// Limit the payload size of messages published to matching channels.
+    #[must_use]
+    pub fn with_max_payload_size(mut self, max: usize) -> Self {
+        self.max_payload_size = Some(max);
+        self
+    }
+
+    /// Expire presence members of matching channels that stop refreshing.
+    #[must_use]
+    pub fn with_presence_ttl(mut self, ttl: Duration) -> Self {
+        self.presence_ttl_ms = Some(u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX));
+        self
+    }
+}
+
+/// Channel state reported for a new subscription.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubscriptionInfo {
+    /// Sequence number of the channel's latest message (0 if none).
+    pub seq: u64,
+    /// Number of subscribers, including the new one.
+    pub subscribers: usize,
+    /// Number of presence members, including the new one on presence channels.
+    pub presence: usize,
+    /// Number of retained messages available for replay.
+    pub history: usize,
+    /// Number of retained messages replayed to the subscriber.
+    pub replayed: usize,
+}
+
+/// The outcome of a publish, as returned by [`Router::publish_with_receipt`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublishReceipt {
+    /// ID assigned to the message.
+    pub id: MessageId,
+    /// Channel sequence number given to the message (0 if the channel does
+    /// not exist).
+    pub seq: u64,
+    /// Number of subscribers the message was queued for.
+    pub recipients: usize,
+}
+
+/// The outcome of [`Router::publish_group`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupPublishReceipt {
+    /// Group ID stamped on every message.
+    pub group_id: u64,
+    /// One receipt per message, in the order given.
+    pub receipts: Vec<PublishReceipt>,
+}
+
+impl GroupPublishReceipt {
+    /// Get the number of deliveries across all messages.
+    #[must_use]
+    pub fn recipients(&self) -> usize {
+        self.receipts.iter().map(|r| r.recipients).sum()
+    }
+}
+
+/// The outcome of [`Router::publish_pattern`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PatternPublishReceipt {
+    /// Number of channels the message was published to.
+    pub channels: usize,
+    /// Number of subscribers the message was queued for, across channels.
+    pub recipients: usize,
 }
 
 impl Default for RouterConfig {
@@ -60,8 +287,16 @@ impl Default for RouterConfig {
             max_channels: 10_000,
             max_subscriptions_per_connection: 100,
             channel_capacity: 1024,
+            connection_queue_capacity: DEFAULT_QUEUE_CAPACITY,
             auto_create_channels: true,
+            create_on_publish: false,
+            unused_channel_ttl: DEFAULT_UNUSED_CHANNEL_TTL,
             auto_delete_empty_channels: true,
+            channel_rules: Vec::new(),
+            max_memory: None,
+            memory_eviction: MemoryEviction::default(),
+            durable: DurableConfig::default(),
+            hot_channel_counters: DEFAULT_HOT_CHANNEL_COUNTERS,
         }
     }
 }
@@ -69,15 +304,63 @@ impl Default for RouterConfig {
 /// Channel entry with presence tracking.
 struct ChannelEntry {
     channel: Channel,
+    /// Concurrent on its own, so presence changes only need a shared
+    /// reference to the entry and don't hold up publishing.
     presence: Presence,
+    /// How long presence members stay listed without refreshing.
+    presence_ttl: Option<Duration>,
+    /// When a publish created the channel, so it can be deleted if nobody
+    /// ever uses it.
+    created_on_publish: Option<Instant>,
 }
 
 impl ChannelEntry {
-    fn new(name: impl Into<ChannelId>, capacity: usize) -> Self {
+    fn new(name: &str, config: &RouterConfig) -> Self {
+        let rule = config.rule_for(name);
         Self {
-            channel: Channel::with_capacity(name, capacity),
+            channel: Channel::with_capacity(name, config.channel_capacity)
+                .with_max_subscribers(rule.and_then(|r| r.max_subscribers))
+                .with_drop_policy(rule.map(|r| r.drop_policy).unwrap_or_default())
+                .with_history_size(rule.map_or(0, |r| r.history_size)),
             presence: Presence::new(),
+            presence_ttl: rule
+                .and_then(|r| r.presence_ttl_ms)
+                .map(Duration::from_millis),
+            created_on_publish: None,
+        }
+    }
+
+    /// Broadcast a presence change to the channel's subscribers.
+    fn publish_presence(&self, action: PresenceAction, data: &impl Serialize) -> usize {
+        let payload = serde_json::to_vec(data).unwrap_or_default();
+        let message = Message::new(self.channel.id().clone(), payload)
+            .with_kind(MessageKind::Presence(action))
+            .with_priority(Priority::High);
+        self.channel.publish(message)
+    }
+
+    /// Broadcast a member's changed data, with the member's new state for
+    /// clients that don't take deltas.
+    fn publish_presence_update(&self, state: &PresenceState, delta: &PresenceDelta) -> usize {
+        let payload = serde_json::to_vec(state).unwrap_or_default();
+        let message = Message::new(self.channel.id().clone(), payload)
+            .with_kind(MessageKind::Presence(PresenceAction::Update))
+            .with_priority(Priority::High)
+            .with_presence_delta(serde_json::to_vec(delta).unwrap_or_default());
+        self.channel.publish(message)
+    }
+
+    /// Remove a connection from the channel and its presence set.
+    ///
+    /// Returns `true` if this left the channel without subscribers.
+    fn remove(&mut self, conn: ConnId, connection_id: &str) -> bool {
+        let removed = self.channel.unsubscribe(conn);
+        if let Some(state) = self.presence.leave(connection_id) {
+            if !self.channel.is_empty() {
+                self.publish_presence(PresenceAction::Leave, &state);
+            }
         }
+        removed && self.channel.is_empty()
     }
 }
 
@@ -88,10 +371,36 @@ impl ChannelEntry {
 pub struct Router {
     /// Channels indexed by name.
     channels: DashMap<ChannelId, ChannelEntry>,
-    /// Connection subscriptions (connection_id -> set of channel names).
-    subscriptions: DashMap<String, dashmap::DashSet<ChannelId>>,
+    /// Connection subscriptions (connection -> set of channel names).
+    subscriptions: DashMap<ConnId, dashmap::DashSet<ChannelId>>,
+    /// Internal IDs of connections, by external connection ID.
+    conn_ids: DashMap<String, ConnId>,
+    /// External connection IDs, by internal ID.
+    conn_names: DashMap<ConnId, Arc<str>>,
+    /// Handles of connected clients.
+    handles: DashMap<ConnId, Arc<ConnectionHandle>>,
+    /// Overflow counters shared by the handles.
+    queue_stats: Arc<QueueStats>,
+    /// Pattern subscriptions (connection -> channel patterns).
+    pattern_subscriptions: DashMap<ConnId, Vec<ChannelPattern>>,
+    /// Next internal connection ID.
+    next_conn_id: AtomicU64,
     /// Configuration.
     config: RouterConfig,
+    /// Authorizer for channels that require auth.
+    authorizer: Option<Arc<dyn ChannelAuthorizer>>,
+    /// Channel name validator.
+    validator: Arc<dyn ChannelNameValidator>,
+    /// Notified when channels become occupied or vacated.
+    occupancy: Option<Arc<dyn OccupancyObserver>>,
+    /// Told how traced messages are routed.
+    tracer: Option<Arc<dyn MessageTracer>>,
+    /// Durable subscriptions by name.
+    durables: DashMap<String, Durable>,
+    /// Names of the durable subscriptions each connection is attached to.
+    durable_consumers: DashMap<ConnId, Vec<String>>,
+    /// Message and byte rates of the busiest channels.
+    hot: HotChannels,
 }
 
 impl Router {
@@ -108,10 +417,95 @@ impl Router {
         Self {
             channels: DashMap::new(),
             subscriptions: DashMap::new(),
+            conn_ids: DashMap::new(),
+            conn_names: DashMap::new(),
+            handles: DashMap::new(),
+            queue_stats: Arc::default(),
+            pattern_subscriptions: DashMap::new(),
+            next_conn_id: AtomicU64::new(1),
+            authorizer: None,
+            validator: Arc::new(DefaultValidator),
+            occupancy: None,
+            tracer: None,
+            durables: DashMap::new(),
+            durable_consumers: DashMap::new(),
+            hot: HotChannels::new(config.hot_channel_counters),
             config,
         }
     }
 
+    /// Set the validator used to check channel names.
+    ///
+    /// Replaces the built-in rules; chain with
+    /// [`DefaultValidator`](crate::validator::DefaultValidator) to extend them.
+    #[must_use]
+    pub fn with_validator(mut self, validator: Arc<dyn ChannelNameValidator>) -> Self {
+        self.validator = validator;
+        self
+    }
+
+    /// Set the authorizer used for private channels.
+    ///
+    /// Without an authorizer, subscriptions to private channels are rejected.
+    #[must_use]
+    pub fn with_authorizer(mut self, authorizer: Arc<dyn ChannelAuthorizer>) -> Self {
+        self.authorizer = Some(authorizer);
+        self
+    }
+
+    /// Set the observer told when channels gain their first subscriber or
+    /// lose their last.
+    #[must_use]
+    pub fn with_occupancy_observer(mut self, observer: Arc<dyn OccupancyObserver>) -> Self {
+        self.occupancy = Some(observer);
+        self
+    }
+
+    /// Set the tracer told how messages with
+    /// [`Message::trace`](crate::Message::trace) set are routed.
+    #[must_use]
+    pub fn with_tracer(mut self, tracer: Arc<dyn MessageTracer>) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    fn notify_occupancy(&self, channel_name: &str, change: OccupancyChange) {
+        if let Some(observer) = &self.occupancy {
+            observer.occupancy_changed(channel_name, change);
+        }
+    }
+
+    /// Normalize a channel name with the configured validator.
+    fn channel_key<'a>(&self, channel_name: &'a str) -> Cow<'a, str> {
+        self.validator.normalize(channel_name)
+    }
+
+    /// Get the internal ID of a connection, assigning one if needed.
+    fn intern_connection(&self, connection_id: &str) -> ConnId {
+        if let Some(conn) = self.conn_ids.get(connection_id) {
+            return *conn;
+        }
+        *self
+            .conn_ids
+            .entry(connection_id.to_string())
+            .or_insert_with(|| {
+                let conn = ConnId::new(self.next_conn_id.fetch_add(1, Ordering::Relaxed));
+                self.conn_names.insert(conn, connection_id.into());
+                conn
+            })
+    }
+
+    /// Get the internal ID of a known connection.
+    fn lookup_connection(&self, connection_id: &str) -> Option<ConnId> {
+        self.conn_ids.get(connection_id).map(|conn| *conn)
+    }
+
+    /// Get the external ID of a connection.
+    #[must_use]
+    pub fn connection_name(&self, conn: ConnId) -> Option<Arc<str>> {
+        self.conn_names.get(&conn).map(|name| name.clone())
+    }
+
     /// Get router statistics.
     #[must_use]
     pub fn stats(&self) -> RouterStats {
@@ -134,14 +528,145 @@ impl Router {
         connection_id: &str,
         channel_name: &str,
     ) -> Result<broadcast::Receiver<Arc<Message>>, RouterError> {
+        self.subscribe_with_auth(connection_id, channel_name, None)
+    }
+
+    /// Subscribe a connection to a channel, presenting an auth signature.
+    ///
+    /// The signature is only checked for channels that require auth. On
+    /// presence channels the connection also joins the presence set and the
+    /// join is broadcast to the channel.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the channel name is invalid, the signature is
+    /// rejected, or limits are exceeded.
+    pub fn subscribe_with_auth(
+        &self,
+        connection_id: &str,
+        channel_name: &str,
+        auth: Option<&str>,
+    ) -> Result<broadcast::Receiver<Arc<Message>>, RouterError> {
+        let conn = self.intern_connection(connection_id);
+        self.subscribe_inner(
+            conn,
+            connection_id,
+            channel_name,
+            Access::Checked(auth),
+            |channel| channel.subscribe(conn),
+        )
+        .map(|(receiver, _)| receiver)
+    }
+
+    /// Subscribe a connection handle to a channel.
+    ///
+    /// Messages on the channel are pushed into the handle's outbound queue.
+    /// Auth and presence behave as in [`subscribe_with_auth`](Self::subscribe_with_auth).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the channel name is invalid, the signature is
+    /// rejected, or limits are exceeded.
+    pub fn subscribe_handle(
+        &self,
+        handle: &Arc<ConnectionHandle>,
+        channel_name: &str,
+        auth: Option<&str>,
+    ) -> Result<SubscriptionInfo, RouterError> {
+        self.subscribe_handle_with(handle, channel_name, auth, SubscribeOptions::default())
+    }
+
+    /// Subscribe a connection handle to a channel with delivery options.
+    ///
+    /// Requested history is replayed into the handle's queue before any
+    /// message published after the subscribe.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the channel name is invalid, the signature is
+    /// rejected, or limits are exceeded.
+    pub fn subscribe_handle_with(
+        &self,
+        handle: &Arc<ConnectionHandle>,
+        channel_name: &str,
+        auth: Option<&str>,
+        options: SubscribeOptions,
+    ) -> Result<SubscriptionInfo, RouterError> {
+        self.subscribe_inner(
+            handle.conn_id(),
+            handle.id(),
+            channel_name,
+            Access::Checked(auth),
+            |channel| channel.subscribe_handle_with(handle.clone(), options),
+        )
+        .map(|(replayed, info)| SubscriptionInfo { replayed, ..info })
+    }
+
+    /// Subscribe a connection handle to a reserved system channel, such as
+    /// `$system:stats`.
+    ///
+    /// System channels skip the naming policy and channel auth; the caller
+    /// decides who may read them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the name lacks the
+    /// [`SYSTEM_CHANNEL_PREFIX`](crate::channel::SYSTEM_CHANNEL_PREFIX), or
+    /// limits are exceeded.
+    pub fn subscribe_system(
+        &self,
+        handle: &Arc<ConnectionHandle>,
+        channel_name: &str,
+        options: SubscribeOptions,
+    ) -> Result<SubscriptionInfo, RouterError> {
+        if !channel_name.starts_with(SYSTEM_CHANNEL_PREFIX) {
+            return Err(RouterError::InvalidChannel("Not a system channel"));
+        }
+        self.subscribe_inner(
+            handle.conn_id(),
+            handle.id(),
+            channel_name,
+            Access::System,
+            |channel| channel.subscribe_handle_with(handle.clone(), options),
+        )
+        .map(|(replayed, info)| SubscriptionInfo { replayed, ..info })
+    }
+
+    fn subscribe_inner<T>(
+        &self,
+        conn: ConnId,
+        connection_id: &str,
+        channel_name: &str,
+        access: Access<'_>,
+        attach: impl FnOnce(&mut Channel) -> T,
+    ) -> Result<(T, SubscriptionInfo), RouterError> {
+        let key = self.channel_key(channel_name);
+        let channel_name = key.as_ref();
+
         // Validate channel name
-        validate_channel_name(channel_name).map_err(RouterError::InvalidChannel)?;
+        let auth = match access {
+            Access::Checked(auth) => {
+                self.validator
+                    .validate(channel_name)
+                    .map_err(RouterError::InvalidChannel)?;
+                Some(auth)
+            }
+            Access::Authorized => {
+                self.validator
+                    .validate(channel_name)
+                    .map_err(RouterError::InvalidChannel)?;
+                None
+            }
+            Access::System => Some(None),
+        };
+
+        let kind = ChannelKind::from_name(channel_name);
+        if let Some(auth) = auth {
+            self.authorize(conn, connection_id, channel_name, auth)?;
+        }
 
         // Check subscription limits
-        let conn_subs = self
-            .subscriptions
-            .entry(connection_id.to_string())
-            .or_default();
+        let conn_subs = self.subscriptions.entry(conn).or_default();
 
         if conn_subs.len() >= self.config.max_subscriptions_per_connection {
             return Err(RouterError::MaxSubscriptionsReached);
@@ -151,18 +676,28 @@ impl Router {
             return Err(RouterError::AlreadySubscribed(channel_name.to_string()));
         }
 
-        // Get or create channel
-        let mut entry = self
-            .channels
-            .entry(channel_name.to_string())
-            .or_insert_with(|| {
-                debug!(channel = %channel_name, "Creating new channel");
-                ChannelEntry::new(channel_name, self.config.channel_capacity)
-            });
+        let mut entry = self.channel_entry(channel_name, self.config.auto_create_channels)?;
+
+        if entry.channel.is_full() {
+            return Err(RouterError::ChannelFull(channel_name.to_string()));
+        }
 
         // Subscribe
-        let receiver = entry.channel.subscribe(connection_id);
-        conn_subs.insert(channel_name.to_string());
+        let subscription = attach(&mut entry.channel);
+        conn_subs.insert(entry.channel.id().clone());
+        if entry.channel.subscriber_count() == 1 {
+            self.notify_occupancy(channel_name, OccupancyChange::Occupied);
+        }
+
+        if kind.tracks_presence() {
+            entry.presence.join(connection_id, None);
+            if let Some(ip) = self.remote_ip(conn) {
+                entry.presence.set_remote_ip(connection_id, ip);
+            }
+            if let Some(state) = entry.presence.get(connection_id) {
+                entry.publish_presence(PresenceAction::Join, &state);
+            }
+        }
 
         debug!(
             channel = %channel_name,
@@ -171,7 +706,355 @@ impl Router {
             "Subscribed"
         );
 
-        Ok(receiver)
+        let info = SubscriptionInfo {
+            seq: entry.channel.seq(),
+            subscribers: entry.channel.subscriber_count(),
+            presence: entry.presence.count(),
+            history: entry.channel.history_len(),
+            replayed: 0,
+        };
+        Ok((subscription, info))
+    }
+
+    /// Check a connection may subscribe to a channel that requires auth.
+    fn authorize(
+        &self,
+        conn: ConnId,
+        connection_id: &str,
+        channel_name: &str,
+        auth: Option<&str>,
+    ) -> Result<(), RouterError> {
+        if !ChannelKind::from_name(channel_name).requires_auth() {
+            return Ok(());
+        }
+        let metadata = self
+            .handles
+            .get(&conn)
+            .and_then(|h| h.metadata().cloned())
+            .unwrap_or_default();
+        let authorized = self.authorizer.as_ref().is_some_and(|a| {
+            a.authorize_with_metadata(connection_id, channel_name, auth, &metadata)
+        });
+        if authorized {
+            Ok(())
+        } else {
+            Err(RouterError::Unauthorized(channel_name.to_string()))
+        }
+    }
+
+    /// Attach a connection handle to a durable subscription, creating it on
+    /// first use.
+    ///
+    /// A new subscription subscribes to the channel with `options`, which
+    /// may ask for a replay. An existing one must be for the same channel
+    /// and have no other live consumer; the messages it buffered are queued
+    /// on the handle before any newer message, and its original options
+    /// stay in effect. `replayed` in the result counts the messages queued
+    /// from history and the buffer.
+    ///
+    /// When the consumer unsubscribes from the channel the subscription is
+    /// deleted. When it disconnects, the subscription buffers messages
+    /// until it is reattached or expires (see [`Router::expire_durables`]).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the channel name is invalid or a presence
+    /// channel, the signature is rejected, the subscription is in use or
+    /// for another channel, or limits are exceeded.
+    pub fn subscribe_durable(
+        &self,
+        handle: &Arc<ConnectionHandle>,
+        name: &str,
+        channel_name: &str,
+        auth: Option<&str>,
+        options: SubscribeOptions,
+    ) -> Result<SubscriptionInfo, RouterError> {
+        let key = self.channel_key(channel_name);
+        let channel_name = key.as_ref();
+        self.validator
+            .validate(channel_name)
+            .map_err(RouterError::InvalidChannel)?;
+        if ChannelKind::from_name(channel_name).tracks_presence() {
+            return Err(RouterError::InvalidChannel(
+                "Presence channels do not support durable subscriptions",
+            ));
+        }
+        let conn = handle.conn_id();
+        self.authorize(conn, handle.id(), channel_name, auth)?;
+
+        {
+            let conn_subs = self.subscriptions.entry(conn).or_default();
+            if conn_subs.len() >= self.config.max_subscriptions_per_connection {
+                return Err(RouterError::MaxSubscriptionsReached);
+            }
+            if conn_subs.contains(channel_name) {
+                return Err(RouterError::AlreadySubscribed(channel_name.to_string()));
+            }
+        }
+        let full = !self.durables.contains_key(name)
+            && self.durables.len() >= self.config.durable.max_subscriptions;
+        if full {
+            return Err(RouterError::MaxDurableSubscriptionsReached);
+        }
+
+        let mut durable = match self.durables.entry(name.to_string()) {
+            dashmap::mapref::entry::Entry::Occupied(entry) => {
+                let durable = entry.into_ref();
+                if durable.channel.as_ref() != channel_name {
+                    return Err(RouterError::DurableChannelMismatch(name.to_string()));
+                }
+                if durable
+                    .consumer
+                    .is_some_and(|c| c != conn && self.handles.contains_key(&c))
+                {
+                    return Err(RouterError::DurableInUse(name.to_string()));
+                }
+                durable
+            }
+            dashmap::mapref::entry::Entry::Vacant(entry) => {
+                let buffer = self.connect_with_capacity(
+                    &format!("durable:{name}"),
+                    self.config.durable.capacity,
+                );
+                let subscribed = self.subscribe_inner(
+                    buffer.conn_id(),
+                    buffer.id(),
+                    channel_name,
+                    Access::Authorized,
+                    |channel| channel.subscribe_handle_with(buffer.clone(), options),
+                );
+                if let Err(e) = subscribed {
+                    self.disconnect(&buffer);
+                    return Err(e);
+                }
+                debug!(durable = %name, channel = %channel_name, "Created durable subscription");
+                entry.insert(Durable {
+                    channel: ChannelId::from(channel_name),
+                    buffer,
+                    consumer: None,
+                    detached_at: None,
+                })
+            }
+        };
+
+        // Hand the buffered messages over under the channel lock, so
+        // nothing published meanwhile overtakes them
+        let mut entry = self
+            .channels
+            .get_mut(channel_name)
+            .ok_or_else(|| RouterError::ChannelNotFound(channel_name.to_string()))?;
+        let buffer = &durable.buffer;
+        let pending: Vec<_> = std::iter::from_fn(|| buffer.try_recv()).collect();
+        let replayed = entry
+            .channel
+            .redirect(buffer.conn_id(), handle.clone(), pending);
+        durable.consumer = Some(conn);
+        durable.detached_at = None;
+        let channel_id = entry.channel.id().clone();
+        let info = SubscriptionInfo {
+            seq: entry.channel.seq(),
+            subscribers: entry.channel.subscriber_count(),
+            presence: entry.presence.count(),
+            history: entry.channel.history_len(),
+            replayed,
+        };
+        drop(entry);
+        drop(durable);
+
+        if let Some(conn_subs) = self.subscriptions.get(&conn) {
+            conn_subs.insert(channel_id);
+        }
+        self.durable_consumers
+            .entry(conn)
+            .or_default()
+            .push(name.to_string());
+        debug!(durable = %name, connection = %handle.id(), replayed, "Attached durable subscription");
+        Ok(info)
+    }
+
+    /// Return a disconnecting consumer's durable subscriptions to their
+    /// buffers, along with the messages still queued for it.
+    fn detach_durables(&self, conn: ConnId, names: &[String], consumer: &ConnectionHandle) {
+        let mut unsent = Vec::new();
+        for name in names {
+            let Some(mut durable) = self.durables.get_mut(name) else {
+                continue;
+            };
+            if durable.consumer != Some(conn) {
+                continue;
+            }
+            if let Some(mut entry) = self.channels.get_mut(durable.channel.as_ref()) {
+                unsent.extend(std::iter::from_fn(|| consumer.try_recv()));
+                let (pending, rest): (Vec<_>, Vec<_>) = unsent
+                    .into_iter()
+                    .partition(|m| m.channel == durable.channel);
+                unsent = rest;
+                entry
+                    .channel
+                    .redirect(durable.buffer.conn_id(), durable.buffer.clone(), pending);
+            }
+            durable.consumer = None;
+            durable.detached_at = Some(Instant::now());
+            debug!(durable = %name, "Detached durable subscription");
+        }
+    }
+
+    /// Stop tracking the durable subscription a connection consumes on a
+    /// channel, returning its name.
+    fn take_durable(&self, conn: ConnId, channel_name: &str) -> Option<String> {
+        let names = self.durable_consumers.get(&conn)?.clone();
+        let name = names.into_iter().find(|name| {
+            self.durables
+                .get(name)
+                .is_some_and(|d| d.channel.as_ref() == channel_name)
+        })?;
+        if let Some(mut names) = self.durable_consumers.get_mut(&conn) {
+            names.retain(|n| *n != name);
+        }
+        Some(name)
+    }
+
+    /// Delete a durable subscription.
+    fn remove_durable(&self, name: &str) {
+        if let Some((_, durable)) = self.durables.remove(name) {
+            self.disconnect(&durable.buffer);
+            debug!(durable = %name, "Removed durable subscription");
+        }
+    }
+
+    /// Delete durable subscriptions that have been without a consumer for
+    /// longer than the configured TTL, or whose buffer was closed by its
+    /// drop policy. Returns the number deleted.
+    pub fn expire_durables(&self) -> usize {
+        let now = Instant::now();
+        let expired: Vec<String> = self
+            .durables
+            .iter()
+            .filter(|d| d.is_expired(self.config.durable.ttl, now))
+            .map(|d| d.key().clone())
+            .collect();
+        for name in &expired {
+            self.remove_durable(name);
+        }
+        if !expired.is_empty() {
+            info!(count = expired.len(), "Expired durable subscriptions");
+        }
+        expired.len()
+    }
+
+    /// Delete channels created by a publish that have gone without
+    /// subscribers and retained history for longer than
+    /// [`unused_channel_ttl`](RouterConfig::unused_channel_ttl). Returns the
+    /// number deleted.
+    ///
+    /// Without this, publishing to made-up names would grow the channel map
+    /// until `max_channels`, since only an unsubscribe deletes empty channels.
+    pub fn expire_unused_channels(&self) -> usize {
+        let now = Instant::now();
+        let ttl = self.config.unused_channel_ttl;
+        let unused = |entry: &ChannelEntry| {
+            entry
+                .created_on_publish
+                .is_some_and(|at| now.duration_since(at) >= ttl)
+                && entry.channel.is_empty()
+                && entry.channel.history_len() == 0
+        };
+        let candidates: Vec<ChannelId> = self
+            .channels
+            .iter()
+            .filter(|e| unused(e.value()))
+            .map(|e| e.key().clone())
+            .collect();
+        // Checked again under the map's lock, as in `remove_if_empty`
+        let expired = candidates
+            .iter()
+            .filter(|name| {
+                self.channels
+                    .remove_if(name.as_ref(), |_, entry| unused(entry))
+                    .is_some()
+            })
+            .count();
+        if expired > 0 {
+            info!(
+                count = expired,
+                "Deleted unused channels created on publish"
+            );
+        }
+        expired
+    }
+
+    /// Get the number of durable subscriptions.
+    #[must_use]
+    pub fn durable_count(&self) -> usize {
+        self.durables.len()
+    }
+
+    /// Get a channel for writing, creating it if `create` is set.
+    ///
+    /// Reuses the interned name if the channel exists. `channel_name` must
+    /// already be normalized and validated.
+    fn channel_entry(
+        &self,
+        channel_name: &str,
+        create: bool,
+    ) -> Result<RefMut<'_, ChannelId, ChannelEntry>, RouterError> {
+        if let Some(entry) = self.channels.get_mut(channel_name) {
+            return Ok(entry);
+        }
+        if !create {
+            return Err(RouterError::ChannelNotFound(channel_name.to_string()));
+        }
+        if self.channels.len() >= self.config.max_channels {
+            warn!(channel = %channel_name, "Channel limit reached");
+            return Err(RouterError::MaxChannelsReached);
+        }
+        Ok(self
+            .channels
+            .entry(ChannelId::from(channel_name))
+            .or_insert_with(|| {
+                debug!(channel = %channel_name, "Creating new channel");
+                ChannelEntry::new(channel_name, &self.config)
+            }))
+    }
+
+    /// Create a channel ahead of any subscriber.
+    ///
+    /// This is how channels come into existence when
+    /// [`auto_create_channels`](RouterConfig::auto_create_channels) is off.
+    /// Returns `true` if the channel was created, `false` if it already existed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the channel name is invalid or the channel limit
+    /// is reached.
+    pub fn create_channel(&self, channel_name: &str) -> Result<bool, RouterError> {
+        let key = self.channel_key(channel_name);
+        let channel_name = key.as_ref();
+        self.validator
+            .validate(channel_name)
+            .map_err(RouterError::InvalidChannel)?;
+
+        if self.channels.contains_key(channel_name) {
+            return Ok(false);
+        }
+        self.channel_entry(channel_name, true)?;
+        Ok(true)
+    }
+
+    /// Create a missing channel for a publish, marking it for
+    /// [`expire_unused_channels`](Self::expire_unused_channels).
+    fn create_for_publish(&self, channel_name: &str) {
+        match self.create_channel(channel_name) {
+            Ok(true) => {
+                if let Some(mut entry) = self.channels.get_mut(channel_name) {
+                    entry.created_on_publish = Some(Instant::now());
+                }
+            }
+            Ok(false) => {}
+            Err(e) => {
+                warn!(channel = %channel_name, error = %e, "Failed to create channel on publish");
+            }
+        }
     }
 
     /// Unsubscribe a connection from a channel.
@@ -180,8 +1063,14 @@ impl Router {
     ///
     /// Returns an error if not subscribed.
     pub fn unsubscribe(&self, connection_id: &str, channel_name: &str) -> Result<(), RouterError> {
+        let key = self.channel_key(channel_name);
+        let channel_name = key.as_ref();
+
         // Remove from connection's subscriptions
-        if let Some(conn_subs) = self.subscriptions.get(connection_id) {
+        let conn = self
+            .lookup_connection(connection_id)
+            .ok_or_else(|| RouterError::NotSubscribed(channel_name.to_string()))?;
+        if let Some(conn_subs) = self.subscriptions.get(&conn) {
             if conn_subs.remove(channel_name).is_none() {
                 return Err(RouterError::NotSubscribed(channel_name.to_string()));
             }
@@ -189,10 +1078,17 @@ impl Router {
             return Err(RouterError::NotSubscribed(channel_name.to_string()));
         }
 
+        // Unsubscribing ends a durable subscription for good
+        if let Some(name) = self.take_durable(conn, channel_name) {
+            self.remove_durable(&name);
+            return Ok(());
+        }
+
         // Remove from channel
         if let Some(mut entry) = self.channels.get_mut(channel_name) {
-            entry.channel.unsubscribe(connection_id);
-            entry.presence.leave(connection_id);
+            if entry.remove(conn, connection_id) {
+                self.notify_occupancy(channel_name, OccupancyChange::Vacated);
+            }
 
             debug!(
                 channel = %channel_name,
@@ -204,26 +1100,54 @@ impl Router {
             // Auto-delete empty channels
             if self.config.auto_delete_empty_channels && entry.channel.is_empty() {
                 drop(entry); // Release the lock
-                self.channels.remove(channel_name);
-                debug!(channel = %channel_name, "Deleted empty channel");
+                self.remove_if_empty(channel_name);
             }
         }
 
         Ok(())
     }
 
+    /// Delete a channel if it has no subscribers.
+    ///
+    /// Checked again under the map's lock: another connection may have
+    /// subscribed since the caller released the entry, and deleting the
+    /// channel then would strand that subscription.
+    fn remove_if_empty(&self, channel_name: &str) {
+        if self
+            .channels
+            .remove_if(channel_name, |_, entry| entry.channel.is_empty())
+            .is_some()
+        {
+            debug!(channel = %channel_name, "Deleted empty channel");
+        }
+    }
+
     /// Unsubscribe a connection from all channels.
+    ///
+    /// This also releases the connection's internal ID.
     pub fn unsubscribe_all(&self, connection_id: &str) {
-        if let Some((_, channels)) = self.subscriptions.remove(connection_id) {
+        let Some((_, conn)) = self.conn_ids.remove(connection_id) else {
+            return;
+        };
+        self.conn_names.remove(&conn);
+        let handle = self.handles.remove(&conn).map(|(_, h)| h);
+        self.pattern_subscriptions.remove(&conn);
+        if let Some((_, names)) = self.durable_consumers.remove(&conn) {
+            if let Some(handle) = &handle {
+                self.detach_durables(conn, &names, handle);
+            }
+        }
+
+        if let Some((_, channels)) = self.subscriptions.remove(&conn) {
             for channel_name in channels.iter() {
-                if let Some(mut entry) = self.channels.get_mut(channel_name.as_str()) {
-                    entry.channel.unsubscribe(connection_id);
-                    entry.presence.leave(connection_id);
+                if let Some(mut entry) = self.channels.get_mut(channel_name.as_ref()) {
+                    if entry.remove(conn, connection_id) {
+                        self.notify_occupancy(&channel_name, OccupancyChange::Vacated);
+                    }
 
                     if self.config.auto_delete_empty_channels && entry.channel.is_empty() {
-                        let name = channel_name.clone();
                         drop(entry);
-                        self.channels.remove(&name);
+                        self.remove_if_empty(&channel_name);
                     }
                 }
             }
@@ -232,39 +1156,687 @@ impl Router {
         debug!(connection = %connection_id, "Unsubscribed from all channels");
     }
 
-    /// Publish a message to a channel.
+    /// Create a handle for a new connection.
     ///
-    /// Returns the number of subscribers that received the message.
-    pub fn publish(&self, message: Message) -> usize {
-        let channel_name = message.channel.clone();
+    /// The handle's queue capacity comes from the router configuration.
+    #[must_use]
+    pub fn connect(&self, connection_id: &str) -> Arc<ConnectionHandle> {
+        self.connect_with_capacity(connection_id, self.config.connection_queue_capacity)
+    }
 
-        if let Some(entry) = self.channels.get(&channel_name) {
-            let count = entry.channel.publish(message);
-            trace!(channel = %channel_name, recipients = count, "Published message");
-            count
-        } else {
-            warn!(channel = %channel_name, "Publish to non-existent channel");
-            0
-        }
+    fn connect_with_capacity(&self, connection_id: &str, capacity: usize) -> Arc<ConnectionHandle> {
+        let conn = self.intern_connection(connection_id);
+        let handle =
+            ConnectionHandle::with_stats(conn, connection_id, capacity, self.queue_stats.clone());
+        self.handles.insert(conn, handle.clone());
+        handle
     }
 
-    /// Publish raw payload to a channel.
-    pub fn publish_to(&self, channel_name: &str, payload: impl Into<bytes::Bytes>) -> usize {
+    /// Get lag and drop counters for all connection queues, including
+    /// connections that have since closed.
+    #[must_use]
+    pub fn queue_stats(&self) -> &QueueStats {
+        &self.queue_stats
+    }
+
+    /// Unsubscribe a connection handle from all channels and close it.
+    pub fn disconnect(&self, handle: &ConnectionHandle) {
+        self.unsubscribe_all(handle.id());
+        handle.close();
+    }
+
+    /// Get the number of connected handles.
+    #[must_use]
+    pub fn connection_count(&self) -> usize {
+        self.handles.len()
+    }
+
+    /// Get the client IP recorded on a connection's handle.
+    fn remote_ip(&self, conn: ConnId) -> Option<IpAddr> {
+        self.handles.get(&conn).and_then(|h| h.remote_ip())
+    }
+
+    /// Get the handle of a connected client.
+    #[must_use]
+    pub fn handle(&self, connection_id: &str) -> Option<Arc<ConnectionHandle>> {
+        let conn = self.lookup_connection(connection_id)?;
+        self.handles.get(&conn).map(|h| h.value().clone())
+    }
+
+    /// The `n` channels with the most subscribers, busiest first.
+    #[must_use]
+    pub fn top_channels(&self, n: usize) -> Vec<(ChannelId, usize)> {
+        let mut channels: Vec<(ChannelId, usize)> = self
+            .channels
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.channel.subscriber_count()))
+            .collect();
+        channels.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        channels.truncate(n);
+        channels
+    }
+
+    /// Counters for every channel, in no particular order.
+    #[must_use]
+    pub fn channel_stats(&self) -> Vec<ChannelStats> {
+        self.channels
+            .iter()
+            .map(|entry| ChannelStats {
+                channel: entry.key().clone(),
+                subscribers: entry.channel.subscriber_count(),
+                seq: entry.channel.seq(),
+                history: entry.channel.history_len(),
+            })
+            .collect()
+    }
+
+    /// List channels a page at a time, filtered and sorted by `query`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RouterError::InvalidCursor`] if the query's cursor doesn't
+    /// fit its sort order.
+    pub fn list_channels(&self, query: &ChannelQuery) -> Result<Page<ChannelStats>, RouterError> {
+        let Some(user_id) = &query.user_id else {
+            return query.page(self.channel_stats());
+        };
+        let mut channels = HashSet::new();
+        for handle in &self.handles {
+            if handle.user_id() == Some(user_id.as_str()) {
+                if let Some(subs) = self.subscriptions.get(handle.key()) {
+                    channels.extend(subs.iter().map(|c| c.clone()));
+                }
+            }
+        }
+        let stats = channels.into_iter().filter_map(|name| {
+            self.channels.get(&name).map(|entry| ChannelStats {
+                channel: name.clone(),
+                subscribers: entry.channel.subscriber_count(),
+                seq: entry.channel.seq(),
+                history: entry.channel.history_len(),
+            })
+        });
+        query.page(stats)
+    }
+
+    /// List a channel's subscribers a page at a time, filtered and sorted
+    /// by `query`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the channel does not exist or the query's cursor
+    /// doesn't fit its sort order.
+    pub fn list_subscribers(
+        &self,
+        channel_name: &str,
+        query: &SubscriberQuery,
+    ) -> Result<Page<SubscriberInfo>, RouterError> {
+        let key = self.channel_key(channel_name);
+        let subscribers = self
+            .channels
+            .get(key.as_ref())
+            .ok_or_else(|| RouterError::ChannelNotFound(channel_name.to_string()))?
+            .channel
+            .subscribers();
+        // Subscribers by receiver rather than handle are listed by ID only
+        let subscribers =
+            subscribers
+                .into_iter()
+                .filter_map(|conn| match self.handles.get(&conn) {
+                    Some(handle) => Some(SubscriberInfo {
+                        connection_id: handle.id().to_string(),
+                        user_id: handle.user_id().map(str::to_string),
+                        transport: handle.transport().map(str::to_string),
+                        ip: handle.remote_ip(),
+                        connected_at: epoch_millis(handle.connected_at()),
+                    }),
+                    None => self.connection_name(conn).map(|id| SubscriberInfo {
+                        connection_id: id.to_string(),
+                        user_id: None,
+                        transport: None,
+                        ip: None,
+                        connected_at: 0,
+                    }),
+                });
+        query.page(subscribers)
+    }
+
+    /// The handles of every connected client, in no particular order.
+    #[must_use]
+    pub fn handles(&self) -> Vec<Arc<ConnectionHandle>> {
+        self.handles.iter().map(|h| h.value().clone()).collect()
+    }
+
+    /// Estimate the memory held by channel history, presence and connection
+    /// queues.
+    ///
+    /// Walks all retained and queued messages, so call it periodically
+    /// rather than per message.
+    #[must_use]
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let mut seen = HashSet::new();
+        let mut usage = MemoryUsage::default();
+        for entry in &self.channels {
+            entry.channel.for_each_retained(|m| {
+                if seen.insert(Arc::as_ptr(m)) {
+                    usage.history += m.size_estimate();
+                }
+            });
+            usage.presence += entry.presence.size_estimate();
+        }
+        for handle in &self.handles {
+            handle.for_each_queued(|m| {
+                usage.queues += std::mem::size_of::<Arc<Message>>();
+                if seen.insert(Arc::as_ptr(m)) {
+                    usage.queues += m.size_estimate();
+                }
+            });
+        }
+        usage
+    }
+
+    /// Evict retained history until router state fits within
+    /// [`RouterConfig::max_memory`], in the order set by
+    /// [`RouterConfig::memory_eviction`].
+    ///
+    /// Returns the usage measured before eviction and the number of
+    /// messages evicted. Evicted messages can no longer be replayed to new
+    /// subscribers.
+    pub fn enforce_memory_limit(&self) -> (MemoryUsage, usize) {
+        let usage = self.memory_usage();
+        let total = usage.total();
+        let Some(max) = self.config.max_memory.filter(|&max| total > max) else {
+            return (usage, 0);
+        };
+        let evicted = self.evict_history(total - max);
+        if evicted > 0 {
+            warn!(
+                bytes = total,
+                max_bytes = max,
+                evicted,
+                "Router memory over limit, evicted history"
+            );
+        }
+        (usage, evicted)
+    }
+
+    /// Evict retained messages until at least `excess` bytes are freed or
+    /// no history is left.
+    fn evict_history(&self, mut excess: usize) -> usize {
+        let oldest_first = self.config.memory_eviction == MemoryEviction::OldestHistory;
+        // Channels by eviction priority: the oldest retained message, or
+        // the most retained bytes
+        let priority = |channel: &Channel| {
+            if oldest_first {
+                channel.oldest_retained().map(|t| u64::MAX - t)
+            } else {
+                let mut bytes = 0;
+                channel.for_each_retained(|m| bytes += m.size_estimate() as u64);
+                (bytes > 0).then_some(bytes)
+            }
+        };
+        let mut queue: BinaryHeap<_> = self
+            .channels
+            .iter()
+            .filter_map(|entry| priority(&entry.channel).map(|p| (p, entry.key().clone())))
+            .collect();
+
+        let mut evicted = 0;
+        while excess > 0 {
+            let Some((bytes, name)) = queue.pop() else {
+                break;
+            };
+            let Some(entry) = self.channels.get(&name) else {
+                continue;
+            };
+            let Some(freed) = entry.channel.evict_oldest() else {
+                continue;
+            };
+            evicted += 1;
+            excess = excess.saturating_sub(freed);
+            let next = if oldest_first {
+                priority(&entry.channel)
+            } else {
+                Some(bytes.saturating_sub(freed as u64)).filter(|&b| b > 0)
+            };
+            if let Some(next) = next {
+                queue.push((next, name));
+            }
+        }
+        evicted
+    }
+
+    /// Messages waiting in connection queues, across all connections.
+    #[must_use]
+    pub fn queued_messages(&self) -> usize {
+        self.handles.iter().map(|h| h.len()).sum()
+    }
+
+    /// Close every connected handle with a reason, e.g. on shutdown.
+    ///
+    /// Subscriptions are released when each connection calls
+    /// [`disconnect`](Self::disconnect). Returns the number of handles closed.
+    pub fn close_all(&self, reason: &CloseReason) -> usize {
+        let closed = self.close_where(reason, |_| true);
+        info!(connections = closed, reason = ?reason.reason, "Closed all connections");
+        closed
+    }
+
+    /// Close the connected handles matching a predicate, e.g. every
+    /// connection of a banned user. Returns the number of handles closed.
+    pub fn close_where(
+        &self,
+        reason: &CloseReason,
+        predicate: impl Fn(&ConnectionHandle) -> bool,
+    ) -> usize {
+        let handles: Vec<_> = self
+            .handles
+            .iter()
+            .filter(|h| predicate(h.value()))
+            .map(|h| h.value().clone())
+            .collect();
+        for handle in &handles {
+            handle.close_with(reason.clone());
+        }
+        handles.len()
+    }
+
+    /// Publish a message on behalf of a client connection.
+    ///
+    /// Private and presence channels only take publishes from connections
+    /// subscribed to them, which for private channels means the connection
+    /// passed the [`ChannelAuthorizer`]. Server-side publishers use
+    /// [`publish_with_receipt`](Self::publish_with_receipt), which trusts
+    /// its caller.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RouterError::Unauthorized`] if the connection may not
+    /// publish to the channel.
+    pub fn publish_from(
+        &self,
+        connection_id: &str,
+        message: Message,
+    ) -> Result<PublishReceipt, RouterError> {
+        self.authorize_publish(connection_id, &message.channel)?;
+        Ok(self.publish_with_receipt(message))
+    }
+
+    /// Publish a group of messages on behalf of a client connection, as
+    /// [`publish_group`](Self::publish_group) does. The group is refused
+    /// whole if any message fails the checks of
+    /// [`publish_from`](Self::publish_from).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RouterError::Unauthorized`] if the connection may not
+    /// publish to one of the channels.
+    pub fn publish_group_from(
+        &self,
+        connection_id: &str,
+        messages: Vec<Message>,
+    ) -> Result<GroupPublishReceipt, RouterError> {
+        for message in &messages {
+            self.authorize_publish(connection_id, &message.channel)?;
+        }
+        Ok(self.publish_group(messages))
+    }
+
+    /// Check a connection may publish to a channel: private and presence
+    /// channels require a subscription.
+    fn authorize_publish(
+        &self,
+        connection_id: &str,
+        channel_name: &str,
+    ) -> Result<(), RouterError> {
+        let key = self.channel_key(channel_name);
+        let kind = ChannelKind::from_name(&key);
+        if !kind.requires_auth() && !kind.tracks_presence() {
+            return Ok(());
+        }
+        let subscribed = self
+            .lookup_connection(connection_id)
+            .and_then(|conn| self.subscriptions.get(&conn))
+            .is_some_and(|subs| subs.contains(key.as_ref()));
+        if subscribed {
+            Ok(())
+        } else {
+            Err(RouterError::Unauthorized(key.into_owned()))
+        }
+    }
+
+    /// Publish a message to a channel.
+    ///
+    /// Returns the number of subscribers that received the message. A missing
+    /// channel is created first if
+    /// [`create_on_publish`](RouterConfig::create_on_publish) is set.
+    pub fn publish(&self, message: Message) -> usize {
+        self.publish_with_receipt(message).recipients
+    }
+
+    /// Publish a message to a channel, returning the ID and sequence number
+    /// it was given along with the number of recipients.
+    pub fn publish_with_receipt(&self, mut message: Message) -> PublishReceipt {
+        let normalized = match self.channel_key(&message.channel) {
+            Cow::Owned(name) => Some(name),
+            Cow::Borrowed(_) => None,
+        };
+        if let Some(name) = normalized {
+            message.channel = name.into();
+        }
+        let channel_name = message.channel.clone();
+        self.record_hot(&message);
+
+        if self.config.create_on_publish && !self.channels.contains_key(channel_name.as_ref()) {
+            self.create_for_publish(&channel_name);
+        }
+
+        let (message, count) = if let Some(entry) = self.channels.get(channel_name.as_ref()) {
+            let (message, count) = entry
+                .channel
+                .publish_shared(message, self.tracer.as_deref());
+            trace!(channel = %channel_name, recipients = count, "Published message");
+            (message, count)
+        } else {
+            if self.pattern_subscriptions.is_empty() {
+                warn!(channel = %channel_name, "Publish to non-existent channel");
+            }
+            if let Some(tracer) = self.tracer.as_deref().filter(|_| message.trace) {
+                tracer.trace(&message, TraceEvent::ChannelMissing);
+            }
+            (Arc::new(message), 0)
+        };
+
+        PublishReceipt {
+            id: message.id,
+            seq: message.seq,
+            recipients: count + self.deliver_to_patterns(&message),
+        }
+    }
+
+    /// Count a publish towards the busiest channels, leaving out system
+    /// channels.
+    fn record_hot(&self, message: &Message) {
+        if !message.channel.starts_with(SYSTEM_CHANNEL_PREFIX) {
+            self.hot.record(&message.channel, message.payload.len());
+        }
+    }
+
+    /// Get the channels with the highest message and byte rates over the
+    /// last window, `k` of each, as estimated by a space-saving sketch.
+    ///
+    /// Windows are closed by [`rotate_hot_channels`](Self::rotate_hot_channels);
+    /// until the first is, the report is empty.
+    #[must_use]
+    pub fn hot_channels(&self, k: usize) -> HotChannelReport {
+        self.hot.report().top(k)
+    }
+
+    /// Close the window of publishes [`hot_channels`](Self::hot_channels)
+    /// reports on and start a new one. Call it periodically.
+    pub fn rotate_hot_channels(&self) {
+        self.hot.rotate();
+    }
+
+    /// Publish messages to several channels as one group.
+    ///
+    /// Every message is stamped with a shared group ID. The channels are
+    /// sequenced together, and each subscriber's queue receives the messages
+    /// it wants back to back, in order, with no other publish landing
+    /// between them; a subscriber of both a board and its spectator channel
+    /// never sees one update without the other. Drop policies still apply
+    /// to full queues. Messages to channels that don't exist are only
+    /// delivered to pattern subscribers, as with [`publish`](Self::publish).
+    pub fn publish_group(&self, messages: Vec<Message>) -> GroupPublishReceipt {
+        let group_id = generate_message_id();
+        let messages: Vec<Message> = messages
+            .into_iter()
+            .map(|mut message| {
+                if let Cow::Owned(name) = self.channel_key(&message.channel) {
+                    message.channel = name.into();
+                }
+                message.group_id = Some(group_id);
+                self.record_hot(&message);
+                message
+            })
+            .collect();
+
+        let mut names: Vec<ChannelId> = messages.iter().map(|m| m.channel.clone()).collect();
+        names.sort_unstable();
+        names.dedup();
+        if self.config.create_on_publish {
+            for name in &names {
+                if !self.channels.contains_key(name.as_ref()) {
+                    self.create_for_publish(name);
+                }
+            }
+        }
+
+        // Lock histories in name order so concurrent groups can't deadlock
+        let entries: Vec<_> = names
+            .iter()
+            .filter_map(|name| self.channels.get(name.as_ref()))
+            .collect();
+        let mut histories: Vec<_> = entries.iter().map(|e| e.channel.lock_history()).collect();
+
+        let mut published = Vec::with_capacity(messages.len());
+        let mut receipts = Vec::with_capacity(messages.len());
+        let mut batches: Vec<(Arc<ConnectionHandle>, Vec<_>)> = Vec::new();
+        let mut batch_index: HashMap<ConnId, usize> = HashMap::new();
+        for message in messages {
+            let Some(i) = entries.iter().position(|e| *e.key() == message.channel) else {
+                receipts.push(PublishReceipt {
+                    id: message.id,
+                    seq: 0,
+                    recipients: 0,
+                });
+                published.push(Arc::new(message));
+                continue;
+            };
+            let channel = &entries[i].channel;
+            let message = channel.sequence(&mut histories[i], message);
+            let mut recipients = channel.broadcast(message.clone());
+            for handle in channel.recipients(&message) {
+                let index = *batch_index.entry(handle.conn_id()).or_insert_with(|| {
+                    batches.push((handle.clone(), Vec::new()));
+                    batches.len() - 1
+                });
+                batches[index]
+                    .1
+                    .push((message.clone(), channel.drop_policy()));
+                recipients += 1;
+            }
+            receipts.push(PublishReceipt {
+                id: message.id,
+                seq: message.seq,
+                recipients,
+            });
+            published.push(message);
+        }
+
+        // Queue under the history locks so later publishes land after
+        for (handle, batch) in batches {
+            handle.push_batch(batch);
+        }
+        drop(histories);
+        drop(entries);
+
+        for (receipt, message) in receipts.iter_mut().zip(&published) {
+            receipt.recipients += self.deliver_to_patterns(message);
+        }
+        trace!(
+            group = group_id,
+            messages = receipts.len(),
+            "Published group"
+        );
+        GroupPublishReceipt { group_id, receipts }
+    }
+
+    /// Publish a message to every existing channel matching a pattern.
+    ///
+    /// Each channel gets its own copy of the message with a fresh ID and the
+    /// channel's next sequence number, as if published to it directly.
+    /// System channels are never matched. Meant for trusted server-side
+    /// callers such as the admin API; clients cannot publish to patterns.
+    pub fn publish_pattern(
+        &self,
+        pattern: &ChannelPattern,
+        message: Message,
+    ) -> PatternPublishReceipt {
+        let names: Vec<ChannelId> = self
+            .channels
+            .iter()
+            .filter(|e| !e.key().starts_with(SYSTEM_CHANNEL_PREFIX) && pattern.matches(e.key()))
+            .map(|e| e.key().clone())
+            .collect();
+
+        let mut receipt = PatternPublishReceipt::default();
+        for name in names {
+            receipt.recipients += self
+                .publish_with_receipt(Message {
+                    id: generate_message_id(),
+                    channel: name,
+                    ..message.clone()
+                })
+                .recipients;
+            receipt.channels += 1;
+        }
+        debug!(pattern = %pattern, channels = receipt.channels, recipients = receipt.recipients, "Published to pattern");
+        receipt
+    }
+
+    /// Get how full the fullest subscriber queue of any channel matching a
+    /// pattern is, from 0.0 to 1.0 (see [`Channel::queue_fill`]).
+    ///
+    /// Publishers can treat a high fill as backpressure: more messages for
+    /// those subscribers would only push out ones still waiting.
+    #[must_use]
+    pub fn queue_fill(&self, pattern: &ChannelPattern) -> f64 {
+        if pattern.is_literal() {
+            return self
+                .channels
+                .get(self.channel_key(pattern.as_str()).as_ref())
+                .map_or(0.0, |e| e.channel.queue_fill());
+        }
+        self.channels
+            .iter()
+            .filter(|e| !e.key().starts_with(SYSTEM_CHANNEL_PREFIX) && pattern.matches(e.key()))
+            .map(|e| e.channel.queue_fill())
+            .fold(0.0, f64::max)
+    }
+
+    /// Subscribe a connection handle to every channel matching a pattern.
+    ///
+    /// Pattern subscribers receive publishes to matching channels whether or
+    /// not the channel exists locally, and never their own publishes. This is
+    /// how federation links relay traffic between servers; there is no
+    /// history replay or presence.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection already subscribed to the pattern.
+    pub fn subscribe_pattern(
+        &self,
+        handle: &Arc<ConnectionHandle>,
+        pattern: impl Into<ChannelPattern>,
+    ) -> Result<(), RouterError> {
+        let pattern = pattern.into();
+        let mut patterns = self
+            .pattern_subscriptions
+            .entry(handle.conn_id())
+            .or_default();
+        if patterns.contains(&pattern) {
+            return Err(RouterError::AlreadySubscribed(pattern.to_string()));
+        }
+        debug!(connection = %handle.id(), pattern = %pattern, "Subscribed to pattern");
+        patterns.push(pattern);
+        Ok(())
+    }
+
+    /// Remove a connection's pattern subscription.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the connection is not subscribed to the pattern.
+    pub fn unsubscribe_pattern(
+        &self,
+        connection_id: &str,
+        pattern: &str,
+    ) -> Result<(), RouterError> {
+        let not_subscribed = || RouterError::NotSubscribed(pattern.to_string());
+        let conn = self
+            .lookup_connection(connection_id)
+            .ok_or_else(not_subscribed)?;
+        let mut patterns = self
+            .pattern_subscriptions
+            .get_mut(&conn)
+            .ok_or_else(not_subscribed)?;
+        let index = patterns
+            .iter()
+            .position(|p| p.as_str() == pattern)
+            .ok_or_else(not_subscribed)?;
+        patterns.remove(index);
+        Ok(())
+    }
+
+    /// Deliver a published message to matching pattern subscribers.
+    fn deliver_to_patterns(&self, message: &Arc<Message>) -> usize {
+        if message.kind != MessageKind::Publish {
+            return 0;
+        }
+        let mut count = 0;
+        for subscription in &self.pattern_subscriptions {
+            if !subscription.iter().any(|p| p.matches(&message.channel)) {
+                continue;
+            }
+            let Some(handle) = self.handles.get(subscription.key()) else {
+                continue;
+            };
+            if message.source.as_deref() == Some(handle.id()) {
+                continue;
+            }
+            let queued = handle.push(message.clone());
+            if queued {
+                count += 1;
+            }
+            if let Some(tracer) = self.tracer.as_deref().filter(|_| message.trace) {
+                let connection = handle.id();
+                let event = if queued {
+                    TraceEvent::Enqueued { connection }
+                } else {
+                    TraceEvent::Dropped { connection }
+                };
+                tracer.trace(message, event);
+            }
+        }
+        count
+    }
+
+    /// Publish raw payload to a channel.
+    pub fn publish_to(&self, channel_name: &str, payload: impl Into<bytes::Bytes>) -> usize {
         let message = Message::new(channel_name, payload);
         self.publish(message)
     }
 
+    /// Find the rule that applies to a channel, matching the name as the
+    /// validator normalizes it, like the channel itself is created.
+    #[must_use]
+    pub fn channel_rule(&self, channel_name: &str) -> Option<&ChannelRule> {
+        self.config.rule_for(&self.channel_key(channel_name))
+    }
+
     /// Check if a channel exists.
     #[must_use]
     pub fn channel_exists(&self, channel_name: &str) -> bool {
-        self.channels.contains_key(channel_name)
+        self.channels
+            .contains_key(self.channel_key(channel_name).as_ref())
     }
 
     /// Get the subscriber count for a channel.
     #[must_use]
     pub fn subscriber_count(&self, channel_name: &str) -> usize {
         self.channels
-            .get(channel_name)
+            .get(self.channel_key(channel_name).as_ref())
             .map(|e| e.channel.subscriber_count())
             .unwrap_or(0)
     }
@@ -272,47 +1844,180 @@ impl Router {
     /// Get all channel names.
     #[must_use]
     pub fn channel_names(&self) -> Vec<String> {
-        self.channels.iter().map(|e| e.key().clone()).collect()
+        self.channels.iter().map(|e| e.key().to_string()).collect()
     }
 
-    /// Join presence for a channel.
+    /// Join presence for a channel, or update the data if already present.
+    ///
+    /// The change is broadcast to the channel. Returns `true` if this is a
+    /// new member.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the channel does not exist, is not a presence
+    /// channel, or the connection is not subscribed to it.
     pub fn presence_join(
         &self,
         connection_id: &str,
         channel_name: &str,
         data: Option<serde_json::Value>,
-    ) -> bool {
-        if let Some(mut entry) = self.channels.get_mut(channel_name) {
-            entry.presence.join(connection_id, data)
+    ) -> Result<bool, RouterError> {
+        let key = self.channel_key(channel_name);
+        let channel_name = key.as_ref();
+
+        let entry = self
+            .channels
+            .get(channel_name)
+            .ok_or_else(|| RouterError::ChannelNotFound(channel_name.to_string()))?;
+
+        if !entry.channel.kind().tracks_presence() {
+            return Err(RouterError::InvalidChannel(
+                "Presence is only available on presence channels",
+            ));
+        }
+        let subscribed = self
+            .lookup_connection(connection_id)
+            .is_some_and(|conn| entry.channel.is_subscribed(conn));
+        if !subscribed {
+            return Err(RouterError::NotSubscribed(channel_name.to_string()));
+        }
+
+        let is_new = !entry.presence.is_present(connection_id);
+        match data {
+            Some(data) if !is_new => {
+                if let Some(delta) = entry.presence.update(connection_id, data) {
+                    if let Some(state) = entry.presence.get(connection_id) {
+                        entry.publish_presence_update(&state, &delta);
+                    }
+                }
+                return Ok(false);
+            }
+            data => {
+                entry.presence.join(connection_id, data);
+                if let Some(ip) = self
+                    .lookup_connection(connection_id)
+                    .and_then(|c| self.remote_ip(c))
+                {
+                    entry.presence.set_remote_ip(connection_id, ip);
+                }
+            }
+        }
+
+        let action = if is_new {
+            PresenceAction::Join
         } else {
-            false
+            PresenceAction::Update
+        };
+        if let Some(state) = entry.presence.get(connection_id) {
+            entry.publish_presence(action, &state);
         }
+
+        Ok(is_new)
     }
 
     /// Leave presence for a channel.
+    ///
+    /// The departure is broadcast to the channel. The connection stays
+    /// subscribed.
     pub fn presence_leave(&self, connection_id: &str, channel_name: &str) -> Option<PresenceState> {
-        if let Some(mut entry) = self.channels.get_mut(channel_name) {
-            entry.presence.leave(connection_id)
-        } else {
-            None
+        let entry = self.channels.get(self.channel_key(channel_name).as_ref())?;
+        let state = entry.presence.leave(connection_id)?;
+        entry.publish_presence(PresenceAction::Leave, &state);
+        Some(state)
+    }
+
+    /// Refresh a connection's presence on the channels it is subscribed to,
+    /// so it outlives their presence TTL.
+    pub fn touch_presence(&self, connection_id: &str) {
+        let Some(conn) = self.lookup_connection(connection_id) else {
+            return;
+        };
+        let channels: Vec<ChannelId> = self
+            .subscriptions
+            .get(&conn)
+            .map(|s| s.iter().map(|c| c.clone()).collect())
+            .unwrap_or_default();
+        for name in channels {
+            if let Some(entry) = self.channels.get(&name) {
+                entry.presence.touch(connection_id);
+            }
+        }
+    }
+
+    /// Remove presence members that have not refreshed within their
+    /// channel's presence TTL, broadcasting a Leave for each. They stay
+    /// subscribed. Returns the number removed.
+    pub fn expire_presence(&self) -> usize {
+        let mut expired = 0;
+        for entry in &self.channels {
+            let Some(ttl) = entry.presence_ttl else {
+                continue;
+            };
+            for state in entry.presence.prune_stale(ttl) {
+                entry.publish_presence(PresenceAction::Leave, &state);
+                expired += 1;
+            }
+        }
+        if expired > 0 {
+            info!(count = expired, "Expired presence members");
         }
+        expired
     }
 
-    /// Get presence snapshot for a channel.
+    /// Get presence snapshot for a channel, including members that other
+    /// servers reported with [`Router::set_remote_presence`].
     #[must_use]
     pub fn presence_snapshot(&self, channel_name: &str) -> Vec<PresenceState> {
         self.channels
-            .get(channel_name)
+            .get(self.channel_key(channel_name).as_ref())
             .map(|e| e.presence.snapshot())
             .unwrap_or_default()
     }
 
+    /// Get the presence members of a channel connected to this server.
+    #[must_use]
+    pub fn local_presence_snapshot(&self, channel_name: &str) -> Vec<PresenceState> {
+        self.channels
+            .get(self.channel_key(channel_name).as_ref())
+            .map(|e| e.presence.local_snapshot())
+            .unwrap_or_default()
+    }
+
+    /// Replace the presence members of a channel connected to another
+    /// server, `node`.
+    ///
+    /// Returns `false` if the channel doesn't exist here or doesn't track
+    /// presence; members of channels nobody here uses are not kept.
+    pub fn set_remote_presence(
+        &self,
+        channel_name: &str,
+        node: &str,
+        members: Vec<PresenceState>,
+    ) -> bool {
+        let Some(entry) = self.channels.get(self.channel_key(channel_name).as_ref()) else {
+            return false;
+        };
+        if !entry.channel.kind().tracks_presence() {
+            return false;
+        }
+        entry.presence.set_remote(node, members);
+        true
+    }
+
+    /// Forget every presence member reported by another server, such as
+    /// when the link to it drops.
+    pub fn remove_remote_presence(&self, node: &str) {
+        for entry in &self.channels {
+            entry.presence.remove_remote(node);
+        }
+    }
+
     /// Get the channels a connection is subscribed to.
     #[must_use]
     pub fn connection_channels(&self, connection_id: &str) -> Vec<String> {
-        self.subscriptions
-            .get(connection_id)
-            .map(|s| s.iter().map(|c| c.clone()).collect())
+        self.lookup_connection(connection_id)
+            .and_then(|conn| self.subscriptions.get(&conn))
+            .map(|s| s.iter().map(|c| c.to_string()).collect())
             .unwrap_or_default()
     }
 }
@@ -323,8 +2028,20 @@ impl Default for Router {
     }
 }
 
+/// How a subscription is checked.
+#[derive(Debug, Clone, Copy)]
+enum Access<'a> {
+    /// Against the naming policy, and the authorizer with this signature.
+    Checked(Option<&'a str>),
+    /// Against the naming policy only; the caller has authorized the
+    /// channel for the connection the subscription is for.
+    Authorized,
+    /// Not at all: a system channel subscribed on the server's behalf.
+    System,
+}
+
 /// Router statistics.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct RouterStats {
     /// Number of active channels.
     pub channel_count: usize,
@@ -334,6 +2051,43 @@ pub struct RouterStats {
     pub total_subscriptions: usize,
 }
 
+/// Approximate memory held by router state, as reported by
+/// [`Router::memory_usage`].
+///
+/// A message both retained and queued is counted once, under history; each
+/// queue entry adds a pointer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct MemoryUsage {
+    /// Messages retained in channel history.
+    pub history: usize,
+    /// Presence members.
+    pub presence: usize,
+    /// Messages waiting in connection queues.
+    pub queues: usize,
+}
+
+impl MemoryUsage {
+    /// Get the total across all kinds of state.
+    #[must_use]
+    pub fn total(&self) -> usize {
+        self.history + self.presence + self.queues
+    }
+}
+
+/// A channel's counters, as listed by [`Router::channel_stats`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelStats {
+    /// Channel name.
+    pub channel: ChannelId,
+    /// Number of subscribers.
+    pub subscribers: usize,
+    /// Sequence number of the latest published message, which counts the
+    /// messages published since the channel was created.
+    pub seq: u64,
+    /// Number of retained history messages.
+    pub history: usize,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -370,11 +2124,523 @@ mod tests {
     }
 
     #[test]
-    fn test_router_invalid_channel() {
+    fn test_router_publish_receipt() {
         let router = Router::new();
+        let handle = router.connect("conn-1");
+        router.subscribe_handle(&handle, "test", None).unwrap();
 
-        assert!(router.subscribe("conn-1", "").is_err());
-        assert!(router.subscribe("conn-1", "$system").is_err());
+        let message = Message::new("test", b"one".to_vec());
+        let id = message.id;
+        let receipt = router.publish_with_receipt(message);
+        assert_eq!(receipt.id, id);
+        assert_eq!(receipt.seq, 1);
+        assert_eq!(receipt.recipients, 1);
+        assert_eq!(
+            router
+                .publish_with_receipt(Message::new("test", b"two".to_vec()))
+                .seq,
+            2
+        );
+
+        // Nobody listening
+        let receipt = router.publish_with_receipt(Message::new("missing", b"three".to_vec()));
+        assert_eq!((receipt.seq, receipt.recipients), (0, 0));
+    }
+
+    #[test]
+    fn test_router_publish_group() {
+        let router = Router::new();
+        let player = router.connect("player");
+        let spectator = router.connect("spectator");
+        router.subscribe_handle(&player, "board:1", None).unwrap();
+        router
+            .subscribe_handle(&spectator, "board:1", None)
+            .unwrap();
+        router
+            .subscribe_handle(&spectator, "spectators:1", None)
+            .unwrap();
+        router.publish(Message::new("spectators:1", b"hello".to_vec()));
+
+        let receipt = router.publish_group(vec![
+            Message::new("board:1", b"e2e4".to_vec()),
+            Message::new("spectators:1", b"white moved".to_vec()),
+            Message::new("missing", b"nobody".to_vec()),
+        ]);
+        let seqs: Vec<_> = receipt.receipts.iter().map(|r| r.seq).collect();
+        assert_eq!(seqs, [1, 2, 0]);
+        assert_eq!(receipt.recipients(), 3);
+
+        assert_eq!(&player.try_recv().unwrap().payload[..], b"e2e4");
+        let delivered: Vec<_> = std::iter::from_fn(|| spectator.try_recv()).collect();
+        assert_eq!(delivered.len(), 3);
+        assert_eq!(delivered[0].group_id, None);
+        assert_eq!(&delivered[1].payload[..], b"e2e4");
+        assert_eq!(&delivered[2].payload[..], b"white moved");
+        assert_eq!(delivered[1].group_id, Some(receipt.group_id));
+        assert_eq!(delivered[2].group_id, Some(receipt.group_id));
+    }
+
+    #[test]
+    fn test_router_publish_pattern() {
+        let router = Router::new();
+        let handle = router.connect("conn-1");
+        for channel in ["region:eu:1", "region:eu:2", "region:us:1"] {
+            router.subscribe_handle(&handle, channel, None).unwrap();
+        }
+        router.create_channel("region:eu:empty").unwrap();
+
+        let message = Message::new("ignored", b"maintenance".to_vec()).with_event("notice");
+        let receipt = router.publish_pattern(&ChannelPattern::new("region:eu:*"), message);
+        assert_eq!(
+            receipt,
+            PatternPublishReceipt {
+                channels: 3,
+                recipients: 2
+            }
+        );
+
+        let mut delivered: Vec<_> = std::iter::from_fn(|| handle.try_recv()).collect();
+        delivered.sort_by(|a, b| a.channel.cmp(&b.channel));
+        assert_eq!(&*delivered[0].channel, "region:eu:1");
+        assert_eq!(&*delivered[1].channel, "region:eu:2");
+        assert_ne!(delivered[0].id, delivered[1].id);
+        assert_eq!(delivered[0].event.as_deref(), Some("notice"));
+        assert_eq!(delivered.len(), 2);
+    }
+
+    #[test]
+    fn test_router_queue_fill() {
+        let router = Router::with_config(RouterConfig {
+            connection_queue_capacity: 4,
+            ..RouterConfig::default()
+        });
+        let fast = router.connect("conn-1");
+        let slow = router.connect("conn-2");
+        for channel in ["region:eu:1", "region:eu:2"] {
+            router.subscribe_handle(&fast, channel, None).unwrap();
+        }
+        router.subscribe_handle(&slow, "region:eu:2", None).unwrap();
+        assert_eq!(router.queue_fill(&ChannelPattern::new("region:*")), 0.0);
+
+        for _ in 0..4 {
+            router.publish_to("region:eu:2", b"update".to_vec());
+        }
+        while fast.try_recv().is_some() {}
+        assert_eq!(router.queue_fill(&ChannelPattern::new("region:eu:1")), 0.0);
+        // The slow subscriber isn't averaged away by the fast one
+        assert_eq!(router.queue_fill(&ChannelPattern::new("region:eu:2")), 1.0);
+        assert_eq!(router.queue_fill(&ChannelPattern::new("region:*")), 1.0);
+        assert_eq!(router.queue_fill(&ChannelPattern::new("missing")), 0.0);
+    }
+
+    #[test]
+    fn test_router_connection_handles() {
+        let router = Router::new();
+        let handle1 = router.connect("conn-1");
+        let handle2 = router.connect("conn-2");
+
+        router.subscribe_handle(&handle1, "test", None).unwrap();
+        router.subscribe_handle(&handle2, "test", None).unwrap();
+        router.subscribe_handle(&handle1, "other", None).unwrap();
+        assert!(matches!(
+            router.subscribe_handle(&handle1, "test", None),
+            Err(RouterError::AlreadySubscribed(_))
+        ));
+
+        assert_eq!(router.publish_to("test", b"hello".to_vec()), 2);
+        assert_eq!(router.publish_to("other", b"world".to_vec()), 1);
+        assert_eq!(handle1.len(), 2);
+        assert_eq!(&*handle2.try_recv().unwrap().channel, "test");
+
+        assert_ne!(handle1.conn_id(), handle2.conn_id());
+        assert_eq!(
+            router.connection_name(handle1.conn_id()).as_deref(),
+            Some("conn-1")
+        );
+
+        router.disconnect(&handle1);
+        assert!(handle1.is_closed());
+        assert!(router.connection_name(handle1.conn_id()).is_none());
+        assert!(!router.channel_exists("other"));
+        assert_eq!(router.publish_to("test", b"again".to_vec()), 1);
+
+        let reason = CloseReason::new(pulse_protocol::DisconnectReason::Shutdown, "Bye");
+        assert_eq!(router.connection_count(), 1);
+        assert_eq!(router.close_all(&reason), 1);
+        assert_eq!(handle2.close_reason(), Some(&reason));
+    }
+
+    #[test]
+    fn test_router_close_where() {
+        let router = Router::new();
+        let alice1 = router.connect("conn-1");
+        let alice2 = router.connect("conn-2");
+        let bob = router.connect("conn-3");
+        assert!(alice1.set_user_id("alice"));
+        assert!(!alice1.set_user_id("mallory"));
+        alice2.set_user_id("alice");
+        bob.set_user_id("bob");
+
+        assert!(Arc::ptr_eq(&router.handle("conn-3").unwrap(), &bob));
+        assert!(router.handle("conn-4").is_none());
+
+        let reason = CloseReason::new(pulse_protocol::DisconnectReason::Kicked, "Banned");
+        assert_eq!(
+            router.close_where(&reason, |h| h.user_id() == Some("alice")),
+            2
+        );
+        assert!(alice1.is_closed() && alice2.is_closed());
+        assert!(!bob.is_closed());
+    }
+
+    #[test]
+    fn test_router_subscribe_system() {
+        let router = Router::new();
+        let handle = router.connect("conn-1");
+        assert!(matches!(
+            router.subscribe_handle(&handle, "$system:stats", None),
+            Err(RouterError::InvalidChannel(_))
+        ));
+        assert!(matches!(
+            router.subscribe_system(&handle, "news", SubscribeOptions::default()),
+            Err(RouterError::InvalidChannel(_))
+        ));
+
+        router
+            .subscribe_system(&handle, "$system:stats", SubscribeOptions::default())
+            .unwrap();
+        assert_eq!(router.publish_to("$system:stats", b"{}".to_vec()), 1);
+    }
+
+    #[test]
+    fn test_router_top_channels() {
+        let router = Router::new();
+        for (conn, channel) in [
+            ("conn-1", "a"),
+            ("conn-2", "b"),
+            ("conn-3", "b"),
+            ("conn-3", "c"),
+        ] {
+            let _rx = router.subscribe(conn, channel).unwrap();
+        }
+        let top = router.top_channels(2);
+        assert_eq!(top.len(), 2);
+        assert_eq!((&*top[0].0, top[0].1), ("b", 2));
+        assert_eq!((&*top[1].0, top[1].1), ("a", 1));
+    }
+
+    #[test]
+    fn test_router_hot_channels() {
+        let router = Router::new();
+        let _rx = router.subscribe("conn-1", "room:busy").unwrap();
+        for _ in 0..20 {
+            router.publish(Message::new("room:busy", "hi"));
+        }
+        router.publish(Message::new("room:big", vec![0u8; 4096]));
+        router.publish_group(vec![Message::new("room:big", "x")]);
+        router.publish(Message::new("$system:stats", vec![0u8; 8192]));
+
+        std::thread::sleep(Duration::from_millis(5));
+        router.rotate_hot_channels();
+        let report = router.hot_channels(5);
+        let channels: Vec<_> = report
+            .by_messages
+            .iter()
+            .map(|c| c.channel.as_str())
+            .collect();
+        // System channels are left out
+        assert_eq!(channels, ["room:busy", "room:big"]);
+        assert_eq!(report.by_bytes[0].channel, "room:big");
+        assert!(report.window_secs > 0.0);
+    }
+
+    #[test]
+    fn test_router_channel_stats() {
+        let router = Router::new();
+        let handle = router.connect("conn-1");
+        router.subscribe_handle(&handle, "news", None).unwrap();
+        router.publish(Message::new("news", b"one".to_vec()));
+        router.publish(Message::new("news", b"two".to_vec()));
+
+        let stats = router.channel_stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(&*stats[0].channel, "news");
+        assert_eq!((stats[0].subscribers, stats[0].seq), (1, 2));
+
+        let handles = router.handles();
+        assert_eq!(handles.len(), 1);
+        assert_eq!(handles[0].len(), 2);
+    }
+
+    #[test]
+    fn test_router_queued_messages() {
+        let router = Router::new();
+        let handle = router.connect("conn-1");
+        router.subscribe_handle(&handle, "news", None).unwrap();
+        router.publish(Message::new("news", b"one".to_vec()));
+        router.publish(Message::new("news", b"two".to_vec()));
+        assert_eq!(router.queued_messages(), 2);
+
+        handle.try_recv().unwrap();
+        assert_eq!(router.queued_messages(), 1);
+    }
+
+    #[test]
+    fn test_router_pattern_subscription() {
+        let router = Router::new();
+        let link = router.connect("link-1");
+        let client = router.connect("conn-1");
+        router.subscribe_pattern(&link, "chat:*").unwrap();
+        assert!(matches!(
+            router.subscribe_pattern(&link, "chat:*"),
+            Err(RouterError::AlreadySubscribed(_))
+        ));
+        router
+            .subscribe_handle(&client, "chat:lobby", None)
+            .unwrap();
+
+        // Delivered with the channel's sequence number
+        assert_eq!(router.publish_to("chat:lobby", b"hi".to_vec()), 2);
+        assert_eq!(link.try_recv().unwrap().seq, 1);
+
+        // Channels that do not exist locally still reach the pattern
+        assert_eq!(router.publish_to("chat:elsewhere", b"hi".to_vec()), 1);
+        assert_eq!(router.publish_to("news:today", b"hi".to_vec()), 0);
+        assert_eq!(&*link.try_recv().unwrap().channel, "chat:elsewhere");
+
+        // Relayed messages are not sent back to the link
+        let relayed = Message::new("chat:lobby", b"remote".to_vec()).with_source("link-1");
+        assert_eq!(router.publish(relayed), 1);
+        assert!(link.try_recv().is_none());
+
+        router.unsubscribe_pattern("link-1", "chat:*").unwrap();
+        assert!(router.unsubscribe_pattern("link-1", "chat:*").is_err());
+        assert_eq!(router.publish_to("chat:elsewhere", b"hi".to_vec()), 0);
+    }
+
+    #[test]
+    fn test_router_presence_records_remote_ip() {
+        let router = Router::new();
+        let handle = router.connect("conn-1");
+        let ip: IpAddr = "192.0.2.10".parse().unwrap();
+        handle.set_remote_ip(ip);
+
+        router
+            .subscribe_handle(&handle, "presence:lobby", None)
+            .unwrap();
+        let members = router.presence_snapshot("presence:lobby");
+        assert_eq!(members[0].remote_ip, Some(ip));
+
+        // Server-side only
+        let json = serde_json::to_value(&members[0]).unwrap();
+        assert!(json.get("remote_ip").is_none());
+    }
+
+    #[test]
+    fn test_router_subscribe_with_replay() {
+        let router = Router::with_config(RouterConfig {
+            channel_rules: vec![ChannelRule::new("ticker:*").with_history_size(10)],
+            ..Default::default()
+        });
+        let publisher = router.connect("conn-1");
+        router
+            .subscribe_handle(&publisher, "ticker:eur", None)
+            .unwrap();
+        for payload in [b"1", b"2", b"3"] {
+            router.publish_to("ticker:eur", payload.to_vec());
+        }
+
+        let handle = router.connect("conn-2");
+        let options = SubscribeOptions {
+            last: Some(2),
+            ..Default::default()
+        };
+        let info = router
+            .subscribe_handle_with(&handle, "ticker:eur", None, options)
+            .unwrap();
+        assert_eq!(
+            info,
+            SubscriptionInfo {
+                seq: 3,
+                subscribers: 2,
+                presence: 0,
+                history: 3,
+                replayed: 2
+            }
+        );
+        assert_eq!(handle.try_recv().unwrap().seq, 2);
+        assert_eq!(handle.try_recv().unwrap().seq, 3);
+        assert_eq!(
+            handle.try_recv().unwrap().kind,
+            MessageKind::ReplayEnd {
+                replayed: 2,
+                truncated: false
+            }
+        );
+    }
+
+    #[test]
+    fn test_router_durable_subscription() {
+        let router = Router::with_config(RouterConfig {
+            durable: DurableConfig {
+                max_subscriptions: 10,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let payloads = |handle: &ConnectionHandle| {
+            std::iter::from_fn(|| handle.try_recv())
+                .map(|m| m.payload.to_vec())
+                .collect::<Vec<_>>()
+        };
+        let attach = |id: &str| {
+            let handle = router.connect(id);
+            let result =
+                router.subscribe_durable(&handle, "jobs", "jobs", None, Default::default());
+            (handle, result)
+        };
+
+        let (first, result) = attach("conn-1");
+        assert_eq!(result.unwrap().replayed, 0);
+        router.publish_to("jobs", b"1".to_vec());
+        assert_eq!(payloads(&first), vec![b"1".to_vec()]);
+
+        // Messages published while nobody is attached are buffered
+        router.disconnect(&first);
+        assert_eq!(router.publish_to("jobs", b"2".to_vec()), 1);
+        let (second, result) = attach("conn-2");
+        assert_eq!(result.unwrap().replayed, 1);
+        assert!(matches!(
+            attach("conn-3").1,
+            Err(RouterError::DurableInUse(_))
+        ));
+        let other = router.connect("conn-4");
+        assert!(matches!(
+            router.subscribe_durable(&other, "jobs", "other", None, Default::default()),
+            Err(RouterError::DurableChannelMismatch(_))
+        ));
+
+        // Messages still queued for a consumer that leaves are kept
+        router.publish_to("jobs", b"3".to_vec());
+        router.disconnect(&second);
+        let (third, _) = attach("conn-5");
+        assert_eq!(payloads(&third), vec![b"2".to_vec(), b"3".to_vec()]);
+
+        // Unsubscribing deletes the subscription
+        router.unsubscribe("conn-5", "jobs").unwrap();
+        assert_eq!(router.durable_count(), 0);
+        assert!(!router.channel_exists("jobs"));
+    }
+
+    #[test]
+    fn test_router_durable_expiry() {
+        let router = Router::with_config(RouterConfig {
+            durable: DurableConfig {
+                max_subscriptions: 1,
+                ttl: std::time::Duration::ZERO,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let handle = router.connect("conn-1");
+        router
+            .subscribe_durable(&handle, "a", "jobs", None, Default::default())
+            .unwrap();
+        assert!(matches!(
+            router.subscribe_durable(&handle, "b", "other", None, Default::default()),
+            Err(RouterError::MaxDurableSubscriptionsReached)
+        ));
+
+        // Attached subscriptions don't expire
+        assert_eq!(router.expire_durables(), 0);
+        router.disconnect(&handle);
+        assert_eq!(router.expire_durables(), 1);
+        assert_eq!(router.durable_count(), 0);
+        assert_eq!(router.connection_count(), 0);
+    }
+
+    #[test]
+    fn test_router_memory_limit() {
+        let history = |router: &Router| {
+            let mut stats = router.channel_stats();
+            stats.sort_by(|a, b| a.channel.cmp(&b.channel));
+            stats.iter().map(|s| s.history).collect::<Vec<_>>()
+        };
+        for (eviction, expected) in [
+            (MemoryEviction::OldestHistory, vec![1, 2]),
+            (MemoryEviction::LargestHistory, vec![2, 1]),
+        ] {
+            let router = Router::with_config(RouterConfig {
+                channel_rules: vec![ChannelRule::new("ticker:*").with_history_size(10)],
+                create_on_publish: true,
+                memory_eviction: eviction,
+                ..Default::default()
+            });
+            router.publish_to("ticker:a", vec![0; 10]);
+            router.publish_to("ticker:a", vec![0; 10]);
+            std::thread::sleep(std::time::Duration::from_millis(5));
+            router.publish_to("ticker:b", vec![0; 1000]);
+            router.publish_to("ticker:b", vec![0; 1000]);
+
+            let usage = router.memory_usage();
+            assert!(usage.history > 2000);
+            assert_eq!(usage.queues, 0);
+            assert_eq!(router.enforce_memory_limit(), (usage, 0));
+
+            // One byte over evicts one message
+            let router = Router {
+                config: RouterConfig {
+                    max_memory: Some(usage.total() - 1),
+                    ..router.config.clone()
+                },
+                ..router
+            };
+            assert_eq!(router.enforce_memory_limit().1, 1);
+            assert_eq!(history(&router), expected);
+            assert!(router.memory_usage().total() < usage.total());
+        }
+    }
+
+    #[test]
+    fn test_router_invalid_channel() {
+        let router = Router::new();
+
+        assert!(router.subscribe("conn-1", "").is_err());
+        assert!(router.subscribe("conn-1", "$system").is_err());
+    }
+
+    #[test]
+    fn test_router_custom_validator() {
+        use crate::validator::{PrefixValidator, ValidatorChain};
+
+        let validator = ValidatorChain::new()
+            .with(DefaultValidator)
+            .with(PrefixValidator::new(["acme:"]));
+        let router = Router::new().with_validator(Arc::new(validator));
+
+        assert!(router.subscribe("conn-1", "acme:chat").is_ok());
+        assert!(matches!(
+            router.subscribe("conn-1", "globex:chat"),
+            Err(RouterError::InvalidChannel(_))
+        ));
+    }
+
+    #[test]
+    fn test_router_unicode_channels_normalized() {
+        use crate::validator::UnicodeValidator;
+
+        let router = Router::new().with_validator(Arc::new(UnicodeValidator));
+        let composed = "chat:caf\u{00E9}";
+        let decomposed = "chat:cafe\u{0301}";
+
+        let mut rx = router.subscribe("conn-1", composed).unwrap();
+        assert!(matches!(
+            router.subscribe("conn-1", decomposed),
+            Err(RouterError::AlreadySubscribed(_))
+        ));
+        assert_eq!(router.publish_to(decomposed, b"hi".to_vec()), 1);
+        assert_eq!(&*rx.try_recv().unwrap().channel, composed);
+        assert_eq!(router.channel_names(), vec![composed.to_string()]);
     }
 
     #[test]
@@ -388,6 +2654,415 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_router_drop_policy_by_pattern() {
+        let router = Router::with_config(RouterConfig {
+            connection_queue_capacity: 1,
+            channel_rules: vec![ChannelRule::new("chat:*").with_drop_policy(DropPolicy::Disconnect)],
+            ..Default::default()
+        });
+        let handle = router.connect("conn-1");
+        router
+            .subscribe_handle(&handle, "telemetry:cpu", None)
+            .unwrap();
+        router.subscribe_handle(&handle, "chat:room", None).unwrap();
+
+        // Telemetry keeps the default policy and sheds old messages
+        router.publish_to("telemetry:cpu", b"1".to_vec());
+        router.publish_to("telemetry:cpu", b"2".to_vec());
+        assert!(!handle.is_closed());
+        assert_eq!(handle.dropped(), 1);
+
+        // Chat would lose a message, so the connection is closed instead
+        router.publish_to("chat:room", b"hello".to_vec());
+        assert!(handle.is_overflowed());
+    }
+
+    #[test]
+    fn test_router_max_channels() {
+        let router = Router::with_config(RouterConfig {
+            max_channels: 2,
+            ..Default::default()
+        });
+
+        let _rx1 = router.subscribe("conn-1", "channel-1").unwrap();
+        let _rx2 = router.subscribe("conn-1", "channel-2").unwrap();
+        assert!(matches!(
+            router.subscribe("conn-1", "channel-3"),
+            Err(RouterError::MaxChannelsReached)
+        ));
+
+        // Existing channels can still be joined
+        let _rx3 = router.subscribe("conn-2", "channel-1").unwrap();
+        assert_eq!(router.stats().channel_count, 2);
+    }
+
+    #[test]
+    fn test_router_auto_create_disabled() {
+        let router = Router::with_config(RouterConfig {
+            auto_create_channels: false,
+            ..Default::default()
+        });
+
+        assert!(matches!(
+            router.subscribe("conn-1", "news"),
+            Err(RouterError::ChannelNotFound(_))
+        ));
+        assert!(!router.channel_exists("news"));
+        assert!(router.connection_channels("conn-1").is_empty());
+
+        assert!(router.create_channel("news").unwrap());
+        assert!(!router.create_channel("news").unwrap());
+        let mut rx = router.subscribe("conn-1", "news").unwrap();
+        assert_eq!(router.publish_to("news", b"hello".to_vec()), 1);
+        assert!(rx.try_recv().is_ok());
+    }
+
+    #[test]
+    fn test_router_create_on_publish() {
+        let router = Router::new();
+        assert_eq!(router.publish_to("events", b"lost".to_vec()), 0);
+        assert!(!router.channel_exists("events"));
+
+        let router = Router::with_config(RouterConfig {
+            auto_create_channels: false,
+            create_on_publish: true,
+            ..Default::default()
+        });
+        assert_eq!(router.publish_to("events", b"first".to_vec()), 0);
+        assert!(router.channel_exists("events"));
+        assert!(router.subscribe("conn-1", "events").is_ok());
+
+        // Invalid names are never created
+        router.publish_to("$system", b"nope".to_vec());
+        assert!(!router.channel_exists("$system"));
+    }
+
+    #[test]
+    fn test_router_expire_unused_channels() {
+        let config = RouterConfig {
+            create_on_publish: true,
+            channel_rules: vec![ChannelRule::new("history:*").with_history_size(2)],
+            ..Default::default()
+        };
+
+        // Nothing is deleted before the TTL has passed
+        let router = Router::with_config(config.clone());
+        router.publish_to("spam:0", b"x".to_vec());
+        assert_eq!(router.expire_unused_channels(), 0);
+        assert!(router.channel_exists("spam:0"));
+
+        let router = Router::with_config(RouterConfig {
+            unused_channel_ttl: Duration::ZERO,
+            ..config
+        });
+        for i in 0..100 {
+            router.publish_to(&format!("spam:{i}"), b"x".to_vec());
+        }
+        router.publish_group(vec![
+            Message::new("spam:a", b"x".to_vec()),
+            Message::new("history:feed", b"x".to_vec()),
+        ]);
+        let _rx = router.subscribe("conn-1", "spam:0").unwrap();
+        router.create_channel("lobby").unwrap();
+        assert_eq!(router.stats().channel_count, 103);
+
+        // Subscribed, retained history and explicitly created channels stay
+        assert_eq!(router.expire_unused_channels(), 100);
+        assert!(router.channel_exists("spam:0"));
+        assert!(router.channel_exists("history:feed"));
+        assert!(router.channel_exists("lobby"));
+        assert!(!router.channel_exists("spam:1"));
+        assert!(!router.channel_exists("spam:a"));
+        assert_eq!(router.expire_unused_channels(), 0);
+    }
+
+    #[test]
+    fn test_router_channel_full() {
+        let router = Router::with_config(RouterConfig {
+            channel_rules: vec![ChannelRule::new("call:*").with_max_subscribers(2)],
+            ..Default::default()
+        });
+
+        let _rx1 = router.subscribe("conn-1", "call:1").unwrap();
+        let _rx2 = router.subscribe("conn-2", "call:1").unwrap();
+        assert!(matches!(
+            router.subscribe("conn-3", "call:1"),
+            Err(RouterError::ChannelFull(_))
+        ));
+        assert_eq!(router.subscriber_count("call:1"), 2);
+
+        // Channels outside the pattern are unaffected
+        let _rx3 = router.subscribe("conn-3", "chat:1").unwrap();
+    }
+
+    #[test]
+    fn test_router_private_channel_requires_auth() {
+        use crate::auth::HmacAuthorizer;
+
+        let router = Router::new();
+        assert!(matches!(
+            router.subscribe("conn-1", "private:room"),
+            Err(RouterError::Unauthorized(_))
+        ));
+
+        let authorizer = Arc::new(HmacAuthorizer::new("secret"));
+        let router = Router::new().with_authorizer(authorizer.clone());
+        let signature = authorizer.sign("conn-1", "private:room");
+
+        assert!(matches!(
+            router.subscribe_with_auth("conn-1", "private:room", Some("bogus")),
+            Err(RouterError::Unauthorized(_))
+        ));
+        assert!(router
+            .subscribe_with_auth("conn-1", "private:room", Some(&signature))
+            .is_ok());
+
+        // Public channels ignore the signature
+        assert!(router.subscribe("conn-1", "chat:room").is_ok());
+    }
+
+    #[test]
+    fn test_router_private_channel_publish_requires_subscription() {
+        use crate::auth::HmacAuthorizer;
+
+        let authorizer = Arc::new(HmacAuthorizer::new("secret"));
+        let router = Router::new().with_authorizer(authorizer.clone());
+        let signature = authorizer.sign("conn-1", "private:x");
+        let mut rx = router
+            .subscribe_with_auth("conn-1", "private:x", Some(&signature))
+            .unwrap();
+
+        // A connection never authorized for the channel can't inject into it
+        let _intruder = router.connect("conn-2");
+        assert!(matches!(
+            router.publish_from("conn-2", Message::new("private:x", "forged")),
+            Err(RouterError::Unauthorized(_))
+        ));
+        assert!(matches!(
+            router.publish_group_from(
+                "conn-2",
+                vec![
+                    Message::new("chat:room", "fine"),
+                    Message::new("private:x", "forged"),
+                ],
+            ),
+            Err(RouterError::Unauthorized(_))
+        ));
+        assert!(matches!(
+            router.publish_from("conn-2", Message::new("presence:x", "forged")),
+            Err(RouterError::Unauthorized(_))
+        ));
+        assert!(rx.try_recv().is_err());
+
+        // The authorized subscriber may publish, and public channels are open
+        let receipt = router
+            .publish_from("conn-1", Message::new("private:x", "hello"))
+            .unwrap();
+        assert_eq!(receipt.recipients, 1);
+        assert_eq!(&rx.try_recv().unwrap().payload[..], b"hello");
+        assert!(router
+            .publish_from("conn-2", Message::new("chat:room", "hi"))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_router_authorize_with_metadata() {
+        use crate::auth::ChannelAuthorizer;
+        use crate::connection::ConnectionMetadata;
+
+        /// Admits connections opened from one origin.
+        struct OriginAuthorizer;
+
+        impl ChannelAuthorizer for OriginAuthorizer {
+            fn authorize(&self, _: &str, _: &str, _: Option<&str>) -> bool {
+                false
+            }
+
+            fn authorize_with_metadata(
+                &self,
+                _: &str,
+                _: &str,
+                _: Option<&str>,
+                metadata: &ConnectionMetadata,
+            ) -> bool {
+                metadata.origin.as_deref() == Some("https://app.example.com")
+            }
+        }
+
+        let router = Router::new().with_authorizer(Arc::new(OriginAuthorizer));
+        let allowed = router.connect("conn-1");
+        allowed.set_metadata(ConnectionMetadata {
+            origin: Some("https://app.example.com".to_string()),
+            ..ConnectionMetadata::default()
+        });
+        let other = router.connect("conn-2");
+
+        assert!(router
+            .subscribe_handle(&allowed, "private:room", None)
+            .is_ok());
+        assert!(matches!(
+            router.subscribe_handle(&other, "private:room", None),
+            Err(RouterError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn test_router_presence_channel_tracks_membership() {
+        let router = Router::new();
+
+        let handle = router.connect("conn-1");
+        let info = router
+            .subscribe_handle(&handle, "presence:room", None)
+            .unwrap();
+        assert_eq!((info.subscribers, info.presence), (1, 1));
+        router.unsubscribe_all("conn-1");
+
+        let mut rx1 = router.subscribe("conn-1", "presence:room").unwrap();
+        assert_eq!(router.presence_snapshot("presence:room").len(), 1);
+        let join = rx1.try_recv().unwrap();
+        assert_eq!(join.kind, MessageKind::Presence(PresenceAction::Join));
+
+        let _rx2 = router.subscribe("conn-2", "presence:room").unwrap();
+        assert_eq!(
+            rx1.try_recv().unwrap().kind,
+            MessageKind::Presence(PresenceAction::Join)
+        );
+
+        assert!(!router
+            .presence_join(
+                "conn-2",
+                "presence:room",
+                Some(serde_json::json!({"name": "Bob"}))
+            )
+            .unwrap());
+        let update = rx1.try_recv().unwrap();
+        assert_eq!(update.kind, MessageKind::Presence(PresenceAction::Update));
+        let delta: PresenceDelta =
+            serde_json::from_slice(update.presence_delta.as_deref().unwrap()).unwrap();
+        assert_eq!(delta.data, serde_json::json!({"name": "Bob"}));
+
+        // Unchanged data is not broadcast
+        router
+            .presence_join(
+                "conn-2",
+                "presence:room",
+                Some(serde_json::json!({"name": "Bob"})),
+            )
+            .unwrap();
+        assert!(rx1.try_recv().is_err());
+
+        router.unsubscribe("conn-2", "presence:room").unwrap();
+        assert_eq!(
+            rx1.try_recv().unwrap().kind,
+            MessageKind::Presence(PresenceAction::Leave)
+        );
+        assert_eq!(router.presence_snapshot("presence:room").len(), 1);
+
+        // Presence is not tracked on ordinary channels
+        let _rx3 = router.subscribe("conn-1", "chat:room").unwrap();
+        assert!(router.presence_snapshot("chat:room").is_empty());
+        assert!(router.presence_join("conn-1", "chat:room", None).is_err());
+    }
+
+    #[test]
+    fn test_router_remote_presence() {
+        let router = Router::new();
+        let _rx = router.subscribe("conn-1", "presence:room").unwrap();
+        let _rx2 = router.subscribe("conn-1", "chat:room").unwrap();
+
+        let remote = vec![PresenceState::new("conn-9")];
+        assert!(router.set_remote_presence("presence:room", "link:eu", remote.clone()));
+        assert!(!router.set_remote_presence("chat:room", "link:eu", remote.clone()));
+        assert!(!router.set_remote_presence("presence:gone", "link:eu", remote));
+        assert_eq!(router.presence_snapshot("presence:room").len(), 2);
+        assert_eq!(router.local_presence_snapshot("presence:room").len(), 1);
+
+        router.remove_remote_presence("link:eu");
+        assert_eq!(router.presence_snapshot("presence:room").len(), 1);
+    }
+
+    #[test]
+    fn test_router_presence_ttl() {
+        let router = Router::with_config(RouterConfig {
+            channel_rules: vec![
+                ChannelRule::new("presence:room").with_presence_ttl(Duration::from_millis(50))
+            ],
+            ..Default::default()
+        });
+        let mut rx = router.subscribe("conn-1", "presence:room").unwrap();
+        let _rx2 = router.subscribe("conn-2", "presence:room").unwrap();
+        let _rx3 = router.subscribe("conn-3", "presence:other").unwrap();
+        while rx.try_recv().is_ok() {}
+
+        // Only the member that refreshed, and channels without a TTL, remain
+        std::thread::sleep(Duration::from_millis(100));
+        router.touch_presence("conn-1");
+        assert_eq!(router.expire_presence(), 1);
+        let leave = rx.try_recv().unwrap();
+        assert_eq!(leave.kind, MessageKind::Presence(PresenceAction::Leave));
+        let snapshot = router.presence_snapshot("presence:room");
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot[0].connection_id, "conn-1");
+        assert_eq!(router.presence_snapshot("presence:other").len(), 1);
+
+        // Expired members stay subscribed
+        assert_eq!(router.connection_channels("conn-2"), ["presence:room"]);
+    }
+
+    #[test]
+    fn test_router_list_subscribers() {
+        let router = Router::new();
+        for i in 0..5 {
+            let handle = router.connect(&format!("conn-{i}"));
+            handle.set_user_id(if i % 2 == 0 { "alice" } else { "bob" });
+            handle.set_transport("websocket");
+            router.subscribe_handle(&handle, "room:1", None).unwrap();
+        }
+        let alice = router.handle("conn-0").unwrap();
+        router.subscribe_handle(&alice, "room:2", None).unwrap();
+
+        let query = SubscriberQuery {
+            user_id: Some("alice".into()),
+            limit: Some(2),
+            ..Default::default()
+        };
+        let page = router.list_subscribers("room:1", &query).unwrap();
+        let ids: Vec<_> = page
+            .items
+            .iter()
+            .map(|s| s.connection_id.as_str())
+            .collect();
+        assert_eq!(ids, ["conn-0", "conn-2"]);
+        assert_eq!(page.items[0].transport.as_deref(), Some("websocket"));
+        let query = SubscriberQuery {
+            cursor: page.next_cursor,
+            ..query
+        };
+        let page = router.list_subscribers("room:1", &query).unwrap();
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.next_cursor, None);
+        assert!(matches!(
+            router.list_subscribers("room:3", &query),
+            Err(RouterError::ChannelNotFound(_))
+        ));
+
+        // Channels can be narrowed to those a user is in
+        let query = ChannelQuery {
+            user_id: Some("alice".into()),
+            ..Default::default()
+        };
+        let page = router.list_channels(&query).unwrap();
+        assert_eq!(page.items.len(), 2);
+        let query = ChannelQuery {
+            min_subscribers: Some(2),
+            ..Default::default()
+        };
+        let page = router.list_channels(&query).unwrap();
+        assert_eq!(&*page.items[0].channel, "room:1");
+        assert_eq!(page.items.len(), 1);
+    }
+
     #[test]
     fn test_router_unsubscribe_all() {
         let router = Router::new();
@@ -401,6 +3076,182 @@ mod tests {
         assert!(!router.channel_exists("channel-2"));
     }
 
+    #[test]
+    fn test_router_occupancy_observer() {
+        #[derive(Default)]
+        struct Recorder(std::sync::Mutex<Vec<(String, OccupancyChange)>>);
+
+        impl OccupancyObserver for Recorder {
+            fn occupancy_changed(&self, channel: &str, change: OccupancyChange) {
+                self.0.lock().unwrap().push((channel.to_string(), change));
+            }
+        }
+
+        let recorder = Arc::new(Recorder::default());
+        let router = Router::new().with_occupancy_observer(recorder.clone());
+        let _rx1 = router.subscribe("conn-1", "room").unwrap();
+        let _rx2 = router.subscribe("conn-2", "room").unwrap();
+        router.unsubscribe("conn-1", "room").unwrap();
+        router.unsubscribe_all("conn-2");
+        let _rx3 = router.subscribe("conn-3", "room").unwrap();
+
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            vec![
+                ("room".to_string(), OccupancyChange::Occupied),
+                ("room".to_string(), OccupancyChange::Vacated),
+                ("room".to_string(), OccupancyChange::Occupied),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_router_tracer() {
+        #[derive(Default)]
+        struct Recorder(std::sync::Mutex<Vec<String>>);
+
+        impl MessageTracer for Recorder {
+            fn trace(&self, _message: &Message, event: TraceEvent<'_>) {
+                self.0.lock().unwrap().push(format!("{event:?}"));
+            }
+        }
+
+        let recorder = Arc::new(Recorder::default());
+        let router = Router::new().with_tracer(recorder.clone());
+        let alice = router.connect("alice");
+        let bob = router.connect("bob");
+        let carol = router.connect("carol");
+        router.subscribe_handle(&alice, "room", None).unwrap();
+        let options = SubscribeOptions {
+            events: vec!["typing".to_string()],
+            ..SubscribeOptions::default()
+        };
+        router
+            .subscribe_handle_with(&bob, "room", None, options)
+            .unwrap();
+        router.subscribe_handle(&carol, "room", None).unwrap();
+        carol.close_with(CloseReason::new(
+            pulse_protocol::DisconnectReason::Kicked,
+            "Kicked",
+        ));
+
+        // Only traced messages are reported
+        router.publish(Message::new("room", b"untraced".to_vec()));
+        assert!(recorder.0.lock().unwrap().is_empty());
+
+        router.publish(
+            Message::new("room", b"traced".to_vec())
+                .with_event("message")
+                .with_trace(),
+        );
+        let mut events = std::mem::take(&mut *recorder.0.lock().unwrap());
+        events.sort();
+        assert_eq!(
+            events,
+            [
+                r#"Dropped { connection: "carol" }"#,
+                r#"Enqueued { connection: "alice" }"#,
+                r#"Filtered { connection: "bob" }"#,
+                "Routed { subscribers: 3 }",
+            ]
+        );
+
+        router.publish(Message::new("missing", b"traced".to_vec()).with_trace());
+        assert_eq!(*recorder.0.lock().unwrap(), ["ChannelMissing"]);
+    }
+
+    /// Rounds each thread runs in the concurrency tests.
+    const ROUNDS: usize = 10_000;
+
+    #[test]
+    fn test_router_concurrent_auto_delete() {
+        // The last subscriber leaving must not delete the channel out from
+        // under a subscriber joining at the same moment
+        let router = Router::new();
+        let leaving = router.connect("leaving");
+        let joining = router.connect("joining");
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for _ in 0..ROUNDS {
+                    router.subscribe_handle(&leaving, "room", None).unwrap();
+                    router.unsubscribe("leaving", "room").unwrap();
+                    while leaving.try_recv().is_some() {}
+                }
+            });
+            s.spawn(|| {
+                for round in 0..ROUNDS {
+                    router.subscribe_handle(&joining, "room", None).unwrap();
+                    router.publish(Message::new("room", "ping"));
+                    let received = std::iter::from_fn(|| joining.try_recv()).count();
+                    assert!(received > 0, "subscription lost in round {round}");
+                    router.unsubscribe("joining", "room").unwrap();
+                }
+            });
+        });
+        assert!(!router.channel_exists("room"));
+    }
+
+    #[test]
+    fn test_router_concurrent_disconnect() {
+        // Same as above, with the last subscriber disconnecting
+        let router = Router::new();
+        let joining = router.connect("joining");
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for round in 0..ROUNDS {
+                    let id = format!("leaving-{round}");
+                    let leaving = router.connect(&id);
+                    router.subscribe_handle(&leaving, "room", None).unwrap();
+                    router.unsubscribe_all(&id);
+                }
+            });
+            s.spawn(|| {
+                for round in 0..ROUNDS {
+                    router.subscribe_handle(&joining, "room", None).unwrap();
+                    router.publish(Message::new("room", "ping"));
+                    let received = std::iter::from_fn(|| joining.try_recv()).count();
+                    assert!(received > 0, "subscription lost in round {round}");
+                    router.unsubscribe("joining", "room").unwrap();
+                }
+            });
+        });
+        assert!(!router.channel_exists("room"));
+        assert_eq!(router.stats().connection_count, 1);
+    }
+
+    #[test]
+    fn test_router_concurrent_churn() {
+        // A steady subscriber gets every message, in order, while others
+        // come and go
+        let router = Router::new();
+        let steady = router.connect_with_capacity("steady", ROUNDS);
+        router.subscribe_handle(&steady, "room", None).unwrap();
+        std::thread::scope(|s| {
+            for n in 0..2 {
+                let router = &router;
+                s.spawn(move || {
+                    let id = format!("churn-{n}");
+                    let handle = router.connect(&id);
+                    for _ in 0..ROUNDS {
+                        router.subscribe_handle(&handle, "room", None).unwrap();
+                        router.unsubscribe(&id, "room").unwrap();
+                        while handle.try_recv().is_some() {}
+                    }
+                });
+            }
+            s.spawn(|| {
+                for n in 0..ROUNDS {
+                    router.publish(Message::new("room", n.to_string()));
+                }
+            });
+        });
+
+        let received: Vec<u64> = std::iter::from_fn(|| steady.try_recv())
+            .map(|message| message.seq)
+            .collect();
+        assert_eq!(received, (1..=ROUNDS as u64).collect::<Vec<_>>());
+    }
+
     #[test]
     fn test_router_stats() {
         let router = Router::new();
@@ -415,3 +3266,168 @@ mod tests {
         assert_eq!(stats.total_subscriptions, 3);
     }
 }
+
+/// Model checks of the channel map's locking, run with
+/// `RUSTFLAGS="--cfg loom" cargo test -p tenvis-pulse-core --release --lib router::model`.
+///
+/// Loom can't see inside `DashMap`, so these replay the router's protocol on
+/// a single loom lock standing in for the shard holding the channel:
+/// subscribing inserts under the write lock (`channel_entry`), unsubscribing
+/// removes under it and then deletes the channel only if a second write lock
+/// still finds it empty (`remove_if_empty`), and publishing fans out under
+/// the read lock. Loom then tries every interleaving, which the stress tests
+/// above only sample.
+#[cfg(all(test, loom))]
+mod model {
+    use loom::sync::atomic::{AtomicU64, Ordering};
+    use loom::sync::{Arc, RwLock};
+    use loom::thread;
+    use std::collections::{BTreeSet, HashMap};
+
+    #[derive(Default)]
+    struct Channel {
+        subscribers: BTreeSet<&'static str>,
+        seq: AtomicU64,
+    }
+
+    #[derive(Default)]
+    struct Channels(RwLock<HashMap<&'static str, Channel>>);
+
+    impl Channels {
+        fn subscribe(&self, conn: &'static str, channel: &'static str) {
+            let mut channels = self.0.write().unwrap();
+            channels
+                .entry(channel)
+                .or_default()
+                .subscribers
+                .insert(conn);
+        }
+
+        fn unsubscribe(&self, conn: &'static str, channel: &'static str) {
+            let empty = {
+                let mut channels = self.0.write().unwrap();
+                let Some(entry) = channels.get_mut(channel) else {
+                    return;
+                };
+                entry.subscribers.remove(conn);
+                entry.subscribers.is_empty()
+            };
+            if empty {
+                let mut channels = self.0.write().unwrap();
+                if channels
+                    .get(channel)
+                    .is_some_and(|c| c.subscribers.is_empty())
+                {
+                    channels.remove(channel);
+                }
+            }
+        }
+
+        /// Returns the recipients and the sequence number they were sent.
+        fn publish(&self, channel: &'static str) -> (Vec<&'static str>, u64) {
+            let channels = self.0.read().unwrap();
+            match channels.get(channel) {
+                Some(entry) => {
+                    let seq = entry.seq.fetch_add(1, Ordering::Relaxed) + 1;
+                    (entry.subscribers.iter().copied().collect(), seq)
+                }
+                None => (Vec::new(), 0),
+            }
+        }
+
+        fn subscribers(&self, channel: &'static str) -> Option<Vec<&'static str>> {
+            let channels = self.0.read().unwrap();
+            channels
+                .get(channel)
+                .map(|c| c.subscribers.iter().copied().collect())
+        }
+    }
+
+    #[test]
+    fn model_auto_delete_spares_joining_subscriber() {
+        loom::model(|| {
+            let channels = Arc::new(Channels::default());
+            channels.subscribe("leaving", "room");
+
+            let leaving = {
+                let channels = channels.clone();
+                thread::spawn(move || channels.unsubscribe("leaving", "room"))
+            };
+            channels.subscribe("joining", "room");
+            let (recipients, _) = channels.publish("room");
+            assert!(recipients.contains(&"joining"));
+            leaving.join().unwrap();
+
+            assert_eq!(channels.subscribers("room"), Some(vec!["joining"]));
+        });
+    }
+
+    #[test]
+    fn model_disconnect_spares_joining_subscriber() {
+        loom::model(|| {
+            let channels = Arc::new(Channels::default());
+            channels.subscribe("leaving", "lobby");
+            channels.subscribe("leaving", "room");
+
+            // Disconnecting leaves every channel of the connection in turn
+            let leaving = {
+                let channels = channels.clone();
+                thread::spawn(move || {
+                    channels.unsubscribe("leaving", "lobby");
+                    channels.unsubscribe("leaving", "room");
+                })
+            };
+            channels.subscribe("joining", "room");
+            let (recipients, _) = channels.publish("room");
+            assert!(recipients.contains(&"joining"));
+            leaving.join().unwrap();
+
+            assert_eq!(channels.subscribers("lobby"), None);
+            assert_eq!(channels.subscribers("room"), Some(vec!["joining"]));
+        });
+    }
+
+    #[test]
+    fn model_churn_keeps_steady_subscriber() {
+        loom::model(|| {
+            let channels = Arc::new(Channels::default());
+            channels.subscribe("steady", "room");
+
+            let churn = {
+                let channels = channels.clone();
+                thread::spawn(move || {
+                    channels.subscribe("churn", "room");
+                    channels.unsubscribe("churn", "room");
+                })
+            };
+            let mut seqs = Vec::new();
+            for _ in 0..2 {
+                let (recipients, seq) = channels.publish("room");
+                assert!(recipients.contains(&"steady"));
+                seqs.push(seq);
+            }
+            churn.join().unwrap();
+
+            assert_eq!(seqs, [1, 2]);
+            assert_eq!(channels.subscribers("room"), Some(vec!["steady"]));
+        });
+    }
+
+    #[test]
+    fn model_last_unsubscribe_deletes_channel() {
+        loom::model(|| {
+            let channels = Arc::new(Channels::default());
+            channels.subscribe("a", "room");
+            channels.subscribe("b", "room");
+
+            let a = {
+                let channels = channels.clone();
+                thread::spawn(move || channels.unsubscribe("a", "room"))
+            };
+            channels.unsubscribe("b", "room");
+            a.join().unwrap();
+
+            assert_eq!(channels.subscribers("room"), None);
+        });
+    }
+}
@@ -0,0 +1,47 @@
+//! Message tracing for Pulse.
+//!
+//! A message with [`Message::trace`](crate::Message::trace) set is followed
+//! through the router: a [`MessageTracer`] is told whether its channel was
+//! found and what became of it at each subscriber's queue, so operators can
+//! tell why a client did not receive it.
+
+use crate::message::Message;
+
+/// A step in routing a traced message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceEvent<'a> {
+    /// The message reached its channel, which has this many subscribers.
+    Routed {
+        /// Subscribers of the channel, including ones filtering it out.
+        subscribers: usize,
+    },
+    /// The channel does not exist, so only pattern subscribers can receive
+    /// the message.
+    ChannelMissing,
+    /// A subscriber's queue took the message.
+    Enqueued {
+        /// The subscriber's connection ID.
+        connection: &'a str,
+    },
+    /// A subscriber's options (event filter or `no_echo`) left the message
+    /// out.
+    Filtered {
+        /// The subscriber's connection ID.
+        connection: &'a str,
+    },
+    /// A subscriber's queue was full or closed and the message was not
+    /// queued.
+    Dropped {
+        /// The subscriber's connection ID.
+        connection: &'a str,
+    },
+}
+
+/// Told about each step in routing a traced message.
+///
+/// The router calls the tracer while holding the channel's lock, so it must
+/// return quickly, e.g. by sending the event to a task.
+pub trait MessageTracer: Send + Sync {
+    /// Handle a step in routing a traced message.
+    fn trace(&self, message: &Message, event: TraceEvent<'_>);
+}
@@ -0,0 +1,278 @@
+//! Channel name validation for Pulse.
+//!
+//! Deployments have different naming conventions, so the router delegates
+//! name checks to a [`ChannelNameValidator`]. The default keeps the built-in
+//! rules; regex policies and tenant prefixes can be layered on top.
+
+use crate::channel::{validate_channel_name, MAX_CHANNEL_NAME_LENGTH};
+use regex::Regex;
+use std::borrow::Cow;
+use std::sync::Arc;
+use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
+use unicode_security::{RestrictionLevel, RestrictionLevelDetection};
+
+/// Validates channel names before the router creates or joins a channel.
+pub trait ChannelNameValidator: Send + Sync {
+    /// Check a channel name.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error message if the channel name is not allowed.
+    fn validate(&self, name: &str) -> Result<(), &'static str>;
+
+    /// Normalize a channel name before validation and lookup.
+    ///
+    /// Names that normalize to the same string refer to the same channel.
+    fn normalize<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        Cow::Borrowed(name)
+    }
+}
+
+impl<F> ChannelNameValidator for F
+where
+    F: Fn(&str) -> Result<(), &'static str> + Send + Sync,
+{
+    fn validate(&self, name: &str) -> Result<(), &'static str> {
+        self(name)
+    }
+}
+
+/// The built-in rules: non-empty, bounded length, printable ASCII, and no
+/// reserved `$` prefix.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultValidator;
+
+impl ChannelNameValidator for DefaultValidator {
+    fn validate(&self, name: &str) -> Result<(), &'static str> {
+        validate_channel_name(name)
+    }
+}
+
+/// Allows UTF-8 channel names such as emoji or CJK room names.
+///
+/// Names are normalized to NFC so that visually identical spellings map to
+/// the same channel. Control characters, invisible formatting characters
+/// (bidi overrides, zero-width spaces) and letters mixing confusable scripts
+/// (e.g. Latin with Cyrillic) are rejected, following the "moderately
+/// restrictive" level of Unicode TR39.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UnicodeValidator;
+
+impl UnicodeValidator {
+    /// Check for invisible characters that can disguise a name.
+    ///
+    /// The zero-width joiner is allowed since emoji sequences rely on it.
+    fn is_invisible(c: char) -> bool {
+        matches!(
+            c,
+            '\u{00AD}'
+                | '\u{200B}'
+                | '\u{200C}'
+                | '\u{200E}'
+                | '\u{200F}'
+                | '\u{202A}'..='\u{202E}'
+                | '\u{2060}'..='\u{2064}'
+                | '\u{2066}'..='\u{2069}'
+                | '\u{FEFF}'
+        )
+    }
+}
+
+impl ChannelNameValidator for UnicodeValidator {
+    fn validate(&self, name: &str) -> Result<(), &'static str> {
+        if name.is_empty() {
+            return Err("Channel name cannot be empty");
+        }
+        if name.len() > MAX_CHANNEL_NAME_LENGTH {
+            return Err("Channel name too long");
+        }
+        if name.starts_with('$') {
+            return Err("Channel names starting with '$' are reserved");
+        }
+        if name.chars().any(char::is_control) {
+            return Err("Channel name contains invalid characters");
+        }
+        if name.chars().any(Self::is_invisible) {
+            return Err("Channel name contains invisible characters");
+        }
+
+        let letters: String = name.chars().filter(|c| c.is_alphanumeric()).collect();
+        if !letters.check_restriction_level(RestrictionLevel::ModeratelyRestrictive) {
+            return Err("Channel name mixes confusable scripts");
+        }
+        Ok(())
+    }
+
+    fn normalize<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        match is_nfc_quick(name.chars()) {
+            IsNormalized::Yes => Cow::Borrowed(name),
+            _ => Cow::Owned(name.nfc().collect()),
+        }
+    }
+}
+
+/// Requires channel names to match a regular expression.
+#[derive(Debug, Clone)]
+pub struct RegexValidator {
+    regex: Regex,
+}
+
+impl RegexValidator {
+    /// Create a validator from a regular expression.
+    ///
+    /// The expression is not anchored implicitly; use `^...$` to match the
+    /// whole name.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the expression is invalid.
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        Ok(Self {
+            regex: Regex::new(pattern)?,
+        })
+    }
+}
+
+impl ChannelNameValidator for RegexValidator {
+    fn validate(&self, name: &str) -> Result<(), &'static str> {
+        if self.regex.is_match(name) {
+            Ok(())
+        } else {
+            Err("Channel name does not match the naming policy")
+        }
+    }
+}
+
+/// Requires channel names to start with one of a set of prefixes, e.g. a
+/// tenant namespace.
+#[derive(Debug, Clone)]
+pub struct PrefixValidator {
+    prefixes: Vec<String>,
+}
+
+impl PrefixValidator {
+    /// Create a validator allowing the given prefixes.
+    #[must_use]
+    pub fn new(prefixes: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            prefixes: prefixes.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl ChannelNameValidator for PrefixValidator {
+    fn validate(&self, name: &str) -> Result<(), &'static str> {
+        if self.prefixes.iter().any(|p| name.starts_with(p.as_str())) {
+            Ok(())
+        } else {
+            Err("Channel name does not start with an allowed prefix")
+        }
+    }
+}
+
+/// Runs several validators in order; a name must pass all of them.
+#[derive(Default, Clone)]
+pub struct ValidatorChain {
+    validators: Vec<Arc<dyn ChannelNameValidator>>,
+}
+
+impl ValidatorChain {
+    /// Create an empty chain.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a validator to the chain.
+    #[must_use]
+    pub fn with(mut self, validator: impl ChannelNameValidator + 'static) -> Self {
+        self.validators.push(Arc::new(validator));
+        self
+    }
+}
+
+impl ChannelNameValidator for ValidatorChain {
+    fn validate(&self, name: &str) -> Result<(), &'static str> {
+        self.validators.iter().try_for_each(|v| v.validate(name))
+    }
+
+    fn normalize<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        self.validators
+            .iter()
+            .fold(Cow::Borrowed(name), |name, v| match name {
+                Cow::Borrowed(name) => v.normalize(name),
+                Cow::Owned(name) => Cow::Owned(v.normalize(&name).into_owned()),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unicode_validator() {
+        let validator = UnicodeValidator;
+        assert!(validator.validate("chat:lobby").is_ok());
+        assert!(validator.validate("chat:日本語").is_ok());
+        assert!(validator.validate("chat:café").is_ok());
+        assert!(validator.validate("room:🎉").is_ok());
+        assert!(validator.validate("room:👨\u{200D}👩\u{200D}👧").is_ok());
+
+        assert!(validator.validate("").is_err());
+        assert!(validator.validate("$system").is_err());
+        assert!(validator.validate("chat:\u{0007}").is_err());
+        assert!(validator.validate("chat:\u{202E}moc").is_err());
+        assert!(validator.validate("chat:lo\u{200B}bby").is_err());
+        // Latin "p" and "l" with Cyrillic "\u{0430}"
+        assert!(validator.validate("chat:p\u{0430}yp\u{0430}l").is_err());
+    }
+
+    #[test]
+    fn test_unicode_normalization() {
+        let validator = UnicodeValidator;
+        let decomposed = "chat:cafe\u{0301}";
+        assert_eq!(validator.normalize(decomposed), "chat:café");
+        assert!(matches!(validator.normalize("chat:café"), Cow::Borrowed(_)));
+
+        let chain = ValidatorChain::new().with(UnicodeValidator);
+        assert_eq!(chain.normalize(decomposed), "chat:café");
+    }
+
+    #[test]
+    fn test_regex_validator() {
+        let validator = RegexValidator::new("^[a-z0-9:-]+$").unwrap();
+        assert!(validator.validate("chat:room-1").is_ok());
+        assert!(validator.validate("Chat:Room").is_err());
+        assert!(RegexValidator::new("[").is_err());
+    }
+
+    #[test]
+    fn test_prefix_validator() {
+        let validator = PrefixValidator::new(["acme:", "globex:"]);
+        assert!(validator.validate("acme:chat").is_ok());
+        assert!(validator.validate("initech:chat").is_err());
+    }
+
+    #[test]
+    fn test_validator_chain() {
+        let chain = ValidatorChain::new()
+            .with(DefaultValidator)
+            .with(PrefixValidator::new(["acme:"]))
+            .with(|name: &str| {
+                if name.ends_with(":admin") {
+                    Err("Admin channels are not allowed")
+                } else {
+                    Ok(())
+                }
+            });
+
+        assert!(chain.validate("acme:chat").is_ok());
+        assert!(chain.validate("$acme:chat").is_err());
+        assert!(chain.validate("other:chat").is_err());
+        assert_eq!(
+            chain.validate("acme:admin"),
+            Err("Admin channels are not allowed")
+        );
+    }
+}
@@ -0,0 +1,106 @@
+//! Capability negotiation for Pulse.
+//!
+//! Clients list the optional features they understand in their Connect frame
+//! and the server answers with the subset it will use. Features outside the
+//! negotiated set are never used on the connection, so clients can
+//! feature-detect without sniffing protocol versions.
+
+use serde::{Deserialize, Serialize};
+
+/// A set of optional protocol features, encoded as a bitmap.
+///
+/// Unknown bits are preserved when decoding and dropped by negotiation, so
+/// peers can advertise features the other side does not know about yet.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    /// No optional features.
+    pub const NONE: Self = Self(0);
+    /// Several frames may share one transport message.
+    pub const BATCHING: Self = Self(1 << 0);
+    /// Transport-level compression (e.g. permessage-deflate).
+    pub const COMPRESSION: Self = Self(1 << 1);
+    /// Frames may be JSON-encoded text instead of MessagePack.
+    pub const JSON: Self = Self(1 << 2);
+    /// Acknowledged message delivery.
+    pub const ACK_QOS: Self = Self(1 << 3);
+    /// Presence changes are sent as deltas rather than full state.
+    pub const PRESENCE_DIFF: Self = Self(1 << 4);
+
+    /// Create a set from its raw bitmap.
+    #[must_use]
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    /// Get the raw bitmap.
+    #[must_use]
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Check if the set is empty.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Check if every feature in `other` is in this set.
+    #[must_use]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Get the features in both sets.
+    #[must_use]
+    pub const fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    /// Get the features in either set.
+    #[must_use]
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl std::ops::BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        self.union(rhs)
+    }
+}
+
+impl std::ops::BitAnd for Capabilities {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        self.intersection(rhs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capability_sets() {
+        let client = Capabilities::BATCHING | Capabilities::JSON;
+        assert!(client.contains(Capabilities::BATCHING));
+        assert!(!client.contains(Capabilities::COMPRESSION));
+        assert!(Capabilities::NONE.is_empty());
+
+        let negotiated = client & (Capabilities::BATCHING | Capabilities::PRESENCE_DIFF);
+        assert_eq!(negotiated, Capabilities::BATCHING);
+    }
+
+    #[test]
+    fn test_unknown_bits_dropped_by_negotiation() {
+        let client = Capabilities::from_bits(Capabilities::BATCHING.bits() | 1 << 31);
+        assert_eq!(client.bits() >> 31, 1);
+        assert_eq!(client & Capabilities::BATCHING, Capabilities::BATCHING);
+    }
+}
@@ -0,0 +1,141 @@
+//! Traffic capture files.
+//!
+//! A capture is a compact log of published frames and when they were
+//! published, written by the server's `[capture]` recorder and read back by
+//! `pulse-cli replay`. The file starts with [`MAGIC`] and a version byte,
+//! followed by records of:
+//! - 8 bytes: Big-endian timestamp, in milliseconds since the Unix epoch
+//! - N bytes: The frame as encoded by [`codec::encode`](crate::codec::encode)
+
+use bytes::{Buf, BufMut, BytesMut};
+
+use crate::codec::{self, ProtocolError, LENGTH_PREFIX_SIZE, MAX_FRAME_SIZE};
+use crate::frames::Frame;
+
+/// Bytes every capture file starts with.
+pub const MAGIC: &[u8; 8] = b"PULSECAP";
+
+/// Version of the record format, written after [`MAGIC`].
+pub const VERSION: u8 = 1;
+
+/// Size of the file header.
+pub const HEADER_SIZE: usize = MAGIC.len() + 1;
+
+/// Timestamp size in bytes.
+const TIMESTAMP_SIZE: usize = 8;
+
+/// A captured frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// When the frame was captured, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The captured frame.
+    pub frame: Frame,
+}
+
+/// Write the file header.
+pub fn encode_header(buf: &mut BytesMut) {
+    buf.put_slice(MAGIC);
+    buf.put_u8(VERSION);
+}
+
+/// Check a file header.
+///
+/// # Errors
+///
+/// Returns an error if the data is not a capture file or has an unsupported
+/// version.
+pub fn check_header(data: &[u8]) -> Result<(), ProtocolError> {
+    if data.len() < HEADER_SIZE || &data[..MAGIC.len()] != MAGIC {
+        return Err(ProtocolError::Invalid("not a capture file".to_string()));
+    }
+    match data[MAGIC.len()] {
+        VERSION => Ok(()),
+        version => Err(ProtocolError::Invalid(format!(
+            "unsupported capture version {version}"
+        ))),
+    }
+}
+
+/// Encode a record into a buffer.
+///
+/// # Errors
+///
+/// Returns an error if the frame is too large or encoding fails.
+pub fn encode_record(
+    timestamp: u64,
+    frame: &Frame,
+    buf: &mut BytesMut,
+) -> Result<(), ProtocolError> {
+    let start = buf.len();
+    buf.put_u64(timestamp);
+    let result = codec::encode_into(frame, buf);
+    if result.is_err() {
+        buf.truncate(start);
+    }
+    result
+}
+
+/// Try to decode a record from a buffer, advancing it if successful.
+///
+/// Returns `Ok(None)` if more data is needed.
+///
+/// # Errors
+///
+/// Returns an error if the frame is too large or invalid.
+pub fn decode_record(buf: &mut BytesMut) -> Result<Option<Record>, ProtocolError> {
+    if buf.len() < TIMESTAMP_SIZE + LENGTH_PREFIX_SIZE {
+        return Ok(None);
+    }
+    let at = TIMESTAMP_SIZE;
+    let length = u32::from_be_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]]) as usize;
+    if length > MAX_FRAME_SIZE {
+        return Err(ProtocolError::FrameTooLarge(length));
+    }
+    if buf.len() < TIMESTAMP_SIZE + LENGTH_PREFIX_SIZE + length {
+        return Ok(None);
+    }
+
+    let timestamp = buf.get_u64();
+    let frame = codec::decode_from(buf)?.expect("the whole frame is buffered");
+    Ok(Some(Record { timestamp, frame }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_roundtrip() {
+        let mut buf = BytesMut::new();
+        encode_header(&mut buf);
+        check_header(&buf).unwrap();
+
+        let first = Frame::publish("chat:lobby", b"hi".to_vec());
+        let second = Frame::publish("chat:ops", b"deploy".to_vec());
+        encode_record(1_700_000_000_000, &first, &mut buf).unwrap();
+        encode_record(1_700_000_000_250, &second, &mut buf).unwrap();
+
+        let mut records = buf.split_off(HEADER_SIZE);
+        // A partial record waits for more data
+        let mut partial = BytesMut::from(&records[..10]);
+        assert_eq!(decode_record(&mut partial).unwrap(), None);
+        assert_eq!(partial.len(), 10);
+
+        let record = decode_record(&mut records).unwrap().unwrap();
+        assert_eq!(record.timestamp, 1_700_000_000_000);
+        assert_eq!(record.frame, first);
+        let record = decode_record(&mut records).unwrap().unwrap();
+        assert_eq!(record.timestamp, 1_700_000_000_250);
+        assert_eq!(record.frame, second);
+        assert_eq!(decode_record(&mut records).unwrap(), None);
+    }
+
+    #[test]
+    fn test_capture_header() {
+        assert!(check_header(b"PULSECAP").is_err());
+        assert!(check_header(b"NOTPULSE\x01").is_err());
+        assert!(check_header(b"PULSECAP\x02").is_err());
+        assert!(check_header(b"PULSECAP\x01").is_ok());
+    }
+}
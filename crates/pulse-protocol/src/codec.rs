@@ -6,6 +6,7 @@ use bytes::{Buf, BufMut, Bytes, BytesMut};
 use thiserror::Error;
 
 use crate::frames::Frame;
+use crate::pool;
 
 /// Maximum frame size (16 MiB).
 pub const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
@@ -47,17 +48,11 @@ pub enum ProtocolError {
 ///
 /// Returns an error if the frame is too large or encoding fails.
 pub fn encode(frame: &Frame) -> Result<Bytes, ProtocolError> {
-    let payload = rmp_serde::to_vec_named(frame)?;
-
-    if payload.len() > MAX_FRAME_SIZE {
-        return Err(ProtocolError::FrameTooLarge(payload.len()));
-    }
-
-    let mut buf = BytesMut::with_capacity(LENGTH_PREFIX_SIZE + payload.len());
-    buf.put_u32(payload.len() as u32);
-    buf.extend_from_slice(&payload);
-
-    Ok(buf.freeze())
+    let pool = pool::global();
+    let mut buf = pool.acquire();
+    let result = encode_into(frame, &mut buf).map(|()| buf.split().freeze());
+    pool.release(buf);
+    result
 }
 
 /// Encode a frame into an existing buffer.
@@ -66,15 +61,20 @@ pub fn encode(frame: &Frame) -> Result<Bytes, ProtocolError> {
 ///
 /// Returns an error if the frame is too large or encoding fails.
 pub fn encode_into(frame: &Frame, buf: &mut BytesMut) -> Result<(), ProtocolError> {
-    let payload = rmp_serde::to_vec_named(frame)?;
-
-    if payload.len() > MAX_FRAME_SIZE {
-        return Err(ProtocolError::FrameTooLarge(payload.len()));
+    // Serialize in place behind a placeholder length prefix
+    let start = buf.len();
+    buf.put_u32(0);
+    if let Err(e) = rmp_serde::encode::write_named(&mut (&mut *buf).writer(), frame) {
+        buf.truncate(start);
+        return Err(e.into());
     }
 
-    buf.reserve(LENGTH_PREFIX_SIZE + payload.len());
-    buf.put_u32(payload.len() as u32);
-    buf.extend_from_slice(&payload);
+    let length = buf.len() - start - LENGTH_PREFIX_SIZE;
+    if length > MAX_FRAME_SIZE {
+        buf.truncate(start);
+        return Err(ProtocolError::FrameTooLarge(length));
+    }
+    buf[start..start + LENGTH_PREFIX_SIZE].copy_from_slice(&(length as u32).to_be_bytes());
 
     Ok(())
 }
@@ -113,13 +113,29 @@ pub fn decode(data: &[u8]) -> Result<Frame, ProtocolError> {
 ///
 /// Returns an error if the frame is too large or invalid.
 pub fn decode_from(buf: &mut BytesMut) -> Result<Option<Frame>, ProtocolError> {
+    decode_from_limited(buf, MAX_FRAME_SIZE)
+}
+
+/// Try to decode a frame from a buffer, rejecting frames larger than
+/// `max_frame_size` as soon as their length prefix arrives.
+///
+/// The limit is capped at [`MAX_FRAME_SIZE`]. A frame that is too large is
+/// left in the buffer; the stream cannot be resynchronized after it.
+///
+/// # Errors
+///
+/// Returns an error if the frame is too large or invalid.
+pub fn decode_from_limited(
+    buf: &mut BytesMut,
+    max_frame_size: usize,
+) -> Result<Option<Frame>, ProtocolError> {
     if buf.len() < LENGTH_PREFIX_SIZE {
         return Ok(None);
     }
 
     let length = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
 
-    if length > MAX_FRAME_SIZE {
+    if length > max_frame_size.min(MAX_FRAME_SIZE) {
         return Err(ProtocolError::FrameTooLarge(length));
     }
 
@@ -136,9 +152,10 @@ pub fn decode_from(buf: &mut BytesMut) -> Result<Option<Frame>, ProtocolError> {
 }
 
 /// Codec for streaming frame encoding/decoding.
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct FrameCodec {
-    // Reserved for future state (e.g., compression context)
+    /// Largest frame accepted when decoding.
+    max_frame_size: usize,
 }
 
 impl FrameCodec {
@@ -148,6 +165,19 @@ impl FrameCodec {
         Self::default()
     }
 
+    /// Limit the size of decoded frames (capped at [`MAX_FRAME_SIZE`]).
+    #[must_use]
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size.min(MAX_FRAME_SIZE);
+        self
+    }
+
+    /// Get the largest frame accepted when decoding.
+    #[must_use]
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
     /// Encode a frame to bytes.
     ///
     /// # Errors
@@ -163,6 +193,12 @@ impl FrameCodec {
     ///
     /// Returns an error if decoding fails.
     pub fn decode(&self, data: &[u8]) -> Result<Frame, ProtocolError> {
+        if data.len() >= LENGTH_PREFIX_SIZE {
+            let length = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+            if length > self.max_frame_size {
+                return Err(ProtocolError::FrameTooLarge(length));
+            }
+        }
         decode(data)
     }
 
@@ -172,24 +208,103 @@ impl FrameCodec {
     ///
     /// Returns an error if the frame is invalid.
     pub fn decode_from(&self, buf: &mut BytesMut) -> Result<Option<Frame>, ProtocolError> {
-        decode_from(buf)
+        decode_from_limited(buf, self.max_frame_size)
+    }
+}
+
+impl Default for FrameCodec {
+    fn default() -> Self {
+        Self {
+            max_frame_size: MAX_FRAME_SIZE,
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::capabilities::Capabilities;
+    use crate::frames::{DisconnectReason, GroupMessage, Priority, SubscribeOptions};
 
     #[test]
     fn test_encode_decode_roundtrip() {
         let frames = vec![
             Frame::subscribe(1, "test-channel"),
+            Frame::subscribe_with_auth(2, "private:room", "abcdef"),
+            Frame::subscribe_with_options(
+                3,
+                "ticker",
+                SubscribeOptions {
+                    last: Some(10),
+                    events: vec!["price".to_string()],
+                    no_echo: true,
+                    ..Default::default()
+                },
+            ),
+            Frame::subscribe_ok(3, "ticker", 42, 7, 0, 10),
             Frame::publish("chat:room", b"Hello, world!".to_vec()),
+            Frame::Publish {
+                id: Some(7),
+                channel: "control".to_string(),
+                event: Some("kick".to_string()),
+                seq: Some(12),
+                message_id: Some(9_876_543_210),
+                timestamp: Some(1_700_000_000_000),
+                priority: Priority::High,
+                coalesce_key: Some("kick:user123".to_string()),
+                group_id: Some(99),
+                encrypted: false,
+                key_id: None,
+                signer: Some("billing".to_string()),
+                signature: Some("9f86d081".to_string()),
+                origin: Some("eu-west".to_string()),
+                trace: true,
+                payload: b"{}".to_vec(),
+            },
+            Frame::publish_encrypted("room:42", "2026-10", vec![0x8f, 0x00, 0xc3]),
+            Frame::PublishGroup {
+                id: Some(8),
+                messages: vec![
+                    GroupMessage::new("board:1", b"move".to_vec()),
+                    GroupMessage {
+                        event: Some("moved".to_string()),
+                        ..GroupMessage::new("spectators:1", b"e4".to_vec())
+                    },
+                ],
+                priority: Priority::Normal,
+            },
             Frame::ack(42),
+            Frame::publish_ok(43, 9_876_543_210, 7, 3),
+            Frame::ReplayEnd {
+                channel: "ticker".to_string(),
+                seq: 42,
+                replayed: 5,
+                truncated: true,
+            },
             Frame::error(1, 1001, "Invalid frame"),
             Frame::ping(),
+            Frame::Disconnect {
+                reason: DisconnectReason::Shutdown,
+                message: "Restarting".to_string(),
+                reconnect_after: Some(1000),
+                alternate_host: Some("pulse-2.example.com".to_string()),
+            },
             Frame::connect(1, Some("token123".to_string())),
             Frame::connected("conn-123", 1, 30000),
+            Frame::Connected {
+                connection_id: "conn-124".to_string(),
+                version: 1,
+                heartbeat: 30000,
+                capabilities: Capabilities::BATCHING,
+                affinity: Some("node-3".to_string()),
+            },
+            Frame::Connect {
+                version: 1,
+                token: None,
+                capabilities: Capabilities::BATCHING | Capabilities::PRESENCE_DIFF,
+                heartbeat: Some(120_000),
+                user_id: Some("user-42".to_string()),
+            },
         ];
 
         for frame in frames {
@@ -222,6 +337,31 @@ mod tests {
             Err(ProtocolError::FrameTooLarge(_)) => {}
             other => panic!("Expected FrameTooLarge error, got {:?}", other),
         }
+
+        // A failed encode leaves earlier frames in the buffer intact
+        let mut buf = BytesMut::new();
+        encode_into(&Frame::ping(), &mut buf).unwrap();
+        let before = buf.clone();
+        assert!(encode_into(&frame, &mut buf).is_err());
+        assert_eq!(buf, before);
+    }
+
+    #[test]
+    fn test_decode_limited() {
+        let frame = Frame::publish("test", vec![0u8; 1024]);
+        let mut buf = BytesMut::new();
+        encode_into(&frame, &mut buf).unwrap();
+
+        // Rejected from the length prefix alone
+        let mut partial = BytesMut::from(&buf[..LENGTH_PREFIX_SIZE]);
+        assert!(matches!(
+            decode_from_limited(&mut partial, 512),
+            Err(ProtocolError::FrameTooLarge(_))
+        ));
+
+        let codec = FrameCodec::new().with_max_frame_size(512);
+        assert!(codec.decode(&buf).is_err());
+        assert_eq!(decode_from_limited(&mut buf, 2048).unwrap(), Some(frame));
     }
 
     #[test]
@@ -241,3 +381,352 @@ mod tests {
         assert!(buf.is_empty());
     }
 }
+
+#[cfg(test)]
+mod proptests {
+    //! Any frame survives encoding, and a stream of frames decodes the same
+    //! however the transport splits it.
+
+    use super::*;
+    use crate::capabilities::Capabilities;
+    use crate::frames::{
+        DisconnectReason, GroupMessage, PresenceAction, Priority, SubscribeOptions,
+    };
+    use proptest::collection::{btree_map, vec};
+    use proptest::option;
+    use proptest::prelude::*;
+    use proptest::sample::{select, Index};
+    use serde_json::Value;
+
+    /// IDs, sequence numbers and timestamps, favoring the edges.
+    fn id() -> impl Strategy<Value = u64> {
+        prop_oneof![
+            Just(0),
+            Just(u64::from(u32::MAX) + 1),
+            Just(u64::MAX),
+            any::<u64>(),
+        ]
+    }
+
+    fn count() -> impl Strategy<Value = u32> {
+        prop_oneof![Just(0), Just(u32::MAX), any::<u32>()]
+    }
+
+    /// Channel, event and other names: conventional, empty, arbitrary
+    /// Unicode and 256 characters long.
+    fn name() -> impl Strategy<Value = String> {
+        prop_oneof![
+            "[a-z]{1,8}(:[a-z0-9_-]{1,8}){0,3}",
+            Just(String::new()),
+            any::<String>(),
+            "\\PC{256}",
+        ]
+    }
+
+    /// Payloads, mostly small with the occasional multi-megabyte one.
+    fn payload() -> impl Strategy<Value = Vec<u8>> {
+        prop_oneof![
+            8 => vec(any::<u8>(), 0..256),
+            1 => (0..4 * 1024 * 1024usize, any::<u8>()).prop_map(|(len, byte)| vec![byte; len]),
+        ]
+    }
+
+    /// Presence metadata. Not null at the top level: `Some(null)` and
+    /// `None` are the same on the wire.
+    fn data() -> BoxedStrategy<Value> {
+        let leaf = prop_oneof![
+            any::<bool>().prop_map(Value::from),
+            any::<i64>().prop_map(Value::from),
+            any::<u64>().prop_map(Value::from),
+            any::<f64>()
+                .prop_filter("JSON numbers are finite", |f| f.is_finite())
+                .prop_map(Value::from),
+            any::<String>().prop_map(Value::from),
+        ];
+        let nested = leaf.prop_recursive(3, 32, 8, |inner| {
+            prop_oneof![
+                Just(Value::Null),
+                inner.clone(),
+                vec(inner.clone(), 0..8).prop_map(Value::from),
+                btree_map(any::<String>(), inner, 0..8)
+                    .prop_map(|map| Value::Object(map.into_iter().collect())),
+            ]
+        });
+        nested
+            .prop_filter("null is no data", |value| !value.is_null())
+            .boxed()
+    }
+
+    fn priority() -> impl Strategy<Value = Priority> {
+        select(vec![Priority::Low, Priority::Normal, Priority::High])
+    }
+
+    fn options() -> BoxedStrategy<SubscribeOptions> {
+        (
+            option::of(count()),
+            option::of(id()),
+            option::of(id()),
+            any::<bool>(),
+            vec(name(), 0..4),
+            option::of(name()),
+            any::<bool>(),
+        )
+            .prop_map(
+                |(last, since, since_id, no_presence, events, durable, no_echo)| SubscribeOptions {
+                    last,
+                    since,
+                    since_id,
+                    no_presence,
+                    events,
+                    durable,
+                    no_echo,
+                },
+            )
+            .boxed()
+    }
+
+    fn publish() -> BoxedStrategy<Frame> {
+        let routing = (
+            option::of(id()),
+            name(),
+            option::of(name()),
+            option::of(id()),
+            option::of(id()),
+            option::of(id()),
+            priority(),
+            option::of(name()),
+            option::of(id()),
+        );
+        let extras = (
+            any::<bool>(),
+            option::of(name()),
+            option::of(name()),
+            option::of("[0-9a-f]{64}"),
+            option::of(name()),
+            any::<bool>(),
+            payload(),
+        );
+        (routing, extras)
+            .prop_map(
+                |(
+                    (
+                        id,
+                        channel,
+                        event,
+                        seq,
+                        message_id,
+                        timestamp,
+                        priority,
+                        coalesce_key,
+                        group_id,
+                    ),
+                    (encrypted, key_id, signer, signature, origin, trace, payload),
+                )| Frame::Publish {
+                    id,
+                    channel,
+                    event,
+                    seq,
+                    message_id,
+                    timestamp,
+                    priority,
+                    coalesce_key,
+                    group_id,
+                    encrypted,
+                    key_id,
+                    signer,
+                    signature,
+                    origin,
+                    trace,
+                    payload,
+                },
+            )
+            .boxed()
+    }
+
+    fn group_message() -> BoxedStrategy<GroupMessage> {
+        (
+            name(),
+            option::of(name()),
+            any::<bool>(),
+            option::of(name()),
+            vec(any::<u8>(), 0..64),
+        )
+            .prop_map(
+                |(channel, event, encrypted, key_id, payload)| GroupMessage {
+                    event,
+                    encrypted,
+                    key_id,
+                    ..GroupMessage::new(channel, payload)
+                },
+            )
+            .boxed()
+    }
+
+    /// Frames clients send.
+    fn client_frame() -> BoxedStrategy<Frame> {
+        let action = select(vec![
+            PresenceAction::Join,
+            PresenceAction::Leave,
+            PresenceAction::Update,
+            PresenceAction::Sync,
+        ]);
+        prop_oneof![
+            (id(), name(), option::of(name()), options()).prop_map(
+                |(id, channel, auth, options)| Frame::Subscribe {
+                    id,
+                    channel,
+                    auth,
+                    options,
+                }
+            ),
+            (id(), name()).prop_map(|(id, channel)| Frame::Unsubscribe { id, channel }),
+            publish(),
+            (option::of(id()), vec(group_message(), 0..8), priority()).prop_map(
+                |(id, messages, priority)| Frame::PublishGroup {
+                    id,
+                    messages,
+                    priority,
+                }
+            ),
+            (id(), name(), action, option::of(data())).prop_map(|(id, channel, action, data)| {
+                Frame::Presence {
+                    id,
+                    channel,
+                    action,
+                    data,
+                }
+            }),
+            option::of(id()).prop_map(|timestamp| Frame::Ping { timestamp }),
+            option::of(id()).prop_map(|timestamp| Frame::Pong { timestamp }),
+            (
+                any::<u8>(),
+                option::of(name()),
+                any::<u32>(),
+                option::of(count()),
+                option::of(name()),
+            )
+                .prop_map(|(version, token, capabilities, heartbeat, user_id)| {
+                    Frame::Connect {
+                        version,
+                        token,
+                        capabilities: Capabilities::from_bits(capabilities),
+                        heartbeat,
+                        user_id,
+                    }
+                }),
+        ]
+        .boxed()
+    }
+
+    /// Frames servers send.
+    fn server_frame() -> BoxedStrategy<Frame> {
+        let reason = select(vec![
+            DisconnectReason::Shutdown,
+            DisconnectReason::Kicked,
+            DisconnectReason::AuthExpired,
+            DisconnectReason::Overloaded,
+            DisconnectReason::SlowConsumer,
+            DisconnectReason::Idle,
+            DisconnectReason::QuotaExceeded,
+            DisconnectReason::RateLimited,
+        ]);
+        prop_oneof![
+            id().prop_map(|id| Frame::Ack { id }),
+            (id(), name(), id(), count(), count(), count()).prop_map(
+                |(id, channel, seq, subscribers, presence, history)| {
+                    Frame::subscribe_ok(id, channel, seq, subscribers, presence, history)
+                }
+            ),
+            (id(), id(), id(), count()).prop_map(|(id, message_id, seq, recipients)| {
+                Frame::publish_ok(id, message_id, seq, recipients)
+            }),
+            (name(), id(), count(), any::<bool>()).prop_map(
+                |(channel, seq, replayed, truncated)| Frame::ReplayEnd {
+                    channel,
+                    seq,
+                    replayed,
+                    truncated,
+                }
+            ),
+            (id(), any::<u16>(), name())
+                .prop_map(|(id, code, message)| Frame::error(id, code, message)),
+            (reason, name(), option::of(count()), option::of(name())).prop_map(
+                |(reason, message, reconnect_after, alternate_host)| Frame::Disconnect {
+                    reason,
+                    message,
+                    reconnect_after,
+                    alternate_host,
+                }
+            ),
+            (
+                name(),
+                any::<u8>(),
+                count(),
+                any::<u32>(),
+                option::of(name())
+            )
+                .prop_map(
+                    |(connection_id, version, heartbeat, capabilities, affinity)| {
+                        Frame::Connected {
+                            connection_id,
+                            version,
+                            heartbeat,
+                            capabilities: Capabilities::from_bits(capabilities),
+                            affinity,
+                        }
+                    }
+                ),
+        ]
+        .boxed()
+    }
+
+    fn frame() -> BoxedStrategy<Frame> {
+        prop_oneof![client_frame(), server_frame()].boxed()
+    }
+
+    proptest! {
+        #[test]
+        fn prop_roundtrip(frame in frame()) {
+            let encoded = encode(&frame).unwrap();
+            let length = u32::from_be_bytes([encoded[0], encoded[1], encoded[2], encoded[3]]);
+            prop_assert_eq!(length as usize + LENGTH_PREFIX_SIZE, encoded.len());
+            prop_assert_eq!(decode(&encoded).unwrap(), frame);
+        }
+
+        #[test]
+        fn prop_split_stream(frames in vec(frame(), 1..8), cuts in vec(any::<Index>(), 0..16)) {
+            let mut stream = BytesMut::new();
+            for frame in &frames {
+                encode_into(frame, &mut stream).unwrap();
+            }
+            let mut cuts: Vec<usize> = cuts.iter().map(|cut| cut.index(stream.len() + 1)).collect();
+            cuts.push(stream.len());
+            cuts.sort_unstable();
+
+            // Feed the stream in pieces, decoding whatever is complete
+            let (mut buf, mut decoded, mut start) = (BytesMut::new(), Vec::new(), 0);
+            for cut in cuts {
+                buf.extend_from_slice(&stream[start..cut]);
+                start = cut;
+                while let Some(frame) = decode_from(&mut buf).unwrap() {
+                    decoded.push(frame);
+                }
+            }
+            prop_assert_eq!(decoded, frames);
+            prop_assert!(buf.is_empty());
+        }
+
+        #[test]
+        fn prop_decode_garbage(body in vec(any::<u8>(), 0..64), next in frame()) {
+            // A malformed frame is consumed whole, so the next one still decodes
+            let mut buf = BytesMut::new();
+            buf.put_u32(u32::try_from(body.len()).unwrap());
+            buf.extend_from_slice(&body);
+            encode_into(&next, &mut buf).unwrap();
+
+            let _ = decode_from(&mut buf);
+            prop_assert_eq!(decode_from(&mut buf).unwrap(), Some(next));
+            prop_assert!(buf.is_empty());
+        }
+    }
+}
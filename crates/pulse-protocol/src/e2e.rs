@@ -0,0 +1,306 @@
+//! End-to-end encryption helpers for clients.
+//!
+//! Publishers seal payloads before sending them in a Publish frame with
+//! `encrypted` set, and subscribers open them with the same key, so the
+//! server only ever relays ciphertext. Keys are derived per channel from a
+//! secret the clients share out of band, so one secret serves many channels
+//! without two channels sharing a key.
+//!
+//! Every key has an ID that travels with each message as `key_id`. A channel
+//! rotates by deriving a new key under a new ID; messages sealed with older
+//! keys stay readable until those keys are retired.
+//!
+//! Payloads are sealed with ChaCha20-Poly1305 under a random nonce, with the
+//! channel name as associated data so ciphertext copied into another channel
+//! does not open.
+//!
+//! ```rust
+//! use pulse_protocol::e2e::ChannelKeys;
+//!
+//! let mut keys = ChannelKeys::new("room:42", b"shared room secret", "v1");
+//! let frame = keys.seal(b"hello").unwrap();
+//! assert_eq!(keys.open(&frame).unwrap(), b"hello");
+//!
+//! // Rotate; messages sealed with v1 still open until it is retired
+//! keys.rotate(b"new room secret", "v2");
+//! assert_eq!(keys.open(&frame).unwrap(), b"hello");
+//! ```
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::frames::Frame;
+
+/// Nonce size in bytes, prepended to each sealed payload.
+pub const NONCE_SIZE: usize = 12;
+
+/// Label mixed into every derived key.
+const KEY_INFO: &[u8] = b"pulse-e2e-v1";
+
+/// Errors sealing or opening encrypted payloads.
+#[derive(Debug, Error)]
+pub enum E2eError {
+    /// The frame is not an encrypted Publish.
+    #[error("Frame is not an encrypted publish")]
+    NotEncrypted,
+
+    /// The frame was published to a different channel.
+    #[error("Frame is for channel {0}")]
+    WrongChannel(String),
+
+    /// The payload was sealed with a key this keyring does not hold.
+    #[error("Unknown key {0:?}")]
+    UnknownKey(Option<String>),
+
+    /// The payload was tampered with or sealed with a different key.
+    #[error("Payload could not be decrypted")]
+    Decrypt,
+
+    /// Sealing failed.
+    #[error("Payload could not be encrypted")]
+    Encrypt,
+}
+
+/// A payload key for one channel.
+pub struct ChannelKey {
+    id: String,
+    cipher: ChaCha20Poly1305,
+}
+
+impl ChannelKey {
+    /// Derive the key `id` for `channel` from a shared secret.
+    ///
+    /// Every client deriving with the same secret, channel and ID gets the
+    /// same key; changing any of them gives an unrelated one.
+    #[must_use]
+    pub fn derive(secret: &[u8], channel: &str, id: impl Into<String>) -> Self {
+        let id = id.into();
+        let hkdf = Hkdf::<Sha256>::new(Some(channel.as_bytes()), secret);
+        let mut key = Key::default();
+        hkdf.expand_multi_info(&[KEY_INFO, id.as_bytes()], &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        Self {
+            id,
+            cipher: ChaCha20Poly1305::new(&key),
+        }
+    }
+
+    /// Get the key ID.
+    #[must_use]
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Encrypt a payload published to `channel`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if encryption fails.
+    pub fn seal(&self, channel: &str, plaintext: &[u8]) -> Result<Vec<u8>, E2eError> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad: channel.as_bytes(),
+                },
+            )
+            .map_err(|_| E2eError::Encrypt)?;
+        let mut sealed = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    /// Decrypt a payload received on `channel`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the payload is truncated, was tampered with, or
+    /// was sealed with another key or for another channel.
+    pub fn open(&self, channel: &str, sealed: &[u8]) -> Result<Vec<u8>, E2eError> {
+        if sealed.len() < NONCE_SIZE {
+            return Err(E2eError::Decrypt);
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+        self.cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: channel.as_bytes(),
+                },
+            )
+            .map_err(|_| E2eError::Decrypt)
+    }
+}
+
+impl std::fmt::Debug for ChannelKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChannelKey")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The keys of one channel: the current one, used to seal, and older ones
+/// still accepted when opening.
+#[derive(Debug)]
+pub struct ChannelKeys {
+    channel: String,
+    /// Oldest first; the last is current.
+    keys: Vec<ChannelKey>,
+}
+
+impl ChannelKeys {
+    /// Create a keyring for `channel` with the key `key_id` derived from
+    /// `secret`.
+    #[must_use]
+    pub fn new(channel: impl Into<String>, secret: &[u8], key_id: impl Into<String>) -> Self {
+        let channel = channel.into();
+        let key = ChannelKey::derive(secret, &channel, key_id);
+        Self {
+            channel,
+            keys: vec![key],
+        }
+    }
+
+    /// Get the channel name.
+    #[must_use]
+    pub fn channel(&self) -> &str {
+        &self.channel
+    }
+
+    /// Get the key new messages are sealed with.
+    #[must_use]
+    pub fn current(&self) -> &ChannelKey {
+        self.keys.last().expect("a keyring always holds a key")
+    }
+
+    /// Seal new messages with the key `key_id` derived from `secret`. Older
+    /// keys still open messages until retired; rotating to an ID already
+    /// held replaces that key.
+    pub fn rotate(&mut self, secret: &[u8], key_id: impl Into<String>) {
+        let key = ChannelKey::derive(secret, &self.channel, key_id);
+        self.keys.retain(|k| k.id != key.id);
+        self.keys.push(key);
+    }
+
+    /// Stop accepting messages sealed with the key `key_id`. The current key
+    /// cannot be retired.
+    ///
+    /// Returns whether the key was retired.
+    pub fn retire(&mut self, key_id: &str) -> bool {
+        if self.current().id == key_id {
+            return false;
+        }
+        let len = self.keys.len();
+        self.keys.retain(|k| k.id != key_id);
+        self.keys.len() < len
+    }
+
+    /// Seal a payload with the current key into a Publish frame.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if encryption fails.
+    pub fn seal(&self, plaintext: &[u8]) -> Result<Frame, E2eError> {
+        let key = self.current();
+        let payload = key.seal(&self.channel, plaintext)?;
+        Ok(Frame::publish_encrypted(&self.channel, &key.id, payload))
+    }
+
+    /// Open the payload of an encrypted Publish frame for this channel.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the frame is not an encrypted publish to this
+    /// channel, its key is unknown or retired, or it does not decrypt.
+    pub fn open(&self, frame: &Frame) -> Result<Vec<u8>, E2eError> {
+        let Frame::Publish {
+            channel,
+            encrypted: true,
+            key_id,
+            payload,
+            ..
+        } = frame
+        else {
+            return Err(E2eError::NotEncrypted);
+        };
+        if *channel != self.channel {
+            return Err(E2eError::WrongChannel(channel.clone()));
+        }
+        let key = self
+            .keys
+            .iter()
+            .find(|k| Some(&k.id) == key_id.as_ref())
+            .ok_or_else(|| E2eError::UnknownKey(key_id.clone()))?;
+        key.open(&self.channel, payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel_key() {
+        let key = ChannelKey::derive(b"secret", "room:1", "v1");
+        let sealed = key.seal("room:1", b"hello").unwrap();
+        assert_ne!(&sealed[NONCE_SIZE..], b"hello");
+        assert_eq!(key.open("room:1", &sealed).unwrap(), b"hello");
+
+        // Same inputs derive the same key, any other input a different one
+        let same = ChannelKey::derive(b"secret", "room:1", "v1");
+        assert_eq!(same.open("room:1", &sealed).unwrap(), b"hello");
+        for other in [
+            ChannelKey::derive(b"other", "room:1", "v1"),
+            ChannelKey::derive(b"secret", "room:2", "v1"),
+            ChannelKey::derive(b"secret", "room:1", "v2"),
+        ] {
+            assert!(other.open("room:1", &sealed).is_err());
+        }
+
+        // Bound to the channel and tamper-evident
+        assert!(key.open("room:2", &sealed).is_err());
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(key.open("room:1", &tampered).is_err());
+        assert!(key.open("room:1", &sealed[..4]).is_err());
+    }
+
+    #[test]
+    fn test_channel_keys_rotation() {
+        let mut keys = ChannelKeys::new("room:1", b"secret", "v1");
+        let old = keys.seal(b"before").unwrap();
+        assert!(matches!(
+            &old,
+            Frame::Publish { encrypted: true, key_id: Some(id), .. } if id == "v1"
+        ));
+
+        keys.rotate(b"secret", "v2");
+        assert_eq!(keys.current().id(), "v2");
+        let new = keys.seal(b"after").unwrap();
+        assert_eq!(keys.open(&old).unwrap(), b"before");
+        assert_eq!(keys.open(&new).unwrap(), b"after");
+
+        assert!(!keys.retire("v2"));
+        assert!(keys.retire("v1"));
+        assert!(matches!(keys.open(&old), Err(E2eError::UnknownKey(_))));
+
+        assert!(matches!(
+            keys.open(&Frame::publish("room:1", b"plain".to_vec())),
+            Err(E2eError::NotEncrypted)
+        ));
+        let elsewhere = ChannelKeys::new("room:2", b"secret", "v2");
+        assert!(matches!(
+            elsewhere.open(&new),
+            Err(E2eError::WrongChannel(_))
+        ));
+    }
+}
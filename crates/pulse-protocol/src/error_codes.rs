@@ -0,0 +1,40 @@
+//! Error codes carried in `Error` frames.
+//!
+//! See `docs/PROTOCOL.md` for the full table.
+
+/// An unknown error occurred.
+pub const UNKNOWN_ERROR: u16 = 1000;
+/// Malformed or invalid frame.
+pub const INVALID_FRAME: u16 = 1001;
+/// Invalid channel name.
+pub const INVALID_CHANNEL: u16 = 1002;
+/// Authentication required or failed.
+pub const UNAUTHORIZED: u16 = 1003;
+/// Permission denied for operation.
+pub const FORBIDDEN: u16 = 1004;
+/// Channel does not exist.
+pub const CHANNEL_NOT_FOUND: u16 = 1005;
+/// Too many requests.
+pub const RATE_LIMITED: u16 = 1006;
+/// Message exceeds size limit.
+pub const PAYLOAD_TOO_LARGE: u16 = 1007;
+/// Not subscribed to channel.
+pub const NOT_SUBSCRIBED: u16 = 1008;
+/// Already subscribed to channel.
+pub const ALREADY_SUBSCRIBED: u16 = 1009;
+/// Connection is closing.
+pub const CONNECTION_CLOSED: u16 = 1010;
+/// Internal server error.
+pub const SERVER_ERROR: u16 = 1011;
+/// Protocol version not supported.
+pub const PROTOCOL_MISMATCH: u16 = 1012;
+/// Channel has reached its subscriber limit.
+pub const CHANNEL_FULL: u16 = 1013;
+/// Publish rejected by moderation.
+pub const REJECTED: u16 = 1014;
+/// Payload does not match the channel's schema.
+pub const INVALID_PAYLOAD: u16 = 1015;
+/// The connection is idle and about to be closed.
+pub const IDLE_WARNING: u16 = 1016;
+/// Payload exceeds the size limit of the channel it was published to.
+pub const CHANNEL_PAYLOAD_TOO_LARGE: u16 = 1017;
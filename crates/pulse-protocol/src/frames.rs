@@ -3,6 +3,7 @@
 //! Frames are the fundamental unit of communication in Pulse.
 //! Each frame is serialized using MessagePack for efficient binary encoding.
 
+use crate::capabilities::Capabilities;
 use serde::{Deserialize, Serialize};
 
 /// Frame type identifiers.
@@ -20,6 +21,11 @@ pub enum FrameType {
     Pong = 0x08,
     Connect = 0x09,
     Connected = 0x0A,
+    SubscribeOk = 0x0B,
+    Disconnect = 0x0C,
+    PublishOk = 0x0D,
+    ReplayEnd = 0x0E,
+    PublishGroup = 0x0F,
 }
 
 impl From<FrameType> for u8 {
@@ -43,6 +49,11 @@ impl TryFrom<u8> for FrameType {
             0x08 => Ok(FrameType::Pong),
             0x09 => Ok(FrameType::Connect),
             0x0A => Ok(FrameType::Connected),
+            0x0B => Ok(FrameType::SubscribeOk),
+            0x0C => Ok(FrameType::Disconnect),
+            0x0D => Ok(FrameType::PublishOk),
+            0x0E => Ok(FrameType::ReplayEnd),
+            0x0F => Ok(FrameType::PublishGroup),
             _ => Err("Invalid frame type"),
         }
     }
@@ -83,6 +94,193 @@ impl TryFrom<u8> for PresenceAction {
     }
 }
 
+/// Why the server is closing a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "u8", try_from = "u8")]
+#[repr(u8)]
+pub enum DisconnectReason {
+    /// The server is shutting down or restarting.
+    Shutdown = 0,
+    /// An operator removed the connection.
+    Kicked = 1,
+    /// The connection's credentials expired.
+    AuthExpired = 2,
+    /// The server is overloaded and shedding connections.
+    Overloaded = 3,
+    /// The client fell too far behind reading its messages.
+    SlowConsumer = 4,
+    /// The client sent no frames for too long.
+    Idle = 5,
+    /// The client's user used up its bandwidth quota.
+    QuotaExceeded = 6,
+    /// The client kept exceeding a rate limit.
+    RateLimited = 7,
+}
+
+impl From<DisconnectReason> for u8 {
+    fn from(reason: DisconnectReason) -> u8 {
+        reason as u8
+    }
+}
+
+impl TryFrom<u8> for DisconnectReason {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(DisconnectReason::Shutdown),
+            1 => Ok(DisconnectReason::Kicked),
+            2 => Ok(DisconnectReason::AuthExpired),
+            3 => Ok(DisconnectReason::Overloaded),
+            4 => Ok(DisconnectReason::SlowConsumer),
+            5 => Ok(DisconnectReason::Idle),
+            6 => Ok(DisconnectReason::QuotaExceeded),
+            7 => Ok(DisconnectReason::RateLimited),
+            _ => Err("Invalid disconnect reason"),
+        }
+    }
+}
+
+/// Delivery priority of a published message.
+///
+/// When a connection's outbound queue backs up, higher-priority messages are
+/// delivered first and lower-priority ones are dropped first.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(into = "u8", try_from = "u8")]
+#[repr(u8)]
+pub enum Priority {
+    /// Bulk traffic that may wait, such as telemetry.
+    Low = 0,
+    /// Ordinary messages.
+    #[default]
+    Normal = 1,
+    /// Control messages that should not wait behind a backlog.
+    High = 2,
+}
+
+impl Priority {
+    fn is_normal(&self) -> bool {
+        *self == Priority::Normal
+    }
+}
+
+impl From<Priority> for u8 {
+    fn from(priority: Priority) -> u8 {
+        priority as u8
+    }
+}
+
+impl TryFrom<u8> for Priority {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Priority::Low),
+            1 => Ok(Priority::Normal),
+            2 => Ok(Priority::High),
+            _ => Err("Invalid priority"),
+        }
+    }
+}
+
+/// Options for a Subscribe request.
+///
+/// Every field is optional on the wire; a Subscribe without options behaves
+/// as before.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubscribeOptions {
+    /// Replay up to this many of the channel's most recent messages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last: Option<u32>,
+    /// Replay retained messages with a sequence number greater than this.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<u64>,
+    /// Replay retained messages published after the message with this ID,
+    /// such as the last one the client received before reconnecting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since_id: Option<u64>,
+    /// Do not deliver presence frames for this channel.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub no_presence: bool,
+    /// Only deliver messages with one of these event names (all if empty).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<String>,
+    /// Name of a durable subscription to create or reattach to. It keeps
+    /// buffering the channel's messages while this connection is away.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub durable: Option<String>,
+    /// Do not deliver this connection's own publishes back to it.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub no_echo: bool,
+}
+
+impl SubscribeOptions {
+    /// Check if no options are set.
+    #[must_use]
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Check if the subscriber asked for a history replay.
+    #[must_use]
+    pub fn wants_replay(&self) -> bool {
+        self.last.is_some() || self.since.is_some() || self.since_id.is_some()
+    }
+
+    /// Check if a message with this event name passes the event filter.
+    #[must_use]
+    pub fn accepts_event(&self, event: Option<&str>) -> bool {
+        self.events.is_empty() || event.is_some_and(|e| self.events.iter().any(|f| f == e))
+    }
+}
+
+/// One message of a [`Frame::PublishGroup`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupMessage {
+    /// Target channel.
+    pub channel: String,
+    /// Optional event name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<String>,
+    /// Whether the payload is end-to-end encrypted.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub encrypted: bool,
+    /// ID of the key an encrypted payload was sealed with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+    /// ID of the signing key the publisher signed the message with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signer: Option<String>,
+    /// Hex-encoded HMAC-SHA256 signature of the message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// Message payload.
+    #[serde(with = "serde_bytes")]
+    pub payload: Vec<u8>,
+}
+
+impl GroupMessage {
+    /// Create a group message.
+    #[must_use]
+    pub fn new(channel: impl Into<String>, payload: impl Into<Vec<u8>>) -> Self {
+        Self {
+            channel: channel.into(),
+            event: None,
+            encrypted: false,
+            key_id: None,
+            signer: None,
+            signature: None,
+            payload: payload.into(),
+        }
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
 /// A protocol frame.
 ///
 /// Frames are the messages exchanged between clients and servers.
@@ -97,6 +295,12 @@ pub enum Frame {
         id: u64,
         /// Channel name to subscribe to.
         channel: String,
+        /// Auth signature, required for private channels.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        auth: Option<String>,
+        /// Replay and delivery options.
+        #[serde(default, skip_serializing_if = "SubscribeOptions::is_default")]
+        options: SubscribeOptions,
     },
 
     /// Unsubscribe from a channel.
@@ -119,11 +323,71 @@ pub enum Frame {
         /// Optional event name.
         #[serde(skip_serializing_if = "Option::is_none")]
         event: Option<String>,
+        /// Channel sequence number, set on messages delivered by the server.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq: Option<u64>,
+        /// Message ID, set on messages delivered by the server; usable as a
+        /// Subscribe `since_id` cursor.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        message_id: Option<u64>,
+        /// When the server accepted the message, in milliseconds since the
+        /// Unix epoch; set on messages delivered by the server.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timestamp: Option<u64>,
+        /// Delivery priority (normal if omitted).
+        #[serde(default, skip_serializing_if = "Priority::is_normal")]
+        priority: Priority,
+        /// Coalescing key: while a subscriber is congested, a queued message
+        /// with the same key is replaced by this one.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        coalesce_key: Option<String>,
+        /// Group ID, set on messages delivered by the server that were
+        /// published together in a PublishGroup.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        group_id: Option<u64>,
+        /// Whether the payload is end-to-end encrypted. The server relays it
+        /// untouched and never inspects it.
+        #[serde(default, skip_serializing_if = "is_false")]
+        encrypted: bool,
+        /// ID of the key an encrypted payload was sealed with, so recipients
+        /// can pick it across key rotations.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        key_id: Option<String>,
+        /// ID of the signing key the publisher signed the message with.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signer: Option<String>,
+        /// Hex-encoded HMAC-SHA256 signature of the message, verified by the
+        /// server and forwarded to subscribers.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signature: Option<String>,
+        /// Region the message was first published in, set between federated
+        /// servers to keep relayed messages from looping.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        origin: Option<String>,
+        /// Whether the server reports each step in routing and delivering
+        /// the message to `$system:trace`. Only honored from admin
+        /// connections.
+        #[serde(default, skip_serializing_if = "is_false")]
+        trace: bool,
         /// Message payload.
         #[serde(with = "serde_bytes")]
         payload: Vec<u8>,
     },
 
+    /// Publish messages to several channels at once. Each subscriber receives
+    /// the messages it is subscribed to back to back, in order.
+    #[serde(rename = "publish_group")]
+    PublishGroup {
+        /// Optional request ID for acknowledgment.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<u64>,
+        /// Messages to publish, in order.
+        messages: Vec<GroupMessage>,
+        /// Delivery priority of every message (normal if omitted).
+        #[serde(default, skip_serializing_if = "Priority::is_normal")]
+        priority: Priority,
+    },
+
     /// Presence update.
     #[serde(rename = "presence")]
     Presence {
@@ -145,6 +409,53 @@ pub enum Frame {
         id: u64,
     },
 
+    /// Successful subscription, with the channel's current state.
+    #[serde(rename = "subscribe_ok")]
+    SubscribeOk {
+        /// ID of the Subscribe request.
+        id: u64,
+        /// Channel subscribed to.
+        channel: String,
+        /// Sequence number of the channel's latest message (0 if none).
+        seq: u64,
+        /// Number of subscribers, including this connection.
+        subscribers: u32,
+        /// Number of presence members (0 on non-presence channels).
+        presence: u32,
+        /// Number of retained messages available for replay (0 if the
+        /// channel keeps no history).
+        history: u32,
+    },
+
+    /// Successful publish, with what the server did with the message.
+    #[serde(rename = "publish_ok")]
+    PublishOk {
+        /// ID of the Publish request.
+        id: u64,
+        /// ID the server assigned to the message.
+        message_id: u64,
+        /// Channel sequence number given to the message (0 if the channel
+        /// does not exist).
+        seq: u64,
+        /// Number of subscribers the message was queued for.
+        recipients: u32,
+    },
+
+    /// End of a subscription's history replay; messages after it are live.
+    #[serde(rename = "replay_end")]
+    ReplayEnd {
+        /// Channel the replay was for.
+        channel: String,
+        /// Channel sequence number when the subscription started.
+        seq: u64,
+        /// Number of messages replayed.
+        replayed: u32,
+        /// Whether messages after the requested cursor had already been
+        /// evicted from history, so some were missed.
+        #[serde(default, skip_serializing_if = "is_false")]
+        truncated: bool,
+    },
+
     /// Error response.
     #[serde(rename = "error")]
     Error {
@@ -156,6 +467,22 @@ pub enum Frame {
         message: String,
     },
 
+    /// Server-initiated close, sent before the server closes the transport.
+    #[serde(rename = "disconnect")]
+    Disconnect {
+        /// Why the connection is being closed.
+        reason: DisconnectReason,
+        /// Human-readable explanation.
+        message: String,
+        /// Milliseconds the client should wait before reconnecting; absent
+        /// if it should not reconnect automatically.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reconnect_after: Option<u32>,
+        /// Host the client should reconnect to instead.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        alternate_host: Option<String>,
+    },
+
     /// Keepalive ping.
     #[serde(rename = "ping")]
     Ping {
@@ -180,6 +507,17 @@ pub enum Frame {
         /// Optional authentication token.
         #[serde(skip_serializing_if = "Option::is_none")]
         token: Option<String>,
+        /// Optional features the client understands.
+        #[serde(default, skip_serializing_if = "Capabilities::is_empty")]
+        capabilities: Capabilities,
+        /// Requested heartbeat interval in milliseconds. The server clamps it
+        /// to its configured bounds and answers with the result in Connected.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        heartbeat: Option<u32>,
+        /// Application user the client acts for, used for moderation such as
+        /// bans. Pulse does not authenticate it.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        user_id: Option<String>,
     },
 
     /// Connection established response.
@@ -189,8 +527,16 @@ pub enum Frame {
         connection_id: String,
         /// Negotiated protocol version.
         version: u8,
-        /// Recommended heartbeat interval in milliseconds.
+        /// Heartbeat interval in milliseconds, as negotiated for this connection.
         heartbeat: u32,
+        /// Optional features in use: those the server supports before the
+        /// client's Connect, the negotiated set after it.
+        #[serde(default, skip_serializing_if = "Capabilities::is_empty")]
+        capabilities: Capabilities,
+        /// Token naming the server, for clients to present when they
+        /// reconnect so they are routed back to it.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        affinity: Option<String>,
     },
 }
 
@@ -202,6 +548,7 @@ impl Frame {
             Frame::Subscribe { .. } => FrameType::Subscribe,
             Frame::Unsubscribe { .. } => FrameType::Unsubscribe,
             Frame::Publish { .. } => FrameType::Publish,
+            Frame::PublishGroup { .. } => FrameType::PublishGroup,
             Frame::Presence { .. } => FrameType::Presence,
             Frame::Ack { .. } => FrameType::Ack,
             Frame::Error { .. } => FrameType::Error,
@@ -209,6 +556,10 @@ impl Frame {
             Frame::Pong { .. } => FrameType::Pong,
             Frame::Connect { .. } => FrameType::Connect,
             Frame::Connected { .. } => FrameType::Connected,
+            Frame::SubscribeOk { .. } => FrameType::SubscribeOk,
+            Frame::Disconnect { .. } => FrameType::Disconnect,
+            Frame::PublishOk { .. } => FrameType::PublishOk,
+            Frame::ReplayEnd { .. } => FrameType::ReplayEnd,
         }
     }
 
@@ -218,6 +569,38 @@ impl Frame {
         Frame::Subscribe {
             id,
             channel: channel.into(),
+            auth: None,
+            options: SubscribeOptions::default(),
+        }
+    }
+
+    /// Create a new Subscribe frame with an auth signature.
+    #[must_use]
+    pub fn subscribe_with_auth(
+        id: u64,
+        channel: impl Into<String>,
+        auth: impl Into<String>,
+    ) -> Self {
+        Frame::Subscribe {
+            id,
+            channel: channel.into(),
+            auth: Some(auth.into()),
+            options: SubscribeOptions::default(),
+        }
+    }
+
+    /// Create a new Subscribe frame with options.
+    #[must_use]
+    pub fn subscribe_with_options(
+        id: u64,
+        channel: impl Into<String>,
+        options: SubscribeOptions,
+    ) -> Self {
+        Frame::Subscribe {
+            id,
+            channel: channel.into(),
+            auth: None,
+            options,
         }
     }
 
@@ -237,6 +620,18 @@ impl Frame {
             id: None,
             channel: channel.into(),
             event: None,
+            seq: None,
+            message_id: None,
+            timestamp: None,
+            priority: Priority::Normal,
+            coalesce_key: None,
+            group_id: None,
+            encrypted: false,
+            key_id: None,
+            signer: None,
+            signature: None,
+            origin: None,
+            trace: false,
             payload: payload.into(),
         }
     }
@@ -252,6 +647,45 @@ impl Frame {
             id: Some(id),
             channel: channel.into(),
             event: None,
+            seq: None,
+            message_id: None,
+            timestamp: None,
+            priority: Priority::Normal,
+            coalesce_key: None,
+            group_id: None,
+            encrypted: false,
+            key_id: None,
+            signer: None,
+            signature: None,
+            origin: None,
+            trace: false,
+            payload: payload.into(),
+        }
+    }
+
+    /// Create a new Publish frame carrying an end-to-end encrypted payload.
+    #[must_use]
+    pub fn publish_encrypted(
+        channel: impl Into<String>,
+        key_id: impl Into<String>,
+        payload: impl Into<Vec<u8>>,
+    ) -> Self {
+        Frame::Publish {
+            id: None,
+            channel: channel.into(),
+            event: None,
+            seq: None,
+            message_id: None,
+            timestamp: None,
+            priority: Priority::Normal,
+            coalesce_key: None,
+            group_id: None,
+            encrypted: true,
+            key_id: Some(key_id.into()),
+            signer: None,
+            signature: None,
+            origin: None,
+            trace: false,
             payload: payload.into(),
         }
     }
@@ -262,6 +696,37 @@ impl Frame {
         Frame::Ack { id }
     }
 
+    /// Create a new SubscribeOk frame.
+    #[must_use]
+    pub fn subscribe_ok(
+        id: u64,
+        channel: impl Into<String>,
+        seq: u64,
+        subscribers: u32,
+        presence: u32,
+        history: u32,
+    ) -> Self {
+        Frame::SubscribeOk {
+            id,
+            channel: channel.into(),
+            seq,
+            subscribers,
+            presence,
+            history,
+        }
+    }
+
+    /// Create a new PublishOk frame.
+    #[must_use]
+    pub fn publish_ok(id: u64, message_id: u64, seq: u64, recipients: u32) -> Self {
+        Frame::PublishOk {
+            id,
+            message_id,
+            seq,
+            recipients,
+        }
+    }
+
     /// Create a new Error frame.
     #[must_use]
     pub fn error(id: u64, code: u16, message: impl Into<String>) -> Self {
@@ -272,6 +737,21 @@ impl Frame {
         }
     }
 
+    /// Create a new Disconnect frame.
+    #[must_use]
+    pub fn disconnect(
+        reason: DisconnectReason,
+        message: impl Into<String>,
+        reconnect_after: Option<u32>,
+    ) -> Self {
+        Frame::Disconnect {
+            reason,
+            message: message.into(),
+            reconnect_after,
+            alternate_host: None,
+        }
+    }
+
     /// Create a new Ping frame.
     #[must_use]
     pub fn ping() -> Self {
@@ -295,7 +775,13 @@ impl Frame {
     /// Create a new Connect frame.
     #[must_use]
     pub fn connect(version: u8, token: Option<String>) -> Self {
-        Frame::Connect { version, token }
+        Frame::Connect {
+            version,
+            token,
+            capabilities: Capabilities::NONE,
+            heartbeat: None,
+            user_id: None,
+        }
     }
 
     /// Create a new Connected frame.
@@ -305,6 +791,8 @@ impl Frame {
             connection_id: connection_id.into(),
             version,
             heartbeat,
+            capabilities: Capabilities::NONE,
+            affinity: None,
         }
     }
 }
@@ -322,6 +810,52 @@ mod tests {
         assert_eq!(publish.frame_type(), FrameType::Publish);
     }
 
+    #[test]
+    fn test_subscribe_options() {
+        let options = SubscribeOptions {
+            events: vec!["price".to_string()],
+            ..Default::default()
+        };
+        assert!(!options.is_default());
+        assert!(!options.wants_replay());
+        assert!(options.accepts_event(Some("price")));
+        assert!(!options.accepts_event(Some("volume")));
+        assert!(!options.accepts_event(None));
+        assert!(SubscribeOptions::default().accepts_event(None));
+
+        let resume = SubscribeOptions {
+            since_id: Some(42),
+            ..Default::default()
+        };
+        assert!(resume.wants_replay());
+    }
+
+    #[test]
+    fn test_disconnect_reason_conversion() {
+        assert_eq!(
+            DisconnectReason::try_from(0),
+            Ok(DisconnectReason::Shutdown)
+        );
+        assert_eq!(
+            DisconnectReason::try_from(4),
+            Ok(DisconnectReason::SlowConsumer)
+        );
+        assert_eq!(DisconnectReason::try_from(5), Ok(DisconnectReason::Idle));
+        assert_eq!(
+            DisconnectReason::try_from(6),
+            Ok(DisconnectReason::QuotaExceeded)
+        );
+        assert_eq!(
+            DisconnectReason::try_from(7),
+            Ok(DisconnectReason::RateLimited)
+        );
+        assert!(DisconnectReason::try_from(8).is_err());
+        assert_eq!(
+            Frame::disconnect(DisconnectReason::Kicked, "bye", None).frame_type(),
+            FrameType::Disconnect
+        );
+    }
+
     #[test]
     fn test_presence_action_conversion() {
         assert_eq!(PresenceAction::try_from(0), Ok(PresenceAction::Join));
@@ -330,4 +864,13 @@ mod tests {
         assert_eq!(PresenceAction::try_from(3), Ok(PresenceAction::Sync));
         assert!(PresenceAction::try_from(4).is_err());
     }
+
+    #[test]
+    fn test_priority_conversion() {
+        assert_eq!(Priority::try_from(0), Ok(Priority::Low));
+        assert_eq!(Priority::try_from(2), Ok(Priority::High));
+        assert!(Priority::try_from(3).is_err());
+        assert_eq!(Priority::default(), Priority::Normal);
+        assert!(Priority::High > Priority::Normal && Priority::Normal > Priority::Low);
+    }
 }
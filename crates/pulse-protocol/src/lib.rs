@@ -12,6 +12,9 @@
 //! - `Presence` - Track online users
 //! - `Ack` / `Error` - Acknowledgments and errors
 //!
+//! With the `e2e` feature, the `e2e` module helps clients seal and open
+//! end-to-end encrypted payloads.
+//!
 //! ## Example
 //!
 //! ```rust
@@ -25,10 +28,20 @@
 //! let decoded = codec::decode(&encoded).unwrap();
 //! ```
 
+pub mod capabilities;
+pub mod capture;
 pub mod codec;
+#[cfg(feature = "e2e")]
+pub mod e2e;
+pub mod error_codes;
 pub mod frames;
+pub mod pool;
 pub mod version;
 
+pub use capabilities::Capabilities;
 pub use codec::{decode, encode, ProtocolError};
-pub use frames::{Frame, PresenceAction};
+pub use frames::{
+    DisconnectReason, Frame, GroupMessage, PresenceAction, Priority, SubscribeOptions,
+};
+pub use pool::{BufferPool, PoolStats};
 pub use version::{Version, PROTOCOL_VERSION};
@@ -0,0 +1,156 @@
+//! Buffer pooling for frame encoding and decoding.
+//!
+//! Encoding and read paths take their `BytesMut` buffers from a shared
+//! [`BufferPool`] and hand them back when done, so steady-state traffic reuses
+//! allocations instead of hitting the allocator for every frame.
+
+use bytes::BytesMut;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+/// Default number of buffers kept by the global pool.
+pub const DEFAULT_MAX_BUFFERS: usize = 256;
+
+/// Default capacity of pooled buffers (8 KiB).
+pub const DEFAULT_BUFFER_CAPACITY: usize = 8 * 1024;
+
+/// Buffers that grew beyond this multiple of the pool's buffer capacity are
+/// freed instead of pooled, so one large frame does not pin memory.
+const MAX_RETAINED_FACTOR: usize = 16;
+
+/// A pool of reusable `BytesMut` buffers.
+#[derive(Debug)]
+pub struct BufferPool {
+    buffers: Mutex<Vec<BytesMut>>,
+    max_buffers: usize,
+    buffer_capacity: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    discarded: AtomicU64,
+}
+
+/// Buffer pool statistics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Buffers served from the pool.
+    pub hits: u64,
+    /// Buffers that had to be allocated.
+    pub misses: u64,
+    /// Buffers freed on release because the pool was full or they were too large.
+    pub discarded: u64,
+    /// Buffers currently in the pool.
+    pub pooled: usize,
+}
+
+impl BufferPool {
+    /// Create a pool keeping up to `max_buffers` buffers of `buffer_capacity` bytes.
+    #[must_use]
+    pub fn new(max_buffers: usize, buffer_capacity: usize) -> Self {
+        Self {
+            buffers: Mutex::new(Vec::with_capacity(max_buffers)),
+            max_buffers,
+            buffer_capacity,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            discarded: AtomicU64::new(0),
+        }
+    }
+
+    /// Take an empty buffer from the pool, allocating one if none is available.
+    #[must_use]
+    pub fn acquire(&self) -> BytesMut {
+        if let Some(buf) = self.lock().pop() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return buf;
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        BytesMut::with_capacity(self.buffer_capacity)
+    }
+
+    /// Return a buffer to the pool.
+    ///
+    /// The buffer is cleared; buffers that are too large, or that arrive while
+    /// the pool is full, are dropped.
+    pub fn release(&self, mut buf: BytesMut) {
+        if buf.capacity() > self.buffer_capacity * MAX_RETAINED_FACTOR {
+            self.discarded.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        buf.clear();
+        if buf.capacity() < self.buffer_capacity {
+            // Reclaims the original allocation once frozen frames are dropped
+            buf.reserve(self.buffer_capacity);
+        }
+
+        let mut buffers = self.lock();
+        if buffers.len() < self.max_buffers {
+            buffers.push(buf);
+        } else {
+            self.discarded.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Get pool statistics.
+    #[must_use]
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            discarded: self.discarded.load(Ordering::Relaxed),
+            pooled: self.lock().len(),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<BytesMut>> {
+        self.buffers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_BUFFERS, DEFAULT_BUFFER_CAPACITY)
+    }
+}
+
+/// The process-wide pool used by the codec and transports.
+pub fn global() -> &'static BufferPool {
+    static POOL: OnceLock<BufferPool> = OnceLock::new();
+    POOL.get_or_init(BufferPool::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BufMut;
+
+    #[test]
+    fn test_pool_reuses_buffers() {
+        let pool = BufferPool::new(2, 64);
+
+        let mut buf = pool.acquire();
+        buf.put_slice(b"hello");
+        pool.release(buf);
+
+        let buf = pool.acquire();
+        assert!(buf.is_empty());
+        assert!(buf.capacity() >= 64);
+
+        let stats = pool.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.pooled, 0);
+    }
+
+    #[test]
+    fn test_pool_discards_excess_buffers() {
+        let pool = BufferPool::new(1, 64);
+
+        pool.release(BytesMut::with_capacity(64));
+        pool.release(BytesMut::with_capacity(64));
+        pool.release(BytesMut::with_capacity(64 * MAX_RETAINED_FACTOR + 1));
+
+        let stats = pool.stats();
+        assert_eq!(stats.pooled, 1);
+        assert_eq!(stats.discarded, 2);
+    }
+}
@@ -0,0 +1,198 @@
+//! Certificates from an ACME CA such as Let's Encrypt.
+//!
+//! With `acme.domains` set, the main listener serves HTTPS/WSS with a
+//! certificate ordered for those hostnames at startup. It is renewed in the
+//! background before it expires and swapped in for new connections without a
+//! restart. Until the first certificate arrives, TLS handshakes fail.
+//!
+//! The CA checks that we control each domain with a TLS-ALPN-01 challenge,
+//! answered on the main listener, or an HTTP-01 challenge, answered on a
+//! separate plain HTTP listener at `acme.http_port`.
+
+use crate::config::{AcmeChallenge, BindAddr, Config, TlsConfig};
+use crate::tls;
+use anyhow::{Context, Result};
+use axum::Router;
+use futures_util::StreamExt;
+use rustls::server::ResolvesServerCert;
+use rustls::ServerConfig;
+use rustls_acme::axum::AxumAcceptor;
+use rustls_acme::caches::DirCache;
+use rustls_acme::{EventOk, UseChallenge};
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tenvis_pulse_transport::SocketOptions;
+use tracing::{debug, error, info, warn};
+
+/// Path HTTP-01 challenges are fetched from.
+const HTTP01_PATH: &str = "/.well-known/acme-challenge/:token";
+
+/// Start ordering certificates, or `None` when ACME is off.
+///
+/// The returned acceptor terminates TLS on the main listener with the
+/// current certificate.
+///
+/// # Errors
+///
+/// Returns an error if the TLS settings are invalid or the HTTP-01
+/// listener cannot be bound.
+pub async fn start(config: &Config) -> Result<Option<AxumAcceptor>> {
+    let acme = &config.acme;
+    if !acme.is_enabled() {
+        return Ok(None);
+    }
+
+    let order = rustls_acme::AcmeConfig::new(&acme.domains)
+        .contact(acme.contact.iter().map(|email| contact_uri(email)))
+        .cache_option(acme.cache_dir.clone().map(DirCache::new))
+        .challenge_type(match acme.challenge {
+            AcmeChallenge::TlsAlpn01 => UseChallenge::TlsAlpn01,
+            AcmeChallenge::Http01 => UseChallenge::Http01,
+        });
+    let order = match &acme.directory_url {
+        Some(url) => order.directory(url),
+        None => order.directory_lets_encrypt(acme.production),
+    };
+    let mut state = order.state();
+
+    let server_config = server_config(&config.tls, state.resolver())?;
+    let acceptor = state.axum_acceptor(Arc::new(server_config));
+
+    if acme.challenge == AcmeChallenge::Http01 {
+        let app = Router::new().route_service(HTTP01_PATH, state.http01_challenge_tower_service());
+        // Answer on every address the main listeners use, so the CA can
+        // connect over IPv4 and IPv6 alike
+        let mut ips = HashSet::new();
+        for listener in config.listeners() {
+            let Some(BindAddr::Tcp(addr)) = listener.bind_addr() else {
+                continue;
+            };
+            if !ips.insert(addr.ip()) {
+                continue;
+            }
+            let addr = SocketAddr::new(addr.ip(), acme.http_port);
+            let options = SocketOptions {
+                only_v6: listener.ipv6_only,
+                ..SocketOptions::default()
+            };
+            let http = options
+                .bind(addr)
+                .with_context(|| format!("Failed to bind the ACME HTTP-01 listener on {addr}"))?;
+            info!("Serving ACME HTTP-01 challenges on {}", addr);
+            let app = app.clone();
+            tokio::spawn(async move {
+                if let Err(e) = axum::serve(http, app).await {
+                    error!("ACME HTTP-01 listener failed: {}", e);
+                }
+            });
+        }
+    }
+
+    let domains = acme.domains.join(",");
+    info!(domains = %domains, "Ordering ACME certificate");
+    tokio::spawn(async move {
+        // Polling the state drives ordering and renewal; failed orders are
+        // retried with backoff
+        while let Some(event) = state.next().await {
+            match event {
+                Ok(EventOk::DeployedNewCert) => {
+                    info!(domains = %domains, "Obtained ACME certificate");
+                }
+                Ok(EventOk::DeployedCachedCert) => {
+                    info!(domains = %domains, "Loaded cached ACME certificate");
+                }
+                Ok(event) => debug!(?event, "ACME cache updated"),
+                Err(e) => warn!(domains = %domains, error = %e, "ACME certificate order failed"),
+            }
+        }
+    });
+
+    Ok(Some(acceptor))
+}
+
+/// TLS settings for the main listener: `[tls]` versions and ALPN, with
+/// certificates from `resolver` in place of `tls.cert`.
+fn server_config(tls: &TlsConfig, resolver: Arc<dyn ResolvesServerCert>) -> Result<ServerConfig> {
+    let mut server_config = tls::builder(tls)?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    server_config.alpn_protocols = tls::alpn_protocols(tls);
+    Ok(server_config)
+}
+
+/// Contact addresses are `mailto:` URIs; plain email addresses are accepted.
+fn contact_uri(email: &str) -> String {
+    if email.starts_with("mailto:") {
+        email.to_string()
+    } else {
+        format!("mailto:{email}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TlsVersion;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    #[test]
+    fn test_contact_uri() {
+        assert_eq!(contact_uri("ops@example.com"), "mailto:ops@example.com");
+        assert_eq!(
+            contact_uri("mailto:ops@example.com"),
+            "mailto:ops@example.com"
+        );
+    }
+
+    #[test]
+    fn test_server_config_follows_tls_settings() {
+        let resolver = rustls_acme::AcmeConfig::new(["pulse.example.com"])
+            .state()
+            .resolver();
+        let mut tls = TlsConfig::default();
+        let config = server_config(&tls, resolver.clone()).unwrap();
+        assert_eq!(config.alpn_protocols, vec![b"http/1.1".to_vec()]);
+
+        tls.alpn = vec!["h2".to_string(), "http/1.1".to_string()];
+        tls.min_version = TlsVersion::Tls13;
+        let config = server_config(&tls, resolver).unwrap();
+        assert_eq!(
+            config.alpn_protocols,
+            vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_start() {
+        assert!(start(&Config::default()).await.unwrap().is_none());
+
+        // Nothing is ordered until the state is polled in the background,
+        // so an unreachable CA doesn't hold up startup
+        let http_port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut config = Config {
+            host: "127.0.0.1".to_string(),
+            ..Config::default()
+        };
+        config.acme.domains = vec!["pulse.example.com".to_string()];
+        config.acme.directory_url = Some("http://127.0.0.1:1/directory".to_string());
+        config.acme.challenge = AcmeChallenge::Http01;
+        config.acme.http_port = http_port;
+        assert!(start(&config).await.unwrap().is_some());
+
+        // HTTP-01 challenges are answered on their own port
+        let mut stream = TcpStream::connect(("127.0.0.1", http_port)).await.unwrap();
+        stream
+            .write_all(b"GET /.well-known/acme-challenge/unknown HTTP/1.1\r\nHost: pulse.example.com\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 404"), "{response}");
+    }
+}
@@ -0,0 +1,580 @@
+//! Admin REST API for Pulse server.
+//!
+//! Mounted under `/admin` when `admin.token` is set. Every request must carry
+//! `Authorization: Bearer <token>`.
+//!
+//! - `GET /admin/channels` lists channels a page at a time, filtered by
+//!   `prefix`, `min_subscribers` and `user_id` and sorted by `name` or
+//!   `subscribers`.
+//! - `GET /admin/channels/:channel/subscribers` lists a channel's
+//!   subscribers a page at a time, filtered by `user_id`, `transport`,
+//!   `connected_after` and `connected_before` and sorted by `connection_id`
+//!   or `connected_at`. Both listings return `next_cursor` until the last
+//!   page; pass it back as `cursor` for the next one.
+//! - `GET /admin/hot-channels` estimates the `limit` channels with the most
+//!   messages and the most payload bytes per second over the last few
+//!   seconds.
+//! - `GET /admin/connections/:id` describes a connection: its user, IP,
+//!   upgrade request metadata, channels, queue and bytes transferred.
+//! - `POST /admin/connections/:id/kick` sends a Disconnect frame and closes
+//!   the connection.
+//! - `GET /admin/bans` lists active bans; `POST /admin/bans` bans a user ID or
+//!   IP for a duration and disconnects its live connections.
+//! - `DELETE /admin/bans/user/:user_id` and `DELETE /admin/bans/ip/:ip` lift
+//!   a ban.
+//! - `GET /admin/audit` returns recent audit events, filtered by `kind`,
+//!   `connection_id`, `user_id`, `ip` and `since`, newest `limit` last.
+//! - `GET /admin/diagnostics` returns a diagnostic snapshot, taking about a
+//!   second to measure channel message rates.
+//! - `POST /admin/publish` publishes a message to every channel matching a
+//!   pattern, or answers 429 with `Retry-After` while a subscriber queue of
+//!   a matching channel is fuller than `admin.publish_max_queue_fill`.
+//!   With `"trace": true`, each copy is traced to `$system:trace`.
+//! - `GET /admin/usage` lists the `limit` connections and users that
+//!   transferred the most bytes.
+//! - `POST /admin/drain` fails readiness, refuses new clients and, after
+//!   `shutdown.drain_delay_ms`, disconnects the connected ones, answering
+//!   once they are gone. Meant for a Kubernetes `preStop` hook.
+
+use crate::audit::{AuditEvent, AuditKind, AuditQuery};
+use crate::bans::{Ban, BanTarget};
+use crate::diagnostics::{Snapshot, RATE_WINDOW};
+use crate::forwarded;
+use crate::handlers::{self, AppState};
+use crate::usage::UserUsage;
+use axum::{
+    extract::{ConnectInfo, Path, Query, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use pulse_protocol::DisconnectReason;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tenvis_pulse_core::{
+    ChannelPattern, ChannelQuery, ChannelStats, CloseReason, ConnectionMetadata, HotChannelReport,
+    Message, Page, RouterError, SubscriberInfo, SubscriberQuery,
+};
+use tracing::info;
+
+/// Build the admin API routes.
+pub fn routes(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/admin/channels", get(list_channels))
+        .route(
+            "/admin/channels/:channel/subscribers",
+            get(list_subscribers),
+        )
+        .route("/admin/hot-channels", get(hot_channels))
+        .route("/admin/connections/:id", get(connection))
+        .route("/admin/connections/:id/kick", post(kick))
+        .route("/admin/bans", get(list_bans).post(create_ban))
+        .route("/admin/bans/user/:user_id", delete(unban_user))
+        .route("/admin/bans/ip/:ip", delete(unban_ip))
+        .route("/admin/audit", get(audit_events))
+        .route("/admin/diagnostics", get(diagnostics))
+        .route("/admin/publish", post(publish))
+        .route("/admin/usage", get(usage))
+        .route("/admin/drain", post(drain))
+        .route_layer(middleware::from_fn_with_state(state, require_token))
+}
+
+/// Reject requests without the configured bearer token.
+async fn require_token(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match (state.config.admin.token.as_deref(), provided) {
+        (Some(expected), Some(provided)) if constant_time_eq(expected, provided) => {
+            next.run(request).await
+        }
+        _ => {
+            let mut event = AuditEvent::new(
+                AuditKind::AuthFailure,
+                format!("Admin API {} {}", request.method(), request.uri().path()),
+            );
+            if let Some(ConnectInfo(addr)) = request.extensions().get::<ConnectInfo<SocketAddr>>() {
+                event = event.with_ip(forwarded::client_ip(
+                    addr.ip().to_canonical(),
+                    request.headers(),
+                    &state.config.transport.trusted_proxies,
+                ));
+            }
+            state.audit.record(event);
+            StatusCode::UNAUTHORIZED.into_response()
+        }
+    }
+}
+
+/// Compare secrets without leaking where they differ through timing.
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// A channel as listed by `GET /admin/channels`.
+#[derive(Debug, Serialize)]
+struct ChannelView {
+    channel: String,
+    subscribers: usize,
+    seq: u64,
+    history: usize,
+}
+
+impl From<ChannelStats> for ChannelView {
+    fn from(stats: ChannelStats) -> Self {
+        Self {
+            channel: stats.channel.to_string(),
+            subscribers: stats.subscribers,
+            seq: stats.seq,
+            history: stats.history,
+        }
+    }
+}
+
+/// List channels.
+async fn list_channels(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ChannelQuery>,
+) -> Result<Json<Page<ChannelView>>, Response> {
+    let page = state.router.list_channels(&query).map_err(listing_error)?;
+    Ok(Json(page.map(ChannelView::from)))
+}
+
+/// List a channel's subscribers.
+async fn list_subscribers(
+    State(state): State<Arc<AppState>>,
+    Path(channel): Path<String>,
+    Query(query): Query<SubscriberQuery>,
+) -> Result<Json<Page<SubscriberInfo>>, Response> {
+    state
+        .router
+        .list_subscribers(&channel, &query)
+        .map(Json)
+        .map_err(listing_error)
+}
+
+/// Query parameters of `GET /admin/hot-channels`.
+#[derive(Debug, Deserialize)]
+struct HotChannelsQuery {
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+/// Estimate the busiest channels by message and byte rate.
+async fn hot_channels(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<HotChannelsQuery>,
+) -> Json<HotChannelReport> {
+    let limit = query.limit.unwrap_or(state.config.diagnostics.top);
+    Json(state.router.hot_channels(limit))
+}
+
+fn listing_error(err: RouterError) -> Response {
+    let status = match err {
+        RouterError::ChannelNotFound(_) => StatusCode::NOT_FOUND,
+        _ => StatusCode::BAD_REQUEST,
+    };
+    (status, err.to_string()).into_response()
+}
+
+/// Body of a kick request.
+#[derive(Debug, Default, Deserialize)]
+struct KickRequest {
+    /// Message shown to the client.
+    #[serde(default)]
+    message: Option<String>,
+    /// How long the client should wait before reconnecting.
+    #[serde(default)]
+    reconnect_after_ms: Option<u64>,
+}
+
+/// A connection as described by `GET /admin/connections/:id`.
+#[derive(Debug, Serialize)]
+struct ConnectionView {
+    connection_id: String,
+    user_id: Option<String>,
+    ip: Option<IpAddr>,
+    metadata: ConnectionMetadata,
+    channels: Vec<String>,
+    queued: usize,
+    max_queued: usize,
+    dropped: u64,
+    bytes_in: u64,
+    bytes_out: u64,
+}
+
+/// Describe a connection.
+async fn connection(
+    State(state): State<Arc<AppState>>,
+    Path(connection_id): Path<String>,
+) -> Result<Json<ConnectionView>, StatusCode> {
+    let handle = state
+        .router
+        .handle(&connection_id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let mut channels = state.router.connection_channels(&connection_id);
+    channels.sort_unstable();
+    Ok(Json(ConnectionView {
+        user_id: handle.user_id().map(str::to_string),
+        ip: handle.remote_ip(),
+        metadata: handle.metadata().cloned().unwrap_or_default(),
+        channels,
+        queued: handle.len(),
+        max_queued: handle.max_queued(),
+        dropped: handle.dropped(),
+        bytes_in: handle.bytes_in(),
+        bytes_out: handle.bytes_out(),
+        connection_id,
+    }))
+}
+
+/// Kick a connection.
+async fn kick(
+    State(state): State<Arc<AppState>>,
+    Path(connection_id): Path<String>,
+    body: Option<Json<KickRequest>>,
+) -> StatusCode {
+    let request = body.map(|Json(request)| request).unwrap_or_default();
+    let Some(handle) = state.router.handle(&connection_id) else {
+        return StatusCode::NOT_FOUND;
+    };
+
+    let message = request
+        .message
+        .unwrap_or_else(|| "Kicked by an operator".to_string());
+    let mut reason = CloseReason::new(DisconnectReason::Kicked, message);
+    if let Some(ms) = request.reconnect_after_ms {
+        reason = reason.with_reconnect_after(Duration::from_millis(ms));
+    }
+    state.audit.record(AuditEvent::for_connection(
+        AuditKind::Kick,
+        &handle,
+        reason.message.clone(),
+    ));
+    handle.close_with(reason);
+
+    info!(connection = %connection_id, "Kicked connection");
+    StatusCode::NO_CONTENT
+}
+
+/// Body of a ban request. Exactly one of `user_id` and `ip` must be set.
+#[derive(Debug, Deserialize)]
+struct BanRequest {
+    #[serde(default)]
+    user_id: Option<String>,
+    #[serde(default)]
+    ip: Option<IpAddr>,
+    /// How long the ban lasts.
+    duration_secs: u64,
+    /// Reason shown to banned clients.
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+/// A ban as reported by the API.
+#[derive(Debug, Serialize)]
+struct BanView {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ip: Option<IpAddr>,
+    reason: String,
+    expires_in_secs: u64,
+}
+
+impl From<&Ban> for BanView {
+    fn from(ban: &Ban) -> Self {
+        let (user_id, ip) = match &ban.target {
+            BanTarget::User(user_id) => (Some(user_id.clone()), None),
+            BanTarget::Ip(ip) => (None, Some(*ip)),
+        };
+        Self {
+            user_id,
+            ip,
+            reason: ban.reason.clone(),
+            expires_in_secs: ban.remaining().as_secs(),
+        }
+    }
+}
+
+/// List active bans.
+async fn list_bans(State(state): State<Arc<AppState>>) -> Json<Vec<BanView>> {
+    Json(state.denylist.list().iter().map(BanView::from).collect())
+}
+
+/// Ban a user ID or IP and disconnect its live connections.
+async fn create_ban(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<BanRequest>,
+) -> Response {
+    let target = match (request.user_id, request.ip) {
+        (Some(user_id), None) => BanTarget::User(user_id),
+        (None, Some(ip)) => BanTarget::Ip(ip),
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "Specify exactly one of user_id and ip",
+            )
+                .into_response()
+        }
+    };
+
+    let reason = request.reason.unwrap_or_else(|| "Banned".to_string());
+    let ban = state
+        .denylist
+        .ban(target, Duration::from_secs(request.duration_secs), reason);
+    let disconnected = state
+        .router
+        .close_where(&ban.close_reason(), |handle| ban.applies_to(handle));
+
+    info!(target = %ban.target, duration_secs = request.duration_secs, disconnected, "Banned");
+    let detail = format!(
+        "{} ({}s, {disconnected} disconnected)",
+        ban.reason, request.duration_secs
+    );
+    state
+        .audit
+        .record(target_event(AuditKind::Ban, &ban.target, detail));
+    let body = serde_json::json!({
+        "ban": BanView::from(&ban),
+        "disconnected": disconnected,
+    });
+    (StatusCode::CREATED, Json(body)).into_response()
+}
+
+/// Lift a user ban.
+async fn unban_user(State(state): State<Arc<AppState>>, Path(user_id): Path<String>) -> StatusCode {
+    unban(&state, BanTarget::User(user_id))
+}
+
+/// Lift an IP ban.
+async fn unban_ip(State(state): State<Arc<AppState>>, Path(ip): Path<IpAddr>) -> StatusCode {
+    unban(&state, BanTarget::Ip(ip))
+}
+
+fn unban(state: &AppState, target: BanTarget) -> StatusCode {
+    if state.denylist.unban(&target) {
+        info!(target = %target, "Unbanned");
+        state
+            .audit
+            .record(target_event(AuditKind::Unban, &target, "Lifted"));
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+/// An audit event naming a ban's target.
+fn target_event(kind: AuditKind, target: &BanTarget, detail: impl Into<String>) -> AuditEvent {
+    let event = AuditEvent::new(kind, detail);
+    match target {
+        BanTarget::User(user_id) => event.with_user(user_id.as_str()),
+        BanTarget::Ip(ip) => event.with_ip(*ip),
+    }
+}
+
+/// Query recent audit events.
+async fn audit_events(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<AuditQuery>,
+) -> Json<Vec<AuditEvent>> {
+    Json(state.audit.query(&query))
+}
+
+/// Body of a pattern publish request.
+#[derive(Debug, Deserialize)]
+struct PublishRequest {
+    /// Channels to publish to, such as `region:eu:*`.
+    pattern: ChannelPattern,
+    /// Event name.
+    #[serde(default)]
+    event: Option<String>,
+    /// Message payload, published as UTF-8 text.
+    payload: String,
+    /// Trace the messages' routing and delivery.
+    #[serde(default)]
+    trace: bool,
+}
+
+/// Publish a message to every channel matching a pattern.
+async fn publish(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<PublishRequest>,
+) -> Response {
+    let max_message_size = state.config.limits.max_message_size;
+    if request.payload.len() > max_message_size {
+        return (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Payload exceeds limit of {max_message_size} bytes"),
+        )
+            .into_response();
+    }
+
+    // Refuse rather than push out messages subscribers have yet to read
+    let max_fill = state.config.admin.publish_max_queue_fill;
+    if max_fill > 0 && state.router.queue_fill(&request.pattern) * 100.0 >= f64::from(max_fill) {
+        info!(pattern = %request.pattern, "Refused publish to saturated channels");
+        let retry_after = Duration::from_millis(state.config.admin.publish_retry_after_ms.into());
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(
+                header::RETRY_AFTER,
+                retry_after.as_secs().max(1).to_string(),
+            )],
+            "Subscriber queues are full",
+        )
+            .into_response();
+    }
+
+    let mut message = Message::new(request.pattern.as_str(), request.payload);
+    if let Some(event) = request.event {
+        message = message.with_event(event);
+    }
+    if request.trace {
+        message = message.with_trace();
+    }
+    let receipt = state.router.publish_pattern(&request.pattern, message);
+
+    info!(pattern = %request.pattern, channels = receipt.channels, recipients = receipt.recipients, "Published to pattern");
+    Json(serde_json::json!({
+        "channels": receipt.channels,
+        "recipients": receipt.recipients,
+    }))
+    .into_response()
+}
+
+/// Query parameters of `GET /admin/usage`.
+#[derive(Debug, Deserialize)]
+struct UsageQuery {
+    #[serde(default = "default_usage_limit")]
+    limit: usize,
+}
+
+fn default_usage_limit() -> usize {
+    10
+}
+
+/// A live connection's bandwidth, as listed by `GET /admin/usage`.
+#[derive(Debug, Serialize)]
+struct ConnectionUsage {
+    connection_id: String,
+    user_id: Option<String>,
+    bytes_in: u64,
+    bytes_out: u64,
+}
+
+/// The top talkers reported by `GET /admin/usage`.
+#[derive(Debug, Serialize)]
+struct UsageView {
+    /// Live connections, by bytes transferred.
+    connections: Vec<ConnectionUsage>,
+    /// Users, by bytes transferred this month.
+    users: Vec<UserUsage>,
+}
+
+/// List the connections and users transferring the most.
+async fn usage(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<UsageQuery>,
+) -> Json<UsageView> {
+    let mut connections: Vec<_> = state
+        .router
+        .handles()
+        .iter()
+        .map(|h| ConnectionUsage {
+            connection_id: h.id().to_string(),
+            user_id: h.user_id().map(str::to_string),
+            bytes_in: h.bytes_in(),
+            bytes_out: h.bytes_out(),
+        })
+        .collect();
+    connections.sort_unstable_by_key(|c| std::cmp::Reverse(c.bytes_in + c.bytes_out));
+    connections.truncate(query.limit);
+    Json(UsageView {
+        connections,
+        users: state.usage.top_users(query.limit),
+    })
+}
+
+/// Drain the server ahead of termination, answering once clients are
+/// disconnected.
+async fn drain(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    info!("Drain requested");
+    handlers::drain(&state).await;
+    Json(serde_json::json!({
+        "draining": true,
+        "connections": state.router.connection_count(),
+    }))
+}
+
+/// Take a diagnostic snapshot.
+async fn diagnostics(State(state): State<Arc<AppState>>) -> Json<Snapshot> {
+    Json(Snapshot::take(&state, RATE_WINDOW).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq("s3cret", "s3cret"));
+        assert!(!constant_time_eq("s3cret", "s3creT"));
+        assert!(!constant_time_eq("s3cret", "s3cret!"));
+    }
+
+    #[tokio::test]
+    async fn test_publish_backpressure() {
+        let mut config = crate::config::Config::default();
+        config.limits.max_queued_messages = 4;
+        config.admin.publish_retry_after_ms = 2500;
+        let state = Arc::new(AppState::new(config).unwrap());
+        let fast = state.router.connect("conn-1");
+        let slow = state.router.connect("conn-2");
+        for handle in [&fast, &slow] {
+            state
+                .router
+                .subscribe_handle(handle, "region:eu:1", None)
+                .unwrap();
+        }
+        let request = || PublishRequest {
+            pattern: ChannelPattern::new("region:*"),
+            event: None,
+            payload: "update".to_string(),
+            trace: false,
+        };
+
+        for _ in 0..3 {
+            let response = publish(State(state.clone()), Json(request())).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            while fast.try_recv().is_some() {}
+        }
+
+        // One subscriber is behind while the other keeps up
+        let response = publish(State(state.clone()), Json(request())).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = publish(State(state.clone()), Json(request())).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "2");
+        assert_eq!(slow.len(), 4);
+
+        while slow.try_recv().is_some() {}
+        let response = publish(State(state.clone()), Json(request())).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,95 @@
+//! Sticky session affinity tokens.
+//!
+//! Durable subscriptions and presence live on the server a client was
+//! connected to, so a client should reconnect to the same server when it can.
+//! With `affinity.enabled`, the server names itself with a token: in the
+//! Connected frame, in the Socket.IO open packet and in the `X-Pulse-Affinity`
+//! header of upgrade responses. Clients present it again when reconnecting,
+//! as `?affinity=<token>` on the URL or in the same header, and the load
+//! balancer (or a redirect layer in front of it) maps the token to its
+//! server.
+//!
+//! A client presenting another server's token lost its affinity, usually
+//! because that server went away. Presented tokens are counted in
+//! `pulse_affinity_total` by outcome (`hit` or `miss`).
+
+use crate::metrics;
+use crate::stats::Transport;
+use axum::http::header::HeaderName;
+use axum::http::{HeaderMap, HeaderValue};
+use axum::response::Response;
+use serde::Deserialize;
+use tracing::debug;
+
+/// Header carrying the token on upgrade requests and responses.
+pub const HEADER: HeaderName = HeaderName::from_static("x-pulse-affinity");
+
+/// Query parameters of a WebSocket upgrade.
+#[derive(Debug, Default, Deserialize)]
+pub struct AffinityQuery {
+    /// Token the client was given by the server it last connected to.
+    affinity: Option<String>,
+}
+
+impl AffinityQuery {
+    /// The token in the query, if any.
+    #[must_use]
+    pub fn token(&self) -> Option<&str> {
+        self.affinity.as_deref()
+    }
+}
+
+/// Get the token a client presented, from the query or the header.
+#[must_use]
+pub fn presented<'a>(query: Option<&'a str>, headers: &'a HeaderMap) -> Option<&'a str> {
+    query
+        .or_else(|| headers.get(&HEADER).and_then(|v| v.to_str().ok()))
+        .filter(|token| !token.is_empty())
+}
+
+/// Count whether a client presenting a token reached the server it names.
+pub fn check(token: Option<&str>, presented: Option<&str>, transport: Transport) {
+    let (Some(token), Some(presented)) = (token, presented) else {
+        return;
+    };
+    if presented == token {
+        metrics::record_affinity("hit");
+    } else {
+        debug!(
+            presented,
+            transport = transport.as_str(),
+            "Client reconnected away from its server"
+        );
+        metrics::record_affinity("miss");
+    }
+}
+
+/// Add the token to an upgrade response.
+#[must_use]
+pub fn tag(mut response: Response, token: Option<&str>) -> Response {
+    if let Some(value) = token.and_then(|t| HeaderValue::from_str(t).ok()) {
+        response.headers_mut().insert(HEADER, value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presented() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(presented(None, &headers), None);
+
+        headers.insert(HEADER, HeaderValue::from_static("node-2"));
+        assert_eq!(presented(None, &headers), Some("node-2"));
+        // The query wins over the header
+        assert_eq!(presented(Some("node-1"), &headers), Some("node-1"));
+        assert_eq!(presented(Some(""), &HeaderMap::new()), None);
+
+        let response = tag(Response::default(), Some("node-1"));
+        assert_eq!(response.headers()[HEADER], "node-1");
+        assert!(tag(Response::default(), None).headers().is_empty());
+    }
+}
@@ -0,0 +1,453 @@
+//! Audit log of security-relevant events.
+//!
+//! Connects and disconnects with the client's identity, auth failures,
+//! access denials, and admin actions are recorded as JSON events. Recent
+//! events are kept in memory for `GET /admin/audit`; each event can also be
+//! appended to a rotated file and published to a channel:
+//!
+//! ```json
+//! {"timestamp": 1700000000000, "kind": "ban", "ip": "203.0.113.7",
+//!  "detail": "Abuse (3600s, 1 disconnected)"}
+//! ```
+
+use crate::config::AuditConfig;
+use crate::handlers::AppState;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tenvis_pulse_core::{ConnectionHandle, Message};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tracing::error;
+
+/// Source of audit messages published to the audit channel.
+const AUDIT_SOURCE: &str = "audit";
+
+/// Events returned by a query when it sets no limit.
+const DEFAULT_QUERY_LIMIT: usize = 100;
+
+/// What an audit event records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditKind {
+    /// A client connected.
+    Connect,
+    /// A client identified itself with a user ID.
+    Identify,
+    /// A client disconnected.
+    Disconnect,
+    /// A credential was missing or wrong.
+    AuthFailure,
+    /// A client was refused by an IP filter, ban or limit.
+    AccessDenied,
+    /// An operator kicked a connection.
+    Kick,
+    /// An operator banned a user ID or IP.
+    Ban,
+    /// An operator lifted a ban.
+    Unban,
+}
+
+impl AuditKind {
+    /// The event's name, as serialized.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Connect => "connect",
+            Self::Identify => "identify",
+            Self::Disconnect => "disconnect",
+            Self::AuthFailure => "auth_failure",
+            Self::AccessDenied => "access_denied",
+            Self::Kick => "kick",
+            Self::Ban => "ban",
+            Self::Unban => "unban",
+        }
+    }
+}
+
+/// A recorded audit event.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEvent {
+    /// When the event happened, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    /// What happened.
+    pub kind: AuditKind,
+    /// The connection involved.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection_id: Option<String>,
+    /// The user involved.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    /// The client IP involved.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<IpAddr>,
+    /// Human-readable details.
+    pub detail: String,
+}
+
+impl AuditEvent {
+    /// Create an event happening now.
+    #[must_use]
+    pub fn new(kind: AuditKind, detail: impl Into<String>) -> Self {
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            kind,
+            connection_id: None,
+            user_id: None,
+            ip: None,
+            detail: detail.into(),
+        }
+    }
+
+    /// Create an event about a connection, with its identity.
+    #[must_use]
+    pub fn for_connection(
+        kind: AuditKind,
+        handle: &ConnectionHandle,
+        detail: impl Into<String>,
+    ) -> Self {
+        let mut event = Self::new(kind, detail).with_connection(handle.id());
+        if let Some(user_id) = handle.user_id() {
+            event = event.with_user(user_id);
+        }
+        if let Some(ip) = handle.remote_ip() {
+            event = event.with_ip(ip);
+        }
+        event
+    }
+
+    /// Set the connection involved.
+    #[must_use]
+    pub fn with_connection(mut self, connection_id: impl Into<String>) -> Self {
+        self.connection_id = Some(connection_id.into());
+        self
+    }
+
+    /// Set the user involved.
+    #[must_use]
+    pub fn with_user(mut self, user_id: impl Into<String>) -> Self {
+        self.user_id = Some(user_id.into());
+        self
+    }
+
+    /// Set the client IP involved.
+    #[must_use]
+    pub fn with_ip(mut self, ip: IpAddr) -> Self {
+        self.ip = Some(ip);
+        self
+    }
+}
+
+/// Filters for querying recent events. Unset fields match everything.
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    #[serde(default)]
+    pub kind: Option<AuditKind>,
+    #[serde(default)]
+    pub connection_id: Option<String>,
+    #[serde(default)]
+    pub user_id: Option<String>,
+    #[serde(default)]
+    pub ip: Option<IpAddr>,
+    /// Only events at or after this time, in milliseconds since the epoch.
+    #[serde(default)]
+    pub since: Option<u64>,
+    /// Return at most this many of the newest matching events.
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    /// Whether an event passes the filters.
+    #[must_use]
+    pub fn matches(&self, event: &AuditEvent) -> bool {
+        self.kind.map_or(true, |kind| event.kind == kind)
+            && self
+                .connection_id
+                .as_ref()
+                .map_or(true, |id| event.connection_id.as_ref() == Some(id))
+            && self
+                .user_id
+                .as_ref()
+                .map_or(true, |id| event.user_id.as_ref() == Some(id))
+            && self.ip.map_or(true, |ip| event.ip == Some(ip))
+            && self.since.map_or(true, |since| event.timestamp >= since)
+    }
+}
+
+/// The server's audit log.
+///
+/// Recording never blocks: events are kept in memory and handed to a
+/// background task that writes them out.
+#[derive(Debug)]
+pub struct AuditLog {
+    enabled: bool,
+    retain: usize,
+    recent: Mutex<VecDeque<AuditEvent>>,
+    sender: Option<mpsc::UnboundedSender<AuditEvent>>,
+    receiver: Mutex<Option<mpsc::UnboundedReceiver<AuditEvent>>>,
+}
+
+impl AuditLog {
+    /// Create an audit log for a configuration.
+    #[must_use]
+    pub fn new(config: &AuditConfig) -> Self {
+        // Only queue events if something will drain them
+        let outputs = config.enabled && (config.file.is_some() || config.channel.is_some());
+        let (sender, receiver) = if outputs {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            (Some(sender), Some(receiver))
+        } else {
+            (None, None)
+        };
+        Self {
+            enabled: config.enabled,
+            retain: config.retain,
+            recent: Mutex::new(VecDeque::new()),
+            sender,
+            receiver: Mutex::new(receiver),
+        }
+    }
+
+    /// Record an event.
+    pub fn record(&self, event: AuditEvent) {
+        if !self.enabled {
+            return;
+        }
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(event.clone());
+        }
+        if self.retain > 0 {
+            let mut recent = self.lock();
+            if recent.len() == self.retain {
+                recent.pop_front();
+            }
+            recent.push_back(event);
+        }
+    }
+
+    /// Recent events matching a query, oldest first.
+    #[must_use]
+    pub fn query(&self, query: &AuditQuery) -> Vec<AuditEvent> {
+        let limit = query.limit.unwrap_or(DEFAULT_QUERY_LIMIT);
+        let recent = self.lock();
+        let mut events: Vec<AuditEvent> = recent
+            .iter()
+            .rev()
+            .filter(|event| query.matches(event))
+            .take(limit)
+            .cloned()
+            .collect();
+        events.reverse();
+        events
+    }
+
+    fn lock(&self) -> MutexGuard<'_, VecDeque<AuditEvent>> {
+        self.recent.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Start writing audit events to the configured file and channel.
+///
+/// # Errors
+///
+/// Returns an error if the audit file cannot be opened.
+pub async fn spawn(state: &Arc<AppState>) -> Result<()> {
+    let receiver = state
+        .audit
+        .receiver
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take();
+    let Some(receiver) = receiver else {
+        return Ok(());
+    };
+
+    let config = &state.config.audit;
+    let file = match &config.file {
+        Some(path) => Some(
+            AuditFile::open(path.clone(), config.max_file_size, config.max_files)
+                .await
+                .with_context(|| format!("Failed to open audit log {}", path.display()))?,
+        ),
+        None => None,
+    };
+    tokio::spawn(write_events(state.clone(), receiver, file));
+    Ok(())
+}
+
+/// Write events out until the log is dropped.
+async fn write_events(
+    state: Arc<AppState>,
+    mut receiver: mpsc::UnboundedReceiver<AuditEvent>,
+    mut file: Option<AuditFile>,
+) {
+    while let Some(event) = receiver.recv().await {
+        let json = serde_json::to_string(&event).expect("audit events serialize");
+        if let Some(file) = &mut file {
+            if let Err(e) = file.append(&json).await {
+                error!(path = %file.path.display(), error = %e, "Failed to write audit log");
+            }
+        }
+        if let Some(channel) = &state.config.audit.channel {
+            let message = Message::new(channel.as_str(), json.into_bytes())
+                .with_event(event.kind.as_str())
+                .with_source(AUDIT_SOURCE);
+            state.router.publish(message);
+        }
+    }
+}
+
+/// An append-only JSON lines file, rotated by size.
+struct AuditFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl AuditFile {
+    async fn open(path: PathBuf, max_size: u64, max_files: usize) -> std::io::Result<Self> {
+        let file = open_append(&path).await?;
+        let size = file.metadata().await?.len();
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_size,
+            max_files,
+        })
+    }
+
+    /// Append one event, rotating first if it would exceed the size limit.
+    async fn append(&mut self, json: &str) -> std::io::Result<()> {
+        let len = json.len() as u64 + 1;
+        if self.max_size > 0 && self.size > 0 && self.size + len > self.max_size {
+            self.rotate().await?;
+        }
+        let mut line = String::with_capacity(json.len() + 1);
+        line.push_str(json);
+        line.push('\n');
+        self.file.write_all(line.as_bytes()).await?;
+        self.file.flush().await?;
+        self.size += len;
+        Ok(())
+    }
+
+    /// Shift `audit.log` to `audit.log.1`, `audit.log.1` to `audit.log.2`, and
+    /// so on, dropping the oldest, then start a new file.
+    async fn rotate(&mut self) -> std::io::Result<()> {
+        if self.max_files == 0 {
+            self.file.set_len(0).await?;
+        } else {
+            for n in (1..self.max_files).rev() {
+                match tokio::fs::rename(
+                    rotated_path(&self.path, n),
+                    rotated_path(&self.path, n + 1),
+                )
+                .await
+                {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            tokio::fs::rename(&self.path, rotated_path(&self.path, 1)).await?;
+            self.file = open_append(&self.path).await?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+async fn open_append(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+}
+
+/// The path of the `n`th rotated file.
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> AuditConfig {
+        AuditConfig {
+            enabled: true,
+            retain: 3,
+            ..AuditConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_audit_query() {
+        let log = AuditLog::new(&config());
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        log.record(AuditEvent::new(AuditKind::Connect, "websocket").with_connection("c1"));
+        log.record(AuditEvent::new(AuditKind::Ban, "Abuse").with_ip(ip));
+        log.record(AuditEvent::new(AuditKind::Connect, "websocket").with_connection("c2"));
+        log.record(AuditEvent::new(AuditKind::Connect, "websocket").with_connection("c3"));
+
+        // Only the newest `retain` events are kept
+        let all = log.query(&AuditQuery::default());
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0].kind, AuditKind::Ban);
+
+        let query = AuditQuery {
+            kind: Some(AuditKind::Connect),
+            limit: Some(1),
+            ..AuditQuery::default()
+        };
+        let connects = log.query(&query);
+        assert_eq!(connects.len(), 1);
+        assert_eq!(connects[0].connection_id.as_deref(), Some("c3"));
+
+        let query = AuditQuery {
+            ip: Some(ip),
+            ..AuditQuery::default()
+        };
+        assert_eq!(log.query(&query)[0].detail, "Abuse");
+    }
+
+    #[test]
+    fn test_audit_disabled() {
+        let log = AuditLog::new(&AuditConfig::default());
+        log.record(AuditEvent::new(AuditKind::Connect, "websocket"));
+        assert!(log.query(&AuditQuery::default()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_audit_file_rotation() {
+        let dir = std::env::temp_dir().join(format!("pulse-audit-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.log");
+
+        let mut file = AuditFile::open(path.clone(), 10, 2).await.unwrap();
+        for line in ["first", "second", "third", "fourth"] {
+            file.append(line).await.unwrap();
+        }
+
+        let read = |n| std::fs::read_to_string(rotated_path(&path, n)).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(read(1), "third\n");
+        assert_eq!(read(2), "second\n");
+        assert!(!rotated_path(&path, 3).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
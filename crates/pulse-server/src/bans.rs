@@ -0,0 +1,156 @@
+//! In-memory denylist for Pulse server.
+//!
+//! Operators ban a user ID or an IP address for a duration through the admin
+//! API. Banned IPs are refused at upgrade time, and banned users when their
+//! Connect frame identifies them. Bans are not persisted across restarts.
+
+use dashmap::DashMap;
+use pulse_protocol::DisconnectReason;
+use std::fmt;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use tenvis_pulse_core::{CloseReason, ConnectionHandle};
+
+/// Stand-in for bans too long to represent, about a century.
+const FOREVER: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+/// What a ban applies to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum BanTarget {
+    /// An application user ID, as sent in the Connect frame.
+    User(String),
+    /// A client IP address.
+    Ip(IpAddr),
+}
+
+impl fmt::Display for BanTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BanTarget::User(user_id) => write!(f, "user {user_id}"),
+            BanTarget::Ip(ip) => write!(f, "ip {ip}"),
+        }
+    }
+}
+
+/// An active ban.
+#[derive(Debug, Clone)]
+pub struct Ban {
+    /// What is banned.
+    pub target: BanTarget,
+    /// Why, shown to the client when it is turned away.
+    pub reason: String,
+    /// When the ban lapses.
+    pub expires_at: Instant,
+}
+
+impl Ban {
+    /// Get how long the ban has left.
+    #[must_use]
+    pub fn remaining(&self) -> Duration {
+        self.expires_at.saturating_duration_since(Instant::now())
+    }
+
+    /// Check if the ban covers a connection.
+    #[must_use]
+    pub fn applies_to(&self, handle: &ConnectionHandle) -> bool {
+        match &self.target {
+            BanTarget::User(user_id) => handle.user_id() == Some(user_id.as_str()),
+            BanTarget::Ip(ip) => handle.remote_ip() == Some(*ip),
+        }
+    }
+
+    /// Get the reason banned connections are closed with.
+    #[must_use]
+    pub fn close_reason(&self) -> CloseReason {
+        CloseReason::new(DisconnectReason::Kicked, self.reason.clone())
+    }
+}
+
+/// Bans by user ID and IP address, expiring after their duration.
+#[derive(Debug, Default)]
+pub struct Denylist {
+    bans: DashMap<BanTarget, Ban>,
+}
+
+impl Denylist {
+    /// Create an empty denylist.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ban a target for a duration, replacing any existing ban on it.
+    pub fn ban(&self, target: BanTarget, duration: Duration, reason: impl Into<String>) -> Ban {
+        let now = Instant::now();
+        let ban = Ban {
+            target: target.clone(),
+            reason: reason.into(),
+            expires_at: now.checked_add(duration).unwrap_or_else(|| now + FOREVER),
+        };
+        self.bans.insert(target, ban.clone());
+        ban
+    }
+
+    /// Lift a ban. Returns `false` if the target was not banned.
+    pub fn unban(&self, target: &BanTarget) -> bool {
+        self.check(target).is_some() && self.bans.remove(target).is_some()
+    }
+
+    /// Get the active ban on a target, if any.
+    ///
+    /// Expired bans are removed as they are found.
+    pub fn check(&self, target: &BanTarget) -> Option<Ban> {
+        let ban = self.bans.get(target)?.clone();
+        if ban.expires_at <= Instant::now() {
+            self.bans
+                .remove_if(target, |_, b| b.expires_at <= Instant::now());
+            return None;
+        }
+        Some(ban)
+    }
+
+    /// Get every active ban.
+    pub fn list(&self) -> Vec<Ban> {
+        let now = Instant::now();
+        self.bans.retain(|_, ban| ban.expires_at > now);
+        self.bans.iter().map(|ban| ban.value().clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_denylist_ban_and_unban() {
+        let denylist = Denylist::new();
+        let user = BanTarget::User("alice".to_string());
+        let ip = BanTarget::Ip("10.0.0.1".parse().unwrap());
+
+        denylist.ban(user.clone(), Duration::from_secs(60), "Spam");
+        assert_eq!(denylist.check(&user).unwrap().reason, "Spam");
+        assert!(denylist.check(&ip).is_none());
+        assert!(denylist
+            .check(&BanTarget::User("bob".to_string()))
+            .is_none());
+
+        denylist.ban(ip.clone(), Duration::from_secs(60), "Abuse");
+        assert_eq!(denylist.list().len(), 2);
+
+        assert!(denylist.unban(&user));
+        assert!(!denylist.unban(&user));
+        assert!(denylist.check(&user).is_none());
+        assert_eq!(denylist.list().len(), 1);
+    }
+
+    #[test]
+    fn test_denylist_bans_expire() {
+        let denylist = Denylist::new();
+        let ip = BanTarget::Ip("::1".parse().unwrap());
+
+        denylist.ban(ip.clone(), Duration::ZERO, "Brief");
+        assert!(denylist.check(&ip).is_none());
+        assert!(denylist.list().is_empty());
+        assert!(!denylist.unban(&ip));
+    }
+}
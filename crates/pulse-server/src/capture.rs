@@ -0,0 +1,260 @@
+//! Traffic capture.
+//!
+//! With `capture.channels` set, a recorder subscribes to those patterns and
+//! appends every publish to `capture.file` with the time it was published,
+//! in the format of `pulse_protocol::capture`. `pulse-cli replay` publishes
+//! a capture again with the original pacing, to reproduce production issues
+//! or load-test with realistic traffic.
+//!
+//! An existing capture file is appended to, and recording stops once it
+//! reaches `capture.max_file_size`. While records are written, new messages
+//! wait in the recorder's connection queue, subject to the channel's drop
+//! policy, so a slow disk loses records rather than slowing publishers down.
+
+use crate::config::CaptureConfig;
+use crate::handlers::AppState;
+use crate::metrics;
+use anyhow::{bail, Context, Result};
+use bytes::BytesMut;
+use pulse_protocol::{capture, Frame};
+use std::path::Path;
+use std::sync::Arc;
+use tenvis_pulse_core::{ConnectionHandle, Message, MessageKind};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::{error, info, warn};
+
+/// Connection ID of the recorder.
+const RECORDER_ID: &str = "capture";
+
+/// Start recording, if any channels are configured.
+///
+/// # Errors
+///
+/// Returns an error if the capture file cannot be opened or is not a capture
+/// file.
+pub async fn spawn(state: &Arc<AppState>) -> Result<()> {
+    let config = &state.config.capture;
+    if !config.enabled() {
+        return Ok(());
+    }
+
+    let (file, size) = open(&config.file)
+        .await
+        .with_context(|| format!("Failed to open capture file {}", config.file.display()))?;
+    let handle = state.router.connect(RECORDER_ID);
+    for pattern in &config.channels {
+        state.router.subscribe_pattern(&handle, pattern.clone())?;
+    }
+    info!(path = %config.file.display(), channels = config.channels.len(), "Capturing traffic");
+    tokio::spawn(record(state.clone(), handle, file, size));
+    Ok(())
+}
+
+/// Open a capture file for appending, writing the header if it is new.
+/// Returns the file and its size.
+async fn open(path: &Path) -> Result<(File, u64)> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)
+        .await?;
+    let size = file.metadata().await?.len();
+    if size == 0 {
+        let mut header = BytesMut::with_capacity(capture::HEADER_SIZE);
+        capture::encode_header(&mut header);
+        file.write_all(&header).await?;
+        return Ok((file, header.len() as u64));
+    }
+
+    let mut header = [0u8; capture::HEADER_SIZE];
+    if file.read_exact(&mut header).await.is_err() {
+        bail!("not a capture file");
+    }
+    capture::check_header(&header)?;
+    Ok((file, size))
+}
+
+/// Write messages out until the recorder is disconnected or the file is
+/// full.
+async fn record(state: Arc<AppState>, handle: Arc<ConnectionHandle>, mut file: File, size: u64) {
+    let config = &state.config.capture;
+    let mut writer = Writer {
+        config,
+        size,
+        buf: BytesMut::with_capacity(64 * 1024),
+    };
+
+    while let Some(first) = handle.recv().await {
+        // Write whatever else is queued along with it
+        let mut next = Some(first);
+        while let Some(msg) = next {
+            writer.push(&msg);
+            next = handle.try_recv();
+        }
+        if writer.buf.is_empty() {
+            continue;
+        }
+
+        let result = async {
+            file.write_all(&writer.buf).await?;
+            file.flush().await
+        }
+        .await;
+        writer.buf.clear();
+        if let Err(e) = result {
+            error!(path = %config.file.display(), error = %e, "Failed to write capture file");
+        }
+        metrics::set_capture_bytes(writer.size);
+
+        if writer.full() {
+            warn!(
+                path = %config.file.display(),
+                max_file_size = config.max_file_size,
+                "Capture file is full; recording stopped"
+            );
+            break;
+        }
+    }
+    state.router.disconnect(&handle);
+}
+
+/// Encodes records up to the file's size limit.
+struct Writer<'a> {
+    config: &'a CaptureConfig,
+    /// Size of the file once the buffer is written.
+    size: u64,
+    buf: BytesMut,
+}
+
+impl Writer<'_> {
+    /// Encode a message, unless it is not a publish or the file is full.
+    fn push(&mut self, msg: &Message) {
+        if msg.kind != MessageKind::Publish || self.full() {
+            return;
+        }
+        let start = self.buf.len();
+        if let Err(e) = capture::encode_record(msg.timestamp, &publish_frame(msg), &mut self.buf) {
+            warn!(channel = %msg.channel, error = %e, "Failed to encode capture record");
+            return;
+        }
+        let len = (self.buf.len() - start) as u64;
+        if self.config.max_file_size > 0 && self.size + len > self.config.max_file_size {
+            self.buf.truncate(start);
+            // Mark the file full so nothing smaller slips in after
+            self.size = self.config.max_file_size;
+            return;
+        }
+        self.size += len;
+        metrics::record_capture();
+    }
+
+    fn full(&self) -> bool {
+        self.config.max_file_size > 0 && self.size >= self.config.max_file_size
+    }
+}
+
+/// The Publish frame that re-publishes a message.
+fn publish_frame(msg: &Message) -> Frame {
+    Frame::Publish {
+        id: None,
+        channel: msg.channel.to_string(),
+        event: msg.event.clone(),
+        seq: None,
+        message_id: None,
+        timestamp: None,
+        priority: msg.priority,
+        coalesce_key: msg.coalesce_key.clone(),
+        group_id: None,
+        encrypted: msg.encrypted,
+        key_id: msg.key_id.clone(),
+        signer: msg.signer.clone(),
+        signature: msg.signature.clone(),
+        origin: None,
+        trace: false,
+        payload: msg.payload.to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_capture_file() {
+        let dir = std::env::temp_dir().join(format!("pulse-capture-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("traffic.cap");
+        let _ = std::fs::remove_file(&path);
+
+        let config = CaptureConfig {
+            channels: vec!["chat:*".into()],
+            file: path.clone(),
+            max_file_size: 0,
+        };
+        let (_, size) = open(&path).await.unwrap();
+        assert_eq!(size, capture::HEADER_SIZE as u64);
+
+        let mut writer = Writer {
+            config: &config,
+            size,
+            buf: BytesMut::new(),
+        };
+        let mut message = Message::new("chat:lobby", "hi").with_event("message");
+        message.timestamp = 1_700_000_000_000;
+        writer.push(&message);
+        let mut presence = Message::new("chat:lobby", "{}");
+        presence.kind = MessageKind::Presence(pulse_protocol::PresenceAction::Join);
+        writer.push(&presence);
+
+        let record = capture::decode_record(&mut writer.buf).unwrap().unwrap();
+        assert_eq!(record.timestamp, 1_700_000_000_000);
+        let Frame::Publish {
+            channel,
+            event,
+            payload,
+            ..
+        } = record.frame
+        else {
+            panic!("expected a Publish frame");
+        };
+        assert_eq!(channel, "chat:lobby");
+        assert_eq!(event.as_deref(), Some("message"));
+        assert_eq!(payload, b"hi");
+        // Presence changes are not recorded
+        assert!(writer.buf.is_empty());
+
+        // Reopening appends after the existing header; other files are refused
+        let (_, size) = open(&path).await.unwrap();
+        assert_eq!(size, capture::HEADER_SIZE as u64);
+        std::fs::write(&path, b"not a capture").unwrap();
+        assert!(open(&path).await.is_err());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_capture_size_limit() {
+        let config = CaptureConfig {
+            channels: vec!["chat:*".into()],
+            max_file_size: 100,
+            ..CaptureConfig::default()
+        };
+        let mut writer = Writer {
+            config: &config,
+            size: capture::HEADER_SIZE as u64,
+            buf: BytesMut::new(),
+        };
+        writer.push(&Message::new("chat:lobby", "hi"));
+        assert!(!writer.buf.is_empty());
+        let written = writer.buf.len();
+
+        // A record that would overflow the limit stops recording
+        writer.push(&Message::new("chat:lobby", vec![0u8; 100]));
+        assert_eq!(writer.buf.len(), written);
+        assert!(writer.full());
+        writer.push(&Message::new("chat:lobby", "hi"));
+        assert_eq!(writer.buf.len(), written);
+    }
+}
@@ -0,0 +1,111 @@
+//! Fault injection for testing clients.
+//!
+//! Built with the `chaos` feature, `[[chaos]]` rules make deliveries on
+//! matching channels misbehave at random: frames are delayed, skipped, lost
+//! together with the rest of the subscriber's queue as if it had lagged, or
+//! the connection is reset. Client reconnect, gap detection and replay logic
+//! can then be exercised against a server that fails the way production
+//! ones do, only more often.
+
+use crate::config::ChaosRule;
+use std::time::Duration;
+use tenvis_pulse_core::{ConnectionHandle, Message};
+use tracing::debug;
+
+/// A fault to inject into a delivery.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Hold the frame, and the connection's later frames, this long.
+    Delay(Duration),
+    /// Skip the delivery.
+    Drop,
+    /// Skip the delivery and discard everything queued behind it.
+    Lag,
+    /// Drop the connection without a close handshake.
+    Reset,
+}
+
+/// Pick the fault, if any, for delivering a message.
+pub fn fault(rules: &[ChaosRule], message: &Message) -> Option<Fault> {
+    let rule = rules
+        .iter()
+        .find(|rule| rule.channels.iter().any(|p| p.matches(&message.channel)))?;
+    let fault = roll(rule, rand::random);
+    if let Some(fault) = fault {
+        debug!(channel = %message.channel, ?fault, "Injecting fault");
+    }
+    fault
+}
+
+/// Pick a fault with `random` returning fractions in `[0, 1)`, trying the
+/// most disruptive first.
+fn roll(rule: &ChaosRule, mut random: impl FnMut() -> f64) -> Option<Fault> {
+    let mut hit = |percent: f64| percent > 0.0 && random() * 100.0 < percent;
+    if hit(rule.reset_percent) {
+        Some(Fault::Reset)
+    } else if hit(rule.lag_percent) {
+        Some(Fault::Lag)
+    } else if hit(rule.drop_percent) {
+        Some(Fault::Drop)
+    } else if hit(rule.delay_percent) {
+        let delay = Duration::from_millis(rule.max_delay_ms).mul_f64(random());
+        Some(Fault::Delay(delay))
+    } else {
+        None
+    }
+}
+
+/// Discard a connection's queued messages, returning how many there were.
+pub fn lag(handle: &ConnectionHandle) -> usize {
+    std::iter::from_fn(|| handle.try_recv()).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chaos_roll() {
+        let rule = ChaosRule {
+            channels: vec!["flaky:*".into()],
+            delay_percent: 50.0,
+            max_delay_ms: 1000,
+            drop_percent: 10.0,
+            ..ChaosRule::default()
+        };
+        assert_eq!(roll(&rule, || 0.05), Some(Fault::Drop));
+        assert_eq!(
+            roll(&rule, || 0.25),
+            Some(Fault::Delay(Duration::from_millis(250)))
+        );
+        assert_eq!(roll(&rule, || 0.75), None);
+
+        let rule = ChaosRule {
+            reset_percent: 100.0,
+            ..rule
+        };
+        assert_eq!(roll(&rule, || 0.99), Some(Fault::Reset));
+    }
+
+    #[test]
+    fn test_chaos_rules() {
+        let rules = vec![ChaosRule {
+            channels: vec!["flaky:*".into()],
+            lag_percent: 100.0,
+            ..ChaosRule::default()
+        }];
+        assert_eq!(
+            fault(&rules, &Message::new("flaky:1", "hi")),
+            Some(Fault::Lag)
+        );
+        assert_eq!(fault(&rules, &Message::new("steady:1", "hi")), None);
+
+        let router = tenvis_pulse_core::Router::new();
+        let handle = router.connect("conn-1");
+        router.subscribe_handle(&handle, "flaky:1", None).unwrap();
+        router.publish(Message::new("flaky:1", "one"));
+        router.publish(Message::new("flaky:1", "two"));
+        assert_eq!(lag(&handle), 2);
+        assert!(handle.try_recv().is_none());
+    }
+}
@@ -0,0 +1,126 @@
+//! Command-line arguments.
+//!
+//! Flags override the config file and environment. Any setting can be given
+//! with `--set` using its dotted TOML path, e.g.
+//! `--set limits.max_connections=50000`.
+
+use anyhow::{anyhow, bail, Result};
+use std::path::PathBuf;
+
+/// Help text for `--help`.
+pub const USAGE: &str = "\
+Usage: pulse [OPTIONS]
+
+Options:
+  -c, --config <PATH>     Config file (default: pulse.toml, /etc/pulse/pulse.toml,
+                          ~/.config/pulse/pulse.toml)
+      --host <HOST>       Address to bind to
+  -p, --port <PORT>       Port to listen on
+      --set <KEY=VALUE>   Override any setting by its dotted path (repeatable)
+  -h, --help              Print help
+  -V, --version           Print version
+
+Settings are also read from PULSE_* environment variables, with nested keys
+joined by `__`, e.g. PULSE_LIMITS__MAX_CONNECTIONS=50000.";
+
+/// What the command line asks for.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Run the server.
+    Run(Args),
+    /// Print help and exit.
+    Help,
+    /// Print the version and exit.
+    Version,
+}
+
+/// Options for running the server.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Args {
+    /// Config file given with `--config`.
+    pub config: Option<PathBuf>,
+    /// Settings to override, as dotted keys and values, in order.
+    pub overrides: Vec<(String, String)>,
+}
+
+/// Parse arguments, excluding the program name.
+///
+/// # Errors
+///
+/// Returns an error for unknown flags or missing values.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command> {
+    let mut parsed = Args::default();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        // Accept both `--flag value` and `--flag=value`
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => {
+                (flag.to_string(), Some(value.to_string()))
+            }
+            _ => (arg, None),
+        };
+        let mut value = || {
+            inline
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| anyhow!("{flag} requires a value"))
+        };
+
+        match flag.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "-c" | "--config" => parsed.config = Some(PathBuf::from(value()?)),
+            "--host" => parsed.overrides.push(("host".to_string(), value()?)),
+            "-p" | "--port" => parsed.overrides.push(("port".to_string(), value()?)),
+            "--set" => {
+                let setting = value()?;
+                let Some((key, value)) = setting.split_once('=') else {
+                    bail!("--set expects KEY=VALUE, got {setting:?}");
+                };
+                parsed
+                    .overrides
+                    .push((key.trim().to_lowercase(), value.trim().to_string()));
+            }
+            _ => bail!("Unknown argument: {flag}\n\n{USAGE}"),
+        }
+    }
+
+    Ok(Command::Run(parsed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Result<Command> {
+        parse(line.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn test_parse() {
+        let Command::Run(parsed) =
+            args("-c /etc/pulse.toml --port=9000 --set limits.max_connections=5").unwrap()
+        else {
+            panic!("expected Run");
+        };
+        assert_eq!(parsed.config, Some(PathBuf::from("/etc/pulse.toml")));
+        assert_eq!(
+            parsed.overrides,
+            vec![
+                ("port".to_string(), "9000".to_string()),
+                ("limits.max_connections".to_string(), "5".to_string()),
+            ]
+        );
+
+        assert_eq!(args("--port 1 --help").unwrap(), Command::Help);
+        assert_eq!(args("").unwrap(), Command::Run(Args::default()));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(args("--port").is_err());
+        assert!(args("--set limits").is_err());
+        assert!(args("--verbose").is_err());
+    }
+}
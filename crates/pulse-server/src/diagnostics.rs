@@ -0,0 +1,229 @@
+//! Diagnostic snapshots for debugging a running server.
+//!
+//! A snapshot holds the `/stats` figures, the busiest channels by
+//! subscribers and by message rate, the fullest connection queues, and
+//! runtime task counts. Send the process SIGUSR1 to write one to
+//! `diagnostics.dir` (or the log when unset), or fetch one from
+//! `GET /admin/diagnostics`.
+//!
+//! Channel message rates are measured over [`RATE_WINDOW`], so a snapshot
+//! takes that long to collect.
+
+use crate::config::DiagnosticsConfig;
+use crate::handlers::AppState;
+use crate::stats::StatsResponse;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tenvis_pulse_core::ChannelId;
+use tracing::{error, info, warn};
+
+/// How long channel message rates are measured for.
+pub const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// A diagnostic snapshot.
+#[derive(Debug, Serialize)]
+pub struct Snapshot {
+    /// When the snapshot was taken, in Unix milliseconds.
+    timestamp: u64,
+    stats: StatsResponse,
+    channels_by_subscribers: Vec<ChannelView>,
+    channels_by_rate: Vec<ChannelView>,
+    queues: QueueSummary,
+    runtime: RuntimeView,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ChannelView {
+    channel: String,
+    subscribers: usize,
+    messages_per_sec: f64,
+    history: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct QueueSummary {
+    /// Messages waiting across all connections.
+    queued: usize,
+    /// Connections whose queue is at capacity.
+    full: usize,
+    /// Connections with the most messages waiting, fullest first.
+    fullest: Vec<QueueView>,
+}
+
+#[derive(Debug, Serialize)]
+struct QueueView {
+    connection_id: String,
+    user_id: Option<String>,
+    ip: Option<IpAddr>,
+    queued: usize,
+    capacity: usize,
+    dropped: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct RuntimeView {
+    workers: usize,
+    alive_tasks: usize,
+    global_queue_depth: usize,
+}
+
+impl Snapshot {
+    /// Take a snapshot, measuring channel message rates over `window`.
+    ///
+    /// Lists are cut to `diagnostics.top` entries.
+    pub async fn take(state: &AppState, window: Duration) -> Self {
+        let top = state.config.diagnostics.top;
+
+        let before: HashMap<ChannelId, u64> = state
+            .router
+            .channel_stats()
+            .into_iter()
+            .map(|channel| (channel.channel, channel.seq))
+            .collect();
+        let started = Instant::now();
+        tokio::time::sleep(window).await;
+        let secs = started.elapsed().as_secs_f64();
+        let channels: Vec<ChannelView> = state
+            .router
+            .channel_stats()
+            .into_iter()
+            .map(|channel| {
+                let since = before.get(&channel.channel).copied().unwrap_or(0);
+                ChannelView {
+                    channel: channel.channel.to_string(),
+                    subscribers: channel.subscribers,
+                    messages_per_sec: channel.seq.saturating_sub(since) as f64 / secs,
+                    history: channel.history,
+                }
+            })
+            .collect();
+
+        let mut channels_by_subscribers = channels.clone();
+        channels_by_subscribers
+            .sort_unstable_by_key(|channel| std::cmp::Reverse(channel.subscribers));
+        channels_by_subscribers.truncate(top);
+        let mut channels_by_rate = channels;
+        channels_by_rate.retain(|channel| channel.messages_per_sec > 0.0);
+        channels_by_rate.sort_unstable_by(|a, b| b.messages_per_sec.total_cmp(&a.messages_per_sec));
+        channels_by_rate.truncate(top);
+
+        let handles = state.router.handles();
+        let mut fullest: Vec<_> = handles.iter().filter(|h| !h.is_empty()).collect();
+        fullest.sort_unstable_by_key(|h| std::cmp::Reverse(h.len()));
+        let queues = QueueSummary {
+            queued: handles.iter().map(|h| h.len()).sum(),
+            full: handles.iter().filter(|h| h.len() >= h.capacity()).count(),
+            fullest: fullest
+                .into_iter()
+                .take(top)
+                .map(|h| QueueView {
+                    connection_id: h.id().to_string(),
+                    user_id: h.user_id().map(str::to_string),
+                    ip: h.remote_ip(),
+                    queued: h.len(),
+                    capacity: h.capacity(),
+                    dropped: h.dropped(),
+                })
+                .collect(),
+        };
+
+        let metrics = tokio::runtime::Handle::current().metrics();
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            stats: StatsResponse::collect(state),
+            channels_by_subscribers,
+            channels_by_rate,
+            queues,
+            runtime: RuntimeView {
+                workers: metrics.num_workers(),
+                alive_tasks: metrics.num_alive_tasks(),
+                global_queue_depth: metrics.global_queue_depth(),
+            },
+        }
+    }
+}
+
+/// Write a snapshot on every SIGUSR1 until the server exits.
+#[cfg(unix)]
+pub fn spawn_signal(state: &Arc<AppState>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let state = state.clone();
+    tokio::spawn(async move {
+        let mut signal = match signal(SignalKind::user_defined1()) {
+            Ok(signal) => signal,
+            Err(e) => {
+                warn!("Failed to listen for SIGUSR1: {}", e);
+                return;
+            }
+        };
+        while signal.recv().await.is_some() {
+            info!("Collecting diagnostics");
+            let snapshot = Snapshot::take(&state, RATE_WINDOW).await;
+            if let Err(e) = dump(&state.config.diagnostics, &snapshot).await {
+                error!("Failed to write diagnostics: {:#}", e);
+            }
+        }
+    });
+}
+
+/// Write a snapshot to `diagnostics.dir`, or to the log when it is unset.
+async fn dump(config: &DiagnosticsConfig, snapshot: &Snapshot) -> Result<()> {
+    let Some(dir) = &config.dir else {
+        info!("Diagnostics: {}", serde_json::to_string(snapshot)?);
+        return Ok(());
+    };
+    let path = dir.join(format!("pulse-diagnostics-{}.json", snapshot.timestamp));
+    tokio::fs::write(&path, serde_json::to_vec_pretty(snapshot)?)
+        .await
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    info!("Wrote diagnostics to {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use tenvis_pulse_core::Message;
+
+    #[tokio::test]
+    async fn test_snapshot() {
+        let mut config = Config::default();
+        config.diagnostics.top = 1;
+        let state = Arc::new(AppState::new(config).unwrap());
+        let router = &state.router;
+        for (conn, channel) in [("conn-1", "quiet"), ("conn-1", "busy"), ("conn-2", "quiet")] {
+            let handle = router.handle(conn).unwrap_or_else(|| router.connect(conn));
+            router.subscribe_handle(&handle, channel, None).unwrap();
+        }
+
+        // Runs while the snapshot measures rates
+        let publishing = state.clone();
+        let publisher = tokio::spawn(async move {
+            for _ in 0..5 {
+                publishing
+                    .router
+                    .publish(Message::new("busy", b"tick".to_vec()));
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        });
+        let snapshot = Snapshot::take(&state, Duration::from_millis(100)).await;
+        publisher.await.unwrap();
+
+        assert_eq!(snapshot.channels_by_subscribers.len(), 1);
+        assert_eq!(snapshot.channels_by_subscribers[0].channel, "quiet");
+        assert_eq!(snapshot.channels_by_rate[0].channel, "busy");
+        assert!(snapshot.channels_by_rate[0].messages_per_sec > 0.0);
+        assert_eq!(snapshot.queues.queued, 5);
+        assert_eq!(snapshot.queues.fullest[0].connection_id, "conn-1");
+        assert!(snapshot.runtime.workers >= 1);
+    }
+}
@@ -0,0 +1,438 @@
+//! Federation links between Pulse servers.
+//!
+//! A link connects this server to another Pulse server as a client,
+//! presenting the remote's federation credential as its Connect token. It
+//! subscribes to the configured channel patterns on both servers and relays
+//! publishes each way, so servers can share channels in hub-and-spoke or
+//! region-bridging topologies without a full cluster.
+//!
+//! Each link relays its `channels` both ways, `send` patterns only to the
+//! remote and `receive` patterns only from it, so region bridges can keep
+//! egress to the channels each region needs.
+//!
+//! Relayed messages are never sent back over the link they arrived on. With
+//! `federation.region` set, relayed messages also carry the region they were
+//! first published in: a link does not send a message to the region it came
+//! from (or, with `transit` off, any message from another region), and a
+//! server drops messages that come back to its own region, so links may form
+//! cycles. Without regions, link topologies must form a tree.
+//!
+//! Presence is exchanged every `presence_sync_ms`: for each local presence
+//! channel the link relays, it sends this server's members to the remote
+//! and asks for the remote's, each side replacing what it last heard from
+//! the other. Each server only reports the members connected to it, so
+//! presence is complete on servers linked to every other, as in a full mesh.
+
+use crate::admin;
+use crate::config::FederationLink;
+use crate::handlers::AppState;
+use crate::metrics;
+use anyhow::{anyhow, Result};
+use pulse_protocol::{
+    codec, Capabilities, Frame, PresenceAction, SubscribeOptions, PROTOCOL_VERSION,
+};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tenvis_pulse_core::{ChannelKind, ConnectionHandle, Message, PresenceState};
+use tenvis_pulse_transport::{
+    websocket, Connection, ConnectionSink, ConnectionStream, HeartbeatConfig, HeartbeatConnection,
+};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tracing::{debug, info, warn};
+
+/// Ping interval used until the remote says otherwise.
+pub(crate) const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Start every configured link. Each one reconnects until the server exits.
+pub fn spawn_links(state: &Arc<AppState>) {
+    for link in &state.config.federation.links {
+        state.health.register_backplane(backplane_name(link));
+        tokio::spawn(run_link(state.clone(), link.clone()));
+    }
+}
+
+/// Keep a link up, reconnecting after it drops.
+async fn run_link(state: Arc<AppState>, link: FederationLink) {
+    let reconnect = Duration::from_millis(link.reconnect_ms);
+    loop {
+        match relay(&state, &link).await {
+            Ok(()) => info!(link = %link.name, "Federation link closed"),
+            Err(e) => warn!(link = %link.name, error = %e, "Federation link failed"),
+        }
+        state.health.set_backplane(&backplane_name(&link), false);
+        tokio::time::sleep(reconnect).await;
+    }
+}
+
+/// Connect to the remote server and relay until either side closes.
+async fn relay(state: &AppState, link: &FederationLink) -> Result<()> {
+    let connection = websocket::connect(&link.url).await?;
+    // Pings follow the remote's heartbeat once it sends Connected
+    let heartbeat = HeartbeatConfig {
+        interval: DEFAULT_PING_INTERVAL,
+        ..HeartbeatConfig::default()
+    };
+    let (mut sink, mut stream) =
+        Box::new(HeartbeatConnection::new(Box::new(connection), heartbeat)).split();
+
+    let connect = Frame::Connect {
+        version: PROTOCOL_VERSION.major,
+        token: Some(link.credential.clone()),
+        capabilities: Capabilities::NONE,
+        heartbeat: None,
+        user_id: None,
+    };
+    sink.send(connect).await?;
+    // Literal channels are ordinary subscriptions, which would echo the
+    // link's own publishes back without `no_echo`
+    let options = SubscribeOptions {
+        no_echo: true,
+        no_presence: true,
+        ..SubscribeOptions::default()
+    };
+    for (id, pattern) in (1..).zip(link.receives()) {
+        let subscribe = Frame::subscribe_with_options(id, pattern.as_str(), options.clone());
+        sink.send(subscribe).await?;
+    }
+
+    // Local publishes reach the link through pattern subscriptions
+    let handle = state.router.connect(&format!("link:{}", link.name));
+    handle.set_transport("federation");
+    for pattern in link.sends() {
+        state.router.subscribe_pattern(&handle, pattern.clone())?;
+    }
+    info!(link = %link.name, url = %link.url, "Federation link established");
+    state.health.set_backplane(&backplane_name(link), true);
+
+    let result = pump(state, link, &handle, sink.as_mut(), stream.as_mut()).await;
+    state.router.disconnect(&handle);
+    state.router.remove_remote_presence(handle.id());
+    result
+}
+
+/// Name a link's entry in `/readyz`.
+fn backplane_name(link: &FederationLink) -> String {
+    format!("link:{}", link.name)
+}
+
+/// Relay messages between the local router and the remote server.
+async fn pump(
+    state: &AppState,
+    link: &FederationLink,
+    handle: &Arc<ConnectionHandle>,
+    sink: &mut dyn ConnectionSink,
+    stream: &mut dyn ConnectionStream,
+) -> Result<()> {
+    let region = state.config.federation.region.as_deref();
+    let presence_sync = Duration::from_millis(link.presence_sync_ms.max(1));
+    let mut presence_sync = tokio::time::interval(presence_sync);
+    presence_sync.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut presence_sent = HashSet::new();
+
+    loop {
+        tokio::select! {
+            _ = presence_sync.tick(), if link.presence_sync_ms > 0 => {
+                presence_sent = sync_presence(state, link, sink, &presence_sent).await?;
+            }
+
+            // Outbound: a local publish matched one of the link's patterns
+            msg = handle.recv() => {
+                let Some(msg) = msg else {
+                    // Closed locally, e.g. on shutdown
+                    return Ok(());
+                };
+                if !link.relays(msg.origin.as_deref()) {
+                    continue;
+                }
+                let frame = Frame::Publish {
+                    id: None,
+                    channel: msg.channel.to_string(),
+                    event: msg.event.clone(),
+                    seq: None,
+                    message_id: None,
+                    timestamp: None,
+                    priority: msg.priority,
+                    coalesce_key: msg.coalesce_key.clone(),
+                    group_id: None,
+                    encrypted: msg.encrypted,
+                    key_id: msg.key_id.clone(),
+                    signer: msg.signer.clone(),
+                    signature: msg.signature.clone(),
+                    origin: msg.origin.as_deref().or(region).map(str::to_string),
+                    trace: false,
+                    payload: msg.payload.to_vec(),
+                };
+                sink.send(frame).await?;
+            }
+
+            // Inbound: frames from the remote server
+            frame = stream.recv() => {
+                let Some(frame) = frame? else {
+                    return Ok(());
+                };
+                match frame {
+                    Frame::Publish { .. } => {
+                        // Sourced from the link so it is not relayed back
+                        let Some(mut message) = inbound_message(frame, handle.id()) else {
+                            continue;
+                        };
+                        if !accept_inbound(link, region, &mut message) {
+                            debug!(link = %link.name, channel = %message.channel, "Dropped message relayed back to its region");
+                            continue;
+                        }
+                        metrics::record_fanout(state.router.publish(message));
+                    }
+                    // The remote's members, in reply to sync_presence
+                    Frame::Presence {
+                        action: PresenceAction::Sync,
+                        channel,
+                        data,
+                        ..
+                    } => {
+                        let members = remote_members(data);
+                        state.router.set_remote_presence(&channel, handle.id(), members);
+                    }
+                    Frame::Error { id, code, message } => {
+                        let pattern = usize::try_from(id)
+                            .ok()
+                            .and_then(|id| id.checked_sub(1))
+                            .and_then(|index| link.receives().nth(index));
+                        if let Some(pattern) = pattern {
+                            return Err(anyhow!(
+                                "remote rejected pattern {pattern} ({code}): {message}"
+                            ));
+                        }
+                        warn!(link = %link.name, code, message = %message, "Remote error");
+                    }
+                    Frame::Disconnect { reason, message, .. } => {
+                        info!(link = %link.name, reason = ?reason, message = %message, "Remote disconnected the link");
+                        return Ok(());
+                    }
+                    other => {
+                        debug!(link = %link.name, frame_type = ?other.frame_type(), "Ignoring frame");
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Send this server's presence members to the remote and ask for its own,
+/// for every local presence channel the link relays.
+///
+/// Channels in `sent` that are gone are sent with no members. Returns the
+/// channels whose members were sent.
+async fn sync_presence(
+    state: &AppState,
+    link: &FederationLink,
+    sink: &mut dyn ConnectionSink,
+    sent: &HashSet<String>,
+) -> Result<HashSet<String>> {
+    let channels: Vec<String> = state
+        .router
+        .channel_names()
+        .into_iter()
+        .filter(|name| ChannelKind::from_name(name).tracks_presence())
+        .collect();
+    let mut sending = HashSet::new();
+    for channel in sent.iter().filter(|c| !channels.contains(c)) {
+        let frame = Frame::Presence {
+            id: 0,
+            channel: channel.clone(),
+            action: PresenceAction::Sync,
+            data: Some(serde_json::Value::Array(Vec::new())),
+        };
+        sink.send(frame).await?;
+    }
+    for channel in channels {
+        if link.sends().any(|pattern| pattern.matches(&channel)) {
+            let members = state.router.local_presence_snapshot(&channel);
+            let frame = Frame::Presence {
+                id: 0,
+                channel: channel.clone(),
+                action: PresenceAction::Sync,
+                data: serde_json::to_value(members).ok(),
+            };
+            sink.send(frame).await?;
+            sending.insert(channel.clone());
+        }
+        if link.receives().any(|pattern| pattern.matches(&channel)) {
+            let frame = Frame::Presence {
+                id: 0,
+                channel,
+                action: PresenceAction::Sync,
+                data: None,
+            };
+            sink.send(frame).await?;
+        }
+    }
+    Ok(sending)
+}
+
+/// Whether a federation peer presented this server's credential.
+pub(crate) fn authenticates(expected: Option<&str>, token: Option<&str>) -> bool {
+    match (expected, token) {
+        (Some(expected), Some(token)) => admin::constant_time_eq(expected, token),
+        _ => false,
+    }
+}
+
+/// Tag a message relayed over `link` with the region it came from, if the
+/// remote didn't say. Returns `false` if it started in this server's
+/// `region` and has come back round a cycle of links.
+pub(crate) fn accept_inbound(
+    link: &FederationLink,
+    region: Option<&str>,
+    message: &mut Message,
+) -> bool {
+    if message.origin.is_none() {
+        message.origin = link.region.clone();
+    }
+    message.origin.is_none() || message.origin.as_deref() != region
+}
+
+/// Parse the members another server sent in a presence Sync frame; anything
+/// unreadable counts as none.
+pub(crate) fn remote_members(data: Option<serde_json::Value>) -> Vec<PresenceState> {
+    data.and_then(|data| serde_json::from_value(data).ok())
+        .unwrap_or_default()
+}
+
+/// Turn a Publish frame from another server into a local message sourced
+/// from `source`. Returns `None` for other frames.
+pub(crate) fn inbound_message(frame: Frame, source: &str) -> Option<Message> {
+    let Frame::Publish {
+        channel,
+        event,
+        priority,
+        coalesce_key,
+        encrypted,
+        key_id,
+        signer,
+        signature,
+        origin,
+        payload,
+        ..
+    } = frame
+    else {
+        return None;
+    };
+    let mut message = Message::new(channel, payload)
+        .with_source(source)
+        .with_priority(priority);
+    if let Some(event) = event {
+        message = message.with_event(event);
+    }
+    if let Some(key) = coalesce_key {
+        message = message.with_coalesce_key(key);
+    }
+    if encrypted {
+        message = message.with_encryption(key_id);
+    }
+    if let (Some(signer), Some(signature)) = (signer, signature) {
+        message = message.with_signature(signer, signature);
+    }
+    if let Some(origin) = origin {
+        message = message.with_origin(origin);
+    }
+    Some(message)
+}
+
+/// Encode a frame as a WebSocket message.
+pub(crate) fn encode(frame: &Frame) -> Result<WsMessage> {
+    Ok(WsMessage::Binary(codec::encode(frame)?.to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tenvis_pulse_core::{ChannelPattern, Router};
+
+    fn link(region: Option<&str>, transit: bool) -> FederationLink {
+        FederationLink {
+            name: "peer".to_string(),
+            url: "ws://peer/ws".to_string(),
+            credential: "peer-secret".to_string(),
+            credential_file: None,
+            channels: vec!["chat:*".into()],
+            send: Vec::new(),
+            receive: Vec::new(),
+            region: region.map(str::to_string),
+            transit,
+            reconnect_ms: 1000,
+            presence_sync_ms: 0,
+        }
+    }
+
+    #[test]
+    fn test_link_relays_by_origin() {
+        let hub = link(Some("eu"), true);
+        assert!(hub.relays(None));
+        assert!(hub.relays(Some("us")));
+        // Never back to the region a message came from
+        assert!(!hub.relays(Some("eu")));
+
+        // A mesh link only carries messages that started here
+        let mesh = link(Some("eu"), false);
+        assert!(mesh.relays(None));
+        assert!(!mesh.relays(Some("us")));
+    }
+
+    #[test]
+    fn test_accept_inbound() {
+        let from_us = link(Some("us"), true);
+        let mut message = Message::new("chat:a", b"x".to_vec());
+        assert!(accept_inbound(&from_us, Some("eu"), &mut message));
+        assert_eq!(message.origin.as_deref(), Some("us"));
+
+        // The remote's tag wins over the link's region
+        let mut message = Message::new("chat:a", b"x".to_vec()).with_origin("ap");
+        assert!(accept_inbound(&from_us, Some("eu"), &mut message));
+        assert_eq!(message.origin.as_deref(), Some("ap"));
+
+        // Started here and came back round a cycle
+        let mut message = Message::new("chat:a", b"x".to_vec()).with_origin("eu");
+        assert!(!accept_inbound(&from_us, Some("eu"), &mut message));
+
+        // Without regions nothing is tagged or dropped
+        let untagged = link(None, true);
+        let mut message = Message::new("chat:a", b"x".to_vec());
+        assert!(accept_inbound(&untagged, None, &mut message));
+        assert!(message.origin.is_none());
+    }
+
+    #[test]
+    fn test_inbound_not_relayed_back() {
+        let router = Router::new();
+        let handle = router.connect("link:peer");
+        router
+            .subscribe_pattern(&handle, ChannelPattern::new("chat:*"))
+            .unwrap();
+
+        let mut frame = Frame::publish("chat:a", "remote");
+        if let Frame::Publish { origin, .. } = &mut frame {
+            *origin = Some("us".to_string());
+        }
+        let message = inbound_message(frame, handle.id()).unwrap();
+        assert_eq!(message.origin.as_deref(), Some("us"));
+        router.publish(message);
+        assert!(handle.try_recv().is_none());
+
+        router.publish(Message::new("chat:a", b"local".to_vec()));
+        let relayed = handle.try_recv().unwrap();
+        assert_eq!(&relayed.payload[..], b"local");
+        assert!(relayed.origin.is_none());
+    }
+
+    #[test]
+    fn test_authenticates() {
+        assert!(authenticates(Some("hub-secret"), Some("hub-secret")));
+        assert!(!authenticates(Some("hub-secret"), Some("hub-secreT")));
+        assert!(!authenticates(Some("hub-secret"), Some("hub")));
+        assert!(!authenticates(Some("hub-secret"), None));
+        // Federation is off without a configured credential
+        assert!(!authenticates(None, Some("hub-secret")));
+        assert!(!authenticates(None, None));
+    }
+}
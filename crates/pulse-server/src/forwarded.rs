@@ -0,0 +1,142 @@
+//! Client address resolution behind reverse proxies.
+//!
+//! When the direct peer is a trusted proxy, the client address is taken from
+//! the `Forwarded` (RFC 7239) or `X-Forwarded-For` headers. The hop list is
+//! walked from the right, skipping trusted proxies, so a client cannot spoof
+//! its address by sending the headers itself.
+
+use axum::http::HeaderMap;
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+
+/// Resolve the client IP for a request from `peer`.
+///
+/// Returns `peer` unless it is one of `trusted_proxies` and the request
+/// carries forwarding headers. `Forwarded` takes precedence over
+/// `X-Forwarded-For`.
+#[must_use]
+pub fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpNet]) -> IpAddr {
+    let trusted = |ip: &IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+    if !trusted(&peer) {
+        return peer;
+    }
+
+    let mut hops = forwarded_for(headers);
+    if hops.is_empty() {
+        hops = x_forwarded_for(headers);
+    }
+
+    // Rightmost untrusted hop is the client; stop at anything unparseable
+    let mut client = peer;
+    for hop in hops.iter().rev() {
+        let Some(ip) = hop else {
+            break;
+        };
+        client = *ip;
+        if !trusted(ip) {
+            break;
+        }
+    }
+    client
+}
+
+/// Get the `for=` hops of every `Forwarded` header, in order.
+fn forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    headers
+        .get_all("forwarded")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.trim().split_once('=')?;
+                key.eq_ignore_ascii_case("for").then(|| parse_node(value))
+            })
+        })
+        .collect()
+}
+
+/// Get the hops of every `X-Forwarded-For` header, in order.
+fn x_forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(parse_node)
+        .collect()
+}
+
+/// Parse a node such as `192.0.2.1`, `192.0.2.1:4711` or
+/// `"[2001:db8::17]:4711"`. Obfuscated nodes (`unknown`, `_hidden`) are `None`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip.to_canonical());
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip().to_canonical());
+    }
+    node.strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+        .and_then(|ip| ip.parse::<IpAddr>().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.append(*name, value.parse().unwrap());
+        }
+        map
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_untrusted_peer_ignores_headers() {
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap()];
+        let h = headers(&[("x-forwarded-for", "198.51.100.7")]);
+        assert_eq!(
+            client_ip(ip("203.0.113.1"), &h, &trusted),
+            ip("203.0.113.1")
+        );
+        assert_eq!(client_ip(ip("10.0.0.1"), &h, &[]), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn test_x_forwarded_for_skips_trusted_hops() {
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap()];
+        // Client-supplied 1.2.3.4 is ignored: the rightmost untrusted hop wins
+        let h = headers(&[
+            ("x-forwarded-for", "1.2.3.4, 198.51.100.7"),
+            ("x-forwarded-for", "10.0.0.2"),
+        ]);
+        assert_eq!(client_ip(ip("10.0.0.1"), &h, &trusted), ip("198.51.100.7"));
+
+        let h = headers(&[("x-forwarded-for", "garbage, 10.0.0.3")]);
+        assert_eq!(client_ip(ip("10.0.0.1"), &h, &trusted), ip("10.0.0.3"));
+    }
+
+    #[test]
+    fn test_forwarded_header() {
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap()];
+        let h = headers(&[
+            (
+                "forwarded",
+                r#"for="[2001:db8:cafe::17]:4711";proto=https, For=192.0.2.43:80"#,
+            ),
+            ("x-forwarded-for", "198.51.100.7"),
+        ]);
+        assert_eq!(client_ip(ip("10.0.0.1"), &h, &trusted), ip("192.0.2.43"));
+
+        let h = headers(&[("forwarded", "for=unknown")]);
+        assert_eq!(client_ip(ip("10.0.0.1"), &h, &trusted), ip("10.0.0.1"));
+        assert_eq!(parse_node(r#""[2001:db8::1]""#), Some(ip("2001:db8::1")));
+    }
+}
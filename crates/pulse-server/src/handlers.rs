@@ -2,54 +2,294 @@
 //!
 //! This module handles the connection lifecycle and message processing.
 
-use crate::config::Config;
+use crate::admin;
+use crate::affinity::{self, AffinityQuery};
+use crate::audit::{self, AuditEvent, AuditKind, AuditLog};
+use crate::bans::{BanTarget, Denylist};
+use crate::capture;
+#[cfg(feature = "chaos")]
+use crate::chaos::{self, Fault};
+use crate::config::{BindAddr, Config};
+use crate::federation;
+use crate::forwarded;
+use crate::health::Health;
+use crate::ip_limits::{IpConnectionGuard, IpConnections};
+use crate::listener::{self, AcceptPacer, TunedAcceptor};
+use crate::metadata;
 use crate::metrics::{self, ConnectionMetricsGuard};
-use anyhow::Result;
+use crate::rate_limit::{CreationLimiter, SubscriptionLimiter, Verdict};
+use crate::routes::Routes;
+use crate::schemas::Schemas;
+use crate::shedding::{self, Shedder};
+use crate::signing::SigningKeys;
+use crate::sinks;
+use crate::standby::{self, Standby};
+use crate::stats::{self, ServerStats, Transport};
+use crate::tls;
+use crate::trace::{self, Tracer};
+use crate::usage::{self, Usage};
+use crate::usage_reports::{self, UsageReports};
+use crate::webhooks::{self, Webhooks};
+use crate::writer::FrameWriter;
+use anyhow::{Context, Result};
 use axum::{
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
-        State,
+        ConnectInfo, Query, State,
     },
-    response::IntoResponse,
-    routing::get,
-    Router,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
 };
-use bytes::BytesMut;
-use futures_util::{SinkExt, StreamExt};
-use pulse_protocol::{codec, Frame};
-use std::collections::HashMap;
+use axum_server::accept::DefaultAcceptor;
+use axum_server::tls_rustls::RustlsAcceptor;
+use bytes::Buf;
+use futures_util::future::{try_join_all, BoxFuture};
+use futures_util::StreamExt;
+use pulse_protocol::{
+    codec, error_codes, pool, Capabilities, DisconnectReason, Frame, PresenceAction, ProtocolError,
+    PROTOCOL_VERSION,
+};
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
-use std::time::Instant;
-use tenvis_pulse_core::{Router as PulseRouter, RouterConfig};
-use tokio::net::TcpListener;
-use tokio::sync::broadcast;
+use std::time::{Duration, SystemTime};
+use tenvis_pulse_core::{
+    ChannelKind, ChannelPattern, CloseReason, ConnectionHandle, ConnectionMetadata, HmacAuthorizer,
+    MessageKind, Moderator, Rejection, Router as PulseRouter, RouterConfig, RouterError,
+    WordFilter, SYSTEM_CHANNEL_PREFIX,
+};
+use tenvis_pulse_transport::{IdGenerator, SocketOptions};
+use tokio::sync::watch;
+use tokio::time::{sleep_until, Instant};
 use tracing::{debug, error, info, warn};
 
+/// Allowance for frame fields other than the payload (channel, event, IDs)
+/// when limiting inbound frame size.
+const FRAME_OVERHEAD: usize = 1024;
+
+/// How long clients are asked to wait at least before reconnecting after a
+/// shutdown; `shutdown.reconnect_jitter_ms` spreads them out past it.
+const SHUTDOWN_RECONNECT_AFTER: Duration = Duration::from_secs(1);
+
 /// Shared server state.
 pub struct AppState {
     /// The message router.
     pub router: PulseRouter,
     /// Server configuration.
     pub config: Config,
+    /// Connection ID generator.
+    pub id_generator: Arc<dyn IdGenerator>,
+    /// Banned users and IPs.
+    pub denylist: Denylist,
+    /// Live connections per client IP.
+    pub ip_connections: Arc<IpConnections>,
+    /// Security-relevant events.
+    pub audit: AuditLog,
+    /// Counters behind `/stats`.
+    pub stats: ServerStats,
+    /// Drain state and backplane connectivity behind `/readyz`.
+    pub health: Health,
+    /// Occupancy changes waiting to be sent as webhooks.
+    pub webhooks: Arc<Webhooks>,
+    /// Moderators consulted on publishes, in order.
+    pub moderators: Vec<Arc<dyn Moderator>>,
+    /// JSON Schemas checked on publishes.
+    pub schemas: Schemas,
+    /// Keys signed publishes are verified with.
+    pub signing_keys: SigningKeys,
+    /// Whether load is being shed.
+    pub shedder: Shedder,
+    /// Bandwidth per user, checked against the quotas.
+    pub usage: Usage,
+    /// Usage per tenant for the current reporting period.
+    pub usage_reports: UsageReports,
+    /// Channels each client may still cause to be created.
+    pub channel_creations: CreationLimiter,
+    /// Whether this server is a standby waiting to take over.
+    pub standby: Standby,
+    /// Trace events of traced messages waiting to be published.
+    pub tracer: Arc<Tracer>,
 }
 
 impl AppState {
     /// Create new app state.
-    #[must_use]
-    pub fn new(config: Config) -> Self {
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the channel naming policy is invalid.
+    pub fn new(config: Config) -> Result<Self> {
         let router_config = RouterConfig {
             max_channels: config.limits.max_channels,
             max_subscriptions_per_connection: config.limits.max_subscriptions_per_connection,
             channel_capacity: 131072,
-            auto_create_channels: true,
+            connection_queue_capacity: config.limits.max_queued_messages,
+            auto_create_channels: config.channel_lifecycle.auto_create,
+            create_on_publish: config.channel_lifecycle.create_on_publish,
+            unused_channel_ttl: Duration::from_secs(
+                config.channel_lifecycle.unused_channel_ttl_secs,
+            ),
             auto_delete_empty_channels: true,
+            channel_rules: config.channels.clone(),
+            max_memory: config.memory.max_bytes(),
+            memory_eviction: config.memory.eviction,
+            durable: config.durable.router_config(),
+            hot_channel_counters: config.diagnostics.hot_channel_counters,
         };
 
-        Self {
-            router: PulseRouter::with_config(router_config),
+        let validator = config.channel_names.validator()?;
+        let mut router =
+            PulseRouter::with_config(router_config).with_validator(Arc::new(validator));
+        if let Some(secret) = &config.auth.channel_secret {
+            router = router.with_authorizer(Arc::new(HmacAuthorizer::new(secret)));
+        }
+        let webhooks = Arc::new(Webhooks::new(&config.webhooks));
+        if webhooks.enabled() {
+            router = router.with_occupancy_observer(webhooks.clone());
+        }
+        let tracer = Arc::new(Tracer::new());
+        router = router.with_tracer(tracer.clone());
+        let schemas = Schemas::new(&config.schemas)?;
+        let signing_keys = SigningKeys::new(&config.signing_keys);
+        let mut moderators: Vec<Arc<dyn Moderator>> = Vec::new();
+        if !config.moderation.blocked_words.is_empty() {
+            moderators.push(Arc::new(WordFilter::new(&config.moderation.blocked_words)));
+        }
+
+        Ok(Self {
+            router,
+            id_generator: config.connection_ids.generator(),
+            denylist: Denylist::new(),
+            ip_connections: IpConnections::new(config.limits.max_connections_per_ip),
+            audit: AuditLog::new(&config.audit),
+            stats: ServerStats::new(),
+            health: Health::new(),
+            webhooks,
+            moderators,
+            schemas,
+            signing_keys,
+            shedder: Shedder::new(&config.shedding),
+            usage: Usage::new(&config.quotas),
+            usage_reports: UsageReports::new(&config.usage_reports),
+            channel_creations: CreationLimiter::new(&config.channel_lifecycle),
+            standby: Standby::new(&config.standby),
+            tracer,
             config,
+        })
+    }
+
+    /// Add a moderator for publishes to the channels matching
+    /// `moderation.channels`, consulted after those already added.
+    #[must_use]
+    pub fn with_moderator(mut self, moderator: Arc<dyn Moderator>) -> Self {
+        self.moderators.push(moderator);
+        self
+    }
+
+    /// Check whether a client may subscribe or publish to a channel, taking
+    /// one of its channel creations if the channel is missing and would be
+    /// created.
+    pub(crate) fn allow_channel_creation(
+        &self,
+        handle: &ConnectionHandle,
+        channel: &str,
+        publish: bool,
+    ) -> bool {
+        let lifecycle = &self.config.channel_lifecycle;
+        let creates = if publish {
+            lifecycle.create_on_publish
+        } else {
+            lifecycle.auto_create
+        };
+        !creates
+            || self.router.channel_exists(channel)
+            || self
+                .channel_creations
+                .try_take(handle, std::time::Instant::now())
+    }
+
+    /// Check a payload published to a channel against the global size limit
+    /// and any smaller limit of the first `[[channels]]` rule matching it.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error code and message to send the publisher.
+    pub(crate) fn check_payload_size(
+        &self,
+        channel: &str,
+        size: usize,
+        transport: Transport,
+    ) -> Result<(), (u16, String)> {
+        let max_message_size = self.config.limits.max_message_size;
+        if size > max_message_size {
+            metrics::record_error("payload_too_large", transport);
+            return Err((
+                error_codes::PAYLOAD_TOO_LARGE,
+                format!("Payload of {size} bytes exceeds limit of {max_message_size}"),
+            ));
+        }
+        let channel_max = self
+            .router
+            .channel_rule(channel)
+            .and_then(|rule| rule.max_payload_size);
+        match channel_max {
+            Some(max) if size > max => {
+                metrics::record_error("channel_payload_too_large", transport);
+                Err((
+                    error_codes::CHANNEL_PAYLOAD_TOO_LARGE,
+                    format!("Payload of {size} bytes exceeds limit of {max} on channel {channel}"),
+                ))
+            }
+            _ => Ok(()),
         }
     }
+
+    /// Ask the moderators about a message a connection is publishing.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first rejection, or one for a moderator that took longer
+    /// than `moderation.timeout_ms`.
+    pub(crate) async fn moderate(
+        &self,
+        message: &tenvis_pulse_core::Message,
+        publisher: &ConnectionHandle,
+    ) -> Result<(), Rejection> {
+        if self.moderators.is_empty() || !self.config.moderation.applies_to(&message.channel) {
+            return Ok(());
+        }
+        let timeout = Duration::from_millis(self.config.moderation.timeout_ms);
+        for moderator in &self.moderators {
+            match tokio::time::timeout(timeout, moderator.moderate(message, publisher)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(rejection)) => {
+                    metrics::record_moderation("rejected");
+                    return Err(rejection);
+                }
+                Err(_) => {
+                    warn!(channel = %message.channel, "Moderation timed out");
+                    metrics::record_moderation("timeout");
+                    return Err(Rejection::new("Moderation timed out"));
+                }
+            }
+        }
+        metrics::record_moderation("allowed");
+        Ok(())
+    }
+
+    /// Optional protocol features this server can use.
+    #[must_use]
+    pub fn capabilities(&self) -> Capabilities {
+        if self.config.transport.write_coalesce_ms > 0 {
+            Capabilities::BATCHING | Capabilities::PRESENCE_DIFF
+        } else {
+            Capabilities::PRESENCE_DIFF
+        }
+    }
+
+    /// Token clients present to be routed back to this server, if issued.
+    #[must_use]
+    pub fn affinity_token(&self) -> Option<String> {
+        self.config.affinity.token(self.config.node_id)
+    }
 }
 
 /// Run the HTTP/WebSocket server.
@@ -58,142 +298,627 @@ impl AppState {
 ///
 /// Returns an error if the server fails to start.
 pub async fn run_server(config: Config) -> Result<()> {
-    let state = Arc::new(AppState::new(config.clone()));
+    tenvis_pulse_core::message::set_node_id(config.node_id);
+    let state = Arc::new(AppState::new(config.clone())?);
 
     // Start metrics server if enabled
     if config.metrics.enabled {
         if let Err(e) = metrics::start_metrics_server(config.metrics.port) {
             error!("Failed to start metrics server: {}", e);
         }
+        tokio::spawn(metrics::report(
+            state.clone(),
+            std::time::Duration::from_secs(5),
+        ));
     }
 
-    // Build router
-    let app = Router::new()
-        .route(&config.transport.websocket_path, get(ws_handler))
-        .route("/health", get(health_handler))
-        .with_state(state);
+    spawn_tasks(&state).await?;
+    #[cfg(unix)]
+    crate::diagnostics::spawn_signal(&state);
+    let router = Routes::new(state.clone()).into_router();
+    let app = router
+        .clone()
+        .into_make_service_with_connect_info::<SocketAddr>();
 
-    // Bind and serve
-    let addr = config.bind_addr();
-    let listener = TcpListener::bind(addr).await?;
+    let tls = tls::load(&config.tls)?;
+    if let Some(rustls) = &tls {
+        tls::spawn_reload(&config.tls, rustls.clone());
+    }
+    #[cfg(feature = "acme")]
+    let acme = crate::acme::start(&config).await?;
+    #[cfg(not(feature = "acme"))]
+    let acme: Option<std::convert::Infallible> = None;
 
-    info!("Pulse server listening on {}", addr);
-    info!(
-        "WebSocket endpoint: ws://{}{}",
-        addr, config.transport.websocket_path
-    );
+    // Bind every listener before serving any, so a bad address fails fast
+    let (handle, stopped) = shutdown_handle(state);
+    let pacer = AcceptPacer::new(&config.limits).map(Arc::new);
+    let mut servers: Vec<BoxFuture<'static, std::io::Result<()>>> = Vec::new();
+    for listener_config in config.listeners() {
+        let addr = listener_config
+            .bind_addr()
+            .with_context(|| format!("Invalid bind address {:?}", listener_config.address))?;
+        let addr = match addr {
+            BindAddr::Tcp(addr) => addr,
+            #[cfg(unix)]
+            BindAddr::Unix(path) => {
+                let listener = listener::bind_unix(&path)
+                    .with_context(|| format!("Failed to bind Unix socket {}", path.display()))?;
+                info!("Pulse server listening on unix:{}", path.display());
+                servers.push(Box::pin(listener::serve_unix(
+                    listener,
+                    router.clone(),
+                    stopped.clone(),
+                )));
+                continue;
+            }
+            #[cfg(not(unix))]
+            BindAddr::Unix(path) => {
+                anyhow::bail!(
+                    "Unix socket {} is not supported on this platform",
+                    path.display()
+                )
+            }
+        };
+
+        let tcp = listener_config
+            .tcp
+            .as_ref()
+            .unwrap_or(&config.transport.tcp);
+        let socket = SocketOptions {
+            only_v6: listener_config.ipv6_only,
+            ..tcp.socket_options()
+        };
+        let listeners = socket
+            .bind_many(addr, tcp.acceptor_count())
+            .with_context(|| format!("Failed to bind {addr}"))?
+            .into_iter()
+            .map(tokio::net::TcpListener::into_std)
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let secure = listener_config.tls && (tls.is_some() || acme.is_some());
+        info!(
+            acceptors = listeners.len(),
+            "Pulse server listening on {}", addr
+        );
+        info!(
+            "WebSocket endpoint: {}://{}{}",
+            if secure { "wss" } else { "ws" },
+            addr,
+            config.transport.websocket_path
+        );
 
-    axum::serve(listener, app).await?;
+        let plain = TunedAcceptor::new(socket.clone(), DefaultAcceptor);
+        let server: BoxFuture<'static, _> = match (&acme, &tls) {
+            #[cfg(feature = "acme")]
+            (Some(acme), _) if listener_config.tls => {
+                let acceptor = TunedAcceptor::new(socket, acme.clone());
+                Box::pin(listener::serve(
+                    listeners,
+                    acceptor,
+                    pacer.clone(),
+                    handle.clone(),
+                    app.clone(),
+                ))
+            }
+            (_, Some(rustls)) if listener_config.tls => {
+                let acceptor = RustlsAcceptor::new(rustls.clone()).acceptor(plain);
+                Box::pin(listener::serve(
+                    listeners,
+                    acceptor,
+                    pacer.clone(),
+                    handle.clone(),
+                    app.clone(),
+                ))
+            }
+            _ => Box::pin(listener::serve(
+                listeners,
+                plain,
+                pacer.clone(),
+                handle.clone(),
+                app.clone(),
+            )),
+        };
+        servers.push(server);
+    }
 
+    try_join_all(servers).await?;
     Ok(())
 }
 
-/// Health check handler.
-async fn health_handler() -> impl IntoResponse {
-    axum::Json(serde_json::json!({
-        "status": "ok",
-        "version": env!("CARGO_PKG_VERSION")
-    }))
+/// Start the background work behind a server: the audit log, `/stats`
+/// sampling, federation links, HTTP sinks, traffic capture, message tracing
+/// and the Postgres bridge.
+///
+/// Call once per [`AppState`], before serving [`Routes`].
+///
+/// # Errors
+///
+/// Returns an error if the audit log file, a sink or the capture file cannot
+/// be set up.
+pub async fn spawn_tasks(state: &Arc<AppState>) -> Result<()> {
+    audit::spawn(state).await?;
+
+    // Sample message rates for /stats
+    stats::spawn(state);
+
+    // Shed load past the high-water marks
+    shedding::spawn(state);
+
+    // Add up bandwidth per user and enforce quotas
+    usage::spawn(state);
+
+    // Report usage per tenant for billing
+    usage_reports::spawn(state)?;
+
+    // Follow the primary, or send state to standbys
+    standby::spawn(state);
+
+    // Relay channels to and from other servers
+    federation::spawn_links(state);
+
+    // Push channel messages to HTTP endpoints
+    sinks::spawn(state)?;
+
+    // Record channel traffic for replay
+    capture::spawn(state).await?;
+
+    // Publish the steps of traced messages to $system:trace
+    trace::spawn(state);
+
+    // Tell the application when channels become occupied or vacated
+    webhooks::spawn(state)?;
+
+    // Bridge Postgres notifications into channels
+    #[cfg(feature = "postgres")]
+    crate::postgres::spawn(state);
+    #[cfg(not(feature = "postgres"))]
+    if state.config.postgres.url.is_some() {
+        warn!("postgres.url is set but this build lacks the `postgres` feature");
+    }
+
+    Ok(())
+}
+
+/// Shut the listeners down gracefully on Ctrl+C or SIGTERM: TCP listeners
+/// through the returned `axum_server` handle, Unix sockets when the flag is
+/// set.
+fn shutdown_handle(state: Arc<AppState>) -> (axum_server::Handle, watch::Receiver<bool>) {
+    let handle = axum_server::Handle::new();
+    let (stop, stopped) = watch::channel(false);
+    let shutdown = handle.clone();
+    tokio::spawn(async move {
+        shutdown_signal(state).await;
+        shutdown.graceful_shutdown(None);
+        let _ = stop.send(true);
+    });
+    (handle, stopped)
+}
+
+/// Wait for Ctrl+C or SIGTERM, then [`shutdown`].
+async fn shutdown_signal(state: Arc<AppState>) {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => {}
+        () = terminate => {}
+    }
+
+    shutdown(&state).await;
+}
+
+/// Tell every client to go away, for a graceful shutdown.
+///
+/// See [`drain`]; a server already drained by `POST /admin/drain` only
+/// disconnects clients still connected.
+pub async fn shutdown(state: &AppState) {
+    info!("Shutting down");
+    drain(state).await;
+}
+
+/// Stop taking clients and disconnect the connected ones.
+///
+/// Readiness fails immediately and new connections are refused; after
+/// `shutdown.drain_delay_ms`, connections are sent a Disconnect frame with a
+/// reconnect hint, and this waits up to `shutdown.grace_period_ms` for them
+/// to close. The delay is only waited the first time.
+pub async fn drain(state: &AppState) {
+    // Fail readiness first so load balancers stop sending new clients
+    let drain_delay = Duration::from_millis(state.config.shutdown.drain_delay_ms);
+    if state.health.start_draining() && !drain_delay.is_zero() {
+        info!(
+            delay_ms = drain_delay.as_millis(),
+            "Draining before disconnecting clients"
+        );
+        tokio::time::sleep(drain_delay).await;
+    }
+
+    let jitter = Duration::from_millis(state.config.shutdown.reconnect_jitter_ms);
+    let mut reason = CloseReason::new(DisconnectReason::Shutdown, "Server shutting down")
+        .with_reconnect_after(SHUTDOWN_RECONNECT_AFTER)
+        .with_reconnect_jitter(jitter);
+    if let Some(host) = &state.config.standby.alternate_host {
+        reason = reason.with_alternate_host(host.as_str());
+    }
+    state.router.close_all(&reason);
+
+    let deadline = Instant::now() + Duration::from_millis(state.config.shutdown.grace_period_ms);
+    while state.router.connection_count() > 0 && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+/// Decide whether a client may connect, before its upgrade completes.
+///
+/// Returns the client IP and its connection slot, or the response refusing it.
+pub(crate) fn admit(
+    state: &AppState,
+    addr: SocketAddr,
+    headers: &HeaderMap,
+    transport: Transport,
+) -> Result<(IpAddr, IpConnectionGuard), Box<Response>> {
+    if state.health.is_draining() {
+        return Err(Box::new(
+            (StatusCode::SERVICE_UNAVAILABLE, "Server shutting down").into_response(),
+        ));
+    }
+    if state.standby.is_waiting() {
+        return Err(Box::new(
+            (StatusCode::SERVICE_UNAVAILABLE, "Server is a standby").into_response(),
+        ));
+    }
+    let ip = forwarded::client_ip(
+        addr.ip().to_canonical(),
+        headers,
+        &state.config.transport.trusted_proxies,
+    );
+    if !state.config.ip_filter.permits(ip) {
+        debug!(ip = %ip, "Rejected filtered IP");
+        metrics::record_error("ip_filtered", transport);
+        state
+            .audit
+            .record(AuditEvent::new(AuditKind::AccessDenied, "IP filtered").with_ip(ip));
+        return Err(Box::new(StatusCode::FORBIDDEN.into_response()));
+    }
+    if let Some(ban) = state.denylist.check(&BanTarget::Ip(ip)) {
+        debug!(ip = %ip, "Rejected banned IP");
+        state.audit.record(
+            AuditEvent::new(
+                AuditKind::AccessDenied,
+                format!("IP banned: {}", ban.reason),
+            )
+            .with_ip(ip),
+        );
+        return Err(Box::new(
+            (StatusCode::FORBIDDEN, ban.reason).into_response(),
+        ));
+    }
+    let Some(ip_guard) = state.ip_connections.acquire(ip) else {
+        debug!(ip = %ip, connections = state.ip_connections.count(ip), "Rejected IP over its connection limit");
+        metrics::record_error("ip_connection_limit", transport);
+        state.audit.record(
+            AuditEvent::new(AuditKind::AccessDenied, "IP connection limit reached").with_ip(ip),
+        );
+        return Err(Box::new(
+            (StatusCode::TOO_MANY_REQUESTS, "Too many connections").into_response(),
+        ));
+    };
+    Ok((ip, ip_guard))
 }
 
 /// WebSocket upgrade handler.
-async fn ws_handler(ws: WebSocketUpgrade, State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_websocket(socket, state))
+pub(crate) async fn ws_handler(
+    ws: WebSocketUpgrade,
+    query: Option<Query<AffinityQuery>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let (ip, ip_guard) = match admit(&state, addr, &headers, Transport::WebSocket) {
+        Ok(admitted) => admitted,
+        Err(response) => return *response,
+    };
+    let token = state.affinity_token();
+    let presented = affinity::presented(query.as_ref().and_then(|q| q.token()), &headers);
+    affinity::check(token.as_deref(), presented, Transport::WebSocket);
+    if state.shedder.is_shedding() {
+        debug!(ip = %ip, "Refused connection while shedding load");
+        metrics::record_error("overloaded", Transport::WebSocket);
+        let retry_after = state.shedder.retry_after();
+        return ws.on_upgrade(move |socket| refuse_overloaded(socket, retry_after));
+    }
+
+    let metadata = metadata::capture(&headers, &state.config.metadata);
+
+    // Oversized frames are rejected with an Error frame after decoding; the
+    // transport limit only bounds memory, and a message may carry several frames
+    let max_frame_size = state.config.limits.max_message_size + FRAME_OVERHEAD;
+    let response = ws
+        .max_message_size(max_frame_size.saturating_mul(4))
+        .on_upgrade(move |socket| handle_websocket(socket, state, ip, metadata, ip_guard));
+    affinity::tag(response, token.as_deref())
+}
+
+/// Tell a client the server is overloaded and when to come back, then close.
+async fn refuse_overloaded(socket: WebSocket, retry_after: Duration) {
+    let (sender, _) = socket.split();
+    let mut writer = FrameWriter::new(sender, 0, Duration::ZERO);
+    let frame = disconnect_frame(
+        &CloseReason::new(DisconnectReason::Overloaded, "Server overloaded")
+            .with_reconnect_after(retry_after)
+            .with_reconnect_jitter(retry_after)
+            .jittered(),
+    );
+    if writer.send(&frame).await.is_ok() {
+        let _ = writer.send_message(Message::Close(None)).await;
+    }
 }
 
 /// Handle a WebSocket connection.
-async fn handle_websocket(socket: WebSocket, state: Arc<AppState>) {
+///
+/// The IP's connection slot is held until the connection ends.
+async fn handle_websocket(
+    socket: WebSocket,
+    state: Arc<AppState>,
+    ip: IpAddr,
+    metadata: ConnectionMetadata,
+    _ip_guard: IpConnectionGuard,
+) {
     // Record connection metrics
-    let _metrics_guard = ConnectionMetricsGuard::new();
+    let _metrics_guard = ConnectionMetricsGuard::new(Transport::WebSocket);
+    let _transport_guard = state.stats.connect(Transport::WebSocket);
 
     // Generate connection ID
-    let connection_id = format!(
-        "conn_{}",
-        std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_nanos()
-    );
+    let connection_id = state.id_generator.generate().0;
 
-    debug!(connection = %connection_id, "WebSocket connected");
+    debug!(connection = %connection_id, ip = %ip, "WebSocket connected");
 
     // Split the WebSocket
-    let (mut sender, mut receiver) = socket.split();
-
-    // Send Connected frame
-    let connected_frame =
-        Frame::connected(&connection_id, 1, state.config.heartbeat.interval_ms as u32);
-    if let Ok(data) = codec::encode(&connected_frame) {
-        if sender.send(Message::Binary(data.to_vec())).await.is_err() {
-            error!(connection = %connection_id, "Failed to send Connected frame");
-            return;
-        }
+    let (sender, mut receiver) = socket.split();
+    // Frames are only coalesced once the client negotiates batching
+    let mut writer = FrameWriter::new(
+        sender,
+        state.config.transport.write_buffer_size,
+        Duration::ZERO,
+    );
+
+    // Send Connected frame, advertising what the server supports
+    let (heartbeat, heartbeat_timeout) = state.config.heartbeat.negotiate(None);
+    let mut session = Session {
+        heartbeat_timeout,
+        federated: false,
+        admin: false,
+        capabilities: Capabilities::NONE,
+        subscriptions: SubscriptionLimiter::new(&state.config.limits, std::time::Instant::now()),
+    };
+    let connected_frame = connected_frame(
+        &connection_id,
+        heartbeat,
+        state.capabilities(),
+        state.affinity_token(),
+    );
+    if writer.send(&connected_frame).await.is_err() || writer.flush().await.is_err() {
+        error!(connection = %connection_id, "Failed to send Connected frame");
+        return;
     }
 
     // Read buffer for partial frames
-    let mut read_buffer = BytesMut::with_capacity(4096);
+    let mut read_buffer = pool::global().acquire();
+    let max_frame_size = state.config.limits.max_message_size + FRAME_OVERHEAD;
 
-    // Track subscription task handles for cleanup
-    let mut subscription_tasks: HashMap<String, tokio::task::JoinHandle<()>> = HashMap::new();
+    // Outbound queue the router delivers subscribed messages into
+    let handle = state.router.connect(&connection_id);
+    handle.set_remote_ip(ip);
+    handle.set_transport("websocket");
+    handle.set_metadata(metadata);
+    writer.count_bytes(handle.clone());
+    state.audit.record(AuditEvent::for_connection(
+        AuditKind::Connect,
+        &handle,
+        "websocket",
+    ));
 
-    // Create a merged stream for all subscription receivers
-    let (sub_tx, mut sub_rx) =
-        tokio::sync::mpsc::unbounded_channel::<(String, Arc<tenvis_pulse_core::Message>)>();
+    // Any inbound traffic counts as a sign of life
+    let mut last_seen = Instant::now();
+    // Only whole frames count as activity; idle clients are warned once
+    let idle = state.config.heartbeat.idle();
+    let mut last_frame = Instant::now();
+    let mut idle_warned = false;
 
     // Message processing loop
     loop {
+        let flush_at = writer.deadline();
+        let idle_at = idle.map(|(timeout, warning)| {
+            if idle_warned {
+                last_frame + timeout
+            } else {
+                last_frame + timeout - warning
+            }
+        });
+
         tokio::select! {
             biased;
 
-            // Receive messages from subscribed channels (via mpsc)
-            Some((channel, msg)) = sub_rx.recv() => {
-                // Forward the message to the WebSocket client
-                let frame = Frame::Publish {
-                    id: None,
-                    channel,
-                    event: msg.event.clone(),
-                    payload: msg.payload.to_vec(),
+            // Flush coalesced frames once they are due
+            _ = sleep_until(flush_at.unwrap_or_else(Instant::now)), if flush_at.is_some() => {
+                if writer.flush().await.is_err() {
+                    break;
+                }
+            }
+
+            // Drop clients that went quiet for longer than their negotiated timeout
+            _ = sleep_until(last_seen + session.heartbeat_timeout) => {
+                info!(connection = %connection_id, timeout_ms = session.heartbeat_timeout.as_millis(), "Heartbeat timed out");
+                metrics::record_error("heartbeat_timeout", Transport::WebSocket);
+                let _ = writer.send_message(Message::Close(None)).await;
+                break;
+            }
+
+            // Warn, then drop, clients that stopped sending frames
+            _ = sleep_until(idle_at.unwrap_or_else(Instant::now)), if idle_at.is_some() => {
+                let (timeout, warning) = idle.unwrap_or_default();
+                if !idle_warned {
+                    idle_warned = true;
+                    let frame = Frame::error(
+                        0,
+                        error_codes::IDLE_WARNING,
+                        format!("Idle; closing in {}s unless a frame is sent", warning.as_secs()),
+                    );
+                    if writer.send(&frame).await.is_err() || writer.flush().await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+                info!(connection = %connection_id, timeout_ms = timeout.as_millis(), "Idle timed out");
+                metrics::record_error("idle_timeout", Transport::WebSocket);
+                let frame = Frame::disconnect(DisconnectReason::Idle, "Idle for too long", None);
+                let _ = writer.send(&frame).await;
+                let _ = writer.send_message(Message::Close(None)).await;
+                break;
+            }
+
+            // Receive messages from subscribed channels
+            msg = handle.recv() => {
+                let Some(msg) = msg else {
+                    // Closed by the server: overflow, shutdown, or an operator
+                    if let Some(reason) = handle.close_reason() {
+                        if handle.is_overflowed() {
+                            warn!(connection = %connection_id, "Outbound queue overflowed, disconnecting");
+                            metrics::record_error("slow_consumer", Transport::WebSocket);
+                        } else {
+                            info!(connection = %connection_id, reason = ?reason.reason, "Disconnecting");
+                        }
+                        let _ = writer.send(&disconnect_frame(reason)).await;
+                        let _ = writer.send_message(Message::Close(None)).await;
+                    }
+                    break;
+                };
+
+                #[cfg(feature = "chaos")]
+                match chaos::fault(&state.config.chaos, &msg) {
+                    Some(Fault::Delay(delay)) => tokio::time::sleep(delay).await,
+                    Some(Fault::Drop) => continue,
+                    Some(Fault::Lag) => {
+                        chaos::lag(&handle);
+                        continue;
+                    }
+                    // Dropping the socket unannounced looks like a network failure
+                    Some(Fault::Reset) => break,
+                    None => {}
+                }
+
+                // Forward the message to the WebSocket client; federated
+                // servers are told where it came from so it doesn't loop
+                let origin = if session.federated {
+                    msg.origin.as_deref().or(state.config.federation.region.as_deref())
+                } else {
+                    None
                 };
-                if let Ok(data) = codec::encode(&frame) {
-                    metrics::record_message(data.len(), "outbound");
-                    if sender.send(Message::Binary(data.to_vec())).await.is_err() {
+                let deltas = session.capabilities.contains(Capabilities::PRESENCE_DIFF);
+                let frame = message_frame(msg.channel.to_string(), &msg, origin, deltas);
+                metrics::record_payload_size(msg.payload.len(), "outbound");
+                if writer.deliver(&frame, msg.created_at).await.is_err() {
+                    break;
+                }
+                if msg.trace {
+                    // Skip coalescing so the trace shows when it was sent
+                    if writer.flush().await.is_err() {
                         break;
                     }
+                    state.tracer.written(&msg, &connection_id);
                 }
+                state.stats.record_delivered();
             }
 
             // Receive from WebSocket
             msg = receiver.next() => {
+                last_seen = Instant::now();
                 match msg {
                     Some(Ok(Message::Binary(data))) => {
                         let start = Instant::now();
+                        handle.record_bytes_in(data.len());
                         read_buffer.extend_from_slice(&data);
 
                         // Try to decode frames
-                        while let Ok(Some(frame)) = codec::decode_from(&mut read_buffer) {
-                            metrics::record_message(data.len(), "inbound");
+                        let mut oversized = false;
+                        loop {
+                            let frame = match codec::decode_from_limited(&mut read_buffer, max_frame_size) {
+                                Ok(Some(frame)) => frame,
+                                Ok(None) => break,
+                                Err(ProtocolError::FrameTooLarge(size)) => {
+                                    warn!(connection = %connection_id, size, "Frame too large");
+                                    metrics::record_error("payload_too_large", Transport::WebSocket);
+                                    let frame = Frame::error(
+                                        0,
+                                        error_codes::PAYLOAD_TOO_LARGE,
+                                        format!("Frame of {size} bytes exceeds limit of {max_frame_size}"),
+                                    );
+                                    if writer.send(&frame).await.is_err() {
+                                        break;
+                                    }
+
+                                    // Skip the frame if it arrived whole; otherwise the
+                                    // stream cannot be resynchronized, so close it
+                                    let total = codec::LENGTH_PREFIX_SIZE + size;
+                                    if read_buffer.len() >= total {
+                                        read_buffer.advance(total);
+                                        continue;
+                                    }
+                                    let _ = writer.flush().await;
+                                    oversized = true;
+                                    break;
+                                }
+                                Err(e) => {
+                                    // The malformed frame was consumed; carry on with the next
+                                    warn!(connection = %connection_id, error = %e, "Invalid frame");
+                                    metrics::record_error("invalid_frame", Transport::WebSocket);
+                                    let frame = Frame::error(0, error_codes::INVALID_FRAME, e.to_string());
+                                    if writer.send(&frame).await.is_err() {
+                                        break;
+                                    }
+                                    continue;
+                                }
+                            };
+                            metrics::record_message(data.len(), "inbound", Transport::WebSocket);
+                            last_frame = Instant::now();
+                            idle_warned = false;
 
                             if let Err(e) = handle_frame(
                                 &frame,
                                 &connection_id,
                                 &state,
-                                &mut sender,
-                                &mut subscription_tasks,
-                                &sub_tx,
+                                &mut writer,
+                                &handle,
+                                &mut session,
                             ).await {
                                 error!(connection = %connection_id, error = %e, "Frame handling error");
                                 break;
                             }
                         }
 
-                        metrics::record_latency(start.elapsed().as_secs_f64());
+                        metrics::record_latency(start.elapsed().as_secs_f64(), Transport::WebSocket);
+                        if oversized {
+                            break;
+                        }
                     }
                     Some(Ok(Message::Text(text))) => {
                         // Treat text as binary
+                        handle.record_bytes_in(text.len());
                         read_buffer.extend_from_slice(text.as_bytes());
                     }
                     Some(Ok(Message::Ping(data))) => {
-                        if sender.send(Message::Pong(data)).await.is_err() {
+                        if writer.send_message(Message::Pong(data)).await.is_err() {
                             break;
                         }
                     }
@@ -206,7 +931,7 @@ async fn handle_websocket(socket: WebSocket, state: Arc<AppState>) {
                     }
                     Some(Err(e)) => {
                         warn!(connection = %connection_id, error = %e, "WebSocket error");
-                        metrics::record_error("websocket");
+                        metrics::record_error("websocket", Transport::WebSocket);
                         break;
                     }
                     None => {
@@ -218,124 +943,624 @@ async fn handle_websocket(socket: WebSocket, state: Arc<AppState>) {
         }
     }
 
-    // Cleanup: abort all subscription tasks
-    for (_, handle) in subscription_tasks {
-        handle.abort();
-    }
-
     // Cleanup: unsubscribe from all channels
-    state.router.unsubscribe_all(&connection_id);
+    state.router.disconnect(&handle);
+    if session.federated {
+        state.router.remove_remote_presence(&connection_id);
+    }
+    state.usage.disconnect(&handle, SystemTime::now());
+    state.usage_reports.disconnect(&handle);
+    metrics::record_connection_lag(handle.max_queued(), Transport::WebSocket);
+    state.audit.record(AuditEvent::for_connection(
+        AuditKind::Disconnect,
+        &handle,
+        "websocket",
+    ));
+    pool::global().release(read_buffer);
     metrics::set_active_channels(state.router.stats().channel_count);
 
     debug!(connection = %connection_id, "WebSocket disconnected");
 }
 
+/// Per-connection state negotiated after the upgrade.
+struct Session {
+    /// Close the connection after this long without inbound traffic.
+    heartbeat_timeout: Duration,
+    /// Whether the peer is another Pulse server that presented the
+    /// federation credential; such peers may subscribe to channel patterns.
+    federated: bool,
+    /// Whether the client presented the admin token and may subscribe to
+    /// system channels.
+    admin: bool,
+    /// Optional features negotiated with the client's Connect frame.
+    capabilities: Capabilities,
+    /// Limits subscription churn.
+    subscriptions: SubscriptionLimiter,
+}
+
 /// Handle a decoded frame.
 async fn handle_frame(
     frame: &Frame,
     connection_id: &str,
     state: &Arc<AppState>,
-    sender: &mut futures_util::stream::SplitSink<WebSocket, Message>,
-    subscription_tasks: &mut HashMap<String, tokio::task::JoinHandle<()>>,
-    sub_tx: &tokio::sync::mpsc::UnboundedSender<(String, Arc<tenvis_pulse_core::Message>)>,
+    writer: &mut FrameWriter,
+    handle: &Arc<ConnectionHandle>,
+    session: &mut Session,
 ) -> Result<()> {
+    if let Frame::Subscribe { id, .. } | Frame::Unsubscribe { id, .. } = frame {
+        if !session.federated {
+            match session.subscriptions.check(std::time::Instant::now()) {
+                Verdict::Allow => {}
+                Verdict::Throttle => {
+                    metrics::record_error("subscription_rate_limited", Transport::WebSocket);
+                    let response = Frame::error(
+                        *id,
+                        error_codes::RATE_LIMITED,
+                        "Too many subscription changes",
+                    );
+                    writer.send(&response).await?;
+                    return Ok(());
+                }
+                Verdict::Disconnect => {
+                    warn!(connection = %connection_id, "Disconnecting client for subscription churn");
+                    metrics::record_error("subscription_rate_limited", Transport::WebSocket);
+                    handle.close_with(CloseReason::new(
+                        DisconnectReason::RateLimited,
+                        "Too many subscription changes",
+                    ));
+                    return Ok(());
+                }
+            }
+        }
+    }
     match frame {
-        Frame::Subscribe { id, channel } => {
+        // Federation links relay whole groups of channels
+        Frame::Subscribe { id, channel, .. } | Frame::Unsubscribe { id, channel }
+            if session.federated && !ChannelPattern::new(channel.as_str()).is_literal() =>
+        {
+            let result = if matches!(frame, Frame::Subscribe { .. }) {
+                state.router.subscribe_pattern(handle, channel.as_str())
+            } else {
+                state.router.unsubscribe_pattern(connection_id, channel)
+            };
+            debug!(connection = %connection_id, pattern = %channel, ok = result.is_ok(), "Federation pattern request");
+            let response = match result {
+                Ok(()) if matches!(frame, Frame::Subscribe { .. }) => {
+                    Frame::subscribe_ok(*id, channel.as_str(), 0, 0, 0, 0)
+                }
+                Ok(()) => Frame::ack(*id),
+                Err(e) => Frame::error(*id, error_code(&e), e.to_string()),
+            };
+            writer.send(&response).await?;
+        }
+
+        Frame::Subscribe {
+            id,
+            channel,
+            auth,
+            options,
+        } => {
             debug!(connection = %connection_id, channel = %channel, "Subscribe request");
 
-            let response = match state.router.subscribe(connection_id, channel) {
-                Ok(mut rx) => {
-                    // Spawn a task to forward messages from broadcast to mpsc
-                    let channel_name = channel.clone();
-                    let tx = sub_tx.clone();
-                    let handle = tokio::spawn(async move {
-                        loop {
-                            match rx.recv().await {
-                                Ok(msg) => {
-                                    if tx.send((channel_name.clone(), msg)).is_err() {
-                                        break; // Receiver dropped
-                                    }
-                                }
-                                Err(broadcast::error::RecvError::Closed) => break,
-                                Err(broadcast::error::RecvError::Lagged(_)) => continue,
-                            }
-                        }
-                    });
-                    subscription_tasks.insert(channel.clone(), handle);
+            if !session.federated && !state.allow_channel_creation(handle, channel, false) {
+                debug!(connection = %connection_id, channel = %channel, "Channel creation rate limited");
+                metrics::record_error("channel_creation_rate_limited", Transport::WebSocket);
+                let frame = Frame::error(*id, error_codes::RATE_LIMITED, "Too many new channels");
+                writer.send(&frame).await?;
+                return Ok(());
+            }
+
+            let result = if let Some(name) = &options.durable {
+                state.router.subscribe_durable(
+                    handle,
+                    name,
+                    channel,
+                    auth.as_deref(),
+                    options.clone(),
+                )
+            } else if !channel.starts_with(SYSTEM_CHANNEL_PREFIX) {
+                state.router.subscribe_handle_with(
+                    handle,
+                    channel,
+                    auth.as_deref(),
+                    options.clone(),
+                )
+            } else if session.admin || (session.federated && channel == standby::STATE_CHANNEL) {
+                state
+                    .router
+                    .subscribe_system(handle, channel, options.clone())
+            } else {
+                Err(RouterError::Unauthorized(channel.clone()))
+            };
+            let subscribed = result.is_ok();
+            let response = match result {
+                Ok(info) => {
                     metrics::record_subscription();
                     metrics::set_active_channels(state.router.stats().channel_count);
-                    Frame::ack(*id)
+                    debug!(connection = %connection_id, channel = %channel, replayed = info.replayed, "Subscribed");
+                    let count = |n: usize| u32::try_from(n).unwrap_or(u32::MAX);
+                    Frame::subscribe_ok(
+                        *id,
+                        channel.as_str(),
+                        info.seq,
+                        count(info.subscribers),
+                        count(info.presence),
+                        count(info.history),
+                    )
                 }
                 Err(e) => {
                     warn!(connection = %connection_id, error = %e, "Subscribe failed");
-                    Frame::error(*id, 1002, e.to_string())
+                    if matches!(e, RouterError::Unauthorized(_)) {
+                        state.audit.record(AuditEvent::for_connection(
+                            AuditKind::AuthFailure,
+                            handle,
+                            format!("Subscribe to {channel}: {e}"),
+                        ));
+                    }
+                    if matches!(e, RouterError::MaxChannelsReached) {
+                        metrics::record_channel_rejected();
+                    }
+                    Frame::error(*id, error_code(&e), e.to_string())
                 }
             };
 
-            send_frame(sender, &response).await?;
+            writer.send(&response).await?;
+
+            // Presence channels start with a full membership sync
+            if subscribed
+                && !options.no_presence
+                && ChannelKind::from_name(channel).tracks_presence()
+            {
+                writer.send(&presence_sync_frame(0, channel, state)).await?;
+            }
         }
 
         Frame::Unsubscribe { id, channel } => {
             debug!(connection = %connection_id, channel = %channel, "Unsubscribe request");
 
-            // Abort the subscription task
-            if let Some(handle) = subscription_tasks.remove(channel) {
-                handle.abort();
-            }
-
             let response = match state.router.unsubscribe(connection_id, channel) {
                 Ok(()) => {
                     metrics::set_active_channels(state.router.stats().channel_count);
                     Frame::ack(*id)
                 }
-                Err(e) => Frame::error(*id, 1008, e.to_string()),
+                Err(e) => Frame::error(*id, error_code(&e), e.to_string()),
             };
 
-            send_frame(sender, &response).await?;
+            writer.send(&response).await?;
         }
 
         Frame::Publish {
             id,
             channel,
             event,
+            priority,
+            coalesce_key,
+            encrypted,
+            key_id,
+            signer,
+            signature,
+            origin,
+            trace,
             payload,
+            ..
         } => {
             debug!(connection = %connection_id, channel = %channel, "Publish");
 
-            let mut message = tenvis_pulse_core::Message::new(channel.clone(), payload.clone())
-                .with_source(connection_id);
+            // Only federated servers say where a message came from; one that
+            // started here has gone round a cycle of links
+            let origin = origin.as_ref().filter(|_| session.federated);
+            if origin.is_some() && origin == state.config.federation.region.as_ref() {
+                debug!(connection = %connection_id, channel = %channel, "Dropped message relayed back to its region");
+                return Ok(());
+            }
+
+            // Only the server publishes to reserved channels
+            if channel.starts_with('$') {
+                let frame = Frame::error(
+                    id.unwrap_or(0),
+                    error_codes::FORBIDDEN,
+                    "Channels starting with '$' are reserved",
+                );
+                writer.send(&frame).await?;
+                return Ok(());
+            }
+
+            if let Err((code, reason)) =
+                state.check_payload_size(channel, payload.len(), Transport::WebSocket)
+            {
+                warn!(connection = %connection_id, channel = %channel, size = payload.len(), "Payload too large");
+                writer
+                    .send(&Frame::error(id.unwrap_or(0), code, reason))
+                    .await?;
+                return Ok(());
+            }
+            metrics::record_payload_size(payload.len(), "inbound");
+
+            let mut message = tenvis_pulse_core::Message::new(channel.as_str(), payload.clone())
+                .with_source(connection_id)
+                .with_priority(*priority);
+            if let Some(origin) = origin {
+                message = message.with_origin(origin.clone());
+            }
 
             if let Some(evt) = event {
                 message = message.with_event(evt.clone());
             }
+            if let Some(key) = coalesce_key {
+                message = message.with_coalesce_key(key.clone());
+            }
+            if *encrypted {
+                message = message.with_encryption(key_id.clone());
+            }
+            if let (Some(signer), Some(signature)) = (signer, signature) {
+                message = message.with_signature(signer.clone(), signature.clone());
+            }
+            // Only admins may trace, as trace events name every subscriber
+            if *trace && session.admin {
+                message = message.with_trace();
+            }
+            let message = state.shedder.deprioritize(message);
+            state.tracer.received(&message);
+            if let Err(reason) = state.signing_keys.check(&message) {
+                debug!(connection = %connection_id, channel = %channel, reason = %reason, "Publish failed signature check");
+                metrics::record_error("invalid_signature", Transport::WebSocket);
+                let frame = Frame::error(id.unwrap_or(0), error_codes::UNAUTHORIZED, reason);
+                writer.send(&frame).await?;
+                return Ok(());
+            }
+            if let Err(reason) = state.schemas.check(&message) {
+                debug!(connection = %connection_id, channel = %channel, reason = %reason, "Publish failed schema validation");
+                metrics::record_error("schema_violation", Transport::WebSocket);
+                let frame = Frame::error(id.unwrap_or(0), error_codes::INVALID_PAYLOAD, reason);
+                writer.send(&frame).await?;
+                return Ok(());
+            }
+            if let Err(rejection) = state.moderate(&message, handle).await {
+                debug!(connection = %connection_id, channel = %channel, reason = %rejection, "Publish rejected");
+                let frame = Frame::error(id.unwrap_or(0), error_codes::REJECTED, rejection.reason);
+                writer.send(&frame).await?;
+                return Ok(());
+            }
+            if !session.federated && !state.allow_channel_creation(handle, channel, true) {
+                debug!(connection = %connection_id, channel = %channel, "Channel creation rate limited");
+                metrics::record_error("channel_creation_rate_limited", Transport::WebSocket);
+                let frame = Frame::error(
+                    id.unwrap_or(0),
+                    error_codes::RATE_LIMITED,
+                    "Too many new channels",
+                );
+                writer.send(&frame).await?;
+                return Ok(());
+            }
 
-            let count = state.router.publish(message);
-            metrics::record_message(payload.len(), "broadcast");
+            // Federation peers relay messages their own side already authorized
+            let receipt = if session.federated {
+                Ok(state.router.publish_with_receipt(message))
+            } else {
+                state.router.publish_from(connection_id, message)
+            };
+            let receipt = match receipt {
+                Ok(receipt) => receipt,
+                Err(e) => {
+                    debug!(connection = %connection_id, channel = %channel, error = %e, "Publish refused");
+                    metrics::record_error("unauthorized_publish", Transport::WebSocket);
+                    let frame = Frame::error(id.unwrap_or(0), error_code(&e), e.to_string());
+                    writer.send(&frame).await?;
+                    return Ok(());
+                }
+            };
+            let count = receipt.recipients;
+            metrics::record_fanout(count);
+            metrics::record_message(payload.len(), "broadcast", Transport::WebSocket);
+            state.stats.record_published();
+            handle.record_messages_in(1);
 
-            // Send ack if requested
+            // Confirm if requested, so producers can tell a publish reached nobody
             if let Some(req_id) = id {
-                send_frame(sender, &Frame::ack(*req_id)).await?;
+                let recipients = u32::try_from(count).unwrap_or(u32::MAX);
+                let frame = Frame::publish_ok(*req_id, receipt.id, receipt.seq, recipients);
+                writer.send(&frame).await?;
             }
 
             debug!(connection = %connection_id, channel = %channel, recipients = count, "Published");
         }
 
+        Frame::PublishGroup {
+            id,
+            messages,
+            priority,
+        } => {
+            debug!(connection = %connection_id, messages = messages.len(), "Publish group");
+
+            let max_group_messages = state.config.limits.max_group_messages;
+            let rejection = if max_group_messages == 0 {
+                Some((
+                    error_codes::FORBIDDEN,
+                    "Group publishes are disabled".to_string(),
+                ))
+            } else if messages.is_empty() {
+                Some((
+                    error_codes::INVALID_FRAME,
+                    "Group has no messages".to_string(),
+                ))
+            } else if messages.len() > max_group_messages {
+                Some((
+                    error_codes::PAYLOAD_TOO_LARGE,
+                    format!(
+                        "Group of {} messages exceeds limit of {max_group_messages}",
+                        messages.len()
+                    ),
+                ))
+            } else if messages.iter().any(|m| m.channel.starts_with('$')) {
+                Some((
+                    error_codes::FORBIDDEN,
+                    "Channels starting with '$' are reserved".to_string(),
+                ))
+            } else {
+                messages.iter().find_map(|m| {
+                    state
+                        .check_payload_size(&m.channel, m.payload.len(), Transport::WebSocket)
+                        .err()
+                })
+            };
+            if let Some((code, message)) = rejection {
+                writer
+                    .send(&Frame::error(id.unwrap_or(0), code, message))
+                    .await?;
+                return Ok(());
+            }
+
+            let group = messages
+                .iter()
+                .map(|m| {
+                    metrics::record_payload_size(m.payload.len(), "inbound");
+                    let mut message =
+                        tenvis_pulse_core::Message::new(m.channel.as_str(), m.payload.clone())
+                            .with_source(connection_id)
+                            .with_priority(*priority);
+                    if let Some(evt) = &m.event {
+                        message = message.with_event(evt.clone());
+                    }
+                    if m.encrypted {
+                        message = message.with_encryption(m.key_id.clone());
+                    }
+                    if let (Some(signer), Some(signature)) = (&m.signer, &m.signature) {
+                        message = message.with_signature(signer.clone(), signature.clone());
+                    }
+                    state.shedder.deprioritize(message)
+                })
+                .collect::<Vec<_>>();
+            // One rejected message rejects the whole group
+            for message in &group {
+                if let Err(reason) = state.signing_keys.check(message) {
+                    debug!(connection = %connection_id, channel = %message.channel, reason = %reason, "Publish group failed signature check");
+                    metrics::record_error("invalid_signature", Transport::WebSocket);
+                    let frame = Frame::error(id.unwrap_or(0), error_codes::UNAUTHORIZED, reason);
+                    writer.send(&frame).await?;
+                    return Ok(());
+                }
+                if let Err(reason) = state.schemas.check(message) {
+                    debug!(connection = %connection_id, channel = %message.channel, reason = %reason, "Publish group failed schema validation");
+                    metrics::record_error("schema_violation", Transport::WebSocket);
+                    let frame = Frame::error(id.unwrap_or(0), error_codes::INVALID_PAYLOAD, reason);
+                    writer.send(&frame).await?;
+                    return Ok(());
+                }
+                if let Err(rejection) = state.moderate(message, handle).await {
+                    debug!(connection = %connection_id, channel = %message.channel, reason = %rejection, "Publish group rejected");
+                    let frame =
+                        Frame::error(id.unwrap_or(0), error_codes::REJECTED, rejection.reason);
+                    writer.send(&frame).await?;
+                    return Ok(());
+                }
+            }
+            let mut channels: Vec<&str> = messages.iter().map(|m| m.channel.as_str()).collect();
+            channels.sort_unstable();
+            channels.dedup();
+            if !session.federated
+                && !channels
+                    .iter()
+                    .all(|channel| state.allow_channel_creation(handle, channel, true))
+            {
+                debug!(connection = %connection_id, "Channel creation rate limited");
+                metrics::record_error("channel_creation_rate_limited", Transport::WebSocket);
+                let frame = Frame::error(
+                    id.unwrap_or(0),
+                    error_codes::RATE_LIMITED,
+                    "Too many new channels",
+                );
+                writer.send(&frame).await?;
+                return Ok(());
+            }
+            let receipt = if session.federated {
+                Ok(state.router.publish_group(group))
+            } else {
+                state.router.publish_group_from(connection_id, group)
+            };
+            let receipt = match receipt {
+                Ok(receipt) => receipt,
+                Err(e) => {
+                    debug!(connection = %connection_id, error = %e, "Publish group refused");
+                    metrics::record_error("unauthorized_publish", Transport::WebSocket);
+                    let frame = Frame::error(id.unwrap_or(0), error_code(&e), e.to_string());
+                    writer.send(&frame).await?;
+                    return Ok(());
+                }
+            };
+            handle.record_messages_in(messages.len());
+            for (m, r) in messages.iter().zip(&receipt.receipts) {
+                metrics::record_fanout(r.recipients);
+                metrics::record_message(m.payload.len(), "broadcast", Transport::WebSocket);
+                state.stats.record_published();
+            }
+
+            // Confirmed like a publish, with the group ID as the message ID
+            if let Some(req_id) = id {
+                let recipients = u32::try_from(receipt.recipients()).unwrap_or(u32::MAX);
+                let frame = Frame::publish_ok(*req_id, receipt.group_id, 0, recipients);
+                writer.send(&frame).await?;
+            }
+        }
+
+        Frame::Presence {
+            id,
+            channel,
+            action,
+            data,
+        } => {
+            debug!(connection = %connection_id, channel = %channel, action = ?action, "Presence");
+
+            let response = match action {
+                PresenceAction::Join | PresenceAction::Update => {
+                    let data = match data {
+                        None if *action == PresenceAction::Join
+                            && state.config.metadata.presence_defaults =>
+                        {
+                            handle
+                                .metadata()
+                                .and_then(|metadata| serde_json::to_value(metadata).ok())
+                        }
+                        data => data.clone(),
+                    };
+                    match state.router.presence_join(connection_id, channel, data) {
+                        Ok(_) => Frame::ack(*id),
+                        Err(e) => Frame::error(*id, error_code(&e), e.to_string()),
+                    }
+                }
+                PresenceAction::Leave => {
+                    state.router.presence_leave(connection_id, channel);
+                    Frame::ack(*id)
+                }
+                // A linked server sending its members, or asking for ours
+                PresenceAction::Sync if session.federated => match data {
+                    Some(data) => {
+                        let members = federation::remote_members(Some(data.clone()));
+                        state
+                            .router
+                            .set_remote_presence(channel, connection_id, members);
+                        return Ok(());
+                    }
+                    None => Frame::Presence {
+                        id: *id,
+                        channel: channel.clone(),
+                        action: PresenceAction::Sync,
+                        data: serde_json::to_value(state.router.local_presence_snapshot(channel))
+                            .ok(),
+                    },
+                },
+                PresenceAction::Sync => presence_sync_frame(*id, channel, state),
+            };
+
+            writer.send(&response).await?;
+        }
+
         Frame::Ping { timestamp } => {
-            send_frame(sender, &Frame::pong(*timestamp)).await?;
+            state.router.touch_presence(connection_id);
+            writer.send(&Frame::pong(*timestamp)).await?;
         }
 
         Frame::Pong { .. } => {
-            // Update last seen for presence
+            state.router.touch_presence(connection_id);
         }
 
-        Frame::Connect { version, token } => {
+        Frame::Connect {
+            version,
+            token,
+            capabilities,
+            heartbeat,
+            user_id,
+        } => {
             debug!(
                 connection = %connection_id,
                 version = version,
                 has_token = token.is_some(),
-                "Connect frame (already connected)"
+                capabilities = capabilities.bits(),
+                heartbeat = ?heartbeat,
+                user_id = ?user_id,
+                "Connect frame"
             );
-            // Connection already established, ignore
+
+            if *version != PROTOCOL_VERSION.major {
+                let message = format!("Protocol version {version} is not supported");
+                writer
+                    .send(&Frame::error(0, error_codes::PROTOCOL_MISMATCH, message))
+                    .await?;
+                return Ok(());
+            }
+
+            let credential = state.config.federation.credential.as_deref();
+            if federation::authenticates(credential, token.as_deref()) {
+                info!(connection = %connection_id, "Federation link connected");
+                session.federated = true;
+                state.audit.record(AuditEvent::for_connection(
+                    AuditKind::Identify,
+                    handle,
+                    "Federation link",
+                ));
+            }
+
+            let admin_token = state.config.admin.token.as_deref();
+            if let (Some(expected), Some(token)) = (admin_token, token.as_deref()) {
+                if admin::constant_time_eq(expected, token) {
+                    info!(connection = %connection_id, "Admin client connected");
+                    session.admin = true;
+                    state.audit.record(AuditEvent::for_connection(
+                        AuditKind::Identify,
+                        handle,
+                        "Admin token",
+                    ));
+                }
+            }
+
+            if let Some(user_id) = user_id {
+                if let Some(ban) = state.denylist.check(&BanTarget::User(user_id.clone())) {
+                    debug!(connection = %connection_id, user_id = %user_id, "Rejected banned user");
+                    state.audit.record(
+                        AuditEvent::for_connection(
+                            AuditKind::AccessDenied,
+                            handle,
+                            format!("User banned: {}", ban.reason),
+                        )
+                        .with_user(user_id.as_str()),
+                    );
+                    handle.close_with(ban.close_reason());
+                    return Ok(());
+                }
+                if let Some(reason) = state.usage.check(user_id, SystemTime::now()) {
+                    debug!(connection = %connection_id, user_id = %user_id, "Rejected user over quota");
+                    handle.close_with(reason);
+                    return Ok(());
+                }
+                if handle.set_user_id(user_id.as_str()) {
+                    state.audit.record(AuditEvent::for_connection(
+                        AuditKind::Identify,
+                        handle,
+                        "User ID",
+                    ));
+                }
+            }
+
+            // Only use features both sides understand
+            let negotiated = *capabilities & state.capabilities();
+            session.capabilities = negotiated;
+            if negotiated.contains(Capabilities::BATCHING) {
+                writer.set_flush_interval(Duration::from_millis(
+                    state.config.transport.write_coalesce_ms,
+                ));
+            }
+
+            // Clients may trade liveness for battery within the configured bounds
+            let (interval, timeout) = state.config.heartbeat.negotiate(*heartbeat);
+            session.heartbeat_timeout = timeout;
+
+            writer
+                .send(&connected_frame(
+                    connection_id,
+                    interval,
+                    negotiated,
+                    state.affinity_token(),
+                ))
+                .await?;
         }
 
         _ => {
@@ -346,13 +1571,457 @@ async fn handle_frame(
     Ok(())
 }
 
-/// Send a frame to the WebSocket.
-async fn send_frame(
-    sender: &mut futures_util::stream::SplitSink<WebSocket, Message>,
-    frame: &Frame,
-) -> Result<()> {
-    let data = codec::encode(frame)?;
-    metrics::record_message(data.len(), "outbound");
-    sender.send(Message::Binary(data.to_vec())).await?;
-    Ok(())
+/// Build the frame delivering a routed message to a subscriber, with
+/// presence updates as deltas if it negotiated `PRESENCE_DIFF`.
+fn message_frame(
+    channel: String,
+    msg: &tenvis_pulse_core::Message,
+    origin: Option<&str>,
+    presence_deltas: bool,
+) -> Frame {
+    match msg.kind {
+        MessageKind::Publish => Frame::Publish {
+            id: None,
+            channel,
+            event: msg.event.clone(),
+            seq: Some(msg.seq),
+            message_id: Some(msg.id),
+            timestamp: Some(msg.timestamp),
+            priority: msg.priority,
+            coalesce_key: None,
+            group_id: msg.group_id,
+            encrypted: msg.encrypted,
+            key_id: msg.key_id.clone(),
+            signer: msg.signer.clone(),
+            signature: msg.signature.clone(),
+            origin: origin.map(str::to_string),
+            trace: false,
+            payload: msg.payload.to_vec(),
+        },
+        MessageKind::Presence(action) => {
+            let data = match &msg.presence_delta {
+                Some(delta) if presence_deltas => delta,
+                _ => &msg.payload,
+            };
+            Frame::Presence {
+                id: 0,
+                channel,
+                action,
+                data: serde_json::from_slice(data).ok(),
+            }
+        }
+        MessageKind::ReplayEnd {
+            replayed,
+            truncated,
+        } => Frame::ReplayEnd {
+            channel,
+            seq: msg.seq,
+            replayed: u32::try_from(replayed).unwrap_or(u32::MAX),
+            truncated,
+        },
+    }
+}
+
+/// Build a Connected frame for a connection.
+fn connected_frame(
+    connection_id: &str,
+    heartbeat: Duration,
+    capabilities: Capabilities,
+    affinity: Option<String>,
+) -> Frame {
+    Frame::Connected {
+        connection_id: connection_id.to_string(),
+        version: PROTOCOL_VERSION.major,
+        heartbeat: u32::try_from(heartbeat.as_millis()).unwrap_or(u32::MAX),
+        capabilities,
+        affinity,
+    }
+}
+
+/// Build the Disconnect frame telling a client why it is being closed.
+fn disconnect_frame(reason: &CloseReason) -> Frame {
+    Frame::Disconnect {
+        reason: reason.reason,
+        message: reason.message.clone(),
+        reconnect_after: reason
+            .reconnect_after
+            .map(|d| u32::try_from(d.as_millis()).unwrap_or(u32::MAX)),
+        alternate_host: reason.alternate_host.clone(),
+    }
+}
+
+/// Build a presence Sync frame with the channel's full membership.
+fn presence_sync_frame(id: u64, channel: &str, state: &AppState) -> Frame {
+    let members = state.router.presence_snapshot(channel);
+    Frame::Presence {
+        id,
+        channel: channel.to_string(),
+        action: PresenceAction::Sync,
+        data: serde_json::to_value(members).ok(),
+    }
+}
+
+/// Map a router error to a protocol error code.
+pub(crate) fn error_code(err: &RouterError) -> u16 {
+    match err {
+        RouterError::InvalidChannel(_) => error_codes::INVALID_CHANNEL,
+        RouterError::ChannelNotFound(_) => error_codes::CHANNEL_NOT_FOUND,
+        RouterError::NotSubscribed(_) => error_codes::NOT_SUBSCRIBED,
+        RouterError::AlreadySubscribed(_) => error_codes::ALREADY_SUBSCRIBED,
+        RouterError::MaxSubscriptionsReached => error_codes::FORBIDDEN,
+        RouterError::MaxChannelsReached => error_codes::FORBIDDEN,
+        RouterError::ChannelFull(_) => error_codes::CHANNEL_FULL,
+        RouterError::Unauthorized(_) => error_codes::FORBIDDEN,
+        RouterError::DurableInUse(_) => error_codes::ALREADY_SUBSCRIBED,
+        RouterError::DurableChannelMismatch(_) => error_codes::FORBIDDEN,
+        RouterError::MaxDurableSubscriptionsReached => error_codes::FORBIDDEN,
+        RouterError::InvalidCursor(_) => error_codes::INVALID_FRAME,
+        RouterError::Internal(_) => error_codes::SERVER_ERROR,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{Config, FederationLink};
+    use crate::testing::{TestServer, TIMEOUT};
+    use pulse_protocol::{Capabilities, Frame, PresenceAction, SubscribeOptions, PROTOCOL_VERSION};
+    use std::time::Duration;
+    use tenvis_pulse_core::{ChannelRule, HmacAuthorizer};
+
+    #[tokio::test]
+    async fn test_e2e_fanout() {
+        let server = TestServer::start().await;
+        let [mut publisher, mut alice, mut bob] = server.clients().await;
+        alice.subscribe("chat:lobby").await;
+        let subscribed = bob
+            .subscribe_with(
+                "chat:lobby",
+                SubscribeOptions {
+                    no_echo: true,
+                    ..SubscribeOptions::default()
+                },
+            )
+            .await;
+        assert!(matches!(
+            subscribed,
+            Frame::SubscribeOk { subscribers: 2, .. }
+        ));
+
+        let published = publisher.publish("chat:lobby", "hi").await;
+        assert!(matches!(published, Frame::PublishOk { recipients: 2, .. }));
+        for client in [&mut alice, &mut bob] {
+            let message = client.message("chat:lobby").await;
+            assert!(matches!(message, Frame::Publish { payload, .. } if payload == b"hi"));
+        }
+
+        // no_echo keeps bob's own publishes from him
+        bob.publish("chat:lobby", "mine").await;
+        alice.message("chat:lobby").await;
+        bob.assert_idle(Duration::from_millis(100)).await;
+    }
+
+    #[tokio::test]
+    async fn test_e2e_replay() {
+        let mut config = Config::default();
+        config
+            .channels
+            .push(ChannelRule::new("history:*").with_history_size(3));
+        let server = TestServer::with_config(config).await;
+        let [mut publisher, mut late] = server.clients().await;
+        // Channels exist while someone is subscribed
+        publisher.subscribe("history:feed").await;
+        for n in 1..=5 {
+            publisher.publish("history:feed", format!("{n}")).await;
+        }
+
+        let options = SubscribeOptions {
+            since: Some(3),
+            ..SubscribeOptions::default()
+        };
+        let subscribed = late.subscribe_with("history:feed", options).await;
+        assert!(matches!(
+            subscribed,
+            Frame::SubscribeOk {
+                seq: 5,
+                history: 3,
+                ..
+            }
+        ));
+        for seq in [4, 5] {
+            let message = late.message("history:feed").await;
+            assert!(matches!(message, Frame::Publish { seq: Some(s), .. } if s == seq));
+        }
+        let end = late.recv().await;
+        assert!(matches!(
+            end,
+            Frame::ReplayEnd {
+                replayed: 2,
+                truncated: false,
+                ..
+            }
+        ));
+
+        // Messages after ReplayEnd are live
+        publisher.publish("history:feed", "6").await;
+        let message = late.message("history:feed").await;
+        assert!(matches!(message, Frame::Publish { seq: Some(6), .. }));
+    }
+
+    #[tokio::test]
+    async fn test_e2e_channel_payload_limit() {
+        let mut config = Config::default();
+        config
+            .channels
+            .push(ChannelRule::new("signal:*").with_max_payload_size(4));
+        config.channel_names.allow_unicode = true;
+        config
+            .channels
+            .push(ChannelRule::new("caf\u{e9}:*").with_max_payload_size(4));
+        let server = TestServer::with_config(config).await;
+        let [mut publisher] = server.clients().await;
+
+        publisher.publish("signal:a", "ok").await;
+        publisher.publish("chat:a", "longer").await;
+        publisher
+            .send(&Frame::publish_with_ack(7, "signal:a", "longer"))
+            .await;
+        let code = publisher
+            .expect(|frame| match frame {
+                Frame::Error { id: 7, code, .. } => Some(*code),
+                _ => None,
+            })
+            .await;
+        assert_eq!(code, pulse_protocol::error_codes::CHANNEL_PAYLOAD_TOO_LARGE);
+
+        // The rule matches the name as normalized, not as spelled
+        publisher
+            .send(&Frame::publish_with_ack(8, "cafe\u{301}:a", "longer"))
+            .await;
+        let code = publisher
+            .expect(|frame| match frame {
+                Frame::Error { id: 8, code, .. } => Some(*code),
+                _ => None,
+            })
+            .await;
+        assert_eq!(code, pulse_protocol::error_codes::CHANNEL_PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_private_channel_publish() {
+        let mut config = Config::default();
+        config.auth.channel_secret = Some("channel-secret".to_string());
+        config.federation.credential = Some("hub-secret".to_string());
+        let server = TestServer::with_config(config).await;
+        let [mut member, mut outsider, mut peer] = server.clients().await;
+        let signature =
+            HmacAuthorizer::new("channel-secret").sign(&member.connection_id, "private:room");
+        let id = member.next_id();
+        member
+            .send(&Frame::subscribe_with_auth(id, "private:room", signature))
+            .await;
+        member.reply(id).await;
+
+        // A client that never passed the authorizer can't inject into it
+        outsider
+            .send(&Frame::publish_with_ack(7, "private:room", "forged"))
+            .await;
+        let code = outsider
+            .expect(|frame| match frame {
+                Frame::Error { id: 7, code, .. } => Some(*code),
+                _ => None,
+            })
+            .await;
+        assert_eq!(code, pulse_protocol::error_codes::FORBIDDEN);
+        member.assert_idle(Duration::from_millis(100)).await;
+
+        // A federation link relays what its own server already authorized
+        peer.send(&Frame::connect(
+            PROTOCOL_VERSION.major,
+            Some("hub-secret".to_string()),
+        ))
+        .await;
+        peer.expect(|frame| matches!(frame, Frame::Connected { .. }).then_some(()))
+            .await;
+        peer.publish("private:room", "relayed").await;
+        let message = member.message("private:room").await;
+        assert!(matches!(message, Frame::Publish { payload, .. } if payload == b"relayed"));
+    }
+
+    #[tokio::test]
+    async fn test_e2e_presence() {
+        let server = TestServer::start().await;
+        let [mut alice, mut bob] = server.clients().await;
+        alice.subscribe("presence:room").await;
+        bob.subscribe("presence:room").await;
+
+        let sync = bob.presence("presence:room", PresenceAction::Sync).await;
+        assert_eq!(sync.as_array().map(Vec::len), Some(2));
+        let join = alice
+            .expect(|frame| match frame {
+                Frame::Presence {
+                    action: PresenceAction::Join,
+                    data: Some(data),
+                    ..
+                } if data["connection_id"] == bob.connection_id.as_str() => Some(data.clone()),
+                _ => None,
+            })
+            .await;
+        assert_eq!(join["data"], serde_json::Value::Null);
+
+        let bob_id = bob.connection_id.clone();
+        bob.close().await;
+        let leave = alice.presence("presence:room", PresenceAction::Leave).await;
+        assert_eq!(leave["connection_id"], bob_id.as_str());
+        assert_eq!(
+            server.state.router.presence_snapshot("presence:room").len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_e2e_federated_presence() {
+        let mut config = Config::default();
+        config.federation.credential = Some("hub-secret".to_string());
+        let hub = TestServer::with_config(config).await;
+        let mut config = Config::default();
+        config.federation.links.push(FederationLink {
+            name: "hub".to_string(),
+            url: hub.url(),
+            credential: "hub-secret".to_string(),
+            credential_file: None,
+            channels: vec!["presence:*".into()],
+            send: Vec::new(),
+            receive: Vec::new(),
+            region: None,
+            transit: true,
+            reconnect_ms: 50,
+            presence_sync_ms: 50,
+        });
+        let spoke = TestServer::with_config(config).await;
+
+        let [mut alice] = hub.clients().await;
+        let [mut bob] = spoke.clients().await;
+        alice.subscribe("presence:room").await;
+        bob.subscribe("presence:room").await;
+
+        // Each server lists the other's member once the link syncs
+        let cluster_wide =
+            |server: &TestServer| server.state.router.presence_snapshot("presence:room").len() == 2;
+        tokio::time::timeout(TIMEOUT, async {
+            while !(cluster_wide(&hub) && cluster_wide(&spoke)) {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("presence was not synced");
+        let sync = alice.presence("presence:room", PresenceAction::Sync).await;
+        assert_eq!(sync.as_array().map(Vec::len), Some(1));
+        let id = alice.next_id();
+        alice
+            .send(&Frame::Presence {
+                id,
+                channel: "presence:room".to_string(),
+                action: PresenceAction::Sync,
+                data: None,
+            })
+            .await;
+        let sync = alice.presence("presence:room", PresenceAction::Sync).await;
+        assert_eq!(sync.as_array().map(Vec::len), Some(2));
+
+        // Members leave with their server's next sync
+        bob.close().await;
+        tokio::time::timeout(TIMEOUT, async {
+            while hub.state.router.presence_snapshot("presence:room").len() != 1 {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("departure was not synced");
+    }
+
+    #[tokio::test]
+    async fn test_e2e_batching() {
+        let mut config = Config::default();
+        config.transport.write_coalesce_ms = 200;
+        let server = TestServer::with_config(config).await;
+        let [mut publisher, mut plain, mut batched] = server.clients().await;
+        batched
+            .send(&Frame::Connect {
+                version: PROTOCOL_VERSION.major,
+                token: None,
+                capabilities: Capabilities::BATCHING,
+                heartbeat: None,
+                user_id: None,
+            })
+            .await;
+        let connected = batched.recv_message().await;
+        assert!(
+            matches!(connected[..], [Frame::Connected { capabilities, .. }] if capabilities.contains(Capabilities::BATCHING))
+        );
+        plain.subscribe("chat:lobby").await;
+        batched.subscribe("chat:lobby").await;
+
+        for n in 1..=3 {
+            publisher.publish("chat:lobby", format!("{n}")).await;
+        }
+        // Without BATCHING every message is a frame of its own
+        for _ in 1..=3 {
+            let frames = plain.recv_message().await;
+            assert!(matches!(frames[..], [Frame::Publish { .. }]));
+        }
+        // With it, frames within the coalescing window share a message
+        let frames = batched.recv_message().await;
+        assert_eq!(frames.len(), 3);
+        assert!(frames.iter().all(|f| matches!(f, Frame::Publish { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_e2e_presence_deltas() {
+        let server = TestServer::start().await;
+        let [mut member, mut full, mut deltas] = server.clients().await;
+        deltas
+            .send(&Frame::Connect {
+                version: PROTOCOL_VERSION.major,
+                token: None,
+                capabilities: Capabilities::PRESENCE_DIFF,
+                heartbeat: None,
+                user_id: None,
+            })
+            .await;
+        for client in [&mut member, &mut full, &mut deltas] {
+            client.subscribe("presence:room").await;
+        }
+
+        for status in ["online", "away"] {
+            let id = member.next_id();
+            let frame = Frame::Presence {
+                id,
+                channel: "presence:room".to_string(),
+                action: PresenceAction::Update,
+                data: Some(serde_json::json!({"name": "Alice", "status": status})),
+            };
+            member.send(&frame).await;
+            member.reply(id).await;
+        }
+        for client in [&mut full, &mut deltas] {
+            client
+                .presence("presence:room", PresenceAction::Update)
+                .await;
+        }
+
+        // Clients that didn't negotiate deltas get the whole state
+        let data = serde_json::json!({"name": "Alice", "status": "away"});
+        let update = full.presence("presence:room", PresenceAction::Update).await;
+        assert_eq!(update["data"], data);
+        let update = deltas
+            .presence("presence:room", PresenceAction::Update)
+            .await;
+        assert_eq!(
+            update,
+            serde_json::json!({
+                "connection_id": member.connection_id.as_str(),
+                "data": {"status": "away"}
+            })
+        );
+    }
 }
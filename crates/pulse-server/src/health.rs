@@ -0,0 +1,182 @@
+//! Liveness and readiness probes.
+//!
+//! `GET /livez` answers as long as the process serves HTTP. `GET /readyz`
+//! answers 503 while the server is draining for shutdown, is at its
+//! connection limit, is shedding load, is a standby that has not taken over,
+//! or has a backplane connection (a federation link or the Postgres bridge)
+//! down, so load balancers stop sending it clients before the process exits:
+//!
+//! ```json
+//! {"status": "unavailable", "draining": true, "accepting_connections": true,
+//!  "shedding": false, "standby": false,
+//!  "backplanes": {"link:us-east": true, "postgres": false}}
+//! ```
+
+use crate::handlers::AppState;
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Shutdown state and backplane connectivity behind `/readyz`.
+#[derive(Debug, Default)]
+pub struct Health {
+    draining: AtomicBool,
+    backplanes: Mutex<BTreeMap<String, bool>>,
+}
+
+impl Health {
+    /// Create a ready state with no backplanes.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mark the server as shutting down. It stays unready from then on.
+    ///
+    /// Returns `false` if it was already draining.
+    pub fn start_draining(&self) -> bool {
+        !self.draining.swap(true, Ordering::Relaxed)
+    }
+
+    /// Whether the server is shutting down.
+    #[must_use]
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Track a backplane connection, initially down.
+    pub fn register_backplane(&self, name: impl Into<String>) {
+        self.lock().insert(name.into(), false);
+    }
+
+    /// Record whether a registered backplane is connected.
+    pub fn set_backplane(&self, name: &str, connected: bool) {
+        if let Some(state) = self.lock().get_mut(name) {
+            *state = connected;
+        }
+    }
+
+    /// Connectivity of every registered backplane.
+    #[must_use]
+    pub fn backplanes(&self) -> BTreeMap<String, bool> {
+        self.lock().clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, bool>> {
+        self.backplanes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The `GET /readyz` response.
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    status: &'static str,
+    draining: bool,
+    accepting_connections: bool,
+    shedding: bool,
+    standby: bool,
+    backplanes: BTreeMap<String, bool>,
+}
+
+impl Readiness {
+    /// Whether every check passes.
+    #[must_use]
+    pub fn is_ready(&self) -> bool {
+        !self.draining
+            && self.accepting_connections
+            && !self.shedding
+            && !self.standby
+            && self.backplanes.values().all(|up| *up)
+    }
+}
+
+/// Evaluate readiness.
+#[must_use]
+pub fn readiness(state: &AppState) -> Readiness {
+    let mut readiness = Readiness {
+        status: "unavailable",
+        draining: state.health.is_draining(),
+        accepting_connections: state.stats.connections() < state.config.limits.max_connections,
+        shedding: state.shedder.is_shedding(),
+        standby: state.standby.is_waiting(),
+        backplanes: state.health.backplanes(),
+    };
+    if readiness.is_ready() {
+        readiness.status = "ok";
+    }
+    readiness
+}
+
+/// Serve liveness: the process is up.
+pub async fn livez_handler() -> Json<Value> {
+    Json(json!({
+        "status": "ok",
+        "version": env!("CARGO_PKG_VERSION")
+    }))
+}
+
+/// Serve readiness: 200 when the server should receive clients, else 503.
+pub async fn readyz_handler(State(state): State<Arc<AppState>>) -> (StatusCode, Json<Readiness>) {
+    let readiness = readiness(&state);
+    let status = if readiness.is_ready() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(readiness))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::stats::Transport;
+
+    #[test]
+    fn test_readiness() {
+        let state = AppState::new(Config::default()).unwrap();
+        assert!(readiness(&state).is_ready());
+
+        state.health.register_backplane("postgres");
+        assert!(!readiness(&state).is_ready());
+        state.health.set_backplane("postgres", true);
+        let ready = readiness(&state);
+        assert!(ready.is_ready());
+        assert_eq!(ready.status, "ok");
+
+        assert!(state.health.start_draining());
+        assert!(!state.health.start_draining());
+        let ready = readiness(&state);
+        assert!(!ready.is_ready());
+        assert_eq!(ready.status, "unavailable");
+    }
+
+    #[tokio::test]
+    async fn test_drain() {
+        let mut config = Config::default();
+        config.shutdown.grace_period_ms = 100;
+        let state = AppState::new(config).unwrap();
+        let handle = state.router.connect("conn-1");
+
+        crate::handlers::drain(&state).await;
+        assert!(!readiness(&state).is_ready());
+        let reason = handle.close_reason().unwrap();
+        assert_eq!(reason.reason, pulse_protocol::DisconnectReason::Shutdown);
+        assert!(reason.reconnect_after.is_some());
+    }
+
+    #[test]
+    fn test_connection_limit() {
+        let mut config = Config::default();
+        config.limits.max_connections = 1;
+        let state = AppState::new(config).unwrap();
+        assert!(readiness(&state).accepting_connections);
+
+        let _guard = state.stats.connect(Transport::WebSocket);
+        assert!(!readiness(&state).accepting_connections);
+        assert!(!readiness(&state).is_ready());
+    }
+}
@@ -0,0 +1,103 @@
+//! Per-IP connection limits for Pulse server.
+//!
+//! Connections are counted per source IP from the moment the upgrade request
+//! is accepted until the socket closes, so a single host cannot hold more
+//! than its share of the server.
+
+use dashmap::DashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+/// Counts live connections per IP address.
+#[derive(Debug)]
+pub struct IpConnections {
+    counts: DashMap<IpAddr, usize>,
+    limit: usize,
+}
+
+impl IpConnections {
+    /// Create a tracker allowing `limit` connections per IP (0 = unlimited).
+    #[must_use]
+    pub fn new(limit: usize) -> Arc<Self> {
+        Arc::new(Self {
+            counts: DashMap::new(),
+            limit,
+        })
+    }
+
+    /// Reserve a connection slot for an IP.
+    ///
+    /// Returns `None` if the IP is at its limit. The slot is released when
+    /// the guard is dropped.
+    pub fn acquire(self: &Arc<Self>, ip: IpAddr) -> Option<IpConnectionGuard> {
+        let mut count = self.counts.entry(ip).or_insert(0);
+        if self.limit > 0 && *count >= self.limit {
+            return None;
+        }
+        *count += 1;
+        Some(IpConnectionGuard {
+            tracker: Arc::clone(self),
+            ip,
+        })
+    }
+
+    /// Get the number of live connections from an IP.
+    #[must_use]
+    pub fn count(&self, ip: IpAddr) -> usize {
+        self.counts.get(&ip).map_or(0, |count| *count)
+    }
+
+    fn release(&self, ip: IpAddr) {
+        if let dashmap::mapref::entry::Entry::Occupied(mut entry) = self.counts.entry(ip) {
+            *entry.get_mut() -= 1;
+            if *entry.get() == 0 {
+                entry.remove();
+            }
+        }
+    }
+}
+
+/// A connection slot held for an IP; released on drop.
+#[derive(Debug)]
+pub struct IpConnectionGuard {
+    tracker: Arc<IpConnections>,
+    ip: IpAddr,
+}
+
+impl Drop for IpConnectionGuard {
+    fn drop(&mut self) {
+        self.tracker.release(self.ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ip_connection_limit() {
+        let tracker = IpConnections::new(2);
+        let ip: IpAddr = "198.51.100.1".parse().unwrap();
+        let other: IpAddr = "198.51.100.2".parse().unwrap();
+
+        let first = tracker.acquire(ip).unwrap();
+        let _second = tracker.acquire(ip).unwrap();
+        assert!(tracker.acquire(ip).is_none());
+        assert!(tracker.acquire(other).is_some());
+
+        drop(first);
+        assert_eq!(tracker.count(ip), 1);
+        assert!(tracker.acquire(ip).is_some());
+    }
+
+    #[test]
+    fn test_ip_connection_unlimited() {
+        let tracker = IpConnections::new(0);
+        let ip: IpAddr = "::1".parse().unwrap();
+
+        let guards: Vec<_> = (0..100).map(|_| tracker.acquire(ip).unwrap()).collect();
+        assert_eq!(tracker.count(ip), 100);
+        drop(guards);
+        assert_eq!(tracker.count(ip), 0);
+    }
+}
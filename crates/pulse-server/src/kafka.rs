@@ -0,0 +1,80 @@
+//! Kafka producer for usage records.
+//!
+//! Built with the `kafka` feature, `usage_reports.kafka` produces each usage
+//! record to a topic as a JSON message keyed by its tenant, so a tenant's
+//! records stay in order on one partition. librdkafka batches, retries and
+//! reconnects on its own; a record not acknowledged within
+//! `usage_reports.timeout_ms` is logged and dropped.
+
+use crate::config::UsageKafkaConfig;
+use anyhow::{Context, Result};
+use rdkafka::config::ClientConfig;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::util::Timeout;
+use std::time::Duration;
+
+/// Produces messages to the configured topic.
+pub struct Producer {
+    producer: FutureProducer,
+    topic: String,
+}
+
+impl Producer {
+    /// Create a producer. Brokers are connected to in the background.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if librdkafka rejects a setting.
+    pub fn new(config: &UsageKafkaConfig, timeout: Duration) -> Result<Self> {
+        let mut client = ClientConfig::new();
+        client
+            .set("bootstrap.servers", &config.brokers)
+            .set("message.timeout.ms", timeout.as_millis().to_string());
+        for (key, value) in &config.properties {
+            client.set(key, value);
+        }
+        let producer = client
+            .create()
+            .context("Invalid usage_reports.kafka settings")?;
+        Ok(Self {
+            producer,
+            topic: config.topic.clone(),
+        })
+    }
+
+    /// Produce a message and wait until the brokers acknowledge it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message could not be delivered in time.
+    pub async fn send(&self, key: &str, payload: &[u8]) -> Result<()> {
+        let record = FutureRecord::to(&self.topic).key(key).payload(payload);
+        self.producer
+            .send(record, Timeout::Never)
+            .await
+            .map_err(|(e, _)| e)
+            .with_context(|| format!("Failed to produce to Kafka topic {}", self.topic))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_producer_settings() {
+        let mut config = UsageKafkaConfig {
+            brokers: "127.0.0.1:9092".to_string(),
+            topic: "usage".to_string(),
+            properties: BTreeMap::from([("client.id".to_string(), "pulse".to_string())]),
+        };
+        assert!(Producer::new(&config, Duration::from_secs(5)).is_ok());
+
+        config
+            .properties
+            .insert("no.such.setting".to_string(), "1".to_string());
+        assert!(Producer::new(&config, Duration::from_secs(5)).is_err());
+    }
+}
@@ -0,0 +1,88 @@
+//! # Pulse Server
+//!
+//! The Pulse server as a library, for running it inside an existing axum
+//! application rather than as the standalone `pulse` binary.
+//!
+//! [`routes`] returns the WebSocket endpoint, health probes, `/stats` and,
+//! when configured, Socket.IO and the admin API, so they can be nested under
+//! a prefix and share the application's middleware, port and TLS. [`Routes`]
+//! also takes tower layers for just the upgrade routes or just the HTTP
+//! endpoints:
+//!
+//! ```no_run
+//! use std::net::SocketAddr;
+//! use std::sync::Arc;
+//! use pulse::{AppState, Config};
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let state = Arc::new(AppState::new(Config::default())?);
+//! pulse::spawn_tasks(&state).await?;
+//!
+//! let app = axum::Router::new()
+//!     .route("/", axum::routing::get(|| async { "Hello" }))
+//!     .nest("/realtime", pulse::routes(state.clone()));
+//!
+//! // Clients connect to ws://localhost:3000/realtime/ws
+//! let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await?;
+//! axum::serve(
+//!     listener,
+//!     app.into_make_service_with_connect_info::<SocketAddr>(),
+//! )
+//! .with_graceful_shutdown(async move {
+//!     let _ = tokio::signal::ctrl_c().await;
+//!     pulse::shutdown(&state).await;
+//! })
+//! .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The router needs the client address, so the application must be served
+//! with `into_make_service_with_connect_info::<SocketAddr>()`.
+
+#[cfg(feature = "acme")]
+mod acme;
+mod admin;
+mod affinity;
+mod audit;
+mod bans;
+mod capture;
+#[cfg(feature = "chaos")]
+mod chaos;
+pub mod config;
+mod diagnostics;
+mod federation;
+mod forwarded;
+mod handlers;
+mod health;
+mod ip_limits;
+#[cfg(feature = "kafka")]
+mod kafka;
+mod listener;
+mod metadata;
+mod metrics;
+#[cfg(feature = "postgres")]
+mod postgres;
+mod rate_limit;
+mod routes;
+mod schemas;
+mod secrets;
+mod shedding;
+mod signing;
+mod sinks;
+mod socketio;
+mod standby;
+mod stats;
+#[cfg(any(test, feature = "test-support"))]
+pub mod testing;
+mod tls;
+mod trace;
+mod usage;
+mod usage_reports;
+mod webhooks;
+mod writer;
+
+pub use config::Config;
+pub use handlers::{drain, run_server, shutdown, spawn_tasks, AppState};
+pub use metrics::init_metrics;
+pub use routes::{routes, Routes};
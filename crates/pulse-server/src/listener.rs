@@ -0,0 +1,417 @@
+//! The main listener's TCP sockets.
+//!
+//! Listeners are bound with the `transport.tcp` backlog and buffer sizes;
+//! every accepted connection then gets `TCP_NODELAY`, keepalive and buffer
+//! sizes applied before TLS or HTTP sees it. With `transport.tcp.acceptors`
+//! above 1, several listeners share the port through `SO_REUSEPORT`, each
+//! accepting on its own task so a burst of reconnects is spread over cores.
+//!
+//! With `limits.accept_rate` set, accepted TCP connections are handed to TLS
+//! and HTTP at that rate, shared by all TCP listeners, so a crowd of clients
+//! reconnecting after another node restarts is let in gradually instead of
+//! all handshaking at once. Connections over the rate wait in a queue of up
+//! to `limits.accept_queue`; past that they are closed straight away and
+//! retry later.
+//!
+//! `bind` may list several listeners, each with its own TCP settings, and
+//! Unix domain sockets for a local reverse proxy. Unix sockets are served
+//! directly with hyper, since `axum_server` only accepts TCP.
+
+use crate::config::LimitsConfig;
+use crate::metrics;
+use axum::extract::connect_info::IntoMakeServiceWithConnectInfo;
+use axum::extract::ConnectInfo;
+use axum::middleware::AddExtension;
+use axum::Router;
+use axum_server::accept::Accept;
+use axum_server::service::SendService;
+use axum_server::Handle;
+use futures_util::future::BoxFuture;
+use hyper::body::Incoming;
+use hyper::Request;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tenvis_pulse_transport::SocketOptions;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tracing::warn;
+#[cfg(unix)]
+use {
+    axum::Extension,
+    hyper_util::rt::{TokioExecutor, TokioIo},
+    hyper_util::server::conn::auto,
+    hyper_util::server::graceful::GracefulShutdown,
+    std::net::{Ipv4Addr, SocketAddrV4},
+    std::path::Path,
+    tokio::net::UnixListener,
+    tokio::sync::watch,
+    tower_service::Service,
+    tracing::debug,
+};
+
+/// The address Unix socket clients appear to connect from; they have no IP.
+#[cfg(unix)]
+pub const UNIX_PEER: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+
+/// The app as served on each listener.
+pub type App = IntoMakeServiceWithConnectInfo<Router, SocketAddr>;
+
+/// Serve `app` on every listener, each accept loop on its own task, until
+/// `handle` shuts them down.
+///
+/// Connections wait for `pacer`, if any, before `acceptor` sees them.
+///
+/// # Errors
+///
+/// Returns the first error any listener fails with.
+pub async fn serve<A>(
+    listeners: Vec<std::net::TcpListener>,
+    acceptor: A,
+    pacer: Option<Arc<AcceptPacer>>,
+    handle: Handle,
+    app: App,
+) -> std::io::Result<()>
+where
+    A: Accept<TcpStream, AddExtension<Router, ConnectInfo<SocketAddr>>>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    A::Stream: AsyncRead + AsyncWrite + Unpin + Send,
+    A::Service: SendService<Request<Incoming>> + Send,
+    A::Future: Send + 'static,
+{
+    let acceptor = PacedAcceptor {
+        pacer,
+        inner: acceptor,
+    };
+    let servers: Vec<_> = listeners
+        .into_iter()
+        .map(|listener| {
+            let server = axum_server::from_tcp(listener)
+                .handle(handle.clone())
+                .acceptor(acceptor.clone());
+            tokio::spawn(server.serve(app.clone()))
+        })
+        .collect();
+    for server in servers {
+        server.await??;
+    }
+    Ok(())
+}
+
+/// Bind a Unix socket at `path`, replacing a socket file left behind by an
+/// earlier run.
+///
+/// # Errors
+///
+/// Returns an error if the stale socket cannot be removed or the path cannot
+/// be bound.
+#[cfg(unix)]
+pub fn bind_unix(path: &Path) -> std::io::Result<UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    UnixListener::bind(path)
+}
+
+/// Serve `router` on a Unix socket until `stopped` is set, then wait for
+/// open requests to finish and remove the socket file.
+///
+/// Clients are seen as connecting from [`UNIX_PEER`].
+///
+/// # Errors
+///
+/// Returns an error if the listener's address cannot be read.
+#[cfg(unix)]
+pub async fn serve_unix(
+    listener: UnixListener,
+    router: Router,
+    mut stopped: watch::Receiver<bool>,
+) -> std::io::Result<()> {
+    let path = listener.local_addr()?.as_pathname().map(Path::to_path_buf);
+    let router = router.layer(Extension(ConnectInfo(UNIX_PEER)));
+    let builder = auto::Builder::new(TokioExecutor::new());
+    let graceful = GracefulShutdown::new();
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    // Usually out of file descriptors; give connections time to close
+                    warn!("Failed to accept on Unix socket: {}", e);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            },
+            () = async {
+                let _ = stopped.wait_for(|stopped| *stopped).await;
+            } => break,
+        };
+        let router = router.clone();
+        let service = hyper::service::service_fn(move |request: Request<Incoming>| {
+            router.clone().call(request)
+        });
+        let connection = builder
+            .serve_connection_with_upgrades(TokioIo::new(stream), service)
+            .into_owned();
+        let connection = graceful.watch(connection);
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                debug!("Unix socket connection failed: {}", e);
+            }
+        });
+    }
+    graceful.shutdown().await;
+    if let Some(path) = path {
+        let _ = std::fs::remove_file(path);
+    }
+    Ok(())
+}
+
+/// An acceptor that tunes each accepted stream, then hands it to `inner`.
+#[derive(Clone)]
+pub struct TunedAcceptor<A> {
+    options: Arc<SocketOptions>,
+    inner: A,
+}
+
+impl<A> TunedAcceptor<A> {
+    /// Tune streams with `options` before passing them to `inner`.
+    pub fn new(options: SocketOptions, inner: A) -> Self {
+        Self {
+            options: Arc::new(options),
+            inner,
+        }
+    }
+}
+
+impl<A, S> Accept<TcpStream, S> for TunedAcceptor<A>
+where
+    A: Accept<TcpStream, S>,
+{
+    type Stream = A::Stream;
+    type Service = A::Service;
+    type Future = A::Future;
+
+    fn accept(&self, stream: TcpStream, service: S) -> Self::Future {
+        // A connection that can't be tuned still works; don't drop it
+        if let Err(e) = self.options.apply(&stream) {
+            warn!("Failed to tune accepted socket: {}", e);
+        }
+        self.inner.accept(stream, service)
+    }
+}
+
+/// Lets accepted connections through at a steady rate, with bursts, queueing
+/// those over it.
+///
+/// Each connection is given the next free slot, `1 / accept_rate` seconds
+/// after the previous one; up to `accept_burst` slots may be in the past, so
+/// a quiet listener admits a burst at once.
+#[derive(Debug)]
+pub struct AcceptPacer {
+    interval: Duration,
+    tolerance: Duration,
+    max_queued: usize,
+    next_slot: Mutex<Instant>,
+    queued: AtomicUsize,
+}
+
+impl AcceptPacer {
+    /// Create a pacer for the limits, or `None` if accepts are unlimited.
+    #[must_use]
+    pub fn new(limits: &LimitsConfig) -> Option<Self> {
+        if limits.accept_rate == 0 {
+            return None;
+        }
+        let interval = Duration::from_secs(1) / limits.accept_rate;
+        Some(Self {
+            interval,
+            tolerance: interval * limits.accept_burst.saturating_sub(1),
+            max_queued: limits.accept_queue,
+            next_slot: Mutex::new(Instant::now()),
+            queued: AtomicUsize::new(0),
+        })
+    }
+
+    /// Take the next slot, returning how long to wait for it along with a
+    /// place in the queue if that is not zero, or `None` if the connection
+    /// would have to wait and the queue is full.
+    fn reserve(&self, now: Instant) -> Option<(Duration, Option<Queued<'_>>)> {
+        let mut next_slot = self.next_slot.lock().unwrap_or_else(|e| e.into_inner());
+        let slot = (*next_slot).max(now);
+        let wait = slot
+            .checked_sub(self.tolerance)
+            .map_or(Duration::ZERO, |start| start.saturating_duration_since(now));
+        let queued = if wait.is_zero() {
+            None
+        } else {
+            Some(Queued::take(&self.queued, self.max_queued)?)
+        };
+        *next_slot = slot + self.interval;
+        Some((wait, queued))
+    }
+
+    /// Wait for a slot, returning `false` if the queue is full.
+    async fn wait(&self) -> bool {
+        let Some((wait, queued)) = self.reserve(Instant::now()) else {
+            metrics::record_accept_refused();
+            return false;
+        };
+        if queued.is_some() {
+            tokio::time::sleep(wait).await;
+        }
+        true
+    }
+}
+
+/// A connection's place in the accept queue, given back when dropped, even
+/// if the connection is abandoned while it waits.
+#[derive(Debug)]
+struct Queued<'a> {
+    queued: &'a AtomicUsize,
+}
+
+impl<'a> Queued<'a> {
+    /// Take a place if fewer than `max` are taken.
+    fn take(queued: &'a AtomicUsize, max: usize) -> Option<Self> {
+        let previous = queued
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .ok()?;
+        metrics::record_accept_queued(previous + 1);
+        Some(Self { queued })
+    }
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        let queued = self.queued.fetch_sub(1, Ordering::Relaxed) - 1;
+        metrics::record_accept_queued(queued);
+    }
+}
+
+/// An acceptor that waits for a slot from the pacer, then hands the stream to
+/// `inner`.
+#[derive(Clone)]
+struct PacedAcceptor<A> {
+    pacer: Option<Arc<AcceptPacer>>,
+    inner: A,
+}
+
+impl<A, S> Accept<TcpStream, S> for PacedAcceptor<A>
+where
+    A: Accept<TcpStream, S> + Clone + Send + Sync + 'static,
+    A::Future: Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = A::Stream;
+    type Service = A::Service;
+    type Future = BoxFuture<'static, std::io::Result<(A::Stream, A::Service)>>;
+
+    fn accept(&self, stream: TcpStream, service: S) -> Self::Future {
+        let pacer = self.pacer.clone();
+        let inner = self.inner.clone();
+        Box::pin(async move {
+            // Each accepted connection runs on its own task, so waiting here
+            // holds up only this one
+            if let Some(pacer) = pacer {
+                if !pacer.wait().await {
+                    return Err(std::io::Error::other("accept queue full"));
+                }
+            }
+            inner.accept(stream, service).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accept_pacer() {
+        assert!(AcceptPacer::new(&LimitsConfig::default()).is_none());
+
+        let pacer = AcceptPacer::new(&LimitsConfig {
+            accept_rate: 10,
+            accept_burst: 3,
+            accept_queue: 2,
+            ..LimitsConfig::default()
+        })
+        .unwrap();
+        let now = Instant::now();
+        for _ in 0..3 {
+            let (wait, queued) = pacer.reserve(now).unwrap();
+            assert_eq!(wait, Duration::ZERO);
+            assert!(queued.is_none());
+        }
+        let first = pacer.reserve(now).unwrap();
+        assert_eq!(first.0, Duration::from_millis(100));
+        let second = pacer.reserve(now).unwrap();
+        assert_eq!(second.0, Duration::from_millis(200));
+        assert_eq!(pacer.queued.load(Ordering::Relaxed), 2);
+
+        // A full queue refuses connections that would have to wait
+        assert!(pacer.reserve(now).is_none());
+        drop((first, second));
+        assert_eq!(pacer.queued.load(Ordering::Relaxed), 0);
+
+        // The rate refills the burst
+        let later = now + Duration::from_secs(1);
+        for _ in 0..3 {
+            assert_eq!(pacer.reserve(later).unwrap().0, Duration::ZERO);
+        }
+        assert!(pacer.reserve(later).unwrap().0 > Duration::ZERO);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_accept_pacer_concurrent() {
+        let pacer = Arc::new(
+            AcceptPacer::new(&LimitsConfig {
+                accept_rate: 10,
+                accept_burst: 1,
+                accept_queue: 3,
+                ..LimitsConfig::default()
+            })
+            .unwrap(),
+        );
+
+        // However the accepts race, no more than the queue's worth wait
+        let barrier = Arc::new(tokio::sync::Barrier::new(10));
+        let waiters: Vec<_> = (0..10)
+            .map(|_| {
+                let (pacer, barrier) = (pacer.clone(), barrier.clone());
+                tokio::spawn(async move {
+                    barrier.wait().await;
+                    pacer.wait().await
+                })
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(pacer.queued.load(Ordering::Relaxed), 3);
+        let mut admitted = 0;
+        for waiter in waiters {
+            admitted += usize::from(waiter.await.unwrap());
+        }
+        assert_eq!(admitted, 4);
+        assert_eq!(pacer.queued.load(Ordering::Relaxed), 0);
+
+        // A connection abandoned while queued gives its place back
+        let abandoned = tokio::spawn({
+            let pacer = pacer.clone();
+            async move { pacer.wait().await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(pacer.queued.load(Ordering::Relaxed), 1);
+        abandoned.abort();
+        assert!(abandoned.await.unwrap_err().is_cancelled());
+        assert_eq!(pacer.queued.load(Ordering::Relaxed), 0);
+    }
+}
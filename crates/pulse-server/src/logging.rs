@@ -0,0 +1,37 @@
+//! Log output, formatted and filtered by `[logging]`.
+
+use anyhow::{Context, Result};
+use pulse::config::{LogFormat, LoggingConfig};
+use tracing_subscriber::fmt;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+
+/// Install the global subscriber `config` describes.
+///
+/// `RUST_LOG`, when set, takes the place of the configured levels.
+///
+/// # Errors
+///
+/// Returns an error if the levels cannot be parsed or a subscriber is
+/// already installed.
+pub fn init(config: &LoggingConfig) -> Result<()> {
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        Err(_) => EnvFilter::builder()
+            .parse(config.directives())
+            .context("Invalid logging levels")?,
+    };
+
+    let layer: Box<dyn Layer<Registry> + Send + Sync> = match config.format {
+        LogFormat::Full => fmt::layer().with_ansi(config.ansi).boxed(),
+        LogFormat::Compact => fmt::layer().compact().with_ansi(config.ansi).boxed(),
+        LogFormat::Pretty => fmt::layer().pretty().with_ansi(config.ansi).boxed(),
+        LogFormat::Json => fmt::layer().json().flatten_event(true).boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(layer)
+        .with(filter)
+        .try_init()
+        .context("Failed to install the log subscriber")
+}
@@ -12,37 +12,53 @@
 //! pulse --config /path/to/pulse.toml
 //!
 //! # Run with environment variables
-//! PULSE_PORT=8080 PULSE_HOST=0.0.0.0 pulse
+//! PULSE_PORT=8080 PULSE_HOST=0.0.0.0 PULSE_LIMITS__MAX_CONNECTIONS=50000 pulse
+//!
+//! # Override any setting on the command line
+//! pulse --port 9000 --set heartbeat.interval_ms=15000
 //! ```
 
-mod config;
-mod handlers;
-mod metrics;
+mod cli;
+mod logging;
+mod runtime;
 
 use anyhow::Result;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Initialize tracing
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "pulse=debug,tower_http=debug".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+use pulse::Config;
 
-    // Load configuration
-    let config = config::Config::load()?;
+fn main() -> Result<()> {
+    let args = match cli::parse(std::env::args().skip(1))? {
+        cli::Command::Run(args) => args,
+        cli::Command::Help => {
+            println!("{}", cli::USAGE);
+            return Ok(());
+        }
+        cli::Command::Version => {
+            println!("pulse {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+    };
 
-    tracing::info!("Starting Pulse server on {}:{}", config.host, config.port);
+    // Load configuration, then log as it asks; invalid settings are
+    // reported on stderr before logging is set up
+    let config = Config::load(args.config.as_deref(), &args.overrides)?;
+    let warnings = config.validate()?;
+    logging::init(&config.logging)?;
+    for warning in warnings {
+        tracing::warn!("Config: {warning}");
+    }
 
-    // Initialize metrics
-    metrics::init_metrics();
+    // Size the runtime from the config before anything is spawned on it
+    let runtime = runtime::build(&config.runtime)?;
+    tracing::info!(
+        workers = runtime.metrics().num_workers(),
+        "Starting Pulse server"
+    );
 
-    // Start the server
-    handlers::run_server(config).await?;
+    runtime.block_on(async {
+        // Initialize metrics
+        pulse::init_metrics();
 
-    Ok(())
+        // Start the server
+        pulse::run_server(config).await
+    })
 }
@@ -0,0 +1,89 @@
+//! Request metadata captured at the WebSocket upgrade.
+//!
+//! The upgrade request is the only HTTP a client sends. Its `User-Agent`,
+//! `Origin`, requested subprotocols and any headers listed in
+//! `metadata.headers` are kept on the connection's handle, where channel
+//! authorizers, presence defaults and `GET /admin/connections/:id` read them.
+
+use crate::config::MetadataConfig;
+use axum::http::{header, HeaderMap, HeaderValue};
+use tenvis_pulse_core::ConnectionMetadata;
+
+/// Longest header value kept; longer values are cut short.
+const MAX_VALUE_LEN: usize = 512;
+
+/// Record what the client sent with its upgrade request.
+#[must_use]
+pub fn capture(headers: &HeaderMap, config: &MetadataConfig) -> ConnectionMetadata {
+    let subprotocols = headers
+        .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|protocol| !protocol.is_empty())
+        .map(|protocol| truncate(protocol).to_string())
+        .collect();
+    let recorded = config
+        .headers
+        .iter()
+        .filter_map(|name| {
+            let name = name.to_ascii_lowercase();
+            let value = text(headers.get(name.as_str()))?;
+            Some((name, value))
+        })
+        .collect();
+    ConnectionMetadata {
+        user_agent: text(headers.get(header::USER_AGENT)),
+        origin: text(headers.get(header::ORIGIN)),
+        subprotocols,
+        headers: recorded,
+    }
+}
+
+/// A header value as text, or `None` if it is missing or not visible ASCII.
+fn text(value: Option<&HeaderValue>) -> Option<String> {
+    value
+        .and_then(|value| value.to_str().ok())
+        .map(|value| truncate(value).to_string())
+}
+
+fn truncate(value: &str) -> &str {
+    // Visible ASCII, so any byte index is a char boundary
+    &value[..value.len().min(MAX_VALUE_LEN)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::USER_AGENT, "pulse-js/1.2".parse().unwrap());
+        headers.insert(header::ORIGIN, "https://app.example.com".parse().unwrap());
+        headers.append(
+            header::SEC_WEBSOCKET_PROTOCOL,
+            "pulse.v1, json".parse().unwrap(),
+        );
+        headers.append(header::SEC_WEBSOCKET_PROTOCOL, "msgpack".parse().unwrap());
+        headers.insert("x-client-version", "a".repeat(600).parse().unwrap());
+        headers.insert("x-other", "ignored".parse().unwrap());
+
+        let config = MetadataConfig {
+            headers: vec!["X-Client-Version".to_string(), "x-missing".to_string()],
+            ..MetadataConfig::default()
+        };
+        let metadata = capture(&headers, &config);
+        assert_eq!(metadata.user_agent.as_deref(), Some("pulse-js/1.2"));
+        assert_eq!(metadata.origin.as_deref(), Some("https://app.example.com"));
+        assert_eq!(metadata.subprotocols, vec!["pulse.v1", "json", "msgpack"]);
+        assert_eq!(metadata.headers.len(), 1);
+        assert_eq!(metadata.headers["x-client-version"].len(), MAX_VALUE_LEN);
+
+        assert_eq!(
+            capture(&HeaderMap::new(), &config),
+            ConnectionMetadata::default()
+        );
+    }
+}
@@ -1,11 +1,18 @@
 //! Metrics collection and export for Pulse.
 //!
 //! Uses the `metrics` crate for instrumentation and exports
-//! to Prometheus format.
+//! to Prometheus format. Connection, message, latency and error metrics are
+//! labelled with the client's `transport`, so transports can be compared.
 
+use crate::handlers::AppState;
+use crate::stats::Transport;
 use metrics::{counter, gauge, histogram};
-use metrics_exporter_prometheus::PrometheusBuilder;
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
+use pulse_protocol::pool;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tenvis_pulse_core::{DropPolicy, MemoryUsage, Router};
 use tracing::info;
 
 /// Metric names.
@@ -14,10 +21,39 @@ pub mod names {
     pub const CONNECTIONS_ACTIVE: &str = "pulse_connections_active";
     pub const MESSAGES_TOTAL: &str = "pulse_messages_total";
     pub const MESSAGES_BYTES: &str = "pulse_messages_bytes";
+    pub const PAYLOAD_BYTES: &str = "pulse_payload_bytes";
     pub const CHANNELS_ACTIVE: &str = "pulse_channels_active";
     pub const SUBSCRIPTIONS_TOTAL: &str = "pulse_subscriptions_total";
+    pub const CHANNELS_REJECTED_TOTAL: &str = "pulse_channels_rejected_total";
     pub const LATENCY_SECONDS: &str = "pulse_latency_seconds";
+    pub const PUBLISH_RECIPIENTS: &str = "pulse_publish_recipients";
+    pub const DELIVERY_LATENCY_SECONDS: &str = "pulse_delivery_latency_seconds";
     pub const ERRORS_TOTAL: &str = "pulse_errors_total";
+    pub const WRITE_FLUSHES_TOTAL: &str = "pulse_write_flushes_total";
+    pub const WRITE_FLUSH_BYTES: &str = "pulse_write_flush_bytes";
+    pub const BUFFER_POOL_HITS: &str = "pulse_buffer_pool_hits_total";
+    pub const BUFFER_POOL_MISSES: &str = "pulse_buffer_pool_misses_total";
+    pub const BUFFER_POOL_DISCARDED: &str = "pulse_buffer_pool_discarded_total";
+    pub const BUFFER_POOL_SIZE: &str = "pulse_buffer_pool_size";
+    pub const QUEUE_LAG_EVENTS_TOTAL: &str = "pulse_queue_lag_events_total";
+    pub const MESSAGES_DROPPED_TOTAL: &str = "pulse_messages_dropped_total";
+    pub const QUEUE_MAX_LAG: &str = "pulse_queue_max_lag";
+    pub const CONNECTION_MAX_LAG: &str = "pulse_connection_max_lag";
+    pub const ROUTER_MEMORY_BYTES: &str = "pulse_router_memory_bytes";
+    pub const HISTORY_EVICTED_TOTAL: &str = "pulse_history_evicted_total";
+    pub const DURABLE_SUBSCRIPTIONS: &str = "pulse_durable_subscriptions";
+    pub const DURABLE_EXPIRED_TOTAL: &str = "pulse_durable_expired_total";
+    pub const SINK_BATCHES_TOTAL: &str = "pulse_sink_batches_total";
+    pub const SINK_MESSAGES_TOTAL: &str = "pulse_sink_messages_total";
+    pub const WEBHOOK_EVENTS_TOTAL: &str = "pulse_webhook_events_total";
+    pub const MODERATION_TOTAL: &str = "pulse_moderation_total";
+    pub const SHEDDING: &str = "pulse_shedding";
+    pub const ACCEPT_QUEUED: &str = "pulse_accept_queued";
+    pub const ACCEPT_REFUSED_TOTAL: &str = "pulse_accept_refused_total";
+    pub const AFFINITY_TOTAL: &str = "pulse_affinity_total";
+    pub const CAPTURE_MESSAGES_TOTAL: &str = "pulse_capture_messages_total";
+    pub const CAPTURE_BYTES: &str = "pulse_capture_bytes";
+    pub const TRACE_EVENTS_DROPPED_TOTAL: &str = "pulse_trace_events_dropped_total";
 }
 
 /// Initialize the metrics system.
@@ -33,22 +69,155 @@ pub fn init_metrics() {
     );
     metrics::describe_counter!(names::MESSAGES_TOTAL, "Total number of messages processed");
     metrics::describe_counter!(names::MESSAGES_BYTES, "Total bytes of messages processed");
+    metrics::describe_histogram!(
+        names::PAYLOAD_BYTES,
+        "Payload size of each published and delivered message in bytes"
+    );
     metrics::describe_gauge!(names::CHANNELS_ACTIVE, "Current number of active channels");
     metrics::describe_counter!(
         names::SUBSCRIPTIONS_TOTAL,
         "Total number of channel subscriptions"
     );
+    metrics::describe_counter!(
+        names::CHANNELS_REJECTED_TOTAL,
+        "Total number of channel creations rejected by the channel limit"
+    );
     metrics::describe_histogram!(
         names::LATENCY_SECONDS,
-        "Message processing latency in seconds"
+        "Time spent handling inbound frames in seconds"
+    );
+    metrics::describe_histogram!(
+        names::PUBLISH_RECIPIENTS,
+        "Connections each published message was queued for"
+    );
+    metrics::describe_histogram!(
+        names::DELIVERY_LATENCY_SECONDS,
+        "Time from a message being published to its write to a client socket"
     );
     metrics::describe_counter!(names::ERRORS_TOTAL, "Total number of errors");
+    metrics::describe_counter!(
+        names::WRITE_FLUSHES_TOTAL,
+        "Total number of WebSocket messages written"
+    );
+    metrics::describe_histogram!(
+        names::WRITE_FLUSH_BYTES,
+        "Bytes per WebSocket message written"
+    );
+    metrics::describe_counter!(
+        names::BUFFER_POOL_HITS,
+        "Buffers served from the buffer pool"
+    );
+    metrics::describe_counter!(
+        names::BUFFER_POOL_MISSES,
+        "Buffers allocated because the buffer pool was empty"
+    );
+    metrics::describe_counter!(
+        names::BUFFER_POOL_DISCARDED,
+        "Buffers freed instead of returned to the buffer pool"
+    );
+    metrics::describe_gauge!(names::BUFFER_POOL_SIZE, "Buffers currently pooled");
+    metrics::describe_counter!(
+        names::QUEUE_LAG_EVENTS_TOTAL,
+        "Times a connection's outbound queue filled up and started dropping messages"
+    );
+    metrics::describe_counter!(
+        names::MESSAGES_DROPPED_TOTAL,
+        "Messages lost to full outbound queues, by drop policy"
+    );
+    metrics::describe_gauge!(
+        names::QUEUE_MAX_LAG,
+        "Deepest outbound queue observed on any live connection"
+    );
+    metrics::describe_histogram!(
+        names::CONNECTION_MAX_LAG,
+        "Deepest outbound queue observed on each closed connection"
+    );
+    metrics::describe_gauge!(
+        names::ROUTER_MEMORY_BYTES,
+        "Estimated bytes held by channel history, presence and connection queues"
+    );
+    metrics::describe_counter!(
+        names::HISTORY_EVICTED_TOTAL,
+        "Retained messages evicted to keep router state under memory.max_bytes"
+    );
+    metrics::describe_gauge!(
+        names::DURABLE_SUBSCRIPTIONS,
+        "Durable subscriptions, attached or buffering"
+    );
+    metrics::describe_counter!(
+        names::DURABLE_EXPIRED_TOTAL,
+        "Durable subscriptions deleted after their consumer stayed away past durable.ttl_ms"
+    );
+    metrics::describe_counter!(
+        names::SINK_BATCHES_TOTAL,
+        "Batches pushed to HTTP sinks, by outcome"
+    );
+    metrics::describe_counter!(
+        names::SINK_MESSAGES_TOTAL,
+        "Messages pushed to HTTP sinks, by outcome"
+    );
+    metrics::describe_counter!(
+        names::WEBHOOK_EVENTS_TOTAL,
+        "Occupancy webhook events sent, by outcome"
+    );
+    metrics::describe_counter!(names::MODERATION_TOTAL, "Moderated publishes, by outcome");
+    metrics::describe_gauge!(
+        names::SHEDDING,
+        "1 while load is shed past a [shedding] high-water mark, else 0"
+    );
+    metrics::describe_gauge!(
+        names::ACCEPT_QUEUED,
+        "Accepted connections waiting for their turn under limits.accept_rate"
+    );
+    metrics::describe_counter!(
+        names::ACCEPT_REFUSED_TOTAL,
+        "Accepted connections closed because the accept queue was full"
+    );
+    metrics::describe_counter!(
+        names::AFFINITY_TOTAL,
+        "Clients presenting an affinity token, by whether they reached its server"
+    );
+    metrics::describe_counter!(
+        names::CAPTURE_MESSAGES_TOTAL,
+        "Messages recorded to the capture file"
+    );
+    metrics::describe_gauge!(names::CAPTURE_BYTES, "Size of the capture file");
+    metrics::describe_counter!(
+        names::TRACE_EVENTS_DROPPED_TOTAL,
+        "Message trace events dropped because the trace queue was full"
+    );
 
     info!("Metrics initialized");
 }
 
+/// Bucket bounds for [`names::PUBLISH_RECIPIENTS`].
+const RECIPIENT_BUCKETS: &[f64] = &[
+    0.0, 1.0, 2.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0, 10000.0,
+];
+
+/// Bucket bounds for [`names::DELIVERY_LATENCY_SECONDS`].
+const DELIVERY_LATENCY_BUCKETS: &[f64] = &[
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// Bucket bounds for [`names::PAYLOAD_BYTES`].
+const PAYLOAD_BUCKETS: &[f64] = &[
+    64.0,
+    256.0,
+    1024.0,
+    4096.0,
+    16384.0,
+    65536.0,
+    262_144.0,
+    1_048_576.0,
+];
+
 /// Start the Prometheus metrics server.
 ///
+/// Fan-out, delivery latency and payload size are exported as histograms
+/// with buckets, so tails can be aggregated across servers; other histograms
+/// are exported as summaries.
+///
 /// # Errors
 ///
 /// Returns an error if the server cannot be started.
@@ -57,6 +226,18 @@ pub fn start_metrics_server(port: u16) -> Result<(), Box<dyn std::error::Error>>
 
     PrometheusBuilder::new()
         .with_http_listener(addr)
+        .set_buckets_for_metric(
+            Matcher::Full(names::PUBLISH_RECIPIENTS.to_string()),
+            RECIPIENT_BUCKETS,
+        )?
+        .set_buckets_for_metric(
+            Matcher::Full(names::DELIVERY_LATENCY_SECONDS.to_string()),
+            DELIVERY_LATENCY_BUCKETS,
+        )?
+        .set_buckets_for_metric(
+            Matcher::Full(names::PAYLOAD_BYTES.to_string()),
+            PAYLOAD_BUCKETS,
+        )?
         .install()?;
 
     info!("Metrics server listening on {}", addr);
@@ -64,25 +245,48 @@ pub fn start_metrics_server(port: u16) -> Result<(), Box<dyn std::error::Error>>
 }
 
 /// Record a new connection.
-pub fn record_connection() {
-    counter!(names::CONNECTIONS_TOTAL).increment(1);
-    gauge!(names::CONNECTIONS_ACTIVE).increment(1.0);
+pub fn record_connection(transport: Transport) {
+    counter!(names::CONNECTIONS_TOTAL, "transport" => transport.as_str()).increment(1);
+    gauge!(names::CONNECTIONS_ACTIVE, "transport" => transport.as_str()).increment(1.0);
 }
 
 /// Record a disconnection.
-pub fn record_disconnection() {
-    gauge!(names::CONNECTIONS_ACTIVE).decrement(1.0);
+pub fn record_disconnection(transport: Transport) {
+    gauge!(names::CONNECTIONS_ACTIVE, "transport" => transport.as_str()).decrement(1.0);
 }
 
 /// Record a message.
-pub fn record_message(bytes: usize, direction: &str) {
-    counter!(names::MESSAGES_TOTAL, "direction" => direction.to_string()).increment(1);
-    counter!(names::MESSAGES_BYTES, "direction" => direction.to_string()).increment(bytes as u64);
+pub fn record_message(bytes: usize, direction: &'static str, transport: Transport) {
+    let labels = [("direction", direction), ("transport", transport.as_str())];
+    counter!(names::MESSAGES_TOTAL, &labels).increment(1);
+    counter!(names::MESSAGES_BYTES, &labels).increment(bytes as u64);
+}
+
+/// Record the payload size of a message published (`inbound`) or delivered
+/// (`outbound`).
+pub fn record_payload_size(bytes: usize, direction: &'static str) {
+    histogram!(names::PAYLOAD_BYTES, "direction" => direction).record(bytes as f64);
+}
+
+/// Record the time spent handling inbound frames.
+pub fn record_latency(seconds: f64, transport: Transport) {
+    histogram!(names::LATENCY_SECONDS, "transport" => transport.as_str()).record(seconds);
+}
+
+/// Record how many connections a published message was queued for.
+pub fn record_fanout(recipients: usize) {
+    histogram!(names::PUBLISH_RECIPIENTS).record(recipients as f64);
+}
+
+/// Record the time from a message's publish to its socket write.
+pub fn record_delivery_latency(seconds: f64, transport: Transport) {
+    histogram!(names::DELIVERY_LATENCY_SECONDS, "transport" => transport.as_str()).record(seconds);
 }
 
-/// Record message latency.
-pub fn record_latency(seconds: f64) {
-    histogram!(names::LATENCY_SECONDS).record(seconds);
+/// Record a write of buffered outbound frames.
+pub fn record_flush(bytes: usize) {
+    counter!(names::WRITE_FLUSHES_TOTAL).increment(1);
+    histogram!(names::WRITE_FLUSH_BYTES).record(bytes as f64);
 }
 
 /// Record a subscription.
@@ -90,37 +294,161 @@ pub fn record_subscription() {
     counter!(names::SUBSCRIPTIONS_TOTAL).increment(1);
 }
 
+/// Record a channel creation rejected by the channel limit.
+pub fn record_channel_rejected() {
+    counter!(names::CHANNELS_REJECTED_TOTAL).increment(1);
+}
+
 /// Update active channel count.
 pub fn set_active_channels(count: usize) {
     gauge!(names::CHANNELS_ACTIVE).set(count as f64);
 }
 
 /// Record an error.
-pub fn record_error(error_type: &str) {
-    counter!(names::ERRORS_TOTAL, "type" => error_type.to_string()).increment(1);
+pub fn record_error(error_type: &'static str, transport: Transport) {
+    let labels = [("type", error_type), ("transport", transport.as_str())];
+    counter!(names::ERRORS_TOTAL, &labels).increment(1);
+}
+
+/// Record a batch pushed to an HTTP sink (`delivered` or `dead_lettered`).
+pub fn record_sink_batch(sink: &str, outcome: &'static str, messages: usize) {
+    let labels = [("sink", sink.to_string()), ("outcome", outcome.to_string())];
+    counter!(names::SINK_BATCHES_TOTAL, &labels).increment(1);
+    counter!(names::SINK_MESSAGES_TOTAL, &labels).increment(messages as u64);
+}
+
+/// Record occupancy webhook events (`delivered` or `failed`).
+pub fn record_webhook_events(outcome: &'static str, events: usize) {
+    counter!(names::WEBHOOK_EVENTS_TOTAL, "outcome" => outcome).increment(events as u64);
+}
+
+/// Record a moderation verdict (`allowed`, `rejected` or `timeout`).
+pub fn record_moderation(outcome: &'static str) {
+    counter!(names::MODERATION_TOTAL, "outcome" => outcome).increment(1);
+}
+
+/// Export buffer pool statistics.
+pub fn record_buffer_pool() {
+    let stats = pool::global().stats();
+    counter!(names::BUFFER_POOL_HITS).absolute(stats.hits);
+    counter!(names::BUFFER_POOL_MISSES).absolute(stats.misses);
+    counter!(names::BUFFER_POOL_DISCARDED).absolute(stats.discarded);
+    gauge!(names::BUFFER_POOL_SIZE).set(stats.pooled as f64);
+}
+
+/// Export connection queue lag and drop counters.
+pub fn record_queues(router: &Router) {
+    let stats = router.queue_stats();
+    counter!(names::QUEUE_LAG_EVENTS_TOTAL).absolute(stats.lag_events());
+    for policy in [
+        DropPolicy::DropOldest,
+        DropPolicy::DropNewest,
+        DropPolicy::Coalesce,
+        DropPolicy::Disconnect,
+    ] {
+        counter!(names::MESSAGES_DROPPED_TOTAL, "policy" => policy_name(policy))
+            .absolute(stats.dropped(policy));
+    }
+    let max_lag = router
+        .handles()
+        .iter()
+        .map(|h| h.max_queued())
+        .max()
+        .unwrap_or(0);
+    gauge!(names::QUEUE_MAX_LAG).set(max_lag as f64);
+}
+
+/// Record estimated router memory, and history evicted to stay under the cap.
+pub fn record_router_memory(usage: &MemoryUsage, evicted: usize) {
+    gauge!(names::ROUTER_MEMORY_BYTES, "kind" => "history").set(usage.history as f64);
+    gauge!(names::ROUTER_MEMORY_BYTES, "kind" => "presence").set(usage.presence as f64);
+    gauge!(names::ROUTER_MEMORY_BYTES, "kind" => "queues").set(usage.queues as f64);
+    counter!(names::HISTORY_EVICTED_TOTAL).increment(evicted as u64);
+}
+
+/// Record whether load is being shed.
+pub fn record_shedding(shedding: bool) {
+    gauge!(names::SHEDDING).set(if shedding { 1.0 } else { 0.0 });
+}
+
+/// Record the connections waiting under the accept rate limit.
+pub fn record_accept_queued(queued: usize) {
+    gauge!(names::ACCEPT_QUEUED).set(queued as f64);
+}
+
+/// Record a connection closed because the accept queue was full.
+pub fn record_accept_refused() {
+    counter!(names::ACCEPT_REFUSED_TOTAL).increment(1);
+}
+
+/// Record a client presenting an affinity token.
+pub fn record_affinity(outcome: &'static str) {
+    counter!(names::AFFINITY_TOTAL, "outcome" => outcome).increment(1);
+}
+
+/// Record a message written to the capture file.
+pub fn record_capture() {
+    counter!(names::CAPTURE_MESSAGES_TOTAL).increment(1);
+}
+
+/// Set the size of the capture file.
+pub fn set_capture_bytes(bytes: u64) {
+    gauge!(names::CAPTURE_BYTES).set(bytes as f64);
+}
+
+/// Record a message trace event dropped from the full trace queue.
+pub fn record_trace_dropped() {
+    counter!(names::TRACE_EVENTS_DROPPED_TOTAL).increment(1);
+}
+
+/// Record the number of durable subscriptions, and those just expired.
+pub fn record_durables(count: usize, expired: usize) {
+    gauge!(names::DURABLE_SUBSCRIPTIONS).set(count as f64);
+    counter!(names::DURABLE_EXPIRED_TOTAL).increment(expired as u64);
+}
+
+/// Record the deepest queue a connection had, when it closes.
+pub fn record_connection_lag(max_queued: usize, transport: Transport) {
+    histogram!(names::CONNECTION_MAX_LAG, "transport" => transport.as_str())
+        .record(max_queued as f64);
+}
+
+fn policy_name(policy: DropPolicy) -> &'static str {
+    match policy {
+        DropPolicy::DropOldest => "drop_oldest",
+        DropPolicy::DropNewest => "drop_newest",
+        DropPolicy::Coalesce => "coalesce",
+        DropPolicy::Disconnect => "disconnect",
+    }
+}
+
+/// Periodically export buffer pool and connection queue statistics.
+pub async fn report(state: Arc<AppState>, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        record_buffer_pool();
+        record_queues(&state.router);
+    }
 }
 
 /// Metrics guard that records disconnection on drop.
-pub struct ConnectionMetricsGuard;
+pub struct ConnectionMetricsGuard {
+    transport: Transport,
+}
 
 impl ConnectionMetricsGuard {
     /// Create a new metrics guard, recording a connection.
     #[must_use]
-    pub fn new() -> Self {
-        record_connection();
-        Self
-    }
-}
-
-impl Default for ConnectionMetricsGuard {
-    fn default() -> Self {
-        Self::new()
+    pub fn new(transport: Transport) -> Self {
+        record_connection(transport);
+        Self { transport }
     }
 }
 
 impl Drop for ConnectionMetricsGuard {
     fn drop(&mut self) {
-        record_disconnection();
+        record_disconnection(self.transport);
     }
 }
 
@@ -131,6 +459,6 @@ mod tests {
     #[test]
     fn test_metrics_guard() {
         // Just test that it doesn't panic
-        let _guard = ConnectionMetricsGuard::new();
+        let _guard = ConnectionMetricsGuard::new(Transport::SocketIo);
     }
 }
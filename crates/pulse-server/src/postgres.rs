@@ -0,0 +1,236 @@
+//! Postgres LISTEN/NOTIFY bridge.
+//!
+//! Notifications on configured Postgres channels are published into Pulse
+//! channels, so a trigger calling `pg_notify` reaches subscribers with no
+//! application code in between. Messages on configured Pulse channels can
+//! also be sent back with NOTIFY, as JSON:
+//!
+//! ```json
+//! {"channel": "commands:reindex", "event": "start", "data": {"id": 7}}
+//! ```
+//!
+//! Use different Postgres channels for each direction; a channel that is both
+//! listened to and notified would deliver messages twice.
+
+use crate::config::PostgresConfig;
+use crate::handlers::AppState;
+use crate::metrics;
+use crate::sinks::payload_value;
+use anyhow::{anyhow, Result};
+use futures_util::{stream, StreamExt};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use tenvis_pulse_core::{ConnectionHandle, Message};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::task::JoinHandle;
+use tokio_postgres::{AsyncMessage, NoTls, Notification};
+use tracing::{debug, info, warn};
+
+/// Connection ID the bridge uses in the router.
+const BRIDGE_ID: &str = "postgres";
+
+/// Notifications received but not yet published. Further ones are dropped
+/// until the bridge catches up, rather than queueing without limit.
+const NOTIFICATION_BACKLOG: usize = 1024;
+
+/// Start the bridge if a Postgres URL is configured.
+pub fn spawn(state: &Arc<AppState>) {
+    if state.config.postgres.url.is_some() {
+        state.health.register_backplane(BRIDGE_ID);
+        tokio::spawn(run(state.clone()));
+    }
+}
+
+/// Keep the bridge up, reconnecting after it drops.
+async fn run(state: Arc<AppState>) {
+    let reconnect = Duration::from_millis(state.config.postgres.reconnect_ms);
+    loop {
+        match bridge(&state).await {
+            Ok(()) => info!("Postgres bridge closed"),
+            Err(e) => warn!(error = %e, "Postgres bridge failed"),
+        }
+        state.health.set_backplane(BRIDGE_ID, false);
+        tokio::time::sleep(reconnect).await;
+    }
+}
+
+/// Connect to Postgres and relay until either side closes.
+async fn bridge(state: &AppState) -> Result<()> {
+    let config = &state.config.postgres;
+    let url = config
+        .url
+        .as_deref()
+        .ok_or_else(|| anyhow!("no Postgres URL"))?;
+    let (client, mut connection) = tokio_postgres::connect(url, NoTls).await?;
+
+    // The connection must be polled for notifications to arrive
+    let (tx, mut notifications) = mpsc::channel(NOTIFICATION_BACKLOG);
+    let driver = tokio::spawn(async move {
+        let mut messages = stream::poll_fn(move |cx| connection.poll_message(cx));
+        let mut dropped = 0u64;
+        while let Some(message) = messages.next().await {
+            match message {
+                Ok(AsyncMessage::Notification(notification)) => match tx.try_send(notification) {
+                    Ok(()) if dropped > 0 => {
+                        warn!(
+                            dropped,
+                            "Dropped Postgres notifications while the bridge was behind"
+                        );
+                        dropped = 0;
+                    }
+                    Ok(()) => {}
+                    Err(TrySendError::Full(_)) => dropped += 1,
+                    Err(TrySendError::Closed(_)) => break,
+                },
+                Ok(_) => {}
+                Err(e) => {
+                    warn!(error = %e, "Postgres connection error");
+                    break;
+                }
+            }
+        }
+    });
+    let bridge = Teardown {
+        state,
+        handle: state.router.connect(BRIDGE_ID),
+        driver,
+    };
+    let handle = &bridge.handle;
+
+    for rule in &config.listen {
+        let listen = format!("LISTEN {}", quote_ident(&rule.pg_channel));
+        client.batch_execute(&listen).await?;
+    }
+
+    for rule in &config.notify {
+        state
+            .router
+            .subscribe_pattern(handle, rule.channel.clone())?;
+    }
+    info!(
+        listen = config.listen.len(),
+        notify = config.notify.len(),
+        "Postgres bridge connected"
+    );
+    state.health.set_backplane(BRIDGE_ID, true);
+
+    loop {
+        tokio::select! {
+            notification = notifications.recv() => {
+                // The driver stops when the connection does
+                let Some(notification) = notification else {
+                    return Ok(());
+                };
+                publish(state, config, &notification);
+            }
+
+            msg = handle.recv() => {
+                let Some(msg) = msg else {
+                    return Ok(());
+                };
+                let payload = notify_payload(&msg);
+                for rule in config.notify.iter().filter(|r| r.channel.matches(&msg.channel)) {
+                    client
+                        .execute("SELECT pg_notify($1, $2)", &[&rule.pg_channel, &payload])
+                        .await?;
+                }
+            }
+        }
+    }
+}
+
+/// Leaves the router and stops the connection driver however the bridge
+/// ends, including on errors while it is still being set up.
+struct Teardown<'a> {
+    state: &'a AppState,
+    handle: Arc<ConnectionHandle>,
+    driver: JoinHandle<()>,
+}
+
+impl Drop for Teardown<'_> {
+    fn drop(&mut self) {
+        self.state.router.disconnect(&self.handle);
+        self.driver.abort();
+    }
+}
+
+/// Publish a notification to the Pulse channels listening on it.
+fn publish(state: &AppState, config: &PostgresConfig, notification: &Notification) {
+    for rule in config
+        .listen
+        .iter()
+        .filter(|r| r.pg_channel == notification.channel())
+    {
+        let message = Message::new(
+            rule.channel.as_str(),
+            notification.payload().as_bytes().to_vec(),
+        )
+        .with_source(BRIDGE_ID);
+        let recipients = state.router.publish(message);
+        metrics::record_fanout(recipients);
+        debug!(pg_channel = %notification.channel(), channel = %rule.channel, recipients, "Relayed notification");
+    }
+}
+
+/// Build the NOTIFY payload for a Pulse message.
+fn notify_payload(msg: &Message) -> String {
+    json!({
+        "channel": &*msg.channel,
+        "event": msg.event,
+        "data": payload_value(&msg.payload),
+    })
+    .to_string()
+}
+
+/// Quote a Postgres identifier.
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use serde_json::Value;
+
+    #[test]
+    fn test_quote_ident() {
+        assert_eq!(quote_ident("orders"), "\"orders\"");
+        assert_eq!(quote_ident("a\"; DROP"), "\"a\"\"; DROP\"");
+    }
+
+    #[tokio::test]
+    async fn test_teardown() {
+        let state = AppState::new(Config::default()).unwrap();
+        let handle = state.router.connect(BRIDGE_ID);
+        state.router.subscribe_pattern(&handle, "jobs:*").unwrap();
+        let driver = tokio::spawn(std::future::pending());
+        let abort = driver.abort_handle();
+        assert_eq!(state.router.connection_count(), 1);
+
+        drop(Teardown {
+            state: &state,
+            handle,
+            driver,
+        });
+        assert_eq!(state.router.connection_count(), 0);
+        tokio::task::yield_now().await;
+        assert!(abort.is_finished());
+    }
+
+    #[test]
+    fn test_notify_payload() {
+        let msg = Message::new("commands:reindex", br#"{"id":7}"#.to_vec()).with_event("start");
+        let payload: Value = serde_json::from_str(&notify_payload(&msg)).unwrap();
+        assert_eq!(
+            payload,
+            json!({"channel": "commands:reindex", "event": "start", "data": {"id": 7}})
+        );
+
+        let msg = Message::new("commands:reindex", b"now".to_vec());
+        let payload: Value = serde_json::from_str(&notify_payload(&msg)).unwrap();
+        assert_eq!(payload["data"], "now");
+        assert!(payload["event"].is_null());
+    }
+}
@@ -0,0 +1,266 @@
+//! Per-connection rate limits.
+//!
+//! Subscribing and unsubscribing are cheap for a client but not for the
+//! router, so a loop of them is an easy way to load a server. Each
+//! connection gets a token bucket of `limits.subscription_rate` operations
+//! per second with bursts of `limits.subscription_burst`. Operations over the
+//! limit are refused with error code 1006; a client that keeps going gets
+//! `limits.subscription_strikes` refusals before it is disconnected.
+//!
+//! Auto-created channels count against `limits.max_channels`, so each user
+//! (or anonymous connection) may also only cause
+//! `channel_lifecycle.max_creations_per_minute` channels to be created.
+
+use crate::config::{ChannelLifecycleConfig, LimitsConfig};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tenvis_pulse_core::ConnectionHandle;
+
+/// Refusals are forgotten after this long without another.
+const STRIKE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Channel creation budgets refill over this long.
+const CREATION_WINDOW: Duration = Duration::from_secs(60);
+
+/// Allows `rate` operations per second on average, in bursts of up to
+/// `burst`.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// Create a full bucket.
+    #[must_use]
+    pub fn new(rate: f64, burst: f64, now: Instant) -> Self {
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            updated: now,
+        }
+    }
+
+    /// Take a token if one is available.
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// What to do with a rate-limited operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Perform it.
+    Allow,
+    /// Refuse it, keeping the connection.
+    Throttle,
+    /// Refuse it and disconnect the client.
+    Disconnect,
+}
+
+/// Limits one connection's subscribes and unsubscribes.
+#[derive(Debug, Clone)]
+pub struct SubscriptionLimiter {
+    bucket: Option<TokenBucket>,
+    max_strikes: u32,
+    strikes: u32,
+    last_strike: Option<Instant>,
+}
+
+impl SubscriptionLimiter {
+    /// Create a limiter for a new connection.
+    #[must_use]
+    pub fn new(limits: &LimitsConfig, now: Instant) -> Self {
+        let bucket = (limits.subscription_rate > 0).then(|| {
+            TokenBucket::new(
+                f64::from(limits.subscription_rate),
+                f64::from(limits.subscription_burst.max(1)),
+                now,
+            )
+        });
+        Self {
+            bucket,
+            max_strikes: limits.subscription_strikes,
+            strikes: 0,
+            last_strike: None,
+        }
+    }
+
+    /// Decide on a subscribe or unsubscribe.
+    pub fn check(&mut self, now: Instant) -> Verdict {
+        let Some(bucket) = &mut self.bucket else {
+            return Verdict::Allow;
+        };
+        if bucket.try_take(now) {
+            return Verdict::Allow;
+        }
+        if self
+            .last_strike
+            .is_some_and(|last| now.saturating_duration_since(last) >= STRIKE_WINDOW)
+        {
+            self.strikes = 0;
+        }
+        self.strikes += 1;
+        self.last_strike = Some(now);
+        if self.max_strikes > 0 && self.strikes >= self.max_strikes {
+            Verdict::Disconnect
+        } else {
+            Verdict::Throttle
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Creations {
+    buckets: HashMap<String, TokenBucket>,
+    pruned: Instant,
+}
+
+/// Limits how many channels each user or anonymous connection creates.
+#[derive(Debug)]
+pub struct CreationLimiter {
+    per_minute: u32,
+    inner: Mutex<Creations>,
+}
+
+impl CreationLimiter {
+    /// Create a limiter with every budget full.
+    #[must_use]
+    pub fn new(config: &ChannelLifecycleConfig) -> Self {
+        Self {
+            per_minute: config.max_creations_per_minute,
+            inner: Mutex::new(Creations {
+                buckets: HashMap::new(),
+                pruned: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take one channel creation from a connection's budget, shared by all
+    /// connections of its user.
+    ///
+    /// Returns `false` if the budget is spent.
+    pub fn try_take(&self, handle: &ConnectionHandle, now: Instant) -> bool {
+        if self.per_minute == 0 {
+            return true;
+        }
+        let key = match handle.user_id() {
+            Some(user_id) => format!("user:{user_id}"),
+            None => format!("conn:{}", handle.id()),
+        };
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        // Budgets untouched for a window are full again and can be dropped
+        if now.saturating_duration_since(inner.pruned) >= CREATION_WINDOW {
+            inner.pruned = now;
+            inner.buckets.retain(|_, bucket| {
+                now.saturating_duration_since(bucket.updated) < CREATION_WINDOW
+            });
+        }
+        let per_minute = f64::from(self.per_minute);
+        inner
+            .buckets
+            .entry(key)
+            .or_insert_with(|| {
+                TokenBucket::new(per_minute / CREATION_WINDOW.as_secs_f64(), per_minute, now)
+            })
+            .try_take(now)
+    }
+
+    /// Number of budgets being tracked.
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.inner.lock().unwrap().buckets.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2.0, 3.0, start);
+        assert!((0..3).all(|_| bucket.try_take(start)));
+        assert!(!bucket.try_take(start));
+
+        assert!(bucket.try_take(start + Duration::from_millis(500)));
+        assert!(!bucket.try_take(start + Duration::from_millis(500)));
+        // Refills no further than the burst
+        let later = start + Duration::from_secs(60);
+        assert!((0..3).all(|_| bucket.try_take(later)));
+        assert!(!bucket.try_take(later));
+    }
+
+    #[test]
+    fn test_subscription_limiter() {
+        let limits = LimitsConfig {
+            subscription_rate: 1,
+            subscription_burst: 2,
+            subscription_strikes: 3,
+            ..LimitsConfig::default()
+        };
+        let start = Instant::now();
+        let mut limiter = SubscriptionLimiter::new(&limits, start);
+        assert_eq!(limiter.check(start), Verdict::Allow);
+        assert_eq!(limiter.check(start), Verdict::Allow);
+        assert_eq!(limiter.check(start), Verdict::Throttle);
+        assert_eq!(limiter.check(start), Verdict::Throttle);
+
+        // Strikes are forgiven after a quiet spell
+        let later = start + STRIKE_WINDOW + Duration::from_secs(2);
+        assert_eq!(limiter.check(later), Verdict::Allow);
+        assert_eq!(limiter.check(later), Verdict::Allow);
+        assert_eq!(limiter.check(later), Verdict::Throttle);
+        assert_eq!(limiter.check(later), Verdict::Throttle);
+        assert_eq!(limiter.check(later), Verdict::Disconnect);
+
+        let unlimited = LimitsConfig {
+            subscription_rate: 0,
+            ..LimitsConfig::default()
+        };
+        let mut limiter = SubscriptionLimiter::new(&unlimited, start);
+        assert!((0..10_000).all(|_| limiter.check(start) == Verdict::Allow));
+    }
+
+    #[test]
+    fn test_creation_limiter() {
+        let limiter = CreationLimiter::new(&ChannelLifecycleConfig {
+            max_creations_per_minute: 2,
+            ..ChannelLifecycleConfig::default()
+        });
+        let router = tenvis_pulse_core::Router::new();
+        let first = router.connect("conn-1");
+        let second = router.connect("conn-2");
+        let start = Instant::now();
+        assert!(limiter.try_take(&first, start));
+        assert!(limiter.try_take(&first, start));
+        assert!(!limiter.try_take(&first, start));
+        assert!(limiter.try_take(&second, start));
+
+        // Connections of one user share a budget
+        first.set_user_id("mallory");
+        second.set_user_id("mallory");
+        assert!(limiter.try_take(&first, start));
+        assert!(limiter.try_take(&second, start));
+        assert!(!limiter.try_take(&second, start));
+        assert!(limiter.try_take(&second, start + Duration::from_secs(30)));
+
+        // Idle budgets are dropped
+        assert_eq!(limiter.len(), 3);
+        assert!(limiter.try_take(&first, start + Duration::from_secs(100)));
+        assert_eq!(limiter.len(), 1);
+    }
+}
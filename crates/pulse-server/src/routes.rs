@@ -0,0 +1,172 @@
+//! HTTP routes, with hook points for tower middleware.
+
+use crate::admin;
+use crate::handlers::{ws_handler, AppState};
+use crate::health;
+use crate::socketio;
+use crate::stats;
+use axum::extract::Request;
+use axum::response::IntoResponse;
+use axum::routing::{get, Route};
+use axum::Router;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// The server's routes, open to extra middleware before they are served.
+///
+/// Applications embedding Pulse can wrap its routes in their own layers
+/// (timeouts, authentication, rate limiting, request IDs) rather than
+/// configuring Pulse-specific equivalents. Layers are applied separately to
+/// the upgrade routes (WebSocket and Socket.IO), where they run before the
+/// connection is admitted, and to the plain HTTP endpoints (health probes,
+/// `/stats` and the admin API):
+///
+/// ```no_run
+/// use axum::extract::Request;
+/// use axum::http::StatusCode;
+/// use axum::middleware::{self, Next};
+/// use axum::response::{IntoResponse, Response};
+/// # use std::sync::Arc;
+///
+/// async fn require_session(request: Request, next: Next) -> Response {
+///     if request.headers().contains_key("cookie") {
+///         next.run(request).await
+///     } else {
+///         StatusCode::UNAUTHORIZED.into_response()
+///     }
+/// }
+///
+/// # fn build(state: Arc<pulse::AppState>) -> axum::Router {
+/// let app = axum::Router::new().nest(
+///     "/realtime",
+///     pulse::Routes::new(state)
+///         .upgrade_layer(middleware::from_fn(require_session))
+///         .into_router(),
+/// );
+/// # app
+/// # }
+/// ```
+pub struct Routes {
+    state: Arc<AppState>,
+    upgrade: Router<Arc<AppState>>,
+    http: Router<Arc<AppState>>,
+}
+
+impl Routes {
+    /// The WebSocket endpoint, health probes, `/stats`, and Socket.IO and
+    /// the admin API when enabled.
+    #[must_use]
+    pub fn new(state: Arc<AppState>) -> Self {
+        let config = &state.config;
+        let mut upgrade = Router::new().route(&config.transport.websocket_path, get(ws_handler));
+        if config.transport.socketio {
+            upgrade = upgrade.route(socketio::PATH, get(socketio::handler));
+        }
+
+        let mut http = Router::new()
+            .route("/health", get(health::livez_handler))
+            .route("/livez", get(health::livez_handler))
+            .route("/readyz", get(health::readyz_handler))
+            .route("/stats", get(stats::handler));
+        if config.admin.token.is_some() {
+            http = http.merge(admin::routes(state.clone()));
+        }
+
+        Self {
+            state,
+            upgrade,
+            http,
+        }
+    }
+
+    /// Wrap the WebSocket and Socket.IO upgrade routes in a layer.
+    ///
+    /// The layer sees the upgrade request before Pulse checks the client's
+    /// IP, bans and connection limits, and can reject it with any response.
+    #[must_use]
+    pub fn upgrade_layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<Route> + Clone + Send + 'static,
+        L::Service: Service<Request> + Clone + Send + 'static,
+        <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+    {
+        self.upgrade = self.upgrade.layer(layer);
+        self
+    }
+
+    /// Wrap the HTTP endpoints (health probes, `/stats`, admin API) in a
+    /// layer.
+    #[must_use]
+    pub fn http_layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<Route> + Clone + Send + 'static,
+        L::Service: Service<Request> + Clone + Send + 'static,
+        <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+    {
+        self.http = self.http.layer(layer);
+        self
+    }
+
+    /// Finish into a router that can be served on its own or nested into
+    /// another application with `Router::nest`.
+    ///
+    /// Either way it must be served with
+    /// `into_make_service_with_connect_info::<SocketAddr>()`, since
+    /// connections are admitted by client address.
+    pub fn into_router<S>(self) -> Router<S> {
+        self.upgrade.merge(self.http).with_state(self.state)
+    }
+}
+
+/// The server's routes without extra middleware; see [`Routes`].
+pub fn routes<S>(state: Arc<AppState>) -> Router<S> {
+    Routes::new(state).into_router()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use axum::middleware::{self, Next};
+    use axum::response::Response;
+
+    async fn reject(_request: Request, _next: Next) -> Response {
+        StatusCode::IM_A_TEAPOT.into_response()
+    }
+
+    async fn status(app: &mut Router, path: &str) -> StatusCode {
+        let request = Request::builder().uri(path).body(Body::empty()).unwrap();
+        app.call(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_layers() {
+        let state = Arc::new(AppState::new(Config::default()).unwrap());
+
+        let mut app = Router::new().nest(
+            "/realtime",
+            Routes::new(state.clone())
+                .upgrade_layer(middleware::from_fn(reject))
+                .into_router(),
+        );
+        assert_eq!(
+            status(&mut app, "/realtime/ws").await,
+            StatusCode::IM_A_TEAPOT
+        );
+        assert_eq!(status(&mut app, "/realtime/livez").await, StatusCode::OK);
+
+        let mut app = Routes::new(state)
+            .http_layer(middleware::from_fn(reject))
+            .into_router();
+        assert_eq!(status(&mut app, "/readyz").await, StatusCode::IM_A_TEAPOT);
+        assert_ne!(status(&mut app, "/ws").await, StatusCode::IM_A_TEAPOT);
+    }
+}
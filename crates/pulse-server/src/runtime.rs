@@ -0,0 +1,72 @@
+//! The tokio runtime, sized by `[runtime]`.
+
+use anyhow::{Context, Result};
+use pulse::config::RuntimeConfig;
+use tokio::runtime::{Builder, Runtime};
+
+/// Build the multi-threaded runtime `config` describes.
+///
+/// CPU affinity is set on the calling thread first, so every thread the
+/// runtime starts inherits it, and the default worker count only counts the
+/// allowed CPUs.
+///
+/// # Errors
+///
+/// Returns an error if the affinity cannot be set or the runtime fails to
+/// start.
+pub fn build(config: &RuntimeConfig) -> Result<Runtime> {
+    if !config.cpu_affinity.is_empty() {
+        set_affinity(&config.cpu_affinity).context("Failed to set runtime.cpu_affinity")?;
+    }
+
+    let mut builder = Builder::new_multi_thread();
+    builder.enable_all().thread_name(&config.thread_name);
+    if config.worker_threads > 0 {
+        builder.worker_threads(config.worker_threads);
+    }
+    if config.max_blocking_threads > 0 {
+        builder.max_blocking_threads(config.max_blocking_threads);
+    }
+    builder.build().context("Failed to start the runtime")
+}
+
+#[cfg(target_os = "linux")]
+fn set_affinity(cpus: &[usize]) -> std::io::Result<()> {
+    use rustix::thread::{sched_setaffinity, CpuSet};
+
+    let mut set = CpuSet::new();
+    for &cpu in cpus {
+        set.set(cpu);
+    }
+    Ok(sched_setaffinity(None, &set)?)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_affinity(_cpus: &[usize]) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "CPU affinity is only supported on Linux",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        let config = RuntimeConfig {
+            worker_threads: 2,
+            thread_name: "pulse-test".to_string(),
+            ..RuntimeConfig::default()
+        };
+        let runtime = build(&config).unwrap();
+        assert_eq!(runtime.metrics().num_workers(), 2);
+        let name = runtime.block_on(async {
+            tokio::spawn(async { std::thread::current().name().map(str::to_string) })
+                .await
+                .unwrap()
+        });
+        assert_eq!(name.as_deref(), Some("pulse-test"));
+    }
+}
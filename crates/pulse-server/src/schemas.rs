@@ -0,0 +1,140 @@
+//! Per-channel JSON Schema validation of published payloads.
+//!
+//! Client publishes to a channel matching a `[[schemas]]` pattern must carry
+//! a JSON payload conforming to that schema, or are answered with error code
+//! 1015 before reaching any subscriber. The first matching pattern wins, so
+//! one producer's malformed data never fans out to the channel's
+//! subscribers. End-to-end encrypted payloads cannot be validated, so they
+//! are refused on channels with a schema.
+
+use crate::config::SchemaConfig;
+use anyhow::{anyhow, Context, Result};
+use jsonschema::Validator;
+use serde_json::Value;
+use tenvis_pulse_core::{ChannelPattern, Message};
+
+/// The compiled schemas, by channel pattern.
+#[derive(Default)]
+pub struct Schemas {
+    rules: Vec<(ChannelPattern, Validator)>,
+}
+
+impl Schemas {
+    /// Load and compile the configured schemas.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a schema file cannot be read or parsed, or a
+    /// schema is invalid.
+    pub fn new(configs: &[SchemaConfig]) -> Result<Self> {
+        let rules = configs
+            .iter()
+            .map(|config| {
+                let validator = compile(&load(config)?)
+                    .with_context(|| format!("Invalid schema for {}", config.pattern))?;
+                Ok((config.pattern.clone(), validator))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    /// Check a message's payload against its channel's schema.
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason to give the publisher if the payload is encrypted,
+    /// not JSON or does not conform.
+    pub fn check(&self, message: &Message) -> Result<(), String> {
+        let Some((_, validator)) = self.rules.iter().find(|(p, _)| p.matches(&message.channel))
+        else {
+            return Ok(());
+        };
+        if message.encrypted {
+            return Err("Encrypted payloads cannot be checked against the channel's schema".into());
+        }
+        let instance: Value = serde_json::from_slice(&message.payload)
+            .map_err(|e| format!("Payload is not valid JSON: {e}"))?;
+        validator.validate(&instance).map_err(|e| {
+            let path = e.instance_path.to_string();
+            if path.is_empty() {
+                format!("Payload does not match schema: {e}")
+            } else {
+                format!("Payload does not match schema at {path}: {e}")
+            }
+        })
+    }
+}
+
+/// Read a schema from its configuration.
+fn load(config: &SchemaConfig) -> Result<Value> {
+    match (&config.schema, &config.file) {
+        (Some(schema), None) => Ok(schema.clone()),
+        (None, Some(file)) => {
+            let text = std::fs::read_to_string(file)
+                .with_context(|| format!("Failed to read schema {}", file.display()))?;
+            serde_json::from_str(&text)
+                .with_context(|| format!("Failed to parse schema {}", file.display()))
+        }
+        _ => Err(anyhow!(
+            "Schema for {} needs exactly one of schema and file",
+            config.pattern
+        )),
+    }
+}
+
+/// Compile a schema.
+pub(crate) fn compile(schema: &Value) -> Result<Validator> {
+    jsonschema::validator_for(schema).map_err(|e| anyhow!("{e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_schemas_check() {
+        let schemas = Schemas::new(&[SchemaConfig {
+            pattern: ChannelPattern::new("orders:*"),
+            schema: Some(json!({
+                "type": "object",
+                "required": ["id"],
+                "properties": {"id": {"type": "integer"}}
+            })),
+            file: None,
+        }])
+        .unwrap();
+
+        let check =
+            |channel: &str, payload: &[u8]| schemas.check(&Message::new(channel, payload.to_vec()));
+        assert!(check("orders:eu", br#"{"id": 7}"#).is_ok());
+        let reason = check("orders:eu", br#"{"id": "7"}"#).unwrap_err();
+        assert!(reason.contains("at /id"), "{reason}");
+        assert!(check("orders:eu", br"{}").is_err());
+        assert!(check("orders:eu", b"not json")
+            .unwrap_err()
+            .starts_with("Payload is not valid JSON"));
+        let sealed = Message::new("orders:eu", br#"{"id": 7}"#.to_vec()).with_encryption(None);
+        assert!(schemas.check(&sealed).is_err());
+
+        // Channels without a schema take any payload
+        assert!(check("chat:lobby", b"not json").is_ok());
+    }
+
+    #[test]
+    fn test_schemas_invalid() {
+        let config = SchemaConfig {
+            pattern: ChannelPattern::new("orders:*"),
+            schema: Some(json!({"type": 42})),
+            file: None,
+        };
+        assert!(Schemas::new(&[config]).is_err());
+
+        let config = SchemaConfig {
+            pattern: ChannelPattern::new("orders:*"),
+            schema: None,
+            file: Some("/nonexistent/order.json".into()),
+        };
+        assert!(Schemas::new(&[config]).is_err());
+    }
+}
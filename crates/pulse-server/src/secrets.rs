@@ -0,0 +1,167 @@
+//! Secrets kept out of the config file.
+//!
+//! Strings in `pulse.toml` may reference environment variables as `${NAME}`,
+//! or `${NAME:-default}` to fall back when unset; `$${` is a literal `${`.
+//! Sensitive settings also have `*_file` variants naming a file to read the
+//! value from, such as a mounted Kubernetes or Docker secret.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::path::Path;
+
+/// Replace `${NAME}` references in every string of a TOML document.
+///
+/// # Errors
+///
+/// Returns an error naming the setting if a referenced variable is unset
+/// and has no default, or a reference is not closed.
+pub fn interpolate_toml(
+    value: &mut toml::Value,
+    lookup: &impl Fn(&str) -> Option<String>,
+) -> Result<()> {
+    interpolate_at(value, lookup, &mut String::new())
+}
+
+fn interpolate_at(
+    value: &mut toml::Value,
+    lookup: &impl Fn(&str) -> Option<String>,
+    key: &mut String,
+) -> Result<()> {
+    match value {
+        toml::Value::String(s) => {
+            *s = interpolate(s, lookup).with_context(|| format!("In setting {key}"))?;
+        }
+        toml::Value::Array(values) => {
+            for value in values {
+                interpolate_at(value, lookup, key)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (name, value) in table.iter_mut() {
+                let len = key.len();
+                if !key.is_empty() {
+                    key.push('.');
+                }
+                key.push_str(name);
+                interpolate_at(value, lookup, key)?;
+                key.truncate(len);
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Replace `${NAME}` references in a string.
+///
+/// # Errors
+///
+/// Returns an error if a referenced variable is unset and has no default, or
+/// a reference is not closed.
+pub fn interpolate(input: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(start) = rest.find('$') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(escaped) = rest.strip_prefix("$${") {
+            output.push_str("${");
+            rest = escaped;
+        } else if let Some(reference) = rest.strip_prefix("${") {
+            let end = reference
+                .find('}')
+                .ok_or_else(|| anyhow!("Unclosed ${{ in {input:?}"))?;
+            let (name, default) = match reference[..end].split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (&reference[..end], None),
+            };
+            match lookup(name).or_else(|| default.map(str::to_string)) {
+                Some(value) => output.push_str(&value),
+                None => bail!("Environment variable {name} is not set"),
+            }
+            rest = &reference[end + 1..];
+        } else {
+            output.push('$');
+            rest = &rest[1..];
+        }
+    }
+
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// Fill a secret from its `*_file` variant, if one is given.
+///
+/// # Errors
+///
+/// Returns an error if both the value and the file are set, or the file
+/// cannot be read.
+pub fn fill(name: &str, value: &mut Option<String>, file: Option<&Path>) -> Result<()> {
+    let Some(path) = file else {
+        return Ok(());
+    };
+    if value.is_some() {
+        bail!("{name} and {name}_file are both set; use one");
+    }
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {name}_file: {}", path.display()))?;
+    // Secret files usually end with a newline that isn't part of the secret
+    *value = Some(contents.trim_end_matches(['\r', '\n']).to_string());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(name: &str) -> Option<String> {
+        (name == "DB_PASSWORD").then(|| "hunter2".to_string())
+    }
+
+    #[test]
+    fn test_interpolate() {
+        assert_eq!(
+            interpolate("postgres://pulse:${DB_PASSWORD}@db/app", env).unwrap(),
+            "postgres://pulse:hunter2@db/app"
+        );
+        assert_eq!(
+            interpolate("${DB_HOST:-localhost}", env).unwrap(),
+            "localhost"
+        );
+        assert_eq!(
+            interpolate("$system:stats $${HOME}", env).unwrap(),
+            "$system:stats ${HOME}"
+        );
+        assert!(interpolate("${DB_HOST}", env).is_err());
+        assert!(interpolate("${DB_PASSWORD", env).is_err());
+    }
+
+    #[test]
+    fn test_interpolate_toml() {
+        let mut value: toml::Value = toml::from_str(
+            r#"
+            [admin]
+            token = "${ADMIN_TOKEN}"
+        "#,
+        )
+        .unwrap();
+        let err = interpolate_toml(&mut value, &env).unwrap_err();
+        assert!(format!("{err:#}").contains("admin.token"));
+    }
+
+    #[test]
+    fn test_fill() {
+        let path = std::env::temp_dir().join(format!("pulse-secret-{}", std::process::id()));
+        std::fs::write(&path, "s3cret\n").unwrap();
+
+        let mut token = None;
+        fill("admin.token", &mut token, Some(&path)).unwrap();
+        assert_eq!(token.as_deref(), Some("s3cret"));
+        assert!(fill("admin.token", &mut token, Some(&path)).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+        let mut token = None;
+        assert!(fill("admin.token", &mut token, Some(&path)).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+//! Load shedding under overload.
+//!
+//! When connections, the publish rate or router memory cross a `[shedding]`
+//! high-water mark, the server sheds load until every metric falls back
+//! below [`RECOVERY_RATIO`] of its mark: new WebSocket clients get a
+//! Disconnect with reason `overloaded` and a `reconnect_after` hint of one to
+//! two `retry_after_ms`, Socket.IO clients a 503 with `Retry-After`, and
+//! messages on `low_priority_channels` are queued at low priority. `/readyz`
+//! reports unready and the `pulse_shedding` gauge is 1 while shedding.
+
+use crate::config::SheddingConfig;
+use crate::handlers::AppState;
+use crate::metrics;
+use pulse_protocol::Priority;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tenvis_pulse_core::Message;
+use tracing::{info, warn};
+
+/// How often the high-water marks are checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Fraction of each mark every metric must fall below to stop shedding, so
+/// load hovering around a mark does not flap in and out.
+pub const RECOVERY_RATIO: f64 = 0.9;
+
+/// Load measured against the high-water marks.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Load {
+    /// Live connections.
+    pub connections: usize,
+    /// Publishes per second.
+    pub publish_rate: f64,
+    /// Approximate router memory in bytes.
+    pub memory_bytes: usize,
+}
+
+/// Whether the server is shedding load, and why.
+#[derive(Debug)]
+pub struct Shedder {
+    config: SheddingConfig,
+    active: AtomicBool,
+    memory_bytes: AtomicUsize,
+}
+
+impl Shedder {
+    /// Create a shedder that is not shedding.
+    #[must_use]
+    pub fn new(config: &SheddingConfig) -> Self {
+        Self {
+            config: config.clone(),
+            active: AtomicBool::new(false),
+            memory_bytes: AtomicUsize::new(0),
+        }
+    }
+
+    /// Whether load is being shed.
+    #[must_use]
+    pub fn is_shedding(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// How long refused clients should wait before reconnecting.
+    #[must_use]
+    pub fn retry_after(&self) -> Duration {
+        Duration::from_millis(self.config.retry_after_ms.into())
+    }
+
+    /// Record the router memory last measured.
+    pub fn record_memory(&self, bytes: usize) {
+        self.memory_bytes.store(bytes, Ordering::Relaxed);
+    }
+
+    /// Get the router memory last measured.
+    #[must_use]
+    pub fn memory_bytes(&self) -> usize {
+        self.memory_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Compare load against the marks, starting or stopping shedding.
+    ///
+    /// Returns the mark crossed when shedding starts, or `None` if the state
+    /// is unchanged or shedding stopped.
+    pub fn update(&self, load: Load) -> Option<&'static str> {
+        if self.is_shedding() {
+            if self.over(load, RECOVERY_RATIO).is_none() {
+                self.active.store(false, Ordering::Relaxed);
+                info!(
+                    connections = load.connections,
+                    publish_rate = load.publish_rate,
+                    memory_bytes = load.memory_bytes,
+                    "Load recovered, no longer shedding"
+                );
+                metrics::record_shedding(false);
+            }
+            return None;
+        }
+        let mark = self.over(load, 1.0)?;
+        self.active.store(true, Ordering::Relaxed);
+        warn!(
+            mark,
+            connections = load.connections,
+            publish_rate = load.publish_rate,
+            memory_bytes = load.memory_bytes,
+            "Overloaded, shedding load"
+        );
+        metrics::record_shedding(true);
+        Some(mark)
+    }
+
+    /// Queue a message at low priority if it is on a low-priority channel and
+    /// load is being shed.
+    #[must_use]
+    pub fn deprioritize(&self, message: Message) -> Message {
+        if self.is_shedding()
+            && self
+                .config
+                .low_priority_channels
+                .iter()
+                .any(|pattern| pattern.matches(&message.channel))
+        {
+            message.with_priority(Priority::Low)
+        } else {
+            message
+        }
+    }
+
+    /// The first mark `load` reaches once scaled by `ratio`, if any.
+    fn over(&self, load: Load, ratio: f64) -> Option<&'static str> {
+        let reaches = |value: f64, mark: f64| mark > 0.0 && value >= mark * ratio;
+        let config = &self.config;
+        if reaches(load.connections as f64, config.max_connections as f64) {
+            Some("max_connections")
+        } else if reaches(load.publish_rate, config.max_publish_rate as f64) {
+            Some("max_publish_rate")
+        } else if reaches(load.memory_bytes as f64, config.max_memory_bytes as f64) {
+            Some("max_memory_bytes")
+        } else {
+            None
+        }
+    }
+}
+
+/// Check the high-water marks every second, if any are set.
+pub fn spawn(state: &Arc<AppState>) {
+    if !state.config.shedding.enabled() {
+        return;
+    }
+    let state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        let mut last = (Instant::now(), state.stats.totals().0);
+        loop {
+            interval.tick().await;
+            let now = (Instant::now(), state.stats.totals().0);
+            let secs = now.0.duration_since(last.0).as_secs_f64();
+            let publish_rate = if secs > 0.0 {
+                now.1.saturating_sub(last.1) as f64 / secs
+            } else {
+                0.0
+            };
+            last = now;
+            state.shedder.update(Load {
+                connections: state.stats.connections(),
+                publish_rate,
+                memory_bytes: state.shedder.memory_bytes(),
+            });
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tenvis_pulse_core::ChannelPattern;
+
+    fn load(connections: usize, publish_rate: f64) -> Load {
+        Load {
+            connections,
+            publish_rate,
+            memory_bytes: 0,
+        }
+    }
+
+    #[test]
+    fn test_shedder_hysteresis() {
+        let shedder = Shedder::new(&SheddingConfig {
+            max_connections: 100,
+            max_publish_rate: 1000,
+            ..SheddingConfig::default()
+        });
+        assert_eq!(shedder.update(load(99, 999.0)), None);
+        assert!(!shedder.is_shedding());
+
+        assert_eq!(shedder.update(load(50, 1000.0)), Some("max_publish_rate"));
+        assert!(shedder.is_shedding());
+
+        // Still within 90% of a mark
+        shedder.update(load(95, 10.0));
+        assert!(shedder.is_shedding());
+        shedder.update(load(89, 899.0));
+        assert!(!shedder.is_shedding());
+
+        // Unset marks are never reached
+        shedder.record_memory(usize::MAX);
+        assert_eq!(
+            shedder.update(Load {
+                memory_bytes: shedder.memory_bytes(),
+                ..Load::default()
+            }),
+            None
+        );
+    }
+
+    #[test]
+    fn test_shedder_deprioritize() {
+        let shedder = Shedder::new(&SheddingConfig {
+            max_connections: 1,
+            low_priority_channels: vec![ChannelPattern::new("analytics:*")],
+            ..SheddingConfig::default()
+        });
+        let message = || Message::new("analytics:clicks", b"{}".to_vec());
+        assert_eq!(shedder.deprioritize(message()).priority, Priority::Normal);
+
+        shedder.update(load(1, 0.0));
+        assert_eq!(shedder.deprioritize(message()).priority, Priority::Low);
+        let chat = shedder.deprioritize(Message::new("chat", b"{}".to_vec()));
+        assert_eq!(chat.priority, Priority::Normal);
+    }
+}
@@ -0,0 +1,125 @@
+//! Verification of signed publishes.
+//!
+//! Publishers sign messages with a key from `[[signing_keys]]` and name it in
+//! the Publish `signer` field. A publish with a signature that does not
+//! verify, or an unsigned publish to a channel one of the keys covers, is
+//! answered with error code 1003. Valid signatures are forwarded to
+//! subscribers, who can check them with the same key.
+
+use crate::config::SigningKeyConfig;
+use std::collections::HashMap;
+use tenvis_pulse_core::{ChannelPattern, Message, MessageSigner};
+
+/// The configured signing keys, by ID.
+#[derive(Debug, Default)]
+pub struct SigningKeys {
+    signers: HashMap<String, MessageSigner>,
+    /// Channel patterns that must be signed, with the key they need.
+    required: Vec<(ChannelPattern, String)>,
+}
+
+impl SigningKeys {
+    /// Build the keys from their configuration. Keys without a secret are
+    /// skipped; [`Config::validate`](crate::Config::validate) reports them.
+    #[must_use]
+    pub fn new(configs: &[SigningKeyConfig]) -> Self {
+        let mut keys = Self::default();
+        for config in configs {
+            let Some(secret) = &config.secret else {
+                continue;
+            };
+            keys.signers
+                .insert(config.id.clone(), MessageSigner::new(secret));
+            for pattern in &config.channels {
+                keys.required.push((pattern.clone(), config.id.clone()));
+            }
+        }
+        keys
+    }
+
+    /// Check a message's signature, and that it is signed if its channel
+    /// requires it.
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason to give the publisher if the signature is missing
+    /// or invalid.
+    pub fn check(&self, message: &Message) -> Result<(), String> {
+        let channel = &*message.channel;
+        let mut required = self
+            .required
+            .iter()
+            .filter(|(pattern, _)| pattern.matches(channel))
+            .map(|(_, id)| id)
+            .peekable();
+
+        let (Some(signer), Some(signature)) = (&message.signer, &message.signature) else {
+            if required.peek().is_some() {
+                return Err(format!("Publishes to {channel} must be signed"));
+            }
+            return Ok(());
+        };
+        let verified = self.signers.get(signer).is_some_and(|key| {
+            key.verify(
+                channel,
+                message.event.as_deref(),
+                &message.payload,
+                signature,
+            )
+        });
+        if !verified {
+            return Err(format!("Invalid signature for signing key {signer:?}"));
+        }
+        if required.peek().is_some() && !required.any(|id| id == signer) {
+            return Err(format!(
+                "Publishes to {channel} must be signed with another key"
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(id: &str, secret: &str, channels: &[&str]) -> SigningKeyConfig {
+        SigningKeyConfig {
+            id: id.to_string(),
+            secret: Some(secret.to_string()),
+            secret_file: None,
+            channels: channels.iter().map(|c| ChannelPattern::new(*c)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_signing_keys_check() {
+        let keys = SigningKeys::new(&[
+            key("billing", "billing-secret", &["orders:*"]),
+            key("search", "search-secret", &[]),
+        ]);
+        let billing = MessageSigner::new("billing-secret");
+        let search = MessageSigner::new("search-secret");
+        let signed = |channel: &str, id: &str, signer: &MessageSigner| {
+            let signature = signer.sign(channel, None, b"{}");
+            Message::new(channel, b"{}".to_vec()).with_signature(id, signature)
+        };
+
+        assert!(keys
+            .check(&signed("orders:eu", "billing", &billing))
+            .is_ok());
+        assert!(keys.check(&signed("chat", "search", &search)).is_ok());
+        assert!(keys.check(&Message::new("chat", b"{}".to_vec())).is_ok());
+
+        // Required, forged, tampered or signed with the wrong key
+        assert!(keys
+            .check(&Message::new("orders:eu", b"{}".to_vec()))
+            .is_err());
+        assert!(keys.check(&signed("chat", "billing", &search)).is_err());
+        assert!(keys.check(&signed("chat", "unknown", &search)).is_err());
+        assert!(keys.check(&signed("orders:eu", "search", &search)).is_err());
+        let mut tampered = signed("orders:eu", "billing", &billing);
+        tampered.payload = std::sync::Arc::new(b"{\"x\":1}".to_vec().into());
+        assert!(keys.check(&tampered).is_err());
+    }
+}
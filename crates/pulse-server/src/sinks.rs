@@ -0,0 +1,377 @@
+//! HTTP push sinks.
+//!
+//! A sink subscribes to channel patterns and POSTs the messages it receives
+//! to an application endpoint in JSON batches, so backends can consume
+//! realtime traffic without holding a socket open:
+//!
+//! ```json
+//! {"sink": "analytics", "messages": [
+//!   {"id": 1, "channel": "orders:42", "event": "created", "seq": 7,
+//!    "timestamp": 1700000000000, "data": {"total": 12}}
+//! ]}
+//! ```
+//!
+//! With a secret configured, each request carries `X-Pulse-Timestamp` (Unix
+//! seconds) and `X-Pulse-Signature: sha256=<hex>`, the HMAC-SHA256 of
+//! `{timestamp}.{body}`. Requests failing with a network error, a timeout,
+//! 408, 429 or 5xx are retried with exponential backoff; batches that still
+//! fail are appended to the sink's dead-letter file.
+//!
+//! While a batch is in flight, new messages wait in the sink's connection
+//! queue (`limits.max_queued_messages`), subject to the channel's drop policy.
+
+use crate::config::SinkConfig;
+use crate::handlers::AppState;
+use crate::metrics;
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use http_body_util::{BodyExt, Full};
+use hyper::header::{CONTENT_TYPE, USER_AGENT};
+use hyper::{Method, Request, StatusCode, Uri};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tenvis_pulse_core::{ConnectionHandle, Message};
+use thiserror::Error;
+use tokio::io::AsyncWriteExt;
+use tokio::time::{timeout, timeout_at, Instant};
+use tracing::{debug, error, info, warn};
+
+pub(crate) type HttpClient = Client<HttpsConnector<HttpConnector>, Full<Bytes>>;
+
+/// Longest delay between retries.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A configured sink with its parsed endpoint.
+struct Sink {
+    config: SinkConfig,
+    endpoint: Endpoint,
+}
+
+/// An application endpoint that signed JSON requests are POSTed to, with
+/// its retry policy. Shared by sinks and webhooks.
+pub(crate) struct Endpoint {
+    pub(crate) client: HttpClient,
+    pub(crate) uri: Uri,
+    pub(crate) secret: Option<String>,
+    pub(crate) timeout: Duration,
+    pub(crate) max_retries: u32,
+    pub(crate) retry_backoff: Duration,
+}
+
+/// Why a request could not be delivered.
+#[derive(Debug, Error)]
+pub(crate) enum DeliveryError {
+    #[error("request failed: {0}")]
+    Request(#[from] hyper_util::client::legacy::Error),
+
+    #[error("request timed out")]
+    Timeout,
+
+    #[error("endpoint returned {0}")]
+    Status(StatusCode),
+}
+
+impl DeliveryError {
+    /// Whether the request may succeed if sent again.
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::Request(_) | Self::Timeout => true,
+            Self::Status(status) => {
+                status.is_server_error()
+                    || *status == StatusCode::TOO_MANY_REQUESTS
+                    || *status == StatusCode::REQUEST_TIMEOUT
+            }
+        }
+    }
+}
+
+/// Start every configured sink.
+///
+/// # Errors
+///
+/// Returns an error if a sink URL is invalid or TLS roots cannot be loaded.
+pub fn spawn(state: &Arc<AppState>) -> Result<()> {
+    if state.config.sinks.is_empty() {
+        return Ok(());
+    }
+
+    let client = http_client()?;
+    for config in &state.config.sinks {
+        let uri = config
+            .url
+            .parse()
+            .with_context(|| format!("Invalid URL for sink {}: {}", config.name, config.url))?;
+        let sink = Arc::new(Sink {
+            config: config.clone(),
+            endpoint: Endpoint {
+                client: client.clone(),
+                uri,
+                secret: config.secret.clone(),
+                timeout: Duration::from_millis(config.timeout_ms),
+                max_retries: config.max_retries,
+                retry_backoff: Duration::from_millis(config.retry_backoff_ms),
+            },
+        });
+        tokio::spawn(run_sink(state.clone(), sink));
+    }
+    Ok(())
+}
+
+/// Build the client for requests to application endpoints.
+///
+/// # Errors
+///
+/// Returns an error if TLS root certificates cannot be loaded.
+pub(crate) fn http_client() -> Result<HttpClient> {
+    let connector = HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .context("Failed to load TLS root certificates")?
+        .https_or_http()
+        .enable_http1()
+        .build();
+    Ok(Client::builder(TokioExecutor::new()).build(connector))
+}
+
+/// Keep a sink subscribed, resubscribing if its connection is closed.
+async fn run_sink(state: Arc<AppState>, sink: Arc<Sink>) {
+    let name = &sink.config.name;
+    info!(sink = %name, url = %sink.config.url, "Sink started");
+    loop {
+        match forward(&state, &sink).await {
+            Ok(()) => info!(sink = %name, "Sink connection closed"),
+            Err(e) => warn!(sink = %name, error = %e, "Sink failed"),
+        }
+        tokio::time::sleep(Duration::from_millis(sink.config.batch_ms)).await;
+    }
+}
+
+/// Subscribe to the sink's patterns and deliver batches until closed.
+async fn forward(state: &AppState, sink: &Sink) -> Result<()> {
+    let handle = state.router.connect(&format!("sink:{}", sink.config.name));
+    let result = async {
+        for pattern in &sink.config.channels {
+            state.router.subscribe_pattern(&handle, pattern.clone())?;
+        }
+        collect(sink, &handle).await;
+        Ok(())
+    }
+    .await;
+    state.router.disconnect(&handle);
+    result
+}
+
+/// Gather messages into batches and deliver them.
+async fn collect(sink: &Sink, handle: &ConnectionHandle) {
+    let window = Duration::from_millis(sink.config.batch_ms);
+    let batch_size = sink.config.batch_size.max(1);
+    let mut batch = Vec::with_capacity(batch_size);
+
+    loop {
+        let Some(first) = handle.recv().await else {
+            return;
+        };
+        batch.push(first);
+
+        // Fill the batch until it is full or the window closes
+        let deadline = Instant::now() + window;
+        let mut open = true;
+        while batch.len() < batch_size {
+            match timeout_at(deadline, handle.recv()).await {
+                Ok(Some(msg)) => batch.push(msg),
+                Ok(None) => {
+                    open = false;
+                    break;
+                }
+                Err(_) => break,
+            }
+        }
+
+        deliver(sink, &batch).await;
+        batch.clear();
+        if !open {
+            return;
+        }
+    }
+}
+
+/// POST a batch, retrying transient failures, then dead-letter it.
+async fn deliver(sink: &Sink, batch: &[Arc<Message>]) {
+    let name = &sink.config.name;
+    let body = batch_body(name, batch);
+    let error = match sink.endpoint.send(Bytes::from(body.to_string())).await {
+        Ok(()) => {
+            debug!(sink = %name, messages = batch.len(), "Delivered batch");
+            metrics::record_sink_batch(name, "delivered", batch.len());
+            return;
+        }
+        Err(e) => e,
+    };
+
+    warn!(sink = %name, error = %error, messages = batch.len(), "Failed to deliver batch");
+    metrics::record_sink_batch(name, "dead_lettered", batch.len());
+    if let Some(path) = &sink.config.dead_letter {
+        if let Err(e) = dead_letter(path, &error, body).await {
+            error!(sink = %name, path = %path.display(), error = %e, "Failed to write dead letter");
+        }
+    }
+}
+
+impl Endpoint {
+    /// POST a JSON body, retrying transient failures with exponential
+    /// backoff.
+    pub(crate) async fn send(&self, body: Bytes) -> Result<(), DeliveryError> {
+        let mut backoff = self.retry_backoff;
+        let mut retries = 0;
+        loop {
+            match self.post(body.clone()).await {
+                Ok(()) => return Ok(()),
+                Err(error) if error.is_retryable() && retries < self.max_retries => {
+                    debug!(url = %self.uri, error = %error, retry = retries + 1, "Retrying request");
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    retries += 1;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Send one request.
+    async fn post(&self, body: Bytes) -> Result<(), DeliveryError> {
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(self.uri.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .header(USER_AGENT, concat!("pulse/", env!("CARGO_PKG_VERSION")));
+        if let Some(secret) = &self.secret {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            request = request
+                .header("X-Pulse-Timestamp", timestamp)
+                .header("X-Pulse-Signature", signature(secret, timestamp, &body));
+        }
+        let request = request
+            .body(Full::new(body))
+            .expect("request is well-formed");
+
+        let send = async {
+            let response = self.client.request(request).await?;
+            let status = response.status();
+            // Drain the body so the connection can be reused
+            let _ = response.into_body().collect().await;
+            Ok::<_, DeliveryError>(status)
+        };
+        let status = timeout(self.timeout, send)
+            .await
+            .map_err(|_| DeliveryError::Timeout)??;
+
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(DeliveryError::Status(status))
+        }
+    }
+}
+
+/// Append an undeliverable batch to the dead-letter file.
+async fn dead_letter(path: &Path, error: &DeliveryError, batch: Value) -> std::io::Result<()> {
+    let failed_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let mut line = json!({
+        "failed_at": failed_at,
+        "error": error.to_string(),
+        "batch": batch,
+    })
+    .to_string();
+    line.push('\n');
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(line.as_bytes()).await
+}
+
+/// Build the JSON request body for a batch.
+fn batch_body(name: &str, batch: &[Arc<Message>]) -> Value {
+    let messages: Vec<Value> = batch
+        .iter()
+        .map(|msg| {
+            json!({
+                "id": msg.id,
+                "channel": &*msg.channel,
+                "event": msg.event,
+                "seq": msg.seq,
+                "timestamp": msg.timestamp,
+                "data": payload_value(&msg.payload),
+            })
+        })
+        .collect();
+    json!({ "sink": name, "messages": messages })
+}
+
+/// Represent a payload in JSON: parsed if it is JSON, otherwise as a string.
+pub(crate) fn payload_value(payload: &[u8]) -> Value {
+    serde_json::from_slice(payload)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(payload).into_owned()))
+}
+
+/// Compute the `X-Pulse-Signature` header for a request.
+fn signature(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature() {
+        assert_eq!(
+            signature("secret", 1_700_000_000, br#"{"a":1}"#),
+            "sha256=49f24e537407743fa4a0242bb63b94b9a47ee99cbbe071ccd8a22550ae411686"
+        );
+    }
+
+    #[test]
+    fn test_batch_body() {
+        let json =
+            Arc::new(Message::new("orders:42", br#"{"total":12}"#.to_vec()).with_event("created"));
+        let text = Arc::new(Message::new("orders:43", b"hello".to_vec()));
+        let body = batch_body("analytics", &[json, text]);
+
+        assert_eq!(body["sink"], "analytics");
+        assert_eq!(body["messages"][0]["channel"], "orders:42");
+        assert_eq!(body["messages"][0]["event"], "created");
+        assert_eq!(body["messages"][0]["data"], json!({"total": 12}));
+        assert_eq!(body["messages"][1]["data"], "hello");
+        assert!(body["messages"][1]["event"].is_null());
+    }
+
+    #[test]
+    fn test_retryable() {
+        assert!(DeliveryError::Timeout.is_retryable());
+        assert!(DeliveryError::Status(StatusCode::BAD_GATEWAY).is_retryable());
+        assert!(DeliveryError::Status(StatusCode::TOO_MANY_REQUESTS).is_retryable());
+        assert!(!DeliveryError::Status(StatusCode::BAD_REQUEST).is_retryable());
+        assert!(!DeliveryError::Status(StatusCode::UNAUTHORIZED).is_retryable());
+    }
+}
@@ -0,0 +1,673 @@
+//! Socket.IO compatibility endpoint.
+//!
+//! Lets existing Socket.IO browser clients talk to Pulse while they migrate.
+//! Only Engine.IO v4 over WebSocket is supported (clients must set
+//! `transports: ["websocket"]`), on the default namespace, without binary
+//! attachments.
+//!
+//! Clients use three events, each optionally acknowledged with
+//! `null` on success or `{code, message}` on failure:
+//!
+//! - `emit("subscribe", channel[, auth])`
+//! - `emit("unsubscribe", channel)`
+//! - `emit("publish", channel, data[, event])`
+//!
+//! Channel messages arrive as `(event, data, {channel, seq})`, where `event`
+//! is the message's event name or `"message"`, and presence changes as
+//! `("presence", {channel, action, data})`.
+
+use crate::affinity;
+use crate::audit::{AuditEvent, AuditKind};
+#[cfg(feature = "chaos")]
+use crate::chaos::{self, Fault};
+use crate::handlers::{self, AppState};
+use crate::ip_limits::IpConnectionGuard;
+use crate::metadata;
+use crate::metrics::{self, ConnectionMetricsGuard};
+use crate::rate_limit::{SubscriptionLimiter, Verdict};
+use crate::stats::Transport;
+use axum::{
+    extract::{
+        ws::{Message as WsMessage, WebSocket, WebSocketUpgrade},
+        ConnectInfo, Query, State,
+    },
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures_util::{SinkExt, StreamExt};
+use pulse_protocol::{error_codes, DisconnectReason};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tenvis_pulse_core::{
+    CloseReason, ConnectionHandle, ConnectionMetadata, Message, MessageKind, RouterError,
+};
+use tokio::time::{sleep_until, Instant};
+use tracing::{debug, info, warn};
+
+/// Path Socket.IO clients connect to.
+pub const PATH: &str = "/socket.io/";
+
+/// Handshake query parameters.
+#[derive(Debug, Deserialize)]
+pub struct HandshakeQuery {
+    /// Engine.IO protocol version.
+    #[serde(rename = "EIO")]
+    eio: Option<String>,
+    /// Requested transport.
+    transport: Option<String>,
+    /// Affinity token from the server the client last connected to.
+    affinity: Option<String>,
+}
+
+/// Socket.IO upgrade handler.
+pub async fn handler(
+    ws: Option<WebSocketUpgrade>,
+    Query(query): Query<HandshakeQuery>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    if query.eio.as_deref() != Some("4") {
+        return engine_error(5, "Unsupported protocol version");
+    }
+    let (Some(ws), Some("websocket")) = (ws, query.transport.as_deref()) else {
+        // HTTP long-polling is not supported
+        return engine_error(0, "Transport unknown");
+    };
+
+    let (ip, ip_guard) = match handlers::admit(&state, addr, &headers, Transport::SocketIo) {
+        Ok(admitted) => admitted,
+        Err(response) => return *response,
+    };
+    let token = state.affinity_token();
+    let presented = affinity::presented(query.affinity.as_deref(), &headers);
+    affinity::check(token.as_deref(), presented, Transport::SocketIo);
+    if state.shedder.is_shedding() {
+        debug!(ip = %ip, "Refused Socket.IO connection while shedding load");
+        metrics::record_error("overloaded", Transport::SocketIo);
+        let retry_after = state.shedder.retry_after().as_secs().max(1);
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, retry_after.to_string())],
+            "Server overloaded",
+        )
+            .into_response();
+    }
+    let metadata = metadata::capture(&headers, &state.config.metadata);
+    let max_packet_size = state.config.limits.max_message_size * 2;
+    let response = ws
+        .max_message_size(max_packet_size)
+        .on_upgrade(move |socket| serve(socket, state, ip, metadata, ip_guard));
+    affinity::tag(response, token.as_deref())
+}
+
+/// Refuse a handshake the way Engine.IO servers do.
+fn engine_error(code: u8, message: &str) -> Response {
+    let body = json!({ "code": code, "message": message });
+    (StatusCode::BAD_REQUEST, Json(body)).into_response()
+}
+
+/// Serve a Socket.IO client until it disconnects.
+async fn serve(
+    socket: WebSocket,
+    state: Arc<AppState>,
+    ip: IpAddr,
+    metadata: ConnectionMetadata,
+    _ip_guard: IpConnectionGuard,
+) {
+    let _metrics_guard = ConnectionMetricsGuard::new(Transport::SocketIo);
+    let _transport_guard = state.stats.connect(Transport::SocketIo);
+    let sid = state.id_generator.generate().0;
+    let (mut sender, mut receiver) = socket.split();
+
+    // Engine.IO: the server pings, and the client must answer within the timeout
+    let heartbeat = &state.config.heartbeat;
+    let ping_interval = Duration::from_millis(heartbeat.interval_ms);
+    let ping_timeout =
+        Duration::from_millis(heartbeat.timeout_ms.saturating_sub(heartbeat.interval_ms));
+    let mut open = json!({
+        "sid": sid,
+        "upgrades": [],
+        "pingInterval": ping_interval.as_millis() as u64,
+        "pingTimeout": ping_timeout.as_millis() as u64,
+        "maxPayload": state.config.limits.max_message_size,
+    });
+    if let Some(token) = state.affinity_token() {
+        open["affinity"] = token.into();
+    }
+    if sender
+        .send(WsMessage::Text(format!("0{open}")))
+        .await
+        .is_err()
+    {
+        return;
+    }
+    debug!(connection = %sid, ip = %ip, "Socket.IO connected");
+
+    let handle = state.router.connect(&sid);
+    handle.set_remote_ip(ip);
+    handle.set_transport("socket.io");
+    handle.set_metadata(metadata);
+    state.audit.record(AuditEvent::for_connection(
+        AuditKind::Connect,
+        &handle,
+        "socket.io",
+    ));
+
+    let mut session = Session {
+        sid: &sid,
+        state: &state,
+        handle: &handle,
+        connected: false,
+        subscriptions: SubscriptionLimiter::new(&state.config.limits, std::time::Instant::now()),
+    };
+    let mut next_ping = Instant::now() + ping_interval;
+    let mut last_seen = Instant::now();
+
+    'connection: loop {
+        let mut delivering = None;
+        let mut traced = None;
+        let replies = tokio::select! {
+            _ = sleep_until(next_ping) => {
+                next_ping = Instant::now() + ping_interval;
+                vec!["2".to_string()]
+            }
+
+            _ = sleep_until(last_seen + ping_interval + ping_timeout) => {
+                info!(connection = %sid, "Socket.IO ping timed out");
+                metrics::record_error("heartbeat_timeout", Transport::SocketIo);
+                break;
+            }
+
+            msg = handle.recv() => {
+                let Some(msg) = msg else {
+                    // Closed by the server: tell the client it was disconnected
+                    if let Some(reason) = handle.close_reason() {
+                        info!(connection = %sid, reason = ?reason.reason, "Disconnecting Socket.IO client");
+                        let _ = sender.send(WsMessage::Text("41".to_string())).await;
+                        let _ = sender.send(WsMessage::Close(None)).await;
+                    }
+                    break;
+                };
+                #[cfg(feature = "chaos")]
+                match chaos::fault(&state.config.chaos, &msg) {
+                    Some(Fault::Delay(delay)) => tokio::time::sleep(delay).await,
+                    Some(Fault::Drop) => continue,
+                    Some(Fault::Lag) => {
+                        chaos::lag(&handle);
+                        continue;
+                    }
+                    Some(Fault::Reset) => break,
+                    None => {}
+                }
+                state.stats.record_delivered();
+                delivering = msg.created_at;
+                metrics::record_payload_size(msg.payload.len(), "outbound");
+                let packet = delivery_packet(&msg).encode();
+                traced = msg.trace.then_some(msg);
+                vec![packet]
+            }
+
+            msg = receiver.next() => {
+                last_seen = Instant::now();
+                match msg {
+                    Some(Ok(WsMessage::Text(text))) => {
+                        metrics::record_message(text.len(), "inbound", Transport::SocketIo);
+                        handle.record_bytes_in(text.len());
+                        match session.handle_engine_packet(&text).await {
+                            Some(replies) => replies,
+                            None => break,
+                        }
+                    }
+                    Some(Ok(WsMessage::Close(_))) | None => break,
+                    Some(Err(e)) => {
+                        warn!(connection = %sid, error = %e, "Socket.IO error");
+                        break;
+                    }
+                    Some(Ok(_)) => continue,
+                }
+            }
+        };
+
+        for reply in replies {
+            metrics::record_message(reply.len(), "outbound", Transport::SocketIo);
+            handle.record_bytes_out(reply.len());
+            if sender.send(WsMessage::Text(reply)).await.is_err() {
+                break 'connection;
+            }
+        }
+        if let Some(created_at) = delivering {
+            metrics::record_delivery_latency(
+                created_at.elapsed().as_secs_f64(),
+                Transport::SocketIo,
+            );
+        }
+        if let Some(msg) = traced {
+            state.tracer.written(&msg, &sid);
+        }
+    }
+
+    state.router.disconnect(&handle);
+    state.usage.disconnect(&handle, SystemTime::now());
+    state.usage_reports.disconnect(&handle);
+    metrics::record_connection_lag(handle.max_queued(), Transport::SocketIo);
+    state.audit.record(AuditEvent::for_connection(
+        AuditKind::Disconnect,
+        &handle,
+        "socket.io",
+    ));
+    debug!(connection = %sid, "Socket.IO disconnected");
+}
+
+/// A Socket.IO client's state.
+struct Session<'a> {
+    sid: &'a str,
+    state: &'a AppState,
+    handle: &'a Arc<ConnectionHandle>,
+    /// Whether the client has connected to the default namespace.
+    connected: bool,
+    /// Limits subscription churn.
+    subscriptions: SubscriptionLimiter,
+}
+
+impl Session<'_> {
+    /// Handle an Engine.IO packet, returning the packets to send back, or
+    /// `None` to close the connection.
+    async fn handle_engine_packet(&mut self, text: &str) -> Option<Vec<String>> {
+        match text.as_bytes().first() {
+            // Close
+            Some(b'1') => None,
+            // Ping (clients of older servers), pong, noop
+            Some(b'2') => {
+                self.state.router.touch_presence(self.sid);
+                Some(vec!["3".to_string()])
+            }
+            Some(b'3') => {
+                self.state.router.touch_presence(self.sid);
+                Some(Vec::new())
+            }
+            Some(b'6') => Some(Vec::new()),
+            // Message: a Socket.IO packet
+            Some(b'4') => match Packet::parse(&text[1..]) {
+                Some(packet) => self.handle_packet(packet).await,
+                None => {
+                    warn!(connection = %self.sid, "Invalid Socket.IO packet");
+                    metrics::record_error("invalid_frame", Transport::SocketIo);
+                    Some(Vec::new())
+                }
+            },
+            _ => {
+                warn!(connection = %self.sid, "Unsupported Engine.IO packet");
+                Some(Vec::new())
+            }
+        }
+    }
+
+    async fn handle_packet(&mut self, packet: Packet) -> Option<Vec<String>> {
+        if packet.namespace != "/" {
+            let error = Packet::new(
+                PacketKind::ConnectError,
+                Some(json!({ "message": "Invalid namespace" })),
+            )
+            .with_namespace(packet.namespace);
+            return Some(vec![error.encode()]);
+        }
+
+        match packet.kind {
+            PacketKind::Connect => {
+                self.connected = true;
+                let reply = Packet::new(PacketKind::Connect, Some(json!({ "sid": self.sid })));
+                Some(vec![reply.encode()])
+            }
+            PacketKind::Disconnect => None,
+            PacketKind::Event if self.connected => {
+                let args = match packet.data {
+                    Some(Value::Array(args)) => args,
+                    _ => Vec::new(),
+                };
+                let result = self.handle_event(&args).await;
+                if let Err((code, message)) = &result {
+                    debug!(connection = %self.sid, code, message = %message, "Socket.IO event failed");
+                }
+                let reply = packet.ack.map(|id| {
+                    let error = match result {
+                        Ok(()) => Value::Null,
+                        Err((code, message)) => json!({ "code": code, "message": message }),
+                    };
+                    Packet::new(PacketKind::Ack, Some(json!([error])))
+                        .with_ack(id)
+                        .encode()
+                });
+                Some(reply.into_iter().collect())
+            }
+            PacketKind::BinaryEvent | PacketKind::BinaryAck => {
+                warn!(connection = %self.sid, "Socket.IO binary attachments are not supported");
+                Some(Vec::new())
+            }
+            _ => Some(Vec::new()),
+        }
+    }
+
+    /// Map an event onto the router.
+    async fn handle_event(&mut self, args: &[Value]) -> Result<(), (u16, String)> {
+        let invalid = |message: &str| (error_codes::INVALID_FRAME, message.to_string());
+        let router_error = |e: RouterError| (handlers::error_code(&e), e.to_string());
+        let too_many_channels = || {
+            (
+                error_codes::RATE_LIMITED,
+                "Too many new channels".to_string(),
+            )
+        };
+
+        let name = args.first().and_then(Value::as_str).unwrap_or_default();
+        let channel = args.get(1).and_then(Value::as_str);
+        if matches!(name, "subscribe" | "unsubscribe") {
+            let verdict = self.subscriptions.check(std::time::Instant::now());
+            if verdict != Verdict::Allow {
+                metrics::record_error("subscription_rate_limited", Transport::SocketIo);
+                let message = "Too many subscription changes";
+                if verdict == Verdict::Disconnect {
+                    warn!(connection = %self.sid, "Disconnecting client for subscription churn");
+                    self.handle
+                        .close_with(CloseReason::new(DisconnectReason::RateLimited, message));
+                }
+                return Err((error_codes::RATE_LIMITED, message.to_string()));
+            }
+        }
+        match (name, channel) {
+            ("subscribe", Some(channel)) => {
+                if !self
+                    .state
+                    .allow_channel_creation(self.handle, channel, false)
+                {
+                    metrics::record_error("channel_creation_rate_limited", Transport::SocketIo);
+                    return Err(too_many_channels());
+                }
+                let auth = args.get(2).and_then(Value::as_str);
+                let result = self
+                    .state
+                    .router
+                    .subscribe_handle(self.handle, channel, auth);
+                if let Err(e @ RouterError::Unauthorized(_)) = &result {
+                    self.state.audit.record(AuditEvent::for_connection(
+                        AuditKind::AuthFailure,
+                        self.handle,
+                        format!("Subscribe to {channel}: {e}"),
+                    ));
+                }
+                result.map_err(router_error)?;
+                metrics::record_subscription();
+                Ok(())
+            }
+            ("unsubscribe", Some(channel)) => self
+                .state
+                .router
+                .unsubscribe(self.sid, channel)
+                .map_err(router_error),
+            ("publish", Some(channel)) if channel.starts_with('$') => Err((
+                error_codes::FORBIDDEN,
+                "Channels starting with '$' are reserved".to_string(),
+            )),
+            ("publish", Some(channel)) => {
+                let data = args.get(2).unwrap_or(&Value::Null);
+                let payload = serde_json::to_vec(data).unwrap_or_default();
+                self.state
+                    .check_payload_size(channel, payload.len(), Transport::SocketIo)?;
+                metrics::record_payload_size(payload.len(), "inbound");
+
+                let mut message = Message::new(channel, payload).with_source(self.sid);
+                if let Some(event) = args.get(3).and_then(Value::as_str) {
+                    message = message.with_event(event);
+                }
+                let message = self.state.shedder.deprioritize(message);
+                if let Err(reason) = self.state.signing_keys.check(&message) {
+                    metrics::record_error("invalid_signature", Transport::SocketIo);
+                    return Err((error_codes::UNAUTHORIZED, reason));
+                }
+                if let Err(reason) = self.state.schemas.check(&message) {
+                    metrics::record_error("schema_violation", Transport::SocketIo);
+                    return Err((error_codes::INVALID_PAYLOAD, reason));
+                }
+                self.state
+                    .moderate(&message, self.handle)
+                    .await
+                    .map_err(|rejection| (error_codes::REJECTED, rejection.reason))?;
+                if !self
+                    .state
+                    .allow_channel_creation(self.handle, channel, true)
+                {
+                    metrics::record_error("channel_creation_rate_limited", Transport::SocketIo);
+                    return Err(too_many_channels());
+                }
+                let payload_len = message.payload.len();
+                let receipt = self
+                    .state
+                    .router
+                    .publish_from(self.sid, message)
+                    .map_err(|e| {
+                        metrics::record_error("unauthorized_publish", Transport::SocketIo);
+                        router_error(e)
+                    })?;
+                metrics::record_message(payload_len, "broadcast", Transport::SocketIo);
+                metrics::record_fanout(receipt.recipients);
+                self.state.stats.record_published();
+                self.handle.record_messages_in(1);
+                Ok(())
+            }
+            ("subscribe" | "unsubscribe" | "publish", None) => Err(invalid("Missing channel")),
+            _ => Err(invalid("Unknown event")),
+        }
+    }
+}
+
+/// Build the event packet delivering a routed message.
+fn delivery_packet(msg: &Message) -> Packet {
+    // Payloads published as JSON arrive as JSON; anything else as a string
+    let data = serde_json::from_slice(&msg.payload)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&msg.payload).into_owned()));
+
+    let args = match msg.kind {
+        MessageKind::Publish => {
+            let event = msg.event.as_deref().unwrap_or("message");
+            json!([
+                event,
+                data,
+                { "channel": &*msg.channel, "seq": msg.seq, "id": msg.id, "timestamp": msg.timestamp }
+            ])
+        }
+        MessageKind::Presence(action) => {
+            json!(["presence", { "channel": &*msg.channel, "action": action, "data": data }])
+        }
+        MessageKind::ReplayEnd {
+            replayed,
+            truncated,
+        } => json!([
+            "replay_end",
+            { "channel": &*msg.channel, "seq": msg.seq, "replayed": replayed, "truncated": truncated }
+        ]),
+    };
+    Packet::new(PacketKind::Event, Some(args))
+}
+
+/// Socket.IO packet types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PacketKind {
+    Connect,
+    Disconnect,
+    Event,
+    Ack,
+    ConnectError,
+    BinaryEvent,
+    BinaryAck,
+}
+
+impl PacketKind {
+    fn from_digit(digit: u8) -> Option<Self> {
+        Some(match digit {
+            b'0' => Self::Connect,
+            b'1' => Self::Disconnect,
+            b'2' => Self::Event,
+            b'3' => Self::Ack,
+            b'4' => Self::ConnectError,
+            b'5' => Self::BinaryEvent,
+            b'6' => Self::BinaryAck,
+            _ => return None,
+        })
+    }
+
+    fn digit(self) -> char {
+        match self {
+            Self::Connect => '0',
+            Self::Disconnect => '1',
+            Self::Event => '2',
+            Self::Ack => '3',
+            Self::ConnectError => '4',
+            Self::BinaryEvent => '5',
+            Self::BinaryAck => '6',
+        }
+    }
+}
+
+/// A Socket.IO packet, carried in an Engine.IO message packet.
+#[derive(Debug, Clone, PartialEq)]
+struct Packet {
+    kind: PacketKind,
+    namespace: String,
+    ack: Option<u64>,
+    data: Option<Value>,
+}
+
+impl Packet {
+    fn new(kind: PacketKind, data: Option<Value>) -> Self {
+        Self {
+            kind,
+            namespace: "/".to_string(),
+            ack: None,
+            data,
+        }
+    }
+
+    fn with_namespace(mut self, namespace: String) -> Self {
+        self.namespace = namespace;
+        self
+    }
+
+    fn with_ack(mut self, ack: u64) -> Self {
+        self.ack = Some(ack);
+        self
+    }
+
+    /// Parse a packet such as `2["publish","room",1]`, `/admin,0` or `312[null]`.
+    fn parse(text: &str) -> Option<Self> {
+        let kind = PacketKind::from_digit(*text.as_bytes().first()?)?;
+        let mut rest = &text[1..];
+
+        // Binary packets carry an attachment count we cannot honor
+        if matches!(kind, PacketKind::BinaryEvent | PacketKind::BinaryAck) {
+            rest = rest.split_once('-').map_or(rest, |(_, rest)| rest);
+        }
+
+        let mut namespace = "/".to_string();
+        if rest.starts_with('/') {
+            let (nsp, tail) = rest.split_once(',').unwrap_or((rest, ""));
+            namespace = nsp.to_string();
+            rest = tail;
+        }
+
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let ack = if digits > 0 {
+            Some(rest[..digits].parse().ok()?)
+        } else {
+            None
+        };
+        rest = &rest[digits..];
+
+        let data = if rest.is_empty() {
+            None
+        } else {
+            Some(serde_json::from_str(rest).ok()?)
+        };
+
+        Some(Self {
+            kind,
+            namespace,
+            ack,
+            data,
+        })
+    }
+
+    /// Encode the packet as an Engine.IO message packet.
+    fn encode(&self) -> String {
+        let mut text = String::from("4");
+        text.push(self.kind.digit());
+        if self.namespace != "/" {
+            text.push_str(&self.namespace);
+            text.push(',');
+        }
+        if let Some(ack) = self.ack {
+            text.push_str(&ack.to_string());
+        }
+        if let Some(data) = &self.data {
+            text.push_str(&data.to_string());
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packet_parse() {
+        let packet = Packet::parse(r#"212["publish","room",{"n":1}]"#).unwrap();
+        assert_eq!(packet.kind, PacketKind::Event);
+        assert_eq!(packet.namespace, "/");
+        assert_eq!(packet.ack, Some(12));
+        assert_eq!(packet.data, Some(json!(["publish", "room", {"n": 1}])));
+
+        let packet = Packet::parse("0").unwrap();
+        assert_eq!(packet, Packet::new(PacketKind::Connect, None));
+
+        let packet = Packet::parse(r#"0/admin,{"token":"x"}"#).unwrap();
+        assert_eq!(packet.namespace, "/admin");
+        assert_eq!(packet.data, Some(json!({"token": "x"})));
+
+        assert!(Packet::parse("9").is_none());
+        assert!(Packet::parse("2[oops").is_none());
+    }
+
+    #[test]
+    fn test_packet_encode() {
+        let ack = Packet::new(PacketKind::Ack, Some(json!([null]))).with_ack(7);
+        assert_eq!(ack.encode(), "437[null]");
+
+        let error = Packet::new(PacketKind::ConnectError, Some(json!({"message": "no"})))
+            .with_namespace("/admin".to_string());
+        assert_eq!(error.encode(), r#"44/admin,{"message":"no"}"#);
+        assert_eq!(Packet::parse(&error.encode()[1..]).unwrap(), error);
+    }
+
+    #[test]
+    fn test_delivery_packet() {
+        let mut msg = Message::new("room", br#"{"text":"hi"}"#.to_vec()).with_event("chat");
+        msg.id = 42;
+        msg.timestamp = 1_700_000_000_000;
+        assert_eq!(
+            delivery_packet(&msg).encode(),
+            r#"42["chat",{"text":"hi"},{"channel":"room","id":42,"seq":0,"timestamp":1700000000000}]"#
+        );
+
+        let mut msg = Message::new("room", b"plain".to_vec());
+        msg.id = 43;
+        msg.timestamp = 1_700_000_000_000;
+        assert_eq!(
+            delivery_packet(&msg).encode(),
+            r#"42["message","plain",{"channel":"room","id":43,"seq":0,"timestamp":1700000000000}]"#
+        );
+    }
+}
@@ -0,0 +1,356 @@
+//! Warm standby and failover.
+//!
+//! A server with `standby.primary` set is a warm standby. It connects to the
+//! primary as a federation peer and:
+//!
+//! - mirrors every publish, keeping message IDs so clients resuming with
+//!   `since_id` after failing over get what they missed;
+//! - receives the primary's channels on [`STATE_CHANNEL`] every
+//!   `sync_interval_ms` and creates them, so their history fills up before
+//!   any client arrives:
+//!
+//! ```json
+//! {"channels": [{"channel": "chat:lobby", "subscribers": 120, "presence": 87}]}
+//! ```
+//!
+//! Until it takes over, the standby reports unready and refuses clients. It
+//! takes over when the primary disconnects it for shutdown, or once the
+//! primary has been unreachable for `failover_after_ms`, and stops following
+//! it from then on. Presence members are not carried over; they rejoin as
+//! their clients reconnect.
+//!
+//! A primary with `standby.alternate_host` set names that host in the
+//! Disconnect frames it sends on shutdown and drain, so clients go straight
+//! to the standby instead of retrying the primary's address.
+
+use crate::config::StandbyConfig;
+use crate::federation::{self, DEFAULT_PING_INTERVAL};
+use crate::handlers::AppState;
+use crate::metrics;
+use anyhow::{anyhow, Result};
+use bytes::BytesMut;
+use futures_util::{SinkExt, StreamExt};
+use pulse_protocol::{
+    codec, Capabilities, DisconnectReason, Frame, SubscribeOptions, PROTOCOL_VERSION,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tenvis_pulse_core::{ChannelKind, Message, Router, SYSTEM_CHANNEL_PREFIX};
+use tokio::time::{sleep_until, Instant};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tracing::{debug, info, warn};
+
+/// Channel a primary publishes its state to.
+pub const STATE_CHANNEL: &str = "$system:standby";
+
+/// Channels per state message.
+const STATE_CHUNK: usize = 500;
+
+/// Source of publishes mirrored from the primary.
+const LINK_ID: &str = "standby:primary";
+
+/// A channel as seen on the primary.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelState {
+    pub channel: String,
+    pub subscribers: usize,
+    /// Presence members (0 on channels without presence).
+    pub presence: usize,
+}
+
+/// Body of a state message.
+#[derive(Debug, Deserialize)]
+struct StateMessage {
+    channels: Vec<ChannelState>,
+}
+
+/// Whether this server is a standby waiting to take over.
+#[derive(Debug)]
+pub struct Standby {
+    waiting: AtomicBool,
+}
+
+impl Standby {
+    /// Create the state for a server, waiting if it is configured as a
+    /// standby.
+    #[must_use]
+    pub fn new(config: &StandbyConfig) -> Self {
+        Self {
+            waiting: AtomicBool::new(config.enabled()),
+        }
+    }
+
+    /// Whether this server is a standby that has not taken over yet.
+    #[must_use]
+    pub fn is_waiting(&self) -> bool {
+        self.waiting.load(Ordering::Relaxed)
+    }
+
+    /// Take over from the primary, returning `false` if already active.
+    pub fn promote(&self) -> bool {
+        self.waiting.swap(false, Ordering::Relaxed)
+    }
+}
+
+/// Follow the primary as a standby, or send state to standbys as a primary
+/// that accepts federation links.
+pub fn spawn(state: &Arc<AppState>) {
+    if let Some(primary) = state.config.standby.primary.clone() {
+        info!(primary = %primary, "Running as a warm standby");
+        tokio::spawn(follow(state.clone(), primary));
+    } else if state.config.federation.credential.is_some() {
+        tokio::spawn(publish_state(state.clone()));
+    }
+}
+
+/// Publish the channels to the state channel every interval.
+async fn publish_state(state: Arc<AppState>) {
+    let period = Duration::from_millis(state.config.standby.sync_interval_ms.max(100));
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+
+        // No standby is following
+        if !state.router.channel_exists(STATE_CHANNEL) {
+            continue;
+        }
+
+        for chunk in channel_states(&state.router).chunks(STATE_CHUNK) {
+            let body = json!({ "channels": chunk }).to_string();
+            let message = Message::new(STATE_CHANNEL, body.into_bytes())
+                .with_event("state")
+                .with_source("standby");
+            state.router.publish(message);
+        }
+    }
+}
+
+/// Describe every channel other than system channels.
+fn channel_states(router: &Router) -> Vec<ChannelState> {
+    router
+        .channel_stats()
+        .into_iter()
+        .filter(|stats| !stats.channel.starts_with(SYSTEM_CHANNEL_PREFIX))
+        .map(|stats| {
+            let presence = if ChannelKind::from_name(&stats.channel).tracks_presence() {
+                router.presence_snapshot(&stats.channel).len()
+            } else {
+                0
+            };
+            ChannelState {
+                channel: stats.channel.to_string(),
+                subscribers: stats.subscribers,
+                presence,
+            }
+        })
+        .collect()
+}
+
+/// Create the primary's channels, returning how many were missing.
+fn apply_state(router: &Router, channels: &[ChannelState]) -> usize {
+    channels
+        .iter()
+        .filter(|c| !c.channel.starts_with('$'))
+        .filter(|c| match router.create_channel(&c.channel) {
+            Ok(created) => created,
+            Err(e) => {
+                debug!(channel = %c.channel, error = %e, "Failed to create channel from primary state");
+                false
+            }
+        })
+        .count()
+}
+
+/// Follow the primary until taking over from it.
+async fn follow(state: Arc<AppState>, primary: String) {
+    let config = &state.config.standby;
+    let failover_after = Duration::from_millis(config.failover_after_ms);
+    let reconnect = Duration::from_millis(config.reconnect_ms);
+    let mut last_seen = Instant::now();
+    loop {
+        match mirror(&state, &primary, &mut last_seen).await {
+            Ok(true) => {
+                info!(primary = %primary, "Primary is shutting down");
+                break;
+            }
+            Ok(false) => info!(primary = %primary, "Primary closed the standby link"),
+            Err(e) => warn!(primary = %primary, error = %e, "Standby link failed"),
+        }
+        let deadline = last_seen + failover_after;
+        if Instant::now() >= deadline {
+            warn!(
+                primary = %primary,
+                failover_after_ms = failover_after.as_millis(),
+                "Primary unreachable"
+            );
+            break;
+        }
+        sleep_until((Instant::now() + reconnect).min(deadline)).await;
+    }
+
+    if state.standby.promote() {
+        warn!(
+            channels = state.router.channel_stats().len(),
+            "Taking over from the primary"
+        );
+    }
+}
+
+/// Mirror the primary until the link drops, updating `last_seen` whenever
+/// the primary is heard from.
+///
+/// Returns whether the primary disconnected the link because it is shutting
+/// down.
+async fn mirror(state: &AppState, primary: &str, last_seen: &mut Instant) -> Result<bool> {
+    let config = &state.config.standby;
+    let failover_after = Duration::from_millis(config.failover_after_ms);
+    let (socket, _) = tokio::time::timeout_at(
+        *last_seen + failover_after,
+        tokio_tungstenite::connect_async(primary),
+    )
+    .await
+    .map_err(|_| anyhow!("timed out connecting"))??;
+    let (mut sink, mut stream) = socket.split();
+
+    let connect = Frame::Connect {
+        version: PROTOCOL_VERSION.major,
+        token: config.credential.clone(),
+        capabilities: Capabilities::NONE,
+        heartbeat: None,
+        user_id: None,
+    };
+    sink.send(federation::encode(&connect)?).await?;
+    let options = SubscribeOptions {
+        no_presence: true,
+        ..SubscribeOptions::default()
+    };
+    for (id, channel) in [(1, "*"), (2, STATE_CHANNEL)] {
+        let subscribe = Frame::subscribe_with_options(id, channel, options.clone());
+        sink.send(federation::encode(&subscribe)?).await?;
+    }
+    info!(primary = %primary, "Following primary");
+
+    let mut read_buffer = BytesMut::new();
+    let mut ping_interval = DEFAULT_PING_INTERVAL;
+    let mut next_ping = Instant::now() + ping_interval;
+    loop {
+        tokio::select! {
+            _ = sleep_until(next_ping) => {
+                sink.send(federation::encode(&Frame::ping())?).await?;
+                next_ping = Instant::now() + ping_interval;
+            }
+
+            _ = sleep_until(*last_seen + failover_after) => {
+                return Err(anyhow!("primary stopped responding"));
+            }
+
+            msg = stream.next() => {
+                let data = match msg {
+                    Some(Ok(WsMessage::Binary(data))) => data,
+                    Some(Ok(WsMessage::Ping(data))) => {
+                        *last_seen = Instant::now();
+                        sink.send(WsMessage::Pong(data)).await?;
+                        continue;
+                    }
+                    Some(Ok(WsMessage::Close(_))) | None => return Ok(false),
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return Err(e.into()),
+                };
+                *last_seen = Instant::now();
+
+                read_buffer.extend_from_slice(&data);
+                while let Some(frame) = codec::decode_from(&mut read_buffer)? {
+                    match frame {
+                        Frame::Publish { ref channel, ref payload, .. } if channel == STATE_CHANNEL => {
+                            match serde_json::from_slice::<StateMessage>(payload) {
+                                Ok(update) => {
+                                    let created = apply_state(&state.router, &update.channels);
+                                    if created > 0 {
+                                        debug!(created, "Created channels from primary state");
+                                    }
+                                }
+                                Err(e) => warn!(error = %e, "Invalid state from primary"),
+                            }
+                        }
+                        // Stats and the like are the primary's own
+                        Frame::Publish { ref channel, .. } if channel.starts_with('$') => {}
+                        Frame::Publish { message_id, timestamp, .. } => {
+                            let Some(mut message) = federation::inbound_message(frame, LINK_ID) else {
+                                continue;
+                            };
+                            // Keep IDs so clients can resume with since_id after failing over
+                            if let Some(id) = message_id {
+                                message.id = id;
+                            }
+                            if let Some(timestamp) = timestamp {
+                                message.timestamp = timestamp;
+                            }
+                            metrics::record_fanout(state.router.publish(message));
+                        }
+                        Frame::Connected { heartbeat, .. } => {
+                            ping_interval = Duration::from_millis(u64::from(heartbeat / 2).max(1_000));
+                            next_ping = Instant::now() + ping_interval;
+                        }
+                        Frame::Error { id: 1 | 2, code, message } => {
+                            return Err(anyhow!("primary refused subscription ({code}): {message}"));
+                        }
+                        Frame::Disconnect { reason, message, .. } => {
+                            info!(primary = %primary, reason = ?reason, message = %message, "Primary disconnected the standby");
+                            return Ok(reason == DisconnectReason::Shutdown);
+                        }
+                        other => {
+                            debug!(frame_type = ?other.frame_type(), "Ignoring frame from primary");
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_standby_promote() {
+        let standby = Standby::new(&StandbyConfig::default());
+        assert!(!standby.is_waiting());
+        assert!(!standby.promote());
+
+        let standby = Standby::new(&StandbyConfig {
+            primary: Some("ws://pulse-a:8080/ws".to_string()),
+            ..StandbyConfig::default()
+        });
+        assert!(standby.is_waiting());
+        assert!(standby.promote());
+        assert!(!standby.is_waiting());
+    }
+
+    #[test]
+    fn test_channel_state_handoff() {
+        let primary = Router::new();
+        let handle = primary.connect("conn-1");
+        for channel in ["chat:lobby", "presence:room"] {
+            primary.subscribe_handle(&handle, channel, None).unwrap();
+        }
+        // The standby's own subscription is not part of the state
+        primary
+            .subscribe_system(&handle, STATE_CHANNEL, SubscribeOptions::default())
+            .unwrap();
+
+        let mut states = channel_states(&primary);
+        states.sort_by(|a, b| a.channel.cmp(&b.channel));
+        assert_eq!(states.len(), 2);
+        assert_eq!(states[0].channel, "chat:lobby");
+        assert_eq!((states[1].subscribers, states[1].presence), (1, 1));
+
+        let standby = Router::new();
+        assert_eq!(apply_state(&standby, &states), 2);
+        assert_eq!(apply_state(&standby, &states), 0);
+        assert!(standby.channel_exists("presence:room"));
+    }
+}
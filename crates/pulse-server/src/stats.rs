@@ -0,0 +1,484 @@
+//! Server statistics served as JSON at `GET /stats`.
+//!
+//! For dashboards that don't scrape Prometheus: router counts, connections
+//! per transport, uptime, message rates and memory estimates. Rates are
+//! exponentially weighted moving averages over 1, 5 and 15 minutes, like
+//! Unix load averages, sampled every few seconds.
+//!
+//! With `stats_channel.enabled`, a rolling snapshot is also published to
+//! [`STATS_CHANNEL`] so admin UIs can subscribe over the normal protocol:
+//!
+//! ```json
+//! {"timestamp": 1700000000000, "uptime_secs": 3600,
+//!  "connections": {"total": 340, "websocket": 330, "socketio": 10},
+//!  "channels": 12, "subscriptions": 910,
+//!  "messages_per_sec": {"published": 42.0, "delivered": 1210.4},
+//!  "top_channels": [{"channel": "chat:lobby", "subscribers": 200}],
+//!  "hot_channels": {"window_secs": 5.0,
+//!                   "by_messages": [{"channel": "chat:lobby", "rate": 40.2, "error": 0.0}],
+//!                   "by_bytes": [{"channel": "chat:lobby", "rate": 9120.5, "error": 0.0}]}}
+//! ```
+//!
+//! The busiest channels by message and byte rate are estimated by the
+//! router (see [`Router::hot_channels`](tenvis_pulse_core::Router::hot_channels))
+//! over the last sampling window.
+
+use crate::handlers::AppState;
+use crate::metrics;
+use axum::{extract::State, Json};
+use pulse_protocol::pool;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tenvis_pulse_core::{HotChannelReport, MemoryUsage, Message, RouterStats};
+
+/// How often message rates are sampled.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often durable subscriptions are checked for expiry.
+const DURABLE_EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often presence members are checked against their channel's TTL.
+const PRESENCE_EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often channels created on publish are checked for having gone unused.
+const UNUSED_CHANNEL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Channel live statistics are published to.
+pub const STATS_CHANNEL: &str = "$system:stats";
+
+/// How a client is connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    WebSocket,
+    SocketIo,
+}
+
+impl Transport {
+    /// The transport's name, as used in metric labels.
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::WebSocket => "websocket",
+            Self::SocketIo => "socketio",
+        }
+    }
+}
+
+/// Counters behind the stats endpoint.
+#[derive(Debug)]
+pub struct ServerStats {
+    started: Instant,
+    websocket: AtomicUsize,
+    socketio: AtomicUsize,
+    published: AtomicU64,
+    delivered: AtomicU64,
+    rates: Mutex<Rates>,
+}
+
+impl ServerStats {
+    /// Create statistics starting now.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            websocket: AtomicUsize::new(0),
+            socketio: AtomicUsize::new(0),
+            published: AtomicU64::new(0),
+            delivered: AtomicU64::new(0),
+            rates: Mutex::new(Rates::default()),
+        }
+    }
+
+    /// Count a connection on a transport until the guard is dropped.
+    #[must_use]
+    pub fn connect(&self, transport: Transport) -> TransportGuard<'_> {
+        let count = self.transport_count(transport);
+        count.fetch_add(1, Ordering::Relaxed);
+        TransportGuard { count }
+    }
+
+    /// Clients connected over every transport.
+    #[must_use]
+    pub fn connections(&self) -> usize {
+        self.websocket.load(Ordering::Relaxed) + self.socketio.load(Ordering::Relaxed)
+    }
+
+    /// Count a message published by a client.
+    pub fn record_published(&self) {
+        self.published.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a message delivered to a client.
+    pub fn record_delivered(&self) {
+        self.delivered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Messages published and delivered since the server started.
+    #[must_use]
+    pub fn totals(&self) -> (u64, u64) {
+        (
+            self.published.load(Ordering::Relaxed),
+            self.delivered.load(Ordering::Relaxed),
+        )
+    }
+
+    /// How long the server has been running.
+    #[must_use]
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Fold the counters into the moving averages.
+    pub fn sample(&self, elapsed: Duration) {
+        let (published, delivered) = self.totals();
+        let mut rates = self.lock();
+        rates.published.sample(published, elapsed);
+        rates.delivered.sample(delivered, elapsed);
+    }
+
+    fn transport_count(&self, transport: Transport) -> &AtomicUsize {
+        match transport {
+            Transport::WebSocket => &self.websocket,
+            Transport::SocketIo => &self.socketio,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Rates> {
+        self.rates.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for ServerStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Keeps a connection counted against its transport.
+#[derive(Debug)]
+pub struct TransportGuard<'a> {
+    count: &'a AtomicUsize,
+}
+
+impl Drop for TransportGuard<'_> {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Moving averages of published and delivered messages.
+#[derive(Debug, Default)]
+struct Rates {
+    published: MovingRate,
+    delivered: MovingRate,
+}
+
+/// Messages per second averaged over 1, 5 and 15 minutes.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+struct MovingRate {
+    #[serde(rename = "1m")]
+    one: f64,
+    #[serde(rename = "5m")]
+    five: f64,
+    #[serde(rename = "15m")]
+    fifteen: f64,
+    #[serde(skip)]
+    last_total: Option<u64>,
+}
+
+impl MovingRate {
+    /// Add a sample of a monotonic counter taken `elapsed` after the last.
+    fn sample(&mut self, total: u64, elapsed: Duration) {
+        let Some(last) = self.last_total.replace(total) else {
+            return;
+        };
+        let secs = elapsed.as_secs_f64();
+        if secs <= 0.0 {
+            return;
+        }
+        let rate = total.saturating_sub(last) as f64 / secs;
+        let decay = |window: f64| (-secs / window).exp();
+        self.one = ewma(self.one, rate, decay(60.0));
+        self.five = ewma(self.five, rate, decay(300.0));
+        self.fifteen = ewma(self.fifteen, rate, decay(900.0));
+    }
+}
+
+fn ewma(average: f64, sample: f64, decay: f64) -> f64 {
+    average * decay + sample * (1.0 - decay)
+}
+
+/// Sample message rates and the busiest channels, measure router memory
+/// (evicting history over `memory.max_bytes`), and publish to the stats
+/// channel if enabled, until the server exits.
+pub fn spawn(state: &Arc<AppState>) {
+    let sampled = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+        let mut last = Instant::now();
+        loop {
+            interval.tick().await;
+            sampled.stats.sample(last.elapsed());
+            sampled.router.rotate_hot_channels();
+            last = Instant::now();
+        }
+    });
+
+    tokio::spawn(track_memory(state.clone()));
+
+    if state.config.durable.max_subscriptions > 0 {
+        tokio::spawn(expire_durables(state.clone()));
+    }
+
+    if state
+        .config
+        .channels
+        .iter()
+        .any(|r| r.presence_ttl_ms.is_some())
+    {
+        tokio::spawn(expire_presence(state.clone()));
+    }
+
+    if state.config.channel_lifecycle.create_on_publish {
+        tokio::spawn(expire_unused_channels(state.clone()));
+    }
+
+    if state.config.stats_channel.enabled {
+        tokio::spawn(publish_snapshots(state.clone()));
+    }
+}
+
+/// Measure router memory and enforce its cap every interval.
+async fn track_memory(state: Arc<AppState>) {
+    let period = Duration::from_millis(state.config.memory.check_interval_ms.max(1));
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let (usage, evicted) = state.router.enforce_memory_limit();
+        metrics::record_router_memory(&usage, evicted);
+        state.shedder.record_memory(usage.total());
+    }
+}
+
+/// Delete durable subscriptions whose consumer stayed away too long.
+async fn expire_durables(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(DURABLE_EXPIRY_INTERVAL);
+    loop {
+        interval.tick().await;
+        let expired = state.router.expire_durables();
+        metrics::record_durables(state.router.durable_count(), expired);
+    }
+}
+
+/// Remove presence members that stopped refreshing.
+async fn expire_presence(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(PRESENCE_EXPIRY_INTERVAL);
+    loop {
+        interval.tick().await;
+        state.router.expire_presence();
+    }
+}
+
+/// Delete channels created on publish that nobody has used.
+async fn expire_unused_channels(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(UNUSED_CHANNEL_INTERVAL);
+    loop {
+        interval.tick().await;
+        state.router.expire_unused_channels();
+    }
+}
+
+/// Publish a snapshot to the stats channel every interval.
+async fn publish_snapshots(state: Arc<AppState>) {
+    let config = &state.config.stats_channel;
+    let mut interval = tokio::time::interval(Duration::from_millis(config.interval_ms.max(100)));
+    let mut last = (Instant::now(), state.stats.totals());
+    loop {
+        interval.tick().await;
+        let now = (Instant::now(), state.stats.totals());
+        let rates = per_second(last.1, now.1, now.0.duration_since(last.0));
+        last = now;
+
+        // Nobody is watching
+        if !state.router.channel_exists(STATS_CHANNEL) {
+            continue;
+        }
+
+        let snapshot = snapshot(&state, rates, config.top_channels);
+        let message = Message::new(STATS_CHANNEL, snapshot.to_string().into_bytes())
+            .with_event("stats")
+            .with_source("stats");
+        state.router.publish(message);
+    }
+}
+
+/// Published and delivered messages per second between two totals.
+fn per_second(then: (u64, u64), now: (u64, u64), elapsed: Duration) -> (f64, f64) {
+    let secs = elapsed.as_secs_f64();
+    if secs <= 0.0 {
+        return (0.0, 0.0);
+    }
+    (
+        now.0.saturating_sub(then.0) as f64 / secs,
+        now.1.saturating_sub(then.1) as f64 / secs,
+    )
+}
+
+/// Build a stats channel message body.
+fn snapshot(state: &AppState, (published, delivered): (f64, f64), top: usize) -> Value {
+    let router = state.router.stats();
+    // One extra in case the stats channel itself is among the busiest
+    let top_channels: Vec<Value> = state
+        .router
+        .top_channels(top + 1)
+        .into_iter()
+        .filter(|(channel, _)| !channel.starts_with('$'))
+        .take(top)
+        .map(|(channel, subscribers)| json!({ "channel": &*channel, "subscribers": subscribers }))
+        .collect();
+    json!({
+        "timestamp": SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64,
+        "uptime_secs": state.stats.uptime().as_secs(),
+        "connections": {
+            "total": router.connection_count,
+            "websocket": state.stats.websocket.load(Ordering::Relaxed),
+            "socketio": state.stats.socketio.load(Ordering::Relaxed),
+        },
+        "channels": router.channel_count,
+        "subscriptions": router.total_subscriptions,
+        "messages_per_sec": {
+            "published": published,
+            "delivered": delivered,
+        },
+        "top_channels": top_channels,
+        "hot_channels": state.router.hot_channels(top),
+    })
+}
+
+/// The `GET /stats` response.
+#[derive(Debug, Serialize)]
+pub struct StatsResponse {
+    uptime_secs: u64,
+    router: RouterStats,
+    connections: ConnectionCounts,
+    message_rates: MessageRates,
+    /// Busiest channels by message and byte rate over the last sample.
+    hot_channels: HotChannelReport,
+    memory: MemoryEstimates,
+}
+
+#[derive(Debug, Serialize)]
+struct ConnectionCounts {
+    websocket: usize,
+    socketio: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct MessageRates {
+    published: MovingRate,
+    delivered: MovingRate,
+}
+
+#[derive(Debug, Serialize)]
+struct MemoryEstimates {
+    /// Resident set size, where the platform reports it.
+    rss_bytes: Option<u64>,
+    /// Messages waiting in connection queues.
+    queued_messages: usize,
+    /// Capacity of the buffers idle in the buffer pool.
+    buffer_pool_bytes: usize,
+    /// Estimated bytes held by channel history, presence and queues.
+    router: MemoryUsage,
+}
+
+impl StatsResponse {
+    /// Collect the current statistics.
+    #[must_use]
+    pub fn collect(state: &AppState) -> Self {
+        let stats = &state.stats;
+        let (published, delivered) = {
+            let rates = stats.lock();
+            (rates.published, rates.delivered)
+        };
+        Self {
+            uptime_secs: stats.uptime().as_secs(),
+            router: state.router.stats(),
+            connections: ConnectionCounts {
+                websocket: stats.websocket.load(Ordering::Relaxed),
+                socketio: stats.socketio.load(Ordering::Relaxed),
+            },
+            message_rates: MessageRates {
+                published,
+                delivered,
+            },
+            hot_channels: state.router.hot_channels(state.config.diagnostics.top),
+            memory: MemoryEstimates {
+                rss_bytes: rss_bytes(),
+                queued_messages: state.router.queued_messages(),
+                buffer_pool_bytes: pool::global().stats().pooled * pool::DEFAULT_BUFFER_CAPACITY,
+                router: state.router.memory_usage(),
+            },
+        }
+    }
+}
+
+/// Serve server statistics.
+pub async fn handler(State(state): State<Arc<AppState>>) -> Json<StatsResponse> {
+    Json(StatsResponse::collect(&state))
+}
+
+/// The process's resident set size, from `/proc` on Linux.
+fn rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_moving_rate() {
+        let mut rate = MovingRate::default();
+        rate.sample(0, Duration::from_secs(5));
+        // A steady 10 msg/s converges on 10 in every window
+        for n in 1..=2_000 {
+            rate.sample(n * 50, Duration::from_secs(5));
+        }
+        assert!((rate.one - 10.0).abs() < 0.01);
+        assert!((rate.fifteen - 10.0).abs() < 0.01);
+
+        // The 1 minute average reacts faster when traffic stops
+        for _ in 0..12 {
+            rate.sample(2_000 * 50, Duration::from_secs(5));
+        }
+        assert!(rate.one < 4.0);
+        assert!(rate.five > 8.0);
+    }
+
+    #[test]
+    fn test_per_second() {
+        let rates = per_second((100, 1_000), (150, 3_000), Duration::from_secs(5));
+        assert_eq!(rates, (10.0, 400.0));
+        assert_eq!(per_second((0, 0), (5, 5), Duration::ZERO), (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_transport_guard() {
+        let stats = ServerStats::new();
+        let guard = stats.connect(Transport::SocketIo);
+        assert_eq!(stats.socketio.load(Ordering::Relaxed), 1);
+        drop(guard);
+        assert_eq!(stats.socketio.load(Ordering::Relaxed), 0);
+        assert_eq!(stats.websocket.load(Ordering::Relaxed), 0);
+    }
+}
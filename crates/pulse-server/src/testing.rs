@@ -0,0 +1,279 @@
+//! End-to-end test support.
+//!
+//! [`TestServer`] runs the full server, routes and background tasks
+//! included, on an ephemeral port inside the test's runtime, and
+//! [`TestClient`] drives a WebSocket connection to it frame by frame, so
+//! features that span the handlers, the router and the writer can be tested
+//! the way clients see them:
+//!
+//! ```rust,no_run
+//! # use pulse_protocol::Frame;
+//! # use pulse::testing::TestServer;
+//! # #[tokio::main]
+//! # async fn main() {
+//! let server = TestServer::start().await;
+//! let [mut alice, mut bob] = server.clients().await;
+//! alice.subscribe("chat:lobby").await;
+//! bob.publish("chat:lobby", "hi").await;
+//! let message = alice.message("chat:lobby").await;
+//! assert!(matches!(message, Frame::Publish { payload, .. } if payload == b"hi"));
+//! # }
+//! ```
+//!
+//! Helpers panic on anything unexpected, and waits time out after
+//! [`TIMEOUT`], so a broken feature fails its test instead of hanging it.
+//!
+//! Other crates get this module with the `test-support` feature, as a
+//! dev-dependency:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! tenvis-pulse-server = { version = "0.1", features = ["test-support"] }
+//! ```
+
+use crate::config::Config;
+use crate::handlers::{spawn_tasks, AppState};
+use crate::routes::Routes;
+use bytes::BytesMut;
+use futures_util::{SinkExt, StreamExt};
+use pulse_protocol::{codec, Frame, PresenceAction, SubscribeOptions};
+use serde_json::Value;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+/// How long helpers wait for a frame.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A server listening on an ephemeral port, stopped when dropped.
+pub struct TestServer {
+    /// The server's state, for inspecting or changing it mid-test.
+    pub state: Arc<AppState>,
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl TestServer {
+    /// Start a server with the default configuration.
+    pub async fn start() -> Self {
+        Self::with_config(Config::default()).await
+    }
+
+    /// Start a server with `config`; its listeners are ignored.
+    pub async fn with_config(config: Config) -> Self {
+        let state = Arc::new(AppState::new(config).expect("invalid test config"));
+        spawn_tasks(&state).await.expect("failed to start tasks");
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Routes::new(state.clone())
+            .into_router()
+            .into_make_service_with_connect_info::<SocketAddr>();
+        let task = tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        Self { state, addr, task }
+    }
+
+    /// WebSocket URL of the server.
+    pub fn url(&self) -> String {
+        let path = &self.state.config.transport.websocket_path;
+        format!("ws://{}{path}", self.addr)
+    }
+
+    /// Connect a client.
+    pub async fn client(&self) -> TestClient {
+        TestClient::connect(&self.url()).await
+    }
+
+    /// Connect `N` clients, in order.
+    pub async fn clients<const N: usize>(&self) -> [TestClient; N] {
+        let mut clients = Vec::with_capacity(N);
+        for _ in 0..N {
+            clients.push(self.client().await);
+        }
+        clients.try_into().unwrap_or_else(|_| unreachable!())
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// A scripted client connection.
+pub struct TestClient {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    buf: BytesMut,
+    /// ID the server assigned to this connection.
+    pub connection_id: String,
+    next_id: u64,
+}
+
+impl TestClient {
+    /// Connect to `url` and read the Connected frame.
+    pub async fn connect(url: &str) -> Self {
+        let (socket, _) = connect_async(url).await.unwrap();
+        let mut client = Self {
+            socket,
+            buf: BytesMut::new(),
+            connection_id: String::new(),
+            next_id: 1,
+        };
+        match client.recv().await {
+            Frame::Connected { connection_id, .. } => client.connection_id = connection_id,
+            frame => panic!("expected Connected, got {frame:?}"),
+        }
+        client
+    }
+
+    /// Get an ID for a request.
+    pub fn next_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Send a frame.
+    pub async fn send(&mut self, frame: &Frame) {
+        let bytes = codec::encode(frame).unwrap();
+        self.socket
+            .send(Message::Binary(bytes.to_vec()))
+            .await
+            .unwrap();
+    }
+
+    /// Receive the next frame, whatever it is.
+    pub async fn recv(&mut self) -> Frame {
+        tokio::time::timeout(TIMEOUT, self.next_frame())
+            .await
+            .expect("timed out waiting for a frame")
+            .expect("server closed the connection")
+    }
+
+    async fn next_frame(&mut self) -> Option<Frame> {
+        loop {
+            if let Some(frame) = codec::decode_from(&mut self.buf).unwrap() {
+                return Some(frame);
+            }
+            match self.socket.next().await?.unwrap() {
+                Message::Binary(data) => self.buf.extend_from_slice(&data),
+                Message::Close(_) => return None,
+                _ => {}
+            }
+        }
+    }
+
+    /// Receive the next WebSocket message and the frames it carries, for
+    /// checking how frames were coalesced.
+    pub async fn recv_message(&mut self) -> Vec<Frame> {
+        assert!(
+            self.buf.is_empty(),
+            "frames of an earlier message are buffered"
+        );
+        let message = tokio::time::timeout(TIMEOUT, self.socket.next())
+            .await
+            .expect("timed out waiting for a message")
+            .expect("server closed the connection")
+            .unwrap();
+        let Message::Binary(data) = message else {
+            panic!("expected a binary message, got {message:?}");
+        };
+        let mut buf = BytesMut::from(&data[..]);
+        let mut frames = Vec::new();
+        while let Some(frame) = codec::decode_from(&mut buf).unwrap() {
+            frames.push(frame);
+        }
+        assert!(buf.is_empty(), "message ended inside a frame");
+        frames
+    }
+
+    /// Wait for the frame `pick` accepts, skipping the others.
+    pub async fn expect<T>(&mut self, mut pick: impl FnMut(&Frame) -> Option<T>) -> T {
+        loop {
+            let frame = self.recv().await;
+            if let Some(value) = pick(&frame) {
+                return value;
+            }
+        }
+    }
+
+    /// Check that no frame arrives for `duration`.
+    pub async fn assert_idle(&mut self, duration: Duration) {
+        if let Ok(frame) = tokio::time::timeout(duration, self.next_frame()).await {
+            panic!("expected no frames, got {frame:?}");
+        }
+    }
+
+    /// Subscribe with `options` and return the SubscribeOk frame.
+    pub async fn subscribe_with(&mut self, channel: &str, options: SubscribeOptions) -> Frame {
+        let id = self.next_id();
+        self.send(&Frame::subscribe_with_options(id, channel, options))
+            .await;
+        self.reply(id).await
+    }
+
+    /// Subscribe and return the SubscribeOk frame.
+    pub async fn subscribe(&mut self, channel: &str) -> Frame {
+        self.subscribe_with(channel, SubscribeOptions::default())
+            .await
+    }
+
+    /// Publish and return the PublishOk frame.
+    pub async fn publish(&mut self, channel: &str, payload: impl Into<Vec<u8>>) -> Frame {
+        let id = self.next_id();
+        self.send(&Frame::publish_with_ack(id, channel, payload))
+            .await;
+        self.reply(id).await
+    }
+
+    /// Wait for the reply to request `id`, panicking on an Error frame for
+    /// it.
+    pub async fn reply(&mut self, id: u64) -> Frame {
+        self.expect(|frame| match frame {
+            Frame::Error { id: got, .. } if *got == id => panic!("request {id} failed: {frame:?}"),
+            Frame::SubscribeOk { id: got, .. }
+            | Frame::PublishOk { id: got, .. }
+            | Frame::Ack { id: got }
+                if *got == id =>
+            {
+                Some(frame.clone())
+            }
+            _ => None,
+        })
+        .await
+    }
+
+    /// Wait for a message published to `channel`.
+    pub async fn message(&mut self, channel: &str) -> Frame {
+        self.expect(|frame| match frame {
+            Frame::Publish { channel: got, .. } if got == channel => Some(frame.clone()),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Wait for a presence frame with `action` on `channel` and return its
+    /// data.
+    pub async fn presence(&mut self, channel: &str, action: PresenceAction) -> Value {
+        self.expect(|frame| match frame {
+            Frame::Presence {
+                channel: got,
+                action: a,
+                data,
+                ..
+            } if got == channel && *a == action => Some(data.clone().unwrap_or(Value::Null)),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Close the connection.
+    pub async fn close(mut self) {
+        let _ = self.socket.close(None).await;
+    }
+}
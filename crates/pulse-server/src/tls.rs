@@ -0,0 +1,128 @@
+//! TLS termination for the main listener.
+//!
+//! With `tls.cert` and `tls.key` set, HTTP and WebSocket clients connect over
+//! HTTPS/WSS directly. Both files are PEM. They are checked for changes
+//! every `tls.reload_interval_ms`, and a renewed certificate is used for new
+//! connections without a restart; if the new files fail to load, the old
+//! certificate stays in place.
+
+use crate::config::{TlsConfig, TlsVersion};
+use anyhow::{anyhow, Context, Result};
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::crypto::aws_lc_rs;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{ConfigBuilder, ServerConfig, WantsVerifier};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+/// Load the configured certificate, or `None` when TLS is off.
+///
+/// # Errors
+///
+/// Returns an error if the certificate or key cannot be read or used.
+pub fn load(config: &TlsConfig) -> Result<Option<RustlsConfig>> {
+    let (Some(cert), Some(key)) = (&config.cert, &config.key) else {
+        return Ok(None);
+    };
+    let server_config = server_config(config, cert, key)?;
+    Ok(Some(RustlsConfig::from_config(server_config)))
+}
+
+/// Build the rustls server configuration.
+fn server_config(config: &TlsConfig, cert: &Path, key: &Path) -> Result<Arc<ServerConfig>> {
+    let chain = CertificateDer::pem_file_iter(cert)
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .with_context(|| format!("Failed to read TLS certificate: {}", cert.display()))?;
+    if chain.is_empty() {
+        return Err(anyhow!("No certificates in {}", cert.display()));
+    }
+    let private_key = PrivateKeyDer::from_pem_file(key)
+        .with_context(|| format!("Failed to read TLS key: {}", key.display()))?;
+
+    let mut server_config = builder(config)?
+        .with_no_client_auth()
+        .with_single_cert(chain, private_key)
+        .context("TLS certificate and key do not match")?;
+    server_config.alpn_protocols = alpn_protocols(config);
+    Ok(Arc::new(server_config))
+}
+
+/// Start a rustls server configuration with the allowed protocol versions.
+pub(crate) fn builder(config: &TlsConfig) -> Result<ConfigBuilder<ServerConfig, WantsVerifier>> {
+    let versions: &[&rustls::SupportedProtocolVersion] = match config.min_version {
+        TlsVersion::Tls12 => &[&rustls::version::TLS13, &rustls::version::TLS12],
+        TlsVersion::Tls13 => &[&rustls::version::TLS13],
+    };
+    Ok(
+        ServerConfig::builder_with_provider(Arc::new(aws_lc_rs::default_provider()))
+            .with_protocol_versions(versions)?,
+    )
+}
+
+/// The configured ALPN protocols, in wire format.
+pub(crate) fn alpn_protocols(config: &TlsConfig) -> Vec<Vec<u8>> {
+    config
+        .alpn
+        .iter()
+        .map(|protocol| protocol.as_bytes().to_vec())
+        .collect()
+}
+
+/// Reload the certificate whenever its files change.
+pub fn spawn_reload(config: &TlsConfig, rustls: RustlsConfig) {
+    let (Some(cert), Some(key)) = (config.cert.clone(), config.key.clone()) else {
+        return;
+    };
+    if config.reload_interval_ms == 0 {
+        return;
+    }
+    let config = config.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(config.reload_interval_ms));
+        let mut last = (modified(&cert), modified(&key));
+        loop {
+            interval.tick().await;
+            let current = (modified(&cert), modified(&key));
+            if current == last {
+                continue;
+            }
+            match server_config(&config, &cert, &key) {
+                Ok(server_config) => {
+                    rustls.reload_from_config(server_config);
+                    info!(cert = %cert.display(), "Reloaded TLS certificate");
+                    last = current;
+                }
+                // The files may be mid-rotation; try again next tick
+                Err(e) => warn!(error = %format!("{e:#}"), "Failed to reload TLS certificate"),
+            }
+        }
+    });
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_disabled() {
+        assert!(load(&TlsConfig::default()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_load_missing_files() {
+        let config = TlsConfig {
+            cert: Some("/nonexistent/cert.pem".into()),
+            key: Some("/nonexistent/key.pem".into()),
+            ..TlsConfig::default()
+        };
+        let err = load(&config).unwrap_err();
+        assert!(err.to_string().contains("/nonexistent/cert.pem"));
+    }
+}
@@ -0,0 +1,214 @@
+//! Message tracing.
+//!
+//! Admins set `trace` on a Publish frame, or in a `POST /admin/publish`
+//! body, to follow that message through the server. Each step is published
+//! as JSON to [`TRACE_CHANNEL`], which admin connections can subscribe to:
+//!
+//! ```json
+//! {"stage": "enqueued", "message_id": 8716287246860288, "channel": "chat:lobby",
+//!  "connection": "c-42", "elapsed_us": 85, "timestamp": 1700000000000}
+//! ```
+//!
+//! Stages, in order: `received` once the publish is accepted for checks,
+//! `routed` (with the channel's `subscribers`) or `channel_missing`, then
+//! for each subscriber `enqueued`, `filtered` (by its event filter or
+//! `no_echo`) or `dropped` (its queue was full or closed), and `written`
+//! once the frame is sent on its socket. A publish refused by a check stops
+//! after `received`; a subscriber with `enqueued` but no `written` lost the
+//! message from a full queue or disconnected first.
+//!
+//! Events wait in a bounded queue for the task publishing them; when it is
+//! full they are dropped and counted in `pulse_trace_events_dropped_total`.
+
+use crate::handlers::AppState;
+use crate::metrics;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tenvis_pulse_core::{Message, MessageTracer, TraceEvent};
+use tokio::sync::mpsc;
+
+/// Channel trace events are published to.
+pub const TRACE_CHANNEL: &str = "$system:trace";
+
+/// Trace events waiting to be published, at most.
+const QUEUE_CAPACITY: usize = 65_536;
+
+/// A step in handling a traced message, as published.
+#[derive(Debug, Serialize)]
+struct Record {
+    stage: &'static str,
+    message_id: u64,
+    channel: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connection: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subscribers: Option<usize>,
+    /// Time since the message was created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    elapsed_us: Option<u64>,
+    timestamp: u64,
+}
+
+impl Record {
+    fn new(message: &Message, stage: &'static str) -> Self {
+        Self {
+            stage,
+            message_id: message.id,
+            channel: message.channel.to_string(),
+            connection: None,
+            subscribers: None,
+            elapsed_us: message
+                .created_at
+                .map(|at| u64::try_from(at.elapsed().as_micros()).unwrap_or(u64::MAX)),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+        }
+    }
+
+    fn with_connection(mut self, connection: &str) -> Self {
+        self.connection = Some(connection.to_string());
+        self
+    }
+}
+
+/// Queues the trace events of traced messages for publishing.
+#[derive(Debug)]
+pub struct Tracer {
+    sender: mpsc::Sender<Record>,
+    receiver: Mutex<Option<mpsc::Receiver<Record>>>,
+}
+
+impl Tracer {
+    /// Create an empty trace queue.
+    #[must_use]
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        Self {
+            sender,
+            receiver: Mutex::new(Some(receiver)),
+        }
+    }
+
+    /// Report that a traced publish was received from a client.
+    pub fn received(&self, message: &Message) {
+        if message.trace {
+            self.send(Record::new(message, "received"));
+        }
+    }
+
+    /// Report that a traced message was written to a subscriber's socket.
+    pub fn written(&self, message: &Message, connection: &str) {
+        if message.trace {
+            self.send(Record::new(message, "written").with_connection(connection));
+        }
+    }
+
+    fn send(&self, record: Record) {
+        if self.sender.try_send(record).is_err() {
+            metrics::record_trace_dropped();
+        }
+    }
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MessageTracer for Tracer {
+    fn trace(&self, message: &Message, event: TraceEvent<'_>) {
+        let record = match event {
+            TraceEvent::Routed { subscribers } => Record {
+                subscribers: Some(subscribers),
+                ..Record::new(message, "routed")
+            },
+            TraceEvent::ChannelMissing => Record::new(message, "channel_missing"),
+            TraceEvent::Enqueued { connection } => {
+                Record::new(message, "enqueued").with_connection(connection)
+            }
+            TraceEvent::Filtered { connection } => {
+                Record::new(message, "filtered").with_connection(connection)
+            }
+            TraceEvent::Dropped { connection } => {
+                Record::new(message, "dropped").with_connection(connection)
+            }
+        };
+        self.send(record);
+    }
+}
+
+/// Start publishing trace events.
+pub fn spawn(state: &Arc<AppState>) {
+    let receiver = state
+        .tracer
+        .receiver
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take();
+    if let Some(receiver) = receiver {
+        tokio::spawn(run(state.clone(), receiver));
+    }
+}
+
+/// Publish trace events as they arrive.
+async fn run(state: Arc<AppState>, mut receiver: mpsc::Receiver<Record>) {
+    while let Some(record) = receiver.recv().await {
+        // Nobody is watching
+        if !state.router.channel_exists(TRACE_CHANNEL) {
+            continue;
+        }
+        let Ok(body) = serde_json::to_vec(&record) else {
+            continue;
+        };
+        let message = Message::new(TRACE_CHANNEL, body)
+            .with_event("trace")
+            .with_source("trace");
+        state.router.publish(message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn next(receiver: &mut mpsc::Receiver<Record>) -> Value {
+        serde_json::to_value(receiver.try_recv().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_trace_records() {
+        let tracer = Tracer::new();
+        let mut receiver = tracer.receiver.lock().unwrap().take().unwrap();
+
+        // Untraced messages are not reported
+        let message = Message::new("chat:lobby", "hi");
+        tracer.received(&message);
+        assert!(receiver.try_recv().is_err());
+
+        let message = message.with_trace();
+        tracer.received(&message);
+        tracer.trace(&message, TraceEvent::Routed { subscribers: 2 });
+        tracer.trace(&message, TraceEvent::Dropped { connection: "c-1" });
+        tracer.written(&message, "c-2");
+
+        let received = next(&mut receiver);
+        assert_eq!(received["stage"], "received");
+        assert_eq!(received["message_id"], message.id);
+        assert_eq!(received["channel"], "chat:lobby");
+        assert!(received.get("connection").is_none());
+        let routed = next(&mut receiver);
+        assert_eq!(routed["stage"], "routed");
+        assert_eq!(routed["subscribers"], 2);
+        let dropped = next(&mut receiver);
+        assert_eq!(dropped["stage"], "dropped");
+        assert_eq!(dropped["connection"], "c-1");
+        let written = next(&mut receiver);
+        assert_eq!(written["stage"], "written");
+        assert_eq!(written["connection"], "c-2");
+    }
+}
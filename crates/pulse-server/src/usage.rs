@@ -0,0 +1,326 @@
+//! Bandwidth accounting and quotas.
+//!
+//! Transports count the bytes each connection sends and receives on its
+//! [`ConnectionHandle`]. Every second those counts are added up per user ID
+//! for `GET /admin/usage` and the `[quotas]`: once a user's daily or monthly
+//! total reaches its quota, its connections are closed with a Disconnect
+//! frame (reason `QuotaExceeded`) whose `reconnect_after` is the time left
+//! until the quota resets, and it cannot identify again before then.
+//!
+//! Periods are UTC days and calendar months. Usage is kept in memory, so it
+//! starts over when the server restarts; a user is forgotten once a month
+//! passes without traffic.
+
+use crate::config::QuotaConfig;
+use crate::handlers::AppState;
+use pulse_protocol::DisconnectReason;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tenvis_pulse_core::{CloseReason, ConnId, ConnectionHandle};
+use tracing::info;
+
+/// How often connection byte counts are added to their users.
+const ACCOUNT_INTERVAL: Duration = Duration::from_secs(1);
+
+const SECS_PER_DAY: u64 = 86_400;
+
+/// A user's bandwidth, as listed by `GET /admin/usage`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct UserUsage {
+    pub user_id: String,
+    /// Bytes received from the user's connections since it was first seen.
+    pub bytes_in: u64,
+    /// Bytes sent to the user's connections since it was first seen.
+    pub bytes_out: u64,
+    /// Bytes transferred today.
+    pub today: u64,
+    /// Bytes transferred this month.
+    pub this_month: u64,
+    #[serde(skip)]
+    day: u64,
+    #[serde(skip)]
+    month: u64,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    users: HashMap<String, UserUsage>,
+    /// Bytes in and out of each live connection already added to its user.
+    accounted: HashMap<ConnId, (u64, u64)>,
+}
+
+/// Bandwidth per user, checked against the quotas.
+#[derive(Debug)]
+pub struct Usage {
+    quotas: QuotaConfig,
+    inner: Mutex<Inner>,
+}
+
+impl Usage {
+    /// Create empty accounts.
+    #[must_use]
+    pub fn new(quotas: &QuotaConfig) -> Self {
+        Self {
+            quotas: quotas.clone(),
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    /// Add what a connection transferred since it was last accounted to its
+    /// user.
+    ///
+    /// Returns the reason to close the connection with if its user is over
+    /// quota.
+    pub fn account(&self, handle: &ConnectionHandle, now: SystemTime) -> Option<CloseReason> {
+        let user_id = handle.user_id()?;
+        let (bytes_in, bytes_out) = (handle.bytes_in(), handle.bytes_out());
+        let period = Period::at(now);
+
+        let mut inner = self.lock();
+        let accounted = inner
+            .accounted
+            .insert(handle.conn_id(), (bytes_in, bytes_out))
+            .unwrap_or_default();
+        let added_in = bytes_in.saturating_sub(accounted.0);
+        let added_out = bytes_out.saturating_sub(accounted.1);
+        let usage = inner
+            .users
+            .entry(user_id.to_string())
+            .or_insert_with(|| UserUsage {
+                user_id: user_id.to_string(),
+                ..UserUsage::default()
+            });
+        period.roll(usage);
+        usage.bytes_in += added_in;
+        usage.bytes_out += added_out;
+        usage.today += added_in + added_out;
+        usage.this_month += added_in + added_out;
+        self.exceeded(usage, period)
+    }
+
+    /// Account a closing connection for the last time.
+    pub fn disconnect(&self, handle: &ConnectionHandle, now: SystemTime) {
+        self.account(handle, now);
+        self.lock().accounted.remove(&handle.conn_id());
+    }
+
+    /// Check whether a user may connect, returning the reason to close its
+    /// connection with if it is over quota.
+    #[must_use]
+    pub fn check(&self, user_id: &str, now: SystemTime) -> Option<CloseReason> {
+        let period = Period::at(now);
+        let mut inner = self.lock();
+        let usage = inner.users.get_mut(user_id)?;
+        period.roll(usage);
+        self.exceeded(usage, period)
+    }
+
+    /// Get the users who transferred the most this month, busiest first.
+    #[must_use]
+    pub fn top_users(&self, limit: usize) -> Vec<UserUsage> {
+        let mut users: Vec<_> = self.lock().users.values().cloned().collect();
+        users.sort_unstable_by_key(|usage| std::cmp::Reverse(usage.this_month));
+        users.truncate(limit);
+        users
+    }
+
+    /// Forget users without traffic this month.
+    pub fn prune(&self, now: SystemTime) {
+        let month = Period::at(now).month;
+        self.lock().users.retain(|_, usage| usage.month == month);
+    }
+
+    fn exceeded(&self, usage: &UserUsage, period: Period) -> Option<CloseReason> {
+        let (kind, quota, resets) =
+            if self.quotas.monthly_bytes > 0 && usage.this_month >= self.quotas.monthly_bytes {
+                ("Monthly", self.quotas.monthly_bytes, period.month_ends)
+            } else if self.quotas.daily_bytes > 0 && usage.today >= self.quotas.daily_bytes {
+                ("Daily", self.quotas.daily_bytes, period.day_ends)
+            } else {
+                return None;
+            };
+        Some(
+            CloseReason::new(
+                DisconnectReason::QuotaExceeded,
+                format!("{kind} bandwidth quota of {quota} bytes exceeded"),
+            )
+            .with_reconnect_after(resets),
+        )
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The day and month a moment falls in, and how long until each ends.
+#[derive(Debug, Clone, Copy)]
+struct Period {
+    /// Days since the Unix epoch.
+    day: u64,
+    /// Months since year 0.
+    month: u64,
+    day_ends: Duration,
+    month_ends: Duration,
+}
+
+impl Period {
+    fn at(now: SystemTime) -> Self {
+        let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let day = since_epoch.as_secs() / SECS_PER_DAY;
+        let (year, month, _) = civil_from_days(day);
+        let next_month = if month == 12 {
+            days_from_civil(year + 1, 1)
+        } else {
+            days_from_civil(year, month + 1)
+        };
+        let until = |day: u64| Duration::from_secs(day * SECS_PER_DAY).saturating_sub(since_epoch);
+        Self {
+            day,
+            month: year * 12 + month - 1,
+            day_ends: until(day + 1),
+            month_ends: until(next_month),
+        }
+    }
+
+    /// Start new day and month totals if they have passed.
+    fn roll(self, usage: &mut UserUsage) {
+        if usage.day != self.day {
+            usage.day = self.day;
+            usage.today = 0;
+        }
+        if usage.month != self.month {
+            usage.month = self.month;
+            usage.this_month = 0;
+        }
+    }
+}
+
+/// The year, month and day of a day since the Unix epoch.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    // Howard Hinnant's algorithm, for days on or after 1970-01-01
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+/// The day since the Unix epoch a month starts on.
+fn days_from_civil(year: u64, month: u64) -> u64 {
+    let year = year - u64::from(month <= 2);
+    let era = year / 400;
+    let yoe = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Account connections to their users every second, closing those of users
+/// over quota.
+pub fn spawn(state: &Arc<AppState>) {
+    let state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(ACCOUNT_INTERVAL);
+        let mut month = Period::at(SystemTime::now()).month;
+        loop {
+            interval.tick().await;
+            let now = SystemTime::now();
+            for handle in state.router.handles() {
+                if handle.is_closed() {
+                    continue;
+                }
+                if let Some(reason) = state.usage.account(&handle, now) {
+                    info!(
+                        connection = %handle.id(),
+                        user_id = handle.user_id().unwrap_or_default(),
+                        reason = %reason.message,
+                        "Closing connection over quota"
+                    );
+                    handle.close_with(reason);
+                }
+            }
+            let current = Period::at(now).month;
+            if current != month {
+                month = current;
+                state.usage.prune(now);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tenvis_pulse_core::Router;
+
+    /// 2024-02-29T12:00:00Z
+    fn leap_day() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_709_208_000)
+    }
+
+    #[test]
+    fn test_period() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(days_from_civil(2024, 3), 19_783);
+
+        let period = Period::at(leap_day());
+        assert_eq!(period.day_ends, Duration::from_secs(12 * 3600));
+        assert_eq!(period.month_ends, Duration::from_secs(12 * 3600));
+        let period = Period::at(leap_day() - Duration::from_secs(SECS_PER_DAY));
+        assert_eq!(period.month_ends, Duration::from_secs(36 * 3600));
+    }
+
+    #[test]
+    fn test_usage_quotas() {
+        let usage = Usage::new(&QuotaConfig {
+            daily_bytes: 1000,
+            monthly_bytes: 1500,
+        });
+        let router = Router::new();
+        let handle = router.connect("conn-1");
+        let now = leap_day() - Duration::from_secs(14 * SECS_PER_DAY);
+
+        // Anonymous connections are not accounted
+        handle.record_bytes_in(600);
+        assert!(usage.account(&handle, now).is_none());
+        assert!(usage.top_users(10).is_empty());
+
+        handle.set_user_id("alice");
+        handle.record_bytes_out(300);
+        assert!(usage.account(&handle, now).is_none());
+        assert!(usage.account(&handle, now).is_none());
+        let top = usage.top_users(10);
+        assert_eq!((top[0].bytes_in, top[0].bytes_out), (600, 300));
+        assert_eq!(top[0].today, 900);
+
+        handle.record_bytes_out(100);
+        let reason = usage.account(&handle, now).unwrap();
+        assert_eq!(reason.reason, DisconnectReason::QuotaExceeded);
+        assert!(reason.message.starts_with("Daily"));
+        assert!(usage.check("alice", now).is_some());
+        assert!(usage.check("bob", now).is_none());
+
+        // A new day resets the daily quota, but not the monthly one
+        let tomorrow = now + Duration::from_secs(SECS_PER_DAY);
+        assert!(usage.check("alice", tomorrow).is_none());
+        handle.record_bytes_in(500);
+        let reason = usage.account(&handle, tomorrow).unwrap();
+        assert!(reason.message.starts_with("Monthly"));
+
+        usage.disconnect(&handle, tomorrow);
+        usage.prune(tomorrow);
+        assert_eq!(usage.top_users(10).len(), 1);
+        usage.prune(tomorrow + Duration::from_secs(40 * SECS_PER_DAY));
+        assert!(usage.top_users(10).is_empty());
+    }
+}
@@ -0,0 +1,389 @@
+//! Usage reports for multi-tenant billing.
+//!
+//! With `usage_reports.file`, `usage_reports.url` or `usage_reports.kafka`
+//! set, client connections are sampled every second and their counters added
+//! up per tenant: the part of the connection's user ID before
+//! `tenant_separator`, or `default_tenant`. Every `interval_secs`, one record
+//! per tenant active in the period is appended to the file as a JSON line,
+//! POSTed to the URL as `{"records": [...]}`, signed and retried like sink
+//! batches, and produced to the Kafka topic (with the `kafka` feature):
+//!
+//! ```json
+//! {"tenant": "acme", "node_id": 3, "start_ms": 1700000000000,
+//!  "end_ms": 1700003600000, "peak_connections": 42,
+//!  "messages_published": 1200, "messages_delivered": 53000,
+//!  "bytes_in": 210000, "bytes_out": 9400000}
+//! ```
+//!
+//! Each server reports its own connections, so records from several servers
+//! are summed per tenant and period. Counters are held in memory; a restart
+//! loses the current period.
+
+use crate::config::UsageReportsConfig;
+use crate::handlers::AppState;
+use crate::sinks::{self, Endpoint};
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tenvis_pulse_core::{ConnId, ConnectionHandle};
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, error, info, warn};
+
+/// How often connections are sampled.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Counters a connection's usage is measured by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Counters {
+    messages_in: u64,
+    messages_out: u64,
+    bytes_in: u64,
+    bytes_out: u64,
+}
+
+impl Counters {
+    fn of(handle: &ConnectionHandle) -> Self {
+        Self {
+            messages_in: handle.messages_in(),
+            messages_out: handle.messages_out(),
+            bytes_in: handle.bytes_in(),
+            bytes_out: handle.bytes_out(),
+        }
+    }
+
+    fn add_since(&mut self, now: Self, then: Self) {
+        self.messages_in += now.messages_in.saturating_sub(then.messages_in);
+        self.messages_out += now.messages_out.saturating_sub(then.messages_out);
+        self.bytes_in += now.bytes_in.saturating_sub(then.bytes_in);
+        self.bytes_out += now.bytes_out.saturating_sub(then.bytes_out);
+    }
+}
+
+/// A tenant's usage over one period.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UsageRecord {
+    pub tenant: String,
+    /// The reporting server's `node_id`.
+    pub node_id: u16,
+    /// Start of the period, in milliseconds since the Unix epoch.
+    pub start_ms: u64,
+    /// End of the period, in milliseconds since the Unix epoch.
+    pub end_ms: u64,
+    /// Most connections the tenant had open at once.
+    pub peak_connections: usize,
+    pub messages_published: u64,
+    pub messages_delivered: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+#[derive(Debug, Default)]
+struct Tenant {
+    peak_connections: usize,
+    counters: Counters,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    start_ms: u64,
+    tenants: HashMap<String, Tenant>,
+    /// Each live connection's counters when it was last sampled.
+    sampled: HashMap<ConnId, Counters>,
+}
+
+/// Per-tenant counters for the current period.
+#[derive(Debug)]
+pub struct UsageReports {
+    config: UsageReportsConfig,
+    inner: Mutex<Inner>,
+}
+
+impl UsageReports {
+    /// Start the first period now.
+    #[must_use]
+    pub fn new(config: &UsageReportsConfig) -> Self {
+        Self {
+            config: config.clone(),
+            inner: Mutex::new(Inner {
+                start_ms: now_ms(),
+                ..Inner::default()
+            }),
+        }
+    }
+
+    /// Add what the connections did since the last sample to their tenants.
+    ///
+    /// Only client connections are counted; sinks and outbound federation
+    /// links have no remote IP.
+    pub fn sample(&self, handles: &[Arc<ConnectionHandle>]) {
+        let mut open: HashMap<&str, usize> = HashMap::new();
+        let mut inner = self.lock();
+        for handle in handles {
+            if handle.remote_ip().is_none() {
+                continue;
+            }
+            let tenant = self.config.tenant(handle.user_id());
+            *open.entry(tenant).or_default() += 1;
+            let now = Counters::of(handle);
+            let then = inner
+                .sampled
+                .insert(handle.conn_id(), now)
+                .unwrap_or_default();
+            Self::tenant(&mut inner, tenant)
+                .counters
+                .add_since(now, then);
+        }
+        for (tenant, connections) in open {
+            let tenant = Self::tenant(&mut inner, tenant);
+            tenant.peak_connections = tenant.peak_connections.max(connections);
+        }
+    }
+
+    /// Count a closing connection's last activity.
+    pub fn disconnect(&self, handle: &ConnectionHandle) {
+        if !self.config.enabled() || handle.remote_ip().is_none() {
+            return;
+        }
+        let mut inner = self.lock();
+        let then = inner.sampled.remove(&handle.conn_id()).unwrap_or_default();
+        let tenant = self.config.tenant(handle.user_id());
+        Self::tenant(&mut inner, tenant)
+            .counters
+            .add_since(Counters::of(handle), then);
+    }
+
+    /// End the current period, returning a record for each tenant active in
+    /// it.
+    pub fn take(&self, node_id: u16, end_ms: u64) -> Vec<UsageRecord> {
+        let mut inner = self.lock();
+        let start_ms = std::mem::replace(&mut inner.start_ms, end_ms);
+        let mut records: Vec<_> = inner
+            .tenants
+            .drain()
+            .map(|(tenant, usage)| UsageRecord {
+                tenant,
+                node_id,
+                start_ms,
+                end_ms,
+                peak_connections: usage.peak_connections,
+                messages_published: usage.counters.messages_in,
+                messages_delivered: usage.counters.messages_out,
+                bytes_in: usage.counters.bytes_in,
+                bytes_out: usage.counters.bytes_out,
+            })
+            .collect();
+        records.sort_unstable_by(|a, b| a.tenant.cmp(&b.tenant));
+        records
+    }
+
+    fn tenant<'a>(inner: &'a mut Inner, tenant: &str) -> &'a mut Tenant {
+        if !inner.tenants.contains_key(tenant) {
+            inner.tenants.insert(tenant.to_string(), Tenant::default());
+        }
+        inner
+            .tenants
+            .get_mut(tenant)
+            .expect("tenant was just inserted")
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Where records are emitted.
+struct Destinations {
+    file: Option<PathBuf>,
+    endpoint: Option<Endpoint>,
+    #[cfg(feature = "kafka")]
+    kafka: Option<crate::kafka::Producer>,
+}
+
+/// Start sampling and reporting, if a destination is configured.
+///
+/// # Errors
+///
+/// Returns an error if the URL is invalid, TLS roots cannot be loaded, or
+/// the Kafka producer cannot be created.
+pub fn spawn(state: &Arc<AppState>) -> Result<()> {
+    let config = &state.config.usage_reports;
+    if !config.enabled() {
+        return Ok(());
+    }
+    let endpoint = match &config.url {
+        Some(url) => Some(Endpoint {
+            client: sinks::http_client()?,
+            uri: url
+                .parse()
+                .with_context(|| format!("Invalid usage_reports.url: {url}"))?,
+            secret: config.secret.clone(),
+            timeout: Duration::from_millis(config.timeout_ms),
+            max_retries: config.max_retries,
+            retry_backoff: Duration::from_millis(config.retry_backoff_ms),
+        }),
+        None => None,
+    };
+    let destinations = Arc::new(Destinations {
+        file: config.file.clone(),
+        endpoint,
+        #[cfg(feature = "kafka")]
+        kafka: config
+            .kafka
+            .as_ref()
+            .map(|kafka| {
+                crate::kafka::Producer::new(kafka, Duration::from_millis(config.timeout_ms))
+            })
+            .transpose()?,
+    });
+    info!(
+        interval_secs = config.interval_secs,
+        "Usage reports enabled"
+    );
+    tokio::spawn(run(state.clone(), destinations));
+    Ok(())
+}
+
+/// Sample connections every second and emit records every interval.
+async fn run(state: Arc<AppState>, destinations: Arc<Destinations>) {
+    let config = &state.config.usage_reports;
+    let period = Duration::from_secs(config.interval_secs.max(1));
+    let mut sample = tokio::time::interval(SAMPLE_INTERVAL);
+    let mut report = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    loop {
+        tokio::select! {
+            _ = sample.tick() => state.usage_reports.sample(&state.router.handles()),
+            _ = report.tick() => {
+                // Count up to the end of the period
+                state.usage_reports.sample(&state.router.handles());
+                let records = state.usage_reports.take(state.config.node_id, now_ms());
+                if records.is_empty() {
+                    continue;
+                }
+                debug!(tenants = records.len(), "Emitting usage records");
+                tokio::spawn(emit(destinations.clone(), records));
+            }
+        }
+    }
+}
+
+/// Write records to the file, POST them to the endpoint and produce them to
+/// Kafka.
+async fn emit(destinations: Arc<Destinations>, records: Vec<UsageRecord>) {
+    if let Some(path) = &destinations.file {
+        if let Err(e) = append(path, &records).await {
+            error!(path = %path.display(), error = %e, "Failed to write usage records");
+        }
+    }
+    if let Some(endpoint) = &destinations.endpoint {
+        let body = Bytes::from(json!({ "records": records }).to_string());
+        if let Err(e) = endpoint.send(body).await {
+            warn!(error = %e, records = records.len(), "Failed to deliver usage records");
+        }
+    }
+    #[cfg(feature = "kafka")]
+    if let Some(producer) = &destinations.kafka {
+        let sends = records.iter().filter_map(|record| {
+            let payload = match serde_json::to_vec(record) {
+                Ok(payload) => payload,
+                Err(e) => {
+                    warn!(error = %e, tenant = %record.tenant, "Skipping unencodable usage record");
+                    return None;
+                }
+            };
+            Some(async move { producer.send(&record.tenant, &payload).await })
+        });
+        for result in futures_util::future::join_all(sends).await {
+            if let Err(e) = result {
+                warn!(error = %e, "Failed to produce usage record");
+            }
+        }
+    }
+}
+
+/// Append records to a file, one JSON record per line.
+async fn append(path: &Path, records: &[UsageRecord]) -> std::io::Result<()> {
+    let mut lines = String::new();
+    for record in records {
+        lines.push_str(&serde_json::to_string(record).map_err(std::io::Error::other)?);
+        lines.push('\n');
+    }
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(lines.as_bytes()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tenvis_pulse_core::Router;
+
+    #[test]
+    fn test_usage_reports() {
+        let reports = UsageReports::new(&UsageReportsConfig {
+            file: Some("usage.jsonl".into()),
+            ..UsageReportsConfig::default()
+        });
+        let router = Router::new();
+        let connect = |id: &str, user_id: Option<&str>| {
+            let handle = router.connect(id);
+            handle.set_remote_ip("203.0.113.7".parse().unwrap());
+            if let Some(user_id) = user_id {
+                handle.set_user_id(user_id);
+            }
+            handle
+        };
+        let alice = connect("1", Some("acme:alice"));
+        let bob = connect("2", Some("acme:bob"));
+        let anonymous = connect("3", None);
+        let sink = router.connect("sink:analytics");
+
+        alice.record_messages_in(2);
+        alice.record_bytes_in(100);
+        bob.record_bytes_out(50);
+        sink.record_bytes_in(1000);
+        reports.sample(&router.handles());
+        alice.record_bytes_in(10);
+        reports.sample(&router.handles());
+
+        // Bob leaves between samples; his last bytes still count
+        bob.record_bytes_out(5);
+        reports.disconnect(&bob);
+        router.disconnect(&bob);
+        reports.sample(&router.handles());
+        anonymous.record_bytes_in(7);
+
+        let records = reports.take(3, 2000);
+        assert_eq!(records.len(), 2);
+        let acme = &records[0];
+        assert_eq!(acme.tenant, "acme");
+        assert_eq!(acme.node_id, 3);
+        assert_eq!(acme.end_ms, 2000);
+        assert_eq!(acme.peak_connections, 2);
+        assert_eq!(acme.messages_published, 2);
+        assert_eq!((acme.bytes_in, acme.bytes_out), (110, 55));
+        assert_eq!(records[1].tenant, "default");
+        assert_eq!(records[1].bytes_in, 0);
+
+        // The next period starts from where this one ended
+        reports.sample(&router.handles());
+        let records = reports.take(3, 3000);
+        assert_eq!(records[0].start_ms, 2000);
+        assert_eq!(records[0].bytes_in, 0);
+        assert_eq!(records[1].bytes_in, 7);
+    }
+}
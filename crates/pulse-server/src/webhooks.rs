@@ -0,0 +1,326 @@
+//! Channel occupancy webhooks.
+//!
+//! With `webhooks.url` set, the server tells the application when a channel
+//! gains its first subscriber or loses its last, in the shape apps written
+//! against Pusher's channel existence webhooks expect:
+//!
+//! ```json
+//! {"time_ms": 1700000000000, "events": [
+//!   {"name": "channel_occupied", "channel": "rooms:42"},
+//!   {"name": "channel_vacated", "channel": "rooms:7"}
+//! ]}
+//! ```
+//!
+//! Occupied events are sent at once. Vacated events wait out
+//! `webhooks.quiet_period_ms`, and are dropped if the channel is occupied
+//! again in the meantime, so a client reconnecting produces no events.
+//! Requests are signed and retried like sink batches; events still failing
+//! after the last retry are logged and dropped.
+
+use crate::config::WebhooksConfig;
+use crate::handlers::AppState;
+use crate::metrics;
+use crate::sinks::{self, Endpoint};
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tenvis_pulse_core::{ChannelPattern, OccupancyChange, OccupancyObserver};
+use tokio::sync::mpsc;
+use tokio::time::{sleep_until, Instant};
+use tracing::{debug, info, warn};
+
+type Change = (String, OccupancyChange);
+
+/// Forwards the router's occupancy changes to the webhook task.
+#[derive(Debug)]
+pub struct Webhooks {
+    channels: Vec<ChannelPattern>,
+    sender: Option<mpsc::UnboundedSender<Change>>,
+    receiver: Mutex<Option<mpsc::UnboundedReceiver<Change>>>,
+}
+
+impl Webhooks {
+    /// Create the webhook queue for a configuration.
+    #[must_use]
+    pub fn new(config: &WebhooksConfig) -> Self {
+        let (sender, receiver) = if config.url.is_some() {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            (Some(sender), Some(receiver))
+        } else {
+            (None, None)
+        };
+        Self {
+            channels: config.channels.clone(),
+            sender,
+            receiver: Mutex::new(receiver),
+        }
+    }
+
+    /// Whether webhooks are configured.
+    #[must_use]
+    pub fn enabled(&self) -> bool {
+        self.sender.is_some()
+    }
+
+    fn reports(&self, channel: &str) -> bool {
+        !channel.starts_with('$')
+            && (self.channels.is_empty() || self.channels.iter().any(|p| p.matches(channel)))
+    }
+}
+
+impl OccupancyObserver for Webhooks {
+    fn occupancy_changed(&self, channel: &str, change: OccupancyChange) {
+        if let Some(sender) = &self.sender {
+            if self.reports(channel) {
+                let _ = sender.send((channel.to_string(), change));
+            }
+        }
+    }
+}
+
+/// Start sending occupancy webhooks.
+///
+/// # Errors
+///
+/// Returns an error if the webhook URL is invalid or TLS roots cannot be
+/// loaded.
+pub fn spawn(state: &Arc<AppState>) -> Result<()> {
+    let config = &state.config.webhooks;
+    let Some(url) = &config.url else {
+        return Ok(());
+    };
+    let receiver = state
+        .webhooks
+        .receiver
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take();
+    let Some(receiver) = receiver else {
+        return Ok(());
+    };
+
+    let endpoint = Endpoint {
+        client: sinks::http_client()?,
+        uri: url
+            .parse()
+            .with_context(|| format!("Invalid webhooks.url: {url}"))?,
+        secret: config.secret.clone(),
+        timeout: Duration::from_millis(config.timeout_ms),
+        max_retries: config.max_retries,
+        retry_backoff: Duration::from_millis(config.retry_backoff_ms),
+    };
+    let quiet_period = Duration::from_millis(config.quiet_period_ms);
+    info!(url = %url, "Occupancy webhooks enabled");
+    tokio::spawn(run(endpoint, quiet_period, receiver));
+    Ok(())
+}
+
+/// Debounce changes and deliver the resulting events until the router is
+/// dropped.
+async fn run(
+    endpoint: Endpoint,
+    quiet_period: Duration,
+    mut receiver: mpsc::UnboundedReceiver<Change>,
+) {
+    let mut tracker = Tracker::new(quiet_period);
+    let mut events = Vec::new();
+    loop {
+        let deadline = tracker.next_deadline();
+        tokio::select! {
+            change = receiver.recv() => {
+                let Some((channel, change)) = change else {
+                    return;
+                };
+                events.extend(tracker.record(channel, change, Instant::now()));
+                // Batch whatever else arrived meanwhile
+                while let Ok((channel, change)) = receiver.try_recv() {
+                    events.extend(tracker.record(channel, change, Instant::now()));
+                }
+            }
+            () = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {}
+        }
+        events.extend(tracker.due(Instant::now()));
+        if !events.is_empty() {
+            deliver(&endpoint, &events).await;
+            events.clear();
+        }
+    }
+}
+
+/// POST a batch of events.
+async fn deliver(endpoint: &Endpoint, events: &[Change]) {
+    let time_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let body = Bytes::from(events_body(time_ms, events).to_string());
+    match endpoint.send(body).await {
+        Ok(()) => {
+            debug!(events = events.len(), "Delivered occupancy webhook");
+            metrics::record_webhook_events("delivered", events.len());
+        }
+        Err(e) => {
+            warn!(error = %e, events = events.len(), "Failed to deliver occupancy webhook");
+            metrics::record_webhook_events("failed", events.len());
+        }
+    }
+}
+
+/// Build the JSON request body for a batch of events.
+fn events_body(time_ms: u64, events: &[Change]) -> Value {
+    let events: Vec<Value> = events
+        .iter()
+        .map(|(channel, change)| {
+            let name = match change {
+                OccupancyChange::Occupied => "channel_occupied",
+                OccupancyChange::Vacated => "channel_vacated",
+            };
+            json!({ "name": name, "channel": channel })
+        })
+        .collect();
+    json!({ "time_ms": time_ms, "events": events })
+}
+
+/// Turns raw occupancy changes into the events to report.
+///
+/// Only transitions away from the last reported state are reported, and
+/// vacated channels are held for the quiet period first.
+#[derive(Debug)]
+struct Tracker {
+    quiet_period: Duration,
+    /// Channels last reported occupied.
+    occupied: HashSet<String>,
+    /// Empty channels waiting out the quiet period, with their deadlines.
+    vacating: HashMap<String, Instant>,
+}
+
+impl Tracker {
+    fn new(quiet_period: Duration) -> Self {
+        Self {
+            quiet_period,
+            occupied: HashSet::new(),
+            vacating: HashMap::new(),
+        }
+    }
+
+    /// Record a change, returning an event that is due at once.
+    fn record(&mut self, channel: String, change: OccupancyChange, now: Instant) -> Option<Change> {
+        match change {
+            OccupancyChange::Occupied => {
+                self.vacating.remove(&channel);
+                if self.occupied.insert(channel.clone()) {
+                    return Some((channel, change));
+                }
+            }
+            OccupancyChange::Vacated => {
+                if self.occupied.contains(&channel) {
+                    self.vacating.insert(channel, now + self.quiet_period);
+                }
+            }
+        }
+        None
+    }
+
+    /// Vacated events whose quiet period is over.
+    fn due(&mut self, now: Instant) -> Vec<Change> {
+        let mut due: Vec<String> = self
+            .vacating
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(channel, _)| channel.clone())
+            .collect();
+        due.sort();
+        for channel in &due {
+            self.vacating.remove(channel);
+            self.occupied.remove(channel);
+        }
+        due.into_iter()
+            .map(|channel| (channel, OccupancyChange::Vacated))
+            .collect()
+    }
+
+    /// When the next vacated event falls due.
+    fn next_deadline(&self) -> Option<Instant> {
+        self.vacating.values().min().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracker_debounces_vacated() {
+        let quiet = Duration::from_secs(5);
+        let mut tracker = Tracker::new(quiet);
+        let start = Instant::now();
+
+        let occupied = tracker.record("room".into(), OccupancyChange::Occupied, start);
+        assert_eq!(occupied, Some(("room".into(), OccupancyChange::Occupied)));
+
+        // A reconnect within the quiet period reports nothing
+        assert!(tracker
+            .record("room".into(), OccupancyChange::Vacated, start)
+            .is_none());
+        assert_eq!(tracker.next_deadline(), Some(start + quiet));
+        assert!(tracker
+            .record("room".into(), OccupancyChange::Occupied, start)
+            .is_none());
+        assert!(tracker.next_deadline().is_none());
+        assert!(tracker.due(start + quiet).is_empty());
+
+        tracker.record("room".into(), OccupancyChange::Vacated, start);
+        assert!(tracker.due(start + quiet / 2).is_empty());
+        assert_eq!(
+            tracker.due(start + quiet),
+            vec![("room".into(), OccupancyChange::Vacated)]
+        );
+
+        // Occupied again after being reported vacated
+        assert!(tracker
+            .record("room".into(), OccupancyChange::Occupied, start + quiet)
+            .is_some());
+    }
+
+    #[test]
+    fn test_webhooks_filter() {
+        let config = WebhooksConfig {
+            url: Some("http://localhost/hooks".into()),
+            channels: vec![ChannelPattern::new("rooms:*")],
+            ..WebhooksConfig::default()
+        };
+        let webhooks = Webhooks::new(&config);
+        assert!(webhooks.enabled());
+        assert!(webhooks.reports("rooms:1"));
+        assert!(!webhooks.reports("orders:1"));
+
+        let webhooks = Webhooks::new(&WebhooksConfig::default());
+        assert!(!webhooks.enabled());
+        assert!(!Webhooks::new(&WebhooksConfig {
+            url: Some("http://localhost/hooks".into()),
+            ..WebhooksConfig::default()
+        })
+        .reports("$system:stats"));
+    }
+
+    #[test]
+    fn test_events_body() {
+        let body = events_body(
+            1_700_000_000_000,
+            &[
+                ("rooms:42".into(), OccupancyChange::Occupied),
+                ("rooms:7".into(), OccupancyChange::Vacated),
+            ],
+        );
+        assert_eq!(
+            body,
+            json!({"time_ms": 1_700_000_000_000_u64, "events": [
+                {"name": "channel_occupied", "channel": "rooms:42"},
+                {"name": "channel_vacated", "channel": "rooms:7"},
+            ]})
+        );
+    }
+}
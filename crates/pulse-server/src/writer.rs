@@ -0,0 +1,242 @@
+//! Outbound write coalescing.
+//!
+//! Frames are length-prefixed, so several can share one WebSocket message.
+//! [`FrameWriter`] batches small outbound frames into a buffer and sends it
+//! once it fills up or the flush interval elapses, trading a bounded latency
+//! increase for far fewer sends under fan-out load.
+
+use crate::metrics;
+use crate::stats::Transport;
+use anyhow::Result;
+use axum::extract::ws::{Message, WebSocket};
+use bytes::BytesMut;
+use futures_util::stream::SplitSink;
+use futures_util::{Sink, SinkExt};
+use pulse_protocol::{codec, pool, Frame};
+use std::sync::Arc;
+use std::time::Duration;
+use tenvis_pulse_core::ConnectionHandle;
+use tokio::time::Instant;
+
+/// Buffers outbound frames for a WebSocket connection.
+pub struct FrameWriter<S = SplitSink<WebSocket, Message>> {
+    sink: S,
+    buffer: BytesMut,
+    /// Flush once the buffer holds at least this many bytes.
+    max_buffered: usize,
+    /// How long a frame may wait in the buffer; `None` disables coalescing.
+    flush_interval: Option<Duration>,
+    /// When the buffered frames must be flushed.
+    deadline: Option<Instant>,
+    /// Creation times of the buffered deliveries, recorded once written.
+    delivering: Vec<std::time::Instant>,
+    /// The connection sent bytes are counted on.
+    handle: Option<Arc<ConnectionHandle>>,
+}
+
+impl<S> FrameWriter<S>
+where
+    S: Sink<Message> + Unpin,
+    S::Error: std::error::Error + Send + Sync + 'static,
+{
+    /// Create a writer.
+    ///
+    /// A zero `flush_interval` sends every frame immediately.
+    pub fn new(sink: S, max_buffered: usize, flush_interval: Duration) -> Self {
+        let mut writer = Self {
+            sink,
+            buffer: pool::global().acquire(),
+            max_buffered,
+            flush_interval: None,
+            deadline: None,
+            delivering: Vec::new(),
+            handle: None,
+        };
+        writer.set_flush_interval(flush_interval);
+        writer
+    }
+
+    /// Change how long frames may wait to be coalesced.
+    ///
+    /// A zero interval sends every frame immediately.
+    pub fn set_flush_interval(&mut self, flush_interval: Duration) {
+        self.flush_interval = (!flush_interval.is_zero()).then_some(flush_interval);
+    }
+
+    /// Count the bytes sent from now on against a connection.
+    pub fn count_bytes(&mut self, handle: Arc<ConnectionHandle>) {
+        self.handle = Some(handle);
+    }
+
+    /// Queue a frame, flushing if the buffer is full or coalescing is off.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the frame cannot be encoded or the send fails.
+    pub async fn send(&mut self, frame: &Frame) -> Result<()> {
+        let start = self.buffer.len();
+        codec::encode_into(frame, &mut self.buffer)?;
+        metrics::record_message(self.buffer.len() - start, "outbound", Transport::WebSocket);
+
+        match self.flush_interval {
+            Some(interval) if self.buffer.len() < self.max_buffered => {
+                self.deadline
+                    .get_or_insert_with(|| Instant::now() + interval);
+                Ok(())
+            }
+            _ => self.flush().await,
+        }
+    }
+
+    /// Queue a frame delivering a published message, recording its delivery
+    /// latency once the frame is written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the frame cannot be encoded or the send fails.
+    pub async fn deliver(
+        &mut self,
+        frame: &Frame,
+        created_at: Option<std::time::Instant>,
+    ) -> Result<()> {
+        self.delivering.extend(created_at);
+        self.send(frame).await
+    }
+
+    /// Send a WebSocket control message, flushing buffered frames first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the send fails.
+    pub async fn send_message(&mut self, message: Message) -> Result<()> {
+        self.flush().await?;
+        self.sink.send(message).await?;
+        Ok(())
+    }
+
+    /// Send all buffered frames.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the send fails.
+    pub async fn flush(&mut self) -> Result<()> {
+        self.deadline = None;
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let data = self.buffer.split();
+        metrics::record_flush(data.len());
+        if let Some(handle) = &self.handle {
+            handle.record_bytes_out(data.len());
+        }
+        self.sink.send(Message::Binary(data.to_vec())).await?;
+        for created_at in self.delivering.drain(..) {
+            metrics::record_delivery_latency(
+                created_at.elapsed().as_secs_f64(),
+                Transport::WebSocket,
+            );
+        }
+        Ok(())
+    }
+
+    /// When buffered frames are due, if any are waiting.
+    #[must_use]
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+}
+
+impl<S> Drop for FrameWriter<S> {
+    fn drop(&mut self) {
+        pool::global().release(std::mem::take(&mut self.buffer));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type TestWriter = FrameWriter<Vec<Message>>;
+
+    /// The frames in each WebSocket message sent so far.
+    fn sent(writer: &TestWriter) -> Vec<Vec<Frame>> {
+        writer
+            .sink
+            .iter()
+            .map(|message| {
+                let Message::Binary(data) = message else {
+                    panic!("expected a binary message, got {message:?}");
+                };
+                let mut buf = BytesMut::from(&data[..]);
+                let mut frames = Vec::new();
+                while let Some(frame) = codec::decode_from(&mut buf).unwrap() {
+                    frames.push(frame);
+                }
+                assert!(buf.is_empty(), "partial frame in a message");
+                frames
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_writer_one_frame_per_message() {
+        let mut writer = TestWriter::new(Vec::new(), 64 * 1024, Duration::ZERO);
+        for id in 1..=3 {
+            writer.send(&Frame::ack(id)).await.unwrap();
+        }
+        assert_eq!(
+            sent(&writer),
+            [[Frame::ack(1)], [Frame::ack(2)], [Frame::ack(3)]]
+        );
+        assert_eq!(writer.deadline(), None);
+    }
+
+    #[tokio::test]
+    async fn test_writer_coalesces_until_deadline() {
+        let interval = Duration::from_millis(10);
+        let mut writer = TestWriter::new(Vec::new(), 64 * 1024, interval);
+        let before = Instant::now();
+        writer.send(&Frame::ack(1)).await.unwrap();
+        let deadline = writer.deadline().unwrap();
+        assert!(deadline >= before + interval && deadline <= Instant::now() + interval);
+
+        // Later frames wait for the first one's deadline rather than their own
+        writer.send(&Frame::ack(2)).await.unwrap();
+        assert_eq!(writer.deadline(), Some(deadline));
+        assert!(sent(&writer).is_empty());
+
+        writer.flush().await.unwrap();
+        assert_eq!(sent(&writer), [[Frame::ack(1), Frame::ack(2)]]);
+        assert_eq!(writer.deadline(), None);
+
+        // Control messages go out behind the frames buffered before them
+        writer.send(&Frame::ack(3)).await.unwrap();
+        writer.send_message(Message::Close(None)).await.unwrap();
+        assert!(matches!(
+            writer.sink[1..],
+            [Message::Binary(_), Message::Close(None)]
+        ));
+        assert_eq!(writer.deadline(), None);
+    }
+
+    #[tokio::test]
+    async fn test_writer_flushes_when_full() {
+        let frame_len = codec::encode(&Frame::ack(1)).unwrap().len();
+        let mut writer = TestWriter::new(Vec::new(), frame_len * 3, Duration::from_secs(60));
+        writer.send(&Frame::ack(1)).await.unwrap();
+        writer.send(&Frame::ack(2)).await.unwrap();
+        assert!(sent(&writer).is_empty());
+
+        // The frame reaching the limit flushes without waiting for the deadline
+        writer.send(&Frame::ack(3)).await.unwrap();
+        assert_eq!(
+            sent(&writer),
+            [[Frame::ack(1), Frame::ack(2), Frame::ack(3)]]
+        );
+        assert_eq!(writer.deadline(), None);
+
+        writer.send(&Frame::ack(4)).await.unwrap();
+        assert!(writer.deadline().is_some());
+        assert_eq!(sent(&writer).len(), 1);
+    }
+}
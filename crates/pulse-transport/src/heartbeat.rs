@@ -0,0 +1,355 @@
+//! Heartbeats for client connections.
+//!
+//! [`HeartbeatConnection`] wraps a client [`Connection`] and keeps it alive
+//! the way the server expects: it sends a `Ping` every heartbeat interval,
+//! switching to the interval the server names in its `Connected` frame,
+//! answers the server's pings, and measures the round-trip time of each
+//! `Pong`. After `max_missed` pings in a row go unanswered it closes the
+//! connection and `recv` fails with [`TransportError::Timeout`].
+//!
+//! Pings go out while `recv` is waiting, so the connection has to be read
+//! continuously, as it must be anyway to notice it closing. Ping and Pong
+//! frames are handled here and never returned from `recv`.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use pulse_protocol::Frame;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use tokio::time::{sleep_until, Instant};
+use tracing::{debug, warn};
+
+use crate::traits::{Connection, ConnectionId, ConnectionSink, ConnectionStream, TransportError};
+
+/// Heartbeat configuration.
+#[derive(Debug, Clone)]
+pub struct HeartbeatConfig {
+    /// Time between pings until the server names an interval.
+    pub interval: Duration,
+    /// Pings in a row that may go unanswered before the connection is
+    /// closed.
+    pub max_missed: u32,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            max_missed: 2,
+        }
+    }
+}
+
+/// State shared by the halves.
+struct Shared {
+    sink: Mutex<Box<dyn ConnectionSink>>,
+    closed: AtomicBool,
+    /// Last round-trip time in microseconds, 0 until the first Pong.
+    rtt_micros: AtomicU64,
+}
+
+impl Shared {
+    fn rtt(&self) -> Option<Duration> {
+        match self.rtt_micros.load(Ordering::Relaxed) {
+            0 => None,
+            micros => Some(Duration::from_micros(micros)),
+        }
+    }
+}
+
+/// A [`Connection`] that sends heartbeats and measures round-trip time.
+pub struct HeartbeatConnection {
+    sink: HeartbeatSink,
+    stream: HeartbeatStream,
+    remote_addr: Option<String>,
+}
+
+impl HeartbeatConnection {
+    /// Wrap `connection`, sending the first ping one interval from now.
+    #[must_use]
+    pub fn new(connection: Box<dyn Connection>, config: HeartbeatConfig) -> Self {
+        let remote_addr = connection.remote_addr();
+        let (sink, stream) = connection.split();
+        let shared = Arc::new(Shared {
+            sink: Mutex::new(sink),
+            closed: AtomicBool::new(false),
+            rtt_micros: AtomicU64::new(0),
+        });
+        let interval = config.interval.max(Duration::from_millis(1));
+        Self {
+            sink: HeartbeatSink {
+                id: stream.id().clone(),
+                shared: shared.clone(),
+            },
+            stream: HeartbeatStream {
+                id: stream.id().clone(),
+                stream,
+                shared,
+                interval,
+                max_missed: config.max_missed.max(1),
+                next_ping: Instant::now() + interval,
+                outstanding: None,
+                missed: 0,
+            },
+            remote_addr,
+        }
+    }
+
+    /// Round-trip time of the last answered ping.
+    #[must_use]
+    pub fn rtt(&self) -> Option<Duration> {
+        self.sink.shared.rtt()
+    }
+
+    /// Current time between pings.
+    #[must_use]
+    pub fn interval(&self) -> Duration {
+        self.stream.interval
+    }
+}
+
+#[async_trait]
+impl Connection for HeartbeatConnection {
+    fn id(&self) -> &ConnectionId {
+        &self.sink.id
+    }
+
+    async fn recv(&mut self) -> Result<Option<Frame>, TransportError> {
+        self.stream.recv().await
+    }
+
+    async fn send(&mut self, frame: Frame) -> Result<(), TransportError> {
+        self.sink.send(frame).await
+    }
+
+    async fn send_raw(&mut self, data: Bytes) -> Result<(), TransportError> {
+        self.sink.send_raw(data).await
+    }
+
+    async fn close(&mut self) -> Result<(), TransportError> {
+        self.sink.close().await
+    }
+
+    fn remote_addr(&self) -> Option<String> {
+        self.remote_addr.clone()
+    }
+
+    fn is_open(&self) -> bool {
+        self.sink.is_open()
+    }
+
+    fn split(self: Box<Self>) -> (Box<dyn ConnectionSink>, Box<dyn ConnectionStream>) {
+        (Box::new(self.sink), Box::new(self.stream))
+    }
+}
+
+/// The sending half of a [`HeartbeatConnection`].
+pub struct HeartbeatSink {
+    id: ConnectionId,
+    shared: Arc<Shared>,
+}
+
+impl HeartbeatSink {
+    /// Round-trip time of the last answered ping.
+    #[must_use]
+    pub fn rtt(&self) -> Option<Duration> {
+        self.shared.rtt()
+    }
+}
+
+#[async_trait]
+impl ConnectionSink for HeartbeatSink {
+    fn id(&self) -> &ConnectionId {
+        &self.id
+    }
+
+    async fn send(&mut self, frame: Frame) -> Result<(), TransportError> {
+        self.shared.sink.lock().await.send(frame).await
+    }
+
+    async fn send_raw(&mut self, data: Bytes) -> Result<(), TransportError> {
+        self.shared.sink.lock().await.send_raw(data).await
+    }
+
+    async fn close(&mut self) -> Result<(), TransportError> {
+        self.shared.closed.store(true, Ordering::SeqCst);
+        self.shared.sink.lock().await.close().await
+    }
+
+    fn is_open(&self) -> bool {
+        // A send holding the lock means the sink was open a moment ago
+        !self.shared.closed.load(Ordering::SeqCst)
+            && self
+                .shared
+                .sink
+                .try_lock()
+                .map_or(true, |sink| sink.is_open())
+    }
+}
+
+/// The receiving half of a [`HeartbeatConnection`], which also sends the
+/// pings.
+pub struct HeartbeatStream {
+    id: ConnectionId,
+    stream: Box<dyn ConnectionStream>,
+    shared: Arc<Shared>,
+    interval: Duration,
+    max_missed: u32,
+    next_ping: Instant,
+    /// Timestamp and send time of the last unanswered ping.
+    outstanding: Option<(u64, Instant)>,
+    missed: u32,
+}
+
+impl HeartbeatStream {
+    /// Send the next ping, or give up if too many went unanswered.
+    async fn ping(&mut self) -> Result<(), TransportError> {
+        if self.outstanding.is_some() {
+            self.missed += 1;
+            if self.missed >= self.max_missed {
+                warn!(missed = self.missed, "Heartbeat timed out");
+                self.shared.closed.store(true, Ordering::SeqCst);
+                let _ = self.shared.sink.lock().await.close().await;
+                return Err(TransportError::Timeout);
+            }
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        self.shared
+            .sink
+            .lock()
+            .await
+            .send(Frame::ping_with_timestamp(timestamp))
+            .await?;
+        let now = Instant::now();
+        self.outstanding = Some((timestamp, now));
+        self.next_ping = now + self.interval;
+        Ok(())
+    }
+
+    /// Record a Pong. Any Pong shows the server is alive; only one that
+    /// answers the last ping gives a round-trip time.
+    fn pong(&mut self, timestamp: Option<u64>) {
+        if let Some((sent, at)) = self.outstanding.take() {
+            if timestamp == Some(sent) {
+                let rtt = u64::try_from(at.elapsed().as_micros()).unwrap_or(u64::MAX);
+                self.shared.rtt_micros.store(rtt.max(1), Ordering::Relaxed);
+            }
+        }
+        self.missed = 0;
+    }
+
+    /// Round-trip time of the last answered ping.
+    #[must_use]
+    pub fn rtt(&self) -> Option<Duration> {
+        self.shared.rtt()
+    }
+}
+
+#[async_trait]
+impl ConnectionStream for HeartbeatStream {
+    fn id(&self) -> &ConnectionId {
+        &self.id
+    }
+
+    async fn recv(&mut self) -> Result<Option<Frame>, TransportError> {
+        loop {
+            let frame = tokio::select! {
+                frame = self.stream.recv() => frame?,
+                () = sleep_until(self.next_ping) => {
+                    self.ping().await?;
+                    continue;
+                }
+            };
+            match frame {
+                Some(Frame::Ping { timestamp }) => {
+                    let mut sink = self.shared.sink.lock().await;
+                    sink.send(Frame::pong(timestamp)).await?;
+                }
+                Some(Frame::Pong { timestamp }) => self.pong(timestamp),
+                Some(Frame::Connected { heartbeat, .. }) if heartbeat > 0 => {
+                    self.interval = Duration::from_millis(u64::from(heartbeat));
+                    self.next_ping = Instant::now() + self.interval;
+                    debug!(interval_ms = heartbeat, "Heartbeat interval set by server");
+                    return Ok(frame);
+                }
+                frame => return Ok(frame),
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "websocket"))]
+mod tests {
+    use super::*;
+    use crate::websocket::{self, WebSocketTransport};
+    use crate::Transport;
+
+    async fn pair(config: HeartbeatConfig) -> (HeartbeatConnection, Box<dyn Connection>) {
+        let transport = WebSocketTransport::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let url = format!("ws://{}", transport.local_addr().unwrap());
+        let (client, server) = tokio::join!(websocket::connect(&url), transport.accept());
+        let client = HeartbeatConnection::new(Box::new(client.unwrap()), config);
+        (client, server.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_pings_and_measures_rtt() {
+        let (mut client, mut server) = pair(HeartbeatConfig::default()).await;
+
+        // The server's interval replaces the default
+        let connected = Frame::connected("conn_1", 1, 50);
+        server.send(connected.clone()).await.unwrap();
+        assert_eq!(client.recv().await.unwrap(), Some(connected));
+        assert_eq!(client.interval(), Duration::from_millis(50));
+
+        let reader = tokio::spawn(async move {
+            let frame = client.recv().await;
+            (client, frame)
+        });
+        let Some(Frame::Ping {
+            timestamp: Some(timestamp),
+        }) = server.recv().await.unwrap()
+        else {
+            panic!("expected a ping");
+        };
+        server.send(Frame::pong(Some(timestamp))).await.unwrap();
+        // The server's own pings are answered, not returned
+        server.send(Frame::ping_with_timestamp(9)).await.unwrap();
+        assert_eq!(server.recv().await.unwrap(), Some(Frame::pong(Some(9))));
+        server.send(Frame::ack(1)).await.unwrap();
+
+        let (client, frame) = reader.await.unwrap();
+        assert_eq!(frame.unwrap(), Some(Frame::ack(1)));
+        assert!(client.rtt().is_some());
+        assert!(client.is_open());
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_closes_on_missed_pongs() {
+        let config = HeartbeatConfig {
+            interval: Duration::from_millis(20),
+            max_missed: 2,
+        };
+        let (mut client, mut server) = pair(config).await;
+
+        // The server reads the pings but never answers
+        let server = tokio::spawn(async move {
+            let mut pings = 0;
+            while let Ok(Some(frame)) = server.recv().await {
+                assert!(matches!(frame, Frame::Ping { .. }));
+                pings += 1;
+            }
+            pings
+        });
+        assert!(matches!(client.recv().await, Err(TransportError::Timeout)));
+        assert!(!client.is_open());
+        assert!(client.rtt().is_none());
+        assert_eq!(server.await.unwrap(), 2);
+    }
+}
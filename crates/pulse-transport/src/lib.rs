@@ -10,7 +10,18 @@
 //! ## Transport Abstraction
 //!
 //! All transports implement the `Transport` and `Connection` traits,
-//! allowing the server to be protocol-agnostic.
+//! allowing the server to be protocol-agnostic. A connection can be split
+//! into a `ConnectionSink` and a `ConnectionStream` to send and receive from
+//! separate tasks.
+//!
+//! Clients use the same types: `WebSocketClientTransport` connects to a
+//! server and returns a `Connection`, and `ReconnectingConnection` keeps one
+//! up across drops. `HeartbeatConnection` sends pings at the interval the
+//! server asks for and closes the connection when they go unanswered.
+//!
+//! `ConnectionMiddleware` layers, stacked on any connection with
+//! `MiddlewareConnection`, see every frame in and out and may rewrite or drop
+//! it, for logging, metrics or encryption that work across transports.
 //!
 //! ```rust,ignore
 //! use tenvis_pulse_transport::{Transport, Connection};
@@ -23,6 +34,10 @@
 //! ```
 
 pub mod fallback;
+pub mod heartbeat;
+pub mod middleware;
+pub mod reconnect;
+pub mod socket;
 pub mod traits;
 
 #[cfg(feature = "websocket")]
@@ -31,7 +46,14 @@ pub mod websocket;
 #[cfg(feature = "webtransport")]
 pub mod webtransport;
 
-pub use traits::{Connection, ConnectionId, Transport, TransportError};
+pub use heartbeat::{HeartbeatConfig, HeartbeatConnection};
+pub use middleware::{ConnectionMiddleware, MiddlewareConnection};
+pub use reconnect::{ReconnectConfig, ReconnectEvent, ReconnectingConnection};
+pub use socket::SocketOptions;
+pub use traits::{
+    Connection, ConnectionId, ConnectionSink, ConnectionStream, IdGenerator, RandomIdGenerator,
+    Transport, TransportError, UuidV7Generator,
+};
 
 #[cfg(feature = "websocket")]
-pub use websocket::WebSocketTransport;
+pub use websocket::{WebSocketClientTransport, WebSocketTransport};
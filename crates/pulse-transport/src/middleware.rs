@@ -0,0 +1,336 @@
+//! Frame inspection middleware for connections.
+//!
+//! A [`ConnectionMiddleware`] sees every frame a connection sends and
+//! receives and may pass it on, rewrite it, or drop it. Layers are stacked
+//! on any [`Connection`] with [`MiddlewareConnection`], so logging, metrics,
+//! encryption and rewriting work the same over every transport.
+//!
+//! The first layer is closest to the caller: outbound frames pass through
+//! the layers in order, inbound frames in reverse. An encryption layer added
+//! after a logging layer therefore logs plaintext both ways.
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use pulse_protocol::{codec, Frame};
+use std::sync::Arc;
+
+use crate::traits::{Connection, ConnectionId, ConnectionSink, ConnectionStream, TransportError};
+
+/// Inspects, rewrites or drops the frames of a connection.
+///
+/// Both hooks pass frames through unchanged by default. Returning `None`
+/// drops the frame: it is not sent, or not returned from `recv`.
+pub trait ConnectionMiddleware: Send + Sync {
+    /// Called for each frame received from the peer.
+    fn on_frame_in(&self, connection: &ConnectionId, frame: Frame) -> Option<Frame> {
+        let _ = connection;
+        Some(frame)
+    }
+
+    /// Called for each frame about to be sent to the peer.
+    fn on_frame_out(&self, connection: &ConnectionId, frame: Frame) -> Option<Frame> {
+        let _ = connection;
+        Some(frame)
+    }
+}
+
+/// The layers of a [`MiddlewareConnection`], first closest to the caller.
+type Layers = Arc<[Arc<dyn ConnectionMiddleware>]>;
+
+/// Run an outbound frame through the layers.
+fn outbound(layers: &Layers, id: &ConnectionId, frame: Frame) -> Option<Frame> {
+    layers
+        .iter()
+        .try_fold(frame, |frame, layer| layer.on_frame_out(id, frame))
+}
+
+/// Run an inbound frame through the layers, innermost first.
+fn inbound(layers: &Layers, id: &ConnectionId, frame: Frame) -> Option<Frame> {
+    layers
+        .iter()
+        .rev()
+        .try_fold(frame, |frame, layer| layer.on_frame_in(id, frame))
+}
+
+/// Decode pre-encoded frames, run them through the layers, and encode what
+/// is left. Returns `None` if every frame was dropped.
+fn outbound_raw(
+    layers: &Layers,
+    id: &ConnectionId,
+    data: Bytes,
+) -> Result<Option<Bytes>, TransportError> {
+    let mut input = BytesMut::from(&data[..]);
+    let mut output = BytesMut::new();
+    while let Some(frame) = codec::decode_from(&mut input)? {
+        if let Some(frame) = outbound(layers, id, frame) {
+            output.extend_from_slice(&codec::encode(&frame)?);
+        }
+    }
+    Ok((!output.is_empty()).then(|| output.freeze()))
+}
+
+/// A [`Connection`] whose frames pass through middleware layers.
+///
+/// Raw sends are decoded so that every frame reaches the layers, which
+/// costs a decode and re-encode per frame.
+pub struct MiddlewareConnection {
+    inner: Box<dyn Connection>,
+    layers: Layers,
+}
+
+impl MiddlewareConnection {
+    /// Wrap `connection` in `layers`, the first closest to the caller.
+    #[must_use]
+    pub fn new(
+        connection: Box<dyn Connection>,
+        layers: Vec<Arc<dyn ConnectionMiddleware>>,
+    ) -> Self {
+        Self {
+            inner: connection,
+            layers: layers.into(),
+        }
+    }
+
+    /// Add a layer beneath the existing ones, closer to the transport.
+    #[must_use]
+    pub fn with(self, layer: Arc<dyn ConnectionMiddleware>) -> Self {
+        let mut layers = self.layers.to_vec();
+        layers.push(layer);
+        Self {
+            inner: self.inner,
+            layers: layers.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl Connection for MiddlewareConnection {
+    fn id(&self) -> &ConnectionId {
+        self.inner.id()
+    }
+
+    async fn recv(&mut self) -> Result<Option<Frame>, TransportError> {
+        loop {
+            let Some(frame) = self.inner.recv().await? else {
+                return Ok(None);
+            };
+            if let Some(frame) = inbound(&self.layers, self.inner.id(), frame) {
+                return Ok(Some(frame));
+            }
+        }
+    }
+
+    async fn send(&mut self, frame: Frame) -> Result<(), TransportError> {
+        match outbound(&self.layers, self.inner.id(), frame) {
+            Some(frame) => self.inner.send(frame).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn send_raw(&mut self, data: Bytes) -> Result<(), TransportError> {
+        match outbound_raw(&self.layers, self.inner.id(), data)? {
+            Some(data) => self.inner.send_raw(data).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn close(&mut self) -> Result<(), TransportError> {
+        self.inner.close().await
+    }
+
+    fn remote_addr(&self) -> Option<String> {
+        self.inner.remote_addr()
+    }
+
+    fn is_open(&self) -> bool {
+        self.inner.is_open()
+    }
+
+    fn split(self: Box<Self>) -> (Box<dyn ConnectionSink>, Box<dyn ConnectionStream>) {
+        let (sink, stream) = self.inner.split();
+        (
+            Box::new(MiddlewareSink {
+                inner: sink,
+                layers: self.layers.clone(),
+            }),
+            Box::new(MiddlewareStream {
+                inner: stream,
+                layers: self.layers,
+            }),
+        )
+    }
+}
+
+/// The sending half of a [`MiddlewareConnection`].
+pub struct MiddlewareSink {
+    inner: Box<dyn ConnectionSink>,
+    layers: Layers,
+}
+
+#[async_trait]
+impl ConnectionSink for MiddlewareSink {
+    fn id(&self) -> &ConnectionId {
+        self.inner.id()
+    }
+
+    async fn send(&mut self, frame: Frame) -> Result<(), TransportError> {
+        match outbound(&self.layers, self.inner.id(), frame) {
+            Some(frame) => self.inner.send(frame).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn send_raw(&mut self, data: Bytes) -> Result<(), TransportError> {
+        match outbound_raw(&self.layers, self.inner.id(), data)? {
+            Some(data) => self.inner.send_raw(data).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn close(&mut self) -> Result<(), TransportError> {
+        self.inner.close().await
+    }
+
+    fn is_open(&self) -> bool {
+        self.inner.is_open()
+    }
+}
+
+/// The receiving half of a [`MiddlewareConnection`].
+pub struct MiddlewareStream {
+    inner: Box<dyn ConnectionStream>,
+    layers: Layers,
+}
+
+#[async_trait]
+impl ConnectionStream for MiddlewareStream {
+    fn id(&self) -> &ConnectionId {
+        self.inner.id()
+    }
+
+    async fn recv(&mut self) -> Result<Option<Frame>, TransportError> {
+        loop {
+            let Some(frame) = self.inner.recv().await? else {
+                return Ok(None);
+            };
+            if let Some(frame) = inbound(&self.layers, self.inner.id(), frame) {
+                return Ok(Some(frame));
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "websocket"))]
+mod tests {
+    use super::*;
+    use crate::websocket::{self, WebSocketTransport};
+    use crate::Transport;
+    use std::sync::Mutex;
+
+    /// Records the order layers see frames in.
+    struct Record {
+        name: &'static str,
+        seen: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl ConnectionMiddleware for Record {
+        fn on_frame_in(&self, _: &ConnectionId, frame: Frame) -> Option<Frame> {
+            let entry = format!("{} in {:?}", self.name, frame.frame_type());
+            self.seen.lock().unwrap().push(entry);
+            Some(frame)
+        }
+
+        fn on_frame_out(&self, _: &ConnectionId, frame: Frame) -> Option<Frame> {
+            let entry = format!("{} out {:?}", self.name, frame.frame_type());
+            self.seen.lock().unwrap().push(entry);
+            Some(frame)
+        }
+    }
+
+    /// Drops pings and prefixes published channels.
+    struct Rewrite;
+
+    impl ConnectionMiddleware for Rewrite {
+        fn on_frame_in(&self, _: &ConnectionId, frame: Frame) -> Option<Frame> {
+            match frame {
+                Frame::Ping { .. } => None,
+                frame => Some(frame),
+            }
+        }
+
+        fn on_frame_out(&self, _: &ConnectionId, frame: Frame) -> Option<Frame> {
+            match frame {
+                Frame::Ping { .. } => None,
+                Frame::Subscribe { id, channel, .. } => {
+                    Some(Frame::subscribe(id, format!("tenant:{channel}")))
+                }
+                frame => Some(frame),
+            }
+        }
+    }
+
+    async fn pair() -> (Box<dyn Connection>, Box<dyn Connection>) {
+        let transport = WebSocketTransport::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let url = format!("ws://{}", transport.local_addr().unwrap());
+        let (client, server) = tokio::join!(websocket::connect(&url), transport.accept());
+        (Box::new(client.unwrap()), server.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_middleware_order() {
+        let (client, mut server) = pair().await;
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let layer = |name| -> Arc<dyn ConnectionMiddleware> {
+            Arc::new(Record {
+                name,
+                seen: seen.clone(),
+            })
+        };
+        let mut client =
+            MiddlewareConnection::new(client, vec![layer("outer")]).with(layer("inner"));
+
+        client.send(Frame::ping()).await.unwrap();
+        server.recv().await.unwrap();
+        server.send(Frame::pong(None)).await.unwrap();
+        client.recv().await.unwrap();
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                "outer out Ping",
+                "inner out Ping",
+                "inner in Pong",
+                "outer in Pong"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_middleware_rewrites_and_drops() {
+        let (client, mut server) = pair().await;
+        let client = MiddlewareConnection::new(client, vec![Arc::new(Rewrite)]);
+        let (mut sink, mut stream) = Box::new(client).split();
+
+        // Dropped on the way out, raw or not
+        sink.send(Frame::ping()).await.unwrap();
+        let mut raw = BytesMut::new();
+        raw.extend_from_slice(&codec::encode(&Frame::ping()).unwrap());
+        raw.extend_from_slice(&codec::encode(&Frame::subscribe(2, "b")).unwrap());
+        sink.send(Frame::subscribe(1, "a")).await.unwrap();
+        sink.send_raw(raw.freeze()).await.unwrap();
+        assert_eq!(
+            server.recv().await.unwrap(),
+            Some(Frame::subscribe(1, "tenant:a"))
+        );
+        assert_eq!(
+            server.recv().await.unwrap(),
+            Some(Frame::subscribe(2, "tenant:b"))
+        );
+
+        // Dropped on the way in
+        server.send(Frame::ping()).await.unwrap();
+        server.send(Frame::ack(1)).await.unwrap();
+        assert_eq!(stream.recv().await.unwrap(), Some(Frame::ack(1)));
+    }
+}
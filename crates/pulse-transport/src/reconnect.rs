@@ -0,0 +1,577 @@
+//! Connections that reconnect on their own.
+//!
+//! [`ReconnectingConnection`] wraps a way of opening connections to one
+//! server. When the connection drops it opens another, waiting between
+//! attempts with exponential backoff and jitter, and replays the handshake:
+//! the configured frames (typically a `Connect` carrying a token), then a
+//! `Subscribe` for every channel still subscribed through it. Callers see a
+//! single long-lived [`Connection`] and can follow reconnects through
+//! [`ReconnectingConnection::events`].
+//!
+//! Replayed subscribes are answered like any other, so callers see a
+//! `Connected` frame and a `SubscribeOk` per channel after each reconnect.
+//! Frames that were queued but not yet written when the connection dropped
+//! may be lost; a send that fails is retried on the new connection.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_util::future::BoxFuture;
+use pulse_protocol::{codec, Frame};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, Notify};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use crate::traits::{Connection, ConnectionId, ConnectionSink, ConnectionStream, TransportError};
+
+/// Opens a new connection to the server.
+pub type Connect =
+    Arc<dyn Fn() -> BoxFuture<'static, Result<Box<dyn Connection>, TransportError>> + Send + Sync>;
+
+/// Reconnect configuration.
+#[derive(Debug, Clone)]
+pub struct ReconnectConfig {
+    /// Delay before the first reconnect attempt.
+    pub initial_backoff: Duration,
+    /// Longest delay between attempts.
+    pub max_backoff: Duration,
+    /// Factor the delay grows by after each failed attempt.
+    pub multiplier: f64,
+    /// Fraction of each delay that is random, from 0 (none) to 1 (anywhere
+    /// between zero and the full delay), so clients dropped together don't
+    /// all come back at once.
+    pub jitter: f64,
+    /// Attempts in a row before giving up (`None` retries forever).
+    pub max_attempts: Option<u32>,
+    /// Frames sent first on every connection, e.g. a `Connect` frame.
+    pub handshake: Vec<Frame>,
+    /// Outbound and inbound frames buffered between the caller and the
+    /// connection.
+    pub queue_size: usize,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.5,
+            max_attempts: None,
+            handshake: Vec::new(),
+            queue_size: 256,
+        }
+    }
+}
+
+impl ReconnectConfig {
+    /// Pick the delay before reconnect attempt `attempt`, counting from 1.
+    #[must_use]
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = i32::try_from(attempt.saturating_sub(1)).unwrap_or(i32::MAX);
+        let delay = (self.initial_backoff.as_secs_f64() * self.multiplier.max(1.0).powi(exponent))
+            .min(self.max_backoff.as_secs_f64());
+        let fraction: f64 = rand::random();
+        Duration::from_secs_f64(delay * (1.0 - self.jitter.clamp(0.0, 1.0) * fraction))
+    }
+}
+
+/// A change in a [`ReconnectingConnection`]'s underlying connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReconnectEvent {
+    /// The connection dropped, with the error if there was one.
+    Disconnected {
+        /// Why the connection dropped.
+        error: Option<String>,
+    },
+    /// Waiting `delay` before attempt `attempt`.
+    Reconnecting {
+        /// Attempt number, counting from 1.
+        attempt: u32,
+        /// Time until the attempt.
+        delay: Duration,
+    },
+    /// Connected again and replayed the handshake.
+    Reconnected {
+        /// Attempts it took.
+        attempts: u32,
+    },
+    /// Stopped trying after `max_attempts`; the connection is closed.
+    GaveUp {
+        /// Attempts made.
+        attempts: u32,
+    },
+}
+
+/// State shared by the halves and the background task.
+struct Shared {
+    is_open: AtomicBool,
+    remote_addr: Mutex<Option<String>>,
+    /// Encoded Subscribe frames by channel, replayed on reconnect.
+    subscriptions: Mutex<BTreeMap<String, Bytes>>,
+    closing: Notify,
+    events: broadcast::Sender<ReconnectEvent>,
+}
+
+impl Shared {
+    fn emit(&self, event: ReconnectEvent) {
+        // Nobody listening is fine
+        let _ = self.events.send(event);
+    }
+}
+
+/// A [`Connection`] that reconnects when the underlying one drops.
+///
+/// Frames go through a background task that owns the underlying
+/// connection, so a reconnect in progress only delays sends and receives.
+pub struct ReconnectingConnection {
+    sender: ReconnectingSender,
+    receiver: ReconnectingReceiver,
+}
+
+impl ReconnectingConnection {
+    /// Open the first connection with `connect` and keep it up.
+    ///
+    /// The first connection is not retried, so a wrong address or
+    /// credential is reported straight away.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the first connection or its handshake fails.
+    pub async fn connect(
+        connect: Connect,
+        config: ReconnectConfig,
+    ) -> Result<Self, TransportError> {
+        let (events, _) = broadcast::channel(16);
+        let shared = Arc::new(Shared {
+            is_open: AtomicBool::new(true),
+            remote_addr: Mutex::new(None),
+            subscriptions: Mutex::new(BTreeMap::new()),
+            closing: Notify::new(),
+            events,
+        });
+        let handshake = config
+            .handshake
+            .iter()
+            .map(codec::encode)
+            .collect::<Result<Vec<_>, _>>()?;
+        let halves = open(&connect, &handshake, &shared).await?;
+
+        let id = ConnectionId::generate();
+        let (queue, outbound) = mpsc::channel(config.queue_size.max(1));
+        let (inbound, frames) = mpsc::channel(config.queue_size.max(1));
+        let task = tokio::spawn(run(Task {
+            connect,
+            config,
+            handshake,
+            shared: shared.clone(),
+            outbound,
+            inbound,
+            halves,
+        }));
+        Ok(Self {
+            sender: ReconnectingSender {
+                id: id.clone(),
+                queue: Some(queue),
+                task: Some(task),
+                shared,
+            },
+            receiver: ReconnectingReceiver { id, frames },
+        })
+    }
+
+    /// Connect to a WebSocket URL with `transport` and keep it up.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the first connection or its handshake fails.
+    #[cfg(feature = "websocket")]
+    pub async fn websocket(
+        url: impl Into<String>,
+        transport: crate::websocket::WebSocketClientTransport,
+        config: ReconnectConfig,
+    ) -> Result<Self, TransportError> {
+        let url: Arc<str> = url.into().into();
+        let connect: Connect = Arc::new(move || {
+            let transport = transport.clone();
+            let url = url.clone();
+            Box::pin(async move {
+                let connection: Box<dyn Connection> = Box::new(transport.connect(&url).await?);
+                Ok(connection)
+            })
+        });
+        Self::connect(connect, config).await
+    }
+
+    /// Follow reconnects from now on.
+    #[must_use]
+    pub fn events(&self) -> broadcast::Receiver<ReconnectEvent> {
+        self.sender.shared.events.subscribe()
+    }
+}
+
+#[async_trait]
+impl Connection for ReconnectingConnection {
+    fn id(&self) -> &ConnectionId {
+        &self.sender.id
+    }
+
+    async fn recv(&mut self) -> Result<Option<Frame>, TransportError> {
+        self.receiver.recv().await
+    }
+
+    async fn send(&mut self, frame: Frame) -> Result<(), TransportError> {
+        self.sender.send(frame).await
+    }
+
+    async fn send_raw(&mut self, data: Bytes) -> Result<(), TransportError> {
+        self.sender.send_raw(data).await
+    }
+
+    async fn close(&mut self) -> Result<(), TransportError> {
+        self.sender.close().await
+    }
+
+    fn remote_addr(&self) -> Option<String> {
+        self.sender.shared.remote_addr.lock().unwrap().clone()
+    }
+
+    fn is_open(&self) -> bool {
+        self.sender.is_open()
+    }
+
+    fn split(self: Box<Self>) -> (Box<dyn ConnectionSink>, Box<dyn ConnectionStream>) {
+        (Box::new(self.sender), Box::new(self.receiver))
+    }
+}
+
+/// The sending half of a [`ReconnectingConnection`].
+///
+/// Subscribes and unsubscribes sent as frames are tracked so they can be
+/// replayed; ones sent with `send_raw` are not.
+pub struct ReconnectingSender {
+    id: ConnectionId,
+    queue: Option<mpsc::Sender<Bytes>>,
+    task: Option<JoinHandle<()>>,
+    shared: Arc<Shared>,
+}
+
+impl Drop for ReconnectingSender {
+    fn drop(&mut self) {
+        // Stop a reconnect in progress; the queue closing stops the rest
+        self.shared.closing.notify_one();
+    }
+}
+
+#[async_trait]
+impl ConnectionSink for ReconnectingSender {
+    fn id(&self) -> &ConnectionId {
+        &self.id
+    }
+
+    async fn send(&mut self, frame: Frame) -> Result<(), TransportError> {
+        let data = codec::encode(&frame)?;
+        match frame {
+            Frame::Subscribe { channel, .. } => {
+                let mut subscriptions = self.shared.subscriptions.lock().unwrap();
+                subscriptions.insert(channel, data.clone());
+            }
+            Frame::Unsubscribe { channel, .. } => {
+                self.shared.subscriptions.lock().unwrap().remove(&channel);
+            }
+            _ => {}
+        }
+        self.send_raw(data).await
+    }
+
+    async fn send_raw(&mut self, data: Bytes) -> Result<(), TransportError> {
+        let Some(queue) = self.queue.as_ref().filter(|_| self.is_open()) else {
+            return Err(TransportError::ConnectionClosed);
+        };
+        queue
+            .send(data)
+            .await
+            .map_err(|_| TransportError::ConnectionClosed)
+    }
+
+    async fn close(&mut self) -> Result<(), TransportError> {
+        self.queue.take();
+        let Some(task) = self.task.take() else {
+            return Ok(()); // Already closed
+        };
+        self.shared.is_open.store(false, Ordering::SeqCst);
+        self.shared.closing.notify_one();
+        task.await
+            .map_err(|e| TransportError::Other(format!("Reconnect task failed: {}", e)))
+    }
+
+    fn is_open(&self) -> bool {
+        self.shared.is_open.load(Ordering::SeqCst)
+    }
+}
+
+/// The receiving half of a [`ReconnectingConnection`].
+pub struct ReconnectingReceiver {
+    id: ConnectionId,
+    frames: mpsc::Receiver<Result<Frame, TransportError>>,
+}
+
+#[async_trait]
+impl ConnectionStream for ReconnectingReceiver {
+    fn id(&self) -> &ConnectionId {
+        &self.id
+    }
+
+    /// Receive the next frame. Returns an error once the connection gives
+    /// up reconnecting, and `None` after it is closed.
+    async fn recv(&mut self) -> Result<Option<Frame>, TransportError> {
+        self.frames.recv().await.transpose()
+    }
+}
+
+type Halves = (Box<dyn ConnectionSink>, Box<dyn ConnectionStream>);
+
+/// The background task's state.
+struct Task {
+    connect: Connect,
+    config: ReconnectConfig,
+    handshake: Vec<Bytes>,
+    shared: Arc<Shared>,
+    outbound: mpsc::Receiver<Bytes>,
+    inbound: mpsc::Sender<Result<Frame, TransportError>>,
+    halves: Halves,
+}
+
+/// Open a connection and send the handshake and subscriptions.
+async fn open(
+    connect: &Connect,
+    handshake: &[Bytes],
+    shared: &Shared,
+) -> Result<Halves, TransportError> {
+    let connection = connect().await?;
+    let remote_addr = connection.remote_addr();
+    let (mut sink, stream) = connection.split();
+    let subscriptions: Vec<Bytes> = {
+        let subscriptions = shared.subscriptions.lock().unwrap();
+        subscriptions.values().cloned().collect()
+    };
+    for data in handshake.iter().chain(&subscriptions) {
+        sink.send_raw(data.clone()).await?;
+    }
+    *shared.remote_addr.lock().unwrap() = remote_addr;
+    Ok((sink, stream))
+}
+
+/// Relay frames until the caller closes, reconnecting whenever the
+/// connection drops.
+async fn run(task: Task) {
+    let Task {
+        connect,
+        config,
+        handshake,
+        shared,
+        mut outbound,
+        inbound,
+        halves: (mut sink, mut stream),
+    } = task;
+    // A send that failed, retried on the next connection
+    let mut pending: Option<Bytes> = None;
+
+    loop {
+        let error = 'relay: loop {
+            if let Some(data) = pending.take() {
+                if let Err(e) = sink.send_raw(data.clone()).await {
+                    pending = Some(data);
+                    break 'relay Some(e.to_string());
+                }
+            }
+            tokio::select! {
+                data = outbound.recv() => {
+                    let Some(data) = data else {
+                        // Closed by the caller
+                        let _ = sink.close().await;
+                        return;
+                    };
+                    if let Err(e) = sink.send_raw(data.clone()).await {
+                        pending = Some(data);
+                        break 'relay Some(e.to_string());
+                    }
+                }
+                frame = stream.recv() => match frame {
+                    // The receiving half may have been dropped
+                    Ok(Some(frame)) => { let _ = inbound.send(Ok(frame)).await; }
+                    Ok(None) => break 'relay None,
+                    Err(e) => break 'relay Some(e.to_string()),
+                },
+            }
+        };
+        let _ = sink.close().await;
+        *shared.remote_addr.lock().unwrap() = None;
+        if !shared.is_open.load(Ordering::SeqCst) {
+            return;
+        }
+        debug!(error = ?error, "Connection dropped, reconnecting");
+        shared.emit(ReconnectEvent::Disconnected { error });
+
+        let mut attempt = 0;
+        (sink, stream) = loop {
+            attempt += 1;
+            if config.max_attempts.is_some_and(|max| attempt > max) {
+                warn!(attempts = attempt - 1, "Giving up reconnecting");
+                shared.is_open.store(false, Ordering::SeqCst);
+                shared.emit(ReconnectEvent::GaveUp {
+                    attempts: attempt - 1,
+                });
+                let _ = inbound.send(Err(TransportError::ConnectionClosed)).await;
+                return;
+            }
+            let delay = config.backoff(attempt);
+            shared.emit(ReconnectEvent::Reconnecting { attempt, delay });
+            tokio::select! {
+                () = tokio::time::sleep(delay) => {}
+                () = shared.closing.notified() => return,
+            }
+            match open(&connect, &handshake, &shared).await {
+                Ok(halves) => break halves,
+                Err(e) => debug!(attempt, error = %e, "Reconnect attempt failed"),
+            }
+        };
+        shared.emit(ReconnectEvent::Reconnected { attempts: attempt });
+    }
+}
+
+#[cfg(all(test, feature = "websocket"))]
+mod tests {
+    use super::*;
+    use crate::websocket::{WebSocketClientTransport, WebSocketTransport};
+    use crate::Transport;
+
+    fn config() -> ReconnectConfig {
+        ReconnectConfig {
+            initial_backoff: Duration::from_millis(10),
+            handshake: vec![Frame::ping_with_timestamp(1)],
+            ..ReconnectConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let config = ReconnectConfig {
+            jitter: 0.0,
+            ..ReconnectConfig::default()
+        };
+        let delays: Vec<_> = (1..=4).map(|attempt| config.backoff(attempt)).collect();
+        assert_eq!(
+            delays,
+            [100, 200, 400, 800].map(Duration::from_millis).to_vec()
+        );
+        assert_eq!(config.backoff(20), config.max_backoff);
+
+        // Jitter only ever shortens the delay
+        let config = ReconnectConfig::default();
+        for _ in 0..100 {
+            let delay = config.backoff(3);
+            assert!(delay >= Duration::from_millis(200) && delay <= Duration::from_millis(400));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_replays_handshake() {
+        let transport = WebSocketTransport::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let url = format!("ws://{}", transport.local_addr().unwrap());
+        let (client, server) = tokio::join!(
+            ReconnectingConnection::websocket(url, WebSocketClientTransport::default(), config()),
+            transport.accept()
+        );
+        let mut client = client.unwrap();
+        let mut server = server.unwrap();
+        let mut events = client.events();
+
+        client.send(Frame::subscribe(1, "chat")).await.unwrap();
+        client.send(Frame::subscribe(2, "news")).await.unwrap();
+        client.send(Frame::unsubscribe(3, "news")).await.unwrap();
+        assert_eq!(
+            server.recv().await.unwrap(),
+            Some(Frame::ping_with_timestamp(1))
+        );
+        for _ in 0..3 {
+            server.recv().await.unwrap();
+        }
+
+        // The server drops the client, which comes back with its handshake
+        // and remaining subscription
+        server.close().await.unwrap();
+        let mut server = transport.accept().await.unwrap();
+        assert_eq!(
+            server.recv().await.unwrap(),
+            Some(Frame::ping_with_timestamp(1))
+        );
+        assert_eq!(
+            server.recv().await.unwrap(),
+            Some(Frame::subscribe(1, "chat"))
+        );
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ReconnectEvent::Disconnected { .. }
+        ));
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            ReconnectEvent::Reconnecting { attempt: 1, .. }
+        ));
+        assert_eq!(
+            events.recv().await.unwrap(),
+            ReconnectEvent::Reconnected { attempts: 1 }
+        );
+
+        // Same connection as far as the caller is concerned
+        assert!(client.is_open());
+        server.send(Frame::pong(Some(1))).await.unwrap();
+        assert_eq!(client.recv().await.unwrap(), Some(Frame::pong(Some(1))));
+        client.send(Frame::ping_with_timestamp(2)).await.unwrap();
+        assert_eq!(
+            server.recv().await.unwrap(),
+            Some(Frame::ping_with_timestamp(2))
+        );
+
+        client.close().await.unwrap();
+        assert!(!client.is_open());
+        assert_eq!(server.recv().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_gives_up() {
+        let transport = WebSocketTransport::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let url = format!("ws://{}", transport.local_addr().unwrap());
+        let config = ReconnectConfig {
+            max_attempts: Some(2),
+            ..config()
+        };
+        let (client, server) = tokio::join!(
+            ReconnectingConnection::websocket(url, WebSocketClientTransport::default(), config),
+            transport.accept()
+        );
+        let mut client = client.unwrap();
+        let mut events = client.events();
+
+        // Nothing to reconnect to
+        drop(transport);
+        server.unwrap().close().await.unwrap();
+        assert!(matches!(
+            client.recv().await,
+            Err(TransportError::ConnectionClosed)
+        ));
+        assert!(!client.is_open());
+        let mut last = None;
+        while let Ok(event) = events.try_recv() {
+            last = Some(event);
+        }
+        assert_eq!(last, Some(ReconnectEvent::GaveUp { attempts: 2 }));
+    }
+}
@@ -0,0 +1,217 @@
+//! TCP socket tuning for listeners and accepted connections.
+//!
+//! The OS defaults suit bulk transfers: Nagle's algorithm holds back small
+//! writes, and the accept backlog overflows when thousands of clients
+//! reconnect at once. [`SocketOptions`] sets up the listening socket and
+//! each accepted stream for many small, latency-sensitive messages instead.
+
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+
+/// Default for [`SocketOptions::backlog`].
+pub const DEFAULT_BACKLOG: u32 = 1024;
+
+/// Options for the listening socket and the streams it accepts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SocketOptions {
+    /// Disable Nagle's algorithm so small frames are sent immediately.
+    pub nodelay: bool,
+    /// Idle time before TCP keepalive probes are sent (`None` leaves
+    /// keepalive off).
+    pub keepalive: Option<Duration>,
+    /// Connections waiting to be accepted before new ones are refused.
+    pub backlog: u32,
+    /// Kernel send buffer size in bytes (`None` keeps the OS default).
+    pub send_buffer_size: Option<usize>,
+    /// Kernel receive buffer size in bytes (`None` keeps the OS default).
+    pub recv_buffer_size: Option<usize>,
+    /// Set `SO_REUSEPORT`, so several listeners can bind the same address
+    /// and the kernel spreads new connections across them.
+    pub reuse_port: bool,
+    /// Set `IPV6_V6ONLY` on IPv6 listeners, so `[::]` accepts only IPv6 and
+    /// can share its port with a `0.0.0.0` listener.
+    pub only_v6: bool,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            keepalive: None,
+            backlog: DEFAULT_BACKLOG,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+            reuse_port: false,
+            only_v6: false,
+        }
+    }
+}
+
+impl SocketOptions {
+    /// Bind a listener with these options.
+    ///
+    /// Buffer sizes are also set on the listening socket, since the receive
+    /// window is negotiated before a connection is accepted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the socket cannot be created, configured, or bound,
+    /// or `reuse_port` is set on a platform without `SO_REUSEPORT`.
+    pub fn bind(&self, addr: SocketAddr) -> io::Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        // Allow restarting while old connections linger in TIME_WAIT
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?;
+        if self.reuse_port {
+            set_reuse_port(&socket)?;
+        }
+        if self.only_v6 && addr.is_ipv6() {
+            socket.set_only_v6(true)?;
+        }
+        self.set_buffer_sizes(&SockRef::from(&socket))?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(i32::try_from(self.backlog).unwrap_or(i32::MAX))?;
+        TcpListener::from_std(socket.into())
+    }
+
+    /// Apply the per-connection options to an accepted stream.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the OS rejects an option.
+    pub fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        stream.set_nodelay(self.nodelay)?;
+        let socket = SockRef::from(stream);
+        if let Some(time) = self.keepalive {
+            socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(time))?;
+        }
+        self.set_buffer_sizes(&socket)
+    }
+
+    /// Bind `count` listeners to the same address with `SO_REUSEPORT`.
+    ///
+    /// With port 0, every listener shares the port the first one was given.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any listener cannot be bound.
+    pub fn bind_many(&self, addr: SocketAddr, count: usize) -> io::Result<Vec<TcpListener>> {
+        if count <= 1 {
+            return Ok(vec![self.bind(addr)?]);
+        }
+        let options = Self {
+            reuse_port: true,
+            ..self.clone()
+        };
+        let first = options.bind(addr)?;
+        let addr = first.local_addr()?;
+        let mut listeners = vec![first];
+        for _ in 1..count {
+            listeners.push(options.bind(addr)?);
+        }
+        Ok(listeners)
+    }
+
+    fn set_buffer_sizes(&self, socket: &SockRef<'_>) -> io::Result<()> {
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether this platform supports [`SocketOptions::reuse_port`].
+pub const REUSE_PORT_SUPPORTED: bool = cfg!(all(
+    unix,
+    not(any(
+        target_os = "solaris",
+        target_os = "illumos",
+        target_os = "cygwin"
+    ))
+));
+
+#[cfg(all(
+    unix,
+    not(any(target_os = "solaris", target_os = "illumos", target_os = "cygwin"))
+))]
+fn set_reuse_port(socket: &Socket) -> io::Result<()> {
+    socket.set_reuse_port(true)
+}
+
+#[cfg(not(all(
+    unix,
+    not(any(target_os = "solaris", target_os = "illumos", target_os = "cygwin"))
+)))]
+fn set_reuse_port(_socket: &Socket) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "SO_REUSEPORT is not supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bind_and_apply() {
+        let options = SocketOptions {
+            keepalive: Some(Duration::from_secs(30)),
+            recv_buffer_size: Some(256 * 1024),
+            ..SocketOptions::default()
+        };
+        let listener = options.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let (client, accepted) = tokio::join!(TcpStream::connect(addr), listener.accept());
+        let _client = client.unwrap();
+        let (stream, _) = accepted.unwrap();
+        options.apply(&stream).unwrap();
+
+        assert!(stream.nodelay().unwrap());
+        let socket = SockRef::from(&stream);
+        assert!(socket.keepalive().unwrap());
+        // The kernel may round the size up (Linux doubles it)
+        assert!(socket.recv_buffer_size().unwrap() >= 256 * 1024);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_bind_many() {
+        let options = SocketOptions::default();
+        let listeners = options
+            .bind_many("127.0.0.1:0".parse().unwrap(), 4)
+            .unwrap();
+        assert_eq!(listeners.len(), 4);
+        let addr = listeners[0].local_addr().unwrap();
+        assert!(listeners.iter().all(|l| l.local_addr().unwrap() == addr));
+
+        // Without SO_REUSEPORT the address is taken
+        assert!(options.bind(addr).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bind_only_v6() {
+        let options = SocketOptions {
+            only_v6: true,
+            ..SocketOptions::default()
+        };
+        // Skip on hosts without IPv6
+        let Ok(v6) = options.bind("[::]:0".parse().unwrap()) else {
+            return;
+        };
+        let port = v6.local_addr().unwrap().port();
+        let v4 = options
+            .bind(SocketAddr::from(([0, 0, 0, 0], port)))
+            .unwrap();
+        assert_eq!(v4.local_addr().unwrap().port(), port);
+        assert!(SockRef::from(&v6).only_v6().unwrap());
+    }
+}
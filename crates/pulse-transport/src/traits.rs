@@ -8,6 +8,7 @@ use bytes::Bytes;
 use pulse_protocol::Frame;
 use std::fmt;
 use thiserror::Error;
+use uuid::Uuid;
 
 /// Unique identifier for a connection.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -20,15 +21,10 @@ impl ConnectionId {
         Self(id.into())
     }
 
-    /// Generate a random connection ID.
+    /// Generate a unique connection ID with the default [`UuidV7Generator`].
     #[must_use]
     pub fn generate() -> Self {
-        use std::time::{SystemTime, UNIX_EPOCH};
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_nanos();
-        Self(format!("conn_{:x}", timestamp))
+        UuidV7Generator.generate()
     }
 
     /// Get the ID as a string slice.
@@ -56,6 +52,38 @@ impl From<&str> for ConnectionId {
     }
 }
 
+/// Source of new connection IDs.
+pub trait IdGenerator: Send + Sync {
+    /// Generate a new, unique connection ID.
+    fn generate(&self) -> ConnectionId;
+}
+
+/// Generates time-ordered UUIDv7 IDs (`conn_<uuid>`).
+///
+/// IDs sort by creation time with millisecond precision; the remaining 74
+/// bits are random, so concurrent accepts cannot collide.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UuidV7Generator;
+
+impl IdGenerator for UuidV7Generator {
+    fn generate(&self) -> ConnectionId {
+        ConnectionId(format!("conn_{}", Uuid::now_v7().simple()))
+    }
+}
+
+/// Generates fully random UUIDv4 IDs (`conn_<uuid>`).
+///
+/// Unlike [`UuidV7Generator`], the IDs reveal nothing about when the
+/// connection was accepted.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RandomIdGenerator;
+
+impl IdGenerator for RandomIdGenerator {
+    fn generate(&self) -> ConnectionId {
+        ConnectionId(format!("conn_{}", Uuid::new_v4().simple()))
+    }
+}
+
 /// Transport errors.
 #[derive(Debug, Error)]
 pub enum TransportError {
@@ -140,6 +168,45 @@ pub trait Connection: Send + Sync {
 
     /// Check if the connection is still open.
     fn is_open(&self) -> bool;
+
+    /// Split into halves that send and receive independently.
+    ///
+    /// The halves can be moved to separate tasks, so a pending
+    /// [`ConnectionStream::recv`] does not hold up sends.
+    fn split(self: Box<Self>) -> (Box<dyn ConnectionSink>, Box<dyn ConnectionStream>);
+}
+
+/// The sending half of a [`Connection`].
+#[async_trait]
+pub trait ConnectionSink: Send + Sync {
+    /// Get the connection's unique identifier.
+    fn id(&self) -> &ConnectionId;
+
+    /// Send a frame to the connection.
+    async fn send(&mut self, frame: Frame) -> Result<(), TransportError>;
+
+    /// Send raw bytes to the connection.
+    async fn send_raw(&mut self, data: Bytes) -> Result<(), TransportError>;
+
+    /// Close the connection gracefully.
+    ///
+    /// The receiving half then sees the connection as closed.
+    async fn close(&mut self) -> Result<(), TransportError>;
+
+    /// Check if the connection is still open.
+    fn is_open(&self) -> bool;
+}
+
+/// The receiving half of a [`Connection`].
+#[async_trait]
+pub trait ConnectionStream: Send + Sync {
+    /// Get the connection's unique identifier.
+    fn id(&self) -> &ConnectionId;
+
+    /// Receive the next frame from the connection.
+    ///
+    /// Returns `None` if the connection is closed cleanly.
+    async fn recv(&mut self) -> Result<Option<Frame>, TransportError>;
 }
 
 /// Extension trait for connections with additional capabilities.
@@ -162,6 +229,18 @@ mod tests {
         let id2 = ConnectionId::generate();
         assert_ne!(id1, id2);
         assert!(id1.as_str().starts_with("conn_"));
+        assert_eq!(id1.as_str().len(), "conn_".len() + 32);
+    }
+
+    #[test]
+    fn test_id_generators_are_unique() {
+        use std::collections::HashSet;
+
+        let generators: [&dyn IdGenerator; 2] = [&UuidV7Generator, &RandomIdGenerator];
+        for generator in generators {
+            let ids: HashSet<_> = (0..1000).map(|_| generator.generate()).collect();
+            assert_eq!(ids.len(), 1000);
+        }
     }
 
     #[test]
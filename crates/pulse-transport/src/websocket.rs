@@ -1,24 +1,34 @@
 //! WebSocket transport implementation.
 //!
 //! This module provides a WebSocket-based transport using tokio-tungstenite.
+//! [`WebSocketTransport`] accepts connections; [`WebSocketClientTransport`]
+//! opens them to another server, over `ws://` or `wss://`, and returns the
+//! same [`WebSocketConnection`] so clients share the framing, write queue,
+//! and size limits with the server side.
 
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
+use futures_util::stream::{SplitSink, SplitStream};
 use futures_util::{SinkExt, StreamExt};
-use pulse_protocol::{codec, Frame};
+use pulse_protocol::{codec, pool, Frame};
 use std::net::SocketAddr;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::Mutex;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
 use tokio_tungstenite::{
-    accept_async,
-    tungstenite::{Error as WsError, Message},
-    WebSocketStream,
+    accept_async, client_async_tls_with_config,
+    tungstenite::{client::IntoClientRequest, Error as WsError, Message},
+    Connector, MaybeTlsStream, WebSocketStream,
 };
 use tracing::{debug, error, info, warn};
 
-use crate::traits::{Connection, ConnectionId, Transport, TransportError};
+use crate::socket::SocketOptions;
+use crate::traits::{
+    Connection, ConnectionId, ConnectionSink, ConnectionStream, Transport, TransportError,
+};
 
 /// WebSocket transport configuration.
 #[derive(Debug, Clone)]
@@ -27,13 +37,22 @@ pub struct WebSocketConfig {
     pub bind_addr: SocketAddr,
     /// Maximum message size in bytes.
     pub max_message_size: usize,
+    /// Outbound messages queued per connection before sends wait.
+    pub write_queue_size: usize,
+    /// TCP tuning for the listener and accepted connections.
+    pub socket: SocketOptions,
 }
 
+/// Default for [`WebSocketConfig::write_queue_size`].
+pub const DEFAULT_WRITE_QUEUE_SIZE: usize = 256;
+
 impl Default for WebSocketConfig {
     fn default() -> Self {
         Self {
             bind_addr: "127.0.0.1:8080".parse().unwrap(),
             max_message_size: 64 * 1024, // 64 KB
+            write_queue_size: DEFAULT_WRITE_QUEUE_SIZE,
+            socket: SocketOptions::default(),
         }
     }
 }
@@ -51,8 +70,9 @@ impl WebSocketTransport {
     ///
     /// Returns an error if binding to the address fails.
     pub async fn new(config: WebSocketConfig) -> Result<Self, TransportError> {
-        let listener = TcpListener::bind(config.bind_addr)
-            .await
+        let listener = config
+            .socket
+            .bind(config.bind_addr)
             .map_err(TransportError::Io)?;
 
         info!("WebSocket transport listening on {}", config.bind_addr);
@@ -86,6 +106,9 @@ impl Transport for WebSocketTransport {
         let (stream, addr) = self.listener.accept().await.map_err(TransportError::Io)?;
 
         debug!("Accepted TCP connection from {}", addr);
+        if let Err(e) = self.config.socket.apply(&stream) {
+            warn!("Failed to tune socket for {}: {}", addr, e);
+        }
 
         let ws_stream = accept_async(stream).await.map_err(|e| {
             error!("WebSocket handshake failed: {}", e);
@@ -94,7 +117,12 @@ impl Transport for WebSocketTransport {
 
         debug!("WebSocket handshake completed with {}", addr);
 
-        let conn = WebSocketConnection::new(ws_stream, addr, self.config.max_message_size);
+        let conn = WebSocketConnection::new(
+            ws_stream,
+            Some(addr),
+            self.config.max_message_size,
+            self.config.write_queue_size,
+        );
         Ok(Box::new(conn))
     }
 
@@ -103,51 +131,325 @@ impl Transport for WebSocketTransport {
     }
 }
 
+/// The stream under a client connection: plain TCP for `ws://`, TLS for
+/// `wss://`.
+pub type ClientStream = MaybeTlsStream<TcpStream>;
+
+/// Default for [`WebSocketClientConfig::max_message_size`].
+///
+/// Larger than the server's default, since replies such as history can
+/// carry many messages at once.
+pub const DEFAULT_CLIENT_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// WebSocket client configuration.
+#[derive(Debug, Clone)]
+pub struct WebSocketClientConfig {
+    /// Maximum message size in bytes.
+    pub max_message_size: usize,
+    /// Outbound messages queued before sends wait.
+    pub write_queue_size: usize,
+    /// TCP tuning for the connection. Only the per-connection options
+    /// apply.
+    pub socket: SocketOptions,
+    /// TLS settings for `wss://` URLs (`None` trusts the built-in web PKI
+    /// roots).
+    pub tls: Option<Arc<rustls::ClientConfig>>,
+}
+
+impl Default for WebSocketClientConfig {
+    fn default() -> Self {
+        Self {
+            max_message_size: DEFAULT_CLIENT_MAX_MESSAGE_SIZE,
+            write_queue_size: DEFAULT_WRITE_QUEUE_SIZE,
+            socket: SocketOptions::default(),
+            tls: None,
+        }
+    }
+}
+
+/// Opens WebSocket connections to a server.
+#[derive(Debug, Clone, Default)]
+pub struct WebSocketClientTransport {
+    config: WebSocketClientConfig,
+}
+
+impl WebSocketClientTransport {
+    /// Create a client transport.
+    #[must_use]
+    pub fn new(config: WebSocketClientConfig) -> Self {
+        Self { config }
+    }
+
+    /// Connect to a `ws://` or `wss://` URL.
+    ///
+    /// Only the WebSocket handshake is done here; the Pulse handshake
+    /// (waiting for `Connected`, sending `Connect`) is up to the caller.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the URL is invalid, the server cannot be reached,
+    /// or the TLS or WebSocket handshake fails.
+    pub async fn connect(
+        &self,
+        url: &str,
+    ) -> Result<WebSocketConnection<ClientStream>, TransportError> {
+        let request = url
+            .into_client_request()
+            .map_err(|e| TransportError::Other(format!("Invalid URL {}: {}", url, e)))?;
+        let uri = request.uri();
+        let host = uri
+            .host()
+            .ok_or_else(|| TransportError::Other(format!("No host in URL {}", url)))?;
+        // IPv6 hosts keep their brackets in the URI
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+            Some("wss") => 443,
+            _ => 80,
+        });
+
+        let stream = TcpStream::connect((host, port))
+            .await
+            .map_err(TransportError::Io)?;
+        let remote_addr = stream.peer_addr().ok();
+        if let Err(e) = self.config.socket.apply(&stream) {
+            warn!("Failed to tune socket for {}: {}", url, e);
+        }
+
+        let connector = self.config.tls.clone().map(Connector::Rustls);
+        let (ws_stream, _) = client_async_tls_with_config(request, stream, None, connector)
+            .await
+            .map_err(|e| match e {
+                WsError::Io(e) => TransportError::Io(e),
+                e => TransportError::Other(format!("WebSocket handshake failed: {}", e)),
+            })?;
+
+        debug!("WebSocket handshake completed with {}", url);
+
+        Ok(WebSocketConnection::new(
+            ws_stream,
+            remote_addr,
+            self.config.max_message_size,
+            self.config.write_queue_size,
+        ))
+    }
+}
+
+/// Connect to a `ws://` or `wss://` URL with the default client config.
+///
+/// # Errors
+///
+/// Returns an error if the connection or handshake fails; see
+/// [`WebSocketClientTransport::connect`].
+pub async fn connect(url: &str) -> Result<WebSocketConnection<ClientStream>, TransportError> {
+    WebSocketClientTransport::default().connect(url).await
+}
+
 /// A WebSocket connection.
-pub struct WebSocketConnection {
-    id: ConnectionId,
-    stream: Arc<Mutex<WebSocketStream<TcpStream>>>,
-    remote_addr: SocketAddr,
-    is_open: AtomicBool,
-    read_buffer: BytesMut,
-    max_message_size: usize,
+///
+/// Sending and receiving use separate halves of the socket, so they do not
+/// wait on each other even before [`Connection::split`]. Sends are queued to
+/// a writer task that owns the write half; they wait only while the queue is
+/// full.
+///
+/// Accepted connections run over a [`TcpStream`], client connections over a
+/// [`ClientStream`].
+pub struct WebSocketConnection<S = TcpStream> {
+    sender: WebSocketSender,
+    receiver: WebSocketReceiver<S>,
+    remote_addr: Option<SocketAddr>,
 }
 
-impl WebSocketConnection {
+impl<S> WebSocketConnection<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
     /// Create a new WebSocket connection.
     fn new(
-        stream: WebSocketStream<TcpStream>,
-        remote_addr: SocketAddr,
+        stream: WebSocketStream<S>,
+        remote_addr: Option<SocketAddr>,
         max_message_size: usize,
+        write_queue_size: usize,
     ) -> Self {
+        let id = ConnectionId::generate();
+        let is_open = Arc::new(AtomicBool::new(true));
+        let (sink, stream) = stream.split();
+        let (queue, messages) = mpsc::channel(write_queue_size.max(1));
+        let writer = tokio::spawn(write_loop(sink, messages, is_open.clone()));
         Self {
-            id: ConnectionId::generate(),
-            stream: Arc::new(Mutex::new(stream)),
+            sender: WebSocketSender {
+                id: id.clone(),
+                queue: Some(queue),
+                writer: Some(writer),
+                is_open: is_open.clone(),
+            },
+            receiver: WebSocketReceiver {
+                id,
+                stream,
+                is_open,
+                read_buffer: pool::global().acquire(),
+                max_message_size,
+            },
             remote_addr,
-            is_open: AtomicBool::new(true),
-            read_buffer: BytesMut::with_capacity(4096),
-            max_message_size,
         }
     }
 }
 
 #[async_trait]
-impl Connection for WebSocketConnection {
+impl<S> Connection for WebSocketConnection<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    fn id(&self) -> &ConnectionId {
+        &self.sender.id
+    }
+
+    async fn recv(&mut self) -> Result<Option<Frame>, TransportError> {
+        self.receiver.recv().await
+    }
+
+    async fn send(&mut self, frame: Frame) -> Result<(), TransportError> {
+        self.sender.send(frame).await
+    }
+
+    async fn send_raw(&mut self, data: Bytes) -> Result<(), TransportError> {
+        self.sender.send_raw(data).await
+    }
+
+    async fn close(&mut self) -> Result<(), TransportError> {
+        self.sender.close().await
+    }
+
+    fn remote_addr(&self) -> Option<String> {
+        self.remote_addr.map(|addr| addr.to_string())
+    }
+
+    fn is_open(&self) -> bool {
+        self.sender.is_open()
+    }
+
+    fn split(self: Box<Self>) -> (Box<dyn ConnectionSink>, Box<dyn ConnectionStream>) {
+        (Box::new(self.sender), Box::new(self.receiver))
+    }
+}
+
+/// Write queued messages to the socket until the queue closes or a write
+/// fails, then close the socket.
+async fn write_loop<S>(
+    mut sink: SplitSink<WebSocketStream<S>, Message>,
+    mut messages: mpsc::Receiver<Message>,
+    is_open: Arc<AtomicBool>,
+) -> Result<(), WsError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let result = async {
+        while let Some(message) = messages.recv().await {
+            sink.feed(message).await?;
+            // Flush once for everything already queued
+            while let Ok(message) = messages.try_recv() {
+                sink.feed(message).await?;
+            }
+            sink.flush().await?;
+        }
+        sink.close().await
+    }
+    .await;
+    is_open.store(false, Ordering::SeqCst);
+    if let Err(e) = &result {
+        debug!("WebSocket writer stopped: {}", e);
+    }
+    result
+}
+
+/// The sending half of a [`WebSocketConnection`].
+///
+/// A successful send means the message was queued; if the socket later
+/// fails, the connection is marked closed and further sends are refused.
+pub struct WebSocketSender {
+    id: ConnectionId,
+    queue: Option<mpsc::Sender<Message>>,
+    writer: Option<JoinHandle<Result<(), WsError>>>,
+    is_open: Arc<AtomicBool>,
+}
+
+#[async_trait]
+impl ConnectionSink for WebSocketSender {
+    fn id(&self) -> &ConnectionId {
+        &self.id
+    }
+
+    async fn send(&mut self, frame: Frame) -> Result<(), TransportError> {
+        let data = codec::encode(&frame)?;
+        self.send_raw(data).await
+    }
+
+    async fn send_raw(&mut self, data: Bytes) -> Result<(), TransportError> {
+        let Some(queue) = self.queue.as_ref().filter(|_| self.is_open()) else {
+            return Err(TransportError::ConnectionClosed);
+        };
+
+        queue
+            .send(Message::Binary(data.to_vec()))
+            .await
+            .map_err(|_| TransportError::ConnectionClosed)
+    }
+
+    async fn close(&mut self) -> Result<(), TransportError> {
+        // Closing the queue lets the writer flush what is left, then close
+        // the socket
+        self.queue.take();
+        let Some(writer) = self.writer.take() else {
+            return Ok(()); // Already closed
+        };
+        self.is_open.store(false, Ordering::SeqCst);
+
+        match writer.await {
+            Ok(Ok(()) | Err(WsError::ConnectionClosed | WsError::AlreadyClosed)) => Ok(()),
+            Ok(Err(e)) => Err(TransportError::Other(format!("Failed to close: {}", e))),
+            Err(e) => Err(TransportError::Other(format!("Writer task failed: {}", e))),
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        self.is_open.load(Ordering::SeqCst)
+    }
+}
+
+/// The receiving half of a [`WebSocketConnection`].
+pub struct WebSocketReceiver<S = TcpStream> {
+    id: ConnectionId,
+    stream: SplitStream<WebSocketStream<S>>,
+    is_open: Arc<AtomicBool>,
+    read_buffer: BytesMut,
+    max_message_size: usize,
+}
+
+impl<S> Drop for WebSocketReceiver<S> {
+    fn drop(&mut self) {
+        pool::global().release(std::mem::take(&mut self.read_buffer));
+    }
+}
+
+#[async_trait]
+impl<S> ConnectionStream for WebSocketReceiver<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
     fn id(&self) -> &ConnectionId {
         &self.id
     }
 
     async fn recv(&mut self) -> Result<Option<Frame>, TransportError> {
         // First, try to decode from the existing buffer
-        if let Some(frame) = codec::decode_from(&mut self.read_buffer)? {
+        if let Some(frame) =
+            codec::decode_from_limited(&mut self.read_buffer, self.max_message_size)?
+        {
             return Ok(Some(frame));
         }
 
         // Need more data - read from the WebSocket
-        let mut stream = self.stream.lock().await;
-
         loop {
-            match stream.next().await {
+            match self.stream.next().await {
                 Some(Ok(Message::Binary(data))) => {
                     if data.len() > self.max_message_size {
                         warn!(
@@ -163,7 +465,9 @@ impl Connection for WebSocketConnection {
                     self.read_buffer.extend_from_slice(&data);
 
                     // Try to decode a frame
-                    if let Some(frame) = codec::decode_from(&mut self.read_buffer)? {
+                    if let Some(frame) =
+                        codec::decode_from_limited(&mut self.read_buffer, self.max_message_size)?
+                    {
                         return Ok(Some(frame));
                     }
                     // Need more data, continue reading
@@ -172,18 +476,15 @@ impl Connection for WebSocketConnection {
                     // For compatibility, treat text as binary
                     self.read_buffer.extend_from_slice(text.as_bytes());
 
-                    if let Some(frame) = codec::decode_from(&mut self.read_buffer)? {
+                    if let Some(frame) =
+                        codec::decode_from_limited(&mut self.read_buffer, self.max_message_size)?
+                    {
                         return Ok(Some(frame));
                     }
                 }
-                Some(Ok(Message::Ping(data))) => {
-                    // Respond to ping with pong
-                    if let Err(e) = stream.send(Message::Pong(data)).await {
-                        warn!("Failed to send pong: {}", e);
-                    }
-                }
-                Some(Ok(Message::Pong(_))) => {
-                    // Ignore pong messages
+                Some(Ok(Message::Ping(_) | Message::Pong(_))) => {
+                    // tungstenite queues the pong reply itself and sends
+                    // it on the next read or write
                 }
                 Some(Ok(Message::Close(_))) => {
                     debug!("Received close frame");
@@ -211,43 +512,6 @@ impl Connection for WebSocketConnection {
             }
         }
     }
-
-    async fn send(&mut self, frame: Frame) -> Result<(), TransportError> {
-        let data = codec::encode(&frame)?;
-        self.send_raw(data).await
-    }
-
-    async fn send_raw(&mut self, data: Bytes) -> Result<(), TransportError> {
-        if !self.is_open.load(Ordering::SeqCst) {
-            return Err(TransportError::ConnectionClosed);
-        }
-
-        let mut stream = self.stream.lock().await;
-        stream
-            .send(Message::Binary(data.to_vec()))
-            .await
-            .map_err(|e| TransportError::SendFailed(e.to_string()))
-    }
-
-    async fn close(&mut self) -> Result<(), TransportError> {
-        if !self.is_open.swap(false, Ordering::SeqCst) {
-            return Ok(()); // Already closed
-        }
-
-        let mut stream = self.stream.lock().await;
-        stream
-            .close(None)
-            .await
-            .map_err(|e| TransportError::Other(format!("Failed to close: {}", e)))
-    }
-
-    fn remote_addr(&self) -> Option<String> {
-        Some(self.remote_addr.to_string())
-    }
-
-    fn is_open(&self) -> bool {
-        self.is_open.load(Ordering::SeqCst)
-    }
 }
 
 /// Upgrade an HTTP request to a WebSocket connection.
@@ -263,7 +527,12 @@ pub async fn upgrade_to_websocket(
         .await
         .map_err(|e| TransportError::Other(format!("WebSocket handshake failed: {}", e)))?;
 
-    Ok(WebSocketConnection::new(ws_stream, addr, max_message_size))
+    Ok(WebSocketConnection::new(
+        ws_stream,
+        Some(addr),
+        max_message_size,
+        DEFAULT_WRITE_QUEUE_SIZE,
+    ))
 }
 
 #[cfg(test)]
@@ -275,5 +544,100 @@ mod tests {
         let config = WebSocketConfig::default();
         assert_eq!(config.bind_addr.port(), 8080);
         assert_eq!(config.max_message_size, 64 * 1024);
+        assert_eq!(config.write_queue_size, DEFAULT_WRITE_QUEUE_SIZE);
+    }
+
+    #[tokio::test]
+    async fn test_close_flushes_queue() {
+        let transport = WebSocketTransport::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let url = format!("ws://{}", transport.local_addr().unwrap());
+        let (client, connection) =
+            tokio::join!(tokio_tungstenite::connect_async(url), transport.accept());
+        let (mut client, _) = client.unwrap();
+        let mut connection = connection.unwrap();
+
+        for timestamp in 0..3 {
+            connection
+                .send(Frame::ping_with_timestamp(timestamp))
+                .await
+                .unwrap();
+        }
+        connection.close().await.unwrap();
+        assert!(!connection.is_open());
+        assert!(matches!(
+            connection.send(Frame::ping()).await,
+            Err(TransportError::ConnectionClosed)
+        ));
+
+        for timestamp in 0..3 {
+            let Some(Ok(Message::Binary(data))) = client.next().await else {
+                panic!("expected a binary message");
+            };
+            assert_eq!(
+                codec::decode(&data).unwrap(),
+                Frame::ping_with_timestamp(timestamp)
+            );
+        }
+        assert!(matches!(client.next().await, Some(Ok(Message::Close(_)))));
+    }
+
+    #[tokio::test]
+    async fn test_split_sends_while_receiving() {
+        let transport = WebSocketTransport::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let url = format!("ws://{}", transport.local_addr().unwrap());
+        let (client, connection) =
+            tokio::join!(tokio_tungstenite::connect_async(url), transport.accept());
+        let (mut client, _) = client.unwrap();
+        let (mut sink, mut stream) = connection.unwrap().split();
+        assert_eq!(sink.id(), stream.id());
+
+        // A receive waiting for the client must not block sending to it
+        let receiving = tokio::spawn(async move { stream.recv().await });
+        sink.send(Frame::ping_with_timestamp(7)).await.unwrap();
+        let Some(Ok(Message::Binary(data))) = client.next().await else {
+            panic!("expected a binary message");
+        };
+        assert_eq!(codec::decode(&data).unwrap(), Frame::ping_with_timestamp(7));
+
+        let reply = codec::encode(&Frame::pong(Some(7))).unwrap();
+        client.send(Message::Binary(reply.to_vec())).await.unwrap();
+        let received = receiving.await.unwrap().unwrap();
+        assert_eq!(received, Some(Frame::pong(Some(7))));
+
+        sink.close().await.unwrap();
+        assert!(!sink.is_open());
+    }
+
+    #[tokio::test]
+    async fn test_client_connect() {
+        let transport = WebSocketTransport::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let addr = transport.local_addr().unwrap();
+        let url = format!("ws://{}/ws", addr);
+        let (client, connection) = tokio::join!(connect(&url), transport.accept());
+        let mut client = client.unwrap();
+        let mut connection = connection.unwrap();
+        assert_eq!(client.remote_addr(), Some(addr.to_string()));
+
+        client.send(Frame::ping_with_timestamp(3)).await.unwrap();
+        assert_eq!(
+            connection.recv().await.unwrap(),
+            Some(Frame::ping_with_timestamp(3))
+        );
+        connection.send(Frame::pong(Some(3))).await.unwrap();
+        assert_eq!(client.recv().await.unwrap(), Some(Frame::pong(Some(3))));
+
+        client.close().await.unwrap();
+        assert_eq!(connection.recv().await.unwrap(), None);
+
+        // Nothing listening
+        drop(transport);
+        assert!(matches!(connect(&url).await, Err(TransportError::Io(_))));
+        assert!(connect("not a url").await.is_err());
     }
 }
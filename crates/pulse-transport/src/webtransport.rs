@@ -7,7 +7,9 @@ use async_trait::async_trait;
 use bytes::Bytes;
 use pulse_protocol::Frame;
 
-use crate::traits::{Connection, ConnectionId, Transport, TransportError};
+use crate::traits::{
+    Connection, ConnectionId, ConnectionSink, ConnectionStream, Transport, TransportError,
+};
 
 /// WebTransport configuration.
 #[derive(Debug, Clone)]
@@ -114,4 +116,45 @@ impl Connection for WebTransportConnection {
     fn is_open(&self) -> bool {
         false
     }
+
+    fn split(self: Box<Self>) -> (Box<dyn ConnectionSink>, Box<dyn ConnectionStream>) {
+        let sink = Box::new(Self {
+            id: self.id.clone(),
+        });
+        (sink, self)
+    }
+}
+
+#[async_trait]
+impl ConnectionSink for WebTransportConnection {
+    fn id(&self) -> &ConnectionId {
+        &self.id
+    }
+
+    async fn send(&mut self, frame: Frame) -> Result<(), TransportError> {
+        Connection::send(self, frame).await
+    }
+
+    async fn send_raw(&mut self, data: Bytes) -> Result<(), TransportError> {
+        Connection::send_raw(self, data).await
+    }
+
+    async fn close(&mut self) -> Result<(), TransportError> {
+        Connection::close(self).await
+    }
+
+    fn is_open(&self) -> bool {
+        false
+    }
+}
+
+#[async_trait]
+impl ConnectionStream for WebTransportConnection {
+    fn id(&self) -> &ConnectionId {
+        &self.id
+    }
+
+    async fn recv(&mut self) -> Result<Option<Frame>, TransportError> {
+        Connection::recv(self).await
+    }
 }